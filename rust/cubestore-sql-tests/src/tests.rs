@@ -30,6 +30,7 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
         t("group_by_decimal", group_by_decimal),
         t("float_decimal_scale", float_decimal_scale),
         t("join", join),
+        t("correlated_subquery", correlated_subquery),
         t("three_tables_join", three_tables_join),
         t(
             "three_tables_join_with_filter",
@@ -64,7 +65,14 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
         t("hyperloglog_inplace_group_by", hyperloglog_inplace_group_by),
         t("planning_inplace_aggregate", planning_inplace_aggregate),
         t("planning_hints", planning_hints),
+        t("count_distinct", count_distinct),
+        t("planning_count_distinct", planning_count_distinct),
+        t("having_on_group_keys", having_on_group_keys),
         t("planning_inplace_aggregate2", planning_inplace_aggregate2),
+        t(
+            "inplace_aggregate_keys_out_of_order",
+            inplace_aggregate_keys_out_of_order,
+        ),
         t("topk_large_inputs", topk_large_inputs),
         t("planning_simple", planning_simple),
         t("planning_joins", planning_joins),
@@ -73,6 +81,20 @@ pub fn sql_tests() -> Vec<(&'static str, TestFn)> {
         t("topk_decimals", topk_decimals),
         t("offset", offset),
         t("having", having),
+        t("system_commands", system_commands),
+        t("order_by_expressions", order_by_expressions),
+        t("planning_limit_pushdown", planning_limit_pushdown),
+        t("inline_values", inline_values),
+        t("create_table_as_select", create_table_as_select),
+        t("index_recommendations", index_recommendations),
+        t("table_sample", table_sample),
+        t("checksum_table", checksum_table),
+        t("count_from_metadata", count_from_metadata),
+        t("min_max_from_metadata", min_max_from_metadata),
+        t("nulls_order", nulls_order),
+        t("cast_matrix", cast_matrix),
+        t("sum_overflow", sum_overflow),
+        t("exact_float_sums", exact_float_sums),
     ];
 
     fn t<F>(name: &'static str, f: fn(Box<dyn SqlClient>) -> F) -> (&'static str, TestFn)
@@ -357,6 +379,59 @@ async fn join(service: Box<dyn SqlClient>) {
     );
 }
 
+async fn correlated_subquery(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA foo").await.unwrap();
+    service
+        .exec_query("CREATE TABLE foo.orders (customer_id text, amount int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE TABLE foo.customers (id text, credit int)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO foo.orders (customer_id, amount) VALUES ('a', 10), ('b', 2), ('b', 3), ('d', 1)",
+        )
+        .await
+        .unwrap();
+    // Customer 'c' has no orders.
+    service
+        .exec_query("INSERT INTO foo.customers (id, credit) VALUES ('a', 20), ('b', 6), ('c', 100)")
+        .await
+        .unwrap();
+
+    let result = service
+        .exec_query(
+            "SELECT c.id, c.credit FROM foo.customers c \
+             WHERE c.credit > (SELECT SUM(o.amount) FROM foo.orders o WHERE o.customer_id = c.id) \
+             ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&result),
+        vec![
+            vec![TableValue::String("a".to_string()), TableValue::Int(20)],
+            vec![TableValue::String("b".to_string()), TableValue::Int(6)],
+        ]
+    );
+
+    // Customer 'a' has no orders that pass the inner filter.
+    let result = service
+        .exec_query(
+            "SELECT c.id FROM foo.customers c \
+             WHERE (SELECT MAX(o.amount) FROM foo.orders o WHERE o.customer_id = c.id AND o.amount < 10) < c.credit \
+             ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&result),
+        vec![vec![TableValue::String("b".to_string())]]
+    );
+}
+
 async fn three_tables_join(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA foo").await.unwrap();
 
@@ -1379,6 +1454,21 @@ async fn planning_inplace_aggregate(service: Box<dyn SqlClient>) {
            \n          Empty"
     );
 
+    // Group-by columns are merged in the order of the index.
+    let p = service
+        .plan_query("SELECT day, url, SUM(hits) FROM s.Data GROUP BY 1, 2")
+        .await
+        .unwrap();
+    assert_eq!(
+        pp_phys_plan(p.worker.as_ref()),
+        "FinalInplaceAggregate\
+           \n  Worker\
+           \n    PartialInplaceAggregate\
+           \n      MergeSort\
+           \n        Scan, index: default:1:[1]:sort_on[url, day], fields: [url, day, hits]\
+           \n          Empty"
+    );
+
     // When there is no index, we fallback to inplace aggregates.
     let p = service
         .plan_query("SELECT day, SUM(hits) FROM s.Data GROUP BY 1")
@@ -1400,6 +1490,183 @@ async fn planning_inplace_aggregate(service: Box<dyn SqlClient>) {
     );
 }
 
+async fn planning_limit_pushdown(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, amount int)")
+        .await
+        .unwrap();
+
+    let p = service
+        .plan_query("SELECT id, amount FROM s.Data LIMIT 10")
+        .await
+        .unwrap();
+    assert_eq!(
+        pp_phys_plan(p.router.as_ref()),
+        "GlobalLimit, n: 10\
+          \n  ClusterSend, partitions: [[1]]"
+    );
+    assert_eq!(
+        pp_phys_plan(p.worker.as_ref()),
+        "GlobalLimit, n: 10\
+           \n  Worker\
+           \n    LocalLimit, n: 10\
+           \n      Projection, [id, amount]\
+           \n        Merge\
+           \n          Scan, index: default:1:[1], fields: *\
+           \n            Empty"
+    );
+
+    // Workers sort and send only the first rows, the router sorts again to combine them.
+    let p = service
+        .plan_query("SELECT id, amount FROM s.Data ORDER BY 2 LIMIT 10")
+        .await
+        .unwrap();
+    assert_eq!(
+        pp_phys_plan(p.worker.as_ref()),
+        "GlobalLimit, n: 10\
+           \n  Sort\
+           \n    Worker\
+           \n      LocalLimit, n: 10\
+           \n        Sort\
+           \n          Projection, [id, amount]\
+           \n            Merge\
+           \n              Scan, index: default:1:[1], fields: *\
+           \n                Empty"
+    );
+
+    // Rows skipped by OFFSET are skipped on the router.
+    let p = service
+        .plan_query("SELECT id, amount FROM s.Data ORDER BY 2 LIMIT 10 OFFSET 5")
+        .await
+        .unwrap();
+    let worker = pp_phys_plan(p.worker.as_ref());
+    assert!(
+        worker.starts_with("GlobalLimit, n: 10\n  Skip, n: 5"),
+        "{}",
+        worker
+    );
+    assert!(worker.contains("LocalLimit, n: 15"), "{}", worker);
+}
+
+async fn count_distinct(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Visits(site text, user_id int)")
+        .await
+        .unwrap();
+    for values in &[
+        "('a', 1), ('a', 2), ('b', 1)",
+        "('a', 1), ('b', NULL), ('b', 3)",
+    ] {
+        service
+            .exec_query(&format!(
+                "INSERT INTO s.Visits(site, user_id) VALUES {}",
+                values
+            ))
+            .await
+            .unwrap();
+    }
+
+    let r = service
+        .exec_query("SELECT site, COUNT(DISTINCT user_id) FROM s.Visits GROUP BY 1 ORDER BY 1")
+        .await
+        .unwrap();
+    assert_eq!(r.get_columns()[1].get_name(), "COUNT(DISTINCT user_id)");
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::String("a".to_string()), TableValue::Int(2)],
+            vec![TableValue::String("b".to_string()), TableValue::Int(2)],
+        ]
+    );
+
+    let r = service
+        .exec_query("SELECT COUNT(DISTINCT user_id) AS users FROM s.Visits WHERE site = 'b'")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(2)]]);
+}
+
+async fn having_on_group_keys(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Orders(customer_id int, amount int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Orders(customer_id, amount) VALUES (1, 10), (5, 20), (5, 30)")
+        .await
+        .unwrap();
+
+    let query = "SELECT customer_id, SUM(amount) FROM s.Orders GROUP BY 1 \
+                 HAVING customer_id = 5 AND SUM(amount) > 0";
+    let r = service.exec_query(query).await.unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Int(5), TableValue::Int(50)]]
+    );
+
+    // Conditions on group keys are applied before aggregation, so they prune partitions.
+    let r = service
+        .exec_query(&format!("EXPLAIN {}", query))
+        .await
+        .unwrap();
+    let rows = to_rows(&r);
+    assert_eq!(rows[0][0], TableValue::String("router".to_string()));
+    assert_eq!(rows[1][0], TableValue::String("worker".to_string()));
+    let worker_plan = match &rows[1][1] {
+        TableValue::String(s) => s.clone(),
+        v => panic!("unexpected plan {:?}", v),
+    };
+    let aggregate = worker_plan.find("PartialInplaceAggregate").unwrap();
+    let filter = worker_plan.find("Filter").unwrap();
+    assert!(aggregate < filter, "{}", worker_plan);
+}
+
+async fn planning_count_distinct(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Visits(site text, user_id int, day int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("CREATE INDEX by_user ON s.Visits (user_id)")
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Visits(site, user_id, day) VALUES \
+             ('a', 1, 1), ('a', 2, 1), ('b', 1, 2), ('b', 3, 2), ('b', 3, 3)",
+        )
+        .await
+        .unwrap();
+
+    // All rows of each user are in one partition of `by_user`, so workers count them.
+    let query = "SELECT COUNT(DISTINCT user_id) FROM s.Visits";
+    let p = service.plan_query(query).await.unwrap();
+    assert!(final_aggregate_on_workers(&pp_phys_plan(p.worker.as_ref())));
+    let r = service.exec_query(query).await.unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(3)]]);
+
+    // No index is sorted by `day` alone, the router merges days of all workers.
+    let query = "SELECT COUNT(DISTINCT day) FROM s.Visits";
+    let p = service.plan_query(query).await.unwrap();
+    assert!(!final_aggregate_on_workers(&pp_phys_plan(
+        p.worker.as_ref()
+    )));
+    let r = service.exec_query(query).await.unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(3)]]);
+}
+
+/// Whether aggregation in `worker_plan` is finished on workers.
+fn final_aggregate_on_workers(worker_plan: &str) -> bool {
+    worker_plan
+        .lines()
+        .skip_while(|l| l.trim() != "Worker")
+        .any(|l| l.trim().starts_with("Final"))
+}
+
 async fn planning_hints(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA s").await.unwrap();
     service
@@ -1509,6 +1776,54 @@ async fn planning_hints(service: Box<dyn SqlClient>) {
     );
 }
 
+async fn inplace_aggregate_keys_out_of_order(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(url text, day int, hits int)")
+        .await
+        .unwrap();
+    // Separate inserts produce separate chunks, which are merged by the index order.
+    for (day, hits) in &[(2, 1), (1, 10), (2, 100)] {
+        service
+            .exec_query(&format!(
+                "INSERT INTO s.Data(url, day, hits) VALUES ('a', {0}, {1}), ('b', {0}, {1})",
+                day, hits
+            ))
+            .await
+            .unwrap();
+    }
+
+    let r = service
+        .exec_query("SELECT day, url, SUM(hits) FROM s.Data GROUP BY 1, 2 ORDER BY 2, 1")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![
+                TableValue::Int(1),
+                TableValue::String("a".to_string()),
+                TableValue::Int(10)
+            ],
+            vec![
+                TableValue::Int(2),
+                TableValue::String("a".to_string()),
+                TableValue::Int(101)
+            ],
+            vec![
+                TableValue::Int(1),
+                TableValue::String("b".to_string()),
+                TableValue::Int(10)
+            ],
+            vec![
+                TableValue::Int(2),
+                TableValue::String("b".to_string()),
+                TableValue::Int(101)
+            ],
+        ]
+    );
+}
+
 async fn planning_inplace_aggregate2(service: Box<dyn SqlClient>) {
     service.exec_query("CREATE SCHEMA s").await.unwrap();
     service
@@ -1561,14 +1876,12 @@ async fn planning_inplace_aggregate2(service: Box<dyn SqlClient>) {
            \n              Union, single_vals: [0, 1], sort_order: [0, 1, 2, 3, 4]\
            \n                Projection, [allowed, site_id, url, day, hits], single_vals: [0, 1], sort_order: [0, 1, 2, 3, 4]\
            \n                  Filter, single_vals: [0, 1], sort_order: [0, 1, 2, 3, 4]\
-           \n                    MergeSort, sort_order: [0, 1, 2, 3, 4]\
-           \n                      Scan, index: default:1:[1], fields: *, sort_order: [0, 1, 2, 3, 4]\
-           \n                        Empty\
+           \n                    Scan, index: default:1:[1], fields: *, sort_order: [0, 1, 2, 3, 4]\
+           \n                      Empty\
            \n                Projection, [allowed, site_id, url, day, hits], single_vals: [0, 1], sort_order: [0, 1, 2, 3, 4]\
            \n                  Filter, single_vals: [0, 1], sort_order: [0, 1, 2, 3, 4]\
-           \n                    MergeSort, sort_order: [0, 1, 2, 3, 4]\
-           \n                      Scan, index: default:2:[2], fields: *, sort_order: [0, 1, 2, 3, 4]\
-           \n                        Empty"
+           \n                    Scan, index: default:2:[2], fields: *, sort_order: [0, 1, 2, 3, 4]\
+           \n                      Empty"
         );
 }
 
@@ -1772,13 +2085,11 @@ async fn planning_simple(service: Box<dyn SqlClient>) {
            \n      MergeSort\
            \n        Union\
            \n          Projection, [id, amount]\
-           \n            MergeSort\
-           \n              Scan, index: default:1:[1]:sort_on[id], fields: [id, amount]\
-           \n                Empty\
+           \n            Scan, index: default:1:[1]:sort_on[id], fields: [id, amount]\
+           \n              Empty\
            \n          Projection, [id, amount]\
-           \n            MergeSort\
-           \n              Scan, index: default:1:[1]:sort_on[id], fields: [id, amount]\
-           \n                Empty"
+           \n            Scan, index: default:1:[1]:sort_on[id], fields: [id, amount]\
+           \n              Empty"
     );
 }
 
@@ -2232,6 +2543,713 @@ async fn having(service: Box<dyn SqlClient>) {
     }
 }
 
+async fn system_commands(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, n int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id, n) VALUES (1, 10), (2, 20)")
+        .await
+        .unwrap();
+
+    // Scheduled jobs are listed with the columns of system.jobs.
+    let compactions = to_rows(
+        &service
+            .exec_query("SYSTEM COMPACT TABLE s.Data")
+            .await
+            .unwrap(),
+    );
+    assert!(!compactions.is_empty());
+    for job in &compactions {
+        assert_eq!(
+            job[1],
+            TableValue::String("PartitionCompaction".to_string())
+        );
+        assert_eq!(job[2], TableValue::String("scheduled".to_string()));
+    }
+    // Only partitions that were split and still have chunks are repartitioned.
+    let repartitions = to_rows(
+        &service
+            .exec_query("SYSTEM REPARTITION TABLE s.Data")
+            .await
+            .unwrap(),
+    );
+    for job in &repartitions {
+        assert_eq!(job[1], TableValue::String("Repartition".to_string()));
+        assert_eq!(job[2], TableValue::String("scheduled".to_string()));
+    }
+    // Jobs are removed once completed, the ones still there have the same type and a node.
+    let jobs = to_rows(
+        &service
+            .exec_query("SELECT id, job_type, status, node, progress FROM system.jobs")
+            .await
+            .unwrap(),
+    );
+    for job in &jobs {
+        let scheduled = compactions
+            .iter()
+            .chain(repartitions.iter())
+            .find(|j| j[0] == job[0]);
+        if let Some(scheduled) = scheduled {
+            assert_eq!(job[1], scheduled[1]);
+            assert!(
+                job[2] == TableValue::String("scheduled".to_string())
+                    || job[2] == TableValue::String("processing".to_string()),
+                "{:?}",
+                job
+            );
+            assert_ne!(job[3], TableValue::Null);
+        }
+    }
+
+    let r = service
+        .exec_query("SELECT id, n FROM s.Data ORDER BY id")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::Int(1), TableValue::Int(10)],
+            vec![TableValue::Int(2), TableValue::Int(20)],
+        ]
+    );
+
+    assert!(service
+        .exec_query("SYSTEM COMPACT TABLE s.Missing")
+        .await
+        .is_err());
+    assert!(service
+        .exec_query("SYSTEM CANCEL JOB 1000000")
+        .await
+        .is_err());
+    assert!(service.exec_query("SYSTEM RESTART").await.is_err());
+}
+
+async fn order_by_expressions(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(k text, n int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(k, n) VALUES ('a', 3), ('b', 1), ('b', 4), ('c', 2)")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT k FROM s.Data ORDER BY n DESC")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::String("b".to_string())],
+            vec![TableValue::String("a".to_string())],
+            vec![TableValue::String("c".to_string())],
+            vec![TableValue::String("b".to_string())],
+        ]
+    );
+
+    let r = service
+        .exec_query("SELECT k, SUM(n) FROM s.Data GROUP BY 1 ORDER BY SUM(n) DESC")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::String("b".to_string()), TableValue::Int(5)],
+            vec![TableValue::String("a".to_string()), TableValue::Int(3)],
+            vec![TableValue::String("c".to_string()), TableValue::Int(2)],
+        ]
+    );
+
+    let r = service
+        .exec_query("SELECT k FROM s.Data GROUP BY 1 ORDER BY MAX(n), 1 LIMIT 2")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::String("c".to_string())],
+            vec![TableValue::String("a".to_string())],
+        ]
+    );
+}
+
+async fn inline_values(service: Box<dyn SqlClient>) {
+    let r = service
+        .exec_query("SELECT * FROM (VALUES (2, 'b'), (1, 'a')) AS t (id, name) ORDER BY id")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::Int(1), TableValue::String("a".to_string())],
+            vec![TableValue::Int(2), TableValue::String("b".to_string())],
+        ]
+    );
+
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(k text, n int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(k, n) VALUES ('a', 3), ('b', 1), ('c', 2)")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT d.k, d.n, v.name FROM s.Data d \
+             JOIN (VALUES ('a', 'first'), ('c', 'third')) AS v (k, name) ON d.k = v.k \
+             ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![
+                TableValue::String("a".to_string()),
+                TableValue::Int(3),
+                TableValue::String("first".to_string()),
+            ],
+            vec![
+                TableValue::String("c".to_string()),
+                TableValue::Int(2),
+                TableValue::String("third".to_string()),
+            ],
+        ]
+    );
+
+    let r = service
+        .exec_query("SELECT * FROM (VALUES (1, 2), (3)) AS t (a, b)")
+        .await;
+    assert!(r.is_err());
+}
+
+async fn create_table_as_select(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(k text, n int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(k, n) VALUES ('a', 3), ('b', 1), ('b', 4), ('c', 2)")
+        .await
+        .unwrap();
+
+    service
+        .exec_query(
+            "CREATE TABLE s.Totals AS SELECT k, SUM(n) AS total FROM s.Data GROUP BY 1 \
+             INDEX by_total (total)",
+        )
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT k, total FROM s.Totals ORDER BY total DESC")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![TableValue::String("b".to_string()), TableValue::Int(5)],
+            vec![TableValue::String("a".to_string()), TableValue::Int(3)],
+            vec![TableValue::String("c".to_string()), TableValue::Int(2)],
+        ]
+    );
+
+    // Empty results still create the table.
+    service
+        .exec_query("CREATE TABLE s.NoRows AS SELECT k, n FROM s.Data WHERE n > 100")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT COUNT(*) FROM s.NoRows")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(0)]]);
+
+    service
+        .exec_query("CREATE TABLE s.Bad(k text) AS SELECT k FROM s.Data")
+        .await
+        .unwrap_err();
+}
+
+async fn index_recommendations(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(a int, b int, c int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(a, b, c) VALUES (1, 1, 1), (2, 2, 1), (3, 3, 2)")
+        .await
+        .unwrap();
+
+    // Served by the default index.
+    service
+        .exec_query("SELECT SUM(b) FROM s.Data WHERE a = 1")
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        service
+            .exec_query("SELECT b, SUM(a) FROM s.Data WHERE c = 1 GROUP BY 1")
+            .await
+            .unwrap();
+    }
+
+    let r = service
+        .exec_query(
+            "SELECT table_name, columns, queries, index_definition \
+             FROM system.index_recommendations",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::String("s.Data".to_string()),
+            TableValue::String("c, b".to_string()),
+            TableValue::Int(2),
+            TableValue::String("INDEX Data_c_b (c, b)".to_string()),
+        ]]
+    );
+}
+
+async fn table_sample(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(k text, n int)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(k, n) VALUES ('a', 3), ('b', 1), ('c', 2)")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT COUNT(*) FROM s.Data TABLESAMPLE SYSTEM (100 PERCENT)")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(3)]]);
+
+    let r = service
+        .exec_query("SELECT COUNT(*) FROM s.Data d TABLESAMPLE SYSTEM (0) WHERE d.n > 1")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(0)]]);
+
+    service
+        .exec_query("SELECT COUNT(*) FROM s.Data TABLESAMPLE SYSTEM (101)")
+        .await
+        .unwrap_err();
+}
+
+async fn checksum_table(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    for t in &["A", "B", "C"] {
+        service
+            .exec_query(&format!("CREATE TABLE s.{}(k text, n int, f float)", t))
+            .await
+            .unwrap();
+    }
+    service
+        .exec_query("INSERT INTO s.A(k, n, f) VALUES ('a', 1, 0.5), ('b', NULL, 1.5)")
+        .await
+        .unwrap();
+    // Same rows in a different order and in different chunks.
+    service
+        .exec_query("INSERT INTO s.B(k, n, f) VALUES ('b', NULL, 1.5)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.B(k, n, f) VALUES ('a', 1, 0.5)")
+        .await
+        .unwrap();
+    // Values are swapped between rows.
+    service
+        .exec_query("INSERT INTO s.C(k, n, f) VALUES ('a', 1, 1.5), ('b', NULL, 0.5)")
+        .await
+        .unwrap();
+
+    let mut checksums = Vec::new();
+    for t in &["A", "B", "C"] {
+        let r = service
+            .exec_query(&format!("CHECKSUM TABLE s.{}", t))
+            .await
+            .unwrap();
+        checksums.push(to_rows(&r));
+    }
+    assert_eq!(checksums[0][0][0], TableValue::Int(2));
+    assert_eq!(checksums[0], checksums[1]);
+    assert_ne!(checksums[0], checksums[2]);
+}
+
+async fn count_from_metadata(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, n int)")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT COUNT(*) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(0)]]);
+
+    service
+        .exec_query("INSERT INTO s.Data(id, n) VALUES (1, 1), (2, 2), (3, NULL)")
+        .await
+        .unwrap();
+    service
+        .exec_query("INSERT INTO s.Data(id, n) VALUES (4, 4), (NULL, 5)")
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query("SELECT COUNT(*) AS c, COUNT(1) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Int(5), TableValue::Int(5)]]
+    );
+    // Not answered from the metastore, but must give the same results.
+    let r = service
+        .exec_query("SELECT COUNT(*) FROM s.Data WHERE id >= 2")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Int(3)]]);
+    let r = service
+        .exec_query("SELECT COUNT(n), COUNT(*) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Int(4), TableValue::Int(5)]]
+    );
+}
+
+async fn min_max_from_metadata(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, updated_at timestamp, name text)")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query("SELECT MAX(updated_at) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![TableValue::Null]]);
+
+    service
+        .exec_query(
+            "INSERT INTO s.Data(id, updated_at, name) VALUES \
+             (1, '2020-01-02T00:00:00.000Z', 'b'), (2, NULL, NULL)",
+        )
+        .await
+        .unwrap();
+    service
+        .exec_query(
+            "INSERT INTO s.Data(id, updated_at, name) VALUES \
+             (3, '2020-01-01T00:00:00.000Z', 'c'), (NULL, '2020-01-03T00:00:00.000Z', 'a')",
+        )
+        .await
+        .unwrap();
+
+    let r = service
+        .exec_query(
+            "SELECT MAX(updated_at), MIN(updated_at), MIN(id), MAX(name), COUNT(*) FROM s.Data",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Timestamp(TimestampValue::new(1578009600000000000)),
+            TableValue::Timestamp(TimestampValue::new(1577836800000000000)),
+            TableValue::Int(1),
+            TableValue::String("c".to_string()),
+            TableValue::Int(4),
+        ]]
+    );
+    // Not answered from the metastore, but must give the same results.
+    let r = service
+        .exec_query("SELECT MAX(updated_at) FROM s.Data WHERE id < 3")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Timestamp(TimestampValue::new(
+            1577923200000000000
+        ))]]
+    );
+}
+
+async fn nulls_order(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(a int, b int)")
+        .await
+        .unwrap();
+    // Separate inserts produce overlapping chunks, merged on workers and the router.
+    for values in &[
+        "(1, NULL), (NULL, 2)",
+        "(1, 3), (2, NULL)",
+        "(NULL, NULL), (2, 1)",
+        "(3, NULL)",
+    ] {
+        service
+            .exec_query(&format!("INSERT INTO s.Data(a, b) VALUES {}", values))
+            .await
+            .unwrap();
+    }
+    let v = |v: Option<i64>| v.map(TableValue::Int).unwrap_or(TableValue::Null);
+    let rows = |rows: &[(Option<i64>, Option<i64>)]| {
+        rows.iter().map(|(a, b)| vec![v(*a), v(*b)]).collect_vec()
+    };
+
+    let r = service
+        .exec_query("SELECT a, b FROM s.Data ORDER BY a, b")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[
+            (None, None),
+            (None, Some(2)),
+            (Some(1), None),
+            (Some(1), Some(3)),
+            (Some(2), None),
+            (Some(2), Some(1)),
+            (Some(3), None),
+        ])
+    );
+    let r = service
+        .exec_query("SELECT a, b FROM s.Data ORDER BY a NULLS LAST, b DESC NULLS FIRST")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[
+            (Some(1), None),
+            (Some(1), Some(3)),
+            (Some(2), None),
+            (Some(2), Some(1)),
+            (Some(3), None),
+            (None, None),
+            (None, Some(2)),
+        ])
+    );
+    let r = service
+        .exec_query("SELECT a, b FROM s.Data ORDER BY a DESC NULLS FIRST, b NULLS LAST")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[
+            (None, Some(2)),
+            (None, None),
+            (Some(3), None),
+            (Some(2), Some(1)),
+            (Some(2), None),
+            (Some(1), Some(3)),
+            (Some(1), None),
+        ])
+    );
+
+    // Sums are null for groups without values.
+    let r = service
+        .exec_query("SELECT a, SUM(b) FROM s.Data GROUP BY 1 ORDER BY 2 NULLS LAST, 1 LIMIT 3")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        rows(&[(Some(2), Some(1)), (None, Some(2)), (Some(1), Some(3))])
+    );
+    let r = service
+        .exec_query("SELECT a, SUM(b) FROM s.Data GROUP BY 1 ORDER BY 2 DESC, 1 LIMIT 2")
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), rows(&[(Some(3), None), (Some(1), Some(3))]));
+}
+
+async fn cast_matrix(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(s text, f float, d decimal(18, 2), t timestamp)")
+        .await
+        .unwrap();
+    // Rows are in different chunks, so casts run on workers and on the router.
+    for values in &[
+        "('1.005', 1.005, 2.5, '2021-01-01T10:00:00.000Z')",
+        "('-2.5e1', -0.125, -2.49, '2020-12-31T23:59:59.999Z')",
+    ] {
+        service
+            .exec_query(&format!("INSERT INTO s.Data(s, f, d, t) VALUES {}", values))
+            .await
+            .unwrap();
+    }
+
+    let r = service
+        .exec_query(
+            "SELECT CAST(s AS DECIMAL(10, 2)), CAST(f AS DECIMAL(10, 2)), CAST(d AS BIGINT), \
+                    CAST(t AS DATE) \
+             FROM s.Data \
+             ORDER BY 3",
+        )
+        .await
+        .unwrap();
+    let decimal = |v: &str| TableValue::Decimal(v.to_string());
+    assert_eq!(
+        to_rows(&r),
+        vec![
+            vec![
+                decimal("-25"),
+                decimal("-0.13"),
+                TableValue::Int(-2),
+                TableValue::Timestamp(TimestampValue::new(1609372800000000000)),
+            ],
+            vec![
+                decimal("1.01"),
+                decimal("1.01"),
+                TableValue::Int(3),
+                TableValue::Timestamp(TimestampValue::new(1609459200000000000)),
+            ],
+        ]
+    );
+
+    // Casts of values in GROUP BY and after aggregation on the router agree.
+    let r = service
+        .exec_query(
+            "SELECT CAST(SUM(f) AS DECIMAL(10, 1)) FROM s.Data GROUP BY CAST(t AS DATE) ORDER BY 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(to_rows(&r), vec![vec![decimal("-0.1")], vec![decimal("1")]]);
+
+    service
+        .exec_query("INSERT INTO s.Data(s, f, d, t) VALUES ('abc', 1e20, 0, NULL)")
+        .await
+        .unwrap();
+    let r = service
+        .exec_query(
+            "SELECT TRY_CAST(s AS DECIMAL(10, 2)), TRY_CAST(f AS DECIMAL(10, 2)), \
+                    TRY_CAST(s AS DATE) \
+             FROM s.Data \
+             WHERE s = 'abc'",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![TableValue::Null, TableValue::Null, TableValue::Null]]
+    );
+    let e = service
+        .exec_query("SELECT CAST(s AS DECIMAL(10, 2)) FROM s.Data")
+        .await
+        .unwrap_err();
+    assert!(e.message.contains("Can't cast 'abc'"), "{}", e);
+    let e = service
+        .exec_query("SELECT CAST(f AS DECIMAL(10, 2)) FROM s.Data")
+        .await
+        .unwrap_err();
+    assert!(e.message.contains("out of range"), "{}", e);
+}
+
+async fn sum_overflow(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, n int, d decimal(18, 2))")
+        .await
+        .unwrap();
+    // Rows are in different chunks, so partial sums of workers are merged on the router.
+    for values in &[
+        "(1, 9223372036854775807, 1)",
+        "(1, 10, 0.1)",
+        "(2, 9223372036854775807, 1)",
+        "(2, 10, 1)",
+        "(2, -20, 1)",
+    ] {
+        service
+            .exec_query(&format!("INSERT INTO s.Data(id, n, d) VALUES {}", values))
+            .await
+            .unwrap();
+    }
+
+    // Intermediate sums out of range do not matter.
+    let r = service
+        .exec_query("SELECT id, SUM(n), SUM(d) FROM s.Data WHERE id = 2 GROUP BY 1")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Int(2),
+            TableValue::Int(9223372036854775797),
+            TableValue::Decimal("3".to_string()),
+        ]]
+    );
+
+    let e = service
+        .exec_query("SELECT SUM(n) FROM s.Data WHERE id = 1")
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("out of range"), "{}", e);
+}
+
+async fn exact_float_sums(service: Box<dyn SqlClient>) {
+    service.exec_query("CREATE SCHEMA s").await.unwrap();
+    service
+        .exec_query("CREATE TABLE s.Data(id int, f float)")
+        .await
+        .unwrap();
+    // Partial sums of different chunks are merged in any order.
+    for values in &[
+        "(1, 1e100)",
+        "(1, 0.1)",
+        "(1, -1e100)",
+        "(1, 0.2)",
+        "(2, 0.3)",
+    ] {
+        service
+            .exec_query(&format!("INSERT INTO s.Data(id, f) VALUES {}", values))
+            .await
+            .unwrap();
+    }
+
+    let r = service
+        .exec_query("SELECT /*+ EXACT_FLOAT_SUMS */ SUM(f), AVG(f) FROM s.Data")
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Float(0.6.into()),
+            TableValue::Float(0.12.into()),
+        ]]
+    );
+
+    let r = service
+        .exec_query(
+            "SELECT /*+ EXACT_FLOAT_SUMS */ id, SUM(f) FROM s.Data GROUP BY 1 ORDER BY 2 DESC \
+             LIMIT 1",
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        to_rows(&r),
+        vec![vec![
+            TableValue::Int(1),
+            TableValue::Float(0.30000000000000004.into())
+        ]]
+    );
+}
+
 fn to_rows(d: &DataFrame) -> Vec<Vec<TableValue>> {
     return d
         .get_rows()
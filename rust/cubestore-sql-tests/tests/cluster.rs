@@ -2,6 +2,7 @@
 //! Note that each worker will also spawns 2 subprocesses for actual processing.
 use async_trait::async_trait;
 use cubestore::config::Config;
+use cubestore::queryplanner::query_executor::TransportCompression;
 use cubestore_sql_tests::multiproc::{
     run_multiproc_test, MultiProcTest, SignalInit, WaitCompletion, WorkerProc,
 };
@@ -11,7 +12,7 @@ use serde_derive::{Deserialize, Serialize};
 #[cfg(not(target_os = "windows"))]
 fn main() {
     // Prepare workers.
-    Config::configure_worker_services();
+    Config::default().configure_worker_services();
     procspawn::init(); // TODO: logs in worker processes.
 
     const METASTORE_PORT: u16 = 51336;
@@ -63,6 +64,12 @@ fn main() {
                                 .iter()
                                 .map(|p| format!("localhost:{}", p))
                                 .collect();
+                            // Test configs have no threshold, workers compress all results.
+                            c.transport_compression = TransportCompression::Lz4;
+                            // Duplicate every select slower than its peers to exercise
+                            // speculative execution.
+                            c.speculative_execution_slowdown = 1;
+                            c.speculative_execution_min_delay_ms = 0;
                             c
                         })
                         .start_test(|services| async move {
@@ -6,7 +6,7 @@ use tokio::runtime::Builder;
 #[cfg(not(target_os = "windows"))]
 fn main() {
     // Prepare workers.
-    Config::configure_worker_services();
+    Config::default().configure_worker_services();
     procspawn::init(); // TODO: logs on workers.
 
     run_sql_tests("multi_process", vec![], |test_name, test_fn| {
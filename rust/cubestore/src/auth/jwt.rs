@@ -0,0 +1,158 @@
+//! Validates bearer tokens that are JWTs signed by the identity provider. Tokens must have the
+//! `exp` claim and, if `CUBESTORE_AUTH_JWT_AUDIENCE` is set, the audience. The user is taken from
+//! the `sub` claim and groups from the `groups` claim.
+use crate::auth::{identity_from_claims, AuthProvider, Credentials, Identity};
+use crate::CubeError;
+use async_trait::async_trait;
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use serde_json::Value as JsonValue;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JwtKey {
+    /// Shared secret.
+    Hs256(String),
+    /// Path to the PEM file with the public key.
+    Rs256(String),
+}
+
+impl FromStr for JwtKey {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (algorithm, key) = match s.find(':') {
+            Some(i) if i + 1 < s.len() => (&s[..i], &s[i + 1..]),
+            _ => {
+                return Err(CubeError::user(format!(
+                    "JWT key must be specified as '<algorithm>:<key>', found: {}",
+                    s
+                )))
+            }
+        };
+        match algorithm.to_lowercase().as_str() {
+            "hs256" => Ok(JwtKey::Hs256(key.to_string())),
+            "rs256" => Ok(JwtKey::Rs256(key.to_string())),
+            _ => Err(CubeError::user(format!(
+                "Unsupported JWT algorithm: {}, expected hs256 or rs256",
+                algorithm
+            ))),
+        }
+    }
+}
+
+pub struct JwtAuthProvider {
+    algorithm: Algorithm,
+    /// Secret or the contents of the PEM file.
+    key: Vec<u8>,
+    audience: Option<String>,
+}
+
+impl JwtAuthProvider {
+    pub fn new(key: JwtKey, audience: Option<String>) -> Result<Self, CubeError> {
+        let (algorithm, key) = match key {
+            JwtKey::Hs256(secret) => (Algorithm::HS256, secret.into_bytes()),
+            JwtKey::Rs256(path) => {
+                let pem = std::fs::read(&path).map_err(|e| {
+                    CubeError::user(format!("Can't read JWT public key {}: {}", path, e))
+                })?;
+                DecodingKey::from_rsa_pem(&pem).map_err(|e| {
+                    CubeError::user(format!("Invalid JWT public key {}: {}", path, e))
+                })?;
+                (Algorithm::RS256, pem)
+            }
+        };
+        Ok(Self {
+            algorithm,
+            key,
+            audience,
+        })
+    }
+
+    fn validate(&self, token: &str) -> Result<Identity, CubeError> {
+        let key = match self.algorithm {
+            Algorithm::RS256 => DecodingKey::from_rsa_pem(&self.key)
+                .map_err(|e| CubeError::internal(e.to_string()))?,
+            _ => DecodingKey::from_secret(&self.key),
+        };
+        let mut validation = Validation::new(self.algorithm);
+        if let Some(audience) = &self.audience {
+            validation.set_audience(&[audience]);
+        }
+        let token = decode::<JsonValue>(token, &key, &validation)
+            .map_err(|e| CubeError::user(format!("Invalid token: {}", e)))?;
+        identity_from_claims(&token.claims, &["sub"])
+    }
+}
+
+#[async_trait]
+impl AuthProvider for JwtAuthProvider {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, CubeError> {
+        match credentials {
+            Credentials::Token(token) => Ok(Some(self.validate(token)?)),
+            Credentials::Password { .. } => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn hs256_tokens() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let token = |claims: JsonValue, secret: &str| {
+            Credentials::Token(
+                encode(
+                    &Header::default(),
+                    &claims,
+                    &EncodingKey::from_secret(secret.as_bytes()),
+                )
+                .unwrap(),
+            )
+        };
+        let provider = JwtAuthProvider::new(
+            "hs256:secret".parse().unwrap(),
+            Some("cubestore".to_string()),
+        )
+        .unwrap();
+
+        let valid = json!({"sub": "u1", "aud": "cubestore", "exp": now + 60, "groups": ["a"]});
+        assert_eq!(
+            provider
+                .authenticate(&token(valid.clone(), "secret"))
+                .await
+                .unwrap(),
+            Some(Identity {
+                user: "u1".to_string(),
+                groups: vec!["a".to_string()]
+            })
+        );
+        assert!(provider.authenticate(&token(valid, "other")).await.is_err());
+        let expired = json!({"sub": "u1", "aud": "cubestore", "exp": now - 3600});
+        assert!(provider
+            .authenticate(&token(expired, "secret"))
+            .await
+            .is_err());
+        let other_audience = json!({"sub": "u1", "aud": "other", "exp": now + 60});
+        assert!(provider
+            .authenticate(&token(other_audience, "secret"))
+            .await
+            .is_err());
+        let password = Credentials::Password {
+            user: "u1".to_string(),
+            password: "secret".to_string(),
+        };
+        assert_eq!(provider.authenticate(&password).await.unwrap(), None);
+
+        assert!("hs256".parse::<JwtKey>().is_err());
+        assert!("es256:key".parse::<JwtKey>().is_err());
+        assert!(JwtAuthProvider::new(JwtKey::Rs256("/nonexistent.pem".to_string()), None).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! Checks user names and passwords with a simple bind to an LDAP server. Groups of the user are
+//! read from the `memberOf` attribute of its entry, a group is named by the value of the first
+//! RDN of its DN, e.g. `admins` for `cn=admins,ou=groups,dc=example,dc=com`.
+use crate::auth::{AuthProvider, Credentials, Identity};
+use crate::CubeError;
+use async_trait::async_trait;
+use ldap3::{dn_escape, LdapConnAsync, LdapError, Scope, SearchEntry};
+
+pub struct LdapAuthProvider {
+    url: String,
+    /// DN of users with `{user}` in place of the user name.
+    user_dn: String,
+}
+
+impl LdapAuthProvider {
+    pub fn new(url: String, user_dn: String) -> Self {
+        Self { url, user_dn }
+    }
+
+    fn dn(&self, user: &str) -> String {
+        self.user_dn.replace("{user}", &dn_escape(user))
+    }
+
+    async fn bind(&self, user: &str, password: &str) -> Result<Identity, LdapError> {
+        let dn = self.dn(user);
+        let (conn, mut ldap) = LdapConnAsync::new(&self.url).await?;
+        ldap3::drive!(conn);
+        ldap.simple_bind(&dn, password).await?.success()?;
+        let (entries, _) = ldap
+            .search(&dn, Scope::Base, "(objectClass=*)", vec!["memberOf"])
+            .await?
+            .success()?;
+        ldap.unbind().await?;
+        let groups = entries
+            .into_iter()
+            .flat_map(|e| {
+                SearchEntry::construct(e)
+                    .attrs
+                    .remove("memberOf")
+                    .unwrap_or_default()
+            })
+            .map(|g| group_name(&g))
+            .collect();
+        Ok(Identity {
+            user: user.to_string(),
+            groups,
+        })
+    }
+}
+
+/// Value of the first RDN of the group DN.
+fn group_name(dn: &str) -> String {
+    let mut name = String::new();
+    let mut in_value = false;
+    let mut chars = dn.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    name.push(c)
+                }
+            }
+            ',' | '+' => break,
+            '=' if !in_value => {
+                in_value = true;
+                name.clear();
+            }
+            c => name.push(c),
+        }
+    }
+    name.trim().to_string()
+}
+
+#[async_trait]
+impl AuthProvider for LdapAuthProvider {
+    async fn authenticate(&self, credentials: &Credentials) -> Result<Option<Identity>, CubeError> {
+        let (user, password) = match credentials {
+            Credentials::Password { user, password } => (user, password),
+            Credentials::Token(_) => return Ok(None),
+        };
+        // Binds without a password are anonymous and succeed for any DN.
+        if user.is_empty() || password.is_empty() {
+            return Err(CubeError::user(
+                "User and password are required".to_string(),
+            ));
+        }
+        match self.bind(user, password).await {
+            Ok(identity) => Ok(Some(identity)),
+            Err(LdapError::LdapResult { result }) => Err(CubeError::user(format!(
+                "LDAP authentication of {} failed: {}",
+                user, result
+            ))),
+            Err(e) => Err(CubeError::internal(format!(
+                "LDAP authentication of {} failed: {}",
+                user, e
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn dn_and_groups() {
+        let provider = LdapAuthProvider::new(
+            "ldap://localhost:389".to_string(),
+            "uid={user},ou=people,dc=example,dc=com".to_string(),
+        );
+        let dn = provider.dn("doe, john");
+        assert!(dn.starts_with("uid=doe\\"), "{}", dn);
+        assert!(dn.ends_with(" john,ou=people,dc=example,dc=com"), "{}", dn);
+        assert_eq!(
+            group_name("cn=admins,ou=groups,dc=example,dc=com"),
+            "admins"
+        );
+        assert_eq!(group_name("CN=Data\\, BI+OU=x,DC=example"), "Data, BI");
+        assert_eq!(group_name("admins"), "admins");
+
+        let empty_password = Credentials::Password {
+            user: "john".to_string(),
+            password: "".to_string(),
+        };
+        assert!(provider.authenticate(&empty_password).await.is_err());
+        assert_eq!(
+            provider
+                .authenticate(&Credentials::Token("t".to_string()))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,567 @@
+//! Micro-benchmark run with `cubestored bench [options]`, to compare hardware and releases. It
+//! starts a single node in [BenchOptions::dir], imports a generated table and runs a fixed suite of
+//! queries against it, then prints latencies and throughput of each query. The table is
+//! `bench.facts (d0 text, .., m0 int, .., ts timestamp)`, values of dimensions are uniformly
+//! distributed and `bench.labels (d0 text, label text)` has a row per value of `d0` for joins.
+//! Data is generated from a fixed seed, so runs with the same options read the same data.
+//! With `--dataset tpch` the tables and queries are the ones of [tpch] instead.
+//! Other settings, e.g. `CUBESTORE_SELECT_WORKERS`, are read from the environment as usual.
+pub mod tpch;
+
+use crate::config::{Config, FileStoreProvider};
+use crate::sql::SqlService;
+use crate::CubeError;
+use futures::stream::{self, StreamExt};
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const USAGE: &str = "Usage: cubestored bench [options]
+    --dataset <facts|tpch>     generated table or TPC-H style tables, facts by default
+    --scale <n>                scale factor of tpch, 0.1 by default
+    --rows <n>                 rows in the table, 1000000 by default
+    --dimensions <n>           text columns, 3 by default
+    --cardinality <n>[,<n>..]  distinct values of each dimension, the last one is used for the rest,
+                               1000 by default
+    --measures <n>             int columns, 2 by default
+    --days <n>                 days timestamps are spread over, 30 by default
+    --files <n>                CSV files the table is imported from in parallel, the largest
+                               tables of tpch are split the same way, 4 by default
+    --iterations <n>           runs of each query, 10 by default
+    --concurrency <n>          runs of the same query in flight, 1 by default
+    --dir <path>               directory of the node and generated files, removed before the run";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dataset {
+    Facts,
+    Tpch,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchOptions {
+    pub dataset: Dataset,
+    /// Scale factor of [Dataset::Tpch], other options of the table are ignored.
+    pub scale: f64,
+    pub rows: u64,
+    pub dimensions: usize,
+    /// Has a value per dimension.
+    pub cardinality: Vec<u64>,
+    pub measures: usize,
+    pub days: u64,
+    pub files: usize,
+    pub iterations: usize,
+    pub concurrency: usize,
+    pub dir: PathBuf,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        BenchOptions {
+            dataset: Dataset::Facts,
+            scale: 0.1,
+            rows: 1000000,
+            dimensions: 3,
+            cardinality: vec![1000; 3],
+            measures: 2,
+            days: 30,
+            files: 4,
+            iterations: 10,
+            concurrency: 1,
+            dir: std::env::temp_dir().join("cubestore-bench"),
+        }
+    }
+}
+
+impl BenchOptions {
+    /// Arguments after `bench`.
+    pub fn parse(args: &[String]) -> Result<BenchOptions, CubeError> {
+        let mut options = BenchOptions::default();
+        let mut cardinality = vec![1000];
+        let mut args = args.iter();
+        while let Some(name) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| CubeError::user(format!("Missing value of {}\n{}", name, USAGE)))?;
+            let number = || {
+                value.parse::<u64>().ok().filter(|v| *v > 0).ok_or_else(|| {
+                    CubeError::user(format!(
+                        "Value of {} must be a positive integer, found: {}",
+                        name, value
+                    ))
+                })
+            };
+            match name.as_str() {
+                "--dataset" => {
+                    options.dataset = match value.as_str() {
+                        "facts" => Dataset::Facts,
+                        "tpch" => Dataset::Tpch,
+                        _ => {
+                            return Err(CubeError::user(format!(
+                                "Value of --dataset must be facts or tpch, found: {}",
+                                value
+                            )))
+                        }
+                    }
+                }
+                "--scale" => {
+                    options.scale =
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|v| *v > 0.0)
+                            .ok_or_else(|| {
+                                CubeError::user(format!(
+                                    "Value of --scale must be a positive number, found: {}",
+                                    value
+                                ))
+                            })?
+                }
+                "--rows" => options.rows = number()?,
+                "--dimensions" => options.dimensions = number()? as usize,
+                "--cardinality" => {
+                    cardinality = value
+                        .split(',')
+                        .map(|v| v.trim().parse::<u64>().ok().filter(|v| *v > 0))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| {
+                            CubeError::user(format!(
+                            "Value of --cardinality must be a list of positive integers, found: {}",
+                            value
+                        ))
+                        })?
+                }
+                "--measures" => options.measures = number()? as usize,
+                "--days" => options.days = number()?,
+                "--files" => options.files = number()? as usize,
+                "--iterations" => options.iterations = number()? as usize,
+                "--concurrency" => options.concurrency = number()? as usize,
+                "--dir" => options.dir = PathBuf::from(value),
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "Unknown option: {}\n{}",
+                        name, USAGE
+                    )))
+                }
+            }
+        }
+        let last = *cardinality.last().unwrap();
+        cardinality.resize(options.dimensions, last);
+        options.cardinality = cardinality;
+        Ok(options)
+    }
+
+    /// Config of the node the benchmark runs on. Removes results of previous runs.
+    pub fn config(&self) -> Config {
+        let _ = fs::remove_dir_all(&self.dir);
+        let dir = self.dir.clone();
+        Config::default().update_config(move |mut c| {
+            c.data_dir = dir.join("data");
+            c.store_provider = FileStoreProvider::Filesystem {
+                remote_dir: Some(dir.join("remote")),
+            };
+            c.bind_address = None;
+            c.http_bind_address = None;
+            c.select_workers = Vec::new();
+            c.read_replica_workers = Vec::new();
+            c.worker_bind_address = None;
+            c.metastore_bind_address = None;
+            c.metastore_remote_address = None;
+            c.max_partitions_per_query = 0;
+            c.max_rows_per_query = 0;
+            c
+        })
+    }
+
+    fn source_dir(&self) -> PathBuf {
+        self.dir.join("source")
+    }
+}
+
+#[derive(Debug)]
+pub struct QueryStats {
+    pub name: &'static str,
+    /// Sorted.
+    pub latencies: Vec<Duration>,
+    pub total: Duration,
+}
+
+impl QueryStats {
+    fn percentile(&self, p: usize) -> Duration {
+        let i = (self.latencies.len() * p + 99) / 100;
+        self.latencies[i.max(1) - 1]
+    }
+
+    fn queries_per_sec(&self) -> f64 {
+        self.latencies.len() as f64 / self.total.as_secs_f64()
+    }
+}
+
+#[derive(Debug)]
+pub struct BenchReport {
+    pub rows: u64,
+    pub import: Duration,
+    pub queries: Vec<QueryStats>,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        writeln!(
+            f,
+            "Import: {} rows in {:.1} s, {:.0} rows/s",
+            self.rows,
+            self.import.as_secs_f64(),
+            self.rows as f64 / self.import.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "{:<16}{:>6}{:>10}{:>10}{:>10}{:>10}{:>12}",
+            "query", "runs", "min ms", "p50 ms", "p95 ms", "max ms", "queries/s"
+        )?;
+        for q in &self.queries {
+            writeln!(
+                f,
+                "{:<16}{:>6}{:>10.1}{:>10.1}{:>10.1}{:>10.1}{:>12.1}",
+                q.name,
+                q.latencies.len(),
+                ms(q.latencies[0]),
+                ms(q.percentile(50)),
+                ms(q.percentile(95)),
+                ms(*q.latencies.last().unwrap()),
+                q.queries_per_sec()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Generates and imports the tables, then runs each query of the suite.
+pub async fn run(
+    service: Arc<dyn SqlService>,
+    options: &BenchOptions,
+) -> Result<BenchReport, CubeError> {
+    let (rows, import, suite) = match options.dataset {
+        Dataset::Facts => {
+            if options.dimensions == 0 || options.measures == 0 {
+                return Err(CubeError::user(
+                    "At least one dimension and one measure are required".to_string(),
+                ));
+            }
+            let files = generate_files(options)?;
+            let start = Instant::now();
+            import(service.as_ref(), options, &files).await?;
+            (options.rows, start.elapsed(), queries_suite(options))
+        }
+        Dataset::Tpch => {
+            let tables = tpch::generate_files(options)?;
+            let start = Instant::now();
+            tpch::import(service.as_ref(), &tables).await?;
+            (
+                tables.iter().map(|t| t.rows).sum(),
+                start.elapsed(),
+                tpch::queries_suite(),
+            )
+        }
+    };
+
+    let mut queries = Vec::new();
+    for (name, query) in suite {
+        queries.push(run_query(service.clone(), options, name, query).await?);
+    }
+    Ok(BenchReport {
+        rows,
+        import,
+        queries,
+    })
+}
+
+fn generate_files(options: &BenchOptions) -> Result<Vec<PathBuf>, CubeError> {
+    let dir = options.source_dir();
+    fs::create_dir_all(&dir)?;
+    let mut files = Vec::new();
+    let rows_per_file = (options.rows + options.files as u64 - 1) / options.files as u64;
+    for file in 0..options.files {
+        let rows =
+            rows_per_file.min(options.rows - (rows_per_file * file as u64).min(options.rows));
+        let path = dir.join(format!("facts-{}.csv", file));
+        write_facts(&path, options, file as u64, rows)?;
+        files.push(path);
+    }
+    let labels = dir.join("labels.csv");
+    let mut out = BufWriter::new(File::create(&labels)?);
+    writeln!(out, "d0,label")?;
+    for v in 0..options.cardinality[0] {
+        writeln!(out, "v{},label {}", v, v % 10)?;
+    }
+    out.flush()?;
+    files.push(labels);
+    Ok(files)
+}
+
+fn write_facts(path: &Path, options: &BenchOptions, seed: u64, rows: u64) -> Result<(), CubeError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = (0..options.dimensions)
+        .map(|i| format!("d{}", i))
+        .chain((0..options.measures).map(|i| format!("m{}", i)))
+        .chain(std::iter::once("ts".to_string()))
+        .join(",");
+    writeln!(out, "{}", header)?;
+    let start = chrono::NaiveDate::from_ymd(2021, 1, 1).and_hms(0, 0, 0);
+    for _ in 0..rows {
+        for c in &options.cardinality {
+            write!(out, "v{},", rng.gen_range(0..*c))?;
+        }
+        for _ in 0..options.measures {
+            write!(out, "{},", rng.gen_range(0..1000))?;
+        }
+        let ts = start + chrono::Duration::seconds(rng.gen_range(0..options.days as i64 * 86400));
+        writeln!(out, "{}", ts.format("%Y-%m-%dT%H:%M:%S.000Z"))?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+async fn import(
+    service: &dyn SqlService,
+    options: &BenchOptions,
+    files: &[PathBuf],
+) -> Result<(), CubeError> {
+    let (labels, facts) = files.split_last().unwrap();
+    let location = |files: &[PathBuf]| {
+        files
+            .iter()
+            .map(|f| format!("'{}'", f.to_str().unwrap()))
+            .join(", ")
+    };
+    let columns = (0..options.dimensions)
+        .map(|i| format!("d{} text", i))
+        .chain((0..options.measures).map(|i| format!("m{} int", i)))
+        .chain(std::iter::once("ts timestamp".to_string()))
+        .join(", ");
+    service.exec_query("CREATE SCHEMA bench").await?;
+    service
+        .exec_query(&format!(
+            "CREATE TABLE bench.facts ({}) LOCATION {}",
+            columns,
+            location(facts)
+        ))
+        .await?;
+    service
+        .exec_query(&format!(
+            "CREATE TABLE bench.labels (d0 text, label text) LOCATION {}",
+            location(std::slice::from_ref(labels))
+        ))
+        .await?;
+    Ok(())
+}
+
+fn queries_suite(options: &BenchOptions) -> Vec<(&'static str, String)> {
+    let measures = (0..options.measures)
+        .map(|i| format!("SUM(m{})", i))
+        .join(", ");
+    let last = options.dimensions - 1;
+    vec![
+        (
+            "scan",
+            format!("SELECT COUNT(*), {} FROM bench.facts", measures),
+        ),
+        (
+            "filter",
+            format!(
+                "SELECT COUNT(*), {} FROM bench.facts WHERE d0 = 'v1'",
+                measures
+            ),
+        ),
+        (
+            "group_by",
+            format!(
+                "SELECT d{}, {} FROM bench.facts GROUP BY 1 ORDER BY 1",
+                last, measures
+            ),
+        ),
+        (
+            "group_by_sorted",
+            "SELECT d0, COUNT(*) FROM bench.facts GROUP BY 1 ORDER BY 1".to_string(),
+        ),
+        (
+            "time_series",
+            "SELECT DATE_TRUNC('day', ts), COUNT(*) FROM bench.facts GROUP BY 1 ORDER BY 1"
+                .to_string(),
+        ),
+        (
+            "top_k",
+            "SELECT d0, SUM(m0) FROM bench.facts GROUP BY 1 ORDER BY 2 DESC LIMIT 10".to_string(),
+        ),
+        (
+            "join",
+            "SELECT l.label, SUM(f.m0) FROM bench.facts f JOIN bench.labels l ON f.d0 = l.d0 \
+             GROUP BY 1 ORDER BY 1"
+                .to_string(),
+        ),
+    ]
+}
+
+async fn run_query(
+    service: Arc<dyn SqlService>,
+    options: &BenchOptions,
+    name: &'static str,
+    query: String,
+) -> Result<QueryStats, CubeError> {
+    let start = Instant::now();
+    let mut latencies = stream::iter(0..options.iterations)
+        .map(|i| {
+            let service = service.clone();
+            // Results of the same query text are cached.
+            let query = format!("{} /* run {} */", query, i);
+            async move {
+                let start = Instant::now();
+                service.exec_query(&query).await?;
+                Ok(start.elapsed())
+            }
+        })
+        .buffer_unordered(options.concurrency)
+        .collect::<Vec<Result<Duration, CubeError>>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    let total = start.elapsed();
+    latencies.sort();
+    Ok(QueryStats {
+        name,
+        latencies,
+        total,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::TableValue;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn options() {
+        let options =
+            BenchOptions::parse(&args("--rows 100 --dimensions 4 --cardinality 10,20")).unwrap();
+        assert_eq!(options.rows, 100);
+        assert_eq!(options.cardinality, vec![10, 20, 20, 20]);
+        assert_eq!(BenchOptions::parse(&[]).unwrap().cardinality, vec![1000; 3]);
+        assert!(BenchOptions::parse(&args("--rows 0")).is_err());
+        assert!(BenchOptions::parse(&args("--rows")).is_err());
+        assert!(BenchOptions::parse(&args("--cardinality 1,x")).is_err());
+        assert!(BenchOptions::parse(&args("--size 1")).is_err());
+        let options = BenchOptions::parse(&args("--dataset tpch --scale 0.01")).unwrap();
+        assert_eq!(options.dataset, Dataset::Tpch);
+        assert_eq!(options.scale, 0.01);
+        assert!(BenchOptions::parse(&args("--dataset ssb")).is_err());
+        assert!(BenchOptions::parse(&args("--scale 0")).is_err());
+    }
+
+    #[tokio::test]
+    async fn suite() {
+        Config::test("bench_suite")
+            .start_test(async move |services| {
+                let options = BenchOptions {
+                    rows: 50,
+                    cardinality: vec![5, 3, 2],
+                    files: 3,
+                    iterations: 4,
+                    concurrency: 2,
+                    dir: std::env::temp_dir().join("bench_suite"),
+                    ..BenchOptions::default()
+                };
+                let _ = fs::remove_dir_all(&options.dir);
+                let report = run(services.sql_service.clone(), &options).await.unwrap();
+                assert_eq!(
+                    report.queries.iter().map(|q| q.name).collect::<Vec<_>>(),
+                    vec![
+                        "scan",
+                        "filter",
+                        "group_by",
+                        "group_by_sorted",
+                        "time_series",
+                        "top_k",
+                        "join"
+                    ]
+                );
+                assert!(report.queries.iter().all(|q| q.latencies.len() == 4));
+                let count = services
+                    .sql_service
+                    .exec_query("SELECT COUNT(*) FROM bench.facts")
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    count.get_rows()[0].values()[0],
+                    crate::table::TableValue::Int(50)
+                );
+                assert!(report.to_string().contains("group_by_sorted"));
+                let _ = fs::remove_dir_all(&options.dir);
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn tpch_suite() {
+        Config::test("bench_tpch_suite")
+            .start_test(async move |services| {
+                let options = BenchOptions {
+                    dataset: Dataset::Tpch,
+                    scale: 0.001,
+                    files: 2,
+                    iterations: 1,
+                    dir: std::env::temp_dir().join("bench_tpch_suite"),
+                    ..BenchOptions::default()
+                };
+                let _ = fs::remove_dir_all(&options.dir);
+                let report = run(services.sql_service.clone(), &options).await.unwrap();
+                assert_eq!(
+                    report.queries.iter().map(|q| q.name).collect::<Vec<_>>(),
+                    vec![
+                        "q1_pricing",
+                        "q3_shipping",
+                        "q6_forecast",
+                        "q12_ship_modes",
+                        "q13_segments",
+                        "q14_promotion",
+                        "q15_suppliers"
+                    ]
+                );
+                let count = |table: &str| {
+                    let service = services.sql_service.clone();
+                    let query = format!("SELECT COUNT(*) FROM tpch.{}", table);
+                    async move {
+                        service.exec_query(&query).await.unwrap().get_rows()[0].values()[0].clone()
+                    }
+                };
+                assert_eq!(count("nation").await, TableValue::Int(25));
+                assert_eq!(count("orders").await, TableValue::Int(1500));
+                assert_eq!(count("customer").await, TableValue::Int(150));
+                // Every order has between 1 and 7 line items.
+                match count("lineitem").await {
+                    TableValue::Int(n) => assert!(1500 <= n && n <= 7 * 1500),
+                    v => panic!("unexpected count: {:?}", v),
+                }
+                let rows = services
+                    .sql_service
+                    .exec_query(
+                        "SELECT COUNT(*) FROM tpch.lineitem l \
+                         JOIN tpch.orders o ON l.l_orderkey = o.o_orderkey",
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(rows.get_rows()[0].values()[0], count("lineitem").await);
+                let _ = fs::remove_dir_all(&options.dir);
+            })
+            .await;
+    }
+}
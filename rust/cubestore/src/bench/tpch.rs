@@ -0,0 +1,477 @@
+//! TPC-H style dataset of `cubestored bench --dataset tpch`, to track performance of joins and
+//! aggregations release over release. Tables of the `tpch` schema follow the TPC-H schema with
+//! fewer columns and have the row counts of the specification at [BenchOptions::scale], e.g. 6
+//! million rows of `lineitem` at scale 1. Values follow the distributions of the specification,
+//! but are generated from a fixed seed and don't match the output of `dbgen`.
+//!
+//! Tables are sorted by their keys and have indexes on the foreign keys used by the suite, so
+//! its joins are merge joins.
+use crate::bench::BenchOptions;
+use crate::sql::SqlService;
+use crate::CubeError;
+use chrono::NaiveDate;
+use itertools::Itertools;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+struct TableDef {
+    name: &'static str,
+    columns: &'static str,
+    /// Name and columns of each index.
+    indexes: &'static [(&'static str, &'static str)],
+}
+
+const TABLES: [TableDef; 7] = [
+    TableDef {
+        name: "region",
+        columns: "r_regionkey int, r_name text",
+        indexes: &[],
+    },
+    TableDef {
+        name: "nation",
+        columns: "n_nationkey int, n_name text, n_regionkey int",
+        indexes: &[],
+    },
+    TableDef {
+        name: "supplier",
+        columns: "s_suppkey int, s_name text, s_nationkey int, s_acctbal double",
+        indexes: &[],
+    },
+    TableDef {
+        name: "customer",
+        columns: "c_custkey int, c_name text, c_nationkey int, c_acctbal double, \
+                  c_mktsegment text",
+        indexes: &[],
+    },
+    TableDef {
+        name: "part",
+        columns: "p_partkey int, p_name text, p_brand text, p_type text, p_size int, \
+                  p_retailprice double",
+        indexes: &[],
+    },
+    TableDef {
+        name: "orders",
+        columns: "o_orderkey int, o_custkey int, o_orderstatus text, o_totalprice double, \
+                  o_orderdate timestamp, o_orderpriority text",
+        indexes: &[("orders_custkey", "o_custkey")],
+    },
+    TableDef {
+        name: "lineitem",
+        columns: "l_orderkey int, l_partkey int, l_suppkey int, l_linenumber int, \
+                  l_quantity int, l_extendedprice double, l_discount double, l_tax double, \
+                  l_returnflag text, l_linestatus text, l_shipdate timestamp, l_shipmode text",
+        indexes: &[
+            ("lineitem_partkey", "l_partkey"),
+            ("lineitem_suppkey", "l_suppkey"),
+        ],
+    },
+];
+
+const REGIONS: [&str; 5] = ["AFRICA", "AMERICA", "ASIA", "EUROPE", "MIDDLE EAST"];
+
+/// Names and region keys.
+const NATIONS: [(&str, u64); 25] = [
+    ("ALGERIA", 0),
+    ("ARGENTINA", 1),
+    ("BRAZIL", 1),
+    ("CANADA", 1),
+    ("EGYPT", 4),
+    ("ETHIOPIA", 0),
+    ("FRANCE", 3),
+    ("GERMANY", 3),
+    ("INDIA", 2),
+    ("INDONESIA", 2),
+    ("IRAN", 4),
+    ("IRAQ", 4),
+    ("JAPAN", 2),
+    ("JORDAN", 4),
+    ("KENYA", 0),
+    ("MOROCCO", 0),
+    ("MOZAMBIQUE", 0),
+    ("PERU", 1),
+    ("CHINA", 2),
+    ("ROMANIA", 3),
+    ("SAUDI ARABIA", 4),
+    ("VIETNAM", 2),
+    ("RUSSIA", 3),
+    ("UNITED KINGDOM", 3),
+    ("UNITED STATES", 1),
+];
+
+const SEGMENTS: [&str; 5] = [
+    "AUTOMOBILE",
+    "BUILDING",
+    "FURNITURE",
+    "HOUSEHOLD",
+    "MACHINERY",
+];
+
+const PRIORITIES: [&str; 5] = ["1-URGENT", "2-HIGH", "3-MEDIUM", "4-NOT SPECIFIED", "5-LOW"];
+
+const SHIP_MODES: [&str; 7] = ["AIR", "FOB", "MAIL", "RAIL", "REG AIR", "SHIP", "TRUCK"];
+
+const TYPE_SIZES: [&str; 6] = ["STANDARD", "SMALL", "MEDIUM", "LARGE", "ECONOMY", "PROMO"];
+
+const TYPE_FINISHES: [&str; 5] = ["ANODIZED", "BURNISHED", "PLATED", "POLISHED", "BRUSHED"];
+
+const TYPE_MATERIALS: [&str; 5] = ["TIN", "NICKEL", "BRASS", "STEEL", "COPPER"];
+
+const COLORS: [&str; 16] = [
+    "almond", "antique", "azure", "beige", "black", "blue", "brown", "coral", "cyan", "forest",
+    "green", "ivory", "lemon", "navy", "olive", "red",
+];
+
+/// Days between the first and the last order date, 1992-01-01 and 1998-08-02.
+const ORDER_DAYS: i64 = 2405;
+
+/// Days between the first order date and 1995-06-17, the current date of the specification.
+const CURRENT_DAY: i64 = 1263;
+
+pub struct TpchTable {
+    pub name: &'static str,
+    pub files: Vec<PathBuf>,
+    pub rows: u64,
+}
+
+/// Row counts of tables with a scale factor.
+struct Counts {
+    suppliers: u64,
+    customers: u64,
+    parts: u64,
+    orders: u64,
+}
+
+impl Counts {
+    fn new(scale: f64) -> Counts {
+        let scaled = |rows: u64| ((rows as f64 * scale).round() as u64).max(1);
+        Counts {
+            suppliers: scaled(10000),
+            customers: scaled(150000),
+            parts: scaled(200000),
+            orders: scaled(1500000),
+        }
+    }
+}
+
+/// Writes CSV files of all tables. `orders` and `lineitem` are split into [BenchOptions::files].
+pub fn generate_files(options: &BenchOptions) -> Result<Vec<TpchTable>, CubeError> {
+    let dir = options.source_dir();
+    fs::create_dir_all(&dir)?;
+    let counts = Counts::new(options.scale);
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut tables = Vec::new();
+
+    tables.push(write_table(
+        &dir,
+        "region",
+        REGIONS.len() as u64,
+        |out, key| writeln!(out, "{},{}", key, REGIONS[key as usize]),
+    )?);
+    tables.push(write_table(
+        &dir,
+        "nation",
+        NATIONS.len() as u64,
+        |out, key| {
+            let (name, region) = NATIONS[key as usize];
+            writeln!(out, "{},{},{}", key, name, region)
+        },
+    )?);
+    tables.push(write_table(
+        &dir,
+        "supplier",
+        counts.suppliers,
+        |out, key| {
+            let key = key + 1;
+            writeln!(
+                out,
+                "{},Supplier#{:09},{},{:.2}",
+                key,
+                key,
+                rng.gen_range(0..NATIONS.len()),
+                rng.gen_range(-99999..1000000) as f64 / 100.0
+            )
+        },
+    )?);
+    tables.push(write_table(
+        &dir,
+        "customer",
+        counts.customers,
+        |out, key| {
+            let key = key + 1;
+            writeln!(
+                out,
+                "{},Customer#{:09},{},{:.2},{}",
+                key,
+                key,
+                rng.gen_range(0..NATIONS.len()),
+                rng.gen_range(-99999..1000000) as f64 / 100.0,
+                SEGMENTS.choose(&mut rng).unwrap()
+            )
+        },
+    )?);
+    tables.push(write_table(&dir, "part", counts.parts, |out, key| {
+        let key = key + 1;
+        writeln!(
+            out,
+            "{},{},Brand#{}{},{} {} {},{},{:.2}",
+            key,
+            COLORS.choose_multiple(&mut rng, 3).join(" "),
+            rng.gen_range(1..6),
+            rng.gen_range(1..6),
+            TYPE_SIZES.choose(&mut rng).unwrap(),
+            TYPE_FINISHES.choose(&mut rng).unwrap(),
+            TYPE_MATERIALS.choose(&mut rng).unwrap(),
+            rng.gen_range(1..51),
+            retail_price(key)
+        )
+    })?);
+
+    let mut orders = TpchTable {
+        name: "orders",
+        files: Vec::new(),
+        rows: counts.orders,
+    };
+    let mut lineitem = TpchTable {
+        name: "lineitem",
+        files: Vec::new(),
+        rows: 0,
+    };
+    let per_file = (counts.orders + options.files as u64 - 1) / options.files as u64;
+    for file in 0..options.files as u64 {
+        let first = (per_file * file).min(counts.orders);
+        let last = (per_file * (file + 1)).min(counts.orders);
+        let orders_path = dir.join(format!("orders-{}.csv", file));
+        let lineitem_path = dir.join(format!("lineitem-{}.csv", file));
+        lineitem.rows += write_orders(&orders_path, &lineitem_path, &counts, file, first..last)?;
+        orders.files.push(orders_path);
+        lineitem.files.push(lineitem_path);
+    }
+    tables.push(orders);
+    tables.push(lineitem);
+    Ok(tables)
+}
+
+/// Writes a table that fits a single file, `write_row` gets the index of each row.
+fn write_table(
+    dir: &Path,
+    name: &'static str,
+    rows: u64,
+    mut write_row: impl FnMut(&mut dyn Write, u64) -> std::io::Result<()>,
+) -> Result<TpchTable, CubeError> {
+    let path = dir.join(format!("{}.csv", name));
+    let mut out = BufWriter::new(File::create(&path)?);
+    writeln!(out, "{}", header(name))?;
+    for i in 0..rows {
+        write_row(&mut out, i)?;
+    }
+    out.flush()?;
+    Ok(TpchTable {
+        name,
+        files: vec![path],
+        rows,
+    })
+}
+
+/// Writes orders with keys in `keys` and their line items, returns the number of line items.
+fn write_orders(
+    orders_path: &Path,
+    lineitem_path: &Path,
+    counts: &Counts,
+    seed: u64,
+    keys: std::ops::Range<u64>,
+) -> Result<u64, CubeError> {
+    let mut rng = StdRng::seed_from_u64(1000 + seed);
+    let mut orders = BufWriter::new(File::create(orders_path)?);
+    let mut lineitem = BufWriter::new(File::create(lineitem_path)?);
+    writeln!(orders, "{}", header("orders"))?;
+    writeln!(lineitem, "{}", header("lineitem"))?;
+    let mut line_items = 0;
+    for key in keys {
+        let key = key + 1;
+        let order_day = rng.gen_range(0..ORDER_DAYS);
+        let mut total_price = 0.0;
+        let mut statuses = Vec::new();
+        for line in 1..rng.gen_range(2..9) {
+            let part = rng.gen_range(1..counts.parts + 1);
+            let quantity = rng.gen_range(1..51);
+            let price = retail_price(part) * quantity as f64;
+            let discount = rng.gen_range(0..11) as f64 / 100.0;
+            let tax = rng.gen_range(0..9) as f64 / 100.0;
+            let ship_day = order_day + rng.gen_range(1..122);
+            let receipt_day = ship_day + rng.gen_range(1..31);
+            let return_flag = if receipt_day <= CURRENT_DAY {
+                if rng.gen_bool(0.5) {
+                    "R"
+                } else {
+                    "A"
+                }
+            } else {
+                "N"
+            };
+            let status = if ship_day > CURRENT_DAY { "O" } else { "F" };
+            writeln!(
+                lineitem,
+                "{},{},{},{},{},{:.2},{:.2},{:.2},{},{},{},{}",
+                key,
+                part,
+                rng.gen_range(1..counts.suppliers + 1),
+                line,
+                quantity,
+                price,
+                discount,
+                tax,
+                return_flag,
+                status,
+                date(ship_day),
+                SHIP_MODES.choose(&mut rng).unwrap()
+            )?;
+            total_price += price * (1.0 + tax) * (1.0 - discount);
+            statuses.push(status);
+            line_items += 1;
+        }
+        let status = if statuses.iter().all(|s| *s == "F") {
+            "F"
+        } else if statuses.iter().all(|s| *s == "O") {
+            "O"
+        } else {
+            "P"
+        };
+        writeln!(
+            orders,
+            "{},{},{},{:.2},{},{}",
+            key,
+            rng.gen_range(1..counts.customers + 1),
+            status,
+            total_price,
+            date(order_day),
+            PRIORITIES.choose(&mut rng).unwrap()
+        )?;
+    }
+    orders.flush()?;
+    lineitem.flush()?;
+    Ok(line_items)
+}
+
+fn header(table: &str) -> String {
+    let def = TABLES.iter().find(|t| t.name == table).unwrap();
+    def.columns
+        .split(',')
+        .map(|c| c.trim().split(' ').next().unwrap())
+        .join(",")
+}
+
+fn retail_price(part: u64) -> f64 {
+    (90000 + ((part / 10) % 20001) + 100 * (part % 1000)) as f64 / 100.0
+}
+
+/// Timestamp of the day `day` days after the first order date.
+fn date(day: i64) -> String {
+    let date = NaiveDate::from_ymd(1992, 1, 1) + chrono::Duration::days(day);
+    date.format("%Y-%m-%dT00:00:00.000Z").to_string()
+}
+
+pub async fn import(service: &dyn SqlService, tables: &[TpchTable]) -> Result<(), CubeError> {
+    service.exec_query("CREATE SCHEMA tpch").await?;
+    for table in tables {
+        let def = TABLES.iter().find(|t| t.name == table.name).unwrap();
+        let indexes = def
+            .indexes
+            .iter()
+            .map(|(name, columns)| format!(" INDEX {} ({})", name, columns))
+            .join("");
+        let location = table
+            .files
+            .iter()
+            .map(|f| format!("'{}'", f.to_str().unwrap()))
+            .join(", ");
+        service
+            .exec_query(&format!(
+                "CREATE TABLE tpch.{} ({}){} LOCATION {}",
+                table.name, def.columns, indexes, location
+            ))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Queries derived from TPC-H queries with the same number, with joins limited to two tables.
+pub fn queries_suite() -> Vec<(&'static str, String)> {
+    let revenue = "l_extendedprice * (1 - l_discount)";
+    vec![
+        (
+            "q1_pricing",
+            format!(
+                "SELECT l_returnflag, l_linestatus, SUM(l_quantity), SUM(l_extendedprice), \
+                 SUM({revenue}), SUM({revenue} * (1 + l_tax)), AVG(l_quantity), \
+                 AVG(l_extendedprice), AVG(l_discount), COUNT(*) \
+                 FROM tpch.lineitem \
+                 WHERE l_shipdate <= to_timestamp('1998-09-02T00:00:00.000Z') \
+                 GROUP BY 1, 2 ORDER BY 1, 2",
+                revenue = revenue
+            ),
+        ),
+        (
+            "q3_shipping",
+            format!(
+                "SELECT l_orderkey, SUM({}), o_orderdate \
+                 FROM tpch.lineitem l JOIN tpch.orders o ON l.l_orderkey = o.o_orderkey \
+                 WHERE o_orderdate < to_timestamp('1995-03-15T00:00:00.000Z') \
+                 AND l_shipdate > to_timestamp('1995-03-15T00:00:00.000Z') \
+                 GROUP BY 1, 3 ORDER BY 2 DESC, 3 LIMIT 10",
+                revenue
+            ),
+        ),
+        (
+            "q6_forecast",
+            "SELECT SUM(l_extendedprice * l_discount) FROM tpch.lineitem \
+             WHERE l_shipdate >= to_timestamp('1994-01-01T00:00:00.000Z') \
+             AND l_shipdate < to_timestamp('1995-01-01T00:00:00.000Z') \
+             AND l_discount >= 0.05 AND l_discount <= 0.07 AND l_quantity < 24"
+                .to_string(),
+        ),
+        (
+            "q12_ship_modes",
+            "SELECT l_shipmode, \
+             SUM(CASE WHEN o_orderpriority = '1-URGENT' OR o_orderpriority = '2-HIGH' \
+             THEN 1 ELSE 0 END), \
+             SUM(CASE WHEN o_orderpriority <> '1-URGENT' AND o_orderpriority <> '2-HIGH' \
+             THEN 1 ELSE 0 END) \
+             FROM tpch.orders o JOIN tpch.lineitem l ON o.o_orderkey = l.l_orderkey \
+             WHERE l_shipmode IN ('MAIL', 'SHIP') \
+             GROUP BY 1 ORDER BY 1"
+                .to_string(),
+        ),
+        (
+            "q13_segments",
+            "SELECT c_mktsegment, COUNT(*), SUM(o_totalprice) \
+             FROM tpch.orders o JOIN tpch.customer c ON o.o_custkey = c.c_custkey \
+             GROUP BY 1 ORDER BY 1"
+                .to_string(),
+        ),
+        (
+            "q14_promotion",
+            format!(
+                "SELECT 100.0 * SUM(CASE WHEN p_type LIKE 'PROMO%' THEN {revenue} ELSE 0.0 END) \
+                 / SUM({revenue}) \
+                 FROM tpch.lineitem l JOIN tpch.part p ON l.l_partkey = p.p_partkey \
+                 WHERE l_shipdate >= to_timestamp('1995-09-01T00:00:00.000Z') \
+                 AND l_shipdate < to_timestamp('1995-10-01T00:00:00.000Z')",
+                revenue = revenue
+            ),
+        ),
+        (
+            "q15_suppliers",
+            format!(
+                "SELECT l_suppkey, SUM({}) FROM tpch.lineitem \
+                 WHERE l_shipdate >= to_timestamp('1996-01-01T00:00:00.000Z') \
+                 AND l_shipdate < to_timestamp('1996-04-01T00:00:00.000Z') \
+                 GROUP BY 1 ORDER BY 2 DESC LIMIT 10",
+                revenue
+            ),
+        ),
+    ]
+}
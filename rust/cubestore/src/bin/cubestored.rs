@@ -1,11 +1,17 @@
+use cubestore::bench::{self, BenchOptions};
 use cubestore::config::{Config, CubeServices};
+use cubestore::replay::{self, MySqlTarget, ReplayOptions, ReplayReport};
+use cubestore::sql::query_log::read_log;
 use cubestore::telemetry::{track_event, ReportingLogger};
+use cubestore::util::memory_pressure::spawn_memory_pressure_loop;
 use cubestore::util::spawn_malloc_trim_loop;
+use cubestore::CubeError;
 use log::debug;
 use log::Level;
 use simple_logger::SimpleLogger;
 use std::collections::HashMap;
 use std::env;
+use std::sync::Arc;
 use std::time::Duration;
 use tokio::runtime::Builder;
 
@@ -28,14 +34,50 @@ fn main() {
         .with_module_level("cubestore", log_level.to_level_filter());
     ReportingLogger::init(Box::new(logger), log_level.to_level_filter()).unwrap();
 
-    let config = Config::default();
+    let args = env::args().collect::<Vec<_>>();
+    if args.get(1).map(|a| a == "replay").unwrap_or(false) {
+        let options = match ReplayOptions::parse(&args[2..]) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        };
+        let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
+        runtime.block_on(run_replay(options));
+        return;
+    }
+
+    let bench_options = if args.get(1).map(|a| a == "bench").unwrap_or(false) {
+        match BenchOptions::parse(&args[2..]) {
+            Ok(options) => Some(options),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
+            }
+        }
+    } else {
+        None
+    };
 
-    Config::configure_worker_services();
+    let config = match &bench_options {
+        Some(options) => options.config(),
+        None => Config::default(),
+    };
+
+    config.configure_worker_services();
 
     let trim_every = config.config_obj().malloc_trim_every_secs();
     if trim_every != 0 {
         spawn_malloc_trim_loop(Duration::from_secs(trim_every));
     }
+    let memory_check_every = config.config_obj().memory_pressure_check_every_secs();
+    if memory_check_every != 0 {
+        spawn_memory_pressure_loop(
+            Duration::from_secs(memory_check_every),
+            config.config_obj().memory_pressure_threshold_percent(),
+        );
+    }
 
     debug!("New process started");
 
@@ -44,6 +86,11 @@ fn main() {
 
     let runtime = Builder::new_multi_thread().enable_all().build().unwrap();
 
+    if let Some(options) = bench_options {
+        runtime.block_on(run_bench(config, options));
+        return;
+    }
+
     runtime.block_on(async move {
         let services = config.configure().await;
 
@@ -54,6 +101,42 @@ fn main() {
     });
 }
 
+async fn run_bench(config: Config, options: BenchOptions) {
+    let services = config.configure().await;
+    services.start_processing_loops().await.unwrap();
+    let report = bench::run(services.sql_service.clone(), &options).await;
+    services.stop_processing_loops().await.ok();
+    match report {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("Benchmark failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Exits with 1 if the replay failed or queries did not behave as recorded.
+async fn run_replay(options: ReplayOptions) {
+    match replay_log(&options).await {
+        Ok(report) => {
+            print!("{}", report);
+            if !report.passed() {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Replay failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn replay_log(options: &ReplayOptions) -> Result<ReplayReport, CubeError> {
+    let entries = read_log(&options.log)?;
+    let target = Arc::new(MySqlTarget::new(&options.target)?);
+    replay::run(target, entries, options).await
+}
+
 async fn stop_on_ctrl_c(s: &CubeServices) {
     let s = s.clone();
     tokio::spawn(async move {
@@ -0,0 +1,548 @@
+//! Arrow Flight endpoint of the router, enabled with `CUBESTORE_FLIGHT_PORT`. Clients send the
+//! query as the ticket of `DoGet` and receive the result as Arrow record batches, without the
+//! conversion to rows of the MySQL and HTTP protocols, e.g. with pyarrow:
+//!     client.do_get(Ticket(b"SELECT * FROM s.orders"), FlightCallOptions(headers=[
+//!         (b"authorization", b"Basic <base64 of user:password>")])).read_all()
+//! Credentials are checked as for HTTP clients, see [crate::http::HttpServer::authorize].
+//! `Handshake` only checks them, no tokens are issued. Other methods are not supported.
+//!
+//! Selects without a final sort send batches as the router produces them, see
+//! [crate::sql::SqlService::exec_query_record_batches], other results come in a single batch.
+//! Decimals are sent as the standard Arrow decimal type with the scale of the column, since other
+//! Arrow implementations can't read the decimal types of CubeStore. Timestamps are sent in UTC.
+//!
+//! The endpoint only serves clients, routers of other deployments included. Workers send results
+//! to the router over the cluster transport, which already carries record batches in the Arrow
+//! IPC format, see [crate::queryplanner::query_executor::SerializedRecordBatchStream]. Moving it
+//! to Flight would only change the framing, so it's left as is.
+use crate::auth::AuthenticatedUser;
+use crate::http::HttpServer;
+use crate::metastore::{Column, ColumnType};
+use crate::mysql::SqlAuthService;
+use crate::sql::query_log::QueryLog;
+use crate::sql::{ResultBatch, SqlQueryContext, SqlService};
+use crate::store::DataFrame;
+use crate::table::TableValue;
+use crate::util::query_id::{new_query_id, with_query_id};
+use crate::{CubeError, CubeErrorCauseType};
+use arrow::array::{
+    Array, ArrayRef, BinaryBuilder, BooleanBuilder, DecimalBuilder, Float64Builder, Int64Builder,
+    Int64Decimal0Array, Int64Decimal10Array, Int64Decimal1Array, Int64Decimal2Array,
+    Int64Decimal3Array, Int64Decimal4Array, Int64Decimal5Array, StringBuilder,
+    TimestampNanosecondBuilder,
+};
+use arrow::compute::kernels::cast::cast;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::ipc::writer::IpcWriteOptions;
+use arrow::record_batch::RecordBatch;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::utils::{flight_data_from_arrow_batch, flight_data_from_arrow_schema};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightInfo,
+    HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+};
+use async_trait::async_trait;
+use bigdecimal::{BigDecimal, Num, ToPrimitive};
+use chrono::Utc;
+use futures::{Stream, StreamExt};
+use log::{error, info};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status, Streaming};
+
+/// Messages buffered for a slow client before the query waits for it.
+const RESULT_CHANNEL_SIZE: usize = 16;
+
+/// Digits of the largest `i64`, decimals of CubeStore are stored as scaled `i64`.
+const DECIMAL_PRECISION: usize = 19;
+
+pub struct FlightServer {
+    bind_address: String,
+    sql_service: Arc<dyn SqlService>,
+    auth: Arc<dyn SqlAuthService>,
+    query_log: Arc<QueryLog>,
+    cancel_token: CancellationToken,
+}
+
+crate::di_service!(FlightServer, []);
+
+impl FlightServer {
+    pub fn new(
+        bind_address: String,
+        sql_service: Arc<dyn SqlService>,
+        auth: Arc<dyn SqlAuthService>,
+        query_log: Arc<QueryLog>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            bind_address,
+            sql_service,
+            auth,
+            query_log,
+            cancel_token: CancellationToken::new(),
+        })
+    }
+
+    pub async fn run_server(&self) -> Result<(), CubeError> {
+        let addr: SocketAddr = self.bind_address.parse().map_err(|e| {
+            CubeError::internal(format!(
+                "Invalid Arrow Flight address {}: {}",
+                self.bind_address, e
+            ))
+        })?;
+        info!("Arrow Flight server is listening on {}", self.bind_address);
+        let service = CubeFlightService {
+            sql_service: self.sql_service.clone(),
+            auth: self.auth.clone(),
+            query_log: self.query_log.clone(),
+        };
+        let cancel_token = self.cancel_token.clone();
+        Server::builder()
+            .add_service(FlightServiceServer::new(service))
+            .serve_with_shutdown(addr, async move { cancel_token.cancelled().await })
+            .await
+            .map_err(|e| CubeError::internal(format!("Arrow Flight server error: {}", e)))
+    }
+
+    pub fn stop_processing(&self) {
+        self.cancel_token.cancel();
+    }
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+struct CubeFlightService {
+    sql_service: Arc<dyn SqlService>,
+    auth: Arc<dyn SqlAuthService>,
+    query_log: Arc<QueryLog>,
+}
+
+impl CubeFlightService {
+    async fn authorize<T>(&self, request: &Request<T>) -> Result<AuthenticatedUser, Status> {
+        let header = match request.metadata().get("authorization") {
+            Some(v) => Some(
+                v.to_str()
+                    .map_err(|_| Status::unauthenticated("Invalid authorization header"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        HttpServer::authorize(self.auth.clone(), header)
+            .await
+            .map_err(|e| Status::unauthenticated(e.message))
+    }
+}
+
+#[async_trait]
+impl FlightService for CubeFlightService {
+    type HandshakeStream = ResponseStream<HandshakeResponse>;
+    type ListFlightsStream = ResponseStream<FlightInfo>;
+    type DoGetStream = ResponseStream<FlightData>;
+    type DoPutStream = ResponseStream<PutResult>;
+    type DoExchangeStream = ResponseStream<FlightData>;
+    type DoActionStream = ResponseStream<arrow_flight::Result>;
+    type ListActionsStream = ResponseStream<ActionType>;
+
+    async fn handshake(
+        &self,
+        request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        self.authorize(&request).await?;
+        let response: Result<_, Status> = Ok(HandshakeResponse {
+            protocol_version: 0,
+            payload: Vec::new(),
+        });
+        Ok(Response::new(Box::pin(futures::stream::iter(vec![
+            response,
+        ]))))
+    }
+
+    async fn list_flights(
+        &self,
+        _request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        Err(unsupported("ListFlights"))
+    }
+
+    async fn get_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        Err(unsupported("GetFlightInfo"))
+    }
+
+    async fn get_schema(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        Err(unsupported("GetSchema"))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let AuthenticatedUser { user, role } = self.authorize(&request).await?;
+        let query = String::from_utf8(request.into_inner().ticket)
+            .map_err(|_| Status::invalid_argument("Ticket must be the query in UTF-8"))?;
+        let context = SqlQueryContext {
+            user: user.clone(),
+            role,
+            ..SqlQueryContext::default()
+        };
+        let sql_service = self.sql_service.clone();
+        let query_log = self.query_log.clone();
+        let (tx, rx) = mpsc::channel(RESULT_CHANNEL_SIZE);
+        let query_id = new_query_id();
+        tokio::spawn(with_query_id(Some(query_id.clone()), async move {
+            let start = Utc::now();
+            let res = send_results(sql_service.as_ref(), context, &query, &tx).await;
+            query_log.record(start, None, &user, &query, res.as_ref().map(|rows| *rows));
+            if let Err(mut e) = res {
+                error!("Error during processing {}: {}", query, e.message);
+                e.message = format!("{} (query id: {})", e.message, query_id);
+                // Fails if the client is gone.
+                let _ = tx.send(Err(error_status(&e))).await;
+            }
+        }));
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(unsupported("DoPut"))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(unsupported("DoExchange"))
+    }
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(unsupported("DoAction"))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Err(unsupported("ListActions"))
+    }
+}
+
+fn unsupported(method: &str) -> Status {
+    Status::unimplemented(format!(
+        "{} is not supported, send the query as the ticket of DoGet",
+        method
+    ))
+}
+
+fn error_status(e: &CubeError) -> Status {
+    match e.cause {
+        CubeErrorCauseType::Unavailable => Status::unavailable(e.client_message()),
+        CubeErrorCauseType::User => Status::invalid_argument(e.client_message()),
+        CubeErrorCauseType::Internal => Status::internal(e.client_message()),
+    }
+}
+
+/// Returns the number of sent rows.
+async fn send_results(
+    sql_service: &dyn SqlService,
+    context: SqlQueryContext,
+    query: &str,
+    tx: &mpsc::Sender<Result<FlightData, Status>>,
+) -> Result<u64, CubeError> {
+    let mut stream = sql_service
+        .exec_query_record_batches(context, query)
+        .await?;
+    let schema = result_schema(&stream.columns);
+    let options = IpcWriteOptions::default();
+    send(tx, flight_data_from_arrow_schema(&schema, &options)).await?;
+    let mut rows = 0;
+    while let Some(batch) = stream.batches.next().await {
+        let batch = match batch? {
+            ResultBatch::RecordBatch(b) => convert_batch(&schema, &b)?,
+            ResultBatch::DataFrame(d) => data_frame_to_batch(&schema, &d)?,
+        };
+        rows += batch.num_rows() as u64;
+        let (dictionaries, data) = flight_data_from_arrow_batch(&batch, &options);
+        for d in dictionaries {
+            send(tx, d).await?;
+        }
+        send(tx, data).await?;
+    }
+    Ok(rows)
+}
+
+async fn send(
+    tx: &mpsc::Sender<Result<FlightData, Status>>,
+    data: FlightData,
+) -> Result<(), CubeError> {
+    tx.send(Ok(data))
+        .await
+        .map_err(|_| CubeError::user("Client closed the connection".to_string()))
+}
+
+/// Schema of results sent to clients, with types other Arrow implementations can read.
+fn result_schema(columns: &[Column]) -> SchemaRef {
+    Arc::new(Schema::new(
+        columns
+            .iter()
+            .map(|c| {
+                let data_type = match c.get_column_type() {
+                    ColumnType::String => DataType::Utf8,
+                    ColumnType::Decimal { scale, .. } => {
+                        DataType::Decimal(DECIMAL_PRECISION, *scale as usize)
+                    }
+                    ColumnType::Int | ColumnType::Enum(_) => DataType::Int64,
+                    ColumnType::Float => DataType::Float64,
+                    ColumnType::Boolean => DataType::Boolean,
+                    ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    ColumnType::Bytes
+                    | ColumnType::HyperLogLog(_)
+                    | ColumnType::Uuid
+                    | ColumnType::Inet
+                    | ColumnType::Cidr => DataType::Binary,
+                };
+                Field::new(c.get_name(), data_type, true)
+            })
+            .collect(),
+    ))
+}
+
+macro_rules! to_decimal_array {
+    ($ARRAY: expr, $ARRAY_TYPE: ident, $FIELD: expr) => {{
+        let a = $ARRAY.as_any().downcast_ref::<$ARRAY_TYPE>().unwrap();
+        let mut builder = match $FIELD.data_type() {
+            DataType::Decimal(precision, scale) => DecimalBuilder::new(a.len(), *precision, *scale),
+            t => {
+                return Err(CubeError::internal(format!(
+                    "Unexpected result type of decimals: {:?}",
+                    t
+                )))
+            }
+        };
+        for i in 0..a.len() {
+            if a.is_null(i) {
+                builder.append_null()?;
+            } else {
+                builder.append_value(a.value(i) as i128)?;
+            }
+        }
+        Arc::new(builder.finish()) as ArrayRef
+    }};
+}
+
+/// Casts columns of `batch` to the types of `schema`.
+fn convert_batch(schema: &SchemaRef, batch: &RecordBatch) -> Result<RecordBatch, CubeError> {
+    let mut columns = Vec::with_capacity(batch.num_columns());
+    for (array, field) in batch.columns().iter().zip(schema.fields()) {
+        // The scale of the schema is the scale of the array, see [result_schema].
+        let array = match array.data_type() {
+            t if t == field.data_type() => array.clone(),
+            DataType::Int64Decimal(0) => to_decimal_array!(array, Int64Decimal0Array, field),
+            DataType::Int64Decimal(1) => to_decimal_array!(array, Int64Decimal1Array, field),
+            DataType::Int64Decimal(2) => to_decimal_array!(array, Int64Decimal2Array, field),
+            DataType::Int64Decimal(3) => to_decimal_array!(array, Int64Decimal3Array, field),
+            DataType::Int64Decimal(4) => to_decimal_array!(array, Int64Decimal4Array, field),
+            DataType::Int64Decimal(5) => to_decimal_array!(array, Int64Decimal5Array, field),
+            DataType::Int64Decimal(10) => to_decimal_array!(array, Int64Decimal10Array, field),
+            _ => cast(array, field.data_type())?,
+        };
+        columns.push(array);
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+fn data_frame_to_batch(
+    schema: &SchemaRef,
+    data_frame: &DataFrame,
+) -> Result<RecordBatch, CubeError> {
+    let rows = data_frame.get_rows();
+    let mut columns = Vec::with_capacity(schema.fields().len());
+    for (i, field) in schema.fields().iter().enumerate() {
+        let unexpected = |v: &TableValue| {
+            CubeError::internal(format!(
+                "Unexpected value {:?} of {:?} column {}",
+                v,
+                field.data_type(),
+                field.name()
+            ))
+        };
+        let values = rows.iter().map(|r| &r.values()[i]);
+        let array: ArrayRef = match field.data_type() {
+            DataType::Utf8 => {
+                let mut builder = StringBuilder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::String(s) => builder.append_value(s)?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Decimal(precision, scale) => {
+                let mut builder = DecimalBuilder::new(rows.len(), *precision, *scale);
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Decimal(d) => builder.append_value(
+                            BigDecimal::from_str_radix(d, 10)?
+                                .with_scale(*scale as i64)
+                                .as_bigint_and_exponent()
+                                .0
+                                .to_i128()
+                                .ok_or_else(|| unexpected(v))?,
+                        )?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Int64 => {
+                let mut builder = Int64Builder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Int(i) => builder.append_value(*i)?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Float64 => {
+                let mut builder = Float64Builder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Float(f) => builder.append_value(f.0)?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Boolean => {
+                let mut builder = BooleanBuilder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Boolean(b) => builder.append_value(*b)?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Timestamp(TimeUnit::Nanosecond, None) => {
+                let mut builder = TimestampNanosecondBuilder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Timestamp(t) => builder.append_value(t.get_time_stamp())?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Binary => {
+                let mut builder = BinaryBuilder::new(rows.len());
+                for v in values {
+                    match v {
+                        TableValue::Null => builder.append_null()?,
+                        TableValue::Bytes(b) => builder.append_value(b)?,
+                        v => return Err(unexpected(v)),
+                    }
+                }
+                Arc::new(builder.finish())
+            }
+            t => {
+                return Err(CubeError::internal(format!(
+                    "Unsupported result type: {:?}",
+                    t
+                )))
+            }
+        };
+        columns.push(array);
+    }
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{Row, TimestampValue};
+    use arrow::array::{DecimalArray, Int64Decimal2Array, TimestampMicrosecondArray, UInt64Array};
+
+    fn columns() -> Vec<Column> {
+        vec![
+            Column::new("n".to_string(), ColumnType::Int, 0),
+            Column::new(
+                "amount".to_string(),
+                ColumnType::Decimal {
+                    scale: 2,
+                    precision: 18,
+                },
+                1,
+            ),
+            Column::new("t".to_string(), ColumnType::Timestamp, 2),
+        ]
+    }
+
+    #[test]
+    fn record_batches_and_data_frames() {
+        let schema = result_schema(&columns());
+        let batch = RecordBatch::try_new(
+            Arc::new(Schema::new(vec![
+                Field::new("n", DataType::UInt64, false),
+                Field::new("amount", DataType::Int64Decimal(2), true),
+                Field::new("t", DataType::Timestamp(TimeUnit::Microsecond, None), false),
+            ])),
+            vec![
+                Arc::new(UInt64Array::from(vec![1, 2])),
+                Arc::new(Int64Decimal2Array::from(vec![Some(-1050), None])),
+                Arc::new(TimestampMicrosecondArray::from(vec![1_000, 2_000])),
+            ],
+        )
+        .unwrap();
+        let from_batch = convert_batch(&schema, &batch).unwrap();
+
+        let data_frame = DataFrame::new(
+            columns(),
+            vec![
+                Row::new(vec![
+                    TableValue::Int(1),
+                    TableValue::Decimal("-10.5".to_string()),
+                    TableValue::Timestamp(TimestampValue::new(1_000_000)),
+                ]),
+                Row::new(vec![
+                    TableValue::Int(2),
+                    TableValue::Null,
+                    TableValue::Timestamp(TimestampValue::new(2_000_000)),
+                ]),
+            ],
+        );
+        let from_data_frame = data_frame_to_batch(&schema, &data_frame).unwrap();
+
+        for b in &[from_batch, from_data_frame] {
+            assert_eq!(b.schema(), schema);
+            assert_eq!(
+                b.schema().field(1).data_type(),
+                &DataType::Decimal(DECIMAL_PRECISION, 2)
+            );
+            let amounts = b.column(1).as_any().downcast_ref::<DecimalArray>().unwrap();
+            assert_eq!(amounts.value(0), -1050);
+            assert!(amounts.is_null(1));
+        }
+    }
+}
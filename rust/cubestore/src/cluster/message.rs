@@ -1,5 +1,7 @@
+use crate::cluster::replication::ReplicatedChunk;
+use crate::config::dynamic::ConfigValues;
 use crate::metastore::{MetaStoreRpcMethodCall, MetaStoreRpcMethodResult};
-use crate::queryplanner::query_executor::SerializedRecordBatchStream;
+use crate::queryplanner::query_executor::{SerializedRecordBatchStream, TransportCompression};
 use crate::queryplanner::serialized_plan::SerializedPlan;
 use crate::CubeError;
 use arrow::datatypes::SchemaRef;
@@ -9,17 +11,22 @@ use tokio::net::TcpStream;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NetworkMessage {
-    Select(SerializedPlan),
+    /// The codec is the one results can be compressed with.
+    Select(SerializedPlan, TransportCompression),
     SelectResult(Result<(SchemaRef, Vec<SerializedRecordBatchStream>), CubeError>),
 
     /// Select that sends results in batches. The immediate response is [SelectResultSchema],
     /// followed by a stream of [SelectResultBatch].
-    SelectStart(SerializedPlan),
+    SelectStart(SerializedPlan, TransportCompression),
     /// Response to [SelectStart].
     SelectResultSchema(Result<SchemaRef, CubeError>),
     /// [None] indicates the end of the stream.
     SelectResultBatch(Result<Option<SerializedRecordBatchStream>, CubeError>),
 
+    /// Stops a select with this id if it's still running, see [crate::cluster::running_selects].
+    CancelSelect(/*select_id*/ u64),
+    CancelSelectResult(Result<(), CubeError>),
+
     WarmupDownload(/*remote_path*/ String),
     WarmupDownloadResult(Result<(), CubeError>),
 
@@ -28,6 +35,14 @@ pub enum NetworkMessage {
 
     NotifyJobListeners,
     NotifyJobListenersSuccess,
+
+    /// Sent to the metastore port of another cluster, see [crate::cluster::replication].
+    ReplicateChunk(ReplicatedChunk),
+    ReplicateChunkResult(Result<(), CubeError>),
+
+    /// Replaces values of hot-reloadable settings, see [crate::config::dynamic].
+    UpdateConfig(ConfigValues),
+    UpdateConfigResult(Result<(), CubeError>),
 }
 
 impl NetworkMessage {
@@ -63,7 +78,7 @@ impl NetworkMessage {
     pub async fn receive(socket: &mut TcpStream) -> Result<Self, CubeError> {
         match Self::maybe_receive(socket).await? {
             Some(m) => Ok(m),
-            None => Err(CubeError::internal("connection closed".to_string())),
+            None => Err(CubeError::unavailable("connection closed".to_string())),
         }
     }
 
@@ -1,5 +1,9 @@
+pub mod flight;
 pub mod message;
-
+pub mod partition_stats;
+pub mod replication;
+pub mod running_selects;
+pub mod speculative;
 pub mod transport;
 #[cfg(not(target_os = "windows"))]
 pub mod worker_pool;
@@ -9,30 +13,39 @@ use crate::cluster::worker_pool::{MessageProcessor, WorkerPool};
 
 use crate::ack_error;
 use crate::cluster::message::NetworkMessage;
+use crate::cluster::partition_stats::PartitionAccessStats;
+use crate::cluster::running_selects::RunningSelects;
+use crate::cluster::speculative::SpeculativeExecution;
 use crate::cluster::transport::{ClusterTransport, MetaStoreTransport, WorkerConnection};
-use crate::config::injection::DIService;
 #[allow(unused_imports)]
-use crate::config::{Config, ConfigObj};
+use crate::config::dynamic::ConfigValues;
+use crate::config::injection::DIService;
+use crate::config::{Config, ConfigObj, MaintenanceWindow};
 use crate::import::ImportService;
 use crate::metastore::chunks::chunk_file_name;
-use crate::metastore::job::{Job, JobStatus, JobType};
+use crate::metastore::job::{Job, JobClass, JobProgress, JobResult, JobStatus, JobType};
 use crate::metastore::partition::partition_file_name;
 use crate::metastore::{Chunk, IdRow, MetaStore, MetaStoreEvent, Partition, RowKey, TableId};
 use crate::metastore::{
     MetaStoreRpcClientTransport, MetaStoreRpcMethodCall, MetaStoreRpcMethodResult,
     MetaStoreRpcServer,
 };
-use crate::queryplanner::query_executor::{QueryExecutor, SerializedRecordBatchStream};
+use crate::queryplanner::query_executor::{
+    QueryExecutor, SerializedRecordBatchStream, TransportCompression,
+};
 use crate::queryplanner::serialized_plan::SerializedPlan;
+use crate::remotefs::storage::storage_file_name;
 use crate::remotefs::RemoteFs;
 use crate::store::compaction::CompactionService;
 use crate::store::ChunkDataStore;
+use crate::util::job_progress::with_job_progress;
+use crate::util::query_id::with_query_id;
 use crate::CubeError;
 use arrow::datatypes::SchemaRef;
 use arrow::error::ArrowError;
 use arrow::record_batch::RecordBatch;
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{Timelike, Utc};
 use core::mem;
 use datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream};
 use flatbuffers::bitflags::_core::pin::Pin;
@@ -48,6 +61,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::iter;
 use std::path::Path;
 use std::sync::Weak;
 use std::sync::{Arc, Mutex};
@@ -83,8 +97,15 @@ pub trait Cluster: DIService + Send + Sync {
         plan: SerializedPlan,
     ) -> Result<SendableRecordBatchStream, CubeError>;
 
+    /// Stops a select sent with [SerializedPlan::select_id] set, if it is still running.
+    async fn cancel_select(&self, node_name: &str, select_id: u64) -> Result<(), CubeError>;
+
     async fn available_nodes(&self) -> Result<Vec<String>, CubeError>;
 
+    /// Applies values of hot-reloadable settings set on this node on all workers, see
+    /// [crate::config::dynamic].
+    async fn propagate_config(&self, values: ConfigValues) -> Result<(), CubeError>;
+
     fn server_name(&self) -> &str;
 
     async fn warmup_download(&self, node_name: &str, remote_path: String) -> Result<(), CubeError>;
@@ -95,10 +116,28 @@ pub trait Cluster: DIService + Send + Sync {
         chunks: Vec<IdRow<Chunk>>,
     ) -> Result<(), CubeError>;
 
+    /// Nodes that keep files of the partition warm. All select workers for partitions of
+    /// replicated tables, see [is_replicated_table].
+    async fn warmup_node_names(
+        &self,
+        partition: &IdRow<Partition>,
+    ) -> Result<Vec<String>, CubeError>;
+
     fn job_result_listener(&self) -> JobResultListener;
 
     fn node_name_by_partitions(&self, partition_ids: &[u64]) -> String;
 
+    /// Settings of speculative execution, `None` if it is disabled.
+    fn speculative_execution(&self) -> Option<SpeculativeExecution>;
+
+    /// Node to run a duplicate of a straggling select on instead of `node_name`, the select
+    /// worker with the least selects of this node in flight. `None` if there are no other workers.
+    fn speculative_node_name(&self, node_name: &str) -> Option<String>;
+
+    /// Node to run compaction and repartitioning of the partition. Same as
+    /// [Cluster::node_name_by_partitions] unless there are read replicas.
+    fn node_name_for_job(&self, partition_id: u64) -> String;
+
     async fn node_name_for_import(
         &self,
         table_id: u64,
@@ -112,10 +151,40 @@ pub trait Cluster: DIService + Send + Sync {
 
 crate::di_service!(MockCluster, [Cluster]);
 
+/// Select workers and read replicas.
+pub fn worker_nodes(config: &dyn ConfigObj) -> Vec<String> {
+    let mut workers = config.select_workers().clone();
+    workers.extend(config.read_replica_workers().iter().cloned());
+    workers.sort();
+    workers.dedup();
+    workers
+}
+
+/// Tables with at most `max_rows` rows, i.e. [ConfigObj::replicated_table_max_rows], are
+/// replicated: every select worker keeps all their files warm, so joins with them run on the
+/// workers that have the other side of the join and no data has to move between workers.
+pub fn is_replicated_table(row_count: u64, max_rows: u64) -> bool {
+    max_rows != 0 && row_count <= max_rows
+}
+
+/// Rows in `partitions` of an index and their chunks, i.e. the row count of the table.
+pub fn index_row_count(partitions: &[(IdRow<Partition>, Vec<IdRow<Chunk>>)]) -> u64 {
+    partitions
+        .iter()
+        .map(|(p, chunks)| {
+            p.get_row().main_table_row_count()
+                + chunks
+                    .iter()
+                    .map(|c| c.get_row().get_row_count())
+                    .sum::<u64>()
+        })
+        .sum()
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub enum JobEvent {
     Started(RowKey, JobType),
-    Success(RowKey, JobType),
+    Success(RowKey, JobType, JobResult),
     Error(RowKey, JobType, String),
 }
 
@@ -133,6 +202,8 @@ pub struct ClusterImpl {
     job_notify: Arc<Notify>,
     meta_store_sender: Sender<MetaStoreEvent>,
     jobs_enabled: Arc<RwLock<bool>>,
+    /// Number of selects sent by this node to each worker that are not answered yet.
+    selects_in_flight: std::sync::Mutex<HashMap<String, usize>>,
     #[cfg(not(target_os = "windows"))]
     select_process_pool: RwLock<
         Option<
@@ -147,6 +218,8 @@ pub struct ClusterImpl {
     >,
     config_obj: Arc<dyn ConfigObj>,
     query_executor: Arc<dyn QueryExecutor>,
+    partition_stats: Arc<PartitionAccessStats>,
+    running_selects: RunningSelects,
     stop_token: CancellationToken,
     close_worker_socket_tx: watch::Sender<bool>,
     close_worker_socket_rx: RwLock<watch::Receiver<bool>>,
@@ -156,7 +229,13 @@ crate::di_service!(ClusterImpl, [Cluster]);
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum WorkerMessage {
-    Select(SerializedPlan, HashMap<String, String>),
+    Select(
+        SerializedPlan,
+        HashMap<String, String>,
+        TransportCompression,
+        /*compression_threshold*/ usize,
+        /*batch_cache_max_size*/ usize,
+    ),
 }
 #[cfg(not(target_os = "windows"))]
 pub struct WorkerProcessor;
@@ -170,16 +249,28 @@ impl MessageProcessor<WorkerMessage, (SchemaRef, Vec<SerializedRecordBatchStream
         args: WorkerMessage,
     ) -> Result<(SchemaRef, Vec<SerializedRecordBatchStream>), CubeError> {
         match args {
-            WorkerMessage::Select(plan_node, remote_to_local_names) => {
+            WorkerMessage::Select(
+                plan_node,
+                remote_to_local_names,
+                compression,
+                compression_threshold,
+                batch_cache_max_size,
+            ) => {
                 debug!("Running select in worker started: {:?}", plan_node);
                 let plan_node_to_send = plan_node.clone();
-                let res = Config::current_worker_services()
-                    .query_executor
-                    .execute_worker_plan(plan_node_to_send, remote_to_local_names)
-                    .await;
+                let query_executor = Config::current_worker_services().query_executor;
+                query_executor.set_batch_cache_max_size(batch_cache_max_size);
+                let res = with_query_id(
+                    plan_node.query_id().cloned(),
+                    query_executor.execute_worker_plan(plan_node_to_send, remote_to_local_names),
+                )
+                .await;
                 debug!("Running select in worker completed: {:?}", plan_node);
                 let (schema, records) = res?;
-                let records = SerializedRecordBatchStream::write(schema.as_ref(), records)?;
+                let records = SerializedRecordBatchStream::write(schema.as_ref(), records)?
+                    .into_iter()
+                    .map(|r| r.compress(compression, compression_threshold))
+                    .collect::<Result<Vec<_>, _>>()?;
                 Ok((schema, records))
             }
         }
@@ -194,6 +285,9 @@ struct JobRunner {
     server_name: String,
     notify: Arc<Notify>,
     jobs_enabled: Arc<RwLock<bool>>,
+    job_class: JobClass,
+    /// Background jobs are only picked up inside this window if it's set.
+    maintenance_window: Option<MaintenanceWindow>,
 }
 
 lazy_static! {
@@ -219,8 +313,25 @@ impl Cluster for ClusterImpl {
         node_name: &str,
         plan_node: SerializedPlan,
     ) -> Result<Vec<RecordBatch>, CubeError> {
+        *self
+            .selects_in_flight
+            .lock()
+            .unwrap()
+            .entry(node_name.to_string())
+            .or_default() += 1;
+        scopeguard::defer!(
+            *self
+                .selects_in_flight
+                .lock()
+                .unwrap()
+                .get_mut(node_name)
+                .unwrap() -= 1
+        );
         let response = self
-            .send_or_process_locally(node_name, NetworkMessage::Select(plan_node))
+            .send_or_process_locally(
+                node_name,
+                NetworkMessage::Select(plan_node, self.transport_compression(node_name)),
+            )
             .await?;
         match response {
             NetworkMessage::SelectResult(r) => {
@@ -242,10 +353,35 @@ impl Cluster for ClusterImpl {
             .await
     }
 
+    async fn cancel_select(&self, node_name: &str, select_id: u64) -> Result<(), CubeError> {
+        match self
+            .send_or_process_locally(node_name, NetworkMessage::CancelSelect(select_id))
+            .await?
+        {
+            NetworkMessage::CancelSelectResult(r) => r,
+            _ => panic!("unexpected response for cancel select"),
+        }
+    }
+
     async fn available_nodes(&self) -> Result<Vec<String>, CubeError> {
         Ok(vec![self.server_name.to_string()])
     }
 
+    async fn propagate_config(&self, values: ConfigValues) -> Result<(), CubeError> {
+        let workers = worker_nodes(self.config_obj.as_ref());
+        let futures = workers
+            .iter()
+            .filter(|w| **w != self.server_name)
+            .map(|w| self.send_to_worker(w, NetworkMessage::UpdateConfig(values.clone())));
+        for response in join_all(futures).await {
+            match response? {
+                NetworkMessage::UpdateConfigResult(r) => r?,
+                _ => panic!("unexpected result for config update"),
+            }
+        }
+        Ok(())
+    }
+
     fn server_name(&self) -> &str {
         self.server_name.as_str()
     }
@@ -280,12 +416,44 @@ impl Cluster for ClusterImpl {
         workers[(hasher.finish() % workers.len() as u64) as usize].clone()
     }
 
+    fn speculative_execution(&self) -> Option<SpeculativeExecution> {
+        let slowdown = self.config_obj.speculative_execution_slowdown();
+        if slowdown == 0 {
+            return None;
+        }
+        Some(SpeculativeExecution {
+            slowdown,
+            min_delay: Duration::from_millis(self.config_obj.speculative_execution_min_delay_ms()),
+        })
+    }
+
+    fn speculative_node_name(&self, node_name: &str) -> Option<String> {
+        let in_flight = self.selects_in_flight.lock().unwrap();
+        self.config_obj
+            .select_workers()
+            .iter()
+            .filter(|w| w.as_str() != node_name)
+            .min_by_key(|w| in_flight.get(w.as_str()).cloned().unwrap_or(0))
+            .cloned()
+    }
+
+    fn node_name_for_job(&self, partition_id: u64) -> String {
+        let workers = self.job_workers();
+        if workers.is_empty() {
+            return self.server_name.to_string();
+        }
+
+        let mut hasher = DefaultHasher::new();
+        partition_id.hash(&mut hasher);
+        workers[(hasher.finish() % workers.len() as u64) as usize].to_string()
+    }
+
     async fn node_name_for_import(
         &self,
         table_id: u64,
         location: &str,
     ) -> Result<String, CubeError> {
-        let workers = self.config_obj.select_workers();
+        let workers = self.job_workers();
         if workers.is_empty() {
             return Ok(self.server_name.to_string());
         }
@@ -300,14 +468,15 @@ impl Cluster for ClusterImpl {
         partition: IdRow<Partition>,
         chunks: Vec<IdRow<Chunk>>,
     ) -> Result<(), CubeError> {
-        let node_name = self.node_name_by_partitions(&[partition.get_id()]);
         let mut futures = Vec::new();
-        if let Some(name) = partition.get_row().get_full_name(partition.get_id()) {
-            futures.push(self.warmup_download(&node_name, name));
-        }
-        for chunk in chunks.iter() {
-            let name = chunk.get_row().get_full_name(chunk.get_id());
-            futures.push(self.warmup_download(&node_name, name));
+        for node_name in self.warmup_node_names(&partition).await? {
+            if let Some(name) = partition.get_row().get_full_name(partition.get_id()) {
+                futures.push(self.warmup_download(&node_name, name));
+            }
+            for chunk in chunks.iter() {
+                let name = chunk.get_row().get_full_name(chunk.get_id());
+                futures.push(self.warmup_download(&node_name, name));
+            }
         }
         join_all(futures)
             .await
@@ -316,13 +485,55 @@ impl Cluster for ClusterImpl {
         Ok(())
     }
 
+    async fn warmup_node_names(
+        &self,
+        partition: &IdRow<Partition>,
+    ) -> Result<Vec<String>, CubeError> {
+        let node_name =
+            self.node_name_by_partitions(&[partition.get_row().placement_id(partition.get_id())]);
+        let workers = self.config_obj.select_workers();
+        if self.config_obj.replicated_table_max_rows() == 0 || workers.is_empty() {
+            return Ok(vec![node_name]);
+        }
+        let partitions = self
+            .meta_store
+            .get_active_partitions_and_chunks_by_index_id_for_select(vec![partition
+                .get_row()
+                .get_index_id()])
+            .await?;
+        if is_replicated_table(
+            index_row_count(&partitions[0]),
+            self.config_obj.replicated_table_max_rows(),
+        ) {
+            Ok(workers.clone())
+        } else {
+            Ok(vec![node_name])
+        }
+    }
+
     #[instrument(level = "trace", skip(self, m))]
     async fn process_message_on_worker(&self, m: NetworkMessage) -> NetworkMessage {
         match m {
-            NetworkMessage::Select(plan) => {
-                let res = self.run_local_select_serialized(plan).await;
+            NetworkMessage::Select(plan, compression) => {
+                let query_id = plan.query_id().cloned();
+                let select_id = plan.select_id();
+                let res = with_query_id(
+                    query_id,
+                    self.running_selects.run(
+                        select_id,
+                        self.run_local_select_serialized(plan, compression),
+                    ),
+                )
+                .await;
                 NetworkMessage::SelectResult(res)
             }
+            NetworkMessage::CancelSelect(select_id) => {
+                self.running_selects.cancel(select_id);
+                NetworkMessage::CancelSelectResult(Ok(()))
+            }
+            NetworkMessage::CancelSelectResult(_) => {
+                panic!("CancelSelectResult sent to worker")
+            }
             NetworkMessage::WarmupDownload(remote_path) => {
                 let res = self.remote_fs.download_file(&remote_path).await;
                 NetworkMessage::WarmupDownloadResult(res.map(|_| ()))
@@ -340,6 +551,18 @@ impl Cluster for ClusterImpl {
             NetworkMessage::NotifyJobListenersSuccess => {
                 panic!("NotifyJobListenersSuccess sent to worker")
             }
+            NetworkMessage::ReplicateChunk(_) | NetworkMessage::ReplicateChunkResult(_) => {
+                panic!("ReplicateChunk sent to worker")
+            }
+            NetworkMessage::UpdateConfig(values) => {
+                self.config_obj
+                    .dynamic_config()
+                    .apply(self.config_obj.as_ref(), values, "router");
+                NetworkMessage::UpdateConfigResult(Ok(()))
+            }
+            NetworkMessage::UpdateConfigResult(_) => {
+                panic!("UpdateConfigResult sent to worker")
+            }
             NetworkMessage::SelectStart(..)
             | NetworkMessage::SelectResultSchema(..)
             | NetworkMessage::SelectResultBatch(..) => {
@@ -355,6 +578,15 @@ impl Cluster for ClusterImpl {
                 let res = server.invoke_method(method_call).await;
                 NetworkMessage::MetaStoreCallResult(res)
             }
+            NetworkMessage::ReplicateChunk(chunk) => {
+                let res = replication::apply_replicated_chunk(
+                    self.meta_store.clone(),
+                    self.chunk_store.clone(),
+                    chunk,
+                )
+                .await;
+                NetworkMessage::ReplicateChunkResult(res)
+            }
             x => panic!("Unexpected message: {:?}", x),
         }
     }
@@ -384,8 +616,18 @@ impl JobResultListener {
     }
 
     pub async fn wait_for_job_results(
+        self,
+        results: Vec<(RowKey, JobType)>,
+    ) -> Result<Vec<JobEvent>, CubeError> {
+        self.wait_for_job_results_with_progress(results, |_| {})
+            .await
+    }
+
+    /// Calls `on_result` with each of the results as soon as it arrives, e.g. to report progress.
+    pub async fn wait_for_job_results_with_progress(
         mut self,
         mut results: Vec<(RowKey, JobType)>,
+        mut on_result: impl FnMut(&JobEvent) + Send,
     ) -> Result<Vec<JobEvent>, CubeError> {
         let mut res = Vec::new();
         loop {
@@ -401,6 +643,7 @@ impl JobResultListener {
                         JobStatus::Completed => Some(JobEvent::Success(
                             new.get_row().row_reference().clone(),
                             new.get_row().job_type().clone(),
+                            new.get_row().result().clone(),
                         )),
                         JobStatus::Timeout => Some(JobEvent::Error(
                             new.get_row().row_reference().clone(),
@@ -414,11 +657,12 @@ impl JobResultListener {
                         )),
                     };
                     if let Some(event) = job_event {
-                        if let JobEvent::Success(k, t) | JobEvent::Error(k, t, _) = &event {
+                        if let JobEvent::Success(k, t, _) | JobEvent::Error(k, t, _) = &event {
                             if let Some((index, _)) = results
                                 .iter()
                                 .find_position(|(row_key, job_type)| k == row_key && t == job_type)
                             {
+                                on_result(&event);
                                 res.push(event);
                                 results.remove(index);
                             }
@@ -451,9 +695,14 @@ impl JobRunner {
     }
 
     async fn fetch_and_process(&self) -> Result<(), CubeError> {
+        if let (JobClass::Background, Some(window)) = (self.job_class, &self.maintenance_window) {
+            if !window.contains(Utc::now().hour()) {
+                return Ok(());
+            }
+        }
         let job = self
             .meta_store
-            .start_processing_job(self.server_name.to_string())
+            .start_processing_job(self.server_name.to_string(), self.job_class)
             .await?;
         if let Some(to_process) = job {
             self.run_local(to_process).await?;
@@ -466,59 +715,89 @@ impl JobRunner {
         let job_id = job.get_id();
         let (mut tx, rx) = oneshot::channel::<()>();
         let meta_store = self.meta_store.clone();
+        let progress = Arc::new(Mutex::new(None));
+        let progress_to_move = progress.clone();
         let heart_beat_timer = tokio::spawn(async move {
+            // Progress is written as it changes and refreshes the heart beat as well.
+            let mut written_progress = None;
+            let mut last_heart_beat = SystemTime::now();
             loop {
                 tokio::select! {
                     _ = tx.closed() => {
                         break;
                     }
-                    _ = Delay::new(Duration::from_secs(30)) => {
-                        let _ = meta_store.update_heart_beat(job_id).await; // TODO handle result
+                    _ = Delay::new(Duration::from_secs(5)) => {
+                        let progress = *progress_to_move.lock().unwrap();
+                        if progress.is_some() && progress != written_progress {
+                            // TODO handle result
+                            let _ = meta_store.update_job_progress(job_id, progress.unwrap()).await;
+                            written_progress = progress;
+                            last_heart_beat = SystemTime::now();
+                        } else if last_heart_beat.elapsed().unwrap_or_default()
+                            >= Duration::from_secs(30)
+                        {
+                            let _ = meta_store.update_heart_beat(job_id).await; // TODO handle result
+                            last_heart_beat = SystemTime::now();
+                        }
                     }
                 }
             }
         });
         debug!("Running job: {:?}", job);
-        let res = timeout(Duration::from_secs(600), self.route_job(job.get_row())).await;
+        let res = timeout(
+            Duration::from_secs(600),
+            self.route_job(job.get_row(), progress),
+        )
+        .await;
         mem::drop(rx);
         heart_beat_timer.await?;
-        if let Err(_) = res {
-            self.meta_store
-                .update_status(job_id, JobStatus::Timeout)
-                .await?;
-            error!(
-                "Running job timed out ({:?}): {:?}",
-                start.elapsed()?,
-                self.meta_store.get_job(job_id).await?
-            );
-        } else if let Ok(Err(cube_err)) = res {
-            self.meta_store
-                .update_status(job_id, JobStatus::Error(cube_err.to_string()))
-                .await?;
-            error!(
-                "Running job error ({:?}): {:?}",
-                start.elapsed()?,
-                self.meta_store.get_job(job_id).await?
-            );
-        } else {
-            let job = self
-                .meta_store
-                .update_status(job_id, JobStatus::Completed)
-                .await?;
-            info!("Running job completed ({:?}): {:?}", start.elapsed()?, job);
-            // TODO delete jobs on reconciliation
-            self.meta_store.delete_job(job_id).await?;
+        match res {
+            Err(_) => {
+                self.meta_store
+                    .update_status(job_id, JobStatus::Timeout)
+                    .await?;
+                error!(
+                    "Running job timed out ({:?}): {:?}",
+                    start.elapsed()?,
+                    self.meta_store.get_job(job_id).await?
+                );
+            }
+            Ok(Err(cube_err)) => {
+                self.meta_store
+                    .update_status(job_id, JobStatus::Error(cube_err.to_string()))
+                    .await?;
+                error!(
+                    "Running job error ({:?}): {:?}",
+                    start.elapsed()?,
+                    self.meta_store.get_job(job_id).await?
+                );
+            }
+            Ok(Ok(result)) => {
+                let job = self.meta_store.complete_job(job_id, result).await?;
+                info!("Running job completed ({:?}): {:?}", start.elapsed()?, job);
+                // TODO delete jobs on reconciliation
+                self.meta_store.delete_job(job_id).await?;
+            }
         }
         Ok(())
     }
 
-    async fn route_job(&self, job: &Job) -> Result<(), CubeError> {
+    /// Jobs report their progress to `progress`, see [crate::util::job_progress].
+    async fn route_job(
+        &self,
+        job: &Job,
+        progress: Arc<Mutex<Option<JobProgress>>>,
+    ) -> Result<JobResult, CubeError> {
+        let mut result = JobResult::default();
         match job.job_type() {
             JobType::WalPartitioning => {
                 if let RowKey::Table(TableId::WALs, wal_id) = job.row_reference() {
                     let chunk_store = self.chunk_store.clone();
                     let wal_id = *wal_id;
-                    tokio::spawn(async move { chunk_store.partition(wal_id).await }).await??
+                    tokio::spawn(with_job_progress(progress, async move {
+                        chunk_store.partition(wal_id).await
+                    }))
+                    .await??
                 } else {
                     Self::fail_job_row_key(job);
                 }
@@ -527,8 +806,10 @@ impl JobRunner {
                 if let RowKey::Table(TableId::Partitions, partition_id) = job.row_reference() {
                     let chunk_store = self.chunk_store.clone();
                     let partition_id = *partition_id;
-                    tokio::spawn(async move { chunk_store.repartition(partition_id).await })
-                        .await??
+                    tokio::spawn(with_job_progress(progress, async move {
+                        chunk_store.repartition(partition_id).await
+                    }))
+                    .await??
                 } else {
                     Self::fail_job_row_key(job);
                 }
@@ -537,8 +818,10 @@ impl JobRunner {
                 if let RowKey::Table(TableId::Partitions, partition_id) = job.row_reference() {
                     let compaction_service = self.compaction_service.clone();
                     let partition_id = *partition_id;
-                    tokio::spawn(async move { compaction_service.compact(partition_id).await })
-                        .await??;
+                    result = tokio::spawn(with_job_progress(progress, async move {
+                        compaction_service.compact(partition_id).await
+                    }))
+                    .await??;
                 } else {
                     Self::fail_job_row_key(job);
                 }
@@ -547,24 +830,29 @@ impl JobRunner {
                 if let RowKey::Table(TableId::Tables, table_id) = job.row_reference() {
                     let import_service = self.import_service.clone();
                     let table_id = *table_id;
-                    tokio::spawn(async move { import_service.import_table(table_id).await })
-                        .await??
+                    tokio::spawn(with_job_progress(progress, async move {
+                        import_service.import_table(table_id).await
+                    }))
+                    .await??
                 } else {
                     Self::fail_job_row_key(job);
                 }
             }
             JobType::TableImportCSV(location) => {
                 if let RowKey::Table(TableId::Tables, table_id) = job.row_reference() {
-                    self.import_service
-                        .clone()
-                        .import_table_part(*table_id, location)
-                        .await?
+                    with_job_progress(
+                        progress,
+                        self.import_service
+                            .clone()
+                            .import_table_part(*table_id, location),
+                    )
+                    .await?
                 } else {
                     Self::fail_job_row_key(job);
                 }
             }
         }
-        Ok(())
+        Ok(result)
     }
 
     fn fail_job_row_key(job: &Job) {
@@ -586,6 +874,7 @@ impl ClusterImpl {
         query_executor: Arc<dyn QueryExecutor>,
         meta_store_sender: Sender<MetaStoreEvent>,
         cluster_transport: Arc<dyn ClusterTransport>,
+        partition_stats: Arc<PartitionAccessStats>,
     ) -> Arc<ClusterImpl> {
         let (close_worker_socket_tx, close_worker_socket_rx) = watch::channel(false);
         Arc::new_cyclic(|this| ClusterImpl {
@@ -602,10 +891,13 @@ impl ClusterImpl {
             job_notify: Arc::new(Notify::new()),
             meta_store_sender,
             jobs_enabled: Arc::new(RwLock::new(true)),
+            selects_in_flight: std::sync::Mutex::new(HashMap::new()),
             #[cfg(not(target_os = "windows"))]
             select_process_pool: RwLock::new(None),
             config_obj,
             query_executor,
+            partition_stats,
+            running_selects: RunningSelects::new(),
             stop_token: CancellationToken::new(),
             close_worker_socket_tx,
             close_worker_socket_rx: RwLock::new(close_worker_socket_rx),
@@ -628,6 +920,22 @@ impl ClusterImpl {
         }
     }
 
+    fn is_read_replica(&self) -> bool {
+        self.config_obj
+            .read_replica_workers()
+            .contains(&self.server_name)
+    }
+
+    /// Workers that run jobs, i.e. all select workers except read replicas.
+    fn job_workers(&self) -> Vec<&String> {
+        let replicas = self.config_obj.read_replica_workers();
+        self.config_obj
+            .select_workers()
+            .iter()
+            .filter(|w| !replicas.contains(w))
+            .collect()
+    }
+
     pub async fn wait_processing_loops(&self) -> Result<(), CubeError> {
         let mut futures = Vec::new();
         #[cfg(not(target_os = "windows"))]
@@ -643,8 +951,21 @@ impl ClusterImpl {
             ));
         }
 
-        for _ in 0..self.config_obj.job_runners_count() {
-            // TODO number of job event loops
+        // Read replicas are never assigned jobs.
+        let job_runners_count = if self.is_read_replica() {
+            0
+        } else {
+            self.config_obj.job_runners_count()
+        };
+        let background_job_runners_count = if self.is_read_replica() {
+            0
+        } else {
+            self.config_obj.background_job_runners_count()
+        };
+        let runner_classes = iter::repeat(JobClass::Ingestion)
+            .take(job_runners_count)
+            .chain(iter::repeat(JobClass::Background).take(background_job_runners_count));
+        for job_class in runner_classes {
             let job_runner = JobRunner {
                 meta_store: self.meta_store.clone(),
                 chunk_store: self.chunk_store.clone(),
@@ -653,6 +974,8 @@ impl ClusterImpl {
                 server_name: self.server_name.clone(),
                 notify: self.job_notify.clone(),
                 jobs_enabled: self.jobs_enabled.clone(),
+                job_class,
+                maintenance_window: self.config_obj.maintenance_window(),
             };
             futures.push(tokio::spawn(async move {
                 job_runner.processing_loop().await;
@@ -845,13 +1168,27 @@ impl ClusterImpl {
         .await
     }
 
+    /// Results sent to other nodes are compressed, there is no point in it for this node.
+    fn transport_compression(&self, node_name: &str) -> TransportCompression {
+        if self.server_name == node_name {
+            TransportCompression::None
+        } else {
+            self.config_obj.transport_compression()
+        }
+    }
+
     #[instrument(level = "trace", skip(self, plan_node))]
     async fn run_local_select_serialized(
         &self,
         plan_node: SerializedPlan,
+        compression: TransportCompression,
     ) -> Result<(SchemaRef, Vec<SerializedRecordBatchStream>), CubeError> {
+        let compression_threshold = self.config_obj.transport_compression_threshold();
+        let batch_cache_max_size = self.config_obj.worker_batch_cache_max_size();
         let start = SystemTime::now();
         debug!("Running select: {:?}", plan_node);
+        self.partition_stats
+            .record_reads(plan_node.partition_ids_to_execute().iter());
         let to_download = plan_node.files_to_download();
         let file_futures = to_download
             .iter()
@@ -877,14 +1214,19 @@ impl ClusterImpl {
 
         #[cfg(target_os = "windows")]
         {
+            self.query_executor
+                .set_batch_cache_max_size(batch_cache_max_size);
             // TODO optimize for no double conversion
             let (schema, records) = self
                 .query_executor
                 .execute_worker_plan(plan_node.clone(), remote_to_local_names)
                 .await?;
-            let records = SerializedRecordBatchStream::write(schema.as_ref(), records);
+            let records = SerializedRecordBatchStream::write(schema.as_ref(), records)?
+                .into_iter()
+                .map(|r| r.compress(compression, compression_threshold))
+                .collect::<Result<Vec<_>, _>>()?;
             info!("Running select completed ({:?})", start.elapsed()?);
-            Ok((schema, records?))
+            Ok((schema, records))
         }
 
         #[cfg(not(target_os = "windows"))]
@@ -893,23 +1235,35 @@ impl ClusterImpl {
 
             let res = if let Some(pool) = pool_option {
                 let serialized_plan_node = plan_node.clone();
-                pool.process(WorkerMessage::Select(
-                    serialized_plan_node,
-                    remote_to_local_names,
-                ))
+                let priority = plan_node.priority();
+                pool.process_with_priority(
+                    WorkerMessage::Select(
+                        serialized_plan_node,
+                        remote_to_local_names,
+                        compression,
+                        compression_threshold,
+                        batch_cache_max_size,
+                    ),
+                    priority,
+                )
                 .instrument(tracing::span!(
                     tracing::Level::TRACE,
                     "execute_worker_plan_on_pool"
                 ))
                 .await
             } else {
+                self.query_executor
+                    .set_batch_cache_max_size(batch_cache_max_size);
                 // TODO optimize for no double conversion
                 let (schema, records) = self
                     .query_executor
                     .execute_worker_plan(plan_node.clone(), remote_to_local_names)
                     .await?;
-                let records = SerializedRecordBatchStream::write(schema.as_ref(), records);
-                Ok((schema, records?))
+                let records = SerializedRecordBatchStream::write(schema.as_ref(), records)?
+                    .into_iter()
+                    .map(|r| r.compress(compression, compression_threshold))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok((schema, records))
             };
 
             info!("Running select completed ({:?})", start.elapsed()?);
@@ -988,7 +1342,7 @@ impl ClusterImpl {
             return Ok(leader.to_string());
         }
 
-        Err(CubeError::internal(
+        Err(CubeError::unavailable(
             "No leader has been elected".to_string(),
         ))
     }
@@ -1040,8 +1394,16 @@ impl ClusterImpl {
 
     async fn start_stream_on_worker(self: Arc<Self>, m: NetworkMessage) -> Box<dyn MessageStream> {
         match m {
-            NetworkMessage::SelectStart(p) => {
-                let (schema, results) = match self.run_local_select_serialized(p).await {
+            NetworkMessage::SelectStart(p, compression) => {
+                let query_id = p.query_id().cloned();
+                let select_id = p.select_id();
+                let (schema, results) = match with_query_id(
+                    query_id,
+                    self.running_selects
+                        .run(select_id, self.run_local_select_serialized(p, compression)),
+                )
+                .await
+                {
                     Err(e) => return Box::new(QueryStream::new_error(e)),
                     Ok(x) => x,
                 };
@@ -1056,7 +1418,7 @@ impl ClusterImpl {
         node_name: &str,
         plan: SerializedPlan,
     ) -> Result<SendableRecordBatchStream, CubeError> {
-        let init_message = NetworkMessage::SelectStart(plan);
+        let init_message = NetworkMessage::SelectStart(plan, self.transport_compression(node_name));
         let mut c = self.call_streaming(node_name, init_message).await?;
         let schema = match c.receive().await? {
             NetworkMessage::SelectResultSchema(s) => s,
@@ -1184,11 +1546,22 @@ impl ClusterImpl {
         };
         log::debug!("Got {} partitions, running the warmup", partitions.len());
 
+        // Partitions come hottest first. Those that were not read for a long time are left to be
+        // downloaded by selects, partitions that were never read might be new and are kept.
+        let idle_secs = self.config_obj.startup_warmup_idle_secs();
+        let idle_since = Utc::now() - chrono::Duration::seconds(idle_secs as i64);
         for (p, chunks) in partitions {
-            if self.node_name_by_partitions(&[p.partition_id]) != self.server_name {
+            let placement_id = p.placement_id.unwrap_or(p.partition_id);
+            if !p.replicated && self.node_name_by_partitions(&[placement_id]) != self.server_name {
+                continue;
+            }
+            if idle_secs != 0 && matches!(p.last_read, Some(t) if t < idle_since) {
                 continue;
             }
-            if let Some(file) = partition_file_name(p.parent_partition_id, p.partition_id) {
+            if let Some(file) = p.attached_file.clone().or_else(|| {
+                partition_file_name(p.parent_partition_id, p.partition_id)
+                    .map(|f| storage_file_name(&p.storage, f))
+            }) {
                 if self.stop_token.is_cancelled() {
                     log::debug!("Startup warmup cancelled");
                     return;
@@ -1202,7 +1575,11 @@ impl ClusterImpl {
                     log::debug!("Startup warmup cancelled");
                     return;
                 }
-                ack_error!(self.remote_fs.download_file(&chunk_file_name(c)).await);
+                ack_error!(
+                    self.remote_fs
+                        .download_file(&storage_file_name(&p.storage, chunk_file_name(c)))
+                        .await
+                );
             }
         }
         log::debug!("Startup warmup finished");
@@ -0,0 +1,96 @@
+//! Access statistics of partitions. Each node that executes selects counts reads of partitions in
+//! memory and adds them to [crate::metastore::Partition::read_count] and
+//! [crate::metastore::Partition::last_used] every
+//! [crate::config::ConfigObj::partition_stats_flush_secs], so the metastore sees reads of all
+//! workers with a delay of at most one interval. Reads that were not flushed before a restart are
+//! lost. `system.partition_stats` reports the persisted statistics, startup warmup downloads
+//! recently read partitions first.
+use crate::config::ConfigObj;
+use crate::metastore::MetaStore;
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use futures_timer::Delay;
+use std::collections::HashMap;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub struct PartitionAccessStats {
+    meta_store: Arc<dyn MetaStore>,
+    interval: Duration,
+    /// Reads and the time of the last read by partition id since the last flush.
+    pending: Mutex<HashMap<u64, (u64, DateTime<Utc>)>>,
+    flush_loop: WorkerLoop,
+}
+
+crate::di_service!(PartitionAccessStats, []);
+
+impl PartitionAccessStats {
+    pub fn new(meta_store: Arc<dyn MetaStore>, config: &dyn ConfigObj) -> Arc<Self> {
+        Arc::new(PartitionAccessStats {
+            meta_store,
+            interval: Duration::from_secs(config.partition_stats_flush_secs()),
+            pending: Mutex::new(HashMap::new()),
+            flush_loop: WorkerLoop::new("PartitionAccessStats"),
+        })
+    }
+
+    /// Counts a read of each of `partition_ids`. Does nothing when statistics are disabled.
+    pub fn record_reads(&self, partition_ids: impl IntoIterator<Item = u64>) {
+        if self.interval == Duration::from_secs(0) {
+            return;
+        }
+        let now = Utc::now();
+        let mut pending = self.pending.lock().unwrap();
+        for id in partition_ids {
+            let (reads, last_read) = pending.entry(id).or_insert((0, now));
+            *reads += 1;
+            *last_read = now;
+        }
+    }
+
+    /// Persists the pending reads in the metastore. Reads are kept for the next flush if the
+    /// metastore can't be reached.
+    pub async fn flush(&self) -> Result<(), CubeError> {
+        let pending = mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let reads = pending
+            .iter()
+            .map(|(id, (reads, last_read))| (*id, *reads, *last_read))
+            .collect::<Vec<_>>();
+        if let Err(e) = self.meta_store.record_partition_reads(reads).await {
+            let mut current = self.pending.lock().unwrap();
+            for (id, (reads, last_read)) in pending {
+                let (r, l) = current.entry(id).or_insert((0, last_read));
+                *r += reads;
+                *l = (*l).max(last_read);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub async fn wait_processing_loop(self: Arc<Self>) {
+        if self.interval == Duration::from_secs(0) {
+            return;
+        }
+        let interval = self.interval;
+        self.flush_loop
+            .process(
+                self.clone(),
+                async move |_| {
+                    Delay::new(interval).await;
+                    Ok(())
+                },
+                async move |s, _| s.flush().await,
+            )
+            .await
+    }
+
+    pub fn stop_processing_loop(&self) {
+        self.flush_loop.stop();
+    }
+}
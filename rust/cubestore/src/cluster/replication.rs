@@ -0,0 +1,522 @@
+//! Logical replication of selected tables to another CubeStore cluster, e.g. for disaster recovery
+//! or to serve queries closer to users. The router queues chunks as they are activated in default
+//! indexes of CUBESTORE_REPLICATION_TABLES and ships their rows to the metastore port of the
+//! router at CUBESTORE_REPLICATION_TARGET. The target creates missing schemas and tables and
+//! ingests the rows into all indexes of its table, so it partitions and compacts data on its own.
+//!
+//! Chunks of a table are shipped in the order they were activated, tables do not wait for each
+//! other. Delivery is at-least-once: a chunk whose acknowledgement is lost is shipped again.
+//! Compacted chunk files are kept for [crate::config::ConfigObj::not_used_timeout], a chunk that
+//! is not shipped by then fails and stays at the head of the queue, so the lag of its table grows
+//! until the table is copied over by hand. Queues are kept in memory of the router, chunks that
+//! were not shipped before a restart are lost. Secondary indexes, schema changes and drops are not
+//! replicated. `system.replication` reports the state of each replicated table.
+use crate::cluster::message::NetworkMessage;
+use crate::cluster::transport::ClusterTransport;
+use crate::config::ConfigObj;
+use crate::metastore::index::Index;
+use crate::metastore::{Chunk, IdRow, MetaStore, MetaStoreEvent};
+use crate::remotefs::RemoteFs;
+use crate::store::{read_remote_rows, ChunkDataStore, DataFrame};
+use crate::table::data::MutRows;
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use futures::future::join_all;
+use futures_timer::Delay;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+pub struct TableReplicator {
+    meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
+    transport: Arc<dyn ClusterTransport>,
+    target: Option<String>,
+    interval: Duration,
+    /// Keyed by `schema.table`.
+    tables: Mutex<HashMap<String, TableReplication>>,
+    event_receiver: tokio::sync::Mutex<Receiver<MetaStoreEvent>>,
+    event_loop: WorkerLoop,
+    ship_loop: WorkerLoop,
+}
+
+crate::di_service!(TableReplicator, []);
+
+impl std::fmt::Debug for TableReplicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TableReplicator")
+            .field("target", &self.target)
+            .field("interval", &self.interval)
+            .field("tables", &self.tables)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableReplication {
+    /// `schema.table`.
+    pub table: String,
+    /// Activated chunks that are not shipped yet, oldest first.
+    pub pending: VecDeque<PendingChunk>,
+    pub shipped_chunks: u64,
+    pub shipped_rows: u64,
+    pub last_shipped: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl TableReplication {
+    /// Time since the oldest chunk that is not shipped yet was activated.
+    pub fn lag(&self, now: DateTime<Utc>) -> ChronoDuration {
+        match self.pending.front() {
+            Some(c) => now - c.activated_at,
+            None => ChronoDuration::zero(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PendingChunk {
+    pub chunk: IdRow<Chunk>,
+    /// Default index the chunk belongs to, its columns are in the order of the index.
+    pub index: Index,
+    pub activated_at: DateTime<Utc>,
+}
+
+/// Rows of a chunk sent to the replication target. Columns are in the order of the table.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReplicatedChunk {
+    pub schema_name: String,
+    pub table_name: String,
+    pub data: DataFrame,
+}
+
+impl TableReplicator {
+    pub fn new(
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+        transport: Arc<dyn ClusterTransport>,
+        config: &dyn ConfigObj,
+        event_receiver: Receiver<MetaStoreEvent>,
+    ) -> Arc<TableReplicator> {
+        let tables = config
+            .replication_tables()
+            .iter()
+            .map(|t| {
+                (
+                    t.clone(),
+                    TableReplication {
+                        table: t.clone(),
+                        pending: VecDeque::new(),
+                        shipped_chunks: 0,
+                        shipped_rows: 0,
+                        last_shipped: None,
+                        last_error: None,
+                    },
+                )
+            })
+            .collect();
+        Arc::new(TableReplicator {
+            meta_store,
+            remote_fs,
+            transport,
+            target: config.replication_target().clone(),
+            interval: Duration::from_secs(config.replication_interval_secs()),
+            tables: Mutex::new(tables),
+            event_receiver: tokio::sync::Mutex::new(event_receiver),
+            event_loop: WorkerLoop::new("TableReplicator events"),
+            ship_loop: WorkerLoop::new("TableReplicator"),
+        })
+    }
+
+    pub fn all(&self) -> Vec<TableReplication> {
+        let mut tables = self
+            .tables
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        tables.sort_by(|a, b| a.table.cmp(&b.table));
+        tables
+    }
+
+    pub async fn wait_processing_loops(self: Arc<Self>) {
+        if self.tables.lock().unwrap().is_empty() {
+            return;
+        }
+        let events = self.event_loop.process(
+            self.clone(),
+            async move |r| match r.event_receiver.lock().await.recv().await {
+                Err(RecvError::Closed) => futures::future::pending().await,
+                res => Ok(res),
+            },
+            async move |r, res| match res {
+                Ok(event) => r.queue_chunk(event).await,
+                Err(e) => {
+                    r.set_error_for_all(format!("Activated chunks were missed: {}", e));
+                    Ok(())
+                }
+            },
+        );
+        let interval = self.interval;
+        let ship = self.ship_loop.process(
+            self.clone(),
+            async move |_| {
+                Delay::new(interval).await;
+                Ok(())
+            },
+            async move |r, _| r.ship_pending().await,
+        );
+        tokio::join!(events, ship);
+    }
+
+    pub fn stop_processing_loops(&self) {
+        self.event_loop.stop();
+        self.ship_loop.stop();
+    }
+
+    async fn queue_chunk(&self, event: MetaStoreEvent) -> Result<(), CubeError> {
+        let chunk = match event {
+            MetaStoreEvent::UpdateChunk(old, new)
+                if !old.get_row().uploaded() && new.get_row().uploaded() =>
+            {
+                new
+            }
+            _ => return Ok(()),
+        };
+        let partition = self
+            .meta_store
+            .get_partition(chunk.get_row().get_partition_id())
+            .await?;
+        let index = self
+            .meta_store
+            .get_index(partition.get_row().get_index_id())
+            .await?;
+        if index.get_row().get_name() != "default" {
+            return Ok(());
+        }
+        let table = self
+            .meta_store
+            .get_table_by_id(index.get_row().table_id())
+            .await?;
+        let schema = self
+            .meta_store
+            .get_schema_by_id(table.get_row().get_schema_id())
+            .await?;
+        let name = format!(
+            "{}.{}",
+            schema.get_row().get_name(),
+            table.get_row().get_table_name()
+        );
+        if let Some(t) = self.tables.lock().unwrap().get_mut(&name) {
+            t.pending.push_back(PendingChunk {
+                chunk,
+                index: index.get_row().clone(),
+                activated_at: Utc::now(),
+            });
+        }
+        Ok(())
+    }
+
+    fn set_error_for_all(&self, error: String) {
+        warn!("Replication: {}", error);
+        for t in self.tables.lock().unwrap().values_mut() {
+            t.last_error = Some(error.clone());
+        }
+    }
+
+    async fn ship_pending(self: Arc<Self>) -> Result<(), CubeError> {
+        let target = match &self.target {
+            Some(t) => t.clone(),
+            None => return Ok(()),
+        };
+        let tables = self
+            .tables
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        join_all(tables.into_iter().map(|table| {
+            let replicator = self.clone();
+            let target = target.clone();
+            async move { replicator.ship_table(&table, &target).await }
+        }))
+        .await;
+        Ok(())
+    }
+
+    /// Ships queued chunks of the table until the queue is empty or a chunk fails.
+    async fn ship_table(&self, table: &str, target: &str) {
+        loop {
+            let pending = match self.tables.lock().unwrap()[table].pending.front() {
+                Some(p) => p.clone(),
+                None => return,
+            };
+            let res = self.ship_chunk(table, &pending, target).await;
+            let mut tables = self.tables.lock().unwrap();
+            let t = tables.get_mut(table).unwrap();
+            match res {
+                Ok(rows) => {
+                    t.pending.pop_front();
+                    t.shipped_chunks += 1;
+                    t.shipped_rows += rows;
+                    t.last_shipped = Some(Utc::now());
+                    t.last_error = None;
+                }
+                Err(e) => {
+                    warn!(
+                        "Replication of chunk {} of {} failed: {}",
+                        pending.chunk.get_id(),
+                        table,
+                        e
+                    );
+                    t.last_error = Some(e.message);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns the number of shipped rows.
+    async fn ship_chunk(
+        &self,
+        table: &str,
+        pending: &PendingChunk,
+        target: &str,
+    ) -> Result<u64, CubeError> {
+        let chunk = self.read_chunk(table, pending).await?;
+        let rows = chunk.data.len() as u64;
+        match self
+            .transport
+            .send_to_worker(target.to_string(), NetworkMessage::ReplicateChunk(chunk))
+            .await?
+        {
+            NetworkMessage::ReplicateChunkResult(res) => res?,
+            m => {
+                return Err(CubeError::internal(format!(
+                    "Unexpected response to replicated chunk: {:?}",
+                    m
+                )))
+            }
+        }
+        Ok(rows)
+    }
+
+    async fn read_chunk(
+        &self,
+        table: &str,
+        pending: &PendingChunk,
+    ) -> Result<ReplicatedChunk, CubeError> {
+        let (schema_name, table_name) = table.split_at(table.find('.').unwrap_or(0));
+        let table_name = table_name.trim_start_matches('.');
+        let table = self
+            .meta_store
+            .get_table(schema_name.to_string(), table_name.to_string())
+            .await?;
+        let columns = table.get_row().get_columns().clone();
+        let remote_path = pending
+            .chunk
+            .get_row()
+            .get_full_name(pending.chunk.get_id());
+        let rows = read_remote_rows(
+            self.remote_fs.as_ref(),
+            &pending.index,
+            &remote_path,
+            &columns,
+        )
+        .await?;
+        Ok(ReplicatedChunk {
+            schema_name: schema_name.to_string(),
+            table_name: table_name.to_string(),
+            data: DataFrame::new(columns, rows),
+        })
+    }
+}
+
+/// Ingests a chunk received from another cluster, runs on the router of the target.
+pub async fn apply_replicated_chunk(
+    meta_store: Arc<dyn MetaStore>,
+    chunk_store: Arc<dyn ChunkDataStore>,
+    chunk: ReplicatedChunk,
+) -> Result<(), CubeError> {
+    let columns = chunk.data.get_columns();
+    let table = match meta_store
+        .get_table(chunk.schema_name.clone(), chunk.table_name.clone())
+        .await
+    {
+        Ok(t) => t,
+        Err(_) => {
+            meta_store
+                .create_schema(chunk.schema_name.clone(), true)
+                .await?;
+            meta_store
+                .create_table(
+                    chunk.schema_name.clone(),
+                    chunk.table_name.clone(),
+                    columns.clone(),
+                    None,
+                    None,
+                    vec![],
+                    true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
+                )
+                .await?
+        }
+    };
+    let table_columns = table.get_row().get_columns();
+    let same_columns = table_columns.len() == columns.len()
+        && table_columns.iter().zip(columns.iter()).all(|(l, r)| {
+            l.get_name() == r.get_name() && l.get_column_type() == r.get_column_type()
+        });
+    if !same_columns {
+        return Err(CubeError::user(format!(
+            "Columns of replicated table {}.{} differ from the source",
+            chunk.schema_name, chunk.table_name
+        )));
+    }
+
+    let rows = MutRows::from_heap_allocated(columns.len(), chunk.data.get_rows()).freeze();
+    let new_chunks = chunk_store
+        .partition_data(table.get_id(), rows, table_columns)
+        .await?;
+    let new_chunk_ids = join_all(new_chunks)
+        .await
+        .into_iter()
+        .map(|c| Ok(c??.get_id()))
+        .collect::<Result<Vec<u64>, CubeError>>()?;
+    meta_store
+        .activate_chunks(table.get_id(), new_chunk_ids)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::table::{Row, TableValue};
+
+    #[tokio::test]
+    async fn replicate_chunks() {
+        Config::test("replicate_chunks")
+            .update_config(|mut c| {
+                c.replication_tables = vec!["foo.orders".to_string()];
+                c
+            })
+            .start_test(async move |services| {
+                let service = services.sql_service;
+                service.exec_query("CREATE SCHEMA foo").await.unwrap();
+                service
+                    .exec_query("CREATE TABLE foo.orders (id int, amount int)")
+                    .await
+                    .unwrap();
+                service
+                    .exec_query("CREATE TABLE foo.other (id int)")
+                    .await
+                    .unwrap();
+                service
+                    .exec_query("INSERT INTO foo.orders (amount, id) VALUES (10, 1), (20, 2)")
+                    .await
+                    .unwrap();
+                service
+                    .exec_query("INSERT INTO foo.other (id) VALUES (1)")
+                    .await
+                    .unwrap();
+
+                let replicator = services
+                    .injector
+                    .get_service_typed::<TableReplicator>()
+                    .await;
+                // Events are processed in the background. Without a target, chunks stay queued.
+                let mut tables = replicator.all();
+                for _ in 0..50 {
+                    if !tables[0].pending.is_empty() {
+                        break;
+                    }
+                    Delay::new(Duration::from_millis(100)).await;
+                    tables = replicator.all();
+                }
+                assert_eq!(tables.len(), 1);
+                assert_eq!(tables[0].table, "foo.orders");
+                assert_eq!(tables[0].pending.len(), 1);
+                let result = service
+                    .exec_query(
+                        "SELECT table_name, pending_chunks, shipped_chunks FROM system.replication",
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    result.get_rows(),
+                    &vec![Row::new(vec![
+                        TableValue::String("foo.orders".to_string()),
+                        TableValue::Int(1),
+                        TableValue::Int(0),
+                    ])]
+                );
+
+                let mut chunk = replicator
+                    .read_chunk("foo.orders", &tables[0].pending[0])
+                    .await
+                    .unwrap();
+                assert_eq!(chunk.data.len(), 2);
+                // Apply it as another table of the same cluster.
+                chunk.schema_name = "bar".to_string();
+                apply_replicated_chunk(
+                    services.meta_store.clone(),
+                    services.injector.get_service_typed().await,
+                    chunk,
+                )
+                .await
+                .unwrap();
+
+                let result = service
+                    .exec_query("SELECT id, amount FROM bar.orders ORDER BY id")
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    result.get_rows(),
+                    &vec![
+                        Row::new(vec![TableValue::Int(1), TableValue::Int(10)]),
+                        Row::new(vec![TableValue::Int(2), TableValue::Int(20)]),
+                    ]
+                );
+
+                service
+                    .exec_query("CREATE TABLE bar.mismatch (id text)")
+                    .await
+                    .unwrap();
+                let mismatch = ReplicatedChunk {
+                    schema_name: "bar".to_string(),
+                    table_name: "mismatch".to_string(),
+                    data: DataFrame::new(
+                        services
+                            .meta_store
+                            .get_table("foo".to_string(), "other".to_string())
+                            .await
+                            .unwrap()
+                            .get_row()
+                            .get_columns()
+                            .clone(),
+                        vec![Row::new(vec![TableValue::Int(1)])],
+                    ),
+                };
+                assert!(apply_replicated_chunk(
+                    services.meta_store.clone(),
+                    services.injector.get_service_typed().await,
+                    mismatch,
+                )
+                .await
+                .is_err());
+            })
+            .await;
+    }
+}
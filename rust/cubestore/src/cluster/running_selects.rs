@@ -0,0 +1,89 @@
+//! Selects running on this worker that the router may stop before they finish, e.g. once a
+//! `LIMIT` is satisfied by results of other workers. The router assigns an id to such selects,
+//! see [crate::queryplanner::serialized_plan::SerializedPlan::select_id], and sends
+//! [crate::cluster::message::NetworkMessage::CancelSelect] with it.
+//!
+//! Cancelling drops the running future, which also kills the select process running it, see
+//! [crate::cluster::worker_pool]. Cancels that arrive before the select started or after it
+//! finished are ignored, the router drops the result in that case.
+use crate::CubeError;
+use futures::Future;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+
+pub struct RunningSelects {
+    selects: Mutex<HashMap<u64, CancellationToken>>,
+}
+
+impl RunningSelects {
+    pub fn new() -> RunningSelects {
+        RunningSelects {
+            selects: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `f` until it completes or the select is cancelled. Selects without an id can't be
+    /// cancelled.
+    pub async fn run<T>(
+        &self,
+        select_id: Option<u64>,
+        f: impl Future<Output = Result<T, CubeError>>,
+    ) -> Result<T, CubeError> {
+        let select_id = match select_id {
+            Some(id) => id,
+            None => return f.await,
+        };
+        let token = CancellationToken::new();
+        self.selects
+            .lock()
+            .unwrap()
+            .insert(select_id, token.clone());
+        scopeguard::defer!({
+            self.selects.lock().unwrap().remove(&select_id);
+        });
+        tokio::select! {
+            res = f => res,
+            _ = token.cancelled() => Err(CubeError::user(format!(
+                "Select {} was cancelled by the router",
+                select_id
+            ))),
+        }
+    }
+
+    pub fn cancel(&self, select_id: u64) {
+        if let Some(token) = self.selects.lock().unwrap().get(&select_id) {
+            token.cancel();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn cancel_running_select() {
+        let selects = Arc::new(RunningSelects::new());
+        let selects_to_move = selects.clone();
+        let running = tokio::spawn(async move {
+            selects_to_move
+                .run(Some(1), async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Ok(())
+                })
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // Unknown ids are ignored.
+        selects.cancel(2);
+        selects.cancel(1);
+        assert!(running.await.unwrap().is_err());
+        assert!(selects.selects.lock().unwrap().is_empty());
+
+        assert_eq!(selects.run(Some(1), async { Ok(5) }).await.unwrap(), 5);
+        assert_eq!(selects.run(None, async { Ok(6) }).await.unwrap(), 6);
+    }
+}
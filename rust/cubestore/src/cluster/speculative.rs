@@ -0,0 +1,158 @@
+//! Speculative execution of straggling selects. When a worker takes much longer to answer its
+//! part of a query than its peers did, e.g. because of a slow disk or a cold cache, the router
+//! sends the same partitions to another worker and takes the result that comes first.
+//!
+//! Selects on workers only read data, so running them twice is safe. A result always comes from
+//! a single worker as a whole, the other one is dropped, so no rows are duplicated. The worker
+//! that lost the race still finishes its select, its result is discarded.
+use crate::cluster::Cluster;
+use crate::queryplanner::serialized_plan::SerializedPlan;
+use crate::CubeError;
+use arrow::record_batch::RecordBatch;
+use futures::future::{select, Either};
+use log::warn;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpeculativeExecution {
+    /// A select is straggling when it runs this many times longer than the median of finished
+    /// selects of the same query.
+    pub slowdown: u64,
+    /// Selects running for less than this are never duplicated.
+    pub min_delay: Duration,
+}
+
+/// Timings of selects sent by a single cluster send node.
+pub struct StragglerTracker {
+    selects: usize,
+    finished: Mutex<Vec<Duration>>,
+    finished_notify: Notify,
+}
+
+impl StragglerTracker {
+    pub fn new(selects: usize) -> StragglerTracker {
+        StragglerTracker {
+            selects,
+            finished: Mutex::new(Vec::new()),
+            finished_notify: Notify::new(),
+        }
+    }
+
+    /// Runs the select on `node_name` and duplicates it on another node once it becomes a
+    /// straggler.
+    pub async fn run_select(
+        &self,
+        cluster: &Arc<dyn Cluster>,
+        speculative: SpeculativeExecution,
+        node_name: &str,
+        plan: SerializedPlan,
+    ) -> Result<Vec<RecordBatch>, CubeError> {
+        let start = Instant::now();
+        let mut primary = cluster.run_select(node_name, plan.clone());
+        let res = tokio::select! {
+            res = &mut primary => res,
+            _ = self.wait_straggling(start, speculative) => {
+                match cluster.speculative_node_name(node_name) {
+                    None => primary.await,
+                    Some(other_node) => {
+                        warn!(
+                            "Select on {} is running for {:?}, running it on {} as well",
+                            node_name,
+                            start.elapsed(),
+                            other_node
+                        );
+                        let duplicate = cluster.run_select(&other_node, plan);
+                        // The first successful result wins.
+                        match select(primary, duplicate).await {
+                            Either::Left((Ok(r), _)) | Either::Right((Ok(r), _)) => Ok(r),
+                            Either::Left((Err(_), duplicate)) => duplicate.await,
+                            Either::Right((Err(_), primary)) => primary.await,
+                        }
+                    }
+                }
+            }
+        };
+        if res.is_ok() {
+            self.finished.lock().unwrap().push(start.elapsed());
+            self.finished_notify.notify_waiters();
+        }
+        res
+    }
+
+    /// Time after which a running select is a straggler. Known once at least half of the selects
+    /// have finished.
+    fn straggler_threshold(&self, speculative: SpeculativeExecution) -> Option<Duration> {
+        let mut finished = self.finished.lock().unwrap().clone();
+        if finished.is_empty() || finished.len() * 2 < self.selects {
+            return None;
+        }
+        finished.sort();
+        let median = finished[finished.len() / 2];
+        Some(std::cmp::max(
+            speculative.min_delay,
+            median * speculative.slowdown as u32,
+        ))
+    }
+
+    async fn wait_straggling(&self, start: Instant, speculative: SpeculativeExecution) {
+        loop {
+            let notified = self.finished_notify.notified();
+            match self.straggler_threshold(speculative) {
+                Some(threshold) => {
+                    let deadline = start + threshold;
+                    if Instant::now() >= deadline {
+                        return;
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep_until(deadline.into()) => return,
+                        _ = notified => {}
+                    }
+                }
+                None => notified.await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn straggler_threshold() {
+        let speculative = SpeculativeExecution {
+            slowdown: 3,
+            min_delay: Duration::from_millis(100),
+        };
+        let tracker = StragglerTracker::new(4);
+        assert_eq!(tracker.straggler_threshold(speculative), None);
+        tracker
+            .finished
+            .lock()
+            .unwrap()
+            .push(Duration::from_millis(200));
+        assert_eq!(tracker.straggler_threshold(speculative), None);
+        tracker
+            .finished
+            .lock()
+            .unwrap()
+            .push(Duration::from_millis(10));
+        assert_eq!(
+            tracker.straggler_threshold(speculative),
+            Some(Duration::from_millis(600))
+        );
+
+        let tracker = StragglerTracker::new(2);
+        tracker
+            .finished
+            .lock()
+            .unwrap()
+            .push(Duration::from_millis(10));
+        assert_eq!(
+            tracker.straggler_threshold(speculative),
+            Some(Duration::from_millis(100))
+        );
+    }
+}
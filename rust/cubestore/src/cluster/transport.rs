@@ -25,14 +25,14 @@ impl dyn WorkerConnection {
         if sent {
             Ok(())
         } else {
-            Err(CubeError::internal("connection closed".to_string()))
+            Err(CubeError::unavailable("connection closed".to_string()))
         }
     }
 
     pub async fn receive(&mut self) -> Result<NetworkMessage, CubeError> {
         match self.maybe_receive().await? {
             Some(m) => Ok(m),
-            None => Err(CubeError::internal("connection closed".to_string())),
+            None => Err(CubeError::unavailable("connection closed".to_string())),
         }
     }
 }
@@ -94,8 +94,9 @@ impl ClusterTransport for ClusterTransportImpl {
             Duration::from_secs(self.config.connection_timeout()),
             TcpStream::connect(worker_node.to_string()),
         )
-        .await?
-        .map_err(|e| CubeError::internal(format!("Can't connect to {}: {}", worker_node, e)))?;
+        .await
+        .map_err(|_| CubeError::unavailable(format!("Timed out connecting to {}", worker_node)))?
+        .map_err(|e| CubeError::unavailable(format!("Can't connect to {}: {}", worker_node, e)))?;
         Ok(Box::new(Connection { stream }))
     }
 }
@@ -128,9 +129,12 @@ impl MetaStoreTransport for MetaStoreTransportImpl {
             .to_string();
         let mut stream = tokio::time::timeout(
             Duration::from_secs(self.config.connection_timeout()),
-            TcpStream::connect(meta_remote_addr),
+            TcpStream::connect(meta_remote_addr.clone()),
         )
-        .await??;
+        .await
+        .map_err(|_| {
+            CubeError::unavailable(format!("Timed out connecting to {}", meta_remote_addr))
+        })??;
         m.send(&mut stream).await?;
         let message = NetworkMessage::receive(&mut stream).await?;
         Ok(message)
@@ -1,6 +1,6 @@
+use crate::sql::priority::QueryPriority;
 use crate::CubeError;
 use async_trait::async_trait;
-use deadqueue::unlimited;
 use futures::future::join_all;
 use ipc_channel::ipc;
 use ipc_channel::ipc::{IpcReceiver, IpcSender};
@@ -8,14 +8,16 @@ use log::error;
 use procspawn::JoinHandle;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
 use std::fmt::Debug;
 use std::marker::PhantomData;
 use std::panic;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::runtime::Builder;
 use tokio::sync::oneshot::Sender;
-use tokio::sync::{oneshot, watch, Notify, RwLock};
+use tokio::sync::{oneshot, watch, Notify, RwLock, Semaphore};
 use tracing::{instrument, Instrument};
 use tracing_futures::WithSubscriber;
 
@@ -24,7 +26,7 @@ pub struct WorkerPool<
     R: Serialize + DeserializeOwned + Sync + Send + 'static,
     P: MessageProcessor<T, R> + Sync + Send + 'static,
 > {
-    queue: Arc<unlimited::Queue<Message<T, R>>>,
+    queue: Arc<PriorityQueue<Message<T, R>>>,
     stopped_tx: watch::Sender<bool>,
     workers: Vec<Arc<WorkerProcess<T, R, P>>>,
     processor: PhantomData<P>,
@@ -56,7 +58,7 @@ impl<
     > WorkerPool<T, R, P>
 {
     pub fn new(num: usize, timeout: Duration) -> WorkerPool<T, R, P> {
-        let queue = Arc::new(unlimited::Queue::new());
+        let queue = Arc::new(PriorityQueue::new());
         let (stopped_tx, stopped_rx) = watch::channel(false);
 
         let mut workers = Vec::new();
@@ -88,13 +90,26 @@ impl<
     }
 
     pub async fn process(&self, message: T) -> Result<R, CubeError> {
+        self.process_with_priority(message, QueryPriority::Normal)
+            .await
+    }
+
+    /// Messages waiting for a free process are taken in order of priority.
+    pub async fn process_with_priority(
+        &self,
+        message: T,
+        priority: QueryPriority,
+    ) -> Result<R, CubeError> {
         let (tx, rx) = oneshot::channel();
-        self.queue.push(Message {
-            message,
-            sender: tx,
-            span: tracing::Span::current(),
-            dispatcher: tracing::dispatcher::get_default(|d| d.clone()),
-        });
+        self.queue.push(
+            Message {
+                message,
+                sender: tx,
+                span: tracing::Span::current(),
+                dispatcher: tracing::dispatcher::get_default(|d| d.clone()),
+            },
+            priority,
+        );
         Ok(rx.await??)
     }
 
@@ -104,12 +119,76 @@ impl<
     }
 }
 
+/// Items of higher priority are popped first, items of the same priority in the order of pushes.
+struct PriorityQueue<M> {
+    entries: Mutex<(BinaryHeap<QueueEntry<M>>, u64)>,
+    available: Semaphore,
+}
+
+struct QueueEntry<M> {
+    priority: QueryPriority,
+    seq: u64,
+    item: M,
+}
+
+impl<M> PartialEq for QueueEntry<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<M> Eq for QueueEntry<M> {}
+
+impl<M> PartialOrd for QueueEntry<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for QueueEntry<M> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+impl<M> PriorityQueue<M> {
+    fn new() -> Self {
+        PriorityQueue {
+            entries: Mutex::new((BinaryHeap::new(), 0)),
+            available: Semaphore::new(0),
+        }
+    }
+
+    fn push(&self, item: M, priority: QueryPriority) {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let seq = entries.1;
+            entries.1 += 1;
+            entries.0.push(QueueEntry {
+                priority,
+                seq,
+                item,
+            });
+        }
+        self.available.add_permits(1);
+    }
+
+    async fn pop(&self) -> M {
+        // Every permit corresponds to an entry. Nothing is awaited after the permit is taken, so
+        // cancelled pops do not lose entries.
+        self.available.acquire().await.unwrap().forget();
+        self.entries.lock().unwrap().0.pop().unwrap().item
+    }
+}
+
 pub struct WorkerProcess<
     T: Debug + Serialize + DeserializeOwned + Sync + Send + 'static,
     R: Serialize + DeserializeOwned + Sync + Send + 'static,
     P: MessageProcessor<T, R> + Sync + Send + 'static,
 > {
-    queue: Arc<unlimited::Queue<Message<T, R>>>,
+    queue: Arc<PriorityQueue<Message<T, R>>>,
     timeout: Duration,
     processor: PhantomData<P>,
     stopped_rx: RwLock<watch::Receiver<bool>>,
@@ -123,7 +202,7 @@ impl<
     > WorkerProcess<T, R, P>
 {
     fn new(
-        queue: Arc<unlimited::Queue<Message<T, R>>>,
+        queue: Arc<PriorityQueue<Message<T, R>>>,
         timeout: Duration,
         stopped_rx: watch::Receiver<bool>,
     ) -> Self {
@@ -147,7 +226,7 @@ impl<
                         let mut stopped_rx = self.stopped_rx.write().await;
                         let Message {
                             message,
-                            sender,
+                            mut sender,
                             span,
                             dispatcher,
                         } = tokio::select! {
@@ -162,19 +241,27 @@ impl<
                                 message
                             }
                         };
+                        if sender.is_closed() {
+                            // Cancelled while waiting in the queue.
+                            continue;
+                        }
                         let process_message_res_timeout = tokio::time::timeout(
                             self.timeout,
                             self.process_message(message, args_tx, res_rx),
                         )
                         .instrument(span)
-                        .with_subscriber(dispatcher)
-                        .await;
-                        let process_message_res = match process_message_res_timeout {
-                            Ok(r) => r,
-                            Err(e) => Err(CubeError::internal(format!(
-                                "Timed out after waiting for {}",
-                                e
-                            ))),
+                        .with_subscriber(dispatcher);
+                        let process_message_res = tokio::select! {
+                            res = process_message_res_timeout => match res {
+                                Ok(r) => r,
+                                Err(e) => Err(CubeError::internal(format!(
+                                    "Timed out after waiting for {}",
+                                    e
+                                ))),
+                            },
+                            // Nobody waits for the result, e.g. the router stopped the select.
+                            // The process is killed and replaced the same way as on timeouts.
+                            _ = sender.closed() => break,
                         };
                         match process_message_res {
                             Ok((res, a, r)) => {
@@ -259,8 +346,9 @@ impl<
 mod tests {
     use std::time::Duration;
 
-    use crate::cluster::worker_pool::{MessageProcessor, WorkerPool};
+    use crate::cluster::worker_pool::{MessageProcessor, PriorityQueue, WorkerPool};
     use crate::queryplanner::serialized_plan::SerializedLogicalPlan;
+    use crate::sql::priority::QueryPriority;
     use crate::CubeError;
     use arrow::datatypes::{DataType, Field, Schema};
     use async_trait::async_trait;
@@ -308,6 +396,25 @@ mod tests {
         }
     }
 
+    #[test]
+    fn priority_queue() {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime.block_on(async move {
+            let queue = PriorityQueue::new();
+            queue.push(1, QueryPriority::Low);
+            queue.push(2, QueryPriority::Normal);
+            queue.push(3, QueryPriority::High);
+            queue.push(4, QueryPriority::Low);
+            queue.push(5, QueryPriority::High);
+            let mut popped = Vec::new();
+            for _ in 0..5 {
+                popped.push(queue.pop().await);
+            }
+            assert_eq!(popped, vec![3, 5, 2, 1, 4]);
+        });
+    }
+
     #[test]
     fn test_basic() {
         let runtime = Builder::new_current_thread().enable_all().build().unwrap();
@@ -377,6 +484,34 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_cancel() {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+
+        runtime.block_on(async move {
+            let pool = Arc::new(WorkerPool::<Message, Response, Processor>::new(
+                1,
+                Duration::from_millis(10000),
+            ));
+            let pool_to_move = pool.clone();
+            tokio::spawn(async move { pool_to_move.wait_processing_loops().await });
+            // The process running the dropped message is replaced, the next one does not wait.
+            let dropped = tokio::time::timeout(
+                Duration::from_millis(100),
+                pool.process(Message::Delay(5000)),
+            )
+            .await;
+            assert!(dropped.is_err());
+            let res = tokio::time::timeout(
+                Duration::from_millis(3000),
+                pool.process(Message::Delay(10)),
+            )
+            .await;
+            assert_eq!(res.unwrap().unwrap(), Response::Foo(10));
+            pool.stop_workers().await.unwrap();
+        });
+    }
+
     #[tokio::test]
     async fn serialize_plan() -> Result<(), CubeError> {
         let schema = Schema::new(vec![
@@ -17,10 +17,12 @@ pub enum HttpCommand {
     HttpQuery = 1,
     HttpResultSet = 2,
     HttpError = 3,
+    HttpQueryBatch = 4,
+    HttpResultSetBatch = 5,
 }
 
 pub const ENUM_MIN_HTTP_COMMAND: u8 = 0;
-pub const ENUM_MAX_HTTP_COMMAND: u8 = 3;
+pub const ENUM_MAX_HTTP_COMMAND: u8 = 5;
 
 impl<'a> flatbuffers::Follow<'a> for HttpCommand {
     type Inner = Self;
@@ -54,16 +56,24 @@ impl flatbuffers::Push for HttpCommand {
 }
 
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_HTTP_COMMAND: [HttpCommand; 4] = [
+pub const ENUM_VALUES_HTTP_COMMAND: [HttpCommand; 6] = [
     HttpCommand::NONE,
     HttpCommand::HttpQuery,
     HttpCommand::HttpResultSet,
     HttpCommand::HttpError,
+    HttpCommand::HttpQueryBatch,
+    HttpCommand::HttpResultSetBatch,
 ];
 
 #[allow(non_camel_case_types)]
-pub const ENUM_NAMES_HTTP_COMMAND: [&'static str; 4] =
-    ["NONE", "HttpQuery", "HttpResultSet", "HttpError"];
+pub const ENUM_NAMES_HTTP_COMMAND: [&'static str; 6] = [
+    "NONE",
+    "HttpQuery",
+    "HttpResultSet",
+    "HttpError",
+    "HttpQueryBatch",
+    "HttpResultSetBatch",
+];
 
 pub fn enum_name_http_command(e: HttpCommand) -> &'static str {
     let index = e as u8;
@@ -160,6 +170,27 @@ impl<'a> HttpMessage<'a> {
             None
         }
     }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn command_as_http_query_batch(&self) -> Option<HttpQueryBatch<'a>> {
+        if self.command_type() == HttpCommand::HttpQueryBatch {
+            self.command().map(|u| HttpQueryBatch::init_from_table(u))
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn command_as_http_result_set_batch(&self) -> Option<HttpResultSetBatch<'a>> {
+        if self.command_type() == HttpCommand::HttpResultSetBatch {
+            self.command()
+                .map(|u| HttpResultSetBatch::init_from_table(u))
+        } else {
+            None
+        }
+    }
 }
 
 pub struct HttpMessageArgs {
@@ -484,6 +515,284 @@ impl<'a: 'b, 'b> HttpResultSetBuilder<'a, 'b> {
     }
 }
 
+pub enum HttpQueryBatchOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct HttpQueryBatch<'a> {
+    pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for HttpQueryBatch<'a> {
+    type Inner = HttpQueryBatch<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> HttpQueryBatch<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        HttpQueryBatch { _tab: table }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args HttpQueryBatchArgs<'args>,
+    ) -> flatbuffers::WIPOffset<HttpQueryBatch<'bldr>> {
+        let mut builder = HttpQueryBatchBuilder::new(_fbb);
+        if let Some(x) = args.queries {
+            builder.add_queries(x);
+        }
+        builder.finish()
+    }
+
+    pub const VT_QUERIES: flatbuffers::VOffsetT = 4;
+
+    #[inline]
+    pub fn queries(
+        &self,
+    ) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>> {
+        self._tab.get::<flatbuffers::ForwardsUOffset<
+            flatbuffers::Vector<flatbuffers::ForwardsUOffset<&'a str>>,
+        >>(HttpQueryBatch::VT_QUERIES, None)
+    }
+}
+
+pub struct HttpQueryBatchArgs<'a> {
+    pub queries: Option<
+        flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>,
+    >,
+}
+impl<'a> Default for HttpQueryBatchArgs<'a> {
+    #[inline]
+    fn default() -> Self {
+        HttpQueryBatchArgs { queries: None }
+    }
+}
+pub struct HttpQueryBatchBuilder<'a: 'b, 'b> {
+    fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> HttpQueryBatchBuilder<'a, 'b> {
+    #[inline]
+    pub fn add_queries(
+        &mut self,
+        queries: flatbuffers::WIPOffset<
+            flatbuffers::Vector<'b, flatbuffers::ForwardsUOffset<&'b str>>,
+        >,
+    ) {
+        self.fbb_
+            .push_slot_always::<flatbuffers::WIPOffset<_>>(HttpQueryBatch::VT_QUERIES, queries);
+    }
+    #[inline]
+    pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> HttpQueryBatchBuilder<'a, 'b> {
+        let start = _fbb.start_table();
+        HttpQueryBatchBuilder {
+            fbb_: _fbb,
+            start_: start,
+        }
+    }
+    #[inline]
+    pub fn finish(self) -> flatbuffers::WIPOffset<HttpQueryBatch<'a>> {
+        let o = self.fbb_.end_table(self.start_);
+        flatbuffers::WIPOffset::new(o.value())
+    }
+}
+
+pub enum HttpResultSetBatchOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct HttpResultSetBatch<'a> {
+    pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for HttpResultSetBatch<'a> {
+    type Inner = HttpResultSetBatch<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> HttpResultSetBatch<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        HttpResultSetBatch { _tab: table }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args HttpResultSetBatchArgs<'args>,
+    ) -> flatbuffers::WIPOffset<HttpResultSetBatch<'bldr>> {
+        let mut builder = HttpResultSetBatchBuilder::new(_fbb);
+        if let Some(x) = args.results {
+            builder.add_results(x);
+        }
+        builder.finish()
+    }
+
+    pub const VT_RESULTS: flatbuffers::VOffsetT = 4;
+
+    #[inline]
+    pub fn results(
+        &self,
+    ) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<HttpQueryResult<'a>>>> {
+        self._tab.get::<flatbuffers::ForwardsUOffset<
+            flatbuffers::Vector<flatbuffers::ForwardsUOffset<HttpQueryResult<'a>>>,
+        >>(HttpResultSetBatch::VT_RESULTS, None)
+    }
+}
+
+pub struct HttpResultSetBatchArgs<'a> {
+    pub results: Option<
+        flatbuffers::WIPOffset<
+            flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<HttpQueryResult<'a>>>,
+        >,
+    >,
+}
+impl<'a> Default for HttpResultSetBatchArgs<'a> {
+    #[inline]
+    fn default() -> Self {
+        HttpResultSetBatchArgs { results: None }
+    }
+}
+pub struct HttpResultSetBatchBuilder<'a: 'b, 'b> {
+    fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> HttpResultSetBatchBuilder<'a, 'b> {
+    #[inline]
+    pub fn add_results(
+        &mut self,
+        results: flatbuffers::WIPOffset<
+            flatbuffers::Vector<'b, flatbuffers::ForwardsUOffset<HttpQueryResult<'b>>>,
+        >,
+    ) {
+        self.fbb_
+            .push_slot_always::<flatbuffers::WIPOffset<_>>(HttpResultSetBatch::VT_RESULTS, results);
+    }
+    #[inline]
+    pub fn new(
+        _fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    ) -> HttpResultSetBatchBuilder<'a, 'b> {
+        let start = _fbb.start_table();
+        HttpResultSetBatchBuilder {
+            fbb_: _fbb,
+            start_: start,
+        }
+    }
+    #[inline]
+    pub fn finish(self) -> flatbuffers::WIPOffset<HttpResultSetBatch<'a>> {
+        let o = self.fbb_.end_table(self.start_);
+        flatbuffers::WIPOffset::new(o.value())
+    }
+}
+
+pub enum HttpQueryResultOffset {}
+#[derive(Copy, Clone, Debug, PartialEq)]
+
+pub struct HttpQueryResult<'a> {
+    pub _tab: flatbuffers::Table<'a>,
+}
+
+impl<'a> flatbuffers::Follow<'a> for HttpQueryResult<'a> {
+    type Inner = HttpQueryResult<'a>;
+    #[inline]
+    fn follow(buf: &'a [u8], loc: usize) -> Self::Inner {
+        Self {
+            _tab: flatbuffers::Table { buf: buf, loc: loc },
+        }
+    }
+}
+
+impl<'a> HttpQueryResult<'a> {
+    #[inline]
+    pub fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
+        HttpQueryResult { _tab: table }
+    }
+    #[allow(unused_mut)]
+    pub fn create<'bldr: 'args, 'args: 'mut_bldr, 'mut_bldr>(
+        _fbb: &'mut_bldr mut flatbuffers::FlatBufferBuilder<'bldr>,
+        args: &'args HttpQueryResultArgs<'args>,
+    ) -> flatbuffers::WIPOffset<HttpQueryResult<'bldr>> {
+        let mut builder = HttpQueryResultBuilder::new(_fbb);
+        if let Some(x) = args.error {
+            builder.add_error(x);
+        }
+        if let Some(x) = args.result_set {
+            builder.add_result_set(x);
+        }
+        builder.finish()
+    }
+
+    pub const VT_RESULT_SET: flatbuffers::VOffsetT = 4;
+    pub const VT_ERROR: flatbuffers::VOffsetT = 6;
+
+    #[inline]
+    pub fn result_set(&self) -> Option<HttpResultSet<'a>> {
+        self._tab
+            .get::<flatbuffers::ForwardsUOffset<HttpResultSet<'a>>>(
+                HttpQueryResult::VT_RESULT_SET,
+                None,
+            )
+    }
+    #[inline]
+    pub fn error(&self) -> Option<&'a str> {
+        self._tab
+            .get::<flatbuffers::ForwardsUOffset<&str>>(HttpQueryResult::VT_ERROR, None)
+    }
+}
+
+pub struct HttpQueryResultArgs<'a> {
+    pub result_set: Option<flatbuffers::WIPOffset<HttpResultSet<'a>>>,
+    pub error: Option<flatbuffers::WIPOffset<&'a str>>,
+}
+impl<'a> Default for HttpQueryResultArgs<'a> {
+    #[inline]
+    fn default() -> Self {
+        HttpQueryResultArgs {
+            result_set: None,
+            error: None,
+        }
+    }
+}
+pub struct HttpQueryResultBuilder<'a: 'b, 'b> {
+    fbb_: &'b mut flatbuffers::FlatBufferBuilder<'a>,
+    start_: flatbuffers::WIPOffset<flatbuffers::TableUnfinishedWIPOffset>,
+}
+impl<'a: 'b, 'b> HttpQueryResultBuilder<'a, 'b> {
+    #[inline]
+    pub fn add_result_set(&mut self, result_set: flatbuffers::WIPOffset<HttpResultSet<'b>>) {
+        self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(
+            HttpQueryResult::VT_RESULT_SET,
+            result_set,
+        );
+    }
+    #[inline]
+    pub fn add_error(&mut self, error: flatbuffers::WIPOffset<&'b str>) {
+        self.fbb_
+            .push_slot_always::<flatbuffers::WIPOffset<_>>(HttpQueryResult::VT_ERROR, error);
+    }
+    #[inline]
+    pub fn new(_fbb: &'b mut flatbuffers::FlatBufferBuilder<'a>) -> HttpQueryResultBuilder<'a, 'b> {
+        let start = _fbb.start_table();
+        HttpQueryResultBuilder {
+            fbb_: _fbb,
+            start_: start,
+        }
+    }
+    #[inline]
+    pub fn finish(self) -> flatbuffers::WIPOffset<HttpQueryResult<'a>> {
+        let o = self.fbb_.end_table(self.start_);
+        flatbuffers::WIPOffset::new(o.value())
+    }
+}
+
 pub enum HttpRowOffset {}
 #[derive(Copy, Clone, Debug, PartialEq)]
 
@@ -0,0 +1,350 @@
+//! Settings that can be changed without a restart. `SET GLOBAL` applies a value on the router and
+//! all workers until they restart:
+//!     SET GLOBAL max_rows_per_query = 1000000
+//! `SYSTEM RELOAD CONFIG` reads the file in `CUBESTORE_CONFIG_FILE`, which is also read on
+//! startup, and replaces all values set before. The file has a `<name> = <value>` setting per
+//! line, empty lines and lines starting with `#` are ignored. Settings that are not set either way
+//! keep their values from the environment.
+//!
+//! Only [HOT_RELOADABLE] settings can be changed this way as they are read on each use, other
+//! settings require a restart. `SHOW CONFIG` lists hot-reloadable settings with their current
+//! values and `SHOW CONFIG CHANGES` lists the last changes, which are also logged.
+use crate::config::ConfigObj;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+/// Getters of these settings in [ConfigObj] return values set here, if any.
+pub const HOT_RELOADABLE: [&str; 11] = [
+    "partition_split_threshold",
+    "compaction_chunks_total_size_threshold",
+    "compaction_chunks_count_threshold",
+    "query_timeout",
+    "max_partitions_per_query",
+    "max_rows_per_query",
+    "transport_compression_threshold",
+    "speculative_execution_slowdown",
+    "speculative_execution_min_delay_ms",
+    "worker_batch_cache_max_size",
+    "result_cache_max_entries",
+];
+
+/// Number of changes kept for `SHOW CONFIG CHANGES`.
+const MAX_CHANGES: usize = 100;
+
+pub type ConfigValues = BTreeMap<String, ConfigValue>;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// Environment variables or defaults, i.e. the value is not set dynamically.
+    Environment,
+    File,
+    SetGlobal,
+}
+
+impl ConfigSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConfigSource::Environment => "environment",
+            ConfigSource::File => "file",
+            ConfigSource::SetGlobal => "set global",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConfigValue {
+    pub value: u64,
+    pub source: ConfigSource,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConfigChange {
+    pub time: DateTime<Utc>,
+    pub name: String,
+    pub old_value: u64,
+    pub new_value: u64,
+    /// Statement or node that made the change.
+    pub origin: String,
+}
+
+#[derive(Debug, Default)]
+pub struct DynamicConfig {
+    file: Option<PathBuf>,
+    values: RwLock<ConfigValues>,
+    changes: Mutex<VecDeque<ConfigChange>>,
+}
+
+impl DynamicConfig {
+    /// Panics if the file can't be read, same as on invalid environment variables.
+    pub fn new(file: Option<PathBuf>) -> DynamicConfig {
+        let values = match &file {
+            Some(file) => read_file(file).unwrap_or_else(|e| panic!("{}", e)),
+            None => ConfigValues::new(),
+        };
+        DynamicConfig {
+            file,
+            values: RwLock::new(values),
+            changes: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.values.read().unwrap().get(name).map(|v| v.value)
+    }
+
+    pub fn values(&self) -> ConfigValues {
+        self.values.read().unwrap().clone()
+    }
+
+    /// Values to apply on `SYSTEM RELOAD CONFIG`.
+    pub fn read_file(&self) -> Result<ConfigValues, CubeError> {
+        match &self.file {
+            Some(file) => read_file(file),
+            None => Err(CubeError::user(
+                "CUBESTORE_CONFIG_FILE must be set to reload config".to_string(),
+            )),
+        }
+    }
+
+    /// Values to apply on `SET GLOBAL`.
+    pub fn with_value(&self, name: &str, value: u64) -> Result<ConfigValues, CubeError> {
+        check_name(name)?;
+        let mut values = self.values();
+        values.insert(
+            name.to_string(),
+            ConfigValue {
+                value,
+                source: ConfigSource::SetGlobal,
+            },
+        );
+        Ok(values)
+    }
+
+    /// Replaces all values. Changes of settings of `config`, which must be the config this one
+    /// belongs to, are logged and kept for `SHOW CONFIG CHANGES`.
+    pub fn apply(&self, config: &dyn ConfigObj, values: ConfigValues, origin: &str) {
+        let mut changes = self.changes.lock().unwrap();
+        let old_values = HOT_RELOADABLE
+            .iter()
+            .map(|name| setting_value(config, name))
+            .collect::<Vec<_>>();
+        *self.values.write().unwrap() = values;
+        for (name, old_value) in HOT_RELOADABLE.iter().zip(old_values) {
+            let new_value = setting_value(config, name);
+            if new_value == old_value {
+                continue;
+            }
+            info!(
+                "Setting {} changed from {} to {} by {}",
+                name, old_value, new_value, origin
+            );
+            if changes.len() == MAX_CHANGES {
+                changes.pop_front();
+            }
+            changes.push_back(ConfigChange {
+                time: Utc::now(),
+                name: name.to_string(),
+                old_value,
+                new_value,
+                origin: origin.to_string(),
+            });
+        }
+    }
+
+    /// Oldest first.
+    pub fn changes(&self) -> Vec<ConfigChange> {
+        self.changes.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn source(&self, name: &str) -> ConfigSource {
+        self.values
+            .read()
+            .unwrap()
+            .get(name)
+            .map(|v| v.source)
+            .unwrap_or(ConfigSource::Environment)
+    }
+}
+
+/// Current value of one of [HOT_RELOADABLE] settings.
+pub fn setting_value(config: &dyn ConfigObj, name: &str) -> u64 {
+    match name {
+        "partition_split_threshold" => config.partition_split_threshold(),
+        "compaction_chunks_total_size_threshold" => config.compaction_chunks_total_size_threshold(),
+        "compaction_chunks_count_threshold" => config.compaction_chunks_count_threshold(),
+        "query_timeout" => config.query_timeout(),
+        "max_partitions_per_query" => config.max_partitions_per_query(),
+        "max_rows_per_query" => config.max_rows_per_query(),
+        "transport_compression_threshold" => config.transport_compression_threshold() as u64,
+        "speculative_execution_slowdown" => config.speculative_execution_slowdown(),
+        "speculative_execution_min_delay_ms" => config.speculative_execution_min_delay_ms(),
+        "worker_batch_cache_max_size" => config.worker_batch_cache_max_size() as u64,
+        "result_cache_max_entries" => config.result_cache_max_entries(),
+        _ => panic!("{} is not a hot-reloadable setting", name),
+    }
+}
+
+fn check_name(name: &str) -> Result<(), CubeError> {
+    if HOT_RELOADABLE.contains(&name) {
+        return Ok(());
+    }
+    Err(CubeError::user(format!(
+        "{} is not a setting that can be changed without a restart, expected one of: {}",
+        name,
+        HOT_RELOADABLE.join(", ")
+    )))
+}
+
+fn read_file(file: &Path) -> Result<ConfigValues, CubeError> {
+    let content = fs::read_to_string(file).map_err(|e| {
+        CubeError::user(format!("Can't read config file {}: {}", file.display(), e))
+    })?;
+    parse_file(&content).map_err(|e| {
+        CubeError::user(format!(
+            "Invalid config file {}: {}",
+            file.display(),
+            e.message
+        ))
+    })
+}
+
+fn parse_file(content: &str) -> Result<ConfigValues, CubeError> {
+    let mut values = ConfigValues::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim()),
+            _ => {
+                return Err(CubeError::user(format!(
+                    "line {}: expected '<name> = <value>', found: {}",
+                    i + 1,
+                    line
+                )))
+            }
+        };
+        check_name(&name).map_err(|e| CubeError::user(format!("line {}: {}", i + 1, e)))?;
+        let value = value.parse::<u64>().map_err(|_| {
+            CubeError::user(format!(
+                "line {}: value of {} must be a non-negative integer, found: {}",
+                i + 1,
+                name,
+                value
+            ))
+        })?;
+        values.insert(
+            name,
+            ConfigValue {
+                value,
+                source: ConfigSource::File,
+            },
+        );
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn config_file() {
+        let values = parse_file(
+            "# Guardrails\n\
+             max_rows_per_query = 1000\n\
+             \n\
+             QUERY_TIMEOUT=30\n",
+        )
+        .unwrap();
+        assert_eq!(
+            values.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    "max_rows_per_query".to_string(),
+                    ConfigValue {
+                        value: 1000,
+                        source: ConfigSource::File
+                    }
+                ),
+                (
+                    "query_timeout".to_string(),
+                    ConfigValue {
+                        value: 30,
+                        source: ConfigSource::File
+                    }
+                ),
+            ]
+        );
+        assert!(parse_file("max_rows_per_query").is_err());
+        assert!(parse_file("max_rows_per_query = -1").is_err());
+        assert!(parse_file("select_workers = 1").is_err());
+    }
+
+    #[test]
+    fn cache_sizes() {
+        let config = Config::test("dynamic_config_cache_sizes").config_obj();
+        let dynamic = config.dynamic_config();
+        let values = parse_file(
+            "worker_batch_cache_max_size = 1048576\n\
+             result_cache_max_entries = 0\n",
+        )
+        .unwrap();
+        dynamic.apply(config.as_ref(), values, "SYSTEM RELOAD CONFIG");
+        assert_eq!(config.worker_batch_cache_max_size(), 1048576);
+        assert_eq!(config.result_cache_max_entries(), 0);
+    }
+
+    #[test]
+    fn overrides() {
+        let config = Config::test("dynamic_config_overrides").config_obj();
+        let dynamic = config.dynamic_config();
+        assert_eq!(config.max_rows_per_query(), 0);
+
+        let values = dynamic.with_value("max_rows_per_query", 10).unwrap();
+        dynamic.apply(config.as_ref(), values, "SET GLOBAL");
+        assert_eq!(config.max_rows_per_query(), 10);
+        assert_eq!(
+            dynamic.source("max_rows_per_query"),
+            ConfigSource::SetGlobal
+        );
+        assert_eq!(dynamic.source("query_timeout"), ConfigSource::Environment);
+        assert!(dynamic.with_value("data_dir", 1).is_err());
+
+        // Applying the same values again is not a change.
+        dynamic.apply(config.as_ref(), dynamic.values(), "router");
+        dynamic.apply(config.as_ref(), ConfigValues::new(), "SYSTEM RELOAD CONFIG");
+        assert_eq!(config.max_rows_per_query(), 0);
+        let changes = dynamic
+            .changes()
+            .into_iter()
+            .map(|c| (c.name, c.old_value, c.new_value, c.origin))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    "max_rows_per_query".to_string(),
+                    0,
+                    10,
+                    "SET GLOBAL".to_string()
+                ),
+                (
+                    "max_rows_per_query".to_string(),
+                    10,
+                    0,
+                    "SYSTEM RELOAD CONFIG".to_string()
+                ),
+            ]
+        );
+    }
+}
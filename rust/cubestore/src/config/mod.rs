@@ -1,28 +1,52 @@
+pub mod dynamic;
 pub mod injection;
 pub mod processing_loop;
 
+use crate::auth::{
+    parse_mysql_users, parse_roles, AuthProviderConfig, MySqlUser, ProviderAuthService, Role,
+};
+use crate::cluster::flight::FlightServer;
+use crate::cluster::partition_stats::PartitionAccessStats;
+use crate::cluster::replication::TableReplicator;
 use crate::cluster::transport::{
     ClusterTransport, ClusterTransportImpl, MetaStoreTransport, MetaStoreTransportImpl,
 };
 use crate::cluster::{Cluster, ClusterImpl, ClusterMetaStoreClient};
+use crate::config::dynamic::DynamicConfig;
 use crate::config::injection::{get_service, get_service_typed, DIService, Injector, InjectorRef};
 use crate::config::processing_loop::ProcessingLoop;
+use crate::http::health::HealthChecker;
 use crate::http::HttpServer;
+use crate::import::decoder::RowDecoderRegistry;
 use crate::import::limits::ConcurrencyLimits;
 use crate::import::{ImportService, ImportServiceImpl};
+use crate::metastore::history::MetaStoreHistory;
 use crate::metastore::{MetaStore, MetaStoreRpcClient, RocksMetaStore};
+use crate::mysql::sessions::Sessions;
 use crate::mysql::{MySqlServer, SqlAuthDefaultImpl, SqlAuthService};
-use crate::queryplanner::query_executor::{QueryExecutor, QueryExecutorImpl};
+use crate::queryplanner::casts::CastOverflow;
+use crate::queryplanner::query_executor::{QueryExecutor, QueryExecutorImpl, TransportCompression};
+use crate::queryplanner::time_zones::QueryTimeZone;
 use crate::queryplanner::{QueryPlanner, QueryPlannerImpl};
 use crate::remotefs::gcs::GCSRemoteFs;
 use crate::remotefs::queue::QueueRemoteFs;
 use crate::remotefs::s3::S3RemoteFs;
+use crate::remotefs::storage::StorageRemoteFs;
 use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
 use crate::scheduler::SchedulerImpl;
+use crate::secrets::SecretStore;
+use crate::sql::attach::AttachedTableRefresher;
+use crate::sql::export::ResultExports;
+use crate::sql::pre_aggregations::PreAggregationRefresher;
+use crate::sql::prefetch::ResultPrefetcher;
+use crate::sql::query_log::QueryLog;
+use crate::sql::submitted_queries::SubmittedQueries;
 use crate::sql::{SqlService, SqlServiceImpl};
 use crate::store::compaction::{CompactionService, CompactionServiceImpl};
+use crate::store::leases::DataFileLeases;
 use crate::store::{ChunkDataStore, ChunkStore, WALDataStore, WALStore};
 use crate::telemetry::{start_track_event_loop, stop_track_event_loop};
+use crate::util::scratch::ScratchSpace;
 use crate::CubeError;
 use futures::future::join_all;
 use log::Level;
@@ -80,11 +104,24 @@ impl CubeServices {
     }
 
     async fn spawn_processing_loops(&self) -> Result<Vec<LoopHandle>, CubeError> {
+        self.injector
+            .get_service_typed::<ScratchSpace>()
+            .await
+            .remove_orphans()
+            .await?;
         let mut futures = Vec::new();
         let cluster = self.cluster.clone();
         futures.push(tokio::spawn(async move {
             cluster.wait_processing_loops().await
         }));
+        let partition_stats = self
+            .injector
+            .get_service_typed::<PartitionAccessStats>()
+            .await;
+        futures.push(tokio::spawn(async move {
+            partition_stats.wait_processing_loop().await;
+            Ok(())
+        }));
         let remote_fs = self.remote_fs.clone();
         futures.push(tokio::spawn(async move {
             QueueRemoteFs::wait_processing_loops(remote_fs.clone()).await
@@ -105,6 +142,46 @@ impl CubeServices {
             let scheduler = self.scheduler.clone();
             futures.extend(SchedulerImpl::spawn_processing_loops(scheduler));
 
+            let prefetcher = self.injector.get_service_typed::<ResultPrefetcher>().await;
+            let sql_service = self.sql_service.clone();
+            futures.push(tokio::spawn(async move {
+                prefetcher.wait_processing_loop(sql_service).await;
+                Ok(())
+            }));
+
+            let pre_aggregation_refresher = self
+                .injector
+                .get_service_typed::<PreAggregationRefresher>()
+                .await;
+            let sql_service = self.sql_service.clone();
+            futures.push(tokio::spawn(async move {
+                pre_aggregation_refresher
+                    .wait_processing_loop(sql_service)
+                    .await;
+                Ok(())
+            }));
+
+            let replicator = self.injector.get_service_typed::<TableReplicator>().await;
+            futures.push(tokio::spawn(async move {
+                replicator.wait_processing_loops().await;
+                Ok(())
+            }));
+
+            let metastore_history = self.injector.get_service_typed::<MetaStoreHistory>().await;
+            futures.push(tokio::spawn(async move {
+                metastore_history.wait_processing_loop().await;
+                Ok(())
+            }));
+
+            let refresher = self
+                .injector
+                .get_service_typed::<AttachedTableRefresher>()
+                .await;
+            futures.push(tokio::spawn(async move {
+                refresher.wait_processing_loop().await;
+                Ok(())
+            }));
+
             if self.injector.has_service_typed::<MySqlServer>().await {
                 let mysql_server = self.injector.get_service_typed::<MySqlServer>().await;
                 futures.push(tokio::spawn(
@@ -115,6 +192,12 @@ impl CubeServices {
                 let http_server = self.injector.get_service_typed::<HttpServer>().await;
                 futures.push(tokio::spawn(async move { http_server.run_server().await }));
             }
+            if self.injector.has_service_typed::<FlightServer>().await {
+                let flight_server = self.injector.get_service_typed::<FlightServer>().await;
+                futures.push(tokio::spawn(
+                    async move { flight_server.run_server().await },
+                ));
+            }
         } else {
             let cluster = self.cluster.clone();
             let (started_tx, started_rx) = tokio::sync::oneshot::channel();
@@ -127,7 +210,15 @@ impl CubeServices {
             futures.push(tokio::spawn(async move {
                 cluster.warmup_select_worker().await;
                 Ok(())
-            }))
+            }));
+
+            let config = self.injector.get_service_typed::<dyn ConfigObj>().await;
+            if let Some(address) = config.http_bind_address().clone() {
+                let health = self.injector.get_service_typed::<HealthChecker>().await;
+                futures.push(tokio::spawn(
+                    async move { health.run_server(address).await },
+                ));
+            }
         }
         futures.push(tokio::spawn(async move {
             start_track_event_loop().await;
@@ -158,7 +249,41 @@ impl CubeServices {
                 .stop_processing()
                 .await;
         }
+        if self.injector.has_service_typed::<FlightServer>().await {
+            self.injector
+                .get_service_typed::<FlightServer>()
+                .await
+                .stop_processing();
+        }
+        self.injector
+            .get_service_typed::<HealthChecker>()
+            .await
+            .stop_processing();
         self.scheduler.stop_processing_loops()?;
+        self.injector
+            .get_service_typed::<ResultPrefetcher>()
+            .await
+            .stop_processing_loop();
+        self.injector
+            .get_service_typed::<TableReplicator>()
+            .await
+            .stop_processing_loops();
+        self.injector
+            .get_service_typed::<MetaStoreHistory>()
+            .await
+            .stop_processing_loop();
+        self.injector
+            .get_service_typed::<AttachedTableRefresher>()
+            .await
+            .stop_processing_loop();
+        self.injector
+            .get_service_typed::<PreAggregationRefresher>()
+            .await
+            .stop_processing_loop();
+        self.injector
+            .get_service_typed::<PartitionAccessStats>()
+            .await
+            .stop_processing_loop();
         stop_track_event_loop().await;
         Ok(())
     }
@@ -181,6 +306,81 @@ pub enum FileStoreProvider {
     },
 }
 
+impl FileStoreProvider {
+    /// Location of the storage as in [crate::remotefs::storage], [None] for storage that is only
+    /// on the local disk.
+    pub fn location(&self) -> Option<String> {
+        let bucket_location = |scheme: &str, bucket: &str, sub_path: &Option<String>| match sub_path
+        {
+            Some(p) => format!("{}://{}/{}", scheme, bucket, p.trim_matches('/')),
+            None => format!("{}://{}", scheme, bucket),
+        };
+        match self {
+            FileStoreProvider::Local | FileStoreProvider::Filesystem { remote_dir: None } => None,
+            FileStoreProvider::Filesystem {
+                remote_dir: Some(dir),
+            } => Some(format!(
+                "file://{}",
+                env::current_dir().ok()?.join(dir).to_string_lossy()
+            )),
+            FileStoreProvider::S3 {
+                bucket_name,
+                sub_path,
+                ..
+            } => Some(bucket_location("s3", bucket_name, sub_path)),
+            FileStoreProvider::GCS {
+                bucket_name,
+                sub_path,
+            } => Some(bucket_location("gcs", bucket_name, sub_path)),
+        }
+    }
+}
+
+/// Hours of day in UTC when background jobs are allowed to run, e.g. `1-5`. Windows that cross
+/// midnight, e.g. `22-4`, are supported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaintenanceWindow {
+    pub start_hour: u32,
+    pub end_hour: u32,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, hour: u32) -> bool {
+        if self.start_hour <= self.end_hour {
+            self.start_hour <= hour && hour < self.end_hour
+        } else {
+            self.start_hour <= hour || hour < self.end_hour
+        }
+    }
+}
+
+impl FromStr for MaintenanceWindow {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_hour = |h: &str| -> Result<u32, CubeError> {
+            match h.trim().parse::<u32>() {
+                Ok(h) if h <= 24 => Ok(h),
+                _ => Err(CubeError::user(format!(
+                    "Invalid hour '{}' in maintenance window '{}'",
+                    h, s
+                ))),
+            }
+        };
+        let mut parts = s.splitn(2, '-');
+        match (parts.next(), parts.next()) {
+            (Some(start), Some(end)) => Ok(MaintenanceWindow {
+                start_hour: parse_hour(start)?,
+                end_hour: parse_hour(end)?,
+            }),
+            _ => Err(CubeError::user(format!(
+                "Maintenance window should be in '<start hour>-<end hour>' format but found '{}'",
+                s
+            ))),
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Config {
     config_obj: Arc<ConfigObjImpl>,
@@ -189,10 +389,13 @@ pub struct Config {
 
 #[automock]
 pub trait ConfigObj: DIService {
+    /// Hot-reloadable, see [dynamic].
     fn partition_split_threshold(&self) -> u64;
 
+    /// Hot-reloadable, see [dynamic].
     fn compaction_chunks_total_size_threshold(&self) -> u64;
 
+    /// Hot-reloadable, see [dynamic].
     fn compaction_chunks_count_threshold(&self) -> u64;
 
     fn wal_split_threshold(&self) -> u64;
@@ -201,16 +404,29 @@ pub trait ConfigObj: DIService {
 
     fn job_runners_count(&self) -> usize;
 
+    fn background_job_runners_count(&self) -> usize;
+
+    fn maintenance_window(&self) -> Option<MaintenanceWindow>;
+
     fn bind_address(&self) -> &Option<String>;
 
     fn http_bind_address(&self) -> &Option<String>;
 
+    /// Address of the Arrow Flight endpoint of the router, see [crate::cluster::flight]. Not set
+    /// by default.
+    fn flight_bind_address(&self) -> &Option<String>;
+
+    /// Hot-reloadable, see [dynamic].
     fn query_timeout(&self) -> u64;
 
     fn not_used_timeout(&self) -> u64;
 
     fn select_workers(&self) -> &Vec<String>;
 
+    /// Workers that only serve queries. Compaction, repartitioning and import jobs are never
+    /// assigned to them.
+    fn read_replica_workers(&self) -> &Vec<String>;
+
     fn worker_bind_address(&self) -> &Option<String>;
 
     fn metastore_bind_address(&self) -> &Option<String>;
@@ -221,6 +437,12 @@ pub trait ConfigObj: DIService {
 
     fn upload_concurrency(&self) -> u64;
 
+    /// Number of metastore reads run in parallel when a query plans scans of multiple tables.
+    fn metastore_read_concurrency(&self) -> u64;
+
+    /// Number of indexes whose active partitions and chunks are cached for query planning.
+    fn metastore_select_cache_max_entries(&self) -> u64;
+
     fn data_dir(&self) -> &PathBuf;
 
     fn connection_timeout(&self) -> u64;
@@ -235,7 +457,204 @@ pub trait ConfigObj: DIService {
 
     fn enable_startup_warmup(&self) -> bool;
 
+    /// Startup warmup skips partitions that were not read for this number of seconds, see
+    /// [crate::cluster::partition_stats]. Zero warms up all partitions.
+    fn startup_warmup_idle_secs(&self) -> u64;
+
     fn malloc_trim_every_secs(&self) -> u64;
+
+    /// Interval of memory usage checks that shed caches when the resident set size gets close to
+    /// the memory limit, see [crate::util::memory_pressure]. Zero disables the checks.
+    fn memory_pressure_check_every_secs(&self) -> u64;
+
+    /// Caches are shed when the resident set size exceeds this percentage of the memory limit.
+    fn memory_pressure_threshold_percent(&self) -> u64;
+
+    /// Entries of `system.metastore_log` older than this are dropped, see
+    /// [crate::metastore::history].
+    fn metastore_log_retention_secs(&self) -> u64;
+
+    /// Maximum number of entries kept in `system.metastore_log`, the oldest are dropped first.
+    fn metastore_log_max_entries(&self) -> usize;
+
+    /// Size budget in bytes of [crate::queryplanner::batch_cache::BatchCache] on workers. Zero
+    /// disables the cache. Hot-reloadable, see [dynamic].
+    fn worker_batch_cache_max_size(&self) -> usize;
+
+    /// Number of select results kept by [crate::sql::cache::SqlResultCache] on the router. Zero
+    /// disables the cache. Hot-reloadable, see [dynamic].
+    fn result_cache_max_entries(&self) -> u64;
+
+    fn meta_store_log_upload_interval(&self) -> u64;
+
+    fn meta_store_snapshot_interval(&self) -> u64;
+
+    /// Queries scanning more partitions are rejected unless they have the override hint, see
+    /// [crate::sql::scan_limits]. Zero means no limit. Hot-reloadable, see [dynamic].
+    fn max_partitions_per_query(&self) -> u64;
+
+    /// Same as [ConfigObj::max_partitions_per_query], but for the number of rows. Hot-reloadable.
+    fn max_rows_per_query(&self) -> u64;
+
+    /// Size budget in bytes of [crate::util::scratch::ScratchSpace] shared by all queries on the
+    /// node. Zero means no limit.
+    fn scratch_max_size(&self) -> u64;
+
+    /// Number of cores the router merges results of workers on, see
+    /// [crate::queryplanner::parallel_merge]. One disables parallel merges.
+    fn router_merge_partitions(&self) -> usize;
+
+    /// Bytes of worker results a single parallel merge keeps in memory before spilling to
+    /// scratch space.
+    fn router_merge_memory_limit(&self) -> usize;
+
+    /// Bytes of results a single operator of a worker plan may hold, see
+    /// [crate::queryplanner::operator_limits]. Zero means no limit.
+    fn operator_memory_limit(&self) -> u64;
+
+    /// Milliseconds of CPU time a single operator of a worker plan may use. Zero means no limit.
+    fn operator_cpu_limit_ms(&self) -> u64;
+
+    /// Unsupported features listed in the message of the error on queries that can't be
+    /// distributed, see [crate::queryplanner::unsupported_features].
+    fn max_reported_unsupported_features(&self) -> usize;
+
+    /// Seconds results of finished exports are kept for, see [crate::sql::export].
+    fn export_ttl_secs(&self) -> u64;
+
+    /// Seconds results of finished submitted queries are kept for, see
+    /// [crate::sql::submitted_queries].
+    fn submitted_query_ttl_secs(&self) -> u64;
+
+    /// Replaces [ConfigObj::query_timeout] for submitted queries.
+    fn submitted_query_timeout(&self) -> u64;
+
+    /// Seconds before a scheduled refresh its prefetch is run, see [crate::sql::prefetch].
+    fn result_prefetch_lead_secs(&self) -> u64;
+
+    /// Converts unquoted identifiers in queries to lower case, see
+    /// [crate::sql::parser::CubeStoreParser::new_with_identifier_folding]. Tables and columns
+    /// created before with upper case letters in their names have to be quoted.
+    fn case_insensitive_identifiers(&self) -> bool;
+
+    /// ORDER BY keys without `NULLS FIRST` or `NULLS LAST` sort nulls as larger than any other
+    /// value, as PostgreSQL does. Otherwise nulls come first in both directions.
+    fn nulls_largest(&self) -> bool;
+
+    /// What casts do with values out of range of the type, see [crate::queryplanner::casts].
+    /// `TRY_CAST` always produces NULL.
+    fn cast_overflow(&self) -> CastOverflow;
+
+    /// What `SUM` of integers and decimals produces when the result is out of range of the
+    /// column type. Sums are computed in 128 bits, so only the final value is checked.
+    fn sum_overflow(&self) -> CastOverflow;
+
+    /// Compute `SUM` and `AVG` of floats exactly, so results do not depend on the order partial
+    /// sums are merged in. Slower, but gives reproducible results. Enabled for a single query by
+    /// [crate::sql::EXACT_FLOAT_SUMS_HINT].
+    fn exact_float_sums(&self) -> bool;
+
+    /// Time zone of connections that don't set `time_zone`, an IANA name or an offset. See
+    /// [crate::queryplanner::time_zones].
+    fn time_zone(&self) -> QueryTimeZone;
+
+    /// Codec this node asks workers to compress select results with, see
+    /// [crate::queryplanner::query_executor::SerializedRecordBatchStream::compress].
+    fn transport_compression(&self) -> TransportCompression;
+
+    /// Results of selects smaller than this number of bytes are sent uncompressed. Hot-reloadable,
+    /// see [dynamic].
+    fn transport_compression_threshold(&self) -> usize;
+
+    /// Rows in record batches produced by scans and aggregations, queries can override it with
+    /// the [crate::sql::BATCH_SIZE_HINT] hint.
+    fn query_batch_size(&self) -> usize;
+
+    /// Sends rows of selects without a final sort to MySQL clients as soon as first record batches
+    /// are ready, see [crate::sql::SqlService::exec_query_stream]. Such results are not cached.
+    fn early_result_flush(&self) -> bool;
+
+    /// Selects that run this many times longer than the median of finished selects of the same
+    /// query are duplicated on another worker, see [crate::cluster::speculative]. Zero disables
+    /// speculative execution. Hot-reloadable, see [dynamic].
+    fn speculative_execution_slowdown(&self) -> u64;
+
+    /// Selects are never duplicated before they run for this number of milliseconds.
+    /// Hot-reloadable, see [dynamic].
+    fn speculative_execution_min_delay_ms(&self) -> u64;
+
+    /// See [FileStoreProvider::location].
+    fn storage_location(&self) -> Option<String>;
+
+    /// Tables replicated to another cluster as `schema.table`, see [crate::cluster::replication].
+    fn replication_tables(&self) -> &Vec<String>;
+
+    /// Metastore address of the router of the cluster tables are replicated to.
+    fn replication_target(&self) -> &Option<String>;
+
+    /// Seconds between attempts to ship queued chunks to the replication target.
+    fn replication_interval_secs(&self) -> u64;
+
+    /// Seconds between checks for new commits of attached Delta Lake tables, see
+    /// [crate::sql::attach::AttachedTableRefresher]. Zero disables refreshes.
+    fn attached_table_refresh_secs(&self) -> u64;
+
+    /// Seconds between checks of refresh keys of pre-aggregations, see
+    /// [crate::sql::pre_aggregations]. Zero disables builds.
+    fn pre_aggregation_refresh_secs(&self) -> u64;
+
+    /// Seconds between writes of partition access statistics to the metastore, see
+    /// [crate::cluster::partition_stats]. Zero disables the statistics.
+    fn partition_stats_flush_secs(&self) -> u64;
+
+    /// Strings of `ENCODING OFFLOAD` columns longer than this number of bytes are stored apart
+    /// from the column, see [crate::metastore::ColumnEncoding::Offload].
+    fn string_offload_threshold(&self) -> usize;
+
+    /// Tables with at most this many rows are replicated to all select workers, see
+    /// [crate::cluster::is_replicated_table]. Zero disables replication.
+    fn replicated_table_max_rows(&self) -> u64;
+
+    /// Queries fail if a table of a linked server has more rows, unless the server sets its own
+    /// limit. See [crate::queryplanner::linked_servers].
+    fn linked_server_max_rows(&self) -> u64;
+
+    /// Checks of health probes fail after this number of milliseconds, see [crate::http::health].
+    fn health_check_timeout_ms(&self) -> u64;
+
+    /// File queries of MySQL and HTTP clients are appended to, see [crate::sql::query_log]. Not
+    /// set by default.
+    fn query_log_path(&self) -> &Option<PathBuf>;
+
+    /// MySQL connections that sent no queries for this number of seconds are closed, see
+    /// [crate::mysql::sessions]. Zero keeps idle connections open.
+    fn idle_session_timeout_secs(&self) -> u64;
+
+    /// External identity providers that authenticate HTTP and WebSocket clients, see
+    /// [crate::auth]. Once set, MySQL clients can only connect as [ConfigObj::auth_mysql_users].
+    fn auth_providers(&self) -> &Vec<AuthProviderConfig>;
+
+    /// Roles of groups of users authenticated by [ConfigObj::auth_providers].
+    fn auth_roles(&self) -> &Vec<(String, Role)>;
+
+    /// Users that connect over MySQL while [ConfigObj::auth_providers] are set.
+    fn auth_mysql_users(&self) -> &Vec<MySqlUser>;
+
+    /// Audience JWTs must be issued for, see [crate::auth::jwt].
+    fn auth_jwt_audience(&self) -> &Option<String>;
+
+    /// Base64 encoded 256-bit key that secrets are encrypted with, see [crate::secrets]. Must be
+    /// the same on all nodes.
+    fn secrets_key(&self) -> &Option<String>;
+
+    /// Address of the Vault server secrets can be read from, e.g. `https://vault:8200`.
+    fn vault_address(&self) -> &Option<String>;
+
+    fn vault_token(&self) -> &Option<String>;
+
+    /// Values of hot-reloadable settings set without a restart, read from `CUBESTORE_CONFIG_FILE`
+    /// on startup.
+    fn dynamic_config(&self) -> &Arc<DynamicConfig>;
 }
 
 #[derive(Debug, Clone)]
@@ -248,24 +667,83 @@ pub struct ConfigObjImpl {
     pub store_provider: FileStoreProvider,
     pub select_worker_pool_size: usize,
     pub job_runners_count: usize,
+    pub background_job_runners_count: usize,
+    pub maintenance_window: Option<MaintenanceWindow>,
     pub bind_address: Option<String>,
     pub http_bind_address: Option<String>,
+    pub flight_bind_address: Option<String>,
     pub query_timeout: u64,
     /// Must be set to 2*query_timeout in prod, only for overrides in tests.
     pub not_used_timeout: u64,
     pub select_workers: Vec<String>,
+    pub read_replica_workers: Vec<String>,
     pub worker_bind_address: Option<String>,
     pub metastore_bind_address: Option<String>,
     pub metastore_remote_address: Option<String>,
     pub upload_concurrency: u64,
     pub download_concurrency: u64,
+    pub metastore_read_concurrency: u64,
+    pub metastore_select_cache_max_entries: u64,
     pub connection_timeout: u64,
     pub server_name: String,
     pub max_ingestion_data_frames: usize,
     pub upload_to_remote: bool,
     pub enable_topk: bool,
     pub enable_startup_warmup: bool,
+    pub startup_warmup_idle_secs: u64,
     pub malloc_trim_every_secs: u64,
+    pub memory_pressure_check_every_secs: u64,
+    pub memory_pressure_threshold_percent: u64,
+    pub metastore_log_retention_secs: u64,
+    pub metastore_log_max_entries: usize,
+    pub worker_batch_cache_max_size: usize,
+    pub result_cache_max_entries: u64,
+    pub meta_store_log_upload_interval: u64,
+    pub meta_store_snapshot_interval: u64,
+    pub max_partitions_per_query: u64,
+    pub max_rows_per_query: u64,
+    pub scratch_max_size: u64,
+    pub router_merge_partitions: usize,
+    pub router_merge_memory_limit: usize,
+    pub operator_memory_limit: u64,
+    pub operator_cpu_limit_ms: u64,
+    pub max_reported_unsupported_features: usize,
+    pub export_ttl_secs: u64,
+    pub submitted_query_ttl_secs: u64,
+    pub submitted_query_timeout: u64,
+    pub result_prefetch_lead_secs: u64,
+    pub case_insensitive_identifiers: bool,
+    pub nulls_largest: bool,
+    pub cast_overflow: CastOverflow,
+    pub sum_overflow: CastOverflow,
+    pub exact_float_sums: bool,
+    pub time_zone: QueryTimeZone,
+    pub transport_compression: TransportCompression,
+    pub transport_compression_threshold: usize,
+    pub query_batch_size: usize,
+    pub early_result_flush: bool,
+    pub speculative_execution_slowdown: u64,
+    pub speculative_execution_min_delay_ms: u64,
+    pub replication_tables: Vec<String>,
+    pub replication_target: Option<String>,
+    pub replication_interval_secs: u64,
+    pub attached_table_refresh_secs: u64,
+    pub pre_aggregation_refresh_secs: u64,
+    pub partition_stats_flush_secs: u64,
+    pub string_offload_threshold: usize,
+    pub replicated_table_max_rows: u64,
+    pub linked_server_max_rows: u64,
+    pub health_check_timeout_ms: u64,
+    pub query_log_path: Option<PathBuf>,
+    pub idle_session_timeout_secs: u64,
+    pub auth_providers: Vec<AuthProviderConfig>,
+    pub auth_roles: Vec<(String, Role)>,
+    pub auth_mysql_users: Vec<MySqlUser>,
+    pub auth_jwt_audience: Option<String>,
+    pub secrets_key: Option<String>,
+    pub vault_address: Option<String>,
+    pub vault_token: Option<String>,
+    pub dynamic: Arc<DynamicConfig>,
 }
 
 crate::di_service!(ConfigObjImpl, [ConfigObj]);
@@ -273,15 +751,21 @@ crate::di_service!(MockConfigObj, [ConfigObj]);
 
 impl ConfigObj for ConfigObjImpl {
     fn partition_split_threshold(&self) -> u64 {
-        self.partition_split_threshold
+        self.dynamic
+            .get("partition_split_threshold")
+            .unwrap_or(self.partition_split_threshold)
     }
 
     fn compaction_chunks_total_size_threshold(&self) -> u64 {
-        self.compaction_chunks_total_size_threshold
+        self.dynamic
+            .get("compaction_chunks_total_size_threshold")
+            .unwrap_or(self.compaction_chunks_total_size_threshold)
     }
 
     fn compaction_chunks_count_threshold(&self) -> u64 {
-        self.compaction_chunks_count_threshold
+        self.dynamic
+            .get("compaction_chunks_count_threshold")
+            .unwrap_or(self.compaction_chunks_count_threshold)
     }
 
     fn wal_split_threshold(&self) -> u64 {
@@ -296,6 +780,14 @@ impl ConfigObj for ConfigObjImpl {
         self.job_runners_count
     }
 
+    fn background_job_runners_count(&self) -> usize {
+        self.background_job_runners_count
+    }
+
+    fn maintenance_window(&self) -> Option<MaintenanceWindow> {
+        self.maintenance_window
+    }
+
     fn bind_address(&self) -> &Option<String> {
         &self.bind_address
     }
@@ -304,8 +796,14 @@ impl ConfigObj for ConfigObjImpl {
         &self.http_bind_address
     }
 
+    fn flight_bind_address(&self) -> &Option<String> {
+        &self.flight_bind_address
+    }
+
     fn query_timeout(&self) -> u64 {
-        self.query_timeout
+        self.dynamic
+            .get("query_timeout")
+            .unwrap_or(self.query_timeout)
     }
 
     fn not_used_timeout(&self) -> u64 {
@@ -316,6 +814,10 @@ impl ConfigObj for ConfigObjImpl {
         &self.select_workers
     }
 
+    fn read_replica_workers(&self) -> &Vec<String> {
+        &self.read_replica_workers
+    }
+
     fn worker_bind_address(&self) -> &Option<String> {
         &self.worker_bind_address
     }
@@ -336,6 +838,14 @@ impl ConfigObj for ConfigObjImpl {
         self.upload_concurrency
     }
 
+    fn metastore_read_concurrency(&self) -> u64 {
+        self.metastore_read_concurrency
+    }
+
+    fn metastore_select_cache_max_entries(&self) -> u64 {
+        self.metastore_select_cache_max_entries
+    }
+
     fn data_dir(&self) -> &PathBuf {
         &self.data_dir
     }
@@ -363,9 +873,242 @@ impl ConfigObj for ConfigObjImpl {
     fn enable_startup_warmup(&self) -> bool {
         self.enable_startup_warmup
     }
+
+    fn startup_warmup_idle_secs(&self) -> u64 {
+        self.startup_warmup_idle_secs
+    }
+
     fn malloc_trim_every_secs(&self) -> u64 {
         self.malloc_trim_every_secs
     }
+
+    fn memory_pressure_check_every_secs(&self) -> u64 {
+        self.memory_pressure_check_every_secs
+    }
+
+    fn memory_pressure_threshold_percent(&self) -> u64 {
+        self.memory_pressure_threshold_percent
+    }
+
+    fn metastore_log_retention_secs(&self) -> u64 {
+        self.metastore_log_retention_secs
+    }
+
+    fn metastore_log_max_entries(&self) -> usize {
+        self.metastore_log_max_entries
+    }
+
+    fn worker_batch_cache_max_size(&self) -> usize {
+        self.dynamic
+            .get("worker_batch_cache_max_size")
+            .map(|v| v as usize)
+            .unwrap_or(self.worker_batch_cache_max_size)
+    }
+
+    fn result_cache_max_entries(&self) -> u64 {
+        self.dynamic
+            .get("result_cache_max_entries")
+            .unwrap_or(self.result_cache_max_entries)
+    }
+
+    fn meta_store_log_upload_interval(&self) -> u64 {
+        self.meta_store_log_upload_interval
+    }
+
+    fn meta_store_snapshot_interval(&self) -> u64 {
+        self.meta_store_snapshot_interval
+    }
+
+    fn max_partitions_per_query(&self) -> u64 {
+        self.dynamic
+            .get("max_partitions_per_query")
+            .unwrap_or(self.max_partitions_per_query)
+    }
+
+    fn max_rows_per_query(&self) -> u64 {
+        self.dynamic
+            .get("max_rows_per_query")
+            .unwrap_or(self.max_rows_per_query)
+    }
+
+    fn scratch_max_size(&self) -> u64 {
+        self.scratch_max_size
+    }
+
+    fn router_merge_partitions(&self) -> usize {
+        self.router_merge_partitions
+    }
+
+    fn router_merge_memory_limit(&self) -> usize {
+        self.router_merge_memory_limit
+    }
+
+    fn operator_memory_limit(&self) -> u64 {
+        self.operator_memory_limit
+    }
+
+    fn operator_cpu_limit_ms(&self) -> u64 {
+        self.operator_cpu_limit_ms
+    }
+
+    fn max_reported_unsupported_features(&self) -> usize {
+        self.max_reported_unsupported_features
+    }
+
+    fn export_ttl_secs(&self) -> u64 {
+        self.export_ttl_secs
+    }
+
+    fn submitted_query_ttl_secs(&self) -> u64 {
+        self.submitted_query_ttl_secs
+    }
+
+    fn submitted_query_timeout(&self) -> u64 {
+        self.submitted_query_timeout
+    }
+
+    fn result_prefetch_lead_secs(&self) -> u64 {
+        self.result_prefetch_lead_secs
+    }
+
+    fn case_insensitive_identifiers(&self) -> bool {
+        self.case_insensitive_identifiers
+    }
+
+    fn nulls_largest(&self) -> bool {
+        self.nulls_largest
+    }
+
+    fn cast_overflow(&self) -> CastOverflow {
+        self.cast_overflow
+    }
+
+    fn sum_overflow(&self) -> CastOverflow {
+        self.sum_overflow
+    }
+
+    fn exact_float_sums(&self) -> bool {
+        self.exact_float_sums
+    }
+
+    fn time_zone(&self) -> QueryTimeZone {
+        self.time_zone
+    }
+
+    fn transport_compression(&self) -> TransportCompression {
+        self.transport_compression
+    }
+
+    fn transport_compression_threshold(&self) -> usize {
+        self.dynamic
+            .get("transport_compression_threshold")
+            .map(|v| v as usize)
+            .unwrap_or(self.transport_compression_threshold)
+    }
+
+    fn query_batch_size(&self) -> usize {
+        self.query_batch_size
+    }
+
+    fn early_result_flush(&self) -> bool {
+        self.early_result_flush
+    }
+
+    fn speculative_execution_slowdown(&self) -> u64 {
+        self.dynamic
+            .get("speculative_execution_slowdown")
+            .unwrap_or(self.speculative_execution_slowdown)
+    }
+
+    fn speculative_execution_min_delay_ms(&self) -> u64 {
+        self.dynamic
+            .get("speculative_execution_min_delay_ms")
+            .unwrap_or(self.speculative_execution_min_delay_ms)
+    }
+
+    fn storage_location(&self) -> Option<String> {
+        self.store_provider.location()
+    }
+
+    fn replication_tables(&self) -> &Vec<String> {
+        &self.replication_tables
+    }
+
+    fn replication_target(&self) -> &Option<String> {
+        &self.replication_target
+    }
+
+    fn replication_interval_secs(&self) -> u64 {
+        self.replication_interval_secs
+    }
+
+    fn attached_table_refresh_secs(&self) -> u64 {
+        self.attached_table_refresh_secs
+    }
+
+    fn pre_aggregation_refresh_secs(&self) -> u64 {
+        self.pre_aggregation_refresh_secs
+    }
+
+    fn partition_stats_flush_secs(&self) -> u64 {
+        self.partition_stats_flush_secs
+    }
+
+    fn string_offload_threshold(&self) -> usize {
+        self.string_offload_threshold
+    }
+
+    fn replicated_table_max_rows(&self) -> u64 {
+        self.replicated_table_max_rows
+    }
+
+    fn linked_server_max_rows(&self) -> u64 {
+        self.linked_server_max_rows
+    }
+
+    fn health_check_timeout_ms(&self) -> u64 {
+        self.health_check_timeout_ms
+    }
+
+    fn query_log_path(&self) -> &Option<PathBuf> {
+        &self.query_log_path
+    }
+
+    fn idle_session_timeout_secs(&self) -> u64 {
+        self.idle_session_timeout_secs
+    }
+
+    fn auth_providers(&self) -> &Vec<AuthProviderConfig> {
+        &self.auth_providers
+    }
+
+    fn auth_roles(&self) -> &Vec<(String, Role)> {
+        &self.auth_roles
+    }
+
+    fn auth_mysql_users(&self) -> &Vec<MySqlUser> {
+        &self.auth_mysql_users
+    }
+
+    fn auth_jwt_audience(&self) -> &Option<String> {
+        &self.auth_jwt_audience
+    }
+
+    fn secrets_key(&self) -> &Option<String> {
+        &self.secrets_key
+    }
+
+    fn vault_address(&self) -> &Option<String> {
+        &self.vault_address
+    }
+
+    fn vault_token(&self) -> &Option<String> {
+        &self.vault_token
+    }
+
+    fn dynamic_config(&self) -> &Arc<DynamicConfig> {
+        &self.dynamic
+    }
 }
 
 lazy_static! {
@@ -455,12 +1198,19 @@ impl Config {
                         .map(|v| v.parse::<u16>().unwrap())
                         .unwrap_or(3030u16)),
                 )),
+                flight_bind_address: env::var("CUBESTORE_FLIGHT_PORT")
+                    .ok()
+                    .map(|v| format!("0.0.0.0:{}", v)),
                 query_timeout,
                 not_used_timeout: 2 * query_timeout,
                 select_workers: env::var("CUBESTORE_WORKERS")
                     .ok()
                     .map(|v| v.split(",").map(|s| s.to_string()).collect())
                     .unwrap_or(Vec::new()),
+                read_replica_workers: env::var("CUBESTORE_READ_REPLICAS")
+                    .ok()
+                    .map(|v| v.split(",").map(|s| s.to_string()).collect())
+                    .unwrap_or(Vec::new()),
                 worker_bind_address: env::var("CUBESTORE_WORKER_PORT")
                     .ok()
                     .map(|v| format!("0.0.0.0:{}", v)),
@@ -470,6 +1220,11 @@ impl Config {
                 metastore_remote_address: env::var("CUBESTORE_META_ADDR").ok(),
                 upload_concurrency: 4,
                 download_concurrency: 8,
+                metastore_read_concurrency: env_parse("CUBESTORE_METASTORE_READ_CONCURRENCY", 4),
+                metastore_select_cache_max_entries: env_parse(
+                    "CUBESTORE_METASTORE_SELECT_CACHE_MAX_ENTRIES",
+                    1000,
+                ),
                 max_ingestion_data_frames: env::var("CUBESTORE_MAX_DATA_FRAMES")
                     .ok()
                     .map(|v| v.parse::<usize>().unwrap())
@@ -482,6 +1237,16 @@ impl Config {
                     .ok()
                     .map(|v| v.parse::<usize>().unwrap())
                     .unwrap_or(4),
+                background_job_runners_count: env_parse("CUBESTORE_BACKGROUND_JOB_RUNNERS", 1),
+                maintenance_window: env::var("CUBESTORE_MAINTENANCE_WINDOW").ok().map(|v| {
+                    match v.parse::<MaintenanceWindow>() {
+                        Ok(w) => w,
+                        Err(e) => panic!(
+                            "could not parse value for 'CUBESTORE_MAINTENANCE_WINDOW': {}",
+                            e
+                        ),
+                    }
+                }),
                 connection_timeout: 60,
                 server_name: env::var("CUBESTORE_SERVER_NAME")
                     .ok()
@@ -489,7 +1254,148 @@ impl Config {
                 upload_to_remote: !env::var("CUBESTORE_NO_UPLOAD").ok().is_some(),
                 enable_topk: env_bool("CUBESTORE_ENABLE_TOPK", true),
                 enable_startup_warmup: env_bool("CUBESTORE_STARTUP_WARMUP", true),
+                startup_warmup_idle_secs: env_parse("CUBESTORE_STARTUP_WARMUP_IDLE_SECS", 0),
                 malloc_trim_every_secs: env_parse::<u64>("CUBESTORE_MALLOC_TRIM_EVERY_SECS", 30),
+                memory_pressure_check_every_secs: env_parse(
+                    "CUBESTORE_MEMORY_PRESSURE_CHECK_EVERY_SECS",
+                    5,
+                ),
+                memory_pressure_threshold_percent: env_parse(
+                    "CUBESTORE_MEMORY_PRESSURE_THRESHOLD_PERCENT",
+                    85,
+                ),
+                metastore_log_retention_secs: env_parse(
+                    "CUBESTORE_METASTORE_LOG_RETENTION_SECS",
+                    24 * 60 * 60,
+                ),
+                metastore_log_max_entries: env_parse("CUBESTORE_METASTORE_LOG_MAX_ENTRIES", 100000),
+                worker_batch_cache_max_size: env_parse::<usize>(
+                    "CUBESTORE_WORKER_BATCH_CACHE_MAX_SIZE_MB",
+                    0,
+                ) * 1024
+                    * 1024,
+                result_cache_max_entries: env_parse("CUBESTORE_RESULT_CACHE_MAX_ENTRIES", 10000),
+                meta_store_log_upload_interval: env_parse(
+                    "CUBESTORE_META_STORE_LOG_UPLOAD_INTERVAL",
+                    60,
+                ),
+                meta_store_snapshot_interval: env_parse(
+                    "CUBESTORE_META_STORE_SNAPSHOT_INTERVAL",
+                    300,
+                ),
+                max_partitions_per_query: env_parse("CUBESTORE_MAX_PARTITIONS_PER_QUERY", 0),
+                max_rows_per_query: env_parse("CUBESTORE_MAX_ROWS_PER_QUERY", 0),
+                scratch_max_size: env_parse::<u64>("CUBESTORE_SCRATCH_MAX_SIZE_MB", 0)
+                    * 1024
+                    * 1024,
+                router_merge_partitions: env_parse("CUBESTORE_ROUTER_MERGE_PARTITIONS", 4),
+                router_merge_memory_limit: env_parse::<usize>(
+                    "CUBESTORE_ROUTER_MERGE_MEMORY_LIMIT_MB",
+                    512,
+                ) * 1024
+                    * 1024,
+                operator_memory_limit: env_parse::<u64>("CUBESTORE_OPERATOR_MEMORY_LIMIT_MB", 0)
+                    * 1024
+                    * 1024,
+                operator_cpu_limit_ms: env_parse("CUBESTORE_OPERATOR_CPU_LIMIT_MS", 0),
+                max_reported_unsupported_features: env_parse(
+                    "CUBESTORE_MAX_REPORTED_UNSUPPORTED_FEATURES",
+                    10,
+                ),
+                export_ttl_secs: env_parse("CUBESTORE_EXPORT_TTL_SECS", 24 * 60 * 60),
+                submitted_query_ttl_secs: env_parse("CUBESTORE_SUBMITTED_QUERY_TTL_SECS", 60 * 60),
+                submitted_query_timeout: env_parse(
+                    "CUBESTORE_SUBMITTED_QUERY_TIMEOUT",
+                    6 * 60 * 60,
+                ),
+                result_prefetch_lead_secs: env_parse("CUBESTORE_RESULT_PREFETCH_LEAD_SECS", 60),
+                case_insensitive_identifiers: env_bool(
+                    "CUBESTORE_CASE_INSENSITIVE_IDENTIFIERS",
+                    false,
+                ),
+                nulls_largest: env_bool("CUBESTORE_NULLS_LARGEST", false),
+                cast_overflow: env_parse("CUBESTORE_CAST_OVERFLOW", CastOverflow::Error),
+                sum_overflow: env_parse("CUBESTORE_SUM_OVERFLOW", CastOverflow::Error),
+                exact_float_sums: env_bool("CUBESTORE_EXACT_FLOAT_SUMS", false),
+                time_zone: env_parse("CUBESTORE_TIMEZONE", QueryTimeZone::default()),
+                transport_compression: env_parse(
+                    "CUBESTORE_TRANSPORT_COMPRESSION",
+                    TransportCompression::None,
+                ),
+                transport_compression_threshold: env_parse(
+                    "CUBESTORE_TRANSPORT_COMPRESSION_THRESHOLD",
+                    64 * 1024,
+                ),
+                query_batch_size: env_parse("CUBESTORE_QUERY_BATCH_SIZE", 4096),
+                early_result_flush: env_bool("CUBESTORE_EARLY_RESULT_FLUSH", false),
+                speculative_execution_slowdown: env_parse(
+                    "CUBESTORE_SPECULATIVE_EXECUTION_SLOWDOWN",
+                    0,
+                ),
+                speculative_execution_min_delay_ms: env_parse(
+                    "CUBESTORE_SPECULATIVE_EXECUTION_MIN_DELAY_MS",
+                    1000,
+                ),
+                replication_tables: env::var("CUBESTORE_REPLICATION_TABLES")
+                    .ok()
+                    .map(|v| v.split(",").map(|s| s.to_string()).collect())
+                    .unwrap_or(Vec::new()),
+                replication_target: env::var("CUBESTORE_REPLICATION_TARGET").ok(),
+                replication_interval_secs: env_parse("CUBESTORE_REPLICATION_INTERVAL_SECS", 5),
+                attached_table_refresh_secs: env_parse("CUBESTORE_ATTACHED_TABLE_REFRESH_SECS", 60),
+                pre_aggregation_refresh_secs: env_parse(
+                    "CUBESTORE_PRE_AGGREGATION_REFRESH_SECS",
+                    60,
+                ),
+                partition_stats_flush_secs: env_parse("CUBESTORE_PARTITION_STATS_FLUSH_SECS", 60),
+                string_offload_threshold: env_parse("CUBESTORE_STRING_OFFLOAD_THRESHOLD", 1024),
+                replicated_table_max_rows: env_parse(
+                    "CUBESTORE_REPLICATED_TABLE_MAX_ROWS",
+                    100_000,
+                ),
+                linked_server_max_rows: env_parse("CUBESTORE_LINKED_SERVER_MAX_ROWS", 10_000),
+                health_check_timeout_ms: env_parse("CUBESTORE_HEALTH_CHECK_TIMEOUT_MS", 1000),
+                query_log_path: env::var("CUBESTORE_QUERY_LOG").ok().map(PathBuf::from),
+                idle_session_timeout_secs: env_parse("CUBESTORE_IDLE_SESSION_TIMEOUT", 8 * 60 * 60),
+                auth_providers: env::var("CUBESTORE_AUTH_PROVIDERS")
+                    .ok()
+                    .map(|v| {
+                        v.split(';')
+                            .filter(|p| !p.trim().is_empty())
+                            .map(|p| match p.parse::<AuthProviderConfig>() {
+                                Ok(p) => p,
+                                Err(e) => panic!(
+                                    "could not parse value for 'CUBESTORE_AUTH_PROVIDERS': {}",
+                                    e
+                                ),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or(Vec::new()),
+                auth_roles: env::var("CUBESTORE_AUTH_ROLES")
+                    .ok()
+                    .map(|v| match parse_roles(&v) {
+                        Ok(r) => r,
+                        Err(e) => panic!("could not parse value for 'CUBESTORE_AUTH_ROLES': {}", e),
+                    })
+                    .unwrap_or(Vec::new()),
+                auth_mysql_users: env::var("CUBESTORE_AUTH_MYSQL_USERS")
+                    .ok()
+                    .map(|v| match parse_mysql_users(&v) {
+                        Ok(u) => u,
+                        Err(e) => panic!(
+                            "could not parse value for 'CUBESTORE_AUTH_MYSQL_USERS': {}",
+                            e
+                        ),
+                    })
+                    .unwrap_or(Vec::new()),
+                auth_jwt_audience: env::var("CUBESTORE_AUTH_JWT_AUDIENCE").ok(),
+                secrets_key: env::var("CUBESTORE_SECRETS_KEY").ok(),
+                vault_address: env::var("CUBESTORE_VAULT_ADDR").ok(),
+                vault_token: env::var("CUBESTORE_VAULT_TOKEN").ok(),
+                dynamic: Arc::new(DynamicConfig::new(
+                    env::var("CUBESTORE_CONFIG_FILE").ok().map(PathBuf::from),
+                )),
             }),
         }
     }
@@ -514,16 +1420,22 @@ impl Config {
                 },
                 select_worker_pool_size: 0,
                 job_runners_count: 4,
+                background_job_runners_count: 1,
+                maintenance_window: None,
                 bind_address: None,
                 http_bind_address: None,
+                flight_bind_address: None,
                 query_timeout,
                 not_used_timeout: 2 * query_timeout,
                 select_workers: Vec::new(),
+                read_replica_workers: Vec::new(),
                 worker_bind_address: None,
                 metastore_bind_address: None,
                 metastore_remote_address: None,
                 upload_concurrency: 4,
                 download_concurrency: 8,
+                metastore_read_concurrency: 4,
+                metastore_select_cache_max_entries: 1000,
                 max_ingestion_data_frames: 4,
                 wal_split_threshold: 262144,
                 connection_timeout: 60,
@@ -531,7 +1443,60 @@ impl Config {
                 upload_to_remote: true,
                 enable_topk: true,
                 enable_startup_warmup: true,
+                startup_warmup_idle_secs: 0,
                 malloc_trim_every_secs: 0,
+                memory_pressure_check_every_secs: 0,
+                memory_pressure_threshold_percent: 85,
+                metastore_log_retention_secs: 24 * 60 * 60,
+                metastore_log_max_entries: 100000,
+                worker_batch_cache_max_size: 0,
+                result_cache_max_entries: 10000,
+                meta_store_log_upload_interval: 60,
+                meta_store_snapshot_interval: 300,
+                max_partitions_per_query: 0,
+                max_rows_per_query: 0,
+                scratch_max_size: 0,
+                router_merge_partitions: 1,
+                router_merge_memory_limit: 512 * 1024 * 1024,
+                operator_memory_limit: 0,
+                operator_cpu_limit_ms: 0,
+                max_reported_unsupported_features: 10,
+                export_ttl_secs: 60,
+                submitted_query_ttl_secs: 60,
+                submitted_query_timeout: 2 * query_timeout,
+                result_prefetch_lead_secs: 60,
+                case_insensitive_identifiers: false,
+                nulls_largest: false,
+                cast_overflow: CastOverflow::Error,
+                sum_overflow: CastOverflow::Error,
+                exact_float_sums: false,
+                time_zone: QueryTimeZone::default(),
+                transport_compression: TransportCompression::None,
+                transport_compression_threshold: 0,
+                query_batch_size: 4096,
+                early_result_flush: false,
+                speculative_execution_slowdown: 0,
+                speculative_execution_min_delay_ms: 1000,
+                replication_tables: Vec::new(),
+                replication_target: None,
+                replication_interval_secs: 1,
+                attached_table_refresh_secs: 0,
+                pre_aggregation_refresh_secs: 0,
+                partition_stats_flush_secs: 60,
+                string_offload_threshold: 1024,
+                replicated_table_max_rows: 0,
+                linked_server_max_rows: 10_000,
+                health_check_timeout_ms: 1000,
+                query_log_path: None,
+                idle_session_timeout_secs: 8 * 60 * 60,
+                auth_providers: Vec::new(),
+                auth_roles: Vec::new(),
+                auth_mysql_users: Vec::new(),
+                auth_jwt_audience: None,
+                secrets_key: None,
+                vault_address: None,
+                vault_token: None,
+                dynamic: Arc::new(DynamicConfig::default()),
             }),
         }
     }
@@ -705,9 +1670,11 @@ impl Config {
 
         self.injector
             .register_typed_with_default::<dyn RemoteFs, QueueRemoteFs, _, _>(async move |i| {
+                let config = i.get_service_typed::<dyn ConfigObj>().await;
+                let data_dir = config.data_dir().clone();
                 QueueRemoteFs::new(
-                    i.get_service_typed::<dyn ConfigObj>().await,
-                    i.get_service("original_remote_fs").await,
+                    config,
+                    StorageRemoteFs::new(i.get_service("original_remote_fs").await, data_dir),
                 )
             })
             .await;
@@ -796,6 +1763,10 @@ impl Config {
             })
             .await;
 
+        self.injector
+            .register_typed::<RowDecoderRegistry, _, _, _>(async move |_| RowDecoderRegistry::new())
+            .await;
+
         self.injector
             .register_typed::<ConcurrencyLimits, _, _, _>(async move |i| {
                 Arc::new(ConcurrencyLimits::new(
@@ -806,6 +1777,27 @@ impl Config {
             })
             .await;
 
+        self.injector
+            .register_typed::<ScratchSpace, _, _, _>(async move |i| {
+                let config = i.get_service_typed::<dyn ConfigObj>().await;
+                ScratchSpace::new(config.data_dir().join("scratch"), config.scratch_max_size())
+            })
+            .await;
+
+        self.injector
+            .register_typed::<DataFileLeases, _, _, _>(async move |_| DataFileLeases::new())
+            .await;
+
+        self.injector
+            .register_typed::<Sessions, _, _, _>(async move |_| Sessions::new())
+            .await;
+
+        self.injector
+            .register_typed::<SecretStore, _, _, _>(async move |i| {
+                SecretStore::new(i.get_service_typed().await, i.get_service_typed().await)
+            })
+            .await;
+
         self.injector
             .register_typed::<dyn ImportService, _, _, _>(async move |i| {
                 ImportServiceImpl::new(
@@ -814,23 +1806,112 @@ impl Config {
                     i.get_service_typed().await,
                     i.get_service_typed().await,
                     i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<ResultExports, _, _, _>(async move |i| {
+                Arc::new(ResultExports::new(Duration::from_secs(
+                    i.get_service_typed::<dyn ConfigObj>()
+                        .await
+                        .export_ttl_secs(),
+                )))
+            })
+            .await;
+
+        self.injector
+            .register_typed::<SubmittedQueries, _, _, _>(async move |i| {
+                let config = i.get_service_typed::<dyn ConfigObj>().await;
+                Arc::new(SubmittedQueries::new(
+                    Duration::from_secs(config.submitted_query_ttl_secs()),
+                    Duration::from_secs(config.submitted_query_timeout()),
+                ))
+            })
+            .await;
+
+        self.injector
+            .register_typed::<ResultPrefetcher, _, _, _>(async move |i| {
+                Arc::new(ResultPrefetcher::new(Duration::from_secs(
+                    i.get_service_typed::<dyn ConfigObj>()
+                        .await
+                        .result_prefetch_lead_secs(),
+                )))
+            })
+            .await;
+
+        let replication_meta_store_sender = event_sender_to_move.clone();
+        self.injector
+            .register_typed::<TableReplicator, _, _, _>(async move |i| {
+                TableReplicator::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
+                    replication_meta_store_sender.subscribe(),
+                )
+            })
+            .await;
+
+        let history_meta_store_sender = event_sender_to_move.clone();
+        self.injector
+            .register_typed::<MetaStoreHistory, _, _, _>(async move |i| {
+                MetaStoreHistory::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
+                    history_meta_store_sender.subscribe(),
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<AttachedTableRefresher, _, _, _>(async move |i| {
+                AttachedTableRefresher::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<PreAggregationRefresher, _, _, _>(async move |i| {
+                PreAggregationRefresher::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
                 )
             })
             .await;
 
         self.injector
             .register_typed::<dyn QueryPlanner, _, _, _>(async move |i| {
-                QueryPlannerImpl::new(i.get_service_typed().await, i.get_service_typed().await)
+                QueryPlannerImpl::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
             })
             .await;
 
         self.injector
-            .register_typed::<dyn QueryExecutor, _, _, _>(async move |_| {
-                Arc::new(QueryExecutorImpl)
+            .register_typed::<dyn QueryExecutor, _, _, _>(async move |i| {
+                Arc::new(QueryExecutorImpl::new(
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
+                    Some(i.get_service_typed().await),
+                ))
             })
             .await;
 
         let cluster_meta_store_sender = event_sender_to_move.clone();
+        let http_meta_store_sender = event_sender_to_move.clone();
 
         self.injector
             .register_typed_with_default::<dyn Cluster, _, _, _>(async move |i| {
@@ -850,6 +1931,16 @@ impl Config {
                     i.get_service_typed().await,
                     cluster_meta_store_sender,
                     i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<PartitionAccessStats, _, _, _>(async move |i| {
+                PartitionAccessStats::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed::<dyn ConfigObj>().await.as_ref(),
                 )
             })
             .await;
@@ -867,9 +1958,34 @@ impl Config {
                     i.get_service_typed::<dyn ConfigObj>()
                         .await
                         .wal_split_threshold() as usize,
-                    Duration::from_secs(
-                        i.get_service_typed::<dyn ConfigObj>().await.query_timeout(),
-                    ),
+                    i.get_service_typed::<dyn ConfigObj>().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<HealthChecker, _, _, _>(async move |i| {
+                HealthChecker::new(
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                    i.get_service_typed().await,
+                )
+            })
+            .await;
+
+        self.injector
+            .register_typed::<QueryLog, _, _, _>(async move |i| {
+                QueryLog::new(
+                    i.get_service_typed::<dyn ConfigObj>()
+                        .await
+                        .query_log_path()
+                        .clone(),
                 )
             })
             .await;
@@ -882,28 +1998,51 @@ impl Config {
                     i.get_service_typed().await,
                     event_sender_to_move.subscribe(),
                     i.get_service_typed().await,
+                    i.get_service_typed().await,
                 ))
             })
             .await;
 
         if self.config_obj.bind_address().is_some() {
-            self.injector
-                .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| {
-                    Arc::new(SqlAuthDefaultImpl)
-                })
-                .await;
+            if self.config_obj.auth_providers().is_empty() {
+                self.injector
+                    .register_typed::<dyn SqlAuthService, _, _, _>(async move |_| {
+                        Arc::new(SqlAuthDefaultImpl)
+                    })
+                    .await;
+            } else {
+                self.injector
+                    .register_typed::<dyn SqlAuthService, _, _, _>(async move |i| {
+                        let config = i.get_service_typed::<dyn ConfigObj>().await;
+                        let providers = config
+                            .auth_providers()
+                            .iter()
+                            .map(|p| p.provider(config.auth_jwt_audience()))
+                            .collect::<Result<Vec<_>, _>>()
+                            .unwrap_or_else(|e| panic!("could not create auth provider: {}", e));
+                        ProviderAuthService::new(
+                            providers,
+                            config.auth_roles().clone(),
+                            config.auth_mysql_users().clone(),
+                        )
+                    })
+                    .await;
+            }
 
             self.injector
                 .register_typed::<MySqlServer, _, _, _>(async move |i| {
+                    let config = i.get_service_typed::<dyn ConfigObj>().await;
+                    let idle_session_timeout = match config.idle_session_timeout_secs() {
+                        0 => None,
+                        secs => Some(Duration::from_secs(secs)),
+                    };
                     MySqlServer::new(
-                        i.get_service_typed::<dyn ConfigObj>()
-                            .await
-                            .bind_address()
-                            .as_ref()
-                            .unwrap()
-                            .to_string(),
+                        config.bind_address().as_ref().unwrap().to_string(),
                         i.get_service_typed().await,
                         i.get_service_typed().await,
+                        i.get_service_typed().await,
+                        i.get_service_typed().await,
+                        idle_session_timeout,
                     )
                 })
                 .await;
@@ -919,9 +2058,30 @@ impl Config {
                             .to_string(),
                         i.get_service_typed().await,
                         i.get_service_typed().await,
+                        http_meta_store_sender,
+                        i.get_service_typed().await,
+                        i.get_service_typed().await,
                     )
                 })
                 .await;
+
+            if self.config_obj.flight_bind_address().is_some() {
+                self.injector
+                    .register_typed::<FlightServer, _, _, _>(async move |i| {
+                        FlightServer::new(
+                            i.get_service_typed::<dyn ConfigObj>()
+                                .await
+                                .flight_bind_address()
+                                .as_ref()
+                                .unwrap()
+                                .to_string(),
+                            i.get_service_typed().await,
+                            i.get_service_typed().await,
+                            i.get_service_typed().await,
+                        )
+                    })
+                    .await;
+            }
         }
     }
 
@@ -946,10 +2106,10 @@ impl Config {
         self.cube_services().await
     }
 
-    pub fn configure_worker_services() {
+    pub fn configure_worker_services(&self) {
         let mut services = WORKER_SERVICES.write().unwrap();
         *services = Some(WorkerServices {
-            query_executor: Arc::new(QueryExecutorImpl),
+            query_executor: Arc::new(QueryExecutorImpl::new(self.config_obj.as_ref(), None)),
         })
     }
 
@@ -959,3 +2119,26 @@ impl Config {
 }
 
 type LoopHandle = JoinHandle<Result<(), CubeError>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maintenance_window() {
+        let w = "1-5".parse::<MaintenanceWindow>().unwrap();
+        assert!(!w.contains(0));
+        assert!(w.contains(1));
+        assert!(w.contains(4));
+        assert!(!w.contains(5));
+
+        let w = "22-4".parse::<MaintenanceWindow>().unwrap();
+        assert!(w.contains(23));
+        assert!(w.contains(0));
+        assert!(!w.contains(4));
+        assert!(!w.contains(12));
+
+        assert!("5".parse::<MaintenanceWindow>().is_err());
+        assert!("1-30".parse::<MaintenanceWindow>().is_err());
+    }
+}
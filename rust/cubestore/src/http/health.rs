@@ -0,0 +1,269 @@
+//! `/livez` and `/readyz` endpoints for liveness and readiness probes, served on the HTTP port of
+//! the router and of workers. Liveness only depends on the node itself: the router reads its
+//! metastore, workers only have to respond. Readiness checks all dependencies of the node, i.e.
+//! the metastore, the remote filesystem and, on the router, connections to workers. Both return
+//! 200 if all checks passed and 503 otherwise, with a report of the checks:
+//!     {"status": "fail", "node": "router", "checks": [
+//!         {"name": "metastore", "status": "ok", "duration_ms": 1},
+//!         {"name": "remote_fs", "status": "fail", "duration_ms": 1000, "error": "Timed out"}]}
+//! Each check fails after [ConfigObj::health_check_timeout_ms]. Probes are not authenticated.
+use crate::cluster::worker_nodes;
+use crate::config::ConfigObj;
+use crate::metastore::MetaStore;
+use crate::remotefs::RemoteFs;
+use crate::CubeError;
+use futures::future::{join_all, BoxFuture};
+use futures::FutureExt;
+use log::info;
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use warp::http::StatusCode;
+use warp::reply::{Json, WithStatus};
+use warp::{Filter, Rejection};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Fail,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub node: String,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    fn reply(&self) -> WithStatus<Json> {
+        let status = match self.status {
+            HealthStatus::Ok => StatusCode::OK,
+            HealthStatus::Fail => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        warp::reply::with_status(warp::reply::json(self), status)
+    }
+}
+
+pub struct HealthChecker {
+    config: Arc<dyn ConfigObj>,
+    meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
+    cancel_token: CancellationToken,
+}
+
+crate::di_service!(HealthChecker, []);
+
+impl HealthChecker {
+    pub fn new(
+        config: Arc<dyn ConfigObj>,
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            meta_store,
+            remote_fs,
+            cancel_token: CancellationToken::new(),
+        })
+    }
+
+    fn is_router(&self) -> bool {
+        self.config.worker_bind_address().is_none()
+    }
+
+    pub async fn liveness(&self) -> HealthReport {
+        let mut checks = Vec::new();
+        if self.is_router() {
+            checks.push(("metastore".to_string(), self.check_metastore()));
+        }
+        self.run_checks(checks).await
+    }
+
+    pub async fn readiness(&self) -> HealthReport {
+        let mut checks = vec![
+            ("metastore".to_string(), self.check_metastore()),
+            ("remote_fs".to_string(), self.check_remote_fs()),
+        ];
+        if self.is_router() {
+            for worker in worker_nodes(self.config.as_ref())
+                .into_iter()
+                .filter(|w| w != self.config.server_name())
+            {
+                checks.push((format!("worker {}", worker), check_worker(worker)));
+            }
+        }
+        self.run_checks(checks).await
+    }
+
+    fn check_metastore(&self) -> BoxFuture<'_, Result<(), CubeError>> {
+        async move {
+            self.meta_store.get_schemas().await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    /// Lists a single file, so checks of large buckets are as fast as of empty ones.
+    fn check_remote_fs(&self) -> BoxFuture<'_, Result<(), CubeError>> {
+        async move {
+            self.remote_fs.list("metastore-current").await?;
+            Ok(())
+        }
+        .boxed()
+    }
+
+    async fn run_checks(
+        &self,
+        checks: Vec<(String, BoxFuture<'_, Result<(), CubeError>>)>,
+    ) -> HealthReport {
+        let limit = Duration::from_millis(self.config.health_check_timeout_ms());
+        let checks = join_all(checks.into_iter().map(|(name, check)| async move {
+            let start = Instant::now();
+            let result = match timeout(limit, check).await {
+                Ok(result) => result,
+                Err(_) => Err(CubeError::internal("Timed out".to_string())),
+            };
+            HealthCheck {
+                name,
+                status: if result.is_ok() {
+                    HealthStatus::Ok
+                } else {
+                    HealthStatus::Fail
+                },
+                duration_ms: start.elapsed().as_millis() as u64,
+                error: result.err().map(|e| e.message),
+            }
+        }))
+        .await;
+        HealthReport {
+            status: if checks.iter().all(|c| c.status == HealthStatus::Ok) {
+                HealthStatus::Ok
+            } else {
+                HealthStatus::Fail
+            },
+            node: self.config.server_name().to_string(),
+            checks,
+        }
+    }
+
+    pub fn routes(
+        self: &Arc<Self>,
+    ) -> impl Filter<Extract = (WithStatus<Json>,), Error = Rejection> + Clone {
+        let checker = self.clone();
+        let livez = warp::path!("livez").and(warp::get()).and_then(move || {
+            let checker = checker.clone();
+            async move { Ok::<_, Rejection>(checker.liveness().await.reply()) }
+        });
+        let checker = self.clone();
+        let readyz = warp::path!("readyz").and(warp::get()).and_then(move || {
+            let checker = checker.clone();
+            async move { Ok::<_, Rejection>(checker.readiness().await.reply()) }
+        });
+        livez.or(readyz).unify()
+    }
+
+    /// Serves the probes on workers, the router serves them with [crate::http::HttpServer].
+    pub async fn run_server(self: Arc<Self>, bind_address: String) -> Result<(), CubeError> {
+        let addr: SocketAddr = bind_address.parse().unwrap();
+        info!("Health probes are served on {}", bind_address);
+        let cancel_token = self.cancel_token.clone();
+        let (_, server_future) = warp::serve(self.routes())
+            .bind_with_graceful_shutdown(addr, async move { cancel_token.cancelled().await });
+        server_future.await;
+        Ok(())
+    }
+
+    pub fn stop_processing(&self) {
+        self.cancel_token.cancel();
+    }
+}
+
+fn check_worker(address: String) -> BoxFuture<'static, Result<(), CubeError>> {
+    async move {
+        TcpStream::connect(&address)
+            .await
+            .map_err(|e| CubeError::internal(format!("Can't connect to {}: {}", address, e)))?;
+        Ok(())
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn probes() {
+        Config::test("health_probes")
+            .start_test(async move |services| {
+                let checker = services.injector.get_service_typed::<HealthChecker>().await;
+                let routes = checker.routes();
+                let response = warp::test::request().path("/readyz").reply(&routes).await;
+                assert_eq!(response.status(), StatusCode::OK);
+                let report: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+                assert_eq!(report["status"], "ok");
+                assert_eq!(
+                    report["checks"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|c| c["name"].as_str().unwrap())
+                        .collect::<Vec<_>>(),
+                    vec!["metastore", "remote_fs"]
+                );
+                let response = warp::test::request().path("/livez").reply(&routes).await;
+                assert_eq!(response.status(), StatusCode::OK);
+
+                // A worker that is up and one that does not accept connections.
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let up = listener.local_addr().unwrap().to_string();
+                let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let down = closed.local_addr().unwrap().to_string();
+                drop(closed);
+                let config = Config::test("health_probes")
+                    .update_config(|mut c| {
+                        c.select_workers = vec![up.clone(), down.clone()];
+                        c
+                    })
+                    .config_obj();
+                let checker = HealthChecker::new(
+                    config,
+                    services.meta_store.clone(),
+                    services.remote_fs.clone(),
+                );
+                let report = checker.readiness().await;
+                assert_eq!(report.status, HealthStatus::Fail);
+                let workers = report
+                    .checks
+                    .iter()
+                    .filter(|c| c.name.starts_with("worker"))
+                    .map(|c| (c.name.clone(), c.status))
+                    .collect::<Vec<_>>();
+                let mut expected = vec![
+                    (format!("worker {}", up), HealthStatus::Ok),
+                    (format!("worker {}", down), HealthStatus::Fail),
+                ];
+                expected.sort_by(|a, b| a.0.cmp(&b.0));
+                assert_eq!(workers, expected);
+                assert_eq!(checker.liveness().await.status, HealthStatus::Ok);
+            })
+            .await;
+    }
+}
@@ -1,30 +1,42 @@
+pub mod health;
+
 use std::sync::Arc;
 
 use warp::{Filter, Rejection, Reply};
 
+use crate::auth::{AuthenticatedUser, Credentials};
 use crate::codegen::http_message_generated::{
     get_root_as_http_message, HttpColumnValue, HttpColumnValueArgs, HttpError, HttpErrorArgs,
-    HttpMessageArgs, HttpQuery, HttpQueryArgs, HttpResultSet, HttpResultSetArgs, HttpRow,
-    HttpRowArgs,
+    HttpMessageArgs, HttpQuery, HttpQueryArgs, HttpQueryBatch, HttpQueryBatchArgs, HttpQueryResult,
+    HttpQueryResultArgs, HttpResultSet, HttpResultSetArgs, HttpResultSetBatch,
+    HttpResultSetBatchArgs, HttpRow, HttpRowArgs,
 };
+use crate::http::health::HealthChecker;
+use crate::metastore::change_feed::MetaStoreChange;
+use crate::metastore::MetaStoreEvent;
 use crate::mysql::SqlAuthService;
+use crate::sql::query_log::QueryLog;
 use crate::sql::{SqlQueryContext, SqlService};
 use crate::store::DataFrame;
 use crate::table::TableValue;
+use crate::util::query_id::{new_query_id, with_query_id};
 use crate::util::WorkerLoop;
 use crate::CubeError;
 use async_std::fs::File;
+use chrono::Utc;
 use futures::{AsyncWriteExt, SinkExt, Stream, StreamExt};
 use hex::ToHex;
-use http_auth_basic::Credentials;
+use http_auth_basic::Credentials as BasicCredentials;
 use log::error;
 use log::info;
 use log::trace;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::convert::Infallible;
 use std::net::SocketAddr;
 use tempfile::NamedTempFile;
-use tokio::sync::mpsc;
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 use warp::filters::ws::{Message, Ws};
 use warp::http::StatusCode;
@@ -34,6 +46,9 @@ pub struct HttpServer {
     bind_address: String,
     sql_service: Arc<dyn SqlService>,
     auth: Arc<dyn SqlAuthService>,
+    meta_store_events: broadcast::Sender<MetaStoreEvent>,
+    health: Arc<HealthChecker>,
+    query_log: Arc<QueryLog>,
     worker_loop: WorkerLoop,
     cancel_token: CancellationToken,
 }
@@ -57,6 +72,11 @@ pub struct UploadQuery {
     name: String,
 }
 
+#[derive(Deserialize)]
+pub struct RefreshKeyQuery {
+    query: String,
+}
+
 impl Reject for CubeRejection {}
 
 impl HttpServer {
@@ -64,11 +84,17 @@ impl HttpServer {
         bind_address: String,
         auth: Arc<dyn SqlAuthService>,
         sql_service: Arc<dyn SqlService>,
+        meta_store_events: broadcast::Sender<MetaStoreEvent>,
+        health: Arc<HealthChecker>,
+        query_log: Arc<QueryLog>,
     ) -> Arc<Self> {
         Arc::new(Self {
             bind_address,
             auth,
             sql_service,
+            meta_store_events,
+            health,
+            query_log,
             worker_loop: WorkerLoop::new("HttpServer message processing"),
             cancel_token: CancellationToken::new(),
         })
@@ -87,7 +113,11 @@ impl HttpServer {
                 async move {
                     let res = HttpServer::authorize(auth_service, auth_header).await;
                     match res {
-                        Ok(user) => Ok(SqlQueryContext { user }),
+                        Ok(AuthenticatedUser { user, role }) => Ok(SqlQueryContext {
+                            user,
+                            role,
+                            ..SqlQueryContext::default()
+                        }),
                         Err(_) => Err(warp::reject::custom(CubeRejection::NotAuthorized)),
                     }
                 }
@@ -131,7 +161,7 @@ impl HttpServer {
                                                     if let Err(e) = tx_to_move.try_send((response_tx.clone(), sql_query_context.clone(), msg)) {
                                                         error!("Websocket channel error: {:?}", e);
                                                         let send_res = web_socket.send(
-                                                            Message::binary(HttpMessage { message_id, command: HttpCommand::Error { error: e.to_string() } }.bytes())
+                                                            Message::binary(HttpMessage { message_id, command: HttpCommand::Error { error: CubeError::unavailable(e.to_string()).to_string() } }.bytes())
                                                         ).await;
                                                         if let Err(e) = send_res {
                                                             error!("Websocket message send error: {:?}", e)
@@ -175,15 +205,56 @@ impl HttpServer {
                 )
             });
 
+        let auth_filter_to_move = auth_filter.clone();
+        let sql_service = self.sql_service.clone();
+
+        let export_route = warp::path!("export" / String)
+            .and(warp::get())
+            .and(auth_filter_to_move)
+            .and_then(move |id: String, sql_query_context| {
+                HttpServer::handle_export_download(sql_service.clone(), sql_query_context, id)
+            });
+
+        let auth_filter_to_move = auth_filter.clone();
+        let meta_store_events = self.meta_store_events.clone();
+
+        let events_route =
+            warp::path!("events")
+                .and(warp::get())
+                .and(auth_filter_to_move)
+                .map(move |_: SqlQueryContext| {
+                    warp::sse::reply(warp::sse::keep_alive().stream(
+                        HttpServer::meta_store_changes(meta_store_events.subscribe()),
+                    ))
+                });
+
+        let auth_filter_to_move = auth_filter.clone();
+        let sql_service = self.sql_service.clone();
+        let meta_store_events = self.meta_store_events.clone();
+
+        let refresh_key_route = warp::path!("refresh_key")
+            .and(warp::get())
+            .and(auth_filter_to_move)
+            .and(warp::query::query::<RefreshKeyQuery>())
+            .map(move |sql_query_context, q: RefreshKeyQuery| {
+                warp::sse::reply(warp::sse::keep_alive().stream(HttpServer::refresh_keys(
+                    sql_service.clone(),
+                    sql_query_context,
+                    q.query,
+                    meta_store_events.subscribe(),
+                )))
+            });
+
         let sql_service = self.sql_service.clone();
+        let query_log = self.query_log.clone();
 
         let addr: SocketAddr = self.bind_address.parse().unwrap();
         info!("Http Server is listening on {}", self.bind_address);
         let process_loop = self.worker_loop.process_channel(
             sql_service,
             &mut rx,
-            async move |sql_service,
-                        (
+            move |sql_service,
+                  (
                 sender,
                 sql_query_context,
                 HttpMessage {
@@ -191,54 +262,74 @@ impl HttpServer {
                     command,
                 },
             )| {
-                tokio::spawn(async move {
-                    let res =
-                        HttpServer::process_command(sql_service, sql_query_context, command).await;
-                    let message = match res {
-                        Ok(command) => HttpMessage {
-                            message_id,
-                            command,
-                        },
-                        Err(e) => HttpMessage {
-                            message_id,
-                            command: HttpCommand::Error {
-                                error: e.to_string(),
+                let query_log = query_log.clone();
+                async move {
+                    tokio::spawn(async move {
+                        let query_id = new_query_id();
+                        let res = with_query_id(
+                            Some(query_id.clone()),
+                            HttpServer::process_command(
+                                sql_service,
+                                query_log,
+                                sql_query_context,
+                                command,
+                            ),
+                        )
+                        .await;
+                        let message = match res {
+                            Ok(command) => HttpMessage {
+                                message_id,
+                                command,
                             },
-                        },
-                    };
-                    if let Err(e) = sender.send(message).await {
-                        error!("Send result channel error: {:?}", e);
-                    }
-                });
-                Ok(())
+                            Err(mut e) => {
+                                // Details of the error stay on the last line.
+                                e.message = format!("{} (query id: {})", e.message, query_id);
+                                HttpMessage {
+                                    message_id,
+                                    command: HttpCommand::Error {
+                                        error: format!("{:?}: {}", e.cause, e.client_message()),
+                                    },
+                                }
+                            }
+                        };
+                        if let Err(e) = sender.send(message).await {
+                            error!("Send result channel error: {:?}", e);
+                        }
+                    });
+                    Ok(())
+                }
             },
         );
         let cancel_token = self.cancel_token.clone();
-        let (_, server_future) = warp::serve(query_route.or(upload_route).recover(
-            |err: Rejection| async move {
-                let mut obj = HashMap::new();
-                if let Some(ws_error) = err.find::<CubeRejection>() {
-                    match ws_error {
-                        CubeRejection::NotAuthorized => {
-                            obj.insert("error".to_string(), "Not authorized".to_string());
-                            Ok(warp::reply::with_status(
-                                warp::reply::json(&obj),
-                                StatusCode::FORBIDDEN,
-                            ))
-                        }
-                        CubeRejection::Internal(e) => {
-                            obj.insert("error".to_string(), e.to_string());
-                            Ok(warp::reply::with_status(
-                                warp::reply::json(&obj),
-                                StatusCode::INTERNAL_SERVER_ERROR,
-                            ))
-                        }
+        let routes = query_route
+            .or(upload_route)
+            .or(export_route)
+            .or(events_route)
+            .or(refresh_key_route)
+            .or(self.health.routes());
+        let (_, server_future) = warp::serve(routes.recover(|err: Rejection| async move {
+            let mut obj = HashMap::new();
+            if let Some(ws_error) = err.find::<CubeRejection>() {
+                match ws_error {
+                    CubeRejection::NotAuthorized => {
+                        obj.insert("error".to_string(), "Not authorized".to_string());
+                        Ok(warp::reply::with_status(
+                            warp::reply::json(&obj),
+                            StatusCode::FORBIDDEN,
+                        ))
+                    }
+                    CubeRejection::Internal(e) => {
+                        obj.insert("error".to_string(), e.to_string());
+                        Ok(warp::reply::with_status(
+                            warp::reply::json(&obj),
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                        ))
                     }
-                } else {
-                    Err(err)
                 }
-            },
-        ))
+            } else {
+                Err(err)
+            }
+        }))
         .bind_with_graceful_shutdown(addr, async move { cancel_token.cancelled().await });
         let _ = tokio::join!(process_loop, server_future);
 
@@ -278,17 +369,143 @@ impl HttpServer {
         Ok(warp::reply())
     }
 
+    pub async fn handle_export_download(
+        sql_service: Arc<dyn SqlService>,
+        sql_query_context: SqlQueryContext,
+        id: String,
+    ) -> Result<impl Reply, Rejection> {
+        let path = sql_service.export_file(sql_query_context, &id).await?;
+        let body = tokio::fs::read(path)
+            .await
+            .map_err(|e| CubeRejection::Internal(e.to_string()))?;
+        Ok(warp::reply::with_header(body, "content-type", "text/csv"))
+    }
+
+    /// Server-sent events of metastore changes, see [MetaStoreChange].
+    fn meta_store_changes(
+        receiver: broadcast::Receiver<MetaStoreEvent>,
+    ) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> + Send + 'static {
+        futures::stream::unfold(receiver, |mut receiver| async move {
+            let change = loop {
+                match receiver.recv().await {
+                    Ok(event) => {
+                        if let Some(change) = MetaStoreChange::from_event(&event) {
+                            break change;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => break MetaStoreChange::Lagged { skipped },
+                    Err(RecvError::Closed) => return None,
+                }
+            };
+            let event = warp::sse::Event::default()
+                .event(change.name())
+                .data(serde_json::to_string(&change).unwrap());
+            Some((Ok(event), receiver))
+        })
+    }
+
+    /// Server-sent events with results of a refresh key query, e.g. `SELECT MAX(updated_at) FROM t`.
+    /// The query is evaluated right away and again after every metastore change that may affect
+    /// it, an event is only sent when the result differs from the previous one. Such queries are
+    /// usually answered from the metastore, see [crate::queryplanner::metadata_count].
+    fn refresh_keys(
+        sql_service: Arc<dyn SqlService>,
+        sql_query_context: SqlQueryContext,
+        query: String,
+        receiver: broadcast::Receiver<MetaStoreEvent>,
+    ) -> impl Stream<Item = Result<warp::sse::Event, Infallible>> + Send + 'static {
+        futures::stream::unfold(
+            (receiver, None),
+            move |(mut receiver, mut last): (_, Option<Result<serde_json::Value, String>>)| {
+                let sql_service = sql_service.clone();
+                let sql_query_context = sql_query_context.clone();
+                let query = query.clone();
+                async move {
+                    loop {
+                        if last.is_some() {
+                            loop {
+                                match receiver.recv().await {
+                                    Ok(event) => {
+                                        if MetaStoreChange::from_event(&event).is_some() {
+                                            break;
+                                        }
+                                    }
+                                    Err(RecvError::Lagged(_)) => break,
+                                    Err(RecvError::Closed) => return None,
+                                }
+                            }
+                            // A single evaluation covers all changes that are already pending.
+                            loop {
+                                match receiver.try_recv() {
+                                    Ok(_) | Err(TryRecvError::Lagged(_)) => {}
+                                    Err(_) => break,
+                                }
+                            }
+                        }
+                        let value = sql_service
+                            .exec_query_with_context(sql_query_context.clone(), &query)
+                            .await
+                            .map(|data_frame| refresh_key_value(&data_frame))
+                            .map_err(|e| e.to_string());
+                        if last.as_ref() == Some(&value) {
+                            continue;
+                        }
+                        let event = match &value {
+                            Ok(v) => warp::sse::Event::default()
+                                .event("refreshKey")
+                                .data(serde_json::json!({ "value": v }).to_string()),
+                            Err(e) => warp::sse::Event::default()
+                                .event("error")
+                                .data(serde_json::json!({ "error": e }).to_string()),
+                        };
+                        last = Some(value);
+                        return Some((Ok(event), (receiver, last)));
+                    }
+                }
+            },
+        )
+    }
+
     pub async fn process_command(
         sql_service: Arc<dyn SqlService>,
+        query_log: Arc<QueryLog>,
         sql_query_context: SqlQueryContext,
         command: HttpCommand,
     ) -> Result<HttpCommand, CubeError> {
+        let start = Utc::now();
+        let user = sql_query_context.user.clone();
         match command {
-            HttpCommand::Query { query } => Ok(HttpCommand::ResultSet {
-                data_frame: sql_service
+            HttpCommand::Query { query } => {
+                let res = sql_service
                     .exec_query_with_context(sql_query_context, &query)
-                    .await?,
-            }),
+                    .await;
+                let rows = res.as_ref().map(|d| d.get_rows().len() as u64);
+                query_log.record(start, None, &user, &query, rows);
+                Ok(HttpCommand::ResultSet { data_frame: res? })
+            }
+            HttpCommand::QueryBatch { queries } => {
+                let logged = if query_log.is_enabled() {
+                    queries.clone()
+                } else {
+                    Vec::new()
+                };
+                let res = sql_service
+                    .exec_query_batch(sql_query_context, queries)
+                    .await;
+                for (i, query) in logged.iter().enumerate() {
+                    let rows = match &res {
+                        Ok(results) => results[i].as_ref().map(|d| d.get_rows().len() as u64),
+                        Err(e) => Err(e),
+                    };
+                    query_log.record(start, None, &user, query, rows);
+                }
+                Ok(HttpCommand::ResultSetBatch {
+                    results: res?
+                        .into_iter()
+                        .map(|r| r.map_err(|e| e.to_string()))
+                        .collect(),
+                })
+            }
             x => Err(CubeError::user(format!("Unexpected command: {:?}", x))),
         }
     }
@@ -296,25 +513,19 @@ impl HttpServer {
     pub async fn authorize(
         auth: Arc<dyn SqlAuthService>,
         auth_header: Option<String>,
-    ) -> Result<Option<String>, CubeError> {
+    ) -> Result<AuthenticatedUser, CubeError> {
         let credentials = auth_header
-            .map(|auth_header| Credentials::from_header(auth_header))
-            .transpose()
-            .map_err(|e| CubeError::from_error(e))?;
-        if let Some(password) = auth
-            .authenticate(credentials.as_ref().map(|c| c.user_id.to_string()))
-            .await?
-        {
-            if Some(password) != credentials.as_ref().map(|c| c.password.to_string()) {
-                Err(CubeError::user(
-                    "User or password doesn't match".to_string(),
-                ))
-            } else {
-                Ok(credentials.as_ref().map(|c| c.user_id.to_string()))
-            }
-        } else {
-            Ok(credentials.as_ref().map(|c| c.user_id.to_string()))
-        }
+            .map(|auth_header| match auth_header.strip_prefix("Bearer ") {
+                Some(token) => Ok(Credentials::Token(token.trim().to_string())),
+                None => BasicCredentials::from_header(auth_header)
+                    .map(|c| Credentials::Password {
+                        user: c.user_id,
+                        password: c.password,
+                    })
+                    .map_err(|e| CubeError::from_error(e)),
+            })
+            .transpose()?;
+        auth.authenticate_credentials(credentials).await
     }
 
     pub async fn stop_processing(&self) {
@@ -331,9 +542,23 @@ pub struct HttpMessage {
 
 #[derive(Debug)]
 pub enum HttpCommand {
-    Query { query: String },
-    ResultSet { data_frame: Arc<DataFrame> },
-    Error { error: String },
+    Query {
+        query: String,
+    },
+    ResultSet {
+        data_frame: Arc<DataFrame>,
+    },
+    Error {
+        error: String,
+    },
+    /// Statements executed concurrently, see [SqlService::exec_query_batch].
+    QueryBatch {
+        queries: Vec<String>,
+    },
+    /// Results of [HttpCommand::QueryBatch] in the order of queries.
+    ResultSetBatch {
+        results: Vec<Result<Arc<DataFrame>, String>>,
+    },
 }
 
 impl HttpMessage {
@@ -351,6 +576,12 @@ impl HttpMessage {
                 HttpCommand::Error { .. } => {
                     crate::codegen::http_message_generated::HttpCommand::HttpError
                 }
+                HttpCommand::QueryBatch { .. } => {
+                    crate::codegen::http_message_generated::HttpCommand::HttpQueryBatch
+                }
+                HttpCommand::ResultSetBatch { .. } => {
+                    crate::codegen::http_message_generated::HttpCommand::HttpResultSetBatch
+                }
             },
             command: match &self.command {
                 HttpCommand::Query { query } => {
@@ -378,91 +609,39 @@ impl HttpMessage {
                     )
                 }
                 HttpCommand::ResultSet { data_frame } => {
-                    let columns = data_frame
-                        .get_columns()
-                        .iter()
-                        .map(|c| c.get_name().as_str())
-                        .collect::<Vec<_>>();
-                    let columns_vec = builder.create_vector_of_strings(columns.as_slice());
-
-                    let mut row_offsets = Vec::with_capacity(data_frame.get_rows().len());
-                    for row in data_frame.get_rows().iter() {
-                        let mut value_offsets = Vec::with_capacity(row.values().len());
-                        for value in row.values().iter() {
-                            let value = match value {
-                                TableValue::Null => HttpColumnValue::create(
+                    Some(HttpMessage::build_result_set(&mut builder, data_frame).as_union_value())
+                }
+                HttpCommand::QueryBatch { queries } => {
+                    let queries = queries.iter().map(|q| q.as_str()).collect::<Vec<_>>();
+                    let queries = Some(builder.create_vector_of_strings(queries.as_slice()));
+                    Some(
+                        HttpQueryBatch::create(&mut builder, &HttpQueryBatchArgs { queries })
+                            .as_union_value(),
+                    )
+                }
+                HttpCommand::ResultSetBatch { results } => {
+                    let mut result_offsets = Vec::with_capacity(results.len());
+                    for result in results.iter() {
+                        let args = match result {
+                            Ok(data_frame) => HttpQueryResultArgs {
+                                result_set: Some(HttpMessage::build_result_set(
                                     &mut builder,
-                                    &HttpColumnValueArgs { string_value: None },
-                                ),
-                                TableValue::String(v) => {
-                                    let string_value = Some(builder.create_string(v));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Int(v) => {
-                                    let string_value = Some(builder.create_string(&v.to_string()));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Decimal(v) => {
-                                    let string_value = Some(builder.create_string(&v.to_string()));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Float(v) => {
-                                    let string_value = Some(builder.create_string(&v.to_string()));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Bytes(v) => {
-                                    let string_value = Some(builder.create_string(&format!(
-                                        "0x{}",
-                                        v.encode_hex_upper::<String>()
-                                    )));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Timestamp(v) => {
-                                    let string_value = Some(builder.create_string(&v.to_string()));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                                TableValue::Boolean(v) => {
-                                    let string_value = Some(builder.create_string(&v.to_string()));
-                                    HttpColumnValue::create(
-                                        &mut builder,
-                                        &HttpColumnValueArgs { string_value },
-                                    )
-                                }
-                            };
-                            value_offsets.push(value);
-                        }
-                        let values = Some(builder.create_vector(value_offsets.as_slice()));
-                        let row = HttpRow::create(&mut builder, &HttpRowArgs { values });
-                        row_offsets.push(row);
+                                    data_frame,
+                                )),
+                                error: None,
+                            },
+                            Err(error) => HttpQueryResultArgs {
+                                result_set: None,
+                                error: Some(builder.create_string(error)),
+                            },
+                        };
+                        result_offsets.push(HttpQueryResult::create(&mut builder, &args));
                     }
-
-                    let rows = Some(builder.create_vector(row_offsets.as_slice()));
-
+                    let results = Some(builder.create_vector(result_offsets.as_slice()));
                     Some(
-                        HttpResultSet::create(
+                        HttpResultSetBatch::create(
                             &mut builder,
-                            &HttpResultSetArgs {
-                                columns: Some(columns_vec),
-                                rows,
-                            },
+                            &HttpResultSetBatchArgs { results },
                         )
                         .as_union_value(),
                     )
@@ -475,6 +654,75 @@ impl HttpMessage {
         builder.finished_data().to_vec() // TODO copy
     }
 
+    fn build_result_set<'a>(
+        builder: &mut flatbuffers::FlatBufferBuilder<'a>,
+        data_frame: &DataFrame,
+    ) -> flatbuffers::WIPOffset<HttpResultSet<'a>> {
+        let columns = data_frame
+            .get_columns()
+            .iter()
+            .map(|c| c.get_name().as_str())
+            .collect::<Vec<_>>();
+        let columns_vec = builder.create_vector_of_strings(columns.as_slice());
+
+        let mut row_offsets = Vec::with_capacity(data_frame.get_rows().len());
+        for row in data_frame.get_rows().iter() {
+            let mut value_offsets = Vec::with_capacity(row.values().len());
+            for value in row.values().iter() {
+                let value = match value {
+                    TableValue::Null => HttpColumnValue::create(
+                        builder,
+                        &HttpColumnValueArgs { string_value: None },
+                    ),
+                    TableValue::String(v) => {
+                        let string_value = Some(builder.create_string(v));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Int(v) => {
+                        let string_value = Some(builder.create_string(&v.to_string()));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Decimal(v) => {
+                        let string_value = Some(builder.create_string(&v.to_string()));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Float(v) => {
+                        let string_value = Some(builder.create_string(&v.to_string()));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Bytes(v) => {
+                        let string_value = Some(
+                            builder.create_string(&format!("0x{}", v.encode_hex_upper::<String>())),
+                        );
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Timestamp(v) => {
+                        let string_value = Some(builder.create_string(&v.to_string()));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                    TableValue::Boolean(v) => {
+                        let string_value = Some(builder.create_string(&v.to_string()));
+                        HttpColumnValue::create(builder, &HttpColumnValueArgs { string_value })
+                    }
+                };
+                value_offsets.push(value);
+            }
+            let values = Some(builder.create_vector(value_offsets.as_slice()));
+            let row = HttpRow::create(builder, &HttpRowArgs { values });
+            row_offsets.push(row);
+        }
+
+        let rows = Some(builder.create_vector(row_offsets.as_slice()));
+
+        HttpResultSet::create(
+            builder,
+            &HttpResultSetArgs {
+                columns: Some(columns_vec),
+                rows,
+            },
+        )
+    }
+
     pub fn read(buffer: Vec<u8>) -> Result<Self, CubeError> {
         let http_message = get_root_as_http_message(buffer.as_slice());
         Ok(HttpMessage {
@@ -486,6 +734,15 @@ impl HttpMessage {
                         query: query.query().unwrap().to_string(),
                     }
                 }
+                crate::codegen::http_message_generated::HttpCommand::HttpQueryBatch => {
+                    let batch = http_message.command_as_http_query_batch().unwrap();
+                    HttpCommand::QueryBatch {
+                        queries: batch
+                            .queries()
+                            .map(|v| (0..v.len()).map(|i| v.get(i).to_string()).collect())
+                            .unwrap_or_default(),
+                    }
+                }
                 command => {
                     return Err(CubeError::internal(format!(
                         "Unexpected command: {:?}",
@@ -496,3 +753,27 @@ impl HttpMessage {
         })
     }
 }
+
+/// Rows of the result as arrays of strings, nulls are kept as nulls.
+fn refresh_key_value(data_frame: &DataFrame) -> serde_json::Value {
+    let rows = data_frame
+        .get_rows()
+        .iter()
+        .map(|r| {
+            r.values()
+                .iter()
+                .map(|v| match v {
+                    TableValue::Null => None,
+                    TableValue::String(v) => Some(v.clone()),
+                    TableValue::Int(v) => Some(v.to_string()),
+                    TableValue::Decimal(v) => Some(v.to_string()),
+                    TableValue::Float(v) => Some(v.to_string()),
+                    TableValue::Bytes(v) => Some(format!("0x{}", v.encode_hex_upper::<String>())),
+                    TableValue::Timestamp(v) => Some(v.to_string()),
+                    TableValue::Boolean(v) => Some(v.to_string()),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!(rows)
+}
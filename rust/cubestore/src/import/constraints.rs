@@ -0,0 +1,223 @@
+//! NOT NULL and CHECK constraints of columns, e.g.:
+//!     CREATE TABLE s.orders (id int NOT NULL, amount decimal CHECK (amount >= 0))
+//! Every batch of rows of INSERT and import is checked before it is written, so bad upstream
+//! data fails the ingestion instead of producing wrong rollups. Errors list the violating rows,
+//! numbered from the start of the ingestion.
+//! Only comparisons of a single column with literals, `BETWEEN`, `IN` and their conjunctions are
+//! supported as CHECK constraints. As in SQL, nulls satisfy CHECK constraints.
+use crate::import::parse_value;
+use crate::metastore::{CheckOp, Column, ColumnCheck};
+use crate::table::data::{cmp_same_types, Rows, TableValueR};
+use crate::table::TableValue;
+use crate::util::maybe_owned::MaybeOwnedStr;
+use crate::CubeError;
+use bigdecimal::BigDecimal;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// Violations reported in a single error.
+const MAX_REPORTED_VIOLATIONS: usize = 10;
+
+/// Parses the literal of a CHECK constraint, fails if it is not a valid value of the column.
+pub fn check_literal(column: &Column, literal: &str) -> Result<TableValue, CubeError> {
+    match parse_value(MaybeOwnedStr::Borrowed(literal), column) {
+        Ok(TableValue::Null) | Err(_) => Err(CubeError::user(format!(
+            "Invalid literal in CHECK constraint of {}: {}",
+            column.get_name(),
+            literal
+        ))),
+        Ok(v) => Ok(v),
+    }
+}
+
+/// `first_row` is the number of rows validated before in the same ingestion.
+pub fn validate_rows(columns: &[Column], rows: &Rows, first_row: usize) -> Result<(), CubeError> {
+    let mut constraints = Vec::new();
+    for c in columns {
+        let mut checks = Vec::with_capacity(c.checks().len());
+        for check in c.checks() {
+            checks.push(match check {
+                ColumnCheck::Compare(op, v) => (check, vec![check_literal(c, v)?], Some(*op)),
+                ColumnCheck::In(list) => (
+                    check,
+                    list.iter()
+                        .map(|v| check_literal(c, v))
+                        .collect::<Result<Vec<_>, _>>()?,
+                    None,
+                ),
+            });
+        }
+        if c.is_not_null() || !checks.is_empty() {
+            constraints.push((c, checks));
+        }
+    }
+    if constraints.is_empty() {
+        return Ok(());
+    }
+
+    let mut violations = Vec::new();
+    let mut num_violations = 0;
+    for (i, row) in rows.view().iter().enumerate() {
+        for (column, checks) in &constraints {
+            let value = &row[column.get_index()];
+            let violated = if let TableValueR::Null = value {
+                column.is_not_null().then(|| "NOT NULL".to_string())
+            } else {
+                checks
+                    .iter()
+                    .find(|(_, literals, op)| !satisfies(value, literals, *op))
+                    .map(|(check, _, _)| check_to_string(column, check))
+            };
+            if let Some(constraint) = violated {
+                num_violations += 1;
+                if violations.len() < MAX_REPORTED_VIOLATIONS {
+                    violations.push(format!(
+                        "row {} violates {}: {} = {:?}",
+                        first_row + i + 1,
+                        constraint,
+                        column.get_name(),
+                        value
+                    ));
+                }
+            }
+        }
+    }
+    if violations.is_empty() {
+        return Ok(());
+    }
+    if num_violations > violations.len() {
+        violations.push(format!("{} more", num_violations - violations.len()));
+    }
+    Err(CubeError::user(format!(
+        "Constraint violations: {}",
+        violations.join(", ")
+    )))
+}
+
+/// `op` is `None` for IN lists.
+fn satisfies(value: &TableValueR, literals: &[TableValue], op: Option<CheckOp>) -> bool {
+    let cmp = |l: &TableValue| compare(value, &TableValueR::from_heap_allocated(l));
+    match op {
+        None => literals.iter().any(|l| cmp(l) == Ordering::Equal),
+        Some(op) => {
+            let ord = cmp(&literals[0]);
+            match op {
+                CheckOp::Eq => ord == Ordering::Equal,
+                CheckOp::NotEq => ord != Ordering::Equal,
+                CheckOp::Lt => ord == Ordering::Less,
+                CheckOp::LtEq => ord != Ordering::Greater,
+                CheckOp::Gt => ord == Ordering::Greater,
+                CheckOp::GtEq => ord != Ordering::Less,
+            }
+        }
+    }
+}
+
+fn compare(l: &TableValueR, r: &TableValueR) -> Ordering {
+    match (l, r) {
+        (TableValueR::Float(a), TableValueR::Float(b)) => a.cmp(b),
+        // Decimals are kept as strings.
+        (TableValueR::Decimal(a), TableValueR::Decimal(b)) => {
+            match (BigDecimal::from_str(a), BigDecimal::from_str(b)) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            }
+        }
+        (l, r) => cmp_same_types(l, r),
+    }
+}
+
+fn check_to_string(column: &Column, check: &ColumnCheck) -> String {
+    let condition = match check {
+        ColumnCheck::Compare(op, v) => {
+            let op = match op {
+                CheckOp::Eq => "=",
+                CheckOp::NotEq => "<>",
+                CheckOp::Lt => "<",
+                CheckOp::LtEq => "<=",
+                CheckOp::Gt => ">",
+                CheckOp::GtEq => ">=",
+            };
+            format!("{} '{}'", op, v)
+        }
+        ColumnCheck::In(list) => format!(
+            "IN ({})",
+            list.iter()
+                .map(|v| format!("'{}'", v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    format!("CHECK ({} {})", column.get_name(), condition)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::ColumnType;
+    use crate::table::data::MutRows;
+    use crate::table::Row;
+
+    #[test]
+    fn constraint_violations() {
+        let mut id = Column::new("id".to_string(), ColumnType::Int, 0);
+        id.set_not_null(true);
+        let mut amount = Column::new(
+            "amount".to_string(),
+            ColumnType::Decimal {
+                scale: 2,
+                precision: 10,
+            },
+            1,
+        );
+        amount.add_check(ColumnCheck::Compare(CheckOp::GtEq, "0".to_string()));
+        let mut status = Column::new("status".to_string(), ColumnType::String, 2);
+        status.add_check(ColumnCheck::In(vec!["new".to_string(), "paid".to_string()]));
+        let columns = vec![id, amount, status];
+
+        let rows = |rows: Vec<Vec<TableValue>>| {
+            let rows = rows.into_iter().map(Row::new).collect::<Vec<_>>();
+            MutRows::from_heap_allocated(3, &rows).freeze()
+        };
+        let valid = rows(vec![
+            vec![
+                TableValue::Int(1),
+                TableValue::Decimal("10.5".to_string()),
+                TableValue::String("new".to_string()),
+            ],
+            vec![TableValue::Int(2), TableValue::Null, TableValue::Null],
+        ]);
+        validate_rows(&columns, &valid, 0).unwrap();
+
+        let invalid = rows(vec![
+            vec![
+                TableValue::Null,
+                TableValue::Decimal("9".to_string()),
+                TableValue::String("paid".to_string()),
+            ],
+            vec![
+                TableValue::Int(2),
+                TableValue::Decimal("-1.5".to_string()),
+                TableValue::String("lost".to_string()),
+            ],
+        ]);
+        let e = validate_rows(&columns, &invalid, 10).unwrap_err();
+        assert!(e.message.contains("row 11 violates NOT NULL: id"), "{}", e);
+        assert!(
+            e.message
+                .contains("row 12 violates CHECK (amount >= '0'): amount"),
+            "{}",
+            e
+        );
+        assert!(
+            e.message
+                .contains("row 12 violates CHECK (status IN ('new', 'paid')): status"),
+            "{}",
+            e
+        );
+
+        let int = Column::new("n".to_string(), ColumnType::Int, 0);
+        assert!(check_literal(&int, "abc").is_err());
+        assert_eq!(check_literal(&int, "5").unwrap(), TableValue::Int(5));
+    }
+}
@@ -0,0 +1,195 @@
+//! Decoders of custom import formats, e.g. proprietary binary logs. Applications embedding
+//! CubeStore implement [RowDecoder] and register it in [RowDecoderRegistry] before starting the
+//! services:
+//!     config.configure_injector().await;
+//!     config.injector().get_service_typed::<RowDecoderRegistry>().await
+//!         .register(Arc::new(MyLogDecoder {}));
+//! Tables are imported with it by `CREATE TABLE ... WITH (format = '<name>') LOCATION ...`.
+//! Locations are resolved and decompressed the same way as for CSV.
+use crate::metastore::Column;
+use crate::table::Row;
+use crate::CubeError;
+use futures::Stream;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use tokio::io::AsyncBufRead;
+
+pub type RowStream = Pin<Box<dyn Stream<Item = Result<Option<Row>, CubeError>> + Send>>;
+
+pub trait RowDecoder: Debug + Send + Sync {
+    /// Name of the format used in `WITH (format = '<name>')`.
+    fn name(&self) -> &str;
+
+    /// Rows must have values of `columns` in the same order. `None` items are skipped, e.g. for
+    /// headers.
+    fn decode(
+        &self,
+        input: Pin<Box<dyn AsyncBufRead + Send>>,
+        columns: Vec<Column>,
+    ) -> Result<RowStream, CubeError>;
+}
+
+#[derive(Debug)]
+pub struct RowDecoderRegistry {
+    decoders: RwLock<HashMap<String, Arc<dyn RowDecoder>>>,
+}
+
+crate::di_service!(RowDecoderRegistry, []);
+
+impl RowDecoderRegistry {
+    pub fn new() -> Arc<RowDecoderRegistry> {
+        Arc::new(RowDecoderRegistry {
+            decoders: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Replaces the decoder previously registered with the same name.
+    pub fn register(&self, decoder: Arc<dyn RowDecoder>) {
+        self.decoders
+            .write()
+            .unwrap()
+            .insert(decoder.name().to_lowercase(), decoder);
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn RowDecoder>, CubeError> {
+        self.decoders
+            .read()
+            .unwrap()
+            .get(&name.to_lowercase())
+            .cloned()
+            .ok_or_else(|| CubeError::user(format!("Unknown import format: {}", name)))
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::import::parse_value;
+    use crate::metastore::ColumnType;
+    use crate::table::TableValue;
+    use crate::util::maybe_owned::MaybeOwnedStr;
+    use futures::StreamExt;
+    use std::io::Cursor;
+    use tokio::io::AsyncReadExt;
+
+    /// Example of a binary format. Every record is a sequence of values of all columns, each value
+    /// is a little-endian u16 length followed by its text, 0xFFFF stands for null.
+    #[derive(Debug)]
+    pub struct LengthPrefixedDecoder;
+
+    impl LengthPrefixedDecoder {
+        pub fn encode(rows: &[Vec<Option<&str>>]) -> Vec<u8> {
+            let mut data = Vec::new();
+            for r in rows {
+                for v in r {
+                    match v {
+                        Some(v) => {
+                            data.extend_from_slice(&(v.len() as u16).to_le_bytes());
+                            data.extend_from_slice(v.as_bytes());
+                        }
+                        None => data.extend_from_slice(&u16::MAX.to_le_bytes()),
+                    }
+                }
+            }
+            data
+        }
+
+        async fn read_row(
+            input: &mut Pin<Box<dyn AsyncBufRead + Send>>,
+            columns: &[Column],
+        ) -> Result<Option<Row>, CubeError> {
+            let mut row = Vec::with_capacity(columns.len());
+            for (i, c) in columns.iter().enumerate() {
+                let mut len = [0u8; 2];
+                if i == 0 && input.read(&mut len[..1]).await? == 0 {
+                    return Ok(None);
+                }
+                input
+                    .read_exact(&mut len[if i == 0 { 1 } else { 0 }..])
+                    .await?;
+                let len = u16::from_le_bytes(len);
+                if len == u16::MAX {
+                    row.push(TableValue::Null);
+                    continue;
+                }
+                let mut value = vec![0; len as usize];
+                input.read_exact(&mut value).await?;
+                let value = String::from_utf8(value)
+                    .map_err(|e| CubeError::user(format!("Invalid value: {}", e)))?;
+                row.push(parse_value(MaybeOwnedStr::Owned(value), c)?);
+            }
+            Ok(Some(Row::new(row)))
+        }
+    }
+
+    impl RowDecoder for LengthPrefixedDecoder {
+        fn name(&self) -> &str {
+            "length_prefixed"
+        }
+
+        fn decode(
+            &self,
+            input: Pin<Box<dyn AsyncBufRead + Send>>,
+            columns: Vec<Column>,
+        ) -> Result<RowStream, CubeError> {
+            Ok(Box::pin(futures::stream::unfold(
+                Some((input, columns)),
+                |state| async move {
+                    let (mut input, columns) = state?;
+                    match Self::read_row(&mut input, &columns).await {
+                        Ok(Some(row)) => Some((Ok(Some(row)), Some((input, columns)))),
+                        Ok(None) => None,
+                        Err(e) => Some((Err(e), None)),
+                    }
+                },
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_decoder() {
+        let registry = RowDecoderRegistry::new();
+        registry.register(Arc::new(LengthPrefixedDecoder));
+        assert!(registry.get("csv_v2").is_err());
+
+        let columns = vec![
+            Column::new("id".to_string(), ColumnType::Int, 0),
+            Column::new("name".to_string(), ColumnType::String, 1),
+        ];
+        let data =
+            LengthPrefixedDecoder::encode(&[vec![Some("1"), Some("foo")], vec![Some("2"), None]]);
+        let rows = registry
+            .get("LENGTH_PREFIXED")
+            .unwrap()
+            .decode(Box::pin(Cursor::new(data.clone())), columns.clone())
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                Some(Row::new(vec![
+                    TableValue::Int(1),
+                    TableValue::String("foo".to_string())
+                ])),
+                Some(Row::new(vec![TableValue::Int(2), TableValue::Null])),
+            ]
+        );
+
+        // Truncated records are errors.
+        let rows = LengthPrefixedDecoder
+            .decode(
+                Box::pin(Cursor::new(data[..data.len() - 1].to_vec())),
+                columns,
+            )
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        assert!(rows.last().unwrap().is_err());
+    }
+}
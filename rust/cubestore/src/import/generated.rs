@@ -0,0 +1,353 @@
+//! Stored generated columns, e.g.:
+//!     CREATE TABLE s.events (ts timestamp, email text, day AS (DATE_TRUNC('day', ts)) STORED)
+//! Values are computed from other columns of the same row when INSERT and import write chunks,
+//! so generated columns can be used in sort keys and partitioning like any other column. Sources
+//! of imports and INSERT statements do not provide values for them.
+//! Expressions are planned by DataFusion and may use its built-in scalar functions and columns
+//! that are not generated. Results are cast to the type of the column, which is inferred from the
+//! expression if not declared.
+use crate::import::parse_value;
+use crate::metastore::{Column, ColumnType};
+use crate::queryplanner::query_executor::{arrow_to_column_type, batch_to_dataframe};
+use crate::sql::parser::parse_expr;
+use crate::table::data::{MutRows, Rows, RowsView, TableValueR};
+use crate::table::TableValue;
+use crate::util::maybe_owned::MaybeOwnedStr;
+use crate::CubeError;
+use arrow::array::{
+    ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, StringArray,
+    TimestampNanosecondArray,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use datafusion::catalog::catalog::MemoryCatalogList;
+use datafusion::catalog::TableReference;
+use datafusion::datasource::TableProvider;
+use datafusion::execution::context::{ExecutionConfig, ExecutionContextState};
+use datafusion::logical_plan::{Expr, ToDFSchema};
+use datafusion::physical_plan::planner::DefaultPhysicalPlanner;
+use datafusion::physical_plan::udaf::AggregateUDF;
+use datafusion::physical_plan::udf::ScalarUDF;
+use datafusion::physical_plan::PhysicalExpr;
+use datafusion::sql::planner::{ContextProvider, SqlToRel};
+use std::sync::Arc;
+
+pub struct GeneratedColumns {
+    /// Indices of columns that expressions can reference, in the order of the input schema.
+    inputs: Vec<usize>,
+    input_schema: SchemaRef,
+    /// Expressions are cast to [value_type] of their columns.
+    exprs: Vec<(Column, Arc<dyn PhysicalExpr>)>,
+}
+
+impl GeneratedColumns {
+    /// `None` if the table has no generated columns.
+    pub fn new(columns: &[Column]) -> Result<Option<GeneratedColumns>, CubeError> {
+        if columns.iter().all(|c| c.generated().is_none()) {
+            return Ok(None);
+        }
+        let (inputs, input_schema) = input_schema(columns)?;
+        let ctx = ExecutionContextState {
+            catalog_list: Arc::new(MemoryCatalogList::new()),
+            scalar_functions: Default::default(),
+            var_provider: Default::default(),
+            aggregate_functions: Default::default(),
+            config: ExecutionConfig::new(),
+        };
+        let mut exprs = Vec::new();
+        for c in columns {
+            if let Some(expr) = c.generated() {
+                let expr = Expr::Cast {
+                    expr: Box::new(logical_expr(c, expr, &input_schema)?),
+                    data_type: value_type(c.get_column_type())?,
+                };
+                let expr = DefaultPhysicalPlanner::default()
+                    .create_physical_expr(&expr, &input_schema, &ctx)
+                    .map_err(|e| invalid_expr(c, e))?;
+                exprs.push((c.clone(), expr));
+            }
+        }
+        Ok(Some(GeneratedColumns {
+            inputs,
+            input_schema: Arc::new(input_schema),
+            exprs,
+        }))
+    }
+
+    /// Replaces values of generated columns.
+    pub fn compute(&self, rows: Rows) -> Result<Rows, CubeError> {
+        let num_rows = rows.num_rows();
+        if num_rows == 0 {
+            return Ok(rows);
+        }
+        let view = rows.view();
+        let inputs = self
+            .inputs
+            .iter()
+            .zip(self.input_schema.fields())
+            .map(|(i, f)| to_array(&view, *i, f.data_type()))
+            .collect();
+        let batch = RecordBatch::try_new(self.input_schema.clone(), inputs)?;
+        let mut fields = Vec::with_capacity(self.exprs.len());
+        let mut values = Vec::with_capacity(self.exprs.len());
+        for (c, e) in &self.exprs {
+            let v = e.evaluate(&batch)?.into_array(num_rows);
+            fields.push(Field::new(c.get_name(), v.data_type().clone(), true));
+            values.push(v);
+        }
+        let values = batch_to_dataframe(&vec![RecordBatch::try_new(
+            Arc::new(Schema::new(fields)),
+            values,
+        )?])?;
+
+        let mut result = MutRows::with_capacity(rows.num_columns(), num_rows);
+        for (r, generated) in view.iter().zip(values.get_rows()) {
+            let mut row = result.add_row_copy(r);
+            for ((c, _), v) in self.exprs.iter().zip(generated.values()) {
+                let v = match (v, c.get_column_type()) {
+                    // Decimals are computed as floats.
+                    (TableValue::Float(f), ColumnType::Decimal { .. }) => {
+                        parse_value(MaybeOwnedStr::Owned(f.0.to_string()), c)?
+                    }
+                    (v, _) => v.clone(),
+                };
+                row.set_interned(c.get_index(), TableValueR::from_heap_allocated(&v));
+            }
+        }
+        Ok(result.freeze())
+    }
+}
+
+/// Type of the values of `expr`, used for generated columns without a declared type.
+pub fn generated_column_type(
+    column: &Column,
+    expr: &str,
+    columns: &[Column],
+) -> Result<ColumnType, CubeError> {
+    let (_, input_schema) = input_schema(columns)?;
+    let data_type = logical_expr(column, expr, &input_schema)?
+        .get_type(&input_schema.to_dfschema()?)
+        .map_err(|e| invalid_expr(column, e))?;
+    arrow_to_column_type(data_type).map_err(|e| invalid_expr(column, e))
+}
+
+/// Columns that are not generated.
+fn input_schema(columns: &[Column]) -> Result<(Vec<usize>, Schema), CubeError> {
+    let mut inputs = Vec::new();
+    let mut fields = Vec::new();
+    for c in columns.iter().filter(|c| c.generated().is_none()) {
+        let data_type = match c.get_column_type() {
+            ColumnType::HyperLogLog(_) | ColumnType::Uuid | ColumnType::Inet | ColumnType::Cidr => {
+                DataType::Binary
+            }
+            ColumnType::Enum(_) => DataType::Int64,
+            t => value_type(t)?,
+        };
+        inputs.push(c.get_index());
+        fields.push(Field::new(c.get_name(), data_type, true));
+    }
+    Ok((inputs, Schema::new(fields)))
+}
+
+/// Arrow type used to compute values of the column type.
+fn value_type(column_type: &ColumnType) -> Result<DataType, CubeError> {
+    Ok(match column_type {
+        ColumnType::String => DataType::Utf8,
+        ColumnType::Int => DataType::Int64,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Nanosecond, None),
+        ColumnType::Boolean => DataType::Boolean,
+        ColumnType::Float | ColumnType::Decimal { .. } => DataType::Float64,
+        ColumnType::Bytes => DataType::Binary,
+        ColumnType::HyperLogLog(_) => {
+            return Err(CubeError::user(
+                "Generated columns can't have HyperLogLog type".to_string(),
+            ))
+        }
+        ColumnType::Uuid | ColumnType::Inet | ColumnType::Cidr => {
+            return Err(CubeError::user(
+                "Generated columns can't have UUID, INET or CIDR type".to_string(),
+            ))
+        }
+        ColumnType::Enum(_) => {
+            return Err(CubeError::user(
+                "Generated columns can't have ENUM type".to_string(),
+            ))
+        }
+    })
+}
+
+fn logical_expr(column: &Column, expr: &str, schema: &Schema) -> Result<Expr, CubeError> {
+    let expr = parse_expr(expr).map_err(|e| invalid_expr(column, e))?;
+    SqlToRel::new(&BuiltinFunctions {})
+        .sql_to_rex(&expr, &schema.clone().to_dfschema()?)
+        .map_err(|e| invalid_expr(column, e))
+}
+
+fn invalid_expr(column: &Column, e: impl ToString) -> CubeError {
+    CubeError::user(format!(
+        "Invalid expression of generated column {}: {}",
+        column.get_name(),
+        e.to_string()
+    ))
+}
+
+fn to_array(rows: &RowsView, column: usize, data_type: &DataType) -> ArrayRef {
+    let values = rows.iter().map(|r| r[column]);
+    match data_type {
+        DataType::Utf8 => Arc::new(StringArray::from(
+            values
+                .map(|v| match v {
+                    TableValueR::String(s) => Some(s),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        DataType::Int64 => Arc::new(Int64Array::from(
+            values
+                .map(|v| match v {
+                    TableValueR::Int(i) => Some(i),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        DataType::Timestamp(_, _) => Arc::new(TimestampNanosecondArray::from_opt_vec(
+            values
+                .map(|v| match v {
+                    TableValueR::Timestamp(t) => Some(t.get_time_stamp()),
+                    _ => None,
+                })
+                .collect(),
+            None,
+        )),
+        DataType::Boolean => Arc::new(BooleanArray::from(
+            values
+                .map(|v| match v {
+                    TableValueR::Boolean(b) => Some(b),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        DataType::Float64 => Arc::new(Float64Array::from(
+            values
+                .map(|v| match v {
+                    TableValueR::Float(f) => Some(f.0),
+                    TableValueR::Decimal(d) => d.parse().ok(),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        DataType::Binary => Arc::new(BinaryArray::from(
+            values
+                .map(|v| match v {
+                    TableValueR::Bytes(b) => Some(b),
+                    _ => None,
+                })
+                .collect::<Vec<_>>(),
+        )),
+        t => panic!("unexpected type of generated column input: {:?}", t),
+    }
+}
+
+/// Only built-in functions of DataFusion are available in expressions.
+struct BuiltinFunctions {}
+
+impl ContextProvider for BuiltinFunctions {
+    fn get_table_provider(&self, _name: TableReference) -> Option<Arc<dyn TableProvider>> {
+        None
+    }
+
+    fn get_function_meta(&self, _name: &str) -> Option<Arc<ScalarUDF>> {
+        None
+    }
+
+    fn get_aggregate_meta(&self, _name: &str) -> Option<Arc<AggregateUDF>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::{Row, TimestampValue};
+
+    #[test]
+    fn compute_generated_columns() {
+        let mut columns = vec![
+            Column::new("ts".to_string(), ColumnType::Timestamp, 0),
+            Column::new("email".to_string(), ColumnType::String, 1),
+            Column::new("day".to_string(), ColumnType::Timestamp, 2),
+            Column::new("lower_email".to_string(), ColumnType::String, 3),
+            Column::new(
+                "amount".to_string(),
+                ColumnType::Decimal {
+                    scale: 2,
+                    precision: 10,
+                },
+                4,
+            ),
+            Column::new("doubled".to_string(), ColumnType::Int, 5),
+        ];
+        assert!(GeneratedColumns::new(&columns).unwrap().is_none());
+        columns[2].set_generated("DATE_TRUNC('day', ts)".to_string(), ColumnType::Timestamp);
+        columns[3].set_generated("lower(email)".to_string(), ColumnType::String);
+        let doubled_type = generated_column_type(&columns[5], "amount * 2", &columns).unwrap();
+        columns[5].set_generated("amount * 2".to_string(), ColumnType::Int);
+        assert_eq!(doubled_type, ColumnType::Float);
+
+        let generated = GeneratedColumns::new(&columns).unwrap().unwrap();
+        let hour = 60 * 60 * 1_000_000_000;
+        let rows = MutRows::from_heap_allocated(
+            6,
+            &[
+                Row::new(vec![
+                    TableValue::Timestamp(TimestampValue::new(25 * hour + 5)),
+                    TableValue::String("Foo@Example.COM".to_string()),
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Decimal("1.25".to_string()),
+                    TableValue::Null,
+                ]),
+                Row::new(vec![
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                ]),
+            ],
+        )
+        .freeze();
+        let rows = generated.compute(rows).unwrap();
+        assert_eq!(
+            rows.view().convert_to_heap_allocated(),
+            vec![
+                Row::new(vec![
+                    TableValue::Timestamp(TimestampValue::new(25 * hour + 5)),
+                    TableValue::String("Foo@Example.COM".to_string()),
+                    TableValue::Timestamp(TimestampValue::new(24 * hour)),
+                    TableValue::String("foo@example.com".to_string()),
+                    TableValue::Decimal("1.25".to_string()),
+                    TableValue::Int(2),
+                ]),
+                Row::new(vec![
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                    TableValue::Null,
+                ]),
+            ]
+        );
+
+        let unknown = generated_column_type(&columns[2], "DATE_TRUNC('day', day)", &columns);
+        assert!(unknown.is_err());
+        let aggregate = {
+            let mut columns = columns.clone();
+            columns[5].set_generated("SUM(amount)".to_string(), ColumnType::Int);
+            GeneratedColumns::new(&columns)
+        };
+        assert!(aggregate.is_err());
+    }
+}
@@ -9,6 +9,7 @@ use async_std::io::SeekFrom;
 use async_std::task::{Context, Poll};
 use async_trait::async_trait;
 use bigdecimal::{BigDecimal, Num};
+use chrono::{DateTime, Utc};
 use futures::future::join_all;
 use futures::{Stream, StreamExt};
 use itertools::Itertools;
@@ -20,20 +21,33 @@ use tokio::task::JoinHandle;
 
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
+use crate::import::constraints::validate_rows;
+use crate::import::database::DatabaseSource;
+use crate::import::decoder::{RowDecoderRegistry, RowStream};
+use crate::import::generated::GeneratedColumns;
 use crate::import::limits::ConcurrencyLimits;
 use crate::metastore::table::Table;
 use crate::metastore::{is_valid_hll, IdRow};
-use crate::metastore::{Column, ColumnType, ImportFormat, MetaStore};
+use crate::metastore::{Column, ColumnDefault, ColumnType, ImportFormat, MetaStore};
+use crate::queryplanner::stored_types::{enum_position, parse_stored_bytes};
 use crate::remotefs::RemoteFs;
+use crate::secrets::{
+    authorize_request, database_location_with_secret, SecretProperties, SecretStore,
+};
 use crate::sql::timestamp_from_string;
 use crate::store::ChunkDataStore;
-use crate::table::data::{MutRows, Rows};
-use crate::table::{Row, TableValue};
+use crate::table::data::{MutRows, Rows, TableValueR};
+use crate::table::{Row, TableValue, TimestampValue};
+use crate::util::job_progress::report_job_progress;
 use crate::util::maybe_owned::MaybeOwnedStr;
 use crate::util::ordfloat::OrdF64;
 use crate::CubeError;
 use tempfile::TempPath;
 
+pub mod constraints;
+pub mod database;
+pub mod decoder;
+pub mod generated;
 pub mod limits;
 
 impl ImportFormat {
@@ -42,112 +56,219 @@ impl ImportFormat {
         file: File,
         location: String,
         columns: Vec<Column>,
-    ) -> Result<Pin<Box<dyn Stream<Item = Result<Option<Row>, CubeError>> + Send>>, CubeError> {
+        decoders: &RowDecoderRegistry,
+    ) -> Result<RowStream, CubeError> {
         match self {
             ImportFormat::CSV => {
-                let lines_stream: Pin<Box<dyn Stream<Item = Result<String, CubeError>> + Send>> =
-                    if location.contains(".csv.gz") {
-                        let reader = BufReader::new(GzipDecoder::new(BufReader::new(file)));
-                        Box::pin(CsvLineStream::new(reader))
-                    } else {
-                        let reader = BufReader::new(file);
-                        Box::pin(CsvLineStream::new(reader))
-                    };
-
-                let mut header_mapping = None;
-                let mut mapping_insert_indices = Vec::with_capacity(columns.len());
-                let rows = lines_stream.map(move |line| -> Result<Option<Row>, CubeError> {
-                    let str = line?;
-
-                    let mut parser = CsvLineParser::new(str.as_str());
-
-                    if header_mapping.is_none() {
-                        let mut mapping = Vec::new();
-                        for _ in 0..columns.len() {
-                            let next_column_buf = parser.next_value()?;
-                            let next_column = next_column_buf.as_ref();
-                            let (i, to_insert) = columns
-                                .iter()
-                                .find_position(|c| c.get_name() == &next_column)
-                                .map(|(i, c)| (i, c.clone()))
-                                .ok_or(CubeError::user(format!(
-                                    "Column '{}' is not found during import in {:?}",
-                                    next_column, columns
-                                )))?;
-                            // This is tricky indices structure: it remembers indices of inserts
-                            // with regards to moving element indices due to these inserts.
-                            // It saves some column resorting trips.
-                            let insert_pos = mapping
-                                .iter()
-                                .find_position(|(col_index, _)| *col_index > i)
-                                .map(|(insert_pos, _)| insert_pos)
-                                .unwrap_or_else(|| mapping.len());
-                            mapping_insert_indices.push(insert_pos);
-                            mapping.push((i, to_insert));
-                            parser.advance()?;
-                        }
-                        header_mapping = Some(mapping);
-                        return Ok(None);
+                let lines_stream: LineStream = if location.contains(".csv.gz") {
+                    let reader = BufReader::new(GzipDecoder::new(BufReader::new(file)));
+                    Box::pin(CsvLineStream::new(reader))
+                } else {
+                    let reader = BufReader::new(file);
+                    Box::pin(CsvLineStream::new(reader))
+                };
+
+                Ok(csv_row_stream(lines_stream, None, columns))
+            }
+            ImportFormat::Custom(name) => {
+                let input: Pin<Box<dyn AsyncBufRead + Send>> = if location.ends_with(".gz") {
+                    Box::pin(BufReader::new(GzipDecoder::new(BufReader::new(file))))
+                } else {
+                    Box::pin(BufReader::new(file))
+                };
+                decoders.get(name)?.decode(input, columns)
+            }
+        }
+    }
+}
+
+type LineStream = Pin<Box<dyn Stream<Item = Result<String, CubeError>> + Send>>;
+
+/// Rows of CSV lines. Without a `header`, the first line is the header with names of the columns
+/// in the file.
+fn csv_row_stream(
+    lines_stream: LineStream,
+    header: Option<Vec<String>>,
+    columns: Vec<Column>,
+) -> RowStream {
+    let mut header = header;
+    let mut header_mapping: Option<HeaderMapping> = None;
+    let now = Utc::now();
+    let rows = lines_stream.map(move |line| -> Result<Option<Row>, CubeError> {
+        let str = line?;
+
+        let mut parser = CsvLineParser::new(str.as_str());
+
+        if header_mapping.is_none() {
+            match header.take() {
+                Some(names) => {
+                    header_mapping = Some(HeaderMapping::new(
+                        names.iter().map(|n| n.as_str()),
+                        &columns,
+                        now,
+                    )?);
+                }
+                None => {
+                    let mut names = Vec::new();
+                    while !parser.is_empty() && names.len() < columns.len() {
+                        names.push(parser.next_value()?.as_ref().to_string());
+                        parser.advance()?;
                     }
+                    header_mapping = Some(HeaderMapping::new(
+                        names.iter().map(|n| n.as_str()),
+                        &columns,
+                        now,
+                    )?);
+                    return Ok(None);
+                }
+            }
+        }
+        let mapping = header_mapping.as_ref().unwrap();
 
-                    let resolved_mapping = header_mapping.as_ref().ok_or(CubeError::user(
-                        "Header is required for CSV import".to_string(),
-                    ))?;
+        let mut row = Vec::with_capacity(columns.len());
 
-                    let mut row = Vec::with_capacity(columns.len());
+        for (i, (_, column)) in mapping.columns.iter().enumerate() {
+            let value_buf = parser.next_value()?;
+            let value = value_buf.as_ref();
 
-                    for (i, (_, column)) in resolved_mapping.iter().enumerate() {
-                        let value_buf = parser.next_value()?;
-                        let value = value_buf.as_ref();
+            if value == "" {
+                row.insert(mapping.insert_indices[i], TableValue::Null);
+            } else {
+                row.insert(mapping.insert_indices[i], parse_value(value_buf, column)?);
+            }
 
-                        if value == "" {
-                            row.insert(mapping_insert_indices[i], TableValue::Null);
-                        } else {
-                            row.insert(
-                                mapping_insert_indices[i],
-                                match column.get_column_type() {
-                                    ColumnType::String => {
-                                        TableValue::String(value_buf.take_string())
-                                    }
-                                    ColumnType::Int => value
-                                        .parse()
-                                        .map(|v| TableValue::Int(v))
-                                        .unwrap_or(TableValue::Null),
-                                    ColumnType::Decimal { .. } => {
-                                        BigDecimal::from_str_radix(value, 10)
-                                            .map(|d| TableValue::Decimal(d.to_string()))
-                                            .unwrap_or(TableValue::Null)
-                                    }
-                                    ColumnType::Bytes => TableValue::Bytes(base64::decode(value)?),
-                                    ColumnType::HyperLogLog(f) => {
-                                        let data = base64::decode(value)?;
-                                        is_valid_hll(&data, *f)?;
-
-                                        TableValue::Bytes(data)
-                                    }
-                                    ColumnType::Timestamp => {
-                                        TableValue::Timestamp(timestamp_from_string(value)?)
-                                    }
-                                    ColumnType::Float => {
-                                        TableValue::Float(OrdF64(value.parse::<f64>()?))
-                                    }
-                                    ColumnType::Boolean => {
-                                        TableValue::Boolean(value.to_lowercase() == "true")
-                                    }
-                                },
-                            );
-                        }
+            parser.advance()?;
+        }
+        for (i, v) in &mapping.omitted {
+            row.insert(*i, v.clone());
+        }
+        Ok(Some(Row::new(row)))
+    });
+    rows.boxed()
+}
 
-                        parser.advance()?;
-                    }
-                    Ok(Some(Row::new(row)))
-                });
-                Ok(rows.boxed())
+/// Where values of a CSV line go in the row of the table.
+struct HeaderMapping {
+    /// Indices and columns of the table, in the order of the header.
+    columns: Vec<(usize, Column)>,
+    /// This is tricky indices structure: it remembers indices of inserts
+    /// with regards to moving element indices due to these inserts.
+    /// It saves some column resorting trips.
+    insert_indices: Vec<usize>,
+    /// Values of columns missing in the header, in the order of columns.
+    omitted: Vec<(usize, TableValue)>,
+}
+
+impl HeaderMapping {
+    fn new<'a>(
+        names: impl Iterator<Item = &'a str>,
+        columns: &[Column],
+        now: DateTime<Utc>,
+    ) -> Result<HeaderMapping, CubeError> {
+        let mut mapping = Vec::new();
+        let mut insert_indices = Vec::with_capacity(columns.len());
+        for name in names {
+            let (i, to_insert) = find_column(columns, name)
+                .map(|(i, c)| (i, c.clone()))
+                .ok_or(CubeError::user(format!(
+                    "Column '{}' is not found during import in {:?}",
+                    name, columns
+                )))?;
+            let insert_pos = mapping
+                .iter()
+                .find_position(|(col_index, _)| *col_index > i)
+                .map(|(insert_pos, _)| insert_pos)
+                .unwrap_or_else(|| mapping.len());
+            insert_indices.push(insert_pos);
+            mapping.push((i, to_insert));
+        }
+        let mut omitted = Vec::new();
+        for (i, c) in columns.iter().enumerate() {
+            if mapping.iter().all(|(col_index, _)| *col_index != i) {
+                omitted.push((i, default_value(c, now)?));
             }
         }
+        Ok(HeaderMapping {
+            columns: mapping,
+            insert_indices,
+            omitted,
+        })
     }
 }
 
+/// Rows of CSV `input` without a header, e.g. loaded by `LOAD DATA INFILE`, see
+/// [crate::sql::load_data]. Values are in the order of `header`. The first `ignore_lines` lines
+/// are skipped.
+pub fn headless_csv_row_stream(
+    input: impl AsyncBufRead + Send + 'static,
+    header: &[String],
+    ignore_lines: usize,
+    columns: Vec<Column>,
+) -> RowStream {
+    let lines_stream = CsvLineStream::new(input).skip(ignore_lines);
+    csv_row_stream(Box::pin(lines_stream), Some(header.to_vec()), columns)
+}
+
+/// Exact match of the name or, as names in upstream files often differ from tables only by case,
+/// the only column with the same name in lower case.
+fn find_column<'a>(columns: &'a [Column], name: &str) -> Option<(usize, &'a Column)> {
+    if let Some(c) = columns.iter().find_position(|c| c.get_name() == name) {
+        return Some(c);
+    }
+    let name = name.to_lowercase();
+    let mut matches = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.get_name().to_lowercase() == name);
+    match (matches.next(), matches.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Value of the column omitted by INSERT or an import source. `now` is the start of the ingestion.
+pub fn default_value(column: &Column, now: DateTime<Utc>) -> Result<TableValue, CubeError> {
+    Ok(match column.default() {
+        None => TableValue::Null,
+        Some(ColumnDefault::Value(v)) => parse_value(MaybeOwnedStr::Borrowed(v), column)?,
+        Some(ColumnDefault::Now) => {
+            TableValue::Timestamp(TimestampValue::new(now.timestamp_nanos()))
+        }
+        Some(ColumnDefault::Today) => TableValue::Timestamp(TimestampValue::new(
+            now.date().and_hms(0, 0, 0).timestamp_nanos(),
+        )),
+    })
+}
+
+/// Parses a text representation of a non-null value, e.g. a CSV cell.
+pub fn parse_value(value_buf: MaybeOwnedStr, column: &Column) -> Result<TableValue, CubeError> {
+    let value = value_buf.as_ref();
+    Ok(match column.get_column_type() {
+        ColumnType::String => TableValue::String(value_buf.take_string()),
+        ColumnType::Int => value
+            .parse()
+            .map(|v| TableValue::Int(v))
+            .unwrap_or(TableValue::Null),
+        ColumnType::Decimal { .. } => BigDecimal::from_str_radix(value, 10)
+            .map(|d| TableValue::Decimal(d.to_string()))
+            .unwrap_or(TableValue::Null),
+        ColumnType::Bytes => TableValue::Bytes(base64::decode(value)?),
+        t @ ColumnType::Uuid | t @ ColumnType::Inet | t @ ColumnType::Cidr => {
+            TableValue::Bytes(parse_stored_bytes(t, value)?)
+        }
+        ColumnType::Enum(values) => TableValue::Int(enum_position(column, values, value)?),
+        ColumnType::HyperLogLog(f) => {
+            let data = base64::decode(value)?;
+            is_valid_hll(&data, *f)?;
+
+            TableValue::Bytes(data)
+        }
+        ColumnType::Timestamp => TableValue::Timestamp(timestamp_from_string(value)?),
+        ColumnType::Float => TableValue::Float(OrdF64(value.parse::<f64>()?)),
+        ColumnType::Boolean => TableValue::Boolean(value.to_lowercase() == "true"),
+    })
+}
+
 struct CsvLineParser<'a> {
     line: &'a str,
     remaining: &'a str,
@@ -209,6 +330,10 @@ impl<'a> CsvLineParser<'a> {
         )
     }
 
+    fn is_empty(&self) -> bool {
+        self.remaining.is_empty()
+    }
+
     fn advance(&mut self) -> Result<(), CubeError> {
         if let Some(b',') = self.remaining.as_bytes().iter().nth(0) {
             self.remaining = self.remaining[1..].as_ref()
@@ -326,6 +451,8 @@ pub struct ImportServiceImpl {
     remote_fs: Arc<dyn RemoteFs>,
     config_obj: Arc<dyn ConfigObj>,
     limits: Arc<ConcurrencyLimits>,
+    decoders: Arc<RowDecoderRegistry>,
+    secrets: Arc<SecretStore>,
 }
 
 crate::di_service!(ImportServiceImpl, [ImportService]);
@@ -337,6 +464,8 @@ impl ImportServiceImpl {
         remote_fs: Arc<dyn RemoteFs>,
         config_obj: Arc<dyn ConfigObj>,
         limits: Arc<ConcurrencyLimits>,
+        decoders: Arc<RowDecoderRegistry>,
+        secrets: Arc<SecretStore>,
     ) -> Arc<ImportServiceImpl> {
         Arc::new(ImportServiceImpl {
             meta_store,
@@ -344,6 +473,8 @@ impl ImportServiceImpl {
             remote_fs,
             config_obj,
             limits,
+            decoders,
+            secrets,
         })
     }
 
@@ -352,6 +483,7 @@ impl ImportServiceImpl {
         location: &str,
         table_id: u64,
         temp_dir: &Path,
+        secret: Option<&SecretProperties>,
     ) -> Result<(File, Option<TempPath>), CubeError> {
         if location.starts_with("http") {
             let (file, path) = tempfile::Builder::new()
@@ -359,7 +491,11 @@ impl ImportServiceImpl {
                 .tempfile_in(temp_dir)?
                 .into_parts();
             let mut file = File::from_std(file);
-            let mut stream = reqwest::get(location).await?.bytes_stream();
+            let mut request = reqwest::Client::new().get(location);
+            if let Some(secret) = secret {
+                request = authorize_request(request, secret);
+            }
+            let mut stream = request.send().await?.bytes_stream();
             while let Some(bytes) = stream.next().await {
                 file.write_all(bytes?.as_ref()).await?;
             }
@@ -381,44 +517,63 @@ impl ImportServiceImpl {
     async fn do_import(
         &self,
         table: &IdRow<Table>,
-        format: ImportFormat,
+        format: &ImportFormat,
         location: &str,
     ) -> Result<(), CubeError> {
         let temp_dir = self.config_obj.data_dir().join("tmp");
         tokio::fs::create_dir_all(temp_dir.clone()).await?;
 
-        let (file, tmp_path) = self
-            .resolve_location(location.clone(), table.get_id(), &temp_dir)
-            .await?;
-        let mut row_stream = format
-            .row_stream(
-                file,
-                location.to_string(),
-                table.get_row().get_columns().clone(),
-            )
-            .await?;
+        // Values of generated columns are not present in sources, they are set by the ingestion.
+        let source_columns = table
+            .get_row()
+            .get_columns()
+            .iter()
+            .filter(|c| c.generated().is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        let columns = source_columns.clone();
+        let secret = match table.get_row().source_secret() {
+            Some(name) => Some(self.secrets.resolve(name).await?),
+            None => None,
+        };
+        let database_location = match &secret {
+            Some(secret) if DatabaseSource::parse(location, 1)?.is_some() => {
+                Some(database_location_with_secret(location, secret)?)
+            }
+            _ => None,
+        };
+        let (mut row_stream, tmp_path) = match DatabaseSource::parse(
+            database_location.as_deref().unwrap_or(location),
+            self.config_obj.wal_split_threshold() as usize,
+        )? {
+            Some(source) => (source.row_stream(columns)?, None),
+            None => {
+                let (file, tmp_path) = self
+                    .resolve_location(location.clone(), table.get_id(), &temp_dir, secret.as_ref())
+                    .await?;
+                let row_stream = format
+                    .row_stream(file, location.to_string(), columns, &self.decoders)
+                    .await?;
+                (row_stream, tmp_path)
+            }
+        };
 
         let mut ingestion = Ingestion::new(
             self.meta_store.clone(),
             self.chunk_store.clone(),
             self.limits.clone(),
             table.clone(),
-        );
-        let mut rows = MutRows::new(table.get_row().get_columns().len());
-        while let Some(row) = row_stream.next().await {
-            if let Some(row) = row? {
-                rows.add_row_heap_allocated(&row);
-                if rows.num_rows() >= self.config_obj.wal_split_threshold() as usize {
-                    let mut to_add = MutRows::new(table.get_row().get_columns().len());
-                    mem::swap(&mut rows, &mut to_add);
-                    ingestion.queue_data_frame(to_add.freeze()).await?;
-                }
-            }
-        }
+        )?;
+        ingestion
+            .queue_row_stream(
+                &source_columns,
+                row_stream,
+                self.config_obj.wal_split_threshold() as usize,
+            )
+            .await?;
 
         mem::drop(tmp_path);
 
-        ingestion.queue_data_frame(rows.freeze()).await?;
         ingestion.wait_completion().await
     }
 }
@@ -443,7 +598,7 @@ impl ImportService for ImportServiceImpl {
                 table
             )))?;
         for location in locations.into_iter() {
-            self.do_import(&table, *format, &location).await?;
+            self.do_import(&table, format, &location).await?;
         }
 
         Ok(())
@@ -473,16 +628,47 @@ impl ImportService for ImportServiceImpl {
                 table, location
             )));
         }
-        self.do_import(&table, *format, location).await
+        self.do_import(&table, format, location).await
     }
 }
 
 /// Handles row-based data ingestion, e.g. on CSV import and SQL insert.
+/// Truncates timestamps to the precision of their columns. Otherwise they are truncated to
+/// microseconds when stored.
+pub fn truncate_timestamps(columns: &[Column], rows: Rows) -> Rows {
+    let units = columns
+        .iter()
+        .filter_map(|c| {
+            let unit = 10i64.pow(9 - c.timestamp_precision()? as u32);
+            (1000 < unit).then(|| (c.get_index(), unit))
+        })
+        .collect_vec();
+    if units.is_empty() {
+        return rows;
+    }
+    let mut result = MutRows::with_capacity(rows.num_columns(), rows.num_rows());
+    for r in rows.view().iter() {
+        let mut row = result.add_row_copy(r);
+        for (i, unit) in &units {
+            let nanos = match row.get(*i) {
+                TableValueR::Timestamp(t) => t.get_time_stamp(),
+                _ => continue,
+            };
+            let truncated = TimestampValue::new(nanos - nanos.rem_euclid(*unit));
+            row.set_interned(*i, TableValueR::Timestamp(truncated));
+        }
+    }
+    result.freeze()
+}
+
 pub struct Ingestion {
     meta_store: Arc<dyn MetaStore>,
     chunk_store: Arc<dyn ChunkDataStore>,
     limits: Arc<ConcurrencyLimits>,
     table: IdRow<Table>,
+    /// Rows queued so far, to report the positions of rows that violate constraints.
+    rows_queued: usize,
+    generated: Option<GeneratedColumns>,
 
     partition_jobs: Vec<JoinHandle<Result<(), CubeError>>>,
 }
@@ -493,17 +679,28 @@ impl Ingestion {
         chunk_store: Arc<dyn ChunkDataStore>,
         limits: Arc<ConcurrencyLimits>,
         table: IdRow<Table>,
-    ) -> Ingestion {
-        Ingestion {
+    ) -> Result<Ingestion, CubeError> {
+        let generated = GeneratedColumns::new(table.get_row().get_columns())?;
+        Ok(Ingestion {
             meta_store,
             chunk_store,
             limits,
             table,
+            rows_queued: 0,
+            generated,
             partition_jobs: Vec::new(),
-        }
+        })
     }
 
     pub async fn queue_data_frame(&mut self, rows: Rows) -> Result<(), CubeError> {
+        let rows = match &self.generated {
+            Some(generated) => generated.compute(rows)?,
+            None => rows,
+        };
+        let rows = truncate_timestamps(self.table.get_row().get_columns(), rows);
+        validate_rows(self.table.get_row().get_columns(), &rows, self.rows_queued)?;
+        self.rows_queued += rows.num_rows();
+
         let active_data_frame = self.limits.acquire_data_frame().await?;
 
         let meta_store = self.meta_store.clone();
@@ -527,6 +724,36 @@ impl Ingestion {
         Ok(())
     }
 
+    /// Queues rows of `row_stream` in data frames of `rows_per_chunk` rows. Values of rows are in
+    /// the order of `source_columns`. Returns the number of queued rows.
+    pub async fn queue_row_stream(
+        &mut self,
+        source_columns: &[Column],
+        mut row_stream: RowStream,
+        rows_per_chunk: usize,
+    ) -> Result<u64, CubeError> {
+        let num_columns = self.table.get_row().get_columns().len();
+        let mut rows = MutRows::new(num_columns);
+        let mut num_rows = 0;
+        while let Some(row) = row_stream.next().await {
+            if let Some(row) = row? {
+                let mut inserted = rows.add_row();
+                for (c, v) in source_columns.iter().zip(row.values()) {
+                    inserted.set_interned(c.get_index(), TableValueR::from_heap_allocated(v));
+                }
+                num_rows += 1;
+                if rows.num_rows() >= rows_per_chunk {
+                    let mut to_add = MutRows::new(num_columns);
+                    mem::swap(&mut rows, &mut to_add);
+                    self.queue_data_frame(to_add.freeze()).await?;
+                    report_job_progress(num_rows, None);
+                }
+            }
+        }
+        self.queue_data_frame(rows.freeze()).await?;
+        Ok(num_rows)
+    }
+
     pub async fn wait_completion(self) -> Result<(), CubeError> {
         for j in self.partition_jobs {
             j.await??;
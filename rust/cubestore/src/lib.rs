@@ -18,6 +18,8 @@
 extern crate lazy_static;
 
 use crate::metastore::TableId;
+use crate::queryplanner::operator_limits::LimitExceeded;
+use crate::queryplanner::unsupported_features::UnsupportedFeature;
 use crate::remotefs::queue::RemoteFsOpResult;
 use arrow::error::ArrowError;
 use core::fmt;
@@ -36,6 +38,8 @@ use tokio::sync::broadcast;
 use tokio::sync::mpsc::error::SendError;
 use tokio::time::error::Elapsed;
 
+pub mod auth;
+pub mod bench;
 pub mod cluster;
 pub mod codegen;
 pub mod config;
@@ -45,7 +49,9 @@ pub mod metastore;
 pub mod mysql;
 pub mod queryplanner;
 pub mod remotefs;
+pub mod replay;
 pub mod scheduler;
+pub mod secrets;
 pub mod sql;
 pub mod store;
 pub mod sys;
@@ -57,21 +63,42 @@ pub mod util;
 pub struct CubeError {
     pub message: String,
     pub cause: CubeErrorCauseType,
+    /// Machine-readable details, sent to clients along with the message.
+    pub details: Option<CubeErrorDetails>,
 }
 
 impl std::error::Error for CubeError {}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Tells clients whether to retry a failed request. The cause is kept when errors are passed
+/// between nodes and through DataFusion, MySQL clients get
+/// `ER_QUERY_INTERRUPTED` for [CubeErrorCauseType::Unavailable] and HTTP clients get the cause
+/// before the message.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 pub enum CubeErrorCauseType {
+    /// The request is invalid and fails the same way when retried.
     User,
+    /// Unexpected failure, most likely a bug. Retries are not expected to help.
     Internal,
+    /// Temporary failure, the same request is expected to succeed when retried later.
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CubeErrorDetails {
+    LimitExceeded(LimitExceeded),
+    UnsupportedFeatures(Vec<UnsupportedFeature>),
 }
 
+/// DataFusion and Arrow pass errors of operators as strings. Details of errors are appended to
+/// these strings as JSON after this marker, so they survive the way back to [CubeError].
+const ENGINE_ERROR_MARKER: &str = "\nCube Store error: ";
+
 impl CubeError {
     pub fn user(message: String) -> CubeError {
         CubeError {
             message,
             cause: CubeErrorCauseType::User,
+            details: None,
         }
     }
 
@@ -79,6 +106,15 @@ impl CubeError {
         CubeError {
             message,
             cause: CubeErrorCauseType::Internal,
+            details: None,
+        }
+    }
+
+    pub fn unavailable(message: String) -> CubeError {
+        CubeError {
+            message,
+            cause: CubeErrorCauseType::Unavailable,
+            details: None,
         }
     }
 
@@ -86,6 +122,7 @@ impl CubeError {
         CubeError {
             message: format!("{}\n{}", error, Backtrace::capture()),
             cause: CubeErrorCauseType::Internal,
+            details: None,
         }
     }
 
@@ -93,8 +130,53 @@ impl CubeError {
         CubeError {
             message: format!("{:?}\n{}", error, Backtrace::capture()),
             cause: CubeErrorCauseType::Internal,
+            details: None,
+        }
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.cause == CubeErrorCauseType::Unavailable
+    }
+
+    pub fn with_details(self, details: CubeErrorDetails) -> CubeError {
+        CubeError {
+            details: Some(details),
+            ..self
         }
     }
+
+    /// Message for clients, details follow as JSON on the last line.
+    pub fn client_message(&self) -> String {
+        match &self.details {
+            Some(details) => format!(
+                "{}\n{}",
+                self.message,
+                serde_json::to_string(details).unwrap()
+            ),
+            None => self.message.clone(),
+        }
+    }
+
+    /// String to pass the error through DataFusion and Arrow, see [ENGINE_ERROR_MARKER]. Internal
+    /// errors without details turn out internal on the way back anyway and are passed as is.
+    pub fn to_engine_message(&self) -> String {
+        match (&self.details, self.cause) {
+            (None, CubeErrorCauseType::Internal) => self.to_string(),
+            _ => format!(
+                "{}{}{}",
+                self,
+                ENGINE_ERROR_MARKER,
+                serde_json::to_string(self).unwrap()
+            ),
+        }
+    }
+
+    /// Restores the error with details from an error of DataFusion or Arrow.
+    fn from_engine_message(message: &str) -> Option<CubeError> {
+        let start = message.find(ENGINE_ERROR_MARKER)? + ENGINE_ERROR_MARKER.len();
+        let json = message[start..].lines().next()?;
+        serde_json::from_str(json).ok()
+    }
 }
 
 impl fmt::Display for CubeError {
@@ -117,13 +199,23 @@ impl From<rocksdb::Error> for CubeError {
 
 impl From<std::io::Error> for CubeError {
     fn from(v: std::io::Error) -> Self {
-        CubeError::internal(format!("{:?}\n{}", v, Backtrace::capture()))
+        match v.kind() {
+            std::io::ErrorKind::ConnectionRefused
+            | std::io::ErrorKind::ConnectionReset
+            | std::io::ErrorKind::ConnectionAborted
+            | std::io::ErrorKind::NotConnected
+            | std::io::ErrorKind::BrokenPipe
+            | std::io::ErrorKind::TimedOut => CubeError::unavailable(v.to_string()),
+            _ => CubeError::internal(format!("{:?}\n{}", v, Backtrace::capture())),
+        }
     }
 }
 
 impl From<ParserError> for CubeError {
     fn from(v: ParserError) -> Self {
-        CubeError::internal(format!("{:?}", v))
+        match v {
+            ParserError::TokenizerError(m) | ParserError::ParserError(m) => CubeError::user(m),
+        }
     }
 }
 
@@ -160,6 +252,9 @@ impl From<std::time::SystemTimeError> for CubeError {
     }
 }
 
+/// Timeouts of queries are not retryable, the same query times out again. Transient timeouts, e.g.
+/// of connections to other nodes, are marked as [CubeErrorCauseType::Unavailable] where they
+/// happen.
 impl From<Elapsed> for CubeError {
     fn from(v: Elapsed) -> Self {
         CubeError::internal(v.to_string())
@@ -168,19 +263,32 @@ impl From<Elapsed> for CubeError {
 
 impl From<datafusion::error::DataFusionError> for CubeError {
     fn from(v: datafusion::error::DataFusionError) -> Self {
-        CubeError::from_error(v)
+        if let datafusion::error::DataFusionError::ArrowError(ArrowError::ExternalError(e)) = &v {
+            if let Some(e) = e.downcast_ref::<CubeError>() {
+                return e.clone();
+            }
+        }
+        CubeError::from_engine_message(&v.to_string()).unwrap_or_else(|| CubeError::from_error(v))
     }
 }
 
 impl From<CubeError> for datafusion::error::DataFusionError {
     fn from(v: CubeError) -> Self {
-        datafusion::error::DataFusionError::Execution(v.to_string())
+        datafusion::error::DataFusionError::Execution(v.to_engine_message())
     }
 }
 
 impl From<arrow::error::ArrowError> for CubeError {
     fn from(v: ArrowError) -> Self {
-        CubeError::internal(v.to_string())
+        let v = match v {
+            ArrowError::ExternalError(e) => match e.downcast::<CubeError>() {
+                Ok(e) => return *e,
+                Err(e) => ArrowError::ExternalError(e),
+            },
+            v => v,
+        };
+        let message = v.to_string();
+        CubeError::from_engine_message(&message).unwrap_or_else(|| CubeError::internal(message))
     }
 }
 
@@ -260,7 +368,13 @@ impl From<tokio::sync::oneshot::error::RecvError> for CubeError {
 #[cfg(not(target_os = "windows"))]
 impl From<ipc_channel::ipc::IpcError> for CubeError {
     fn from(v: ipc_channel::ipc::IpcError) -> Self {
-        CubeError::from_debug_error(v)
+        match v {
+            // The worker process exited, it is restarted for the next request.
+            ipc_channel::ipc::IpcError::Disconnected => {
+                CubeError::unavailable("Select worker process exited".to_string())
+            }
+            v => CubeError::from_debug_error(v),
+        }
     }
 }
 
@@ -305,7 +419,14 @@ impl From<ParseIntError> for CubeError {
 
 impl From<reqwest::Error> for CubeError {
     fn from(v: reqwest::Error) -> Self {
-        CubeError::from_error(v)
+        let transient_status = v
+            .status()
+            .map_or(false, |s| is_transient_http_status(s.as_u16()));
+        if v.is_timeout() || v.is_connect() || transient_status {
+            CubeError::unavailable(v.to_string())
+        } else {
+            CubeError::from_error(v)
+        }
     }
 }
 
@@ -373,6 +494,12 @@ impl From<base64::DecodeError> for CubeError {
     }
 }
 
+impl From<csv::Error> for CubeError {
+    fn from(v: csv::Error) -> Self {
+        return CubeError::from_error(v);
+    }
+}
+
 impl From<tempfile::PathPersistError> for CubeError {
     fn from(v: tempfile::PathPersistError) -> Self {
         return CubeError::from_error(v);
@@ -385,8 +512,61 @@ impl From<tokio::sync::AcquireError> for CubeError {
     }
 }
 
+/// Statuses of HTTP responses that are expected to succeed when retried, e.g. S3 503 Slow Down.
+pub fn is_transient_http_status(status: u16) -> bool {
+    status == 408 || status == 429 || 500 <= status
+}
+
 impl Into<ArrowError> for CubeError {
     fn into(self) -> ArrowError {
         ArrowError::ExternalError(Box::new(self))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::error::DataFusionError;
+
+    #[test]
+    fn cause_survives_engine() {
+        for e in vec![
+            CubeError::user("Unknown column".to_string()),
+            CubeError::unavailable("Worker restarted".to_string()),
+        ] {
+            let through_datafusion = CubeError::from(DataFusionError::from(e.clone()));
+            assert_eq!(through_datafusion.cause, e.cause);
+            assert_eq!(through_datafusion.message, e.message);
+            let arrow: ArrowError = e.clone().into();
+            let through_arrow = CubeError::from(DataFusionError::ArrowError(arrow));
+            assert_eq!(through_arrow.cause, e.cause);
+        }
+        let e = CubeError::from(DataFusionError::from(CubeError::internal(
+            "bug".to_string(),
+        )));
+        assert_eq!(e.cause, CubeErrorCauseType::Internal);
+    }
+
+    #[tokio::test]
+    async fn query_timeouts_are_not_retryable() {
+        let elapsed = tokio::time::timeout(
+            std::time::Duration::from_millis(1),
+            futures::future::pending::<()>(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!CubeError::from(elapsed).is_retryable());
+    }
+
+    #[test]
+    fn retryable() {
+        let refused = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        assert!(CubeError::from(refused).is_retryable());
+        let not_found = std::io::Error::new(std::io::ErrorKind::NotFound, "not found");
+        assert!(!CubeError::from(not_found).is_retryable());
+        assert!(!CubeError::user("Syntax error".to_string()).is_retryable());
+        assert!(is_transient_http_status(503));
+        assert!(is_transient_http_status(429));
+        assert!(!is_transient_http_status(403));
+    }
+}
@@ -0,0 +1,145 @@
+//! Changes of the metastore that make cached query results stale. They are published to external
+//! caches, e.g. pre-aggregation caches of the Cube.js server, by the `/events` endpoint of the HTTP
+//! server as server-sent events, so caches can be invalidated right away instead of polling.
+//! Only the node that owns the metastore, i.e. the router, publishes changes.
+use crate::metastore::{MetaStoreEvent, TableId};
+use serde::Serialize;
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum MetaStoreChange {
+    TableCreated {
+        table_id: u64,
+    },
+    TableDropped {
+        table_id: u64,
+        schema_id: u64,
+        table_name: String,
+    },
+    /// New data of the partition became visible to queries.
+    ChunkActivated {
+        chunk_id: u64,
+        partition_id: u64,
+    },
+    /// Partitions are activated when they replace their parent after compaction.
+    PartitionActivated {
+        partition_id: u64,
+        index_id: u64,
+    },
+    PartitionDropped {
+        partition_id: u64,
+        index_id: u64,
+    },
+    /// The subscriber did not keep up and `skipped` events were lost. Every cache should be
+    /// invalidated.
+    Lagged {
+        skipped: u64,
+    },
+}
+
+impl MetaStoreChange {
+    pub fn from_event(event: &MetaStoreEvent) -> Option<MetaStoreChange> {
+        match event {
+            MetaStoreEvent::Insert(TableId::Tables, table_id) => {
+                Some(MetaStoreChange::TableCreated {
+                    table_id: *table_id,
+                })
+            }
+            MetaStoreEvent::DeleteTable(t) => Some(MetaStoreChange::TableDropped {
+                table_id: t.get_id(),
+                schema_id: t.get_row().get_schema_id(),
+                table_name: t.get_row().get_table_name().clone(),
+            }),
+            MetaStoreEvent::UpdateChunk(old, new)
+                if !old.get_row().uploaded() && new.get_row().uploaded() =>
+            {
+                Some(MetaStoreChange::ChunkActivated {
+                    chunk_id: new.get_id(),
+                    partition_id: new.get_row().get_partition_id(),
+                })
+            }
+            MetaStoreEvent::UpdatePartition(old, new)
+                if !old.get_row().is_active() && new.get_row().is_active() =>
+            {
+                Some(MetaStoreChange::PartitionActivated {
+                    partition_id: new.get_id(),
+                    index_id: new.get_row().get_index_id(),
+                })
+            }
+            MetaStoreEvent::DeletePartition(p) => Some(MetaStoreChange::PartitionDropped {
+                partition_id: p.get_id(),
+                index_id: p.get_row().get_index_id(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Name of the server-sent event.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MetaStoreChange::TableCreated { .. } => "tableCreated",
+            MetaStoreChange::TableDropped { .. } => "tableDropped",
+            MetaStoreChange::ChunkActivated { .. } => "chunkActivated",
+            MetaStoreChange::PartitionActivated { .. } => "partitionActivated",
+            MetaStoreChange::PartitionDropped { .. } => "partitionDropped",
+            MetaStoreChange::Lagged { .. } => "lagged",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::chunks::Chunk;
+    use crate::metastore::partition::Partition;
+    use crate::metastore::IdRow;
+
+    #[test]
+    fn changes_from_events() {
+        let chunk = IdRow::new(3, Chunk::new(2, 10, None));
+        let uploaded = IdRow::new(3, chunk.get_row().set_uploaded(true));
+        assert_eq!(
+            MetaStoreChange::from_event(&MetaStoreEvent::UpdateChunk(
+                chunk.clone(),
+                uploaded.clone()
+            )),
+            Some(MetaStoreChange::ChunkActivated {
+                chunk_id: 3,
+                partition_id: 2
+            })
+        );
+        assert_eq!(
+            MetaStoreChange::from_event(&MetaStoreEvent::UpdateChunk(uploaded.clone(), uploaded)),
+            None
+        );
+
+        let partition = IdRow::new(2, Partition::new(1, None, None).to_active(false));
+        let active = IdRow::new(2, partition.get_row().to_active(true));
+        assert_eq!(
+            MetaStoreChange::from_event(&MetaStoreEvent::UpdatePartition(
+                partition,
+                active.clone()
+            )),
+            Some(MetaStoreChange::PartitionActivated {
+                partition_id: 2,
+                index_id: 1
+            })
+        );
+        let dropped =
+            MetaStoreChange::from_event(&MetaStoreEvent::DeletePartition(active)).unwrap();
+        assert_eq!(dropped.name(), "partitionDropped");
+        assert_eq!(
+            serde_json::to_string(&dropped).unwrap(),
+            r#"{"event":"partitionDropped","partition_id":2,"index_id":1}"#
+        );
+
+        assert_eq!(
+            MetaStoreChange::from_event(&MetaStoreEvent::Insert(TableId::Tables, 5)),
+            Some(MetaStoreChange::TableCreated { table_id: 5 })
+        );
+        assert_eq!(
+            MetaStoreChange::from_event(&MetaStoreEvent::Insert(TableId::Chunks, 5)),
+            None
+        );
+    }
+}
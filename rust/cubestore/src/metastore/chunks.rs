@@ -1,6 +1,9 @@
-use super::{BaseRocksSecondaryIndex, Chunk, IndexId, RocksSecondaryIndex, RocksTable, TableId};
+use super::{
+    BaseRocksSecondaryIndex, Chunk, ColumnBounds, IndexId, RocksSecondaryIndex, RocksTable, TableId,
+};
 use crate::base_rocks_secondary_index;
 use crate::metastore::{IdRow, MetaStoreEvent};
+use crate::remotefs::storage::storage_file_name;
 use crate::rocks_table_impl;
 use byteorder::{BigEndian, WriteBytesExt};
 use rocksdb::DB;
@@ -8,13 +11,15 @@ use serde::{Deserialize, Deserializer};
 use std::io::Cursor;
 
 impl Chunk {
-    pub fn new(partition_id: u64, row_count: usize) -> Chunk {
+    pub fn new(partition_id: u64, row_count: usize, column_bounds: Option<ColumnBounds>) -> Chunk {
         Chunk {
             partition_id,
             row_count: row_count as u64,
             uploaded: false,
             active: false,
             last_used: None,
+            storage: None,
+            column_bounds,
         }
     }
 
@@ -23,7 +28,7 @@ impl Chunk {
     }
 
     pub fn get_full_name(&self, chunk_id: u64) -> String {
-        chunk_file_name(chunk_id)
+        storage_file_name(&self.storage, chunk_file_name(chunk_id))
     }
 
     pub fn get_partition_id(&self) -> u64 {
@@ -31,23 +36,26 @@ impl Chunk {
     }
 
     pub fn set_uploaded(&self, uploaded: bool) -> Chunk {
-        Chunk {
-            partition_id: self.partition_id,
-            row_count: self.row_count,
-            uploaded,
-            active: uploaded,
-            last_used: self.last_used.clone(),
-        }
+        let mut c = self.clone();
+        c.uploaded = uploaded;
+        c.active = uploaded;
+        c
     }
 
     pub fn deactivate(&self) -> Chunk {
-        Chunk {
-            partition_id: self.partition_id,
-            row_count: self.row_count,
-            uploaded: self.uploaded,
-            active: false,
-            last_used: self.last_used.clone(),
-        }
+        let mut c = self.clone();
+        c.active = false;
+        c
+    }
+
+    pub fn set_storage(&self, storage: Option<String>) -> Chunk {
+        let mut c = self.clone();
+        c.storage = storage;
+        c
+    }
+
+    pub fn column_bounds(&self) -> &Option<ColumnBounds> {
+        &self.column_bounds
     }
 
     pub fn uploaded(&self) -> bool {
@@ -0,0 +1,346 @@
+//! Recent changes of the metastore, queryable as `system.metastore_log`, to find out when a table
+//! was dropped or why partitions of an index keep changing. The router records every inserted,
+//! updated and deleted row of the metastore in memory, so the log starts empty after a restart.
+//! Entries are dropped after [ConfigObj::metastore_log_retention_secs] or when there are more than
+//! [ConfigObj::metastore_log_max_entries] of them.
+use crate::config::ConfigObj;
+use crate::metastore::{MetaStore, MetaStoreEvent, TableId};
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::warn;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::broadcast::Receiver;
+
+pub struct MetaStoreHistory {
+    meta_store: Arc<dyn MetaStore>,
+    retention: ChronoDuration,
+    max_entries: usize,
+    /// Oldest first.
+    entries: Mutex<VecDeque<MetaStoreLogEntry>>,
+    event_receiver: tokio::sync::Mutex<Receiver<MetaStoreEvent>>,
+    event_loop: WorkerLoop,
+}
+
+crate::di_service!(MetaStoreHistory, []);
+
+impl std::fmt::Debug for MetaStoreHistory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MetaStoreHistory")
+            .field("retention", &self.retention)
+            .field("max_entries", &self.max_entries)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetaStoreLogEntry {
+    pub time: DateTime<Utc>,
+    /// `insert`, `update` or `delete`.
+    pub operation: &'static str,
+    /// Kind of the changed row, e.g. `table` or `partition`.
+    pub entity: &'static str,
+    pub id: u64,
+    /// Name of schemas, tables, indexes, linked servers, secrets and pre-aggregations.
+    pub name: Option<String>,
+    /// Id of the row this one belongs to: the schema of a table, the table of an index, the index
+    /// of a partition or the partition of a chunk.
+    pub parent_id: Option<u64>,
+}
+
+impl MetaStoreHistory {
+    pub fn new(
+        meta_store: Arc<dyn MetaStore>,
+        config: &dyn ConfigObj,
+        event_receiver: Receiver<MetaStoreEvent>,
+    ) -> Arc<MetaStoreHistory> {
+        Arc::new(MetaStoreHistory {
+            meta_store,
+            retention: ChronoDuration::seconds(config.metastore_log_retention_secs() as i64),
+            max_entries: config.metastore_log_max_entries(),
+            entries: Mutex::new(VecDeque::new()),
+            event_receiver: tokio::sync::Mutex::new(event_receiver),
+            event_loop: WorkerLoop::new("MetaStoreHistory"),
+        })
+    }
+
+    /// Entries within the retention period, oldest first.
+    pub fn all(&self) -> Vec<MetaStoreLogEntry> {
+        let mut entries = self.entries.lock().unwrap();
+        self.expire(&mut entries, Utc::now());
+        entries.iter().cloned().collect()
+    }
+
+    pub async fn wait_processing_loop(self: Arc<Self>) {
+        if self.max_entries == 0 {
+            return;
+        }
+        self.event_loop
+            .process(
+                self.clone(),
+                async move |h| match h.event_receiver.lock().await.recv().await {
+                    Err(RecvError::Closed) => futures::future::pending().await,
+                    res => Ok(res),
+                },
+                async move |h, res| match res {
+                    Ok(event) => h.record(event).await,
+                    Err(e) => {
+                        warn!("Metastore log misses changes: {}", e);
+                        Ok(())
+                    }
+                },
+            )
+            .await;
+    }
+
+    pub fn stop_processing_loop(&self) {
+        self.event_loop.stop();
+    }
+
+    async fn record(&self, event: MetaStoreEvent) -> Result<(), CubeError> {
+        let mut entry = match MetaStoreLogEntry::from_event(&event, Utc::now()) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        // Inserts carry no row. Only schemas and tables are looked up, other rows are inserted
+        // too often to afford it. The row may be gone already, then it is logged without a name.
+        if entry.operation == "insert" {
+            match entry.entity {
+                "schema" => {
+                    if let Ok(s) = self.meta_store.get_schema_by_id(entry.id).await {
+                        entry.name = Some(s.get_row().get_name().clone());
+                    }
+                }
+                "table" => {
+                    if let Ok(t) = self.meta_store.get_table_by_id(entry.id).await {
+                        entry.name = Some(t.get_row().get_table_name().clone());
+                        entry.parent_id = Some(t.get_row().get_schema_id());
+                    }
+                }
+                _ => {}
+            }
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.push_back(entry);
+        self.expire(&mut entries, Utc::now());
+        Ok(())
+    }
+
+    fn expire(&self, entries: &mut VecDeque<MetaStoreLogEntry>, now: DateTime<Utc>) {
+        while entries.len() > self.max_entries
+            || entries
+                .front()
+                .map(|e| now - e.time > self.retention)
+                .unwrap_or(false)
+        {
+            entries.pop_front();
+        }
+    }
+}
+
+impl MetaStoreLogEntry {
+    /// Every write of a row emits a generic event and, for updates and deletes, one with the row.
+    /// Only one of them is logged.
+    pub fn from_event(event: &MetaStoreEvent, time: DateTime<Utc>) -> Option<MetaStoreLogEntry> {
+        let entry = |operation, entity, id, name: Option<&String>, parent_id| {
+            Some(MetaStoreLogEntry {
+                time,
+                operation,
+                entity,
+                id,
+                name: name.cloned(),
+                parent_id,
+            })
+        };
+        match event {
+            MetaStoreEvent::Insert(t, id) => entry("insert", entity_name(*t), *id, None, None),
+            MetaStoreEvent::Update(..) | MetaStoreEvent::Delete(..) => None,
+
+            MetaStoreEvent::UpdateChunk(_, r) => entry(
+                "update",
+                "chunk",
+                r.get_id(),
+                None,
+                Some(r.get_row().get_partition_id()),
+            ),
+            MetaStoreEvent::UpdateIndex(_, r) => entry(
+                "update",
+                "index",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                Some(r.get_row().table_id()),
+            ),
+            MetaStoreEvent::UpdateJob(_, r) => entry("update", "job", r.get_id(), None, None),
+            MetaStoreEvent::UpdateLinkedServer(_, r) => entry(
+                "update",
+                "linked_server",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::UpdatePartition(_, r) => entry(
+                "update",
+                "partition",
+                r.get_id(),
+                None,
+                Some(r.get_row().get_index_id()),
+            ),
+            MetaStoreEvent::UpdatePreAggregation(_, r) => entry(
+                "update",
+                "pre_aggregation",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::UpdateSchema(_, r) => entry(
+                "update",
+                "schema",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::UpdateSecret(_, r) => entry(
+                "update",
+                "secret",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::UpdateTable(_, r) => entry(
+                "update",
+                "table",
+                r.get_id(),
+                Some(r.get_row().get_table_name()),
+                Some(r.get_row().get_schema_id()),
+            ),
+            MetaStoreEvent::UpdateWAL(_, r) => entry(
+                "update",
+                "wal",
+                r.get_id(),
+                None,
+                Some(r.get_row().table_id()),
+            ),
+
+            MetaStoreEvent::DeleteChunk(r) => entry(
+                "delete",
+                "chunk",
+                r.get_id(),
+                None,
+                Some(r.get_row().get_partition_id()),
+            ),
+            MetaStoreEvent::DeleteIndex(r) => entry(
+                "delete",
+                "index",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                Some(r.get_row().table_id()),
+            ),
+            MetaStoreEvent::DeleteJob(r) => entry("delete", "job", r.get_id(), None, None),
+            MetaStoreEvent::DeleteLinkedServer(r) => entry(
+                "delete",
+                "linked_server",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::DeletePartition(r) => entry(
+                "delete",
+                "partition",
+                r.get_id(),
+                None,
+                Some(r.get_row().get_index_id()),
+            ),
+            MetaStoreEvent::DeletePreAggregation(r) => entry(
+                "delete",
+                "pre_aggregation",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::DeleteSchema(r) => entry(
+                "delete",
+                "schema",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::DeleteSecret(r) => entry(
+                "delete",
+                "secret",
+                r.get_id(),
+                Some(r.get_row().get_name()),
+                None,
+            ),
+            MetaStoreEvent::DeleteTable(r) => entry(
+                "delete",
+                "table",
+                r.get_id(),
+                Some(r.get_row().get_table_name()),
+                Some(r.get_row().get_schema_id()),
+            ),
+            MetaStoreEvent::DeleteWAL(r) => entry(
+                "delete",
+                "wal",
+                r.get_id(),
+                None,
+                Some(r.get_row().table_id()),
+            ),
+        }
+    }
+}
+
+fn entity_name(table_id: TableId) -> &'static str {
+    match table_id {
+        TableId::Schemas => "schema",
+        TableId::Tables => "table",
+        TableId::Indexes => "index",
+        TableId::Partitions => "partition",
+        TableId::Chunks => "chunk",
+        TableId::WALs => "wal",
+        TableId::Jobs => "job",
+        TableId::Secrets => "secret",
+        TableId::LinkedServers => "linked_server",
+        TableId::PreAggregations => "pre_aggregation",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::partition::Partition;
+    use crate::metastore::IdRow;
+
+    #[test]
+    fn entries_from_events() {
+        let now = Utc::now();
+        assert_eq!(
+            MetaStoreLogEntry::from_event(&MetaStoreEvent::Insert(TableId::Partitions, 4), now),
+            Some(MetaStoreLogEntry {
+                time: now,
+                operation: "insert",
+                entity: "partition",
+                id: 4,
+                name: None,
+                parent_id: None,
+            })
+        );
+        assert_eq!(
+            MetaStoreLogEntry::from_event(&MetaStoreEvent::Delete(TableId::Partitions, 4), now),
+            None
+        );
+        let partition = IdRow::new(4, Partition::new(2, None, None));
+        assert_eq!(
+            MetaStoreLogEntry::from_event(&MetaStoreEvent::DeletePartition(partition), now),
+            Some(MetaStoreLogEntry {
+                time: now,
+                operation: "delete",
+                entity: "partition",
+                id: 4,
+                name: None,
+                parent_id: Some(2),
+            })
+        );
+    }
+}
@@ -26,9 +26,24 @@ impl Index {
             table_id,
             columns,
             sort_key_size,
+            is_ready: true,
         })
     }
 
+    pub fn is_ready_default() -> bool {
+        true
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.is_ready
+    }
+
+    pub fn update_is_ready(&self, is_ready: bool) -> Index {
+        let mut index = self.clone();
+        index.is_ready = is_ready;
+        index
+    }
+
     pub fn table_id(&self) -> u64 {
         return self.table_id;
     }
@@ -49,6 +64,17 @@ impl Index {
     pub fn sort_key_size(&self) -> u64 {
         self.sort_key_size
     }
+
+    /// Number of leading sort key columns that have the same types in both indexes. Partitions of
+    /// co-located tables are aligned on this prefix of their keys.
+    pub fn colocation_key_size(&self, other: &Index) -> usize {
+        self.columns
+            .iter()
+            .zip(other.columns.iter())
+            .take(std::cmp::min(self.sort_key_size, other.sort_key_size) as usize)
+            .take_while(|(l, r)| l.get_column_type() == r.get_column_type())
+            .count()
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
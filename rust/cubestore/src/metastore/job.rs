@@ -17,6 +17,27 @@ pub enum JobType {
     TableImportCSV(/*location*/ String),
 }
 
+impl JobType {
+    pub fn class(&self) -> JobClass {
+        match self {
+            JobType::PartitionCompaction | JobType::Repartition => JobClass::Background,
+            JobType::WalPartitioning | JobType::TableImport | JobType::TableImportCSV(_) => {
+                JobClass::Ingestion
+            }
+        }
+    }
+}
+
+/// Jobs of different classes are processed by separate runners, so heavy background work can't
+/// delay ingestion.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum JobClass {
+    /// Makes inserted data available for queries.
+    Ingestion,
+    /// Maintenance of already queryable data, e.g. compaction.
+    Background,
+}
+
 fn get_job_type_index(j: &JobType) -> u32 {
     match j {
         JobType::WalPartitioning => 1,
@@ -36,12 +57,54 @@ pub enum JobStatus {
     Error(String),
 }
 
+impl JobStatus {
+    /// As shown in `system.jobs`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Scheduled(_) => "scheduled",
+            JobStatus::ProcessingBy(_) => "processing",
+            JobStatus::Completed => "completed",
+            JobStatus::Timeout => "timeout",
+            JobStatus::Error(_) => "error",
+        }
+    }
+
+    /// Node the job is scheduled on or processed by.
+    pub fn node(&self) -> Option<&str> {
+        match self {
+            JobStatus::Scheduled(node) | JobStatus::ProcessingBy(node) => Some(node.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// Details of a completed job, reported by [crate::cluster::JobEvent::Success].
+#[derive(Clone, Default, Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
+pub struct JobResult {
+    /// Exact duplicate rows dropped by compaction, see
+    /// [crate::metastore::table::Table::deduplicate].
+    pub removed_duplicates: u64,
+}
+
+/// Work done by a running job, reported with [crate::util::job_progress::report_job_progress].
+/// Units depend on the job type, see [Job::progress_description].
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Hash, Eq, PartialEq)]
+pub struct JobProgress {
+    pub done: u64,
+    /// `None` if the amount of work isn't known upfront, e.g. rows of an import.
+    pub total: Option<u64>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, Hash)]
 pub struct Job {
     row_reference: RowKey,
     job_type: JobType,
     last_heart_beat: DateTime<Utc>,
     status: JobStatus,
+    #[serde(default)]
+    result: JobResult,
+    #[serde(default)]
+    progress: Option<JobProgress>,
 }
 
 impl Job {
@@ -51,6 +114,8 @@ impl Job {
             job_type,
             last_heart_beat: Utc::now(),
             status: JobStatus::Scheduled(shard),
+            result: JobResult::default(),
+            progress: None,
         }
     }
 
@@ -70,12 +135,36 @@ impl Job {
         &self.status
     }
 
+    pub fn result(&self) -> &JobResult {
+        &self.result
+    }
+
+    pub fn progress(&self) -> Option<JobProgress> {
+        self.progress
+    }
+
+    /// E.g. `3/10 chunks` for a repartition.
+    pub fn progress_description(&self) -> Option<String> {
+        let progress = self.progress?;
+        let unit = match self.job_type {
+            JobType::WalPartitioning | JobType::Repartition => "chunks",
+            JobType::PartitionCompaction => "steps",
+            JobType::TableImport | JobType::TableImportCSV(_) => "rows",
+        };
+        Some(match progress.total {
+            Some(total) => format!("{}/{} {}", progress.done, total, unit),
+            None => format!("{} {}", progress.done, unit),
+        })
+    }
+
     pub fn update_status(&self, status: JobStatus) -> Job {
         Job {
             row_reference: self.row_reference.clone(),
             job_type: self.job_type.clone(),
             last_heart_beat: Utc::now(),
             status,
+            result: self.result.clone(),
+            progress: self.progress,
         }
     }
 
@@ -87,8 +176,18 @@ impl Job {
         self.update_status(self.status.clone())
     }
 
-    pub fn completed(&self) -> Job {
-        self.update_status(JobStatus::Completed)
+    pub fn update_progress(&self, progress: JobProgress) -> Job {
+        Job {
+            progress: Some(progress),
+            ..self.update_heart_beat()
+        }
+    }
+
+    pub fn completed(&self, result: JobResult) -> Job {
+        Job {
+            result,
+            ..self.update_status(JobStatus::Completed)
+        }
     }
 }
 
@@ -1,9 +1,14 @@
+pub mod change_feed;
 pub mod chunks;
+pub mod history;
 pub mod index;
 pub mod job;
+pub mod linked_server;
 pub mod listener;
 pub mod partition;
+pub mod pre_aggregation;
 pub mod schema;
+pub mod secret;
 pub mod table;
 pub mod wal;
 
@@ -20,17 +25,28 @@ use std::{collections::hash_map::DefaultHasher, env, io::Cursor, sync::Arc, time
 use tokio::fs;
 use tokio::sync::{Notify, RwLock};
 
+use crate::cluster::is_replicated_table;
 use crate::config::injection::DIService;
 use crate::config::{Config, ConfigObj};
 use crate::metastore::chunks::{ChunkIndexKey, ChunkRocksIndex};
 use crate::metastore::index::IndexIndexKey;
-use crate::metastore::job::{Job, JobIndexKey, JobRocksIndex, JobRocksTable, JobStatus};
+use crate::metastore::job::{
+    Job, JobClass, JobIndexKey, JobProgress, JobResult, JobRocksIndex, JobRocksTable, JobStatus,
+};
+use crate::metastore::linked_server::{
+    LinkedServer, LinkedServerRocksIndex, LinkedServerRocksTable,
+};
 use crate::metastore::partition::PartitionIndexKey;
+use crate::metastore::pre_aggregation::{
+    PreAggregation, PreAggregationRocksIndex, PreAggregationRocksTable, PreAggregationState,
+};
+use crate::metastore::secret::{Secret, SecretRocksIndex, SecretRocksTable, SecretValue};
 use crate::metastore::table::{TableIndexKey, TablePath};
 use crate::metastore::wal::{WALIndexKey, WALRocksIndex};
 use crate::remotefs::{LocalDirRemoteFs, RemoteFs};
 use crate::store::DataFrame;
-use crate::table::{Row, TableValue};
+use crate::table::data::{self, convert_row_to_heap_allocated, RowR, TableValueR};
+use crate::table::{cmp_same_types, Row, TableValue};
 use crate::util::lock::acquire_lock;
 use crate::util::time_span::{warn_long, warn_long_fut};
 use crate::util::WorkerLoop;
@@ -43,10 +59,12 @@ use core::{fmt, mem};
 use cubehll::HllSketch;
 use cubezetasketch::HyperLogLogPlusPlus;
 use futures::future::join_all;
+use futures::{stream, StreamExt, TryStreamExt};
 use futures_timer::Delay;
 use index::{IndexRocksIndex, IndexRocksTable};
 use itertools::Itertools;
 use log::trace;
+use lru::LruCache;
 use parquet::basic::{ConvertedType, Repetition};
 use parquet::{basic::Type, schema::types};
 use partition::{PartitionRocksIndex, PartitionRocksTable};
@@ -54,10 +72,13 @@ use regex::Regex;
 use rocksdb::checkpoint::Checkpoint;
 use schema::{SchemaRocksIndex, SchemaRocksTable};
 use smallvec::alloc::fmt::Formatter;
-use std::collections::HashMap;
+use std::cmp;
+use std::cmp::max;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
 use std::time::{Duration, SystemTime};
 use table::Table;
@@ -269,6 +290,12 @@ impl DataFrameValue<String> for Option<u64> {
     }
 }
 
+impl DataFrameValue<String> for Vec<u64> {
+    fn value(v: &Self) -> String {
+        format!("{:?}", v)
+    }
+}
+
 impl DataFrameValue<String> for Option<Row> {
     fn value(v: &Self) -> String {
         v.as_ref()
@@ -323,6 +350,10 @@ pub enum ColumnType {
     Decimal { scale: i32, precision: i32 },
     Float,
     Boolean,
+    Uuid, // 16 bytes, exposed to queries as strings, see [crate::queryplanner::stored_types].
+    Enum(Vec<String>), // Positions of the values, exposed to queries as the values.
+    Inet, // 16 bytes of IPv6 or IPv4-mapped addresses, exposed as strings.
+    Cidr, // Network address followed by the prefix length, exposed as strings.
 }
 
 impl ColumnType {
@@ -350,7 +381,7 @@ impl From<&Column> for parquet::schema::types::Type {
                     .build()
                     .unwrap()
             }
-            crate::metastore::ColumnType::Int => {
+            crate::metastore::ColumnType::Int | ColumnType::Enum(_) => {
                 types::Type::primitive_type_builder(&column.get_name(), Type::INT64)
                     .with_converted_type(ConvertedType::INT_64)
                     .with_repetition(Repetition::OPTIONAL)
@@ -366,7 +397,11 @@ impl From<&Column> for parquet::schema::types::Type {
                     .build()
                     .unwrap()
             }
-            crate::metastore::ColumnType::Bytes | ColumnType::HyperLogLog(_) => {
+            crate::metastore::ColumnType::Bytes
+            | ColumnType::HyperLogLog(_)
+            | ColumnType::Uuid
+            | ColumnType::Inet
+            | ColumnType::Cidr => {
                 types::Type::primitive_type_builder(&column.get_name(), Type::BYTE_ARRAY)
                     .with_converted_type(ConvertedType::NONE)
                     .with_repetition(Repetition::OPTIONAL)
@@ -402,6 +437,76 @@ pub struct Column {
     name: String,
     column_type: ColumnType,
     column_index: usize,
+    /// Enforced on INSERT and import, see [crate::import::constraints].
+    #[serde(default, skip_serializing_if = "is_false")]
+    not_null: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    checks: Vec<ColumnCheck>,
+    /// SQL expression of a stored generated column, see [crate::import::generated].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    generated: Option<String>,
+    /// Value of the column when INSERT or import sources omit it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<ColumnDefault>,
+    /// Collation of string columns, see [crate::queryplanner::collation].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    collation: Option<Collation>,
+    /// Digits of fractional seconds kept in timestamps, microseconds if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp_precision: Option<u8>,
+    /// Encoding of values in parquet files, chosen by the writer if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<ColumnEncoding>,
+}
+
+fn is_false(v: &bool) -> bool {
+    !*v
+}
+
+/// Simple CHECK constraint of a column. Literals are kept in their text form, as in CSV files.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum ColumnCheck {
+    Compare(CheckOp, String),
+    In(Vec<String>),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum ColumnDefault {
+    /// Text form of the value, as in CSV files.
+    Value(String),
+    /// Time of the ingestion, i.e. `DEFAULT now()` and `DEFAULT CURRENT_TIMESTAMP`.
+    Now,
+    /// Start of the UTC day of the ingestion, i.e. `DEFAULT CURRENT_DATE`.
+    Today,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum Collation {
+    /// `COLLATE ci`, compares lowercase forms of strings.
+    CaseInsensitive,
+    /// `COLLATE unicode_ci`, also ignores accents of Latin letters.
+    UnicodeCaseInsensitive,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum ColumnEncoding {
+    /// `ENCODING DELTA`, differences between consecutive values are bit-packed, i.e.
+    /// DELTA_BINARY_PACKED of parquet. Takes a few bits per value of sorted timestamps and
+    /// sequences. Only applies to integer-based columns.
+    Delta,
+    /// `ENCODING OFFLOAD`, strings longer than `threshold` bytes are kept apart from the column,
+    /// which only holds their prefix, see [crate::table::parquet::offloaded_columns].
+    Offload { threshold: usize },
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum CheckOp {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
 }
 
 impl Into<Field> for Column {
@@ -418,6 +523,8 @@ impl Into<Field> for Column {
                 }
                 ColumnType::Bytes => DataType::Binary,
                 ColumnType::HyperLogLog(_) => DataType::Binary,
+                ColumnType::Uuid | ColumnType::Inet | ColumnType::Cidr => DataType::Binary,
+                ColumnType::Enum(_) => DataType::Int64,
                 ColumnType::Float => DataType::Float64,
             },
             false,
@@ -439,14 +546,26 @@ impl fmt::Display for Column {
             ColumnType::HyperLogLog(HllFlavour::Airlift) => "HYPERLOGLOG".to_string(),
             ColumnType::HyperLogLog(HllFlavour::ZetaSketch) => "HYPERLOGLOGPP".to_string(),
             ColumnType::Float => "FLOAT".to_string(),
+            ColumnType::Uuid => "UUID".to_string(),
+            ColumnType::Inet => "INET".to_string(),
+            ColumnType::Cidr => "CIDR".to_string(),
+            ColumnType::Enum(values) => format!(
+                "ENUM({})",
+                values
+                    .iter()
+                    .map(|v| format!("'{}'", v.replace('\'', "''")))
+                    .join(", ")
+            ),
         };
         f.write_fmt(format_args!("{} {}", self.name, column_type))
     }
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
 pub enum ImportFormat {
     CSV,
+    /// Decoded by the [crate::import::decoder::RowDecoder] registered with the name.
+    Custom(String),
 }
 
 data_frame_from! {
@@ -462,8 +581,109 @@ pub struct Index {
     name: String,
     table_id: u64,
     columns: Vec<Column>,
-    sort_key_size: u64
+    sort_key_size: u64,
+    /// Indexes added to tables with data are not used by queries until existing rows are copied
+    /// to them, see [crate::sql::index_build].
+    #[serde(default="Index::is_ready_default")]
+    is_ready: bool
+}
+}
+
+/// File of an attached table, see [MetaStore::attach_table].
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct AttachedFile {
+    pub path: String,
+    /// Bounds of the sort key, [None] if rows of the file are not sorted by it.
+    pub min_value: Option<Row>,
+    pub max_value: Option<Row>,
+    /// Rows of the file without the deleted ones.
+    pub row_count: u64,
+    /// Sorted positions of deleted rows in the file.
+    pub deleted_rows: Vec<u64>,
 }
+
+impl AttachedFile {
+    fn into_partition(self, index_id: u64) -> Partition {
+        Partition::new(index_id, None, None)
+            .update_min_max_and_row_count(self.min_value, self.max_value, self.row_count)
+            .set_attached_file(Some(self.path))
+            .set_deleted_rows(self.deleted_rows)
+    }
+}
+
+/// The smallest and the largest non-null value of each column of a chunk or a partition, in the
+/// order of index columns. Bounds of columns with only nulls are nulls. They answer MIN and MAX
+/// without reading the data, see [crate::queryplanner::metadata_count].
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub struct ColumnBounds {
+    pub min: Row,
+    pub max: Row,
+}
+
+impl ColumnBounds {
+    pub fn of_rows<'a, 'b>(
+        num_columns: usize,
+        rows: impl Iterator<Item = &'b RowR<'a>>,
+    ) -> ColumnBounds {
+        let mut min = vec![TableValueR::Null; num_columns];
+        let mut max = vec![TableValueR::Null; num_columns];
+        for r in rows {
+            for (i, v) in r.iter().enumerate() {
+                if *v == TableValueR::Null {
+                    continue;
+                }
+                if min[i] == TableValueR::Null || cmp_bound(v, &min[i]) == cmp::Ordering::Less {
+                    min[i] = *v;
+                }
+                if max[i] == TableValueR::Null || cmp_bound(v, &max[i]) == cmp::Ordering::Greater {
+                    max[i] = *v;
+                }
+            }
+        }
+        ColumnBounds {
+            min: convert_row_to_heap_allocated(&min),
+            max: convert_row_to_heap_allocated(&max),
+        }
+    }
+
+    /// Bounds of the rows of both.
+    pub fn merge(&self, other: &ColumnBounds) -> ColumnBounds {
+        let pick = |l: &[TableValue], r: &[TableValue], ord: cmp::Ordering| {
+            Row::new(
+                l.iter()
+                    .zip(r.iter())
+                    .map(|(l, r)| match (l, r) {
+                        (TableValue::Null, v) | (v, TableValue::Null) => v.clone(),
+                        (l, r) if cmp_same_types(l, r) == ord => l.clone(),
+                        (_, r) => r.clone(),
+                    })
+                    .collect(),
+            )
+        };
+        ColumnBounds {
+            min: pick(self.min.values(), other.min.values(), cmp::Ordering::Less),
+            max: pick(
+                self.max.values(),
+                other.max.values(),
+                cmp::Ordering::Greater,
+            ),
+        }
+    }
+}
+
+fn cmp_bound(l: &TableValueR, r: &TableValueR) -> cmp::Ordering {
+    match (l, r) {
+        (TableValueR::Float(l), TableValueR::Float(r)) => l.cmp(r),
+        (l, r) => data::cmp_same_types(l, r),
+    }
+}
+
+impl DataFrameValue<String> for Option<ColumnBounds> {
+    fn value(v: &Self) -> String {
+        v.as_ref()
+            .map(|b| format!("{:?} - {:?}", b.min.values(), b.max.values()))
+            .unwrap_or("NULL".to_string())
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
@@ -483,14 +703,35 @@ pub struct Partition {
     #[serde(default)]
     warmed_up: bool,
     main_table_row_count: u64,
-    /// Not used or updated anymore.
+    /// Last time the partition was read by a select, see [crate::cluster::partition_stats].
     #[serde(default)]
-    last_used: Option<DateTime<Utc>>
+    last_used: Option<DateTime<Utc>>,
+    /// Storage location of the table, see [crate::remotefs::storage].
+    #[serde(default)]
+    storage: Option<String>,
+    /// Partition of the table this one is co-located with whose key range contains the range of
+    /// this partition. Both are assigned to the same worker.
+    #[serde(default)]
+    colocated_partition_id: Option<u64>,
+    /// Remote path of the file of a partition attached from a manifest of another cluster, see
+    /// [crate::sql::manifest]. The file is never compacted or removed by this cluster.
+    #[serde(default)]
+    attached_file: Option<String>,
+    /// Sorted positions of rows of [Partition::attached_file] deleted by the table format the
+    /// file belongs to, see [crate::queryplanner::deleted_rows].
+    #[serde(default)]
+    deleted_rows: Vec<u64>,
+    /// Number of selects that read the partition, see [crate::cluster::partition_stats].
+    #[serde(default)]
+    read_count: u64,
+    /// [None] if the bounds of the rows in the partition file are unknown.
+    #[serde(default)]
+    column_bounds: Option<ColumnBounds>
 }
 }
 
 data_frame_from! {
-#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
 pub struct Chunk {
     partition_id: u64,
     row_count: u64,
@@ -498,7 +739,13 @@ pub struct Chunk {
     active: bool,
     /// Not used or updated anymore.
     #[serde(default)]
-    last_used: Option<DateTime<Utc>>
+    last_used: Option<DateTime<Utc>>,
+    /// Storage location of the table, see [crate::remotefs::storage].
+    #[serde(default)]
+    storage: Option<String>,
+    /// [None] for chunks written before the bounds were tracked.
+    #[serde(default)]
+    column_bounds: Option<ColumnBounds>
 }
 }
 
@@ -660,6 +907,9 @@ pub trait MetaStore: DIService + Send + Sync {
     async fn delete_schema_by_id(&self, schema_id: u64) -> Result<(), CubeError>;
 
     fn tables_table(&self) -> TableMetaStoreTable;
+    /// Columns of `sort_order` lead the sort key of the default index, so chunks and partitions
+    /// of the table keep rows clustered on them. Other sortable columns follow in table order.
+    /// See [Table::deduplicate] for `deduplicate`.
     async fn create_table(
         &self,
         schema_name: String,
@@ -669,8 +919,35 @@ pub trait MetaStore: DIService + Send + Sync {
         import_format: Option<ImportFormat>,
         indexes: Vec<IndexDef>,
         is_ready: bool,
+        storage: Option<String>,
+        colocate_with: Option<u64>,
+        source_secret: Option<String>,
+        sort_order: Vec<String>,
+        deduplicate: bool,
     ) -> Result<IdRow<Table>, CubeError>;
     async fn table_ready(&self, id: u64, is_ready: bool) -> Result<IdRow<Table>, CubeError>;
+    /// Creates a read-only table over files of an external directory. The default index keeps
+    /// the order of `columns` and its sort key is their first `sort_key_size` columns, every file
+    /// becomes a partition of it. Tables with a `version` are kept up to date with
+    /// [MetaStore::refresh_attached_table].
+    async fn attach_table(
+        &self,
+        schema_name: String,
+        table_name: String,
+        columns: Vec<Column>,
+        sort_key_size: u64,
+        location: String,
+        files: Vec<AttachedFile>,
+        version: Option<u64>,
+    ) -> Result<IdRow<Table>, CubeError>;
+    /// Replaces files of an attached table with `files` of its `version`. Partitions of files that
+    /// did not change are kept as is.
+    async fn refresh_attached_table(
+        &self,
+        table_id: u64,
+        version: u64,
+        files: Vec<AttachedFile>,
+    ) -> Result<IdRow<Table>, CubeError>;
     async fn get_table(
         &self,
         schema_name: String,
@@ -698,6 +975,12 @@ pub trait MetaStore: DIService + Send + Sync {
     ) -> Result<(), CubeError>;
     async fn delete_partition(&self, partition_id: u64) -> Result<IdRow<Partition>, CubeError>;
     async fn mark_partition_warmed_up(&self, partition_id: u64) -> Result<(), CubeError>;
+    /// Adds `(partition_id, reads, last_read)` to access statistics of partitions. Missing
+    /// partitions are skipped, they might have been removed by compaction in the meantime.
+    async fn record_partition_reads(
+        &self,
+        reads: Vec<(u64, u64, DateTime<Utc>)>,
+    ) -> Result<(), CubeError>;
 
     fn index_table(&self) -> IndexMetaStoreTable;
     async fn create_index(
@@ -706,18 +989,40 @@ pub trait MetaStore: DIService + Send + Sync {
         table_name: String,
         index_def: IndexDef,
     ) -> Result<IdRow<Index>, CubeError>;
+    /// Adds an index to a table that already has data. Inserts go to the index as to the others,
+    /// but queries don't use it until [MetaStore::finish_index_build]. Returns active partitions
+    /// and chunks of the default index at the time the index was added, their rows are the ones
+    /// to copy to it.
+    async fn start_index_build(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index_def: IndexDef,
+    ) -> Result<(IdRow<Index>, Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>), CubeError>;
+    /// Activates uploaded chunks with the copied rows and lets queries use the index.
+    async fn finish_index_build(
+        &self,
+        index_id: u64,
+        uploaded_chunk_ids: Vec<u64>,
+    ) -> Result<IdRow<Index>, CubeError>;
+    /// Removes the index of a failed build with its partitions and chunks.
+    async fn abort_index_build(&self, index_id: u64) -> Result<(), CubeError>;
     async fn get_default_index(&self, table_id: u64) -> Result<IdRow<Index>, CubeError>;
     async fn get_table_indexes(&self, table_id: u64) -> Result<Vec<IdRow<Index>>, CubeError>;
     async fn get_active_partitions_by_index_id(
         &self,
         index_id: u64,
     ) -> Result<Vec<IdRow<Partition>>, CubeError>;
+    async fn get_inactive_partitions_by_index_id(
+        &self,
+        index_id: u64,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError>;
     async fn get_index(&self, index_id: u64) -> Result<IdRow<Index>, CubeError>;
 
     async fn get_active_partitions_and_chunks_by_index_id_for_select(
         &self,
         index_id: Vec<u64>,
-    ) -> Result<Vec<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>, CubeError>;
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError>;
 
     async fn get_warmup_partitions(
         &self,
@@ -729,6 +1034,11 @@ pub trait MetaStore: DIService + Send + Sync {
         partition_id: u64,
         row_count: usize,
     ) -> Result<IdRow<Chunk>, CubeError>;
+    /// Allocates chunks for `(partition_id, row_count, column_bounds)` in a single metastore write.
+    async fn create_chunks(
+        &self,
+        chunks: Vec<(u64, usize, Option<ColumnBounds>)>,
+    ) -> Result<Vec<IdRow<Chunk>>, CubeError>;
     async fn get_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
     async fn get_chunks_by_partition(
         &self,
@@ -737,6 +1047,7 @@ pub trait MetaStore: DIService + Send + Sync {
     ) -> Result<Vec<IdRow<Chunk>>, CubeError>;
     async fn chunk_uploaded(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
     async fn deactivate_chunk(&self, chunk_id: u64) -> Result<(), CubeError>;
+    async fn deactivate_chunks(&self, chunk_ids: Vec<u64>) -> Result<(), CubeError>;
     async fn swap_chunks(
         &self,
         deactivate_ids: Vec<u64>,
@@ -754,6 +1065,9 @@ pub trait MetaStore: DIService + Send + Sync {
         uploaded_chunk_ids: Vec<u64>,
     ) -> Result<(), CubeError>;
     async fn delete_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError>;
+    /// Deletes all `chunk_ids` in a single metastore write. Fails without deleting anything if
+    /// any of the chunks is still active.
+    async fn delete_chunks(&self, chunk_ids: Vec<u64>) -> Result<Vec<IdRow<Chunk>>, CubeError>;
 
     async fn create_wal(&self, table_id: u64, row_count: usize) -> Result<IdRow<WAL>, CubeError>;
     async fn get_wal(&self, wal_id: u64) -> Result<IdRow<WAL>, CubeError>;
@@ -764,24 +1078,98 @@ pub trait MetaStore: DIService + Send + Sync {
     async fn add_job(&self, job: Job) -> Result<Option<IdRow<Job>>, CubeError>;
     async fn get_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
     async fn delete_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    /// Deletes the job unless some node has already started processing it.
+    async fn cancel_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    async fn all_jobs(&self) -> Result<Vec<IdRow<Job>>, CubeError>;
     async fn start_processing_job(
         &self,
         server_name: String,
+        job_class: JobClass,
     ) -> Result<Option<IdRow<Job>>, CubeError>;
     async fn update_status(&self, job_id: u64, status: JobStatus) -> Result<IdRow<Job>, CubeError>;
+    /// Marks the job completed with `result`.
+    async fn complete_job(&self, job_id: u64, result: JobResult) -> Result<IdRow<Job>, CubeError>;
     async fn update_heart_beat(&self, job_id: u64) -> Result<IdRow<Job>, CubeError>;
+    /// Also updates the heart beat.
+    async fn update_job_progress(
+        &self,
+        job_id: u64,
+        progress: JobProgress,
+    ) -> Result<IdRow<Job>, CubeError>;
 
     async fn get_tables_with_indexes(
         &self,
         table_name: Vec<(String, String)>,
     ) -> Result<Vec<(IdRow<Schema>, IdRow<Table>, Vec<IdRow<Index>>)>, CubeError>;
+
+    /// Fails if a secret with the name exists, unless `or_replace` is set.
+    async fn create_secret(
+        &self,
+        name: String,
+        value: SecretValue,
+        or_replace: bool,
+    ) -> Result<IdRow<Secret>, CubeError>;
+    async fn get_secret(&self, name: String) -> Result<IdRow<Secret>, CubeError>;
+    async fn get_secrets(&self) -> Result<Vec<IdRow<Secret>>, CubeError>;
+    /// Fails if the secret is used by any table.
+    async fn drop_secret(&self, name: String) -> Result<IdRow<Secret>, CubeError>;
+
+    /// Fails if a linked server with the name exists, unless `or_replace` is set.
+    async fn create_linked_server(
+        &self,
+        name: String,
+        location: String,
+        max_rows: Option<u64>,
+        or_replace: bool,
+    ) -> Result<IdRow<LinkedServer>, CubeError>;
+    async fn get_linked_servers(&self) -> Result<Vec<IdRow<LinkedServer>>, CubeError>;
+    async fn drop_linked_server(&self, name: String) -> Result<IdRow<LinkedServer>, CubeError>;
+
+    /// Fails if the schema doesn't exist or has a pre-aggregation with the name.
+    async fn create_pre_aggregation(
+        &self,
+        pre_aggregation: PreAggregation,
+    ) -> Result<IdRow<PreAggregation>, CubeError>;
+    async fn get_pre_aggregation(
+        &self,
+        schema: String,
+        name: String,
+    ) -> Result<IdRow<PreAggregation>, CubeError>;
+    async fn get_pre_aggregations(&self) -> Result<Vec<IdRow<PreAggregation>>, CubeError>;
+    async fn update_pre_aggregation_state(
+        &self,
+        id: u64,
+        state: PreAggregationState,
+    ) -> Result<IdRow<PreAggregation>, CubeError>;
+    /// Tables of the pre-aggregation are kept, they are dropped by the caller.
+    async fn drop_pre_aggregation(
+        &self,
+        schema: String,
+        name: String,
+    ) -> Result<IdRow<PreAggregation>, CubeError>;
 }
 
 /// Information required to produce partition name on remote fs.
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PartitionName {
     pub parent_partition_id: Option<u64>,
     pub partition_id: u64,
+    /// Also applies to chunks of the partition.
+    #[serde(default)]
+    pub storage: Option<String>,
+    /// See [Partition::placement_id].
+    #[serde(default)]
+    pub placement_id: Option<u64>,
+    /// See [Partition::attached_file].
+    #[serde(default)]
+    pub attached_file: Option<String>,
+    /// See [Partition::last_used].
+    #[serde(default)]
+    pub last_read: Option<DateTime<Utc>>,
+    /// Set for partitions of replicated tables, which are warmed up on all select workers, see
+    /// [crate::cluster::is_replicated_table].
+    #[serde(default)]
+    pub replicated: bool,
 }
 
 crate::di_service!(RocksMetaStore, [MetaStore]);
@@ -796,16 +1184,22 @@ pub enum MetaStoreEvent {
     UpdateChunk(IdRow<Chunk>, IdRow<Chunk>),
     UpdateIndex(IdRow<Index>, IdRow<Index>),
     UpdateJob(IdRow<Job>, IdRow<Job>),
+    UpdateLinkedServer(IdRow<LinkedServer>, IdRow<LinkedServer>),
     UpdatePartition(IdRow<Partition>, IdRow<Partition>),
+    UpdatePreAggregation(IdRow<PreAggregation>, IdRow<PreAggregation>),
     UpdateSchema(IdRow<Schema>, IdRow<Schema>),
+    UpdateSecret(IdRow<Secret>, IdRow<Secret>),
     UpdateTable(IdRow<Table>, IdRow<Table>),
     UpdateWAL(IdRow<WAL>, IdRow<WAL>),
 
     DeleteChunk(IdRow<Chunk>),
     DeleteIndex(IdRow<Index>),
     DeleteJob(IdRow<Job>),
+    DeleteLinkedServer(IdRow<LinkedServer>),
     DeletePartition(IdRow<Partition>),
+    DeletePreAggregation(IdRow<PreAggregation>),
     DeleteSchema(IdRow<Schema>),
+    DeleteSecret(IdRow<Secret>),
     DeleteTable(IdRow<Table>),
     DeleteWAL(IdRow<WAL>),
 }
@@ -914,7 +1308,10 @@ enum_from_primitive! {
         Partitions = 0x0400,
         Chunks = 0x0500,
         WALs = 0x0600,
-        Jobs = 0x0700
+        Jobs = 0x0700,
+        Secrets = 0x0800,
+        LinkedServers = 0x0900,
+        PreAggregations = 0x0A00
     }
 }
 
@@ -933,10 +1330,85 @@ impl MemorySequence {
     }
 }
 
+type PartitionsAndChunks = Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>;
+
+/// Active partitions and chunks of indexes requested by the query planner. Entries are read and
+/// put under the read lock of [RocksMetaStore::db], writes evict indexes whose partitions or
+/// chunks they touch before releasing the write lock, so readers never observe stale entries.
+struct SelectPartitionsCache {
+    by_index: Mutex<LruCache<u64, Arc<PartitionsAndChunks>>>,
+}
+
+impl SelectPartitionsCache {
+    fn new(max_entries: usize) -> SelectPartitionsCache {
+        SelectPartitionsCache {
+            by_index: Mutex::new(LruCache::new(max_entries)),
+        }
+    }
+
+    fn get(&self, index_id: u64) -> Result<Option<Arc<PartitionsAndChunks>>, CubeError> {
+        Ok(self.by_index.lock()?.get(&index_id).cloned())
+    }
+
+    fn put(&self, index_id: u64, partitions: Arc<PartitionsAndChunks>) -> Result<(), CubeError> {
+        self.by_index.lock()?.put(index_id, partitions);
+        Ok(())
+    }
+
+    /// `db` must already have the rows written with `events`, inserts only carry row ids.
+    fn invalidate(&self, events: &[MetaStoreEvent], db: DbTableRef) -> Result<(), CubeError> {
+        if self.by_index.lock()?.is_empty() {
+            return Ok(());
+        }
+        let rocks_partition = PartitionRocksTable::new(db.clone());
+        let rocks_chunk = ChunkRocksTable::new(db);
+        let index_of_partition = |partition_id: u64| -> Result<Option<u64>, CubeError> {
+            Ok(rocks_partition
+                .get_row(partition_id)?
+                .map(|p| p.get_row().get_index_id()))
+        };
+        let mut affected = HashSet::new();
+        for e in events {
+            let index_id = match e {
+                MetaStoreEvent::Insert(TableId::Indexes, id) => Some(*id),
+                MetaStoreEvent::Insert(TableId::Partitions, id) => index_of_partition(*id)?,
+                MetaStoreEvent::Insert(TableId::Chunks, id) => match rocks_chunk.get_row(*id)? {
+                    Some(c) => index_of_partition(c.get_row().get_partition_id())?,
+                    None => None,
+                },
+                MetaStoreEvent::DeleteIndex(i) => Some(i.get_id()),
+                MetaStoreEvent::UpdatePartition(_, p) | MetaStoreEvent::DeletePartition(p) => {
+                    Some(p.get_row().get_index_id())
+                }
+                MetaStoreEvent::UpdateChunk(_, c) | MetaStoreEvent::DeleteChunk(c) => {
+                    index_of_partition(c.get_row().get_partition_id())?
+                }
+                _ => continue,
+            };
+            match index_id {
+                Some(index_id) => {
+                    affected.insert(index_id);
+                }
+                // Partition was deleted in the same write, its own event may come later.
+                None => {
+                    self.by_index.lock()?.clear();
+                    return Ok(());
+                }
+            }
+        }
+        let mut by_index = self.by_index.lock()?;
+        for index_id in affected {
+            by_index.pop(&index_id);
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone)]
 pub struct RocksMetaStore {
     pub db: Arc<RwLock<Arc<DB>>>,
     seq_store: Arc<Mutex<HashMap<TableId, u64>>>,
+    select_partitions_cache: Arc<SelectPartitionsCache>,
     listeners: Arc<RwLock<Vec<Sender<MetaStoreEvent>>>>,
     remote_fs: Arc<dyn RemoteFs>,
     last_checkpoint_time: Arc<RwLock<SystemTime>>,
@@ -1499,6 +1971,33 @@ fn meta_store_merge(
     Some(result)
 }
 
+/// Logs progress of a long-running metastore load step.
+struct LoadProgress {
+    name: &'static str,
+    total: usize,
+    done: AtomicUsize,
+}
+
+impl LoadProgress {
+    fn new(name: &'static str, total: usize) -> Self {
+        info!("{}: {} files", name, total);
+        Self {
+            name,
+            total,
+            done: AtomicUsize::new(0),
+        }
+    }
+
+    fn advance(&self) {
+        let done = self.done.fetch_add(1, Ordering::SeqCst) + 1;
+        // Report roughly every 10%.
+        let step = max(self.total / 10, 1);
+        if done % step == 0 || done == self.total {
+            info!("{}: {}/{}", self.name, done, self.total);
+        }
+    }
+}
+
 impl RocksMetaStore {
     pub fn with_listener(
         path: impl AsRef<Path>,
@@ -1527,6 +2026,9 @@ impl RocksMetaStore {
         let meta_store = RocksMetaStore {
             db: Arc::new(RwLock::new(db_arc.clone())),
             seq_store: Arc::new(Mutex::new(HashMap::new())),
+            select_partitions_cache: Arc::new(SelectPartitionsCache::new(
+                config.metastore_select_cache_max_entries() as usize,
+            )),
             listeners: Arc::new(RwLock::new(listeners)),
             remote_fs,
             last_checkpoint_time: Arc::new(RwLock::new(SystemTime::now())),
@@ -1580,29 +2082,59 @@ impl RocksMetaStore {
                 };
 
                 if let Some(snapshot) = last_metastore_snapshot {
+                    let start_time = SystemTime::now();
+                    let download_concurrency = max(config.download_concurrency() as usize, 1);
                     let to_load = remote_fs.list(&format!("metastore-{}", snapshot)).await?;
                     let meta_store_path = remote_fs.local_file("metastore").await?;
                     fs::create_dir_all(meta_store_path.to_string()).await?;
-                    for file in to_load.iter() {
-                        remote_fs.download_file(file).await?;
-                        let local = remote_fs.local_file(file).await?;
-                        let path = Path::new(&local);
-                        fs::copy(
-                            path,
-                            PathBuf::from(&meta_store_path)
-                                .join(path.file_name().unwrap().to_str().unwrap()),
-                        )
-                        .await?;
+                    let progress =
+                        LoadProgress::new("Downloading metastore snapshot", to_load.len());
+                    let mut downloads = stream::iter(to_load.into_iter().map(|file| {
+                        let remote_fs = remote_fs.clone();
+                        let meta_store_path = meta_store_path.clone();
+                        async move {
+                            remote_fs.download_file(&file).await?;
+                            let local = remote_fs.local_file(&file).await?;
+                            let path = Path::new(&local);
+                            fs::copy(
+                                path,
+                                PathBuf::from(&meta_store_path)
+                                    .join(path.file_name().unwrap().to_str().unwrap()),
+                            )
+                            .await?;
+                            Ok::<_, CubeError>(())
+                        }
+                    }))
+                    .buffer_unordered(download_concurrency);
+                    while let Some(res) = downloads.next().await {
+                        res?;
+                        progress.advance();
                     }
 
                     let meta_store = Self::new(path.as_ref(), remote_fs.clone(), config);
 
-                    let logs_to_batch = remote_fs
+                    let mut logs_to_batch = remote_fs
                         .list(&format!("metastore-{}-logs", snapshot))
                         .await?;
-                    for log_file in logs_to_batch.iter() {
-                        let path_to_log = remote_fs.local_file(log_file).await?;
-                        let batch = WriteBatchContainer::read_from_file(&path_to_log).await;
+                    // Logs are named by the first sequence number they contain and must be
+                    // replayed in that order. Remote listings are sorted lexicographically.
+                    logs_to_batch.sort_by_key(|f| Self::log_file_seq(f));
+                    let progress =
+                        LoadProgress::new("Replaying metastore logs", logs_to_batch.len());
+                    // Logs are downloaded in parallel but applied strictly in order.
+                    let mut log_downloads =
+                        stream::iter(logs_to_batch.into_iter().map(|log_file| {
+                            let remote_fs = remote_fs.clone();
+                            async move {
+                                let path_to_log = remote_fs.download_file(&log_file).await?;
+                                let batch = WriteBatchContainer::read_from_file(&path_to_log).await;
+                                Ok::<_, CubeError>((log_file, batch))
+                            }
+                        }))
+                        .buffered(download_concurrency);
+                    while let Some(res) = log_downloads.next().await {
+                        let (log_file, batch) = res?;
+                        progress.advance();
                         if let Ok(batch) = batch {
                             let db =
                                 acquire_lock("meta store load from remote", meta_store.db.write())
@@ -1617,6 +2149,11 @@ impl RocksMetaStore {
                         }
                     }
 
+                    info!(
+                        "Metastore snapshot {} loaded ({:?})",
+                        snapshot,
+                        start_time.elapsed()?
+                    );
                     return Ok(meta_store);
                 }
             } else {
@@ -1636,6 +2173,15 @@ impl RocksMetaStore {
         Ok(Self::new(path, remote_fs, config))
     }
 
+    fn log_file_seq(log_file: &str) -> u64 {
+        log_file
+            .rsplit('/')
+            .next()
+            .and_then(|f| f.strip_suffix(".flex"))
+            .and_then(|seq| u64::from_str(seq).ok())
+            .unwrap_or(0)
+    }
+
     pub async fn add_listener(&self, listener: Sender<MetaStoreEvent>) {
         self.listeners.write().await.push(listener);
     }
@@ -1653,6 +2199,7 @@ impl RocksMetaStore {
             seq_store: self.seq_store.clone(),
         };
         let db_to_send = db.clone();
+        let select_partitions_cache = self.select_partitions_cache.clone();
         let (spawn_res, events) =
             tokio::task::spawn_blocking(move || -> Result<(R, Vec<MetaStoreEvent>), CubeError> {
                 let mut batch = BatchPipe::new(db_to_send.as_ref());
@@ -1661,11 +2208,20 @@ impl RocksMetaStore {
                     DbTableRef {
                         db: db_to_send.as_ref(),
                         snapshot: &snapshot,
-                        mem_seq,
+                        mem_seq: mem_seq.clone(),
                     },
                     &mut batch,
                 )?;
                 let write_result = batch.batch_write_rows()?;
+                let written = db_to_send.snapshot();
+                select_partitions_cache.invalidate(
+                    &write_result,
+                    DbTableRef {
+                        db: db_to_send.as_ref(),
+                        snapshot: &written,
+                        mem_seq,
+                    },
+                )?;
                 Ok((res, write_result))
             })
             .await??;
@@ -1693,7 +2249,12 @@ impl RocksMetaStore {
             .upload_loop
             .process(
                 meta_store.clone(),
-                async move |_| Ok(Delay::new(Duration::from_secs(60)).await),
+                async move |m| {
+                    Ok(Delay::new(Duration::from_secs(
+                        m.config.meta_store_log_upload_interval(),
+                    ))
+                    .await)
+                },
                 async move |m, _| m.run_upload().await,
             )
             .await;
@@ -1750,7 +2311,10 @@ impl RocksMetaStore {
         }
 
         let last_checkpoint_time: SystemTime = self.last_checkpoint_time.read().await.clone();
-        if last_checkpoint_time + time::Duration::from_secs(300) < SystemTime::now() {
+        if last_checkpoint_time
+            + time::Duration::from_secs(self.config.meta_store_snapshot_interval())
+            < SystemTime::now()
+        {
             info!("Uploading meta store check point");
             self.upload_check_point().await?;
         }
@@ -1882,6 +2446,9 @@ impl RocksMetaStore {
         let checkpoint_path = db.path().join("..").join(remote_path.clone());
         let path_to_move = checkpoint_path.clone();
         tokio::task::spawn_blocking(move || -> Result<(), CubeError> {
+            // Full compaction merges accumulated SST files, so the snapshot consists of fewer and
+            // smaller files to download on cold start.
+            db.compact_range::<&[u8], &[u8]>(None, None);
             let checkpoint = Checkpoint::new(db.as_ref())?;
             checkpoint.create_checkpoint(path_to_move.as_path())?;
             Ok(())
@@ -1933,6 +2500,42 @@ impl RocksMetaStore {
         res
     }
 
+    /// Cached results are returned without reading RocksDB.
+    async fn active_partitions_and_chunks_for_select(
+        &self,
+        index_id: u64,
+    ) -> Result<Arc<PartitionsAndChunks>, CubeError> {
+        let cache = self.select_partitions_cache.clone();
+        self.read_operation(move |db_ref| {
+            if let Some(cached) = cache.get(index_id)? {
+                return Ok(cached);
+            }
+            let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref);
+            // TODO iterate over range
+            let result = rocks_partition
+                .get_rows_by_index(
+                    &PartitionIndexKey::ByIndexId(index_id),
+                    &PartitionRocksIndex::IndexId,
+                )?
+                .into_iter()
+                .filter(|r| r.get_row().active)
+                .map(|p| -> Result<_, CubeError> {
+                    let chunks = Self::chunks_by_partitioned_with_non_repartitioned(
+                        p.get_id(),
+                        &rocks_chunk,
+                        &rocks_partition,
+                    )?;
+                    Ok((p, chunks))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let result = Arc::new(result);
+            cache.put(index_id, result.clone())?;
+            Ok(result)
+        })
+        .await
+    }
+
     fn check_if_exists(name: &String, existing_keys_len: usize) -> Result<(), CubeError> {
         if existing_keys_len > 1 {
             let e = CubeError::user(format!(
@@ -1994,6 +2597,7 @@ impl RocksMetaStore {
         table_cols: &Vec<Column>,
         table_id: &IdRow<Table>,
         index_def: IndexDef,
+        is_ready: bool,
     ) -> Result<IdRow<Index>, CubeError> {
         if let Some(not_found) = index_def
             .columns
@@ -2037,9 +2641,11 @@ impl RocksMetaStore {
             table_id.get_id(),
             index_columns,
             sorted_key_size,
-        )?;
+        )?
+        .update_is_ready(is_ready);
         let index_id = rocks_index.insert(index, batch_pipe)?;
-        let partition = Partition::new(index_id.id, None, None);
+        let partition = Partition::new(index_id.id, None, None)
+            .set_storage(table_id.get_row().storage().clone());
         let _ = rocks_partition.insert(partition, batch_pipe)?;
         Ok(index_id)
     }
@@ -2088,6 +2694,21 @@ impl RocksMetaStore {
         Ok(chunks)
     }
 
+    // Must be run under write_operation(). Returns deactivated row count.
+    fn deactivate_chunks_impl(
+        db_ref: DbTableRef,
+        batch_pipe: &mut BatchPipe,
+        chunk_ids: &[u64],
+    ) -> Result<u64, CubeError> {
+        let table = ChunkRocksTable::new(db_ref.clone());
+        let mut deactivated_row_count = 0;
+        for id in chunk_ids {
+            deactivated_row_count += table.get_row_or_not_found(*id)?.get_row().get_row_count();
+            table.update_with_fn(*id, |row| row.deactivate(), batch_pipe)?;
+        }
+        return Ok(deactivated_row_count);
+    }
+
     // Must be run under write_operation(). Returns activated row count.
     fn activate_chunks_impl(
         db_ref: DbTableRef,
@@ -2261,6 +2882,11 @@ impl MetaStore for RocksMetaStore {
         import_format: Option<ImportFormat>,
         indexes: Vec<IndexDef>,
         is_ready: bool,
+        storage: Option<String>,
+        colocate_with: Option<u64>,
+        source_secret: Option<String>,
+        sort_order: Vec<String>,
+        deduplicate: bool,
     ) -> Result<IdRow<Table>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let rocks_table = TableRocksTable::new(db_ref.clone());
@@ -2271,6 +2897,15 @@ impl MetaStore for RocksMetaStore {
             let schema_id =
                 rocks_schema.get_single_row_by_index(&schema_name, &SchemaRocksIndex::Name)?;
             let index_cols = columns.clone();
+            let default_cols = default_index_columns(&table_name, &columns, &sort_order)?;
+            // Tables co-located with a co-located table join the group of its root.
+            let colocate_with = match colocate_with {
+                Some(id) => {
+                    let target = rocks_table.get_row_or_not_found(id)?;
+                    Some(target.get_row().colocate_with().unwrap_or(id))
+                }
+                None => None,
+            };
             let table = Table::new(
                 table_name,
                 schema_id.get_id(),
@@ -2278,7 +2913,11 @@ impl MetaStore for RocksMetaStore {
                 locations,
                 import_format,
                 is_ready,
-            );
+                storage,
+                colocate_with,
+            )
+            .set_source_secret(source_secret)
+            .set_deduplicate(deduplicate);
             let table_id = rocks_table.insert(table, batch_pipe)?;
             for index_def in indexes.into_iter() {
                 RocksMetaStore::add_index(
@@ -2288,16 +2927,13 @@ impl MetaStore for RocksMetaStore {
                     &index_cols,
                     &table_id,
                     index_def,
+                    true,
                 )?;
             }
 
-            let (mut sorted, mut unsorted) =
-                index_cols.clone().into_iter().partition::<Vec<_>, _>(|c| {
-                    match c.get_column_type() {
-                        ColumnType::Decimal { .. } | ColumnType::Bytes | ColumnType::Float => false,
-                        _ => true,
-                    }
-                });
+            let (mut sorted, mut unsorted) = default_cols
+                .into_iter()
+                .partition::<Vec<_>, _>(|c| is_sortable(c.get_column_type()));
 
             let sorted_key_size = sorted.len() as u64;
             sorted.append(&mut unsorted);
@@ -2312,8 +2948,20 @@ impl MetaStore for RocksMetaStore {
                     .collect::<Vec<_>>(),
                 sorted_key_size,
             )?;
+            if let Some(root) = colocate_with {
+                let root_index = get_default_index_impl(db_ref.clone(), root)?;
+                if index.colocation_key_size(root_index.get_row()) == 0 {
+                    let root_table = rocks_table.get_row_or_not_found(root)?;
+                    return Err(CubeError::user(format!(
+                        "Can't co-locate {} with {}: first sort key columns of their default indexes must have the same type",
+                        table_id.get_row().get_table_name(),
+                        root_table.get_row().get_table_name()
+                    )));
+                }
+            }
             let index_id = rocks_index.insert(index, batch_pipe)?;
-            let partition = Partition::new(index_id.id, None, None);
+            let partition = Partition::new(index_id.id, None, None)
+                .set_storage(table_id.get_row().storage().clone());
             let _ = rocks_partition.insert(partition, batch_pipe)?;
 
             Ok(table_id)
@@ -2329,47 +2977,169 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
-    async fn get_table(
+    async fn attach_table(
         &self,
         schema_name: String,
         table_name: String,
+        columns: Vec<Column>,
+        sort_key_size: u64,
+        location: String,
+        files: Vec<AttachedFile>,
+        version: Option<u64>,
     ) -> Result<IdRow<Table>, CubeError> {
-        self.read_operation(move |db_ref| get_table_impl(db_ref, schema_name, table_name))
-            .await
-    }
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_table = TableRocksTable::new(db_ref.clone());
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_schema = SchemaRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
 
-    async fn get_table_by_id(&self, table_id: u64) -> Result<IdRow<Table>, CubeError> {
-        self.read_operation(move |db_ref| {
-            TableRocksTable::new(db_ref.clone()).get_row_or_not_found(table_id)
-        })
-        .await
-    }
+            let schema_id =
+                rocks_schema.get_single_row_by_index(&schema_name, &SchemaRocksIndex::Name)?;
+            let table = Table::new(
+                table_name,
+                schema_id.get_id(),
+                columns.clone(),
+                None,
+                None,
+                true,
+                None,
+                None,
+            )
+            .set_attached_location(Some(location))
+            .set_attached_version(version)
+            .update_has_data(!files.is_empty());
+            let table_id = rocks_table.insert(table, batch_pipe)?;
 
-    async fn get_tables(&self) -> Result<Vec<IdRow<Table>>, CubeError> {
-        self.read_operation(|db_ref| TableRocksTable::new(db_ref).all_rows())
-            .await
-    }
+            let index = Index::try_new(
+                "default".to_string(),
+                table_id.get_id(),
+                columns,
+                sort_key_size,
+            )?;
+            let index_id = rocks_index.insert(index, batch_pipe)?;
+            if files.is_empty() {
+                rocks_partition
+                    .insert(Partition::new(index_id.get_id(), None, None), batch_pipe)?;
+            }
+            for f in files.into_iter() {
+                rocks_partition.insert(f.into_partition(index_id.get_id()), batch_pipe)?;
+            }
 
-    async fn get_tables_with_path(&self) -> Result<Vec<TablePath>, CubeError> {
-        self.read_operation(|db_ref| {
-            let tables = TableRocksTable::new(db_ref.clone())
-                .all_rows()?
-                .into_iter()
-                .filter(|t| t.get_row().is_ready())
-                .collect::<Vec<_>>();
-            let schemas = SchemaRocksTable::new(db_ref);
-            Ok(schemas.build_path_rows(
-                tables,
-                |t| t.get_row().get_schema_id(),
-                |table, schema| TablePath { table, schema },
-            )?)
+            Ok(table_id)
         })
         .await
     }
 
-    async fn drop_table(&self, table_id: u64) -> Result<IdRow<Table>, CubeError> {
+    async fn refresh_attached_table(
+        &self,
+        table_id: u64,
+        version: u64,
+        files: Vec<AttachedFile>,
+    ) -> Result<IdRow<Table>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
-            let tables_table = TableRocksTable::new(db_ref.clone());
+            let rocks_table = TableRocksTable::new(db_ref.clone());
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
+
+            // Attached tables only have the default index.
+            let index_id = rocks_index
+                .get_single_row_by_index(
+                    &IndexIndexKey::TableId(table_id),
+                    &IndexRocksIndex::TableID,
+                )?
+                .get_id();
+            let mut existing = HashMap::new();
+            let mut roots = Vec::new();
+            for p in rocks_partition.get_rows_by_index(
+                &PartitionIndexKey::ByIndexId(index_id),
+                &PartitionRocksIndex::IndexId,
+            )? {
+                match p.get_row().attached_file() {
+                    Some(file) => {
+                        existing.insert(file.clone(), p);
+                    }
+                    None => roots.push(p),
+                }
+            }
+
+            let has_data = !files.is_empty();
+            for f in files.into_iter() {
+                match existing.remove(&f.path) {
+                    Some(p) if p.get_row().deleted_rows() == &f.deleted_rows => {}
+                    Some(p) => {
+                        rocks_partition.delete(p.get_id(), batch_pipe)?;
+                        rocks_partition.insert(f.into_partition(index_id), batch_pipe)?;
+                    }
+                    None => {
+                        rocks_partition.insert(f.into_partition(index_id), batch_pipe)?;
+                    }
+                }
+            }
+            for p in existing.values() {
+                rocks_partition.delete(p.get_id(), batch_pipe)?;
+            }
+            // Tables without files keep a single partition without a file.
+            if has_data {
+                for p in roots {
+                    rocks_partition.delete(p.get_id(), batch_pipe)?;
+                }
+            } else if roots.is_empty() {
+                rocks_partition.insert(Partition::new(index_id, None, None), batch_pipe)?;
+            }
+
+            rocks_table.update_with_fn(
+                table_id,
+                |t| {
+                    t.update_has_data(has_data)
+                        .set_attached_version(Some(version))
+                },
+                batch_pipe,
+            )
+        })
+        .await
+    }
+
+    async fn get_table(
+        &self,
+        schema_name: String,
+        table_name: String,
+    ) -> Result<IdRow<Table>, CubeError> {
+        self.read_operation(move |db_ref| get_table_impl(db_ref, schema_name, table_name))
+            .await
+    }
+
+    async fn get_table_by_id(&self, table_id: u64) -> Result<IdRow<Table>, CubeError> {
+        self.read_operation(move |db_ref| {
+            TableRocksTable::new(db_ref.clone()).get_row_or_not_found(table_id)
+        })
+        .await
+    }
+
+    async fn get_tables(&self) -> Result<Vec<IdRow<Table>>, CubeError> {
+        self.read_operation(|db_ref| TableRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn get_tables_with_path(&self) -> Result<Vec<TablePath>, CubeError> {
+        self.read_operation(|db_ref| {
+            let tables = TableRocksTable::new(db_ref.clone())
+                .all_rows()?
+                .into_iter()
+                .filter(|t| t.get_row().is_ready())
+                .collect::<Vec<_>>();
+            let schemas = SchemaRocksTable::new(db_ref);
+            Ok(schemas.build_path_rows(
+                tables,
+                |t| t.get_row().get_schema_id(),
+                |table, schema| TablePath { table, schema },
+            )?)
+        })
+        .await
+    }
+
+    async fn drop_table(&self, table_id: u64) -> Result<IdRow<Table>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let tables_table = TableRocksTable::new(db_ref.clone());
             let indexes_table = IndexRocksTable::new(db_ref.clone());
             let partitions_table = PartitionRocksTable::new(db_ref.clone());
             let chunks_table = ChunkRocksTable::new(db_ref);
@@ -2473,6 +3243,10 @@ impl MetaStore for RocksMetaStore {
 
             let mut deactivated_row_count = 0;
             let mut activated_row_count = 0;
+            // New partitions replace the current ones for readers, so they take over the access
+            // statistics.
+            let mut inherited_reads = 0;
+            let mut inherited_last_read = None;
 
             for current in current_active.iter() {
                 let current_partition =
@@ -2495,7 +3269,9 @@ impl MetaStore for RocksMetaStore {
                     current_partition.get_row(),
                     batch_pipe,
                 )?;
-                deactivated_row_count += current_partition.get_row().main_table_row_count()
+                deactivated_row_count += current_partition.get_row().main_table_row_count();
+                inherited_reads += current_partition.get_row().read_count();
+                inherited_last_read = inherited_last_read.max(*current_partition.get_row().last_used());
             }
 
             for (new, (count, (min_value, max_value))) in
@@ -2516,7 +3292,8 @@ impl MetaStore for RocksMetaStore {
                     new_partition
                         .get_row()
                         .to_active(true)
-                        .update_min_max_and_row_count(min_value, max_value, count),
+                        .update_min_max_and_row_count(min_value, max_value, count)
+                        .add_reads(inherited_reads, inherited_last_read),
                     new_partition.get_row(),
                     batch_pipe,
                 )?;
@@ -2566,6 +3343,27 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn record_partition_reads(
+        &self,
+        reads: Vec<(u64, u64, DateTime<Utc>)>,
+    ) -> Result<(), CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = PartitionRocksTable::new(db_ref);
+            for (partition_id, reads, last_read) in reads {
+                if let Some(partition) = table.get_row(partition_id)? {
+                    table.update(
+                        partition_id,
+                        partition.row.add_reads(reads, Some(last_read)),
+                        &partition.row,
+                        batch_pipe,
+                    )?;
+                }
+            }
+            Ok(())
+        })
+        .await
+    }
+
     fn index_table(&self) -> IndexMetaStoreTable {
         IndexMetaStoreTable {
             rocks_meta_store: self.clone(),
@@ -2606,11 +3404,125 @@ impl MetaStore for RocksMetaStore {
                 table.get_row().get_columns(),
                 &table,
                 index_def,
+                true,
             )?)
         })
         .await
     }
 
+    async fn start_index_build(
+        &self,
+        schema_name: String,
+        table_name: String,
+        index_def: IndexDef,
+    ) -> Result<(IdRow<Index>, PartitionsAndChunks), CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
+            let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
+            let table = RocksMetaStore::get_table_by_name(
+                schema_name,
+                table_name,
+                TableRocksTable::new(db_ref.clone()),
+                SchemaRocksTable::new(db_ref.clone()),
+            )?;
+
+            // Rows of WALs partitioned before the index exists would miss it.
+            let wals = WALRocksTable::new(db_ref.clone()).get_rows_by_index(
+                &WALIndexKey::ByTable(table.get_id()),
+                &WALRocksIndex::TableID,
+            )?;
+            if !wals.is_empty() {
+                return Err(CubeError::user(format!(
+                    "Can't create '{}' index while inserts into '{}' table are in progress, \
+                     please retry",
+                    index_def.name,
+                    table.get_row().get_table_name()
+                )));
+            }
+
+            let default_index = get_default_index_impl(db_ref, table.get_id())?;
+            let partitions = rocks_partition
+                .get_rows_by_index(
+                    &PartitionIndexKey::ByIndexId(default_index.get_id()),
+                    &PartitionRocksIndex::IndexId,
+                )?
+                .into_iter()
+                .filter(|p| p.get_row().is_active())
+                .map(|p| -> Result<_, CubeError> {
+                    let chunks = Self::chunks_by_partitioned_with_non_repartitioned(
+                        p.get_id(),
+                        &rocks_chunk,
+                        &rocks_partition,
+                    )?;
+                    Ok((p, chunks))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let index = RocksMetaStore::add_index(
+                batch_pipe,
+                &rocks_index,
+                &rocks_partition,
+                table.get_row().get_columns(),
+                &table,
+                index_def,
+                false,
+            )?;
+            Ok((index, partitions))
+        })
+        .await
+    }
+
+    async fn finish_index_build(
+        &self,
+        index_id: u64,
+        uploaded_chunk_ids: Vec<u64>,
+    ) -> Result<IdRow<Index>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            // Fails if the table was dropped meanwhile.
+            rocks_index.get_row_or_not_found(index_id)?;
+            Self::activate_chunks_impl(db_ref, batch_pipe, &uploaded_chunk_ids)?;
+            Ok(rocks_index.update_with_fn(index_id, |i| i.update_is_ready(true), batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn abort_index_build(&self, index_id: u64) -> Result<(), CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_index = IndexRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
+            let rocks_chunk = ChunkRocksTable::new(db_ref);
+            let index = match rocks_index.get_row(index_id)? {
+                Some(index) => index,
+                None => return Ok(()),
+            };
+            if index.get_row().is_ready() {
+                return Err(CubeError::internal(format!(
+                    "Can't abort build of ready index {}",
+                    index_id
+                )));
+            }
+            let partitions = rocks_partition.get_rows_by_index(
+                &PartitionIndexKey::ByIndexId(index_id),
+                &PartitionRocksIndex::IndexId,
+            )?;
+            for partition in partitions.into_iter() {
+                let chunks = rocks_chunk.get_rows_by_index(
+                    &ChunkIndexKey::ByPartitionId(partition.get_id()),
+                    &ChunkRocksIndex::PartitionId,
+                )?;
+                for chunk in chunks.into_iter() {
+                    rocks_chunk.delete(chunk.get_id(), batch_pipe)?;
+                }
+                rocks_partition.delete(partition.get_id(), batch_pipe)?;
+            }
+            rocks_index.delete(index_id, batch_pipe)?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn get_default_index(&self, table_id: u64) -> Result<IdRow<Index>, CubeError> {
         self.read_operation(move |db_ref| get_default_index_impl(db_ref, table_id))
             .await
@@ -2644,6 +3556,24 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn get_inactive_partitions_by_index_id(
+        &self,
+        index_id: u64,
+    ) -> Result<Vec<IdRow<Partition>>, CubeError> {
+        self.read_operation(move |db_ref| {
+            let rocks_partition = PartitionRocksTable::new(db_ref);
+            Ok(rocks_partition
+                .get_rows_by_index(
+                    &PartitionIndexKey::ByIndexId(index_id),
+                    &PartitionRocksIndex::IndexId,
+                )?
+                .into_iter()
+                .filter(|r| !r.get_row().active)
+                .collect::<Vec<_>>())
+        })
+        .await
+    }
+
     async fn get_index(&self, index_id: u64) -> Result<IdRow<Index>, CubeError> {
         self.read_operation(move |db_ref| {
             IndexRocksTable::new(db_ref).get_row_or_not_found(index_id)
@@ -2654,41 +3584,26 @@ impl MetaStore for RocksMetaStore {
     async fn get_active_partitions_and_chunks_by_index_id_for_select(
         &self,
         index_id: Vec<u64>,
-    ) -> Result<Vec<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>, CubeError> {
-        self.read_operation(move |db_ref| {
-            let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
-            let rocks_partition = PartitionRocksTable::new(db_ref);
-
-            let mut results = Vec::with_capacity(index_id.len());
-            for index_id in index_id {
-                // TODO iterate over range
-                let result = rocks_partition
-                    .get_rows_by_index(
-                        &PartitionIndexKey::ByIndexId(index_id),
-                        &PartitionRocksIndex::IndexId,
-                    )?
-                    .into_iter()
-                    .filter(|r| r.get_row().active)
-                    .map(|p| -> Result<_, CubeError> {
-                        let chunks = Self::chunks_by_partitioned_with_non_repartitioned(
-                            p.get_id(),
-                            &rocks_chunk,
-                            &rocks_partition,
-                        )?;
-                        Ok((p, chunks))
-                    })
-                    .collect::<Result<Vec<_>, _>>()?;
-                results.push(result)
-            }
-            Ok(results)
-        })
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError> {
+        // Indexes are read in separate snapshots, which is fine as long as the snapshot of each
+        // index is consistent.
+        let concurrency = max(self.config.metastore_read_concurrency() as usize, 1);
+        stream::iter(
+            index_id
+                .into_iter()
+                .map(|index_id| self.active_partitions_and_chunks_for_select(index_id)),
+        )
+        .buffered(concurrency)
+        .try_collect()
         .await
     }
 
     async fn get_warmup_partitions(&self) -> Result<Vec<(PartitionName, Vec<u64>)>, CubeError> {
-        self.read_operation(|db| {
+        let replicated_table_max_rows = self.config.replicated_table_max_rows();
+        self.read_operation(move |db| {
             // Do full scan, likely only a small number chunks and partitions are inactive.
             let mut partition_to_chunks = HashMap::new();
+            let mut partition_chunk_rows = HashMap::new();
             for c in ChunkRocksTable::new(db.clone()).table_scan(db.snapshot)? {
                 let c = c?;
                 if !c.row.active() {
@@ -2697,35 +3612,61 @@ impl MetaStore for RocksMetaStore {
                 partition_to_chunks
                     .entry(c.row.partition_id)
                     .or_insert(Vec::new())
-                    .push(c.id)
+                    .push(c.id);
+                *partition_chunk_rows.entry(c.row.partition_id).or_insert(0) +=
+                    c.row.get_row_count();
             }
 
-            let mut partitions = Vec::new();
+            let mut active = Vec::new();
+            let mut index_rows = HashMap::new();
             for p in PartitionRocksTable::new(db.clone()).table_scan(db.snapshot)? {
                 let p = p?;
                 if p.row.is_active() {
-                    let mut chunks = Vec::new();
-                    chunks.extend(partition_to_chunks.entry(p.id).or_default().iter().cloned());
-                    if let Some(parent_id) = p.row.parent_partition_id {
-                        chunks.extend(
-                            partition_to_chunks
-                                .entry(parent_id)
-                                .or_default()
-                                .iter()
-                                .cloned(),
-                        );
-                    }
+                    *index_rows.entry(p.row.index_id).or_insert(0) += p.row.main_table_row_count()
+                        + partition_chunk_rows.get(&p.id).cloned().unwrap_or(0);
+                    active.push(p);
+                }
+            }
 
-                    partitions.push((
-                        PartitionName {
-                            parent_partition_id: p.row.parent_partition_id,
-                            partition_id: p.id,
-                        },
-                        chunks,
-                    ));
+            let mut partitions = Vec::new();
+            for p in active {
+                let mut chunks = Vec::new();
+                chunks.extend(partition_to_chunks.entry(p.id).or_default().iter().cloned());
+                if let Some(parent_id) = p.row.parent_partition_id {
+                    chunks.extend(
+                        partition_to_chunks
+                            .entry(parent_id)
+                            .or_default()
+                            .iter()
+                            .cloned(),
+                    );
                 }
+
+                partitions.push((
+                    PartitionName {
+                        parent_partition_id: p.row.parent_partition_id,
+                        partition_id: p.id,
+                        storage: p.row.storage.clone(),
+                        placement_id: Some(p.row.placement_id(p.id)),
+                        attached_file: p.row.attached_file.clone(),
+                        last_read: p.row.last_used.clone(),
+                        replicated: is_replicated_table(
+                            index_rows[&p.row.index_id],
+                            replicated_table_max_rows,
+                        ),
+                    },
+                    chunks,
+                    p.row.read_count,
+                ));
             }
-            Ok(partitions)
+            // Hottest first: recently read ones, then the most read ones.
+            partitions.sort_by(|(a, _, a_reads), (b, _, b_reads)| {
+                (b.last_read, b_reads).cmp(&(a.last_read, a_reads))
+            });
+            Ok(partitions
+                .into_iter()
+                .map(|(p, chunks, _)| (p, chunks))
+                .collect())
         })
         .await
     }
@@ -2737,8 +3678,11 @@ impl MetaStore for RocksMetaStore {
     ) -> Result<IdRow<Chunk>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
+            let partition =
+                PartitionRocksTable::new(db_ref.clone()).get_row_or_not_found(partition_id)?;
 
-            let chunk = Chunk::new(partition_id, row_count);
+            let chunk = Chunk::new(partition_id, row_count, None)
+                .set_storage(partition.get_row().storage().clone());
             let id_row = rocks_chunk.insert(chunk, batch_pipe)?;
 
             Ok(id_row)
@@ -2746,6 +3690,26 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn create_chunks(
+        &self,
+        chunks: Vec<(u64, usize, Option<ColumnBounds>)>,
+    ) -> Result<Vec<IdRow<Chunk>>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let rocks_chunk = ChunkRocksTable::new(db_ref.clone());
+            let rocks_partition = PartitionRocksTable::new(db_ref.clone());
+            chunks
+                .into_iter()
+                .map(|(partition_id, row_count, column_bounds)| {
+                    let partition = rocks_partition.get_row_or_not_found(partition_id)?;
+                    let chunk = Chunk::new(partition_id, row_count, column_bounds)
+                        .set_storage(partition.get_row().storage().clone());
+                    rocks_chunk.insert(chunk, batch_pipe)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+    }
+
     async fn get_chunk(&self, chunk_id: u64) -> Result<IdRow<Chunk>, CubeError> {
         self.read_operation(move |db_ref| {
             ChunkRocksTable::new(db_ref).get_row_or_not_found(chunk_id)
@@ -2800,6 +3764,15 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn deactivate_chunks(&self, chunk_ids: Vec<u64>) -> Result<(), CubeError> {
+        trace!("Deactivating chunks ({})", chunk_ids.iter().join(", "));
+        self.write_operation(move |db_ref, batch_pipe| {
+            Self::deactivate_chunks_impl(db_ref, batch_pipe, &chunk_ids)?;
+            Ok(())
+        })
+        .await
+    }
+
     async fn activate_wal(
         &self,
         wal_id_to_delete: u64,
@@ -2866,17 +3839,10 @@ impl MetaStore for RocksMetaStore {
             uploaded_ids.iter().join(", ")
         );
         self.write_operation(move |db_ref, batch_pipe| {
-            let table = ChunkRocksTable::new(db_ref.clone());
-            let mut deactivated_row_count = 0;
-            let mut activated_row_count = 0;
-            for id in deactivate_ids.iter() {
-                deactivated_row_count += table.get_row_or_not_found(*id)?.get_row().get_row_count();
-                table.update_with_fn(*id, |row| row.deactivate(), batch_pipe)?;
-            }
-            for id in uploaded_ids.iter() {
-                activated_row_count += table.get_row_or_not_found(*id)?.get_row().get_row_count();
-                table.update_with_fn(*id, |row| row.set_uploaded(true), batch_pipe)?;
-            }
+            let deactivated_row_count =
+                Self::deactivate_chunks_impl(db_ref.clone(), batch_pipe, &deactivate_ids)?;
+            let activated_row_count =
+                Self::activate_chunks_impl(db_ref, batch_pipe, &uploaded_ids)?;
             if deactivate_ids.len() > 0 && activated_row_count != deactivated_row_count {
                 return Err(CubeError::internal(format!(
                     "Deactivated row count ({}) doesn't match activated row count ({}) during swap of ({}) to ({}) chunks",
@@ -2907,6 +3873,26 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn delete_chunks(&self, chunk_ids: Vec<u64>) -> Result<Vec<IdRow<Chunk>>, CubeError> {
+        trace!("Deleting chunks ({})", chunk_ids.iter().join(", "));
+        self.write_operation(move |db_ref, batch_pipe| {
+            let chunks = ChunkRocksTable::new(db_ref.clone());
+            let mut deleted = Vec::with_capacity(chunk_ids.len());
+            for chunk_id in chunk_ids {
+                let chunk = chunks.get_row_or_not_found(chunk_id)?;
+                if chunk.get_row().active() {
+                    return Err(CubeError::internal(format!(
+                        "Can't remove active chunk #{}. It should be deactivated first",
+                        chunk_id
+                    )));
+                }
+                deleted.push(chunks.delete(chunk_id, batch_pipe)?);
+            }
+            Ok(deleted)
+        })
+        .await
+    }
+
     fn chunks_table(&self) -> ChunkMetaStoreTable {
         ChunkMetaStoreTable {
             rocks_meta_store: self.clone(),
@@ -3000,9 +3986,30 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn cancel_job(&self, job_id: u64) -> Result<IdRow<Job>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = JobRocksTable::new(db_ref.clone());
+            let job = table.get_row_or_not_found(job_id)?;
+            if let JobStatus::ProcessingBy(node) = job.get_row().status() {
+                return Err(CubeError::user(format!(
+                    "Job {} is already processing by {} and can't be cancelled",
+                    job_id, node
+                )));
+            }
+            Ok(table.delete(job_id, batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn all_jobs(&self) -> Result<Vec<IdRow<Job>>, CubeError> {
+        self.read_operation(move |db_ref| Ok(JobRocksTable::new(db_ref).all_rows()?))
+            .await
+    }
+
     async fn start_processing_job(
         &self,
         server_name: String,
+        job_class: JobClass,
     ) -> Result<Option<IdRow<Job>>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             let table = JobRocksTable::new(db_ref);
@@ -3012,7 +4019,7 @@ impl MetaStore for RocksMetaStore {
                     &JobRocksIndex::ByShard,
                 )?
                 .into_iter()
-                .nth(0);
+                .find(|j| j.get_row().job_type().class() == job_class);
             if let Some(job) = next_job {
                 if let JobStatus::ProcessingBy(node) = job.get_row().status() {
                     return Err(CubeError::internal(format!(
@@ -3043,6 +4050,21 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn update_job_progress(
+        &self,
+        job_id: u64,
+        progress: JobProgress,
+    ) -> Result<IdRow<Job>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            Ok(JobRocksTable::new(db_ref).update_with_fn(
+                job_id,
+                |row| row.update_progress(progress),
+                batch_pipe,
+            )?)
+        })
+        .await
+    }
+
     async fn update_status(&self, job_id: u64, status: JobStatus) -> Result<IdRow<Job>, CubeError> {
         self.write_operation(move |db_ref, batch_pipe| {
             Ok(JobRocksTable::new(db_ref).update_with_fn(
@@ -3054,13 +4076,24 @@ impl MetaStore for RocksMetaStore {
         .await
     }
 
+    async fn complete_job(&self, job_id: u64, result: JobResult) -> Result<IdRow<Job>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            Ok(JobRocksTable::new(db_ref).update_with_fn(
+                job_id,
+                |row| row.completed(result),
+                batch_pipe,
+            )?)
+        })
+        .await
+    }
+
     async fn get_tables_with_indexes(
         &self,
         table_name: Vec<(String, String)>,
     ) -> Result<Vec<(IdRow<Schema>, IdRow<Table>, Vec<IdRow<Index>>)>, CubeError> {
-        self.read_operation(|db| {
-            let mut r = Vec::with_capacity(table_name.len());
-            for (schema, table) in table_name {
+        let concurrency = max(self.config.metastore_read_concurrency() as usize, 1);
+        stream::iter(table_name.into_iter().map(|(schema, table)| {
+            self.read_operation(move |db| {
                 let table = get_table_impl(db.clone(), schema, table)?;
                 let schema = SchemaRocksTable::new(db.clone())
                     .get_row_or_not_found(table.get_row().get_schema_id())?;
@@ -3072,16 +4105,204 @@ impl MetaStore for RocksMetaStore {
                 )?;
                 indexes.insert(0, get_default_index_impl(db.clone(), table.get_id())?);
 
-                r.push((schema, table, indexes))
+                Ok((schema, table, indexes))
+            })
+        }))
+        .buffered(concurrency)
+        .try_collect()
+        .await
+    }
+
+    async fn create_secret(
+        &self,
+        name: String,
+        value: SecretValue,
+        or_replace: bool,
+    ) -> Result<IdRow<Secret>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = SecretRocksTable::new(db_ref.clone());
+            let existing = table.get_rows_by_index(&name, &SecretRocksIndex::Name)?;
+            let secret = Secret::new(name.clone(), value);
+            match existing.into_iter().nth(0) {
+                Some(_) if !or_replace => {
+                    Err(CubeError::user(format!("Secret {} already exists", name)))
+                }
+                Some(row) => Ok(table.update(row.get_id(), secret, row.get_row(), batch_pipe)?),
+                None => Ok(table.insert(secret, batch_pipe)?),
             }
-            Ok(r)
         })
         .await
     }
-}
 
-fn get_table_impl(
-    db_ref: DbTableRef,
+    async fn get_secret(&self, name: String) -> Result<IdRow<Secret>, CubeError> {
+        self.read_operation(move |db_ref| get_secret_impl(db_ref, &name))
+            .await
+    }
+
+    async fn get_secrets(&self) -> Result<Vec<IdRow<Secret>>, CubeError> {
+        self.read_operation(|db_ref| SecretRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn drop_secret(&self, name: String) -> Result<IdRow<Secret>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let secret = get_secret_impl(db_ref.clone(), &name)?;
+            let tables = TableRocksTable::new(db_ref.clone()).all_rows()?;
+            if let Some(t) = tables
+                .iter()
+                .find(|t| t.get_row().source_secret().as_ref() == Some(&name))
+            {
+                return Err(CubeError::user(format!(
+                    "Secret {} is used by table {} and can't be dropped",
+                    name,
+                    t.get_row().get_table_name()
+                )));
+            }
+            Ok(SecretRocksTable::new(db_ref).delete(secret.get_id(), batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn create_linked_server(
+        &self,
+        name: String,
+        location: String,
+        max_rows: Option<u64>,
+        or_replace: bool,
+    ) -> Result<IdRow<LinkedServer>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = LinkedServerRocksTable::new(db_ref.clone());
+            let existing = table.get_rows_by_index(&name, &LinkedServerRocksIndex::Name)?;
+            let server = LinkedServer::new(name.clone(), location, max_rows);
+            match existing.into_iter().nth(0) {
+                Some(_) if !or_replace => Err(CubeError::user(format!(
+                    "Linked server {} already exists",
+                    name
+                ))),
+                Some(row) => Ok(table.update(row.get_id(), server, row.get_row(), batch_pipe)?),
+                None => Ok(table.insert(server, batch_pipe)?),
+            }
+        })
+        .await
+    }
+
+    async fn get_linked_servers(&self) -> Result<Vec<IdRow<LinkedServer>>, CubeError> {
+        self.read_operation(|db_ref| LinkedServerRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn drop_linked_server(&self, name: String) -> Result<IdRow<LinkedServer>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = LinkedServerRocksTable::new(db_ref);
+            let server = table
+                .get_rows_by_index(&name, &LinkedServerRocksIndex::Name)?
+                .into_iter()
+                .nth(0)
+                .ok_or_else(|| CubeError::user(format!("Linked server {} does not exist", name)))?;
+            Ok(table.delete(server.get_id(), batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn create_pre_aggregation(
+        &self,
+        pre_aggregation: PreAggregation,
+    ) -> Result<IdRow<PreAggregation>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let schema = pre_aggregation.get_schema().to_string();
+            if SchemaRocksTable::new(db_ref.clone())
+                .get_rows_by_index(&schema, &SchemaRocksIndex::Name)?
+                .is_empty()
+            {
+                return Err(CubeError::user(format!("Schema {} does not exist", schema)));
+            }
+            let table = PreAggregationRocksTable::new(db_ref);
+            let name = format!("{}.{}", schema, pre_aggregation.get_name());
+            if !table
+                .get_rows_by_index(&name, &PreAggregationRocksIndex::Name)?
+                .is_empty()
+            {
+                return Err(CubeError::user(format!(
+                    "Pre-aggregation {} already exists",
+                    name
+                )));
+            }
+            Ok(table.insert(pre_aggregation, batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn get_pre_aggregation(
+        &self,
+        schema: String,
+        name: String,
+    ) -> Result<IdRow<PreAggregation>, CubeError> {
+        self.read_operation(move |db_ref| get_pre_aggregation_impl(db_ref, &schema, &name))
+            .await
+    }
+
+    async fn get_pre_aggregations(&self) -> Result<Vec<IdRow<PreAggregation>>, CubeError> {
+        self.read_operation(|db_ref| PreAggregationRocksTable::new(db_ref).all_rows())
+            .await
+    }
+
+    async fn update_pre_aggregation_state(
+        &self,
+        id: u64,
+        state: PreAggregationState,
+    ) -> Result<IdRow<PreAggregation>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let table = PreAggregationRocksTable::new(db_ref);
+            let row = table.get_row_or_not_found(id)?;
+            let updated = row.get_row().set_state(state);
+            Ok(table.update(id, updated, row.get_row(), batch_pipe)?)
+        })
+        .await
+    }
+
+    async fn drop_pre_aggregation(
+        &self,
+        schema: String,
+        name: String,
+    ) -> Result<IdRow<PreAggregation>, CubeError> {
+        self.write_operation(move |db_ref, batch_pipe| {
+            let row = get_pre_aggregation_impl(db_ref.clone(), &schema, &name)?;
+            Ok(PreAggregationRocksTable::new(db_ref).delete(row.get_id(), batch_pipe)?)
+        })
+        .await
+    }
+}
+
+fn get_pre_aggregation_impl(
+    db_ref: DbTableRef,
+    schema: &str,
+    name: &str,
+) -> Result<IdRow<PreAggregation>, CubeError> {
+    PreAggregationRocksTable::new(db_ref)
+        .get_rows_by_index(
+            &format!("{}.{}", schema, name),
+            &PreAggregationRocksIndex::Name,
+        )?
+        .into_iter()
+        .nth(0)
+        .ok_or_else(|| {
+            CubeError::user(format!(
+                "Pre-aggregation {}.{} does not exist",
+                schema, name
+            ))
+        })
+}
+
+fn get_secret_impl(db_ref: DbTableRef, name: &str) -> Result<IdRow<Secret>, CubeError> {
+    SecretRocksTable::new(db_ref)
+        .get_rows_by_index(&name.to_string(), &SecretRocksIndex::Name)?
+        .into_iter()
+        .nth(0)
+        .ok_or_else(|| CubeError::user(format!("Secret {} does not exist", name)))
+}
+
+fn get_table_impl(
+    db_ref: DbTableRef,
     schema_name: String,
     table_name: String,
 ) -> Result<IdRow<Table>, CubeError> {
@@ -3092,6 +4313,54 @@ fn get_table_impl(
     Ok(table)
 }
 
+/// Decimals, floats and bytes are kept out of the sort key of default indexes.
+fn is_sortable(t: &ColumnType) -> bool {
+    match t {
+        ColumnType::Decimal { .. } | ColumnType::Bytes | ColumnType::Float => false,
+        _ => true,
+    }
+}
+
+/// Columns of the table with the ones of `sort_order` moved to the front.
+fn default_index_columns(
+    table_name: &str,
+    columns: &[Column],
+    sort_order: &[String],
+) -> Result<Vec<Column>, CubeError> {
+    let mut result = Vec::with_capacity(columns.len());
+    for name in sort_order {
+        let c = match columns.iter().find(|c| &c.name == name) {
+            Some(c) => c,
+            None => {
+                return Err(CubeError::user(format!(
+                    "Column {} in the sort order not found in table {}",
+                    name, table_name
+                )))
+            }
+        };
+        if !is_sortable(c.get_column_type()) {
+            return Err(CubeError::user(format!(
+                "Column {} can't be used in the sort order of table {}",
+                c, table_name
+            )));
+        }
+        if result.contains(c) {
+            return Err(CubeError::user(format!(
+                "Column {} is repeated in the sort order of table {}",
+                name, table_name
+            )));
+        }
+        result.push(c.clone());
+    }
+    result.extend(
+        columns
+            .iter()
+            .filter(|c| !sort_order.contains(&c.name))
+            .cloned(),
+    );
+    Ok(result)
+}
+
 fn get_default_index_impl(db_ref: DbTableRef, table_id: u64) -> Result<IdRow<Index>, CubeError> {
     let index = IndexRocksTable::new(db_ref);
     let indexes = index.get_rows_by_index(
@@ -3396,6 +4665,11 @@ mod tests {
                     None,
                     vec![],
                     true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
                 )
                 .await
                 .unwrap();
@@ -3410,7 +4684,12 @@ mod tests {
                     None,
                     None,
                     vec![],
-                    true
+                    true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
                 )
                 .await
                 .is_err());
@@ -3437,6 +4716,360 @@ mod tests {
         let _ = fs::remove_dir_all(remote_store_path.clone());
     }
 
+    #[tokio::test]
+    async fn table_sort_order() {
+        let (_, meta_store) = RocksMetaStore::prepare_test_metastore("table_sort_order");
+        meta_store
+            .create_schema("foo".to_string(), false)
+            .await
+            .unwrap();
+        let columns = vec![
+            Column::new("id".to_string(), ColumnType::Int, 0),
+            Column::new("amount".to_string(), ColumnType::Float, 1),
+            Column::new("tenant".to_string(), ColumnType::String, 2),
+            Column::new("ts".to_string(), ColumnType::Timestamp, 3),
+        ];
+        let create = |name: &str, sort_order: Vec<&str>| {
+            meta_store.create_table(
+                "foo".to_string(),
+                name.to_string(),
+                columns.clone(),
+                None,
+                None,
+                vec![],
+                true,
+                None,
+                None,
+                None,
+                sort_order.into_iter().map(|c| c.to_string()).collect(),
+                false,
+            )
+        };
+
+        let table = create("events", vec!["tenant", "ts"]).await.unwrap();
+        let index = meta_store.get_default_index(table.get_id()).await.unwrap();
+        let names = index
+            .get_row()
+            .get_columns()
+            .iter()
+            .map(|c| c.get_name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["tenant", "ts", "id", "amount"]);
+        assert_eq!(index.get_row().sort_key_size(), 3);
+
+        assert!(create("missing", vec!["day"]).await.is_err());
+        assert!(create("float", vec!["amount"]).await.is_err());
+        assert!(create("repeated", vec!["ts", "ts"]).await.is_err());
+
+        RocksMetaStore::cleanup_test_metastore("table_sort_order");
+    }
+
+    #[tokio::test]
+    async fn batch_chunks_test() {
+        let config = Config::test("batch_chunks_test");
+        let store_path = env::current_dir().unwrap().join("test-batch-chunks-local");
+        let remote_store_path = env::current_dir().unwrap().join("test-batch-chunks-remote");
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let remote_fs = LocalDirRemoteFs::new(Some(remote_store_path.clone()), store_path.clone());
+        {
+            let meta_store = RocksMetaStore::new(
+                store_path.clone().join("metastore").as_path(),
+                remote_fs,
+                config.config_obj(),
+            );
+
+            meta_store
+                .create_schema("foo".to_string(), false)
+                .await
+                .unwrap();
+            let table = meta_store
+                .create_table(
+                    "foo".to_string(),
+                    "boo".to_string(),
+                    vec![Column::new("col1".to_string(), ColumnType::Int, 0)],
+                    None,
+                    None,
+                    vec![],
+                    true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
+                )
+                .await
+                .unwrap();
+            let index = meta_store.get_default_index(table.get_id()).await.unwrap();
+            let partition = meta_store
+                .get_active_partitions_by_index_id(index.get_id())
+                .await
+                .unwrap()[0]
+                .get_id();
+
+            let chunks = meta_store
+                .create_chunks(vec![
+                    (partition, 10, None),
+                    (partition, 20, None),
+                    (partition, 30, None),
+                ])
+                .await
+                .unwrap();
+            let ids = chunks.iter().map(|c| c.get_id()).collect::<Vec<_>>();
+            assert_eq!(ids.len(), 3);
+            assert_eq!(chunks[1].get_row().get_row_count(), 20);
+
+            meta_store
+                .activate_chunks(table.get_id(), ids.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                meta_store
+                    .get_chunks_by_partition(partition, false)
+                    .await
+                    .unwrap()
+                    .len(),
+                3
+            );
+
+            // Active chunks can't be deleted.
+            assert!(meta_store.delete_chunks(ids.clone()).await.is_err());
+            assert_eq!(
+                meta_store
+                    .get_chunks_by_partition(partition, true)
+                    .await
+                    .unwrap()
+                    .len(),
+                3
+            );
+
+            meta_store.deactivate_chunks(ids.clone()).await.unwrap();
+            assert!(meta_store
+                .get_chunks_by_partition(partition, false)
+                .await
+                .unwrap()
+                .is_empty());
+
+            let deleted = meta_store.delete_chunks(ids.clone()).await.unwrap();
+            assert_eq!(deleted.len(), 3);
+            assert!(meta_store
+                .get_chunks_by_partition(partition, true)
+                .await
+                .unwrap()
+                .is_empty());
+        }
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+    }
+
+    #[tokio::test]
+    async fn select_partitions_cache_test() {
+        let config = Config::test("select_partitions_cache_test");
+        let store_path = env::current_dir()
+            .unwrap()
+            .join("test-select-partitions-cache-local");
+        let remote_store_path = env::current_dir()
+            .unwrap()
+            .join("test-select-partitions-cache-remote");
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let remote_fs = LocalDirRemoteFs::new(Some(remote_store_path.clone()), store_path.clone());
+        {
+            let meta_store = RocksMetaStore::new(
+                store_path.clone().join("metastore").as_path(),
+                remote_fs,
+                config.config_obj(),
+            );
+
+            meta_store
+                .create_schema("foo".to_string(), false)
+                .await
+                .unwrap();
+            let table = meta_store
+                .create_table(
+                    "foo".to_string(),
+                    "boo".to_string(),
+                    vec![Column::new("col1".to_string(), ColumnType::Int, 0)],
+                    None,
+                    None,
+                    vec![],
+                    true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
+                )
+                .await
+                .unwrap();
+            let other_table = meta_store
+                .create_table(
+                    "foo".to_string(),
+                    "other".to_string(),
+                    vec![Column::new("col1".to_string(), ColumnType::Int, 0)],
+                    None,
+                    None,
+                    vec![],
+                    true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
+                )
+                .await
+                .unwrap();
+            let index = meta_store.get_default_index(table.get_id()).await.unwrap();
+            let other_index = meta_store
+                .get_default_index(other_table.get_id())
+                .await
+                .unwrap();
+
+            let select = || {
+                meta_store.get_active_partitions_and_chunks_by_index_id_for_select(vec![
+                    index.get_id(),
+                    other_index.get_id(),
+                ])
+            };
+            let partitions = select().await.unwrap();
+            assert_eq!(partitions[0].len(), 1);
+            assert!(partitions[0][0].1.is_empty());
+            // Served from cache.
+            let cached = select().await.unwrap();
+            assert!(Arc::ptr_eq(&partitions[0], &cached[0]));
+            assert!(Arc::ptr_eq(&partitions[1], &cached[1]));
+            let other_partitions = partitions[1].clone();
+
+            let chunks = meta_store
+                .create_chunks(vec![(partitions[0][0].0.get_id(), 10, None)])
+                .await
+                .unwrap();
+            meta_store
+                .activate_chunks(table.get_id(), vec![chunks[0].get_id()])
+                .await
+                .unwrap();
+
+            let partitions = select().await.unwrap();
+            assert_eq!(partitions[0][0].1.len(), 1);
+            assert_eq!(partitions[0][0].1[0].get_id(), chunks[0].get_id());
+            // Only the index that got the chunk is evicted.
+            assert!(Arc::ptr_eq(&partitions[1], &other_partitions));
+        }
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+
+        let cache = SelectPartitionsCache::new(1);
+        cache.put(1, Arc::new(Vec::new())).unwrap();
+        cache.put(2, Arc::new(Vec::new())).unwrap();
+        assert!(cache.get(1).unwrap().is_none());
+        assert!(cache.get(2).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn parallel_select_test() {
+        let config = Config::test("parallel_select_test").update_config(|mut c| {
+            c.metastore_read_concurrency = 2;
+            c
+        });
+        let store_path = env::current_dir()
+            .unwrap()
+            .join("test-parallel-select-local");
+        let remote_store_path = env::current_dir()
+            .unwrap()
+            .join("test-parallel-select-remote");
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+        let remote_fs = LocalDirRemoteFs::new(Some(remote_store_path.clone()), store_path.clone());
+        {
+            let meta_store = RocksMetaStore::new(
+                store_path.clone().join("metastore").as_path(),
+                remote_fs,
+                config.config_obj(),
+            );
+
+            meta_store
+                .create_schema("foo".to_string(), false)
+                .await
+                .unwrap();
+            let mut tables = Vec::new();
+            for i in 0..5 {
+                tables.push(
+                    meta_store
+                        .create_table(
+                            "foo".to_string(),
+                            format!("t{}", i),
+                            vec![Column::new("col1".to_string(), ColumnType::Int, 0)],
+                            None,
+                            None,
+                            vec![],
+                            true,
+                            None,
+                            None,
+                            None,
+                            vec![],
+                            false,
+                        )
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            // Results keep the order of requests.
+            let with_indexes = meta_store
+                .get_tables_with_indexes(
+                    (0..5)
+                        .rev()
+                        .map(|i| ("foo".to_string(), format!("t{}", i)))
+                        .collect(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                with_indexes
+                    .iter()
+                    .map(|(_, t, _)| t.get_id())
+                    .collect_vec(),
+                tables.iter().rev().map(|t| t.get_id()).collect_vec()
+            );
+
+            let index_ids = with_indexes
+                .iter()
+                .map(|(_, _, indexes)| indexes[0].get_id())
+                .collect_vec();
+            let partitions = meta_store
+                .get_active_partitions_and_chunks_by_index_id_for_select(index_ids.clone())
+                .await
+                .unwrap();
+            assert_eq!(
+                partitions
+                    .iter()
+                    .map(|p| p[0].0.get_row().get_index_id())
+                    .collect_vec(),
+                index_ids
+            );
+        }
+        let _ = fs::remove_dir_all(store_path.clone());
+        let _ = fs::remove_dir_all(remote_store_path.clone());
+    }
+
+    #[test]
+    fn log_file_seq_order() {
+        let mut logs = vec![
+            "metastore-1-logs/100.flex".to_string(),
+            "metastore-1-logs/20.flex".to_string(),
+            "metastore-1-logs/3.flex".to_string(),
+        ];
+        logs.sort_by_key(|f| RocksMetaStore::log_file_seq(f));
+        assert_eq!(
+            logs,
+            vec![
+                "metastore-1-logs/3.flex".to_string(),
+                "metastore-1-logs/20.flex".to_string(),
+                "metastore-1-logs/100.flex".to_string(),
+            ]
+        );
+    }
+
     #[tokio::test]
     async fn cold_start_test() {
         {
@@ -2,10 +2,12 @@ use super::{
     BaseRocksSecondaryIndex, IndexId, Partition, RocksSecondaryIndex, RocksTable, TableId,
 };
 use crate::base_rocks_secondary_index;
-use crate::metastore::{IdRow, MetaStoreEvent};
+use crate::metastore::{ColumnBounds, IdRow, MetaStoreEvent};
+use crate::remotefs::storage::storage_file_name;
 use crate::rocks_table_impl;
 use crate::table::Row;
 use byteorder::{BigEndian, WriteBytesExt};
+use chrono::{DateTime, Utc};
 use rocksdb::DB;
 use serde::{Deserialize, Deserializer};
 
@@ -20,6 +22,12 @@ impl Partition {
             warmed_up: false,
             main_table_row_count: 0,
             last_used: None,
+            storage: None,
+            colocated_partition_id: None,
+            attached_file: None,
+            deleted_rows: Vec::new(),
+            read_count: 0,
+            column_bounds: None,
         }
     }
 
@@ -33,6 +41,12 @@ impl Partition {
             warmed_up: false,
             main_table_row_count: 0,
             last_used: None,
+            storage: self.storage.clone(),
+            colocated_partition_id: self.colocated_partition_id,
+            attached_file: None,
+            deleted_rows: Vec::new(),
+            read_count: 0,
+            column_bounds: None,
         }
     }
 
@@ -45,7 +59,57 @@ impl Partition {
     }
 
     pub fn get_full_name(&self, partition_id: u64) -> Option<String> {
+        if let Some(file) = &self.attached_file {
+            return Some(file.clone());
+        }
         partition_file_name(self.parent_partition_id, partition_id)
+            .map(|f| storage_file_name(&self.storage, f))
+    }
+
+    pub fn set_storage(&self, storage: Option<String>) -> Partition {
+        let mut p = self.clone();
+        p.storage = storage;
+        p
+    }
+
+    pub fn storage(&self) -> &Option<String> {
+        &self.storage
+    }
+
+    pub fn set_colocated_partition_id(&self, colocated_partition_id: Option<u64>) -> Partition {
+        let mut p = self.clone();
+        p.colocated_partition_id = colocated_partition_id;
+        p
+    }
+
+    pub fn colocated_partition_id(&self) -> &Option<u64> {
+        &self.colocated_partition_id
+    }
+
+    pub fn set_attached_file(&self, attached_file: Option<String>) -> Partition {
+        let mut p = self.clone();
+        p.attached_file = attached_file;
+        p
+    }
+
+    pub fn attached_file(&self) -> &Option<String> {
+        &self.attached_file
+    }
+
+    pub fn set_deleted_rows(&self, deleted_rows: Vec<u64>) -> Partition {
+        let mut p = self.clone();
+        p.deleted_rows = deleted_rows;
+        p
+    }
+
+    pub fn deleted_rows(&self) -> &Vec<u64> {
+        &self.deleted_rows
+    }
+
+    /// Id that decides which worker serves the partition. Partitions of co-located tables are
+    /// placed by the partition they are co-located with.
+    pub fn placement_id(&self, partition_id: u64) -> u64 {
+        self.colocated_partition_id.unwrap_or(partition_id)
     }
 
     pub fn to_active(&self, active: bool) -> Partition {
@@ -89,6 +153,32 @@ impl Partition {
         self.warmed_up
     }
 
+    /// Adds `reads` to access statistics, `last_read` is the time of the latest of them.
+    pub fn add_reads(&self, reads: u64, last_read: Option<DateTime<Utc>>) -> Partition {
+        let mut p = self.clone();
+        p.read_count += reads;
+        p.last_used = p.last_used.max(last_read);
+        p
+    }
+
+    pub fn column_bounds(&self) -> &Option<ColumnBounds> {
+        &self.column_bounds
+    }
+
+    pub fn set_column_bounds(&self, column_bounds: Option<ColumnBounds>) -> Partition {
+        let mut p = self.clone();
+        p.column_bounds = column_bounds;
+        p
+    }
+
+    pub fn read_count(&self) -> u64 {
+        self.read_count
+    }
+
+    pub fn last_used(&self) -> &Option<DateTime<Utc>> {
+        &self.last_used
+    }
+
     pub fn main_table_row_count(&self) -> u64 {
         self.main_table_row_count
     }
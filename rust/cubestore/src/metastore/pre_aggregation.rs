@@ -0,0 +1,258 @@
+use super::{BaseRocksSecondaryIndex, IndexId, RocksSecondaryIndex, RocksTable, TableId};
+use crate::base_rocks_secondary_index;
+use crate::metastore::{IdRow, MetaStoreEvent};
+use crate::rocks_table_impl;
+use crate::CubeError;
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use rocksdb::DB;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::str::FromStr;
+
+/// Rollup of a source table that is materialized into tables of the schema and used by queries
+/// it can answer, see [crate::queryplanner::pre_aggregations].
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct PreAggregation {
+    schema: String,
+    name: String,
+    /// Select with GROUP BY over a single table.
+    query: String,
+    /// Query with a single value, the rollup is rebuilt when the value changes.
+    refresh_key: Option<String>,
+    /// Rebuild interval of rollups without a refresh key.
+    refresh_every_secs: u64,
+    partitioning: Option<PreAggregationPartitioning>,
+    state: PreAggregationState,
+}
+
+/// Splits the rollup into a table per time range of a column of the source table.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct PreAggregationPartitioning {
+    pub time_dimension: String,
+    pub granularity: TimeGranularity,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash, Default)]
+pub struct PreAggregationState {
+    /// Incremented on every build, names of built tables include it.
+    pub version: u64,
+    /// Built tables ordered by time, empty until the first build.
+    pub partitions: Vec<PreAggregationPartition>,
+    pub refresh_key_value: Option<String>,
+    pub last_refresh: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// Tables replaced by the last builds. They are dropped after queries that may still read
+    /// them are done.
+    pub retired_tables: Vec<(u64, DateTime<Utc>)>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct PreAggregationPartition {
+    /// Start of the time range, [None] if the rollup is not partitioned.
+    pub start: Option<DateTime<Utc>>,
+    pub table_id: u64,
+    pub table_name: String,
+}
+
+impl PreAggregation {
+    pub fn new(
+        schema: String,
+        name: String,
+        query: String,
+        refresh_key: Option<String>,
+        refresh_every_secs: u64,
+        partitioning: Option<PreAggregationPartitioning>,
+    ) -> PreAggregation {
+        PreAggregation {
+            schema,
+            name,
+            query,
+            refresh_key,
+            refresh_every_secs,
+            partitioning,
+            state: PreAggregationState::default(),
+        }
+    }
+
+    pub fn get_schema(&self) -> &String {
+        &self.schema
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn query(&self) -> &String {
+        &self.query
+    }
+
+    pub fn refresh_key(&self) -> &Option<String> {
+        &self.refresh_key
+    }
+
+    pub fn refresh_every_secs(&self) -> u64 {
+        self.refresh_every_secs
+    }
+
+    pub fn partitioning(&self) -> &Option<PreAggregationPartitioning> {
+        &self.partitioning
+    }
+
+    pub fn state(&self) -> &PreAggregationState {
+        &self.state
+    }
+
+    pub fn set_state(&self, state: PreAggregationState) -> PreAggregation {
+        let mut p = self.clone();
+        p.state = state;
+        p
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum TimeGranularity {
+    Hour,
+    Day,
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl TimeGranularity {
+    /// Start of the range that contains `t`. Weeks start on Monday.
+    pub fn truncate(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let day = Utc.ymd(t.year(), t.month(), t.day()).and_hms(0, 0, 0);
+        match self {
+            TimeGranularity::Hour => day + Duration::hours(t.hour() as i64),
+            TimeGranularity::Day => day,
+            TimeGranularity::Week => {
+                day - Duration::days(t.weekday().num_days_from_monday() as i64)
+            }
+            TimeGranularity::Month => Utc.ymd(t.year(), t.month(), 1).and_hms(0, 0, 0),
+            TimeGranularity::Quarter => Utc
+                .ymd(t.year(), (t.month() - 1) / 3 * 3 + 1, 1)
+                .and_hms(0, 0, 0),
+            TimeGranularity::Year => Utc.ymd(t.year(), 1, 1).and_hms(0, 0, 0),
+        }
+    }
+
+    /// Start of the next range, `start` must be truncated.
+    pub fn next(&self, start: DateTime<Utc>) -> DateTime<Utc> {
+        let add_months = |months: u32| {
+            let m = start.month0() + months;
+            Utc.ymd(start.year() + (m / 12) as i32, m % 12 + 1, 1)
+                .and_hms(0, 0, 0)
+        };
+        match self {
+            TimeGranularity::Hour => start + Duration::hours(1),
+            TimeGranularity::Day => start + Duration::days(1),
+            TimeGranularity::Week => start + Duration::weeks(1),
+            TimeGranularity::Month => add_months(1),
+            TimeGranularity::Quarter => add_months(3),
+            TimeGranularity::Year => add_months(12),
+        }
+    }
+}
+
+impl FromStr for TimeGranularity {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "hour" => Ok(TimeGranularity::Hour),
+            "day" => Ok(TimeGranularity::Day),
+            "week" => Ok(TimeGranularity::Week),
+            "month" => Ok(TimeGranularity::Month),
+            "quarter" => Ok(TimeGranularity::Quarter),
+            "year" => Ok(TimeGranularity::Year),
+            _ => Err(CubeError::user(format!(
+                "Granularity must be one of hour, day, week, month, quarter or year, found: {}",
+                s
+            ))),
+        }
+    }
+}
+
+impl ToString for TimeGranularity {
+    fn to_string(&self) -> String {
+        match self {
+            TimeGranularity::Hour => "hour",
+            TimeGranularity::Day => "day",
+            TimeGranularity::Week => "week",
+            TimeGranularity::Month => "month",
+            TimeGranularity::Quarter => "quarter",
+            TimeGranularity::Year => "year",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum PreAggregationRocksIndex {
+    Name = 1,
+}
+
+rocks_table_impl!(
+    PreAggregation,
+    PreAggregationRocksTable,
+    TableId::PreAggregations,
+    { vec![Box::new(PreAggregationRocksIndex::Name)] }
+);
+
+base_rocks_secondary_index!(PreAggregation, PreAggregationRocksIndex);
+
+impl RocksSecondaryIndex<PreAggregation, String> for PreAggregationRocksIndex {
+    fn typed_key_by(&self, row: &PreAggregation) -> String {
+        match self {
+            PreAggregationRocksIndex::Name => format!("{}.{}", row.schema, row.name),
+        }
+    }
+
+    fn key_to_bytes(&self, key: &String) -> Vec<u8> {
+        key.as_bytes().to_vec()
+    }
+
+    fn is_unique(&self) -> bool {
+        match self {
+            PreAggregationRocksIndex::Name => true,
+        }
+    }
+
+    fn get_id(&self) -> IndexId {
+        *self as IndexId
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_granularity() {
+        let t = Utc.ymd(2021, 11, 17).and_hms(13, 45, 10);
+        let cases = [
+            (
+                TimeGranularity::Hour,
+                (2021, 11, 17, 13),
+                (2021, 11, 17, 14),
+            ),
+            (TimeGranularity::Day, (2021, 11, 17, 0), (2021, 11, 18, 0)),
+            (TimeGranularity::Week, (2021, 11, 15, 0), (2021, 11, 22, 0)),
+            (TimeGranularity::Month, (2021, 11, 1, 0), (2021, 12, 1, 0)),
+            (TimeGranularity::Quarter, (2021, 10, 1, 0), (2022, 1, 1, 0)),
+            (TimeGranularity::Year, (2021, 1, 1, 0), (2022, 1, 1, 0)),
+        ];
+        for (g, (y, m, d, h), (ny, nm, nd, nh)) in cases.iter().cloned() {
+            let start = g.truncate(t);
+            assert_eq!(start, Utc.ymd(y, m, d).and_hms(h, 0, 0), "{:?}", g);
+            assert_eq!(
+                g.next(start),
+                Utc.ymd(ny, nm, nd).and_hms(nh, 0, 0),
+                "{:?}",
+                g
+            );
+            assert_eq!(g.to_string().parse::<TimeGranularity>().unwrap(), g);
+        }
+        assert!("minute".parse::<TimeGranularity>().is_err());
+    }
+}
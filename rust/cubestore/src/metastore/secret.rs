@@ -0,0 +1,68 @@
+use super::{BaseRocksSecondaryIndex, IndexId, RocksSecondaryIndex, RocksTable, TableId};
+use crate::base_rocks_secondary_index;
+use crate::metastore::{IdRow, MetaStoreEvent};
+use crate::rocks_table_impl;
+use rocksdb::DB;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Named credentials of table sources, see [crate::secrets].
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub struct Secret {
+    name: String,
+    value: SecretValue,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Hash)]
+pub enum SecretValue {
+    /// Properties of the secret as a JSON object encrypted with AES-256-GCM.
+    Encrypted { nonce: Vec<u8>, data: Vec<u8> },
+    /// Location of the secret in an external secret store.
+    External(String),
+}
+
+impl Secret {
+    pub fn new(name: String, value: SecretValue) -> Secret {
+        Secret { name, value }
+    }
+
+    pub fn get_name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn value(&self) -> &SecretValue {
+        &self.value
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum SecretRocksIndex {
+    Name = 1,
+}
+
+rocks_table_impl!(Secret, SecretRocksTable, TableId::Secrets, {
+    vec![Box::new(SecretRocksIndex::Name)]
+});
+
+base_rocks_secondary_index!(Secret, SecretRocksIndex);
+
+impl RocksSecondaryIndex<Secret, String> for SecretRocksIndex {
+    fn typed_key_by(&self, row: &Secret) -> String {
+        match self {
+            SecretRocksIndex::Name => row.name.to_string(),
+        }
+    }
+
+    fn key_to_bytes(&self, key: &String) -> Vec<u8> {
+        key.as_bytes().to_vec()
+    }
+
+    fn is_unique(&self) -> bool {
+        match self {
+            SecretRocksIndex::Name => true,
+        }
+    }
+
+    fn get_id(&self) -> IndexId {
+        *self as IndexId
+    }
+}
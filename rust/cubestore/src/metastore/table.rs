@@ -1,5 +1,6 @@
 use super::{
-    BaseRocksSecondaryIndex, Column, ColumnType, IndexId, RocksSecondaryIndex, RocksTable, TableId,
+    BaseRocksSecondaryIndex, Collation, Column, ColumnCheck, ColumnDefault, ColumnEncoding,
+    ColumnType, IndexId, RocksSecondaryIndex, RocksTable, TableId,
 };
 use super::{DataFrameValue, TableValue};
 use crate::base_rocks_secondary_index;
@@ -31,7 +32,30 @@ pub struct Table {
     #[serde(default="Table::is_ready_default")]
     is_ready: bool,
     #[serde(default)]
-    created_at: Option<DateTime<Utc>>
+    created_at: Option<DateTime<Utc>>,
+    /// Location of partitions and chunks if they are not kept in the default storage.
+    #[serde(default)]
+    storage: Option<String>,
+    /// Table that partitions of the default index are aligned with and placed next to, so joins
+    /// with it stay on workers. Always the root of the co-located group, never a table that is
+    /// co-located itself.
+    #[serde(default)]
+    colocate_with: Option<u64>,
+    /// Directory of parquet files the table is attached to, see [crate::sql::attach]. Attached
+    /// tables are read-only.
+    #[serde(default)]
+    attached_location: Option<String>,
+    /// Version of the attached Delta Lake table the partitions were read from, see
+    /// [crate::sql::attach::AttachedTableRefresher]. [None] for tables that are not refreshed.
+    #[serde(default)]
+    attached_version: Option<u64>,
+    /// Name of the secret with credentials of the locations, see [crate::secrets].
+    #[serde(default)]
+    source_secret: Option<String>,
+    /// Compaction drops exact duplicates of rows, e.g. of files delivered twice. Set by
+    /// `WITH (deduplicate = 'true')`.
+    #[serde(default)]
+    deduplicate: bool
 }
 }
 
@@ -57,6 +81,8 @@ impl Table {
         locations: Option<Vec<String>>,
         import_format: Option<ImportFormat>,
         is_ready: bool,
+        storage: Option<String>,
+        colocate_with: Option<u64>,
     ) -> Table {
         Table {
             table_name,
@@ -67,6 +93,12 @@ impl Table {
             has_data: false,
             is_ready,
             created_at: Some(Utc::now()),
+            storage,
+            colocate_with,
+            attached_location: None,
+            attached_version: None,
+            source_secret: None,
+            deduplicate: false,
         }
     }
     pub fn get_columns(&self) -> &Vec<Column> {
@@ -116,6 +148,54 @@ impl Table {
     pub fn created_at(&self) -> &Option<DateTime<Utc>> {
         &self.created_at
     }
+
+    pub fn storage(&self) -> &Option<String> {
+        &self.storage
+    }
+
+    pub fn colocate_with(&self) -> &Option<u64> {
+        &self.colocate_with
+    }
+
+    pub fn set_attached_location(&self, attached_location: Option<String>) -> Self {
+        let mut table = self.clone();
+        table.attached_location = attached_location;
+        table
+    }
+
+    pub fn attached_location(&self) -> &Option<String> {
+        &self.attached_location
+    }
+
+    pub fn set_attached_version(&self, attached_version: Option<u64>) -> Self {
+        let mut table = self.clone();
+        table.attached_version = attached_version;
+        table
+    }
+
+    pub fn attached_version(&self) -> &Option<u64> {
+        &self.attached_version
+    }
+
+    pub fn set_source_secret(&self, source_secret: Option<String>) -> Self {
+        let mut table = self.clone();
+        table.source_secret = source_secret;
+        table
+    }
+
+    pub fn source_secret(&self) -> &Option<String> {
+        &self.source_secret
+    }
+
+    pub fn set_deduplicate(&self, deduplicate: bool) -> Self {
+        let mut table = self.clone();
+        table.deduplicate = deduplicate;
+        table
+    }
+
+    pub fn deduplicate(&self) -> bool {
+        self.deduplicate
+    }
 }
 
 impl Column {
@@ -124,6 +204,13 @@ impl Column {
             name,
             column_type,
             column_index,
+            not_null: false,
+            checks: Vec::new(),
+            generated: None,
+            default: None,
+            collation: None,
+            timestamp_precision: None,
+            encoding: None,
         }
     }
     pub fn get_name(&self) -> &String {
@@ -140,9 +227,74 @@ impl Column {
 
     pub fn replace_index(&self, column_index: usize) -> Column {
         Column {
-            name: self.name.clone(),
-            column_type: self.column_type.clone(),
             column_index,
+            ..self.clone()
+        }
+    }
+
+    pub fn is_not_null(&self) -> bool {
+        self.not_null
+    }
+
+    pub fn set_not_null(&mut self, not_null: bool) {
+        self.not_null = not_null;
+    }
+
+    pub fn checks(&self) -> &Vec<ColumnCheck> {
+        &self.checks
+    }
+
+    pub fn add_check(&mut self, check: ColumnCheck) {
+        self.checks.push(check);
+    }
+
+    pub fn generated(&self) -> Option<&String> {
+        self.generated.as_ref()
+    }
+
+    /// The type of generated columns is inferred from the expression if it is not declared.
+    pub fn set_generated(&mut self, expr: String, column_type: ColumnType) {
+        self.generated = Some(expr);
+        self.column_type = column_type;
+    }
+
+    pub fn default(&self) -> Option<&ColumnDefault> {
+        self.default.as_ref()
+    }
+
+    pub fn set_default(&mut self, default: ColumnDefault) {
+        self.default = Some(default);
+    }
+
+    pub fn collation(&self) -> Option<Collation> {
+        self.collation
+    }
+
+    pub fn set_collation(&mut self, collation: Collation) {
+        self.collation = Some(collation);
+    }
+
+    pub fn timestamp_precision(&self) -> Option<u8> {
+        self.timestamp_precision
+    }
+
+    pub fn set_timestamp_precision(&mut self, precision: u8) {
+        self.timestamp_precision = Some(precision);
+    }
+
+    pub fn encoding(&self) -> Option<ColumnEncoding> {
+        self.encoding
+    }
+
+    pub fn set_encoding(&mut self, encoding: ColumnEncoding) {
+        self.encoding = Some(encoding);
+    }
+
+    /// Strings longer than this number of bytes are offloaded, see [ColumnEncoding::Offload].
+    pub fn offload_threshold(&self) -> Option<usize> {
+        match self.encoding {
+            Some(ColumnEncoding::Offload { threshold }) => Some(threshold),
+            _ => None,
         }
     }
 }
@@ -0,0 +1,275 @@
+//! `LOAD DATA LOCAL INFILE` over the MySQL protocol. The server answers the query with a request
+//! for the file, the client sends the file in packets terminated by an empty one and only then
+//! gets the result of the query. [msql_srv] can't ask clients for files, so [proxy] sits between
+//! the socket and it: it receives the file into a temporary file of the [Session] before passing
+//! the query on, see [crate::sql::load_data]. Sequence ids of the server's response are shifted
+//! by the packets of the file exchange.
+//!
+//! The proxy also announces `CLIENT_LOCAL_FILES` in the greeting, clients don't send files
+//! otherwise. Files are only requested from clients that allowed it in turn, e.g. with
+//! `mysql --local-infile`.
+use crate::mysql::sessions::Session;
+use crate::sql::parser::{CubeStoreParser, Statement};
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{split, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+
+const CLIENT_LOCAL_FILES: u8 = 0x80;
+const COM_QUERY: u8 = 0x03;
+const LOCAL_INFILE_REQUEST: u8 = 0xFB;
+
+struct Packet {
+    seq: u8,
+    payload: Vec<u8>,
+}
+
+/// `None` once the stream is closed.
+async fn read_packet(r: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Packet>> {
+    let mut header = [0u8; 4];
+    match r.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload).await?;
+    Ok(Some(Packet {
+        seq: header[3],
+        payload,
+    }))
+}
+
+async fn write_packet(w: &mut (impl AsyncWrite + Unpin), p: &Packet) -> io::Result<()> {
+    let len = (p.payload.len() as u32).to_le_bytes();
+    w.write_all(&[len[0], len[1], len[2], p.seq]).await?;
+    w.write_all(&p.payload).await?;
+    w.flush().await
+}
+
+/// Passes packets between `client` and `server` until the server closes its stream.
+pub async fn proxy(
+    client: impl AsyncRead + AsyncWrite + Send + 'static,
+    server: impl AsyncRead + AsyncWrite + Send + 'static,
+    session: Arc<Session>,
+) -> io::Result<()> {
+    let (client_read, client_write) = split(client);
+    let (server_read, server_write) = split(server);
+    let client_write = Arc::new(Mutex::new(client_write));
+    let seq_offset = Arc::new(AtomicU8::new(0));
+    let to_client = to_client(server_read, client_write.clone(), seq_offset.clone());
+    let to_server = to_server(client_read, server_write, client_write, seq_offset, session);
+    tokio::pin!(to_client);
+    tokio::select! {
+        res = &mut to_client => return res,
+        res = to_server => res?,
+    }
+    // The client closed its stream, pass on what's left of the response.
+    to_client.await
+}
+
+async fn to_client<S: AsyncRead + Send, C: AsyncWrite + Send>(
+    mut server: ReadHalf<S>,
+    client: Arc<Mutex<WriteHalf<C>>>,
+    seq_offset: Arc<AtomicU8>,
+) -> io::Result<()> {
+    let mut greeting = true;
+    while let Some(mut p) = read_packet(&mut server).await? {
+        if greeting {
+            announce_local_files(&mut p.payload);
+            greeting = false;
+        }
+        p.seq = p.seq.wrapping_add(seq_offset.load(Ordering::SeqCst));
+        write_packet(&mut *client.lock().await, &p).await?;
+    }
+    client.lock().await.shutdown().await
+}
+
+/// Sets `CLIENT_LOCAL_FILES` in the capabilities of the initial handshake packet.
+fn announce_local_files(greeting: &mut [u8]) {
+    // Protocol version, server version, connection id, auth data and a filler go first.
+    let version_end = match greeting.iter().skip(1).position(|b| *b == 0) {
+        Some(i) => 1 + i,
+        None => return,
+    };
+    let capabilities = version_end + 1 + 4 + 8 + 1;
+    if capabilities < greeting.len() {
+        greeting[capabilities] |= CLIENT_LOCAL_FILES;
+    }
+}
+
+async fn to_server<C: AsyncRead + Send, CW: AsyncWrite + Send, S: AsyncWrite + Send>(
+    mut client: ReadHalf<C>,
+    mut server: WriteHalf<S>,
+    client_write: Arc<Mutex<WriteHalf<CW>>>,
+    seq_offset: Arc<AtomicU8>,
+    session: Arc<Session>,
+) -> io::Result<()> {
+    let mut handshake_response = true;
+    let mut local_files = false;
+    while let Some(p) = read_packet(&mut client).await? {
+        if handshake_response {
+            local_files = p
+                .payload
+                .first()
+                .map_or(false, |c| c & CLIENT_LOCAL_FILES != 0);
+            handshake_response = false;
+        } else if p.seq == 0 {
+            let mut offset = 0;
+            if local_files {
+                if let Some(location) = local_infile_location(&p.payload) {
+                    let mut request = vec![LOCAL_INFILE_REQUEST];
+                    request.extend_from_slice(location.as_bytes());
+                    write_packet(
+                        &mut *client_write.lock().await,
+                        &Packet {
+                            seq: 1,
+                            payload: request,
+                        },
+                    )
+                    .await?;
+                    let path = tempfile::NamedTempFile::new()?.into_temp_path();
+                    let mut file = File::create(&path).await?;
+                    offset = 1;
+                    loop {
+                        let data = match read_packet(&mut client).await? {
+                            Some(data) => data,
+                            None => return server.shutdown().await,
+                        };
+                        offset = data.seq;
+                        if data.payload.is_empty() {
+                            break;
+                        }
+                        file.write_all(&data.payload).await?;
+                    }
+                    file.flush().await?;
+                    session.set_local_infile(path);
+                }
+            }
+            seq_offset.store(offset, Ordering::SeqCst);
+        }
+        write_packet(&mut server, &p).await?;
+    }
+    server.shutdown().await
+}
+
+/// Location of `LOAD DATA LOCAL INFILE` if the packet is such a query.
+fn local_infile_location(payload: &[u8]) -> Option<String> {
+    if payload.first() != Some(&COM_QUERY) {
+        return None;
+    }
+    let query = std::str::from_utf8(&payload[1..]).ok()?.trim_start();
+    if !query
+        .get(..4)
+        .map_or(false, |p| p.eq_ignore_ascii_case("load"))
+    {
+        return None;
+    }
+    match CubeStoreParser::new(query).ok()?.parse_statement().ok()? {
+        Statement::LoadData {
+            local: true,
+            location,
+            ..
+        } => Some(location),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mysql::sessions::Sessions;
+    use tokio::io::duplex;
+
+    async fn send(w: &mut (impl AsyncWrite + Unpin), seq: u8, payload: &[u8]) {
+        write_packet(
+            w,
+            &Packet {
+                seq,
+                payload: payload.to_vec(),
+            },
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn receive(r: &mut (impl AsyncRead + Unpin)) -> (u8, Vec<u8>) {
+        let p = read_packet(r).await.unwrap().unwrap();
+        (p.seq, p.payload)
+    }
+
+    fn query(sql: &str) -> Vec<u8> {
+        let mut payload = vec![COM_QUERY];
+        payload.extend_from_slice(sql.as_bytes());
+        payload
+    }
+
+    #[tokio::test]
+    async fn receive_local_file() {
+        let session = Sessions::new().open(1, "127.0.0.1:50000".to_string());
+        let (mut client, client_proxy) = duplex(1024);
+        let (mut server, server_proxy) = duplex(1024);
+        let proxy = tokio::spawn(proxy(client_proxy, server_proxy, session.clone()));
+
+        let mut greeting = vec![10];
+        greeting.extend_from_slice(b"5.7.0\0");
+        greeting.extend_from_slice(&[0; 4 + 8 + 1]);
+        greeting.extend_from_slice(&[0x00, 0x02]);
+        send(&mut server, 0, &greeting).await;
+        let (_, greeting) = receive(&mut client).await;
+        assert_eq!(greeting[20], CLIENT_LOCAL_FILES);
+        assert_eq!(greeting[21], 0x02);
+
+        send(&mut client, 1, &[CLIENT_LOCAL_FILES, 0, 0, 0]).await;
+        assert_eq!(receive(&mut server).await.0, 1);
+        send(&mut server, 2, b"ok").await;
+        assert_eq!(receive(&mut client).await.0, 2);
+
+        let load = query("LOAD DATA LOCAL INFILE 'orders.csv' INTO TABLE foo.orders");
+        send(&mut client, 0, &load).await;
+        let (seq, request) = receive(&mut client).await;
+        assert_eq!(seq, 1);
+        assert_eq!(request, b"\xFBorders.csv".to_vec());
+        send(&mut client, 2, b"1,paid\n").await;
+        send(&mut client, 3, b"2,new\n").await;
+        send(&mut client, 4, b"").await;
+        assert_eq!(receive(&mut server).await, (0, load));
+        let file = session.query_context().local_infile.lock().unwrap().take();
+        assert_eq!(
+            std::fs::read_to_string(file.unwrap()).unwrap(),
+            "1,paid\n2,new\n"
+        );
+        send(&mut server, 1, b"ok").await;
+        assert_eq!(receive(&mut client).await.0, 5);
+
+        // Other queries are passed as is.
+        send(&mut client, 0, &query("SELECT 1")).await;
+        assert_eq!(receive(&mut server).await.0, 0);
+        send(&mut server, 1, b"ok").await;
+        assert_eq!(receive(&mut client).await.0, 1);
+
+        drop(client);
+        assert!(read_packet(&mut server).await.unwrap().is_none());
+        drop(server);
+        proxy.await.unwrap().unwrap();
+    }
+
+    #[test]
+    fn local_infile_locations() {
+        assert_eq!(
+            local_infile_location(&query(
+                "load data local infile '/tmp/a.csv' into table s.t IGNORE 1 LINES"
+            )),
+            Some("/tmp/a.csv".to_string())
+        );
+        assert_eq!(
+            local_infile_location(&query("LOAD DATA INFILE 'temp://a.csv' INTO TABLE s.t")),
+            None
+        );
+        assert_eq!(local_infile_location(&query("SELECT 1")), None);
+        assert_eq!(local_infile_location(&[0x01]), None);
+    }
+}
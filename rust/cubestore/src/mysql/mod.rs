@@ -1,9 +1,22 @@
+pub mod local_infile;
+pub mod sessions;
+pub mod text_rows;
+
+use crate::auth::{AuthenticatedUser, Credentials, Role};
 use crate::config::processing_loop::ProcessingLoop;
-use crate::sql::{SqlQueryContext, SqlService};
+use crate::mysql::sessions::{CloseReason, Session, Sessions};
+use crate::mysql::text_rows::TextRows;
+use crate::queryplanner::time_zones::QueryTimeZone;
+use crate::sql::query_log::QueryLog;
+use crate::sql::{ResultBatch, SqlService};
+use crate::store::DataFrame;
 use crate::table::TableValue;
+use crate::util::query_id::{new_query_id, with_query_id};
 use crate::util::time_span::warn_long;
-use crate::{metastore, CubeError};
+use crate::{metastore, CubeError, CubeErrorCauseType};
 use async_trait::async_trait;
+use chrono::Utc;
+use futures::StreamExt;
 use hex::ToHex;
 use log::{error, info, warn};
 use msql_srv::*;
@@ -17,7 +30,8 @@ use tokio::sync::{watch, RwLock};
 struct Backend {
     sql_service: Arc<dyn SqlService>,
     auth: Arc<dyn SqlAuthService>,
-    user: Option<String>,
+    query_log: Arc<QueryLog>,
+    session: Arc<Session>,
 }
 
 #[async_trait]
@@ -52,25 +66,91 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
         query: &'a str,
         results: QueryResultWriter<'a, W>,
     ) -> Result<(), Self::Error> {
+        let query_id = new_query_id();
+        with_query_id(
+            Some(query_id.clone()),
+            self.run_query(query, results, &query_id),
+        )
+        .await
+    }
+
+    async fn on_auth<'a>(&'a mut self, user: Vec<u8>) -> Result<Option<Vec<u8>>, Self::Error>
+    where
+        W: 'async_trait,
+    {
+        let user = if !user.is_empty() {
+            Some(String::from_utf8_lossy(user.as_slice()).to_string())
+        } else {
+            None
+        };
+        self.session.set_user(user.clone());
+        // Only takes effect if the client knows the password.
+        self.session.set_role(self.auth.mysql_role(&user));
+        self.auth
+            .authenticate(user)
+            .await
+            .map(|p| p.map(|p| p.as_bytes().to_vec()))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+    }
+}
+
+impl Backend {
+    /// Runs in the scope of `query_id`, see [crate::util::query_id].
+    async fn run_query<W: io::Write + Send>(
+        &mut self,
+        query: &str,
+        results: QueryResultWriter<'_, W>,
+        query_id: &str,
+    ) -> Result<(), io::Error> {
+        self.session.start_query(query);
+        let res = self.send_query_results(query, results, query_id).await;
+        self.session.finish_query();
+        res
+    }
+
+    async fn send_query_results<W: io::Write + Send>(
+        &mut self,
+        query: &str,
+        results: QueryResultWriter<'_, W>,
+        query_id: &str,
+    ) -> Result<(), io::Error> {
         let start = SystemTime::now();
+        let log_start = Utc::now();
+        let user = self.session.user();
         let res = self
             .sql_service
-            .exec_query_with_context(
-                SqlQueryContext {
-                    user: self.user.clone(),
-                },
-                query,
-            )
+            .exec_query_stream(self.session.query_context(), query)
             .await;
-        if let Err(e) = res {
-            error!("Error during processing {}: {}", query, e.message);
-            results.error(ErrorKind::ER_INTERNAL_ERROR, e.message.as_bytes())?;
-            return Ok(());
-        }
+        // Errors of the first batch are still reported as query errors.
+        let res = match res {
+            Ok(mut s) => match s.batches.next().await {
+                Some(Err(e)) => Err(e),
+                first => Ok((s, first)),
+            },
+            Err(e) => Err(e),
+        };
+        let (mut stream, first) = match res {
+            Ok(r) => r,
+            Err(mut e) => {
+                error!("Error during processing {}: {}", query, e.message);
+                self.query_log
+                    .record(log_start, Some(self.session.id), &user, query, Err(&e));
+                let kind = match e.cause {
+                    // Lets clients tell apart errors that go away on retry.
+                    CubeErrorCauseType::Unavailable => ErrorKind::ER_QUERY_INTERRUPTED,
+                    CubeErrorCauseType::User | CubeErrorCauseType::Internal => {
+                        ErrorKind::ER_INTERNAL_ERROR
+                    }
+                };
+                // Details of the error stay on the last line.
+                e.message = format!("{} (query id: {})", e.message, query_id);
+                results.error(kind, e.client_message().as_bytes())?;
+                return Ok(());
+            }
+        };
         let _s = warn_long("sending query results", Duration::from_millis(100));
-        let data_frame = res.unwrap();
-        let columns = data_frame
-            .get_columns()
+        let columns = stream
+            .columns
             .iter()
             .map(|c| Column {
                 table: "result".to_string(), // TODO
@@ -84,30 +164,52 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
                     metastore::ColumnType::Bytes => ColumnType::MYSQL_TYPE_STRING,
                     metastore::ColumnType::HyperLogLog(_) => ColumnType::MYSQL_TYPE_STRING,
                     metastore::ColumnType::Float => ColumnType::MYSQL_TYPE_STRING,
+                    metastore::ColumnType::Uuid => ColumnType::MYSQL_TYPE_STRING,
+                    metastore::ColumnType::Inet => ColumnType::MYSQL_TYPE_STRING,
+                    metastore::ColumnType::Cidr => ColumnType::MYSQL_TYPE_STRING,
+                    metastore::ColumnType::Enum(_) => ColumnType::MYSQL_TYPE_STRING,
                 },
                 colflags: ColumnFlags::empty(),
             })
             .collect::<Vec<_>>();
 
         let mut rw = results.start(&columns)?;
-        for row in data_frame.get_rows().iter() {
-            for value in row.values().iter() {
-                match value {
-                    TableValue::String(s) => rw.write_col(s)?,
-                    TableValue::Timestamp(s) => rw.write_col(s.to_string())?,
-                    TableValue::Int(i) => rw.write_col(i)?,
-                    TableValue::Decimal(v) => rw.write_col(v.to_string())?,
-                    TableValue::Boolean(v) => rw.write_col(v.to_string())?,
-                    TableValue::Float(v) => rw.write_col(v.to_string())?,
-                    TableValue::Bytes(b) => {
-                        rw.write_col(format!("0x{}", b.encode_hex_upper::<String>()))?
-                    }
-                    TableValue::Null => rw.write_col(Option::<String>::None)?,
+        let mut next = first;
+        let mut rows = 0;
+        let mut text_rows = TextRows::new(stream.time_zone);
+        while let Some(batch) = next {
+            // Rows were already sent, the connection is closed to let the client know the
+            // result is incomplete.
+            let log_error = |e: CubeError| {
+                error!("Error during processing {}: {}", query, e.message);
+                self.query_log
+                    .record(log_start, Some(self.session.id), &user, query, Err(&e));
+                io::Error::new(io::ErrorKind::Other, e.message)
+            };
+            let batch = batch.map_err(log_error)?;
+            rows += batch.num_rows() as u64;
+            match batch {
+                ResultBatch::DataFrame(data_frame) => {
+                    write_rows(&mut rw, data_frame.as_ref(), stream.time_zone)?
+                }
+                ResultBatch::RecordBatch(batch) => {
+                    // Buffers are moved to the blocking task and back to be reused.
+                    let (converted, r) = tokio::task::spawn_blocking(move || {
+                        let r = text_rows.convert(&batch);
+                        (text_rows, r)
+                    })
+                    .await
+                    .map_err(|e| log_error(e.into()))?;
+                    text_rows = converted;
+                    r.map_err(log_error)?;
+                    text_rows.write_rows(&mut rw)?;
                 }
             }
-            rw.end_row()?;
+            next = stream.batches.next().await;
         }
         rw.finish()?;
+        self.query_log
+            .record(log_start, Some(self.session.id), &user, query, Ok(rows));
         if start.elapsed().unwrap().as_millis() > 200 && query.to_lowercase().starts_with("select")
         {
             warn!(
@@ -118,28 +220,45 @@ impl<W: io::Write + Send> AsyncMysqlShim<W> for Backend {
         }
         Ok(())
     }
+}
 
-    async fn on_auth<'a>(&'a mut self, user: Vec<u8>) -> Result<Option<Vec<u8>>, Self::Error>
-    where
-        W: 'async_trait,
-    {
-        self.user = if !user.is_empty() {
-            Some(String::from_utf8_lossy(user.as_slice()).to_string())
-        } else {
-            None
-        };
-        self.auth
-            .authenticate(self.user.clone())
-            .await
-            .map(|p| p.map(|p| p.as_bytes().to_vec()))
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))
+fn write_rows<W: io::Write>(
+    rw: &mut RowWriter<'_, W>,
+    data_frame: &DataFrame,
+    time_zone: QueryTimeZone,
+) -> io::Result<()> {
+    let mut timestamp = String::new();
+    for row in data_frame.get_rows().iter() {
+        for value in row.values().iter() {
+            match value {
+                TableValue::String(s) => rw.write_col(s)?,
+                TableValue::Timestamp(t) => {
+                    timestamp.clear();
+                    time_zone.write_timestamp(&mut timestamp, t.get_time_stamp());
+                    rw.write_col(&timestamp)?
+                }
+                TableValue::Int(i) => rw.write_col(i)?,
+                TableValue::Decimal(v) => rw.write_col(v.to_string())?,
+                TableValue::Boolean(v) => rw.write_col(v.to_string())?,
+                TableValue::Float(v) => rw.write_col(v.to_string())?,
+                TableValue::Bytes(b) => {
+                    rw.write_col(format!("0x{}", b.encode_hex_upper::<String>()))?
+                }
+                TableValue::Null => rw.write_col(Option::<String>::None)?,
+            }
+        }
+        rw.end_row()?;
     }
+    Ok(())
 }
 
 pub struct MySqlServer {
     address: String,
     sql_service: Arc<dyn SqlService>,
     auth: Arc<dyn SqlAuthService>,
+    query_log: Arc<QueryLog>,
+    sessions: Arc<Sessions>,
+    idle_session_timeout: Option<Duration>,
     close_socket_rx: RwLock<watch::Receiver<bool>>,
     close_socket_tx: watch::Sender<bool>,
 }
@@ -176,19 +295,53 @@ impl ProcessingLoop for MySqlServer {
 
             let sql_service = self.sql_service.clone();
             let auth = self.auth.clone();
+            let query_log = self.query_log.clone();
+            let sessions = self.sessions.clone();
+            let idle_session_timeout = self.idle_session_timeout;
+            let client_address = socket
+                .peer_addr()
+                .map(|a| a.to_string())
+                .unwrap_or_default();
+            let session = sessions.open(query_log.new_session(), client_address);
             tokio::spawn(async move {
-                if let Err(e) = AsyncMysqlIntermediary::run_on(
-                    Backend {
-                        sql_service,
-                        auth,
-                        user: None,
-                    },
-                    socket,
-                )
-                .await
-                {
-                    error!("Error during processing MySQL connection: {}", e);
+                // See [local_infile] for why packets go through a proxy.
+                let (server, proxy) = tokio::io::duplex(64 * 1024);
+                let connection = async {
+                    tokio::try_join!(
+                        AsyncMysqlIntermediary::run_on(
+                            Backend {
+                                sql_service,
+                                auth,
+                                query_log,
+                                session: session.clone(),
+                            },
+                            server,
+                        ),
+                        local_infile::proxy(socket, proxy, session.clone())
+                    )
+                };
+                // The socket is closed once the connection future is dropped.
+                tokio::select! {
+                    res = connection => {
+                        if let Err(e) = res {
+                            error!("Error during processing MySQL connection: {}", e);
+                        }
+                    }
+                    reason = session.wait_close(idle_session_timeout) => {
+                        match reason {
+                            CloseReason::Killed => {
+                                info!("Connection {} killed", session.id)
+                            }
+                            CloseReason::Idle => info!(
+                                "Closing connection {} from {} idle for {:?}",
+                                session.id,
+                                session.client_address,
+                                idle_session_timeout.unwrap()
+                            ),
+                        }
+                    }
                 }
+                sessions.close(session.id);
             });
         }
     }
@@ -204,12 +357,18 @@ impl MySqlServer {
         address: String,
         sql_service: Arc<dyn SqlService>,
         auth: Arc<dyn SqlAuthService>,
+        query_log: Arc<QueryLog>,
+        sessions: Arc<Sessions>,
+        idle_session_timeout: Option<Duration>,
     ) -> Arc<Self> {
         let (close_socket_tx, close_socket_rx) = watch::channel(false);
         Arc::new(Self {
             address,
             sql_service,
             auth,
+            query_log,
+            sessions,
+            idle_session_timeout,
             close_socket_rx: RwLock::new(close_socket_rx),
             close_socket_tx,
         })
@@ -218,7 +377,40 @@ impl MySqlServer {
 
 #[async_trait]
 pub trait SqlAuthService: Send + Sync {
+    /// Password MySQL clients of `user` must know, `None` accepts any password.
     async fn authenticate(&self, user: Option<String>) -> Result<Option<String>, CubeError>;
+
+    /// Role of MySQL connections of `user` that passed [SqlAuthService::authenticate]. Without
+    /// auth providers all users are admins.
+    fn mysql_role(&self, _user: &Option<String>) -> Role {
+        Role::Admin
+    }
+
+    /// Checks credentials HTTP and WebSocket clients send in clear text. By default the password
+    /// must be the one of [SqlAuthService::authenticate] and bearer tokens are rejected.
+    async fn authenticate_credentials(
+        &self,
+        credentials: Option<Credentials>,
+    ) -> Result<AuthenticatedUser, CubeError> {
+        let (user, password) = match credentials {
+            Some(Credentials::Password { user, password }) => (Some(user), Some(password)),
+            Some(Credentials::Token(_)) => {
+                return Err(CubeError::user(
+                    "Bearer tokens are not supported without auth providers".to_string(),
+                ))
+            }
+            None => (None, None),
+        };
+        match self.authenticate(user.clone()).await? {
+            Some(expected) if Some(expected) != password => Err(CubeError::user(
+                "User or password doesn't match".to_string(),
+            )),
+            _ => Ok(AuthenticatedUser {
+                user,
+                role: Role::Admin,
+            }),
+        }
+    }
 }
 
 pub struct SqlAuthDefaultImpl;
@@ -0,0 +1,281 @@
+//! Open MySQL connections, queryable as `system.sessions` and closed with
+//! `KILL [CONNECTION] <id>`. Ids are the session ids of the query log, see
+//! [crate::sql::query_log].
+//!
+//! State of a connection, i.e. its temporary functions, the pinned read snapshot, `SET` options
+//! and a file received for `LOAD DATA LOCAL INFILE`, lives in its [Session] and is released once
+//! the connection closes. CubeStore has no temporary tables or cursors and does not keep prepared
+//! statements, so there's nothing else to clean up. Clients that crash without closing the socket
+//! would otherwise hold their session forever, connections idle for
+//! [crate::config::ConfigObj::idle_session_timeout_secs] are closed.
+//! Killing a connection stops its running query on the router.
+use crate::auth::Role;
+use crate::queryplanner::read_snapshot::ReadSnapshot;
+use crate::queryplanner::time_zones::QueryTimeZone;
+use crate::sql::priority::QueryPriority;
+use crate::sql::result_limits::ResultLimits;
+use crate::sql::temporary_functions::TemporaryFunctions;
+use crate::sql::SqlQueryContext;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tempfile::TempPath;
+use tokio::sync::Notify;
+
+pub struct Sessions {
+    sessions: Mutex<HashMap<u64, Arc<Session>>>,
+}
+
+crate::di_service!(Sessions, []);
+
+impl std::fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sessions")
+            .field("sessions", &self.sessions.lock().unwrap().len())
+            .finish()
+    }
+}
+
+pub struct Session {
+    pub id: u64,
+    pub client_address: String,
+    pub connected_at: DateTime<Utc>,
+    activity: Mutex<Activity>,
+    killed: Notify,
+    result_limits: Arc<Mutex<ResultLimits>>,
+    priority: Arc<Mutex<QueryPriority>>,
+    temporary_functions: Arc<Mutex<TemporaryFunctions>>,
+    read_snapshot: Arc<Mutex<Option<Arc<ReadSnapshot>>>>,
+    time_zone: Arc<Mutex<Option<QueryTimeZone>>>,
+    local_infile: Arc<Mutex<Option<TempPath>>>,
+}
+
+struct Activity {
+    user: Option<String>,
+    /// Set once the user is authenticated.
+    role: Role,
+    last_activity: DateTime<Utc>,
+    /// Running query or the last one.
+    query: Option<String>,
+    running: bool,
+}
+
+/// Row of `system.sessions`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub user: Option<String>,
+    pub client_address: String,
+    pub connected_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+    /// `query` while a query runs, `idle` otherwise.
+    pub state: &'static str,
+    pub query: Option<String>,
+    pub temporary_functions: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CloseReason {
+    Killed,
+    Idle,
+}
+
+impl Sessions {
+    pub fn new() -> Arc<Sessions> {
+        Arc::new(Sessions {
+            sessions: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn open(&self, id: u64, client_address: String) -> Arc<Session> {
+        let now = Utc::now();
+        let session = Arc::new(Session {
+            id,
+            client_address,
+            connected_at: now,
+            activity: Mutex::new(Activity {
+                user: None,
+                role: Role::default(),
+                last_activity: now,
+                query: None,
+                running: false,
+            }),
+            killed: Notify::new(),
+            result_limits: Arc::new(Mutex::new(ResultLimits::default())),
+            priority: Arc::new(Mutex::new(QueryPriority::default())),
+            temporary_functions: Arc::new(Mutex::new(TemporaryFunctions::new())),
+            read_snapshot: Arc::new(Mutex::new(None)),
+            time_zone: Arc::new(Mutex::new(None)),
+            local_infile: Arc::new(Mutex::new(None)),
+        });
+        self.sessions.lock().unwrap().insert(id, session.clone());
+        session
+    }
+
+    /// Forgets the session and releases its state.
+    pub fn close(&self, id: u64) {
+        if let Some(session) = self.sessions.lock().unwrap().remove(&id) {
+            session.release();
+        }
+    }
+
+    /// Ordered by id, i.e. by the time of connection.
+    pub fn all(&self) -> Vec<SessionInfo> {
+        let mut sessions = self
+            .sessions
+            .lock()
+            .unwrap()
+            .values()
+            .map(|s| s.info())
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|s| s.id);
+        sessions
+    }
+
+    /// The connection is closed by its task, see [Session::wait_close].
+    pub fn kill(&self, id: u64) -> Result<(), CubeError> {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(session) => {
+                session.killed.notify_one();
+                Ok(())
+            }
+            None => Err(CubeError::user(format!("Unknown connection id: {}", id))),
+        }
+    }
+}
+
+impl Session {
+    pub fn query_context(&self) -> SqlQueryContext {
+        let (user, role) = {
+            let activity = self.activity.lock().unwrap();
+            (activity.user.clone(), activity.role)
+        };
+        SqlQueryContext {
+            user,
+            role,
+            result_limits: self.result_limits.clone(),
+            priority: self.priority.clone(),
+            temporary_functions: self.temporary_functions.clone(),
+            read_snapshot: self.read_snapshot.clone(),
+            time_zone: self.time_zone.clone(),
+            local_infile: self.local_infile.clone(),
+        }
+    }
+
+    pub fn user(&self) -> Option<String> {
+        self.activity.lock().unwrap().user.clone()
+    }
+
+    pub fn set_user(&self, user: Option<String>) {
+        self.activity.lock().unwrap().user = user;
+    }
+
+    pub fn set_role(&self, role: Role) {
+        self.activity.lock().unwrap().role = role;
+    }
+
+    /// File for the next `LOAD DATA LOCAL INFILE`, see [crate::mysql::local_infile].
+    pub fn set_local_infile(&self, file: TempPath) {
+        *self.local_infile.lock().unwrap() = Some(file);
+    }
+
+    pub fn start_query(&self, query: &str) {
+        let mut activity = self.activity.lock().unwrap();
+        activity.last_activity = Utc::now();
+        activity.query = Some(query.to_string());
+        activity.running = true;
+    }
+
+    pub fn finish_query(&self) {
+        let mut activity = self.activity.lock().unwrap();
+        activity.last_activity = Utc::now();
+        activity.running = false;
+    }
+
+    pub fn info(&self) -> SessionInfo {
+        let activity = self.activity.lock().unwrap();
+        SessionInfo {
+            id: self.id,
+            user: activity.user.clone(),
+            client_address: self.client_address.clone(),
+            connected_at: self.connected_at,
+            last_activity: activity.last_activity,
+            state: if activity.running { "query" } else { "idle" },
+            query: activity.query.clone(),
+            temporary_functions: self.temporary_functions.lock().unwrap().len() as u64,
+        }
+    }
+
+    /// Drops temporary functions, the read snapshot and a file received for `LOAD DATA LOCAL
+    /// INFILE` but not loaded. Queries still running keep what they use.
+    fn release(&self) {
+        self.temporary_functions.lock().unwrap().clear();
+        self.read_snapshot.lock().unwrap().take();
+        self.local_infile.lock().unwrap().take();
+    }
+
+    /// Completes once the session is killed or no query ran for `idle_timeout`.
+    pub async fn wait_close(&self, idle_timeout: Option<Duration>) -> CloseReason {
+        loop {
+            let wait = match idle_timeout {
+                Some(timeout) => match self.idle_for() {
+                    Some(idle) if idle >= timeout => return CloseReason::Idle,
+                    Some(idle) => timeout - idle,
+                    None => timeout,
+                },
+                None => {
+                    self.killed.notified().await;
+                    return CloseReason::Killed;
+                }
+            };
+            tokio::select! {
+                _ = self.killed.notified() => return CloseReason::Killed,
+                _ = tokio::time::sleep(wait) => {}
+            }
+        }
+    }
+
+    /// `None` while a query runs.
+    fn idle_for(&self) -> Option<Duration> {
+        let activity = self.activity.lock().unwrap();
+        if activity.running {
+            return None;
+        }
+        Some(
+            (Utc::now() - activity.last_activity)
+                .to_std()
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn kill_and_idle_timeout() {
+        let sessions = Sessions::new();
+        let session = sessions.open(1, "127.0.0.1:50000".to_string());
+        session.start_query("SELECT 1");
+        assert_eq!(sessions.all()[0].state, "query");
+        assert_eq!(sessions.all()[0].query, Some("SELECT 1".to_string()));
+
+        sessions.kill(1).unwrap();
+        assert_eq!(session.wait_close(None).await, CloseReason::Killed);
+        assert!(sessions.kill(2).is_err());
+
+        session.finish_query();
+        assert_eq!(sessions.all()[0].state, "idle");
+        assert_eq!(
+            session.wait_close(Some(Duration::from_millis(50))).await,
+            CloseReason::Idle
+        );
+
+        sessions.close(1);
+        assert!(sessions.all().is_empty());
+        assert!(sessions.kill(1).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+//! Rows of the MySQL text protocol, where every value is sent as a string. Record batches of
+//! streamed results are converted a column at a time, with a loop specialized for each Arrow type
+//! writing into buffers that are reused for the following batches. Values look the same as when
+//! converted to a [crate::store::DataFrame] by
+//! [crate::queryplanner::query_executor::batch_to_dataframe] and formatted one by one.
+use crate::queryplanner::time_zones::QueryTimeZone;
+use crate::CubeError;
+use arrow::array::{
+    Array, BinaryArray, BooleanArray, Float64Array, Int64Array, Int64Decimal0Array,
+    Int64Decimal10Array, Int64Decimal1Array, Int64Decimal2Array, Int64Decimal3Array,
+    Int64Decimal4Array, Int64Decimal5Array, StringArray, TimestampMicrosecondArray,
+    TimestampNanosecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use msql_srv::RowWriter;
+use std::fmt::{Display, Write};
+use std::io;
+
+/// Texts of the values of a column.
+#[derive(Default, Debug)]
+struct TextColumn {
+    data: String,
+    /// End of each value in `data`.
+    ends: Vec<usize>,
+    nulls: Vec<bool>,
+}
+
+impl TextColumn {
+    fn clear(&mut self) {
+        self.data.clear();
+        self.ends.clear();
+        self.nulls.clear();
+    }
+
+    fn value(&self, i: usize) -> Option<&str> {
+        if self.nulls[i] {
+            return None;
+        }
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        Some(&self.data[start..self.ends[i]])
+    }
+
+    fn push_null(&mut self) {
+        self.ends.push(self.data.len());
+        self.nulls.push(true);
+    }
+
+    fn end_value(&mut self) {
+        self.ends.push(self.data.len());
+        self.nulls.push(false);
+    }
+}
+
+/// Converts a batch at a time, see the module docs.
+#[derive(Default, Debug)]
+pub struct TextRows {
+    columns: Vec<TextColumn>,
+    num_rows: usize,
+    /// Timestamps are shown in this time zone.
+    time_zone: QueryTimeZone,
+}
+
+macro_rules! convert_column {
+    ($ARRAY: expr, $COLUMN: expr, $ARRAY_TYPE: ident, $WRITE: expr) => {{
+        let a = $ARRAY.as_any().downcast_ref::<$ARRAY_TYPE>().unwrap();
+        for i in 0..a.len() {
+            if a.is_null(i) {
+                $COLUMN.push_null();
+            } else {
+                $WRITE(&mut $COLUMN.data, a.value(i));
+                $COLUMN.end_value();
+            }
+        }
+    }};
+}
+
+impl TextRows {
+    pub fn new(time_zone: QueryTimeZone) -> TextRows {
+        TextRows {
+            time_zone,
+            ..TextRows::default()
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    /// Replaces rows with the ones of `batch`.
+    pub fn convert(&mut self, batch: &RecordBatch) -> Result<(), CubeError> {
+        self.columns
+            .resize_with(batch.num_columns(), TextColumn::default);
+        self.num_rows = batch.num_rows();
+        let time_zone = self.time_zone;
+        for (array, column) in batch.columns().iter().zip(self.columns.iter_mut()) {
+            column.clear();
+            match array.data_type() {
+                DataType::Int64 => convert_column!(array, column, Int64Array, write_display),
+                DataType::UInt64 => convert_column!(array, column, UInt64Array, |d, v: u64| {
+                    write_display(d, v as i64)
+                }),
+                DataType::Float64 => convert_column!(array, column, Float64Array, write_display),
+                DataType::Int64Decimal(0) => {
+                    convert_column!(array, column, Int64Decimal0Array, write_display)
+                }
+                DataType::Int64Decimal(1) => {
+                    convert_column!(array, column, Int64Decimal1Array, |d, v| {
+                        write_decimal(d, v, 1)
+                    })
+                }
+                DataType::Int64Decimal(2) => {
+                    convert_column!(array, column, Int64Decimal2Array, |d, v| {
+                        write_decimal(d, v, 2)
+                    })
+                }
+                DataType::Int64Decimal(3) => {
+                    convert_column!(array, column, Int64Decimal3Array, |d, v| {
+                        write_decimal(d, v, 3)
+                    })
+                }
+                DataType::Int64Decimal(4) => {
+                    convert_column!(array, column, Int64Decimal4Array, |d, v| {
+                        write_decimal(d, v, 4)
+                    })
+                }
+                DataType::Int64Decimal(5) => {
+                    convert_column!(array, column, Int64Decimal5Array, |d, v| {
+                        write_decimal(d, v, 5)
+                    })
+                }
+                DataType::Int64Decimal(10) => {
+                    convert_column!(array, column, Int64Decimal10Array, |d, v| {
+                        write_decimal(d, v, 10)
+                    })
+                }
+                DataType::Timestamp(TimeUnit::Microsecond, None) => {
+                    convert_column!(array, column, TimestampMicrosecondArray, |d, v: i64| {
+                        time_zone.write_timestamp(d, v * 1000)
+                    })
+                }
+                DataType::Timestamp(TimeUnit::Nanosecond, None) => {
+                    convert_column!(array, column, TimestampNanosecondArray, |d, v| {
+                        time_zone.write_timestamp(d, v)
+                    })
+                }
+                DataType::Binary => convert_column!(array, column, BinaryArray, write_hex),
+                DataType::Utf8 => {
+                    convert_column!(array, column, StringArray, |d: &mut String, v| {
+                        d.push_str(v)
+                    })
+                }
+                DataType::Boolean => convert_column!(array, column, BooleanArray, write_display),
+                x => {
+                    return Err(CubeError::internal(format!(
+                        "Unsupported data type: {:?}",
+                        x
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_rows<W: io::Write>(&self, rw: &mut RowWriter<'_, W>) -> io::Result<()> {
+        for i in 0..self.num_rows {
+            for c in &self.columns {
+                rw.write_col(c.value(i))?;
+            }
+            rw.end_row()?;
+        }
+        Ok(())
+    }
+}
+
+fn write_display<T: Display>(d: &mut String, v: T) {
+    write!(d, "{}", v).unwrap();
+}
+
+/// Same as `BigDecimal` with trailing zeros cut by
+/// [crate::queryplanner::query_executor::batch_to_dataframe]: zeros after nonzero digits are cut
+/// and a fraction of zeros is dropped, other fractions are kept as they are, e.g. `1.050`.
+fn write_decimal(d: &mut String, v: i64, scale: u32) {
+    let abs = (v as i128).abs();
+    let div = 10i128.pow(scale);
+    if v < 0 {
+        d.push('-');
+    }
+    write!(d, "{}", abs / div).unwrap();
+    let point = d.len();
+    write!(d, ".{:0width$}", abs % div, width = scale as usize).unwrap();
+    let fraction = &d.as_bytes()[point + 1..];
+    let zeros = fraction.iter().rev().take_while(|c| **c == b'0').count();
+    let inner_zeros = fraction[..fraction.len() - zeros].contains(&b'0');
+    if zeros == scale as usize {
+        d.truncate(point);
+    } else if zeros != 0 && !inner_zeros {
+        d.truncate(d.len() - zeros);
+    }
+}
+
+fn write_hex(d: &mut String, v: &[u8]) {
+    const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+    d.reserve(2 + 2 * v.len());
+    d.push_str("0x");
+    for b in v {
+        d.push(DIGITS[(b >> 4) as usize] as char);
+        d.push(DIGITS[(b & 0xf) as usize] as char);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::queryplanner::query_executor::batch_to_dataframe;
+    use crate::table::TableValue;
+    use arrow::array::ArrayRef;
+    use arrow::datatypes::{Field, Schema};
+    use hex::ToHex;
+    use std::sync::Arc;
+    use test::Bencher;
+
+    /// Text of a value of a [crate::store::DataFrame] sent by `write_rows`.
+    fn row_text(v: &TableValue) -> Option<String> {
+        match v {
+            TableValue::Null => None,
+            TableValue::String(s) | TableValue::Decimal(s) => Some(s.clone()),
+            TableValue::Int(i) => Some(i.to_string()),
+            TableValue::Timestamp(t) => Some(t.to_string()),
+            TableValue::Boolean(b) => Some(b.to_string()),
+            TableValue::Float(f) => Some(f.to_string()),
+            TableValue::Bytes(b) => Some(format!("0x{}", b.encode_hex_upper::<String>())),
+        }
+    }
+
+    fn assert_same_as_rows(batch: &RecordBatch, text_rows: &mut TextRows) {
+        text_rows.convert(batch).unwrap();
+        let expected = batch_to_dataframe(&vec![batch.clone()]).unwrap();
+        assert_eq!(text_rows.num_rows(), expected.get_rows().len());
+        for (i, row) in expected.get_rows().iter().enumerate() {
+            for (c, v) in row.values().iter().enumerate() {
+                assert_eq!(
+                    text_rows.columns[c].value(i).map(|s| s.to_string()),
+                    row_text(v),
+                    "column {}, row {}",
+                    c,
+                    i
+                );
+            }
+        }
+    }
+
+    fn column(name: &str, a: ArrayRef) -> (Field, ArrayRef) {
+        (Field::new(name, a.data_type().clone(), true), a)
+    }
+
+    fn batch(columns: Vec<(Field, ArrayRef)>) -> RecordBatch {
+        let (fields, arrays): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), arrays).unwrap()
+    }
+
+    #[test]
+    fn same_text_as_data_frames() {
+        let ints = vec![
+            Some(0),
+            Some(-1),
+            Some(5),
+            Some(100),
+            Some(105),
+            Some(150),
+            Some(-100_050),
+            None,
+            Some(i64::MAX),
+            Some(i64::MIN),
+        ];
+        let mut columns = vec![
+            column("int", Arc::new(Int64Array::from(ints.clone()))),
+            column(
+                "uint",
+                Arc::new(UInt64Array::from(vec![Some(0), Some(u64::MAX), None])),
+            ),
+            column(
+                "float",
+                Arc::new(Float64Array::from(vec![
+                    Some(0.1 + 0.2),
+                    Some(-0.),
+                    Some(1e100),
+                    Some(f64::NAN),
+                    None,
+                ])),
+            ),
+            column("dec0", Arc::new(Int64Decimal0Array::from(ints.clone()))),
+            column("dec1", Arc::new(Int64Decimal1Array::from(ints.clone()))),
+            column("dec2", Arc::new(Int64Decimal2Array::from(ints.clone()))),
+            column("dec3", Arc::new(Int64Decimal3Array::from(ints.clone()))),
+            column("dec4", Arc::new(Int64Decimal4Array::from(ints.clone()))),
+            column("dec5", Arc::new(Int64Decimal5Array::from(ints.clone()))),
+            column("dec10", Arc::new(Int64Decimal10Array::from(ints.clone()))),
+            column(
+                "ts_micros",
+                Arc::new(TimestampMicrosecondArray::from_opt_vec(
+                    vec![
+                        Some(0),
+                        Some(1_500),
+                        Some(-1),
+                        Some(1_614_556_800_000_001),
+                        None,
+                    ],
+                    None,
+                )),
+            ),
+            column(
+                "ts_nanos",
+                Arc::new(TimestampNanosecondArray::from_opt_vec(
+                    vec![Some(0), Some(1_000), Some(1_001), Some(-1_000_000), None],
+                    None,
+                )),
+            ),
+            column(
+                "binary",
+                Arc::new(BinaryArray::from(vec![
+                    Some(&[0u8, 0x1f, 0xff][..]),
+                    Some(&[][..]),
+                    None,
+                ])),
+            ),
+            column(
+                "string",
+                Arc::new(StringArray::from(vec![
+                    Some("a"),
+                    Some(""),
+                    None,
+                    Some("ü"),
+                ])),
+            ),
+            column(
+                "bool",
+                Arc::new(BooleanArray::from(vec![Some(true), Some(false), None])),
+            ),
+        ];
+        // Columns are converted one by one, buffers are reused by the following batches.
+        let mut text_rows = TextRows::default();
+        for (f, a) in columns.drain(..) {
+            let len = a.len();
+            let b = batch(vec![
+                (f, a),
+                column("n", Arc::new(Int64Array::from(vec![1; len]))),
+            ]);
+            assert_same_as_rows(&b, &mut text_rows);
+        }
+    }
+
+    fn million_rows() -> RecordBatch {
+        let rows = 1_000_000;
+        batch(vec![
+            column(
+                "id",
+                Arc::new(Int64Array::from((0..rows).collect::<Vec<_>>())),
+            ),
+            column(
+                "name",
+                Arc::new(StringArray::from(
+                    (0..rows)
+                        .map(|i| Some(format!("value {}", i % 100)))
+                        .collect::<Vec<_>>(),
+                )),
+            ),
+            column(
+                "amount",
+                Arc::new(Int64Decimal2Array::from(
+                    (0..rows).map(|i| Some(i * 7)).collect::<Vec<_>>(),
+                )),
+            ),
+            column(
+                "ratio",
+                Arc::new(Float64Array::from(
+                    (0..rows).map(|i| i as f64 / 3.).collect::<Vec<_>>(),
+                )),
+            ),
+            column(
+                "created_at",
+                Arc::new(TimestampMicrosecondArray::from_vec(
+                    (0..rows)
+                        .map(|i| 1_614_556_800_000_000 + i * 1000)
+                        .collect::<Vec<_>>(),
+                    None,
+                )),
+            ),
+        ])
+    }
+
+    /// Text of a million rows as sent by the MySQL server, compare with
+    /// [data_frame_text_million_rows].
+    #[bench]
+    fn text_rows_million_rows(b: &mut Bencher) {
+        let batch = million_rows();
+        let mut text_rows = TextRows::default();
+        b.iter(|| {
+            text_rows.convert(&batch).unwrap();
+            assert_eq!(text_rows.num_rows(), 1_000_000);
+        });
+    }
+
+    /// The same rows converted to a data frame and formatted one value at a time.
+    #[bench]
+    fn data_frame_text_million_rows(b: &mut Bencher) {
+        let batch = vec![million_rows()];
+        b.iter(|| {
+            let d = batch_to_dataframe(&batch).unwrap();
+            let mut size = 0;
+            for r in d.get_rows() {
+                for v in r.values() {
+                    size += row_text(v).map(|s| s.len()).unwrap_or(0);
+                }
+            }
+            assert!(size > 0);
+        });
+    }
+}
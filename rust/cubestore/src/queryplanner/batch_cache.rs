@@ -0,0 +1,339 @@
+use crate::util::memory_pressure::{Eviction, ShrinkableCache};
+use crate::CubeError;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::DFSchemaRef;
+use datafusion::physical_plan::memory::MemoryStream;
+use datafusion::physical_plan::{
+    collect, ExecutionPlan, OptimizerHints, Partitioning, SendableRecordBatchStream,
+};
+use std::any::Any;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Keeps decoded data of parquet files in memory on workers, so hot partitions are not decoded
+/// on every query. Partition and chunk files are never modified after they are written, so
+/// entries are only evicted to stay within the size budget. The budget can change while the cache
+/// is in use, see [BatchCache::set_max_size].
+pub struct BatchCache {
+    max_size: AtomicUsize,
+    state: Mutex<BatchCacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct BatchCacheState {
+    entries: lru::LruCache<BatchCacheKey, Arc<Vec<RecordBatch>>>,
+    size: usize,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct BatchCacheKey {
+    pub file: String,
+    pub projection: Option<Vec<usize>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BatchCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub size: usize,
+    pub max_size: usize,
+}
+
+impl BatchCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl BatchCache {
+    pub fn new(max_size: usize) -> BatchCache {
+        BatchCache {
+            max_size: AtomicUsize::new(max_size),
+            state: Mutex::new(BatchCacheState {
+                entries: lru::LruCache::unbounded(),
+                size: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, key: &BatchCacheKey) -> Option<Arc<Vec<RecordBatch>>> {
+        let r = self.state.lock().unwrap().entries.get(key).cloned();
+        if r.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        r
+    }
+
+    /// Data larger than the whole budget is not cached.
+    pub fn put(&self, key: BatchCacheKey, batches: Vec<RecordBatch>) -> Arc<Vec<RecordBatch>> {
+        let size = batches_size(&batches);
+        let batches = Arc::new(batches);
+        let max_size = self.max_size();
+        if max_size < size {
+            return batches;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.put(key, batches.clone()) {
+            state.size -= batches_size(&old);
+        }
+        state.size += size;
+        evict_to(&mut state, max_size);
+        batches
+    }
+
+    /// Zero means the cache is disabled.
+    pub fn max_size(&self) -> usize {
+        self.max_size.load(Ordering::Relaxed)
+    }
+
+    /// Evicts least recently used entries that don't fit into the new budget.
+    pub fn set_max_size(&self, max_size: usize) {
+        if self.max_size.swap(max_size, Ordering::Relaxed) <= max_size {
+            return;
+        }
+        evict_to(&mut self.state.lock().unwrap(), max_size);
+    }
+
+    pub fn stats(&self) -> BatchCacheStats {
+        let state = self.state.lock().unwrap();
+        BatchCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: state.entries.len(),
+            size: state.size,
+            max_size: self.max_size(),
+        }
+    }
+}
+
+impl ShrinkableCache for BatchCache {
+    fn name(&self) -> &'static str {
+        "batch cache"
+    }
+
+    fn shrink(&self, bytes: usize) -> Eviction {
+        let mut state = self.state.lock().unwrap();
+        let mut eviction = Eviction::default();
+        while eviction.bytes < bytes {
+            let (_, evicted) = match state.entries.pop_lru() {
+                Some(e) => e,
+                None => break,
+            };
+            let size = batches_size(&evicted);
+            state.size -= size;
+            eviction.entries += 1;
+            eviction.bytes += size;
+        }
+        eviction
+    }
+}
+
+fn evict_to(state: &mut BatchCacheState, max_size: usize) {
+    while max_size < state.size {
+        let (_, evicted) = state
+            .entries
+            .pop_lru()
+            .expect("cache size is positive, but no entries");
+        state.size -= batches_size(&evicted);
+    }
+}
+
+pub(crate) fn batches_size(batches: &[RecordBatch]) -> usize {
+    batches
+        .iter()
+        .flat_map(|b| b.columns())
+        .map(|c| c.get_array_memory_size())
+        .sum()
+}
+
+/// Reads the whole input through [BatchCache]. The input must produce the same data on every
+/// execution, i.e. it should not filter rows based on query predicates.
+#[derive(Debug)]
+pub struct CachedScanExec {
+    key: BatchCacheKey,
+    cache: Arc<BatchCache>,
+    input: Arc<dyn ExecutionPlan>,
+}
+
+impl std::fmt::Debug for BatchCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchCache")
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl CachedScanExec {
+    pub fn new(
+        key: BatchCacheKey,
+        cache: Arc<BatchCache>,
+        input: Arc<dyn ExecutionPlan>,
+    ) -> CachedScanExec {
+        CachedScanExec { key, cache, input }
+    }
+
+    async fn load(&self) -> Result<Arc<Vec<RecordBatch>>, CubeError> {
+        if let Some(batches) = self.cache.get(&self.key) {
+            return Ok(batches);
+        }
+        let batches = collect(self.input.clone()).await?;
+        Ok(self.cache.put(self.key.clone(), batches))
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for CachedScanExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert_eq!(children.len(), 1);
+        Ok(Arc::new(CachedScanExec::new(
+            self.key.clone(),
+            self.cache.clone(),
+            children.remove(0),
+        )))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        self.input.output_hints()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        assert_eq!(partition, 0);
+        let batches = self
+            .load()
+            .await
+            .map_err(|e| DataFusionError::Execution(e.to_string()))?;
+        Ok(Box::pin(MemoryStream::try_new(
+            batches.as_ref().clone(),
+            self.schema().to_schema_ref(),
+            None,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+
+    fn batch(rows: usize) -> Vec<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        vec![RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(vec![1; rows]))]).unwrap()]
+    }
+
+    fn key(file: &str) -> BatchCacheKey {
+        BatchCacheKey {
+            file: file.to_string(),
+            projection: None,
+        }
+    }
+
+    #[test]
+    fn eviction() {
+        let entry_size = batches_size(&batch(1000));
+        let cache = BatchCache::new(2 * entry_size);
+
+        assert!(cache.get(&key("a")).is_none());
+        cache.put(key("a"), batch(1000));
+        cache.put(key("b"), batch(1000));
+        assert!(cache.get(&key("a")).is_some());
+
+        // "b" is the least recently used entry now.
+        cache.put(key("c"), batch(1000));
+        assert!(cache.get(&key("b")).is_none());
+        assert!(cache.get(&key("a")).is_some());
+        assert!(cache.get(&key("c")).is_some());
+
+        // Too large to fit at all.
+        cache.put(key("d"), batch(3000));
+        assert!(cache.get(&key("d")).is_none());
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries, 2);
+        assert_eq!(stats.size, 2 * entry_size);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn shrink() {
+        let entry_size = batches_size(&batch(1000));
+        let cache = BatchCache::new(3 * entry_size);
+        cache.put(key("a"), batch(1000));
+        cache.put(key("b"), batch(1000));
+        cache.put(key("c"), batch(1000));
+        assert!(cache.get(&key("a")).is_some());
+
+        assert_eq!(
+            cache.shrink(entry_size + 1),
+            Eviction {
+                entries: 2,
+                bytes: 2 * entry_size
+            }
+        );
+        assert!(cache.get(&key("a")).is_some());
+        assert_eq!(cache.stats().size, entry_size);
+
+        assert_eq!(cache.shrink(10 * entry_size).entries, 1);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn set_max_size() {
+        let entry_size = batches_size(&batch(1000));
+        let cache = BatchCache::new(3 * entry_size);
+        cache.put(key("a"), batch(1000));
+        cache.put(key("b"), batch(1000));
+        cache.put(key("c"), batch(1000));
+
+        cache.set_max_size(2 * entry_size);
+        assert_eq!(cache.stats().entries, 2);
+        assert!(cache.get(&key("a")).is_none());
+
+        cache.set_max_size(0);
+        assert_eq!(cache.stats().size, 0);
+        cache.put(key("a"), batch(1000));
+        assert!(cache.get(&key("a")).is_none());
+
+        cache.set_max_size(entry_size);
+        cache.put(key("a"), batch(1000));
+        assert!(cache.get(&key("a")).is_some());
+    }
+}
@@ -0,0 +1,591 @@
+//! Casts to integers, decimals, timestamps and dates are computed by `CUBE_CAST`, so the router
+//! and workers convert values the same way for every pair of types. Casts are replaced before
+//! planning, e.g.:
+//!     SELECT CAST(amount AS DECIMAL(10, 2)), TRY_CAST(day AS DATE) FROM s.orders
+//! becomes:
+//!     SELECT CUBE_CAST(amount, CAST(NULL AS DECIMAL(10, 2)), 'decimal(10,2)', 'error'),
+//!            CUBE_CAST(day, CAST(NULL AS TIMESTAMP), 'date', 'try')
+//!     FROM s.orders
+//! The second argument only gives the result its type. Supported conversions are:
+//!   - integers, decimals, floats, booleans and strings to integers and decimals. Values are
+//!     rounded half away from zero, floats are rounded as they are printed, so `1.005` becomes
+//!     `1.01` as a `DECIMAL(3, 2)`. Booleans are 0 and 1.
+//!   - strings and timestamps to timestamps and dates. Strings are parsed as values of inserts
+//!     are, dates are timestamps at midnight UTC.
+//! Other pairs, e.g. timestamps to decimals, and values that can't be parsed fail the query.
+//! Values out of range of the type, e.g. 1000 cast to `DECIMAL(3, 1)`, are handled as
+//! [crate::config::ConfigObj::cast_overflow] says. `TRY_CAST` produces NULL in both cases.
+//!
+//! Casts of string literals to timestamps are kept, partition filters understand them.
+use crate::queryplanner::collation::function;
+use crate::sql::temporary_functions::visit_query;
+use crate::sql::timestamp_from_string;
+use crate::CubeError;
+use arrow::array::{
+    Array, ArrayRef, BooleanArray, Float64Array, Int64Array, Int64Decimal0Array,
+    Int64Decimal10Array, Int64Decimal1Array, Int64Decimal2Array, Int64Decimal3Array,
+    Int64Decimal4Array, Int64Decimal5Array, StringArray, TimestampMicrosecondArray,
+    TimestampNanosecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, TimeUnit};
+use bigdecimal::BigDecimal;
+use chrono::NaiveDate;
+use datafusion::sql::parser::Statement as DFStatement;
+use num::bigint::Sign;
+use num::{BigInt, One, Signed, ToPrimitive, Zero};
+use serde_derive::{Deserialize, Serialize};
+use sqlparser::ast::{DataType as SqlDataType, Expr, Statement, Value};
+use std::str::FromStr;
+use std::sync::Arc;
+
+const NANOS_IN_DAY: i64 = 86_400_000_000_000;
+
+/// What casts do with values out of range of the type.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum CastOverflow {
+    /// Fail the query.
+    Error,
+    /// Produce the closest value of the type, e.g. `99.9` for `DECIMAL(3, 1)`.
+    Saturate,
+    /// Produce NULL.
+    Null,
+}
+
+impl CastOverflow {
+    fn name(&self) -> &'static str {
+        match self {
+            CastOverflow::Error => "error",
+            CastOverflow::Saturate => "saturate",
+            CastOverflow::Null => "null",
+        }
+    }
+}
+
+impl FromStr for CastOverflow {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(CastOverflow::Error),
+            "saturate" => Ok(CastOverflow::Saturate),
+            "null" => Ok(CastOverflow::Null),
+            _ => Err(CubeError::user(format!(
+                "Cast overflow should be one of 'error', 'saturate' or 'null' but found '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Replaces casts in selects with `CUBE_CAST`, see the module docs.
+pub fn rewrite_casts(statement: &mut DFStatement, overflow: CastOverflow) -> Result<(), CubeError> {
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        visit_query(q, &mut |e| {
+            rewrite_cast(e, overflow);
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn rewrite_cast(e: &mut Expr, overflow: CastOverflow) {
+    let (expr, data_type, mode) = match e {
+        Expr::Cast { expr, data_type } => (expr, data_type, overflow.name()),
+        Expr::TryCast { expr, data_type } => (expr, data_type, "try"),
+        _ => return,
+    };
+    let (target, result_type) = match data_type {
+        SqlDataType::SmallInt | SqlDataType::Int | SqlDataType::BigInt => {
+            ("int".to_string(), SqlDataType::BigInt)
+        }
+        SqlDataType::Decimal(precision, scale) => (
+            format!(
+                "decimal({},{})",
+                precision.unwrap_or(18).min(18),
+                scale.unwrap_or(5)
+            ),
+            data_type.clone(),
+        ),
+        SqlDataType::Timestamp => {
+            if let Expr::Value(Value::SingleQuotedString(_)) = expr.as_ref() {
+                return;
+            }
+            ("timestamp".to_string(), SqlDataType::Timestamp)
+        }
+        SqlDataType::Date => ("date".to_string(), SqlDataType::Timestamp),
+        _ => return,
+    };
+    let value = expr.as_ref().clone();
+    *e = function(
+        "CUBE_CAST",
+        vec![
+            value,
+            Expr::Cast {
+                expr: Box::new(Expr::Value(Value::Null)),
+                data_type: result_type,
+            },
+            Expr::Value(Value::SingleQuotedString(target)),
+            Expr::Value(Value::SingleQuotedString(mode.to_string())),
+        ],
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CastTarget {
+    Int,
+    /// Values are kept with `scale` digits after the point and up to `digits` digits overall.
+    Decimal {
+        digits: u32,
+        scale: u32,
+    },
+    Timestamp,
+    Date,
+}
+
+impl CastTarget {
+    fn parse(target: &str, result_type: &DataType) -> Result<CastTarget, CubeError> {
+        let t = match (target, result_type) {
+            ("int", DataType::Int64) => CastTarget::Int,
+            ("timestamp", DataType::Timestamp(_, None)) => CastTarget::Timestamp,
+            ("date", DataType::Timestamp(_, None)) => CastTarget::Date,
+            (t, DataType::Int64Decimal(arrow_scale)) if t.starts_with("decimal(") => {
+                let (precision, scale) = t["decimal(".len()..]
+                    .strip_suffix(")")
+                    .and_then(|ps| {
+                        let (p, s) = ps.split_once(',')?;
+                        Some((p.parse::<u32>().ok()?, s.parse::<u32>().ok()?))
+                    })
+                    .ok_or_else(|| CubeError::internal(format!("Invalid cast target {}", t)))?;
+                if precision < scale {
+                    return Err(CubeError::user(format!(
+                        "Scale of DECIMAL({}, {}) is larger than its precision",
+                        precision, scale
+                    )));
+                }
+                // Values are stored with more digits after the point for some scales.
+                let arrow_scale = *arrow_scale as u32;
+                CastTarget::Decimal {
+                    digits: (precision - scale + arrow_scale).min(18),
+                    scale: arrow_scale,
+                }
+            }
+            (t, rt) => {
+                return Err(CubeError::internal(format!(
+                    "Cast target {} does not match {:?}",
+                    t, rt
+                )))
+            }
+        };
+        Ok(t)
+    }
+
+    fn name(&self) -> String {
+        match self {
+            CastTarget::Int => "BIGINT".to_string(),
+            CastTarget::Decimal { digits, scale } => format!("DECIMAL({}, {})", digits, scale),
+            CastTarget::Timestamp => "TIMESTAMP".to_string(),
+            CastTarget::Date => "DATE".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CastMode {
+    overflow: CastOverflow,
+    /// Set for `TRY_CAST`, values that can't be converted become NULL.
+    invalid_as_null: bool,
+}
+
+impl CastMode {
+    fn parse(mode: &str) -> Result<CastMode, CubeError> {
+        if mode == "try" {
+            return Ok(CastMode {
+                overflow: CastOverflow::Null,
+                invalid_as_null: true,
+            });
+        }
+        Ok(CastMode {
+            overflow: CastOverflow::from_str(mode)?,
+            invalid_as_null: false,
+        })
+    }
+}
+
+enum SourceValue<'a> {
+    Number(BigDecimal),
+    Float(f64),
+    String(&'a str),
+    /// Nanoseconds.
+    Timestamp(i64),
+}
+
+enum CastFailure {
+    Invalid,
+    /// Carries the closest value of the type.
+    Overflow(i64),
+}
+
+/// Converts `values` to `result_type` as `CUBE_CAST(values, CAST(NULL AS ..), target, mode)`.
+pub fn cast_array(
+    values: &ArrayRef,
+    result_type: &DataType,
+    target: &str,
+    mode: &str,
+) -> Result<ArrayRef, CubeError> {
+    let target = CastTarget::parse(target, result_type)?;
+    let mode = CastMode::parse(mode)?;
+    let mut r = Vec::with_capacity(values.len());
+    for i in 0..values.len() {
+        if values.is_null(i) {
+            r.push(None);
+            continue;
+        }
+        let v = source_value(values, i)?;
+        let display = source_display(&v);
+        r.push(match cast_value(v, target) {
+            Ok(v) => Some(v),
+            Err(CastFailure::Invalid) if mode.invalid_as_null => None,
+            Err(CastFailure::Invalid) => {
+                return Err(CubeError::user(format!(
+                    "Can't cast {} to {}",
+                    display,
+                    target.name()
+                )))
+            }
+            Err(CastFailure::Overflow(closest)) => match mode.overflow {
+                CastOverflow::Error => {
+                    return Err(CubeError::user(format!(
+                        "Value {} is out of range of {}",
+                        display,
+                        target.name()
+                    )))
+                }
+                CastOverflow::Saturate => Some(closest),
+                CastOverflow::Null => None,
+            },
+        });
+    }
+    build_array(r, result_type)
+}
+
+fn source_value(a: &ArrayRef, i: usize) -> Result<SourceValue, CubeError> {
+    macro_rules! decimal {
+        ($ARRAY_TYPE: ident, $SCALE: expr) => {{
+            let v = a.as_any().downcast_ref::<$ARRAY_TYPE>().unwrap().value(i);
+            SourceValue::Number(BigDecimal::new(BigInt::from(v), $SCALE))
+        }};
+    }
+    let v = match a.data_type() {
+        DataType::Int64 => SourceValue::Number(BigDecimal::from(
+            a.as_any().downcast_ref::<Int64Array>().unwrap().value(i),
+        )),
+        DataType::UInt64 => SourceValue::Number(BigDecimal::from(
+            a.as_any().downcast_ref::<UInt64Array>().unwrap().value(i),
+        )),
+        DataType::Int64Decimal(0) => decimal!(Int64Decimal0Array, 0),
+        DataType::Int64Decimal(1) => decimal!(Int64Decimal1Array, 1),
+        DataType::Int64Decimal(2) => decimal!(Int64Decimal2Array, 2),
+        DataType::Int64Decimal(3) => decimal!(Int64Decimal3Array, 3),
+        DataType::Int64Decimal(4) => decimal!(Int64Decimal4Array, 4),
+        DataType::Int64Decimal(5) => decimal!(Int64Decimal5Array, 5),
+        DataType::Int64Decimal(10) => decimal!(Int64Decimal10Array, 10),
+        DataType::Float64 => {
+            SourceValue::Float(a.as_any().downcast_ref::<Float64Array>().unwrap().value(i))
+        }
+        DataType::Boolean => SourceValue::Number(BigDecimal::from(
+            a.as_any().downcast_ref::<BooleanArray>().unwrap().value(i) as i64,
+        )),
+        DataType::Utf8 => {
+            SourceValue::String(a.as_any().downcast_ref::<StringArray>().unwrap().value(i))
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => SourceValue::Timestamp(
+            a.as_any()
+                .downcast_ref::<TimestampNanosecondArray>()
+                .unwrap()
+                .value(i),
+        ),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            let micros = a
+                .as_any()
+                .downcast_ref::<TimestampMicrosecondArray>()
+                .unwrap()
+                .value(i);
+            // Microseconds cover a wider range, later conversions report the overflow.
+            SourceValue::Timestamp(micros.saturating_mul(1000))
+        }
+        t => {
+            return Err(CubeError::user(format!(
+                "Can't cast values of type {:?}",
+                t
+            )))
+        }
+    };
+    Ok(v)
+}
+
+fn source_display(v: &SourceValue) -> String {
+    match v {
+        SourceValue::Number(n) => n.to_string(),
+        SourceValue::Float(f) => f.to_string(),
+        SourceValue::String(s) => format!("'{}'", s),
+        SourceValue::Timestamp(nanos) => format!("timestamp {}", nanos),
+    }
+}
+
+fn cast_value(v: SourceValue, target: CastTarget) -> Result<i64, CastFailure> {
+    match target {
+        CastTarget::Int => cast_number(v, 0, i64::MIN, i64::MAX),
+        CastTarget::Decimal { digits, scale } => {
+            let max = 10i64.pow(digits) - 1;
+            cast_number(v, scale, -max, max)
+        }
+        CastTarget::Timestamp => to_timestamp(v),
+        CastTarget::Date => {
+            let nanos = to_timestamp(v)?;
+            nanos
+                .div_euclid(NANOS_IN_DAY)
+                .checked_mul(NANOS_IN_DAY)
+                .ok_or(CastFailure::Overflow(i64::MIN))
+        }
+    }
+}
+
+/// Raw value with `scale` digits after the point, between `min` and `max`.
+fn cast_number(v: SourceValue, scale: u32, min: i64, max: i64) -> Result<i64, CastFailure> {
+    let n = match v {
+        SourceValue::Number(n) => n,
+        SourceValue::Float(f) if f.is_nan() => return Err(CastFailure::Invalid),
+        SourceValue::Float(f) if f.is_infinite() => {
+            return Err(CastFailure::Overflow(if f < 0. { min } else { max }))
+        }
+        // Floats print the shortest form that reads back as the same value.
+        SourceValue::Float(f) => {
+            BigDecimal::from_str(&f.to_string()).map_err(|_| CastFailure::Invalid)?
+        }
+        SourceValue::String(s) => {
+            BigDecimal::from_str(s.trim()).map_err(|_| CastFailure::Invalid)?
+        }
+        SourceValue::Timestamp(_) => return Err(CastFailure::Invalid),
+    };
+    match rescale(&n, scale).and_then(|r| r.to_i64()) {
+        Some(r) if min <= r && r <= max => Ok(r),
+        _ => Err(CastFailure::Overflow(if n.is_negative() {
+            min
+        } else {
+            max
+        })),
+    }
+}
+
+fn to_timestamp(v: SourceValue) -> Result<i64, CastFailure> {
+    match v {
+        SourceValue::Timestamp(nanos) if nanos == i64::MIN || nanos == i64::MAX => {
+            Err(CastFailure::Overflow(nanos))
+        }
+        SourceValue::Timestamp(nanos) => Ok(nanos),
+        SourceValue::String(s) => {
+            let s = s.trim();
+            if let Ok(t) = timestamp_from_string(s) {
+                return Ok(t.get_time_stamp());
+            }
+            // Dates are midnight UTC.
+            let date =
+                NaiveDate::parse_from_str(s, "%Y-%m-%d").map_err(|_| CastFailure::Invalid)?;
+            Ok(date.and_hms(0, 0, 0).timestamp_nanos())
+        }
+        SourceValue::Number(_) | SourceValue::Float(_) => Err(CastFailure::Invalid),
+    }
+}
+
+/// `n` with `scale` digits after the point, rounded half away from zero. `None` if the result is
+/// too large to be computed.
+fn rescale(n: &BigDecimal, scale: u32) -> Option<BigInt> {
+    let (digits, exponent) = n.as_bigint_and_exponent();
+    if digits.is_zero() {
+        return Some(digits);
+    }
+    let shift = scale as i64 - exponent;
+    if shift >= 0 {
+        // Anything larger overflows 64 bits anyway.
+        if shift > 40 {
+            return None;
+        }
+        return Some(digits * num::pow(BigInt::from(10), shift as usize));
+    }
+    let num_digits = digits.magnitude().to_string().len() as i64;
+    if -shift > num_digits {
+        return Some(BigInt::zero());
+    }
+    let divisor = num::pow(BigInt::from(10), (-shift) as usize);
+    let mut r = &digits / &divisor;
+    let rem = &digits % &divisor;
+    if rem.abs() * 2 >= divisor {
+        match digits.sign() {
+            Sign::Minus => r -= BigInt::one(),
+            _ => r += BigInt::one(),
+        }
+    }
+    Some(r)
+}
+
+fn build_array(values: Vec<Option<i64>>, t: &DataType) -> Result<ArrayRef, CubeError> {
+    let a: ArrayRef = match t {
+        DataType::Int64 => Arc::new(Int64Array::from(values)),
+        DataType::Int64Decimal(0) => Arc::new(Int64Decimal0Array::from(values)),
+        DataType::Int64Decimal(1) => Arc::new(Int64Decimal1Array::from(values)),
+        DataType::Int64Decimal(2) => Arc::new(Int64Decimal2Array::from(values)),
+        DataType::Int64Decimal(3) => Arc::new(Int64Decimal3Array::from(values)),
+        DataType::Int64Decimal(4) => Arc::new(Int64Decimal4Array::from(values)),
+        DataType::Int64Decimal(5) => Arc::new(Int64Decimal5Array::from(values)),
+        DataType::Int64Decimal(10) => Arc::new(Int64Decimal10Array::from(values)),
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => {
+            Arc::new(TimestampNanosecondArray::from_opt_vec(values, None))
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            Arc::new(TimestampMicrosecondArray::from_opt_vec(
+                values
+                    .into_iter()
+                    .map(|v| v.map(|v| v.div_euclid(1000)))
+                    .collect(),
+                None,
+            ))
+        }
+        t => {
+            return Err(CubeError::user(format!(
+                "Casts to {:?} are not supported",
+                t
+            )))
+        }
+    };
+    Ok(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStoreStatement};
+
+    fn cast(values: ArrayRef, result_type: DataType, target: &str, mode: &str) -> Vec<Option<i64>> {
+        let r = cast_array(&values, &result_type, target, mode).unwrap();
+        match result_type {
+            DataType::Int64 => r
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap()
+                .iter()
+                .collect(),
+            DataType::Int64Decimal(2) => r
+                .as_any()
+                .downcast_ref::<Int64Decimal2Array>()
+                .unwrap()
+                .iter()
+                .collect(),
+            DataType::Timestamp(TimeUnit::Nanosecond, None) => r
+                .as_any()
+                .downcast_ref::<TimestampNanosecondArray>()
+                .unwrap()
+                .iter()
+                .collect(),
+            t => panic!("unexpected type {:?}", t),
+        }
+    }
+
+    #[test]
+    fn cast_matrix() {
+        let decimal = DataType::Int64Decimal(2);
+        let strings: ArrayRef = Arc::new(StringArray::from(vec![
+            Some(" 1.005"),
+            Some("-2.5e1"),
+            None,
+            Some("1000"),
+        ]));
+        assert_eq!(
+            cast(strings.clone(), decimal.clone(), "decimal(5,2)", "saturate"),
+            vec![Some(101), Some(-2500), None, Some(99999)]
+        );
+        assert_eq!(
+            cast(strings.clone(), decimal.clone(), "decimal(5,2)", "null"),
+            vec![Some(101), Some(-2500), None, None]
+        );
+        let e = cast_array(&strings, &decimal, "decimal(5,2)", "error").unwrap_err();
+        assert_eq!(e.message, "Value '1000' is out of range of DECIMAL(5, 2)");
+
+        let floats: ArrayRef = Arc::new(Float64Array::from(vec![1.005, -0.125, f64::NAN]));
+        assert_eq!(
+            cast(floats.clone(), decimal.clone(), "decimal(18,2)", "try"),
+            vec![Some(101), Some(-13), None]
+        );
+        let e = cast_array(&floats, &decimal, "decimal(18,2)", "saturate").unwrap_err();
+        assert_eq!(e.message, "Can't cast NaN to DECIMAL(18, 2)");
+
+        let ints: ArrayRef = Arc::new(Int64Array::from(vec![i64::MAX, -3]));
+        assert_eq!(
+            cast(ints, decimal.clone(), "decimal(18,2)", "saturate"),
+            vec![Some(999999999999999999), Some(-300)]
+        );
+        let decimals: ArrayRef = Arc::new(Int64Decimal2Array::from(vec![Some(250), Some(-249)]));
+        assert_eq!(
+            cast(decimals, DataType::Int64, "int", "error"),
+            vec![Some(3), Some(-2)]
+        );
+        let big: ArrayRef = Arc::new(StringArray::from(vec!["1e30", "-1e30", "x"]));
+        assert_eq!(
+            cast(big, DataType::Int64, "int", "try"),
+            vec![None, None, None]
+        );
+
+        let ts = DataType::Timestamp(TimeUnit::Nanosecond, None);
+        let day = 1609459200000000000; // 2021-01-01T00:00:00Z
+        let timestamps: ArrayRef = Arc::new(TimestampNanosecondArray::from_opt_vec(
+            vec![Some(day + 3_600_000_000_000), Some(day - 1)],
+            None,
+        ));
+        assert_eq!(
+            cast(timestamps.clone(), ts.clone(), "date", "error"),
+            vec![Some(day), Some(day - NANOS_IN_DAY)]
+        );
+        assert_eq!(
+            cast(timestamps, ts.clone(), "timestamp", "error"),
+            vec![Some(day + 3_600_000_000_000), Some(day - 1)]
+        );
+        let strings: ArrayRef = Arc::new(StringArray::from(vec![
+            "2021-01-01T10:00:00Z",
+            "2021-01-01",
+            "yesterday",
+        ]));
+        assert_eq!(
+            cast(strings.clone(), ts.clone(), "date", "try"),
+            vec![Some(day), Some(day), None]
+        );
+        let e = cast_array(&strings, &ts, "date", "null").unwrap_err();
+        assert_eq!(e.message, "Can't cast 'yesterday' to DATE");
+        let r = cast_array(&strings, &decimal, "decimal(18,2)", "try").unwrap();
+        assert_eq!(r.null_count(), 3);
+    }
+
+    #[test]
+    fn rewrite() {
+        let query = "SELECT TRY_CAST(b AS DATE), CAST(c AS TEXT), \
+                            CAST('2021-01-01' AS TIMESTAMP), CAST(d AS INT) FROM s.t";
+        let mut statement = match CubeStoreParser::new(query)
+            .unwrap()
+            .parse_statement()
+            .unwrap()
+        {
+            CubeStoreStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        rewrite_casts(&mut statement, CastOverflow::Saturate).unwrap();
+        let sql = match statement {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        };
+        assert_eq!(
+            sql,
+            "SELECT CUBE_CAST(b, CAST(NULL AS TIMESTAMP), 'date', 'try'), CAST(c AS TEXT), \
+                    CAST('2021-01-01' AS TIMESTAMP), \
+                    CUBE_CAST(d, CAST(NULL AS BIGINT), 'int', 'saturate') \
+             FROM s.t"
+        );
+    }
+}
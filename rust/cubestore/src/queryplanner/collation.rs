@@ -0,0 +1,495 @@
+//! Collations of string columns, e.g.:
+//!     CREATE TABLE s.users (email text COLLATE ci, name text COLLATE unicode_ci)
+//! Collated values are compared by their collation keys, see [collation_key]. Queries are
+//! rewritten before planning, so workers and the router evaluate the same expressions, e.g.:
+//!     SELECT email, COUNT(*) FROM s.users WHERE email = 'A@b.com' GROUP BY 1 ORDER BY 1
+//! becomes:
+//!     SELECT MIN(email) AS email, COUNT(*) FROM s.users
+//!     WHERE COLLATION_KEY(email, 'ci') = COLLATION_KEY('A@b.com', 'ci')
+//!     GROUP BY COLLATION_KEY(email, 'ci') ORDER BY COLLATION_KEY(email, 'ci')
+//! Groups of collated columns are represented by their smallest value.
+//! Joins on collated columns compare keys computed in subqueries over the joined tables. Columns
+//! of join conditions must be qualified with the table name or alias.
+use crate::metastore::table::TablePath;
+use crate::metastore::Collation;
+use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+use crate::CubeError;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    BinaryOperator, Expr, Function, FunctionArg, Ident, JoinConstraint, JoinOperator, ObjectName,
+    Query, Select, SelectItem, SetExpr, Statement, TableAlias, TableFactor, TableWithJoins, Value,
+};
+use std::collections::HashMap;
+
+pub fn parse_collation(name: &str) -> Result<Collation, CubeError> {
+    match name.to_lowercase().as_str() {
+        "ci" | "nocase" | "case_insensitive" => Ok(Collation::CaseInsensitive),
+        "unicode_ci" => Ok(Collation::UnicodeCaseInsensitive),
+        _ => Err(CubeError::user(format!("Unsupported collation: {}", name))),
+    }
+}
+
+pub fn collation_name(c: Collation) -> &'static str {
+    match c {
+        Collation::CaseInsensitive => "ci",
+        Collation::UnicodeCaseInsensitive => "unicode_ci",
+    }
+}
+
+/// Strings with equal keys are equal under the collation, keys are ordered by their bytes.
+pub fn collation_key(c: Collation, s: &str) -> String {
+    match c {
+        Collation::CaseInsensitive => s.to_lowercase(),
+        Collation::UnicodeCaseInsensitive => s
+            .to_lowercase()
+            .chars()
+            .map(|c| strip_accent(c).unwrap_or(c))
+            .collect(),
+    }
+}
+
+/// Base letters of lowercase Latin-1 and Latin Extended-A letters with diacritics.
+fn strip_accent(c: char) -> Option<char> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => 'a',
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => 'c',
+        'ď' | 'đ' => 'd',
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => 'e',
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => 'g',
+        'ĥ' | 'ħ' => 'h',
+        'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => 'i',
+        'ĵ' => 'j',
+        'ķ' => 'k',
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => 'l',
+        'ñ' | 'ń' | 'ņ' | 'ň' => 'n',
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => 'o',
+        'ŕ' | 'ŗ' | 'ř' => 'r',
+        'ś' | 'ŝ' | 'ş' | 'š' => 's',
+        'ţ' | 'ť' | 'ŧ' => 't',
+        'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => 'u',
+        'ŵ' => 'w',
+        'ý' | 'ÿ' | 'ŷ' => 'y',
+        'ź' | 'ż' | 'ž' => 'z',
+        _ => return None,
+    })
+}
+
+/// `tables` are keyed by name in the `schema.table` form.
+pub fn apply_collations(
+    statement: &mut DFStatement,
+    tables: &HashMap<String, TablePath>,
+) -> Result<(), CubeError> {
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        visit_query(q, tables)?;
+    }
+    Ok(())
+}
+
+fn visit_query(q: &mut Query, tables: &HashMap<String, TablePath>) -> Result<(), CubeError> {
+    match &mut q.body {
+        SetExpr::Select(s) => {
+            let columns = visit_select(s, tables)?;
+            if !columns.is_empty() {
+                for o in q.order_by.iter_mut() {
+                    let target = select_item(s, &o.expr).unwrap_or(&o.expr);
+                    if let Some(c) = collation_of(target, &columns) {
+                        // Aliases are kept, they refer to the selected values.
+                        let e = match &o.expr {
+                            Expr::Value(_) => target.clone(),
+                            e => e.clone(),
+                        };
+                        o.expr = key(e, c);
+                    }
+                }
+                rewrite_grouping(s, &columns);
+            }
+        }
+        body => visit_set_expr(body, tables)?,
+    }
+    Ok(())
+}
+
+fn visit_set_expr(e: &mut SetExpr, tables: &HashMap<String, TablePath>) -> Result<(), CubeError> {
+    match e {
+        SetExpr::Select(s) => {
+            let columns = visit_select(s, tables)?;
+            rewrite_grouping(s, &columns);
+        }
+        SetExpr::Query(q) => visit_query(q, tables)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, tables)?;
+            visit_set_expr(right, tables)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rewrites comparisons and joins, returns collations of the columns of the selected tables.
+fn visit_select(
+    s: &mut Select,
+    tables: &HashMap<String, TablePath>,
+) -> Result<HashMap<String, Collation>, CubeError> {
+    let mut columns = HashMap::new();
+    for t in s.from.iter_mut() {
+        visit_table_with_joins(t, tables, &mut columns)?;
+    }
+    if columns.is_empty() {
+        return Ok(columns);
+    }
+    if let Some(e) = s.selection.as_mut() {
+        rewrite_comparisons(e, &columns);
+    }
+    if let Some(e) = s.having.as_mut() {
+        rewrite_comparisons(e, &columns);
+    }
+    Ok(columns)
+}
+
+fn visit_table_with_joins(
+    t: &mut TableWithJoins,
+    tables: &HashMap<String, TablePath>,
+    columns: &mut HashMap<String, Collation>,
+) -> Result<(), CubeError> {
+    visit_table_factor(&mut t.relation, tables, columns)?;
+    for j in t.joins.iter_mut() {
+        visit_table_factor(&mut j.relation, tables, columns)?;
+    }
+    for i in 0..t.joins.len() {
+        let on = match &mut t.joins[i].join_operator {
+            JoinOperator::Inner(JoinConstraint::On(on))
+            | JoinOperator::LeftOuter(JoinConstraint::On(on))
+            | JoinOperator::RightOuter(JoinConstraint::On(on))
+            | JoinOperator::FullOuter(JoinConstraint::On(on)) => on,
+            _ => continue,
+        };
+        let mut on = on.clone();
+        rewrite_join_condition(&mut on, t, tables, columns)?;
+        match &mut t.joins[i].join_operator {
+            JoinOperator::Inner(JoinConstraint::On(e))
+            | JoinOperator::LeftOuter(JoinConstraint::On(e))
+            | JoinOperator::RightOuter(JoinConstraint::On(e))
+            | JoinOperator::FullOuter(JoinConstraint::On(e)) => *e = on,
+            _ => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+fn visit_table_factor(
+    t: &mut TableFactor,
+    tables: &HashMap<String, TablePath>,
+    columns: &mut HashMap<String, Collation>,
+) -> Result<(), CubeError> {
+    match t {
+        TableFactor::Table { name, .. } => {
+            if let Some(table) = tables.get(&table_name(name)) {
+                for c in table.table.get_row().get_columns() {
+                    if let Some(collation) = c.collation() {
+                        columns.insert(c.get_name().clone(), collation);
+                    }
+                }
+            }
+        }
+        TableFactor::Derived { subquery, .. } => visit_query(subquery, tables)?,
+        TableFactor::NestedJoin(t) => visit_table_with_joins(t, tables, columns)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Equalities of two qualified columns, at least one of them collated, compare key columns
+/// added to the joined tables. Other comparisons are rewritten as in WHERE.
+fn rewrite_join_condition(
+    e: &mut Expr,
+    t: &mut TableWithJoins,
+    tables: &HashMap<String, TablePath>,
+    columns: &HashMap<String, Collation>,
+) -> Result<(), CubeError> {
+    match e {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            rewrite_join_condition(left, t, tables, columns)?;
+            rewrite_join_condition(right, t, tables, columns)
+        }
+        Expr::Nested(e) => rewrite_join_condition(e, t, tables, columns),
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } if is_column(left) && is_column(right) => {
+            let collation = match collation_of(left, columns).or(collation_of(right, columns)) {
+                Some(c) => c,
+                None => return Ok(()),
+            };
+            *left = Box::new(join_key(left, collation, t, tables)?);
+            *right = Box::new(join_key(right, collation, t, tables)?);
+            Ok(())
+        }
+        e => {
+            rewrite_comparisons(e, columns);
+            Ok(())
+        }
+    }
+}
+
+/// Replaces the table of the column with a subquery that also selects the collation key.
+fn join_key(
+    column: &Expr,
+    collation: Collation,
+    t: &mut TableWithJoins,
+    tables: &HashMap<String, TablePath>,
+) -> Result<Expr, CubeError> {
+    let unsupported = || {
+        CubeError::user(format!(
+            "Join on collated column {} is not supported, qualify it with the name of a table",
+            column
+        ))
+    };
+    let (qualifier, name) = match column {
+        Expr::CompoundIdentifier(parts) if 2 <= parts.len() => (
+            parts[parts.len() - 2].value.clone(),
+            parts[parts.len() - 1].clone(),
+        ),
+        _ => return Err(unsupported()),
+    };
+    let key_name = Ident::new(format!(
+        "__collation_{}_{}",
+        collation_name(collation),
+        name
+    ));
+    let relation = std::iter::once(&mut t.relation)
+        .chain(t.joins.iter_mut().map(|j| &mut j.relation))
+        .find(|r| relation_name(r).as_ref() == Some(&qualifier))
+        .ok_or_else(unsupported)?;
+    if let TableFactor::Table { name: table, .. } = relation {
+        let columns = tables
+            .get(&table_name(table))
+            .ok_or_else(unsupported)?
+            .table
+            .get_row()
+            .get_columns()
+            .iter()
+            .map(|c| SelectItem::UnnamedExpr(Expr::Identifier(Ident::new(c.get_name()))))
+            .collect::<Vec<_>>();
+        let mut subquery = match CubeStoreParser::new(&format!("SELECT * FROM {}", table))
+            .and_then(|mut p| p.parse_statement())
+        {
+            Ok(CubeStatement::Statement(Statement::Query(q))) => q,
+            _ => return Err(unsupported()),
+        };
+        match &mut subquery.body {
+            SetExpr::Select(s) => s.projection = columns,
+            _ => unreachable!(),
+        }
+        *relation = TableFactor::Derived {
+            lateral: false,
+            subquery,
+            alias: Some(TableAlias {
+                name: Ident::new(&qualifier),
+                columns: Vec::new(),
+            }),
+        };
+    }
+    let select = match relation {
+        TableFactor::Derived { subquery, .. } => match &mut subquery.body {
+            SetExpr::Select(s) => s,
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    };
+    let exists = select.projection.iter().any(|p| match p {
+        SelectItem::ExprWithAlias { alias, .. } => alias == &key_name,
+        _ => false,
+    });
+    if !exists {
+        select.projection.push(SelectItem::ExprWithAlias {
+            expr: key(Expr::Identifier(name), collation),
+            alias: key_name.clone(),
+        });
+    }
+    Ok(Expr::CompoundIdentifier(vec![
+        Ident::new(qualifier),
+        key_name,
+    ]))
+}
+
+/// Name used to qualify columns of the table.
+fn relation_name(t: &TableFactor) -> Option<String> {
+    match t {
+        TableFactor::Table {
+            alias: Some(alias), ..
+        }
+        | TableFactor::Derived {
+            alias: Some(alias), ..
+        } => Some(alias.name.value.clone()),
+        TableFactor::Table { name, .. } => name.0.last().map(|i| i.value.clone()),
+        _ => None,
+    }
+}
+
+fn rewrite_comparisons(e: &mut Expr, columns: &HashMap<String, Collation>) {
+    match e {
+        Expr::BinaryOp { left, op, right } => {
+            let is_comparison = match op {
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq
+                | BinaryOperator::Like
+                | BinaryOperator::NotLike => true,
+                _ => false,
+            };
+            let collation = collation_of(left, columns).or(collation_of(right, columns));
+            match collation {
+                Some(c) if is_comparison => {
+                    *left = Box::new(key(left.as_ref().clone(), c));
+                    *right = Box::new(key(right.as_ref().clone(), c));
+                }
+                _ => {
+                    rewrite_comparisons(left, columns);
+                    rewrite_comparisons(right, columns);
+                }
+            }
+        }
+        Expr::InList { expr, list, .. } => {
+            if let Some(c) = collation_of(expr, columns) {
+                *expr = Box::new(key(expr.as_ref().clone(), c));
+                for e in list.iter_mut() {
+                    *e = key(e.clone(), c);
+                }
+            }
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            if let Some(c) = collation_of(expr, columns) {
+                *expr = Box::new(key(expr.as_ref().clone(), c));
+                *low = Box::new(key(low.as_ref().clone(), c));
+                *high = Box::new(key(high.as_ref().clone(), c));
+            }
+        }
+        Expr::UnaryOp { expr, .. } | Expr::Nested(expr) => rewrite_comparisons(expr, columns),
+        _ => {}
+    }
+}
+
+/// Groups by collation keys and selects the smallest value of each group.
+fn rewrite_grouping(s: &mut Select, columns: &HashMap<String, Collation>) {
+    if columns.is_empty() {
+        return;
+    }
+    let mut grouped = Vec::new();
+    for i in 0..s.group_by.len() {
+        let target = select_item(s, &s.group_by[i])
+            .unwrap_or(&s.group_by[i])
+            .clone();
+        if let Some(c) = collation_of(&target, columns) {
+            s.group_by[i] = key(target.clone(), c);
+            grouped.push(target);
+        }
+    }
+    for p in s.projection.iter_mut() {
+        let (expr, alias) = match p {
+            SelectItem::UnnamedExpr(e) if grouped.contains(e) => match e {
+                Expr::Identifier(i) => (e.clone(), i.clone()),
+                Expr::CompoundIdentifier(parts) => (e.clone(), parts.last().unwrap().clone()),
+                _ => continue,
+            },
+            SelectItem::ExprWithAlias { expr, alias } if grouped.contains(expr) => {
+                (expr.clone(), alias.clone())
+            }
+            _ => continue,
+        };
+        *p = SelectItem::ExprWithAlias {
+            expr: function("MIN", vec![expr]),
+            alias,
+        };
+    }
+}
+
+/// Select item referenced by position or alias.
+fn select_item<'a>(s: &'a Select, e: &Expr) -> Option<&'a Expr> {
+    let item = match e {
+        Expr::Value(Value::Number(n, _)) => {
+            let position = n.parse::<usize>().ok()?;
+            s.projection.get(position.checked_sub(1)?)?
+        }
+        Expr::Identifier(i) => s.projection.iter().find(|p| match p {
+            SelectItem::ExprWithAlias { alias, .. } => alias == i,
+            _ => false,
+        })?,
+        _ => return None,
+    };
+    match item {
+        SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => Some(e),
+        _ => None,
+    }
+}
+
+fn collation_of(e: &Expr, columns: &HashMap<String, Collation>) -> Option<Collation> {
+    match e {
+        Expr::Identifier(i) => columns.get(&i.value).copied(),
+        Expr::CompoundIdentifier(parts) => columns.get(&parts.last()?.value).copied(),
+        _ => None,
+    }
+}
+
+fn is_column(e: &Expr) -> bool {
+    matches!(e, Expr::Identifier(_) | Expr::CompoundIdentifier(_))
+}
+
+fn key(e: Expr, c: Collation) -> Expr {
+    function(
+        "COLLATION_KEY",
+        vec![
+            e,
+            Expr::Value(Value::SingleQuotedString(collation_name(c).to_string())),
+        ],
+    )
+}
+
+pub(crate) fn function(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Function(Function {
+        name: ObjectName(vec![Ident::new(name)]),
+        args: args.into_iter().map(FunctionArg::Unnamed).collect(),
+        over: None,
+        distinct: false,
+    })
+}
+
+pub(crate) fn table_name(name: &ObjectName) -> String {
+    name.0
+        .iter()
+        .map(|i| i.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collation_keys() {
+        assert_eq!(parse_collation("CI").unwrap(), Collation::CaseInsensitive);
+        assert_eq!(
+            parse_collation("unicode_ci").unwrap(),
+            Collation::UnicodeCaseInsensitive
+        );
+        assert!(parse_collation("de_DE").is_err());
+
+        let ci = Collation::CaseInsensitive;
+        assert_eq!(collation_key(ci, "Foo@Bar.COM"), "foo@bar.com");
+        assert_eq!(collation_key(ci, "Ärger"), "ärger");
+        let unicode = Collation::UnicodeCaseInsensitive;
+        assert_eq!(collation_key(unicode, "Ärger"), "arger");
+        assert_eq!(collation_key(unicode, "CRÈME Brûlée"), "creme brulee");
+        assert_eq!(collation_key(unicode, "Straße"), "straße");
+    }
+}
@@ -0,0 +1,327 @@
+//! Rewrites correlated scalar subqueries in WHERE into joins with aggregated derived tables,
+//! which the SQL planner does not support otherwise. E.g.:
+//!     SELECT t.id FROM t WHERE t.x = (SELECT MAX(y) FROM s WHERE s.k = t.k)
+//! becomes:
+//!     SELECT t.id FROM t
+//!     JOIN (SELECT s.k AS __k0, MAX(y) AS __v FROM s GROUP BY s.k) __sq0 ON __sq0.__k0 = t.k
+//!     WHERE t.x = __sq0.__v
+//! Inner join drops rows without matches, which is correct as comparisons with NULL results of
+//! aggregates over empty inputs never pass the filter. Hence only a bare MIN, MAX, SUM or AVG is
+//! accepted as the subquery value. Subqueries that do not fit this shape are left untouched.
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    BinaryOperator, Expr, Ident, Join, JoinConstraint, JoinOperator, Query, Select, SelectItem,
+    SetExpr, Statement, TableAlias, TableFactor,
+};
+
+pub fn decorrelate_subqueries(statement: &mut DFStatement) {
+    let select = match statement {
+        DFStatement::Statement(Statement::Query(q)) => match &mut q.body {
+            SetExpr::Select(s) => s.as_mut(),
+            _ => return,
+        },
+        _ => return,
+    };
+    // Columns of joined subqueries would appear in the output of wildcards.
+    if select.from.is_empty()
+        || select.projection.iter().any(|p| match p {
+            SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => true,
+            _ => false,
+        })
+    {
+        return;
+    }
+    let selection = match select.selection.take() {
+        Some(s) => s,
+        None => return,
+    };
+
+    let outer = select
+        .from
+        .iter()
+        .flat_map(|t| std::iter::once(&t.relation).chain(t.joins.iter().map(|j| &j.relation)))
+        .filter_map(table_name)
+        .collect::<Vec<_>>();
+    let mut conjuncts = Vec::new();
+    split_conjunction(selection, &mut conjuncts);
+    let mut joins = Vec::new();
+    for c in conjuncts.iter_mut() {
+        if let Expr::BinaryOp { left, op, right } = c {
+            if !is_comparison(op) {
+                continue;
+            }
+            for side in vec![left, right] {
+                let decorrelated = match &**side {
+                    Expr::Subquery(q) => decorrelate(q, &outer, joins.len()),
+                    _ => None,
+                };
+                if let Some((join, value)) = decorrelated {
+                    joins.push(join);
+                    **side = value;
+                }
+            }
+        }
+    }
+    select.from[0].joins.extend(joins);
+    select.selection = conjuncts.into_iter().fold(None, |acc, c| match acc {
+        None => Some(c),
+        Some(acc) => Some(and(acc, c)),
+    });
+}
+
+fn decorrelate(q: &Query, outer: &[String], index: usize) -> Option<(Join, Expr)> {
+    if !q.order_by.is_empty() || q.limit.is_some() || q.offset.is_some() {
+        return None;
+    }
+    let select = match &q.body {
+        SetExpr::Select(s) => s.as_ref(),
+        _ => return None,
+    };
+    if select.distinct
+        || select.from.len() != 1
+        || !select.from[0].joins.is_empty()
+        || !select.group_by.is_empty()
+        || select.having.is_some()
+    {
+        return None;
+    }
+    let inner = table_name(&select.from[0].relation)?;
+    let value = match select.projection.as_slice() {
+        [SelectItem::UnnamedExpr(e)] | [SelectItem::ExprWithAlias { expr: e, .. }] => e.clone(),
+        _ => return None,
+    };
+    if !is_null_on_empty_aggregate(&value) {
+        return None;
+    }
+
+    let is_outer = |e: &Expr| match e {
+        Expr::CompoundIdentifier(parts) if 2 <= parts.len() => {
+            let table = &parts[parts.len() - 2].value;
+            table != &inner && outer.iter().any(|o| o == table)
+        }
+        _ => false,
+    };
+    let mut keys = Vec::new();
+    let mut filters = Vec::new();
+    let mut conjuncts = Vec::new();
+    split_conjunction(select.selection.clone()?, &mut conjuncts);
+    for c in conjuncts {
+        match &c {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Eq,
+                right,
+            } if is_outer(left) != is_outer(right) => {
+                if is_outer(left) {
+                    keys.push((right.as_ref().clone(), left.as_ref().clone()))
+                } else {
+                    keys.push((left.as_ref().clone(), right.as_ref().clone()))
+                }
+            }
+            _ => {
+                if references_outer(&c, &is_outer)? {
+                    return None;
+                }
+                filters.push(c)
+            }
+        }
+    }
+    if keys.is_empty() || references_outer(&value, &is_outer)? {
+        return None;
+    }
+    for (inner_key, _) in &keys {
+        if references_outer(inner_key, &is_outer)? {
+            return None;
+        }
+    }
+
+    let alias = Ident::new(format!("__sq{}", index));
+    let key_alias = |i: usize| Ident::new(format!("__k{}", i));
+    let value_alias = Ident::new("__v");
+
+    let mut derived = q.clone();
+    let mut derived_select: Select = select.clone();
+    derived_select.projection = keys
+        .iter()
+        .enumerate()
+        .map(|(i, (k, _))| SelectItem::ExprWithAlias {
+            expr: k.clone(),
+            alias: key_alias(i),
+        })
+        .chain(std::iter::once(SelectItem::ExprWithAlias {
+            expr: value,
+            alias: value_alias.clone(),
+        }))
+        .collect();
+    derived_select.selection = filters.into_iter().fold(None, |acc, c| match acc {
+        None => Some(c),
+        Some(acc) => Some(and(acc, c)),
+    });
+    derived_select.group_by = keys.iter().map(|(k, _)| k.clone()).collect();
+    derived.body = SetExpr::Select(Box::new(derived_select));
+
+    let on = keys
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, outer_key))| Expr::BinaryOp {
+            left: Box::new(Expr::CompoundIdentifier(vec![alias.clone(), key_alias(i)])),
+            op: BinaryOperator::Eq,
+            right: Box::new(outer_key),
+        })
+        .fold(None, |acc, c| match acc {
+            None => Some(c),
+            Some(acc) => Some(and(acc, c)),
+        })
+        .unwrap();
+    let join = Join {
+        relation: TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(derived),
+            alias: Some(TableAlias {
+                name: alias.clone(),
+                columns: Vec::new(),
+            }),
+        },
+        join_operator: JoinOperator::Inner(JoinConstraint::On(on)),
+    };
+    Some((join, Expr::CompoundIdentifier(vec![alias, value_alias])))
+}
+
+/// Inner join drops outer rows without matches, so the value must be an aggregate that is NULL
+/// on empty input, e.g. not COUNT or COALESCE(MAX(..), 0).
+fn is_null_on_empty_aggregate(e: &Expr) -> bool {
+    match e {
+        Expr::Function(f) if f.name.0.len() == 1 && f.args.len() == 1 && f.over.is_none() => {
+            let name = &f.name.0[0].value;
+            ["min", "max", "sum", "avg"]
+                .iter()
+                .any(|a| name.eq_ignore_ascii_case(a))
+        }
+        _ => false,
+    }
+}
+
+/// Name used to qualify columns of the table.
+fn table_name(t: &TableFactor) -> Option<String> {
+    match t {
+        TableFactor::Table {
+            alias: Some(alias), ..
+        }
+        | TableFactor::Derived {
+            alias: Some(alias), ..
+        } => Some(alias.name.value.clone()),
+        TableFactor::Table { name, .. } => name.0.last().map(|i| i.value.clone()),
+        _ => None,
+    }
+}
+
+/// Returns [None] if expression is too complex to analyze.
+fn references_outer(e: &Expr, is_outer: &impl Fn(&Expr) -> bool) -> Option<bool> {
+    Some(match e {
+        Expr::Identifier(_) | Expr::Value(_) => false,
+        Expr::CompoundIdentifier(_) => is_outer(e),
+        Expr::BinaryOp { left, right, .. } => {
+            references_outer(left, is_outer)? || references_outer(right, is_outer)?
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. } => references_outer(expr, is_outer)?,
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            references_outer(expr, is_outer)?
+                || references_outer(low, is_outer)?
+                || references_outer(high, is_outer)?
+        }
+        Expr::InList { expr, list, .. } => {
+            references_outer(expr, is_outer)? || {
+                let mut r = false;
+                for e in list {
+                    r |= references_outer(e, is_outer)?;
+                }
+                r
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn is_comparison(op: &BinaryOperator) -> bool {
+    match op {
+        BinaryOperator::Eq
+        | BinaryOperator::NotEq
+        | BinaryOperator::Lt
+        | BinaryOperator::LtEq
+        | BinaryOperator::Gt
+        | BinaryOperator::GtEq => true,
+        _ => false,
+    }
+}
+
+pub(super) fn split_conjunction(e: Expr, out: &mut Vec<Expr>) {
+    match e {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            split_conjunction(*left, out);
+            split_conjunction(*right, out);
+        }
+        e => out.push(e),
+    }
+}
+
+pub(super) fn and(l: Expr, r: Expr) -> Expr {
+    Expr::BinaryOp {
+        left: Box::new(l),
+        op: BinaryOperator::And,
+        right: Box::new(r),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn rewrite(s: &str) -> String {
+        let mut s = match CubeStoreParser::new(s).unwrap().parse_statement().unwrap() {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        decorrelate_subqueries(&mut s);
+        match s {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn correlated_scalar_subquery() {
+        assert_eq!(
+            rewrite(
+                "SELECT t.id FROM s.t t \
+                 WHERE t.x = (SELECT MAX(u.y) FROM s.u u WHERE u.k = t.k AND u.y > 0) AND t.id > 1"
+            ),
+            "SELECT t.id FROM s.t AS t \
+             JOIN (SELECT u.k AS __k0, MAX(u.y) AS __v FROM s.u AS u WHERE u.y > 0 GROUP BY u.k) AS __sq0 \
+             ON __sq0.__k0 = t.k \
+             WHERE t.x = __sq0.__v AND t.id > 1"
+        );
+
+        // Not correlated, COUNT, wrapped aggregates and complex conditions are left as is.
+        let sql = "SELECT t.id FROM s.t AS t WHERE t.x = (SELECT MAX(u.y) FROM s.u AS u)";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT t.id FROM s.t AS t WHERE t.x = (SELECT COUNT(u.y) FROM s.u AS u WHERE u.k = t.k)";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT t.id FROM s.t AS t WHERE t.x = (SELECT COALESCE(MAX(u.y), 0) FROM s.u AS u WHERE u.k = t.k)";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT t.id FROM s.t AS t WHERE t.x = (SELECT MAX(u.y) + 1 FROM s.u AS u WHERE u.k = t.k)";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT t.id FROM s.t AS t WHERE t.x = (SELECT MAX(u.y) FROM s.u AS u WHERE u.k = t.k OR u.y = t.x)";
+        assert_eq!(rewrite(sql), sql);
+    }
+}
@@ -0,0 +1,168 @@
+//! Table formats like Iceberg and Delta Lake delete rows of immutable data files by recording
+//! positions of the deleted rows separately. [DeletedRowsExec] drops these rows when reading
+//! files of attached tables, see [crate::metastore::Partition::deleted_rows]. Its input must read
+//! the whole file in order, i.e. without row group pruning.
+use arrow::array::BooleanArray;
+use arrow::compute::filter_record_batch;
+use arrow::datatypes::SchemaRef;
+use arrow::error::Result as ArrowResult;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::DFSchemaRef;
+use datafusion::physical_plan::{
+    ExecutionPlan, OptimizerHints, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+};
+use futures::stream::{Stream, StreamExt};
+use std::any::Any;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(Debug)]
+pub struct DeletedRowsExec {
+    input: Arc<dyn ExecutionPlan>,
+    /// Sorted positions of rows in the output of `input`.
+    deleted_rows: Arc<Vec<u64>>,
+}
+
+impl DeletedRowsExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>, deleted_rows: Arc<Vec<u64>>) -> DeletedRowsExec {
+        DeletedRowsExec {
+            input,
+            deleted_rows,
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for DeletedRowsExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert_eq!(children.len(), 1);
+        Ok(Arc::new(DeletedRowsExec::new(
+            children.remove(0),
+            self.deleted_rows.clone(),
+        )))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        self.input.output_hints()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        assert_eq!(partition, 0);
+        let input = self.input.execute(0).await?;
+        let schema = input.schema();
+        let deleted_rows = self.deleted_rows.clone();
+        let mut offset = 0;
+        let batches = input.map(move |batch| {
+            let batch = batch?;
+            let filtered = remove_rows(&batch, offset, &deleted_rows);
+            offset += batch.num_rows() as u64;
+            filtered
+        });
+        Ok(Box::pin(DeletedRowsStream {
+            schema,
+            batches: Box::pin(batches),
+        }))
+    }
+}
+
+/// Removes rows of `batch` whose positions are in `deleted_rows`, where `offset` is the position
+/// of the first row of the batch.
+fn remove_rows(batch: &RecordBatch, offset: u64, deleted_rows: &[u64]) -> ArrowResult<RecordBatch> {
+    let end = offset + batch.num_rows() as u64;
+    let start_index = deleted_rows.partition_point(|p| *p < offset);
+    let end_index = deleted_rows.partition_point(|p| *p < end);
+    if start_index == end_index {
+        return Ok(batch.clone());
+    }
+    let mut keep = vec![true; batch.num_rows()];
+    for p in &deleted_rows[start_index..end_index] {
+        keep[(p - offset) as usize] = false;
+    }
+    filter_record_batch(batch, &BooleanArray::from(keep))
+}
+
+struct DeletedRowsStream {
+    schema: SchemaRef,
+    batches: Pin<Box<dyn Stream<Item = ArrowResult<RecordBatch>> + Send>>,
+}
+
+impl Stream for DeletedRowsStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.batches.as_mut().poll_next(cx)
+    }
+}
+
+impl RecordBatchStream for DeletedRowsStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::collect;
+    use datafusion::physical_plan::memory::MemoryExec;
+
+    fn batch(values: Vec<i64>) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        RecordBatch::try_new(schema, vec![Arc::new(Int64Array::from(values))]).unwrap()
+    }
+
+    #[tokio::test]
+    async fn deleted_rows() {
+        let input = vec![
+            batch(vec![0, 1, 2]),
+            batch(vec![3, 4]),
+            batch(vec![5, 6, 7]),
+        ];
+        let schema = input[0].schema();
+        let exec = DeletedRowsExec::new(
+            Arc::new(MemoryExec::try_new(&vec![input], schema, None).unwrap()),
+            Arc::new(vec![0, 3, 4, 7, 100]),
+        );
+        let values = collect(Arc::new(exec))
+            .await
+            .unwrap()
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![1, 2, 5, 6]);
+    }
+}
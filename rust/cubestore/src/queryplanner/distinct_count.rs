@@ -0,0 +1,210 @@
+//! Rewrites COUNT(DISTINCT) into two levels of grouping, e.g.:
+//!     SELECT k, COUNT(DISTINCT user_id) FROM t WHERE x > 0 GROUP BY k
+//! becomes:
+//!     SELECT k, COUNT(user_id) AS "COUNT(DISTINCT user_id)"
+//!     FROM (SELECT k, user_id FROM t WHERE x > 0 GROUP BY k, user_id) AS __distinct
+//!     GROUP BY k
+//! Otherwise workers send sets of all distinct values of each group in a single partial
+//! aggregation state and the router keeps them in memory. Inner grouping is distributed like any
+//! other aggregation: workers group their partitions and the router merges the groups, in a
+//! streaming fashion when an index is sorted by the grouping columns.
+//! Only queries that select plain columns of GROUP BY and COUNT(DISTINCT) of a single column are
+//! rewritten.
+//!
+//! When the group keys and the distinct column include the whole sort key of the index, all rows
+//! of each inner group are in a single partition. Workers then finish the inner grouping and
+//! count distinct values of their partitions, the router only sums the counts, see
+//! [crate::queryplanner::optimizations]. Otherwise the router merges inner groups of all workers.
+//! Unsorted groups go through [crate::queryplanner::parallel_merge], which spills them to disk
+//! over [crate::config::ConfigObj::router_merge_memory_limit].
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    Expr, Function, Ident, Select, SelectItem, SetExpr, Statement, TableAlias, TableFactor,
+    TableWithJoins, Value,
+};
+
+pub fn rewrite_distinct_count(statement: &mut DFStatement) {
+    let query = match statement {
+        DFStatement::Statement(Statement::Query(q)) => q.as_mut(),
+        _ => return,
+    };
+    if query.with.is_some() {
+        return;
+    }
+    let select = match &query.body {
+        SetExpr::Select(s) => s.as_ref(),
+        _ => return,
+    };
+    let (inner, outer) = match split_distinct_count(select) {
+        Some(r) => r,
+        None => return,
+    };
+    let mut inner_query = query.clone();
+    inner_query.body = SetExpr::Select(Box::new(inner));
+    inner_query.order_by = Vec::new();
+    inner_query.limit = None;
+    inner_query.offset = None;
+
+    let mut outer = outer;
+    outer.from = vec![TableWithJoins {
+        relation: TableFactor::Derived {
+            lateral: false,
+            subquery: Box::new(inner_query),
+            alias: Some(TableAlias {
+                name: Ident::new("__distinct"),
+                columns: Vec::new(),
+            }),
+        },
+        joins: Vec::new(),
+    }];
+    query.body = SetExpr::Select(Box::new(outer));
+}
+
+/// Returns the inner and the outer select, the latter without FROM.
+fn split_distinct_count(select: &Select) -> Option<(Select, Select)> {
+    if select.distinct || select.having.is_some() || select.from.is_empty() {
+        return None;
+    }
+    let mut group_keys = Vec::new();
+    for g in &select.group_by {
+        let key = match g {
+            Expr::Identifier(i) => i.clone(),
+            Expr::Value(Value::Number(n, _)) => {
+                let position = n.parse::<usize>().ok()?;
+                match select.projection.get(position.checked_sub(1)?)? {
+                    SelectItem::UnnamedExpr(Expr::Identifier(i))
+                    | SelectItem::ExprWithAlias {
+                        expr: Expr::Identifier(i),
+                        ..
+                    } => i.clone(),
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        group_keys.push(key);
+    }
+
+    let mut distinct_column: Option<String> = None;
+    let mut projection = Vec::with_capacity(select.projection.len());
+    for item in &select.projection {
+        let (expr, alias) = match item {
+            SelectItem::UnnamedExpr(e) => (e, None),
+            SelectItem::ExprWithAlias { expr, alias } => (expr, Some(alias.clone())),
+            _ => return None,
+        };
+        match expr {
+            Expr::Identifier(i) if group_keys.contains(i) => projection.push(item.clone()),
+            Expr::Function(f) if is_count_distinct(f) => {
+                let column = f.args[0].to_string();
+                if !is_plain_identifier(&column)
+                    || *distinct_column.get_or_insert_with(|| column.clone()) != column
+                {
+                    return None;
+                }
+                // Keep the name the column would have without the rewrite.
+                let alias = alias.unwrap_or_else(|| {
+                    Ident::with_quote('"', format!("COUNT(DISTINCT {})", column))
+                });
+                projection.push(SelectItem::ExprWithAlias {
+                    expr: Expr::Function(Function {
+                        distinct: false,
+                        ..f.clone()
+                    }),
+                    alias,
+                });
+            }
+            _ => return None,
+        }
+    }
+    let distinct_column = Ident::new(distinct_column?);
+    // Inner select would have the same column twice.
+    if group_keys.contains(&distinct_column) {
+        return None;
+    }
+
+    let inner_columns = group_keys
+        .iter()
+        .chain(std::iter::once(&distinct_column))
+        .map(|i| Expr::Identifier(i.clone()))
+        .collect::<Vec<_>>();
+    let inner = Select {
+        projection: inner_columns
+            .iter()
+            .map(|e| SelectItem::UnnamedExpr(e.clone()))
+            .collect(),
+        group_by: inner_columns,
+        ..select.clone()
+    };
+    let outer = Select {
+        projection,
+        from: Vec::new(),
+        selection: None,
+        group_by: group_keys.into_iter().map(Expr::Identifier).collect(),
+        ..select.clone()
+    };
+    Some((inner, outer))
+}
+
+fn is_count_distinct(f: &Function) -> bool {
+    f.distinct && f.args.len() == 1 && f.name.to_string().eq_ignore_ascii_case("count")
+}
+
+/// Quoted and qualified names are not supported.
+fn is_plain_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn rewrite(s: &str) -> String {
+        let mut s = match CubeStoreParser::new(s).unwrap().parse_statement().unwrap() {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        rewrite_distinct_count(&mut s);
+        match s {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn distinct_count() {
+        assert_eq!(
+            rewrite(
+                "SELECT k, COUNT(DISTINCT user_id) FROM s.t WHERE x > 0 GROUP BY 1 ORDER BY 2 LIMIT 10"
+            ),
+            "SELECT k, COUNT(user_id) AS \"COUNT(DISTINCT user_id)\" \
+             FROM (SELECT k, user_id FROM s.t WHERE x > 0 GROUP BY k, user_id) AS __distinct \
+             GROUP BY k ORDER BY 2 LIMIT 10"
+        );
+        assert_eq!(
+            rewrite("SELECT count(distinct user_id) AS users FROM s.t"),
+            "SELECT count(user_id) AS users \
+             FROM (SELECT user_id FROM s.t GROUP BY user_id) AS __distinct"
+        );
+
+        // Other aggregates, expressions and distinct counts of group keys are left as is.
+        let sql = "SELECT k, COUNT(DISTINCT user_id), SUM(x) FROM s.t GROUP BY k";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT k, COUNT(DISTINCT user_id), COUNT(DISTINCT x) FROM s.t GROUP BY k";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT k + 1, COUNT(DISTINCT user_id) FROM s.t GROUP BY k + 1";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT t.k, COUNT(DISTINCT user_id) FROM s.t AS t GROUP BY t.k";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT k, COUNT(DISTINCT k) FROM s.t GROUP BY k";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT k, COUNT(DISTINCT user_id) FROM s.t GROUP BY k HAVING k > 1";
+        assert_eq!(rewrite(sql), sql);
+    }
+}
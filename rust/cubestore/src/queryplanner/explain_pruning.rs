@@ -0,0 +1,92 @@
+//! `SELECT * FROM system.explain_pruning('<query>')` reports, for each table scan of the query,
+//! how many partitions and chunks of the chosen index the filters of the query prune and which
+//! filters prune them. Chunks are pruned together with their partitions. Filters prune partitions
+//! only by columns of the sort key of the index, so filters that prune nothing on their own point
+//! at a missing index or a sort key in the wrong order.
+//!
+//! The query is planned as usual, but not executed, see [crate::queryplanner::planning::explain_pruning].
+//! The function is only recognized in the FROM clause of the top-level query.
+use crate::CubeError;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{Expr, FunctionArg, SetExpr, Statement, TableFactor, Value};
+
+pub const EXPLAIN_PRUNING_TABLE: &str = "system.explain_pruning";
+
+/// Removes the argument of `system.explain_pruning('<query>')` and returns the explained query.
+pub fn extract_explained_query(statement: &mut DFStatement) -> Result<Option<String>, CubeError> {
+    let select = match statement {
+        DFStatement::Statement(Statement::Query(q)) => match &mut q.body {
+            SetExpr::Select(s) => s,
+            _ => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    let mut explained = None;
+    for t in select.from.iter_mut() {
+        let relations =
+            std::iter::once(&mut t.relation).chain(t.joins.iter_mut().map(|j| &mut j.relation));
+        for r in relations {
+            let args = match r {
+                TableFactor::Table { name, args, .. }
+                    if name.to_string().eq_ignore_ascii_case(EXPLAIN_PRUNING_TABLE) =>
+                {
+                    args
+                }
+                _ => continue,
+            };
+            let query = match args.as_slice() {
+                [FunctionArg::Unnamed(Expr::Value(Value::SingleQuotedString(q)))] => q.clone(),
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "{} expects the query as a single string, e.g. {}('SELECT ...')",
+                        EXPLAIN_PRUNING_TABLE, EXPLAIN_PRUNING_TABLE
+                    )))
+                }
+            };
+            if explained.is_some() {
+                return Err(CubeError::user(format!(
+                    "Only one query can be explained by {}",
+                    EXPLAIN_PRUNING_TABLE
+                )));
+            }
+            args.clear();
+            explained = Some(query);
+        }
+    }
+    Ok(explained)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn extract(s: &str) -> Result<(String, Option<String>), CubeError> {
+        let mut s = match CubeStoreParser::new(s)?.parse_statement()? {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        let query = extract_explained_query(&mut s)?;
+        match s {
+            DFStatement::Statement(s) => Ok((s.to_string(), query)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn explained_query() {
+        assert_eq!(
+            extract("SELECT * FROM system.explain_pruning('SELECT a FROM s.t WHERE a = ''x''')")
+                .unwrap(),
+            (
+                "SELECT * FROM system.explain_pruning".to_string(),
+                Some("SELECT a FROM s.t WHERE a = 'x'".to_string())
+            )
+        );
+        assert_eq!(
+            extract("SELECT * FROM s.t").unwrap(),
+            ("SELECT * FROM s.t".to_string(), None)
+        );
+        assert!(extract("SELECT * FROM system.explain_pruning(1)").is_err());
+    }
+}
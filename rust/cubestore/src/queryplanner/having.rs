@@ -0,0 +1,143 @@
+//! Moves conditions of HAVING that only reference GROUP BY columns into WHERE, e.g.:
+//!     SELECT tenant_id, SUM(x) FROM t GROUP BY 1 HAVING tenant_id = 5
+//! becomes:
+//!     SELECT tenant_id, SUM(x) FROM t WHERE tenant_id = 5 GROUP BY 1
+//! Such conditions filter out whole groups, so it does not matter whether they are applied before
+//! or after grouping. In WHERE they prune partitions that cannot contain matching rows and are
+//! evaluated before aggregation on workers.
+use crate::queryplanner::decorrelate::{and, split_conjunction};
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{Expr, Select, SelectItem, SetExpr, Statement, Value};
+
+pub fn push_having_to_where(statement: &mut DFStatement) {
+    let select = match statement {
+        DFStatement::Statement(Statement::Query(q)) => match &mut q.body {
+            SetExpr::Select(s) => s.as_mut(),
+            _ => return,
+        },
+        _ => return,
+    };
+    let having = match select.having.take() {
+        Some(h) => h,
+        None => return,
+    };
+    let group_keys = group_keys(select);
+
+    let mut conjuncts = Vec::new();
+    split_conjunction(having, &mut conjuncts);
+    let (pushed, kept): (Vec<_>, Vec<_>) = conjuncts
+        .into_iter()
+        .partition(|c| only_references(c, &group_keys));
+    select.having = conjoin(kept);
+    if let Some(pushed) = conjoin(pushed) {
+        select.selection = Some(match select.selection.take() {
+            Some(s) => and(s, pushed),
+            None => pushed,
+        });
+    }
+}
+
+/// Column references used in GROUP BY, positions are resolved to select items. Aliases are not
+/// included, as they can shadow columns of the table.
+fn group_keys(select: &Select) -> Vec<Expr> {
+    select
+        .group_by
+        .iter()
+        .filter_map(|g| match g {
+            Expr::Value(Value::Number(n, _)) => {
+                let position = n.parse::<usize>().ok()?;
+                match select.projection.get(position.checked_sub(1)?)? {
+                    SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+                        Some(e.clone())
+                    }
+                    _ => None,
+                }
+            }
+            e => Some(e.clone()),
+        })
+        .filter(|e| match e {
+            Expr::Identifier(_) | Expr::CompoundIdentifier(_) => true,
+            _ => false,
+        })
+        .collect()
+}
+
+/// Only simple expressions over group keys and literals qualify.
+fn only_references(e: &Expr, group_keys: &[Expr]) -> bool {
+    match e {
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => group_keys.contains(e),
+        Expr::Value(_) => true,
+        Expr::BinaryOp { left, right, .. } => {
+            only_references(left, group_keys) && only_references(right, group_keys)
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Cast { expr, .. } => only_references(expr, group_keys),
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            only_references(expr, group_keys)
+                && only_references(low, group_keys)
+                && only_references(high, group_keys)
+        }
+        Expr::InList { expr, list, .. } => {
+            only_references(expr, group_keys) && list.iter().all(|e| only_references(e, group_keys))
+        }
+        _ => false,
+    }
+}
+
+fn conjoin(conjuncts: Vec<Expr>) -> Option<Expr> {
+    conjuncts.into_iter().fold(None, |acc, c| match acc {
+        None => Some(c),
+        Some(acc) => Some(and(acc, c)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn rewrite(s: &str) -> String {
+        let mut s = match CubeStoreParser::new(s).unwrap().parse_statement().unwrap() {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        push_having_to_where(&mut s);
+        match s {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn having_on_group_keys() {
+        assert_eq!(
+            rewrite(
+                "SELECT tenant_id, SUM(x) FROM s.t WHERE x > 0 GROUP BY 1 \
+                 HAVING tenant_id = 5 AND SUM(x) > 10"
+            ),
+            "SELECT tenant_id, SUM(x) FROM s.t WHERE x > 0 AND tenant_id = 5 GROUP BY 1 \
+             HAVING SUM(x) > 10"
+        );
+        assert_eq!(
+            rewrite(
+                "SELECT t.tenant_id, SUM(t.x) FROM s.t AS t GROUP BY t.tenant_id \
+                 HAVING t.tenant_id IN (1, 2)"
+            ),
+            "SELECT t.tenant_id, SUM(t.x) FROM s.t AS t WHERE t.tenant_id IN (1, 2) \
+             GROUP BY t.tenant_id"
+        );
+
+        // Aggregates, aliases and columns outside of GROUP BY stay in HAVING.
+        let sql = "SELECT tenant_id, SUM(x) FROM s.t GROUP BY 1 HAVING SUM(x) > 10";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT tenant_id AS t, SUM(x) FROM s.t GROUP BY 1 HAVING t = 5";
+        assert_eq!(rewrite(sql), sql);
+        let sql = "SELECT tenant_id, SUM(x) FROM s.t GROUP BY 1 HAVING tenant_id = 5 OR SUM(x) > 1";
+        assert_eq!(rewrite(sql), sql);
+    }
+}
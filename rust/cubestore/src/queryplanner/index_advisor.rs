@@ -0,0 +1,224 @@
+//! Collects access patterns of queries that had no index sorted on their filter and grouping
+//! columns. `system.index_recommendations` shows the most expensive patterns along with the index
+//! definition that would serve them. Indexes can only be added to tables without data, so
+//! recommendations are applied when the table is created next time, e.g. by the next rollup build.
+use crate::metastore::{IdRow, Index};
+use datafusion::logical_plan::Expr;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// New patterns are ignored after this number is reached.
+const MAX_PATTERNS: usize = 10000;
+
+#[derive(Debug)]
+pub struct IndexAdvisor {
+    patterns: Mutex<HashMap<AccessPattern, AccessStats>>,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+struct AccessPattern {
+    table: String,
+    columns: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct AccessStats {
+    queries: u64,
+    rows_scanned: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexRecommendation {
+    /// In the `schema.table` form.
+    pub table: String,
+    pub columns: Vec<String>,
+    pub queries: u64,
+    /// Total rows read by matching queries, an upper bound on the scan savings.
+    pub rows_scanned: u64,
+}
+
+impl IndexRecommendation {
+    pub fn index_definition(&self) -> String {
+        let table = self.table.rsplit('.').next().unwrap();
+        format!(
+            "INDEX {}_{} ({})",
+            table,
+            self.columns.join("_"),
+            self.columns.join(", ")
+        )
+    }
+}
+
+impl IndexAdvisor {
+    pub fn new() -> IndexAdvisor {
+        IndexAdvisor {
+            patterns: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a scan of `table` that was filtered by `filters` and grouped by `group_by`
+    /// columns. Nothing is recorded if one of the `indexes` already serves the scan.
+    pub fn record(
+        &self,
+        table: &str,
+        filters: &[Expr],
+        group_by: &[String],
+        indexes: &[IdRow<Index>],
+        rows_scanned: u64,
+    ) {
+        let mut columns = Vec::new();
+        for f in filters {
+            filter_columns(f, &mut columns);
+        }
+        for c in group_by {
+            let c = c.rsplit('.').next().unwrap();
+            if !columns.iter().any(|e| e == c) {
+                columns.push(c.to_string());
+            }
+        }
+        if columns.is_empty() || indexes.iter().any(|i| serves(i.get_row(), &columns)) {
+            return;
+        }
+
+        let pattern = AccessPattern {
+            table: table.to_string(),
+            columns,
+        };
+        let mut patterns = self.patterns.lock().unwrap();
+        if !patterns.contains_key(&pattern) && MAX_PATTERNS <= patterns.len() {
+            return;
+        }
+        let stats = patterns.entry(pattern).or_default();
+        stats.queries += 1;
+        stats.rows_scanned += rows_scanned;
+    }
+
+    /// Most expensive patterns go first.
+    pub fn recommendations(&self) -> Vec<IndexRecommendation> {
+        let mut r = self
+            .patterns
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(p, s)| IndexRecommendation {
+                table: p.table.clone(),
+                columns: p.columns.clone(),
+                queries: s.queries,
+                rows_scanned: s.rows_scanned,
+            })
+            .collect::<Vec<_>>();
+        r.sort_by(|a, b| {
+            (b.rows_scanned, b.queries, &a.table, &a.columns).cmp(&(
+                a.rows_scanned,
+                a.queries,
+                &b.table,
+                &b.columns,
+            ))
+        });
+        r
+    }
+}
+
+/// Index serves the scan if its sort key starts with the requested columns in any order.
+fn serves(index: &Index, columns: &[String]) -> bool {
+    let sort_key = &index.columns()[0..index.sort_key_size() as usize];
+    if sort_key.len() < columns.len() {
+        return false;
+    }
+    let prefix = sort_key[0..columns.len()]
+        .iter()
+        .map(|c| c.get_name().as_str())
+        .collect::<HashSet<_>>();
+    columns.iter().all(|c| prefix.contains(c.as_str()))
+}
+
+/// Columns compared with constants, the ones that allow to prune partitions.
+fn filter_columns(e: &Expr, out: &mut Vec<String>) {
+    fn add(e: &Expr, out: &mut Vec<String>) {
+        if let Expr::Column(name, _) = e {
+            if !out.iter().any(|c| c == name) {
+                out.push(name.clone());
+            }
+        }
+    }
+    match e {
+        Expr::BinaryExpr { left, right, .. } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(..), Expr::Literal(_)) => add(left, out),
+            (Expr::Literal(_), Expr::Column(..)) => add(right, out),
+            _ => {
+                filter_columns(left, out);
+                filter_columns(right, out);
+            }
+        },
+        Expr::InList { expr, .. } | Expr::Between { expr, .. } => add(expr, out),
+        Expr::Not(e) => filter_columns(e, out),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{Column, ColumnType};
+    use datafusion::logical_plan::{col, lit};
+
+    #[test]
+    fn recommendations() {
+        let columns = vec![
+            Column::new("a".to_string(), ColumnType::Int, 0),
+            Column::new("b".to_string(), ColumnType::Int, 1),
+            Column::new("c".to_string(), ColumnType::Int, 2),
+        ];
+        let indexes = vec![IdRow::new(
+            1,
+            Index::try_new("default".to_string(), 1, columns, 3).unwrap(),
+        )];
+
+        let advisor = IndexAdvisor::new();
+        // Served by the default index.
+        advisor.record("s.t", &[col("a").eq(lit(1i64))], &[], &indexes, 100);
+        advisor.record(
+            "s.t",
+            &[],
+            &["b".to_string(), "a".to_string()],
+            &indexes,
+            100,
+        );
+        assert_eq!(advisor.recommendations(), vec![]);
+
+        advisor.record("s.t", &[col("c").eq(lit(1i64))], &[], &indexes, 10);
+        advisor.record(
+            "s.t",
+            &[col("c").gt(lit(1i64))],
+            &["b".to_string()],
+            &indexes,
+            100,
+        );
+        advisor.record(
+            "s.t",
+            &[col("c").lt(lit(5i64))],
+            &["b".to_string()],
+            &indexes,
+            50,
+        );
+        let r = advisor.recommendations();
+        assert_eq!(
+            r,
+            vec![
+                IndexRecommendation {
+                    table: "s.t".to_string(),
+                    columns: vec!["c".to_string(), "b".to_string()],
+                    queries: 2,
+                    rows_scanned: 150,
+                },
+                IndexRecommendation {
+                    table: "s.t".to_string(),
+                    columns: vec!["c".to_string()],
+                    queries: 1,
+                    rows_scanned: 10,
+                },
+            ]
+        );
+        assert_eq!(r[0].index_definition(), "INDEX t_c_b (c, b)");
+    }
+}
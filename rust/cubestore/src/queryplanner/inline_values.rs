@@ -0,0 +1,165 @@
+//! The SQL planner does not support VALUES lists. We rewrite them into a UNION ALL of selects
+//! without FROM, i.e. each row is planned as an empty relation with a projection of literals.
+//! E.g. `(VALUES (1, 'a'), (2, 'b')) t(id, name)` becomes
+//! `(SELECT 1 AS id, 'a' AS name UNION ALL SELECT 2 AS id, 'b' AS name) t`.
+use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+use crate::CubeError;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    Ident, Query, Select, SelectItem, SetExpr, SetOperator, Statement, TableFactor, TableWithJoins,
+    Values,
+};
+
+pub fn rewrite_values(statement: &mut DFStatement) -> Result<(), CubeError> {
+    match statement {
+        DFStatement::Statement(Statement::Query(q)) => rewrite_query(q, &[]),
+        _ => Ok(()),
+    }
+}
+
+fn rewrite_query(q: &mut Query, columns: &[Ident]) -> Result<(), CubeError> {
+    rewrite_set_expr(&mut q.body, columns)
+}
+
+fn rewrite_set_expr(e: &mut SetExpr, columns: &[Ident]) -> Result<(), CubeError> {
+    match e {
+        SetExpr::Values(values) => *e = values_to_union(values, columns)?,
+        SetExpr::Select(s) => {
+            for t in s.from.iter_mut() {
+                rewrite_table_with_joins(t)?
+            }
+        }
+        SetExpr::Query(q) => rewrite_query(q, columns)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            rewrite_set_expr(left, columns)?;
+            rewrite_set_expr(right, columns)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn rewrite_table_with_joins(t: &mut TableWithJoins) -> Result<(), CubeError> {
+    rewrite_table_factor(&mut t.relation)?;
+    for j in t.joins.iter_mut() {
+        rewrite_table_factor(&mut j.relation)?;
+    }
+    Ok(())
+}
+
+fn rewrite_table_factor(t: &mut TableFactor) -> Result<(), CubeError> {
+    match t {
+        TableFactor::Derived {
+            subquery, alias, ..
+        } => {
+            let columns = match alias {
+                Some(alias) if matches!(subquery.body, SetExpr::Values(_)) => {
+                    // Names are assigned by the projection instead.
+                    std::mem::replace(&mut alias.columns, Vec::new())
+                }
+                _ => Vec::new(),
+            };
+            rewrite_query(subquery, &columns)
+        }
+        TableFactor::NestedJoin(t) => rewrite_table_with_joins(t),
+        _ => Ok(()),
+    }
+}
+
+fn values_to_union(values: &Values, columns: &[Ident]) -> Result<SetExpr, CubeError> {
+    let width = match values.0.first() {
+        Some(row) => row.len(),
+        None => return Err(CubeError::user("VALUES list is empty".to_string())),
+    };
+    if values.0.iter().any(|row| row.len() != width) {
+        return Err(CubeError::user(
+            "All rows in VALUES list must have the same number of values".to_string(),
+        ));
+    }
+    if !columns.is_empty() && columns.len() != width {
+        return Err(CubeError::user(format!(
+            "VALUES list has {} columns, but {} column names were specified",
+            width,
+            columns.len()
+        )));
+    }
+    let names = (0..width)
+        .map(|i| match columns.get(i) {
+            Some(c) => c.clone(),
+            None => Ident::new(format!("column{}", i + 1)),
+        })
+        .collect::<Vec<_>>();
+
+    let template = empty_select()?;
+    let mut union = None;
+    for row in values.0.iter() {
+        let mut select = template.clone();
+        select.projection = row
+            .iter()
+            .zip(names.iter())
+            .map(|(e, name)| SelectItem::ExprWithAlias {
+                expr: e.clone(),
+                alias: name.clone(),
+            })
+            .collect();
+        let select = SetExpr::Select(Box::new(select));
+        union = Some(match union {
+            None => select,
+            Some(left) => SetExpr::SetOperation {
+                op: SetOperator::Union,
+                all: true,
+                left: Box::new(left),
+                right: Box::new(select),
+            },
+        });
+    }
+    Ok(union.unwrap())
+}
+
+/// Query without FROM, parsed to avoid depending on the exact set of fields in the AST.
+pub(crate) fn empty_query() -> Result<Query, CubeError> {
+    match CubeStoreParser::new("SELECT 1")?.parse_statement()? {
+        CubeStatement::Statement(Statement::Query(q)) => Ok(*q),
+        _ => panic!("unexpected parse result"),
+    }
+}
+
+/// Select without FROM, see [empty_query].
+pub(crate) fn empty_select() -> Result<Select, CubeError> {
+    match empty_query()?.body {
+        SetExpr::Select(s) => Ok(*s),
+        _ => panic!("unexpected parse result"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(s: &str) -> Result<String, CubeError> {
+        let mut s = match CubeStoreParser::new(s)?.parse_statement()? {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        rewrite_values(&mut s)?;
+        match s {
+            DFStatement::Statement(s) => Ok(s.to_string()),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            rewrite("SELECT * FROM (VALUES (1, 'a'), (2, 'b')) AS t (id, name)").unwrap(),
+            "SELECT * FROM (SELECT 1 AS id, 'a' AS name UNION ALL SELECT 2 AS id, 'b' AS name) AS t"
+        );
+        assert_eq!(
+            rewrite("SELECT d.id FROM s.Data AS d JOIN (VALUES (1)) AS v ON d.id = v.column1")
+                .unwrap(),
+            "SELECT d.id FROM s.Data AS d JOIN (SELECT 1 AS column1) AS v ON d.id = v.column1"
+        );
+        assert!(rewrite("SELECT * FROM (VALUES (1, 2), (3)) AS t").is_err());
+        assert!(rewrite("SELECT * FROM (VALUES (1, 2)) AS t (a)").is_err());
+    }
+}
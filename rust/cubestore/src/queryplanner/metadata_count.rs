@@ -0,0 +1,414 @@
+//! Answers `SELECT COUNT(*) FROM t [WHERE ...]` from row counts of partitions and chunks kept in
+//! the metastore, without sending the query to workers. Filters are supported when every chosen
+//! partition lies entirely inside the range they select on the first column of the sort key,
+//! partitions outside of the range are already pruned when choosing the index.
+//! `MIN(col)` and `MAX(col)` are answered under the same conditions from [ColumnBounds] of
+//! partitions and chunks, e.g. `SELECT MAX(updated_at) FROM t` used as a refresh key, unless the
+//! bounds of some of them are unknown. Partition boundaries are split points rather than actual
+//! values of the data, so they are never used as bounds.
+use crate::metastore::{ColumnBounds, IdRow, Index, Partition};
+use crate::queryplanner::partition_filter::PartitionFilter;
+use crate::queryplanner::serialized_plan::{IndexSnapshot, PartitionSnapshot};
+use crate::queryplanner::CubeTableLogical;
+use crate::table::{cmp_same_types, TableValue};
+use arrow::datatypes::{DataType, Field, TimeUnit};
+use datafusion::logical_plan::{DFSchema, Expr, LogicalPlan, Operator};
+use datafusion::physical_plan::aggregates::AggregateFunction;
+use datafusion::scalar::ScalarValue;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// Returns a plan that computes the result without scanning the data or [None] if the query can
+/// not be answered from the metastore. `p` must be the plan passed to index selection, which
+/// produced `snapshots`.
+pub fn answer_from_metadata(p: &LogicalPlan, snapshots: &[IndexSnapshot]) -> Option<LogicalPlan> {
+    match p {
+        LogicalPlan::Projection {
+            expr,
+            input,
+            schema,
+        } => Some(LogicalPlan::Projection {
+            expr: expr.clone(),
+            input: Arc::new(answer_from_metadata(input, snapshots)?),
+            schema: schema.clone(),
+        }),
+        LogicalPlan::Sort { expr, input } => Some(LogicalPlan::Sort {
+            expr: expr.clone(),
+            input: Arc::new(answer_from_metadata(input, snapshots)?),
+        }),
+        LogicalPlan::Limit { n, input } => Some(LogicalPlan::Limit {
+            n: *n,
+            input: Arc::new(answer_from_metadata(input, snapshots)?),
+        }),
+        LogicalPlan::Aggregate {
+            input,
+            group_expr,
+            aggr_expr,
+            schema,
+        } => {
+            if !group_expr.is_empty() {
+                return None;
+            }
+            let snapshot = match snapshots {
+                [s] => s,
+                _ => return None,
+            };
+            if !is_exact(snapshot, &scan_filters(input)?) {
+                return None;
+            }
+            let expr = aggr_expr
+                .iter()
+                .zip(schema.fields().iter())
+                .map(|(e, f)| {
+                    Some(Expr::Alias(
+                        Box::new(Expr::Literal(aggregate_value(snapshot, e, f.data_type())?)),
+                        f.name().clone(),
+                    ))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            Some(LogicalPlan::Projection {
+                expr,
+                input: Arc::new(LogicalPlan::EmptyRelation {
+                    produce_one_row: true,
+                    schema: Arc::new(DFSchema::new(Vec::new()).ok()?),
+                }),
+                schema: schema.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// `COUNT(*)` and `COUNT(1)`.
+fn is_count_rows(e: &Expr) -> bool {
+    match e {
+        Expr::AggregateFunction {
+            fun: AggregateFunction::Count,
+            args,
+            distinct,
+        } => {
+            !*distinct
+                && match args.as_slice() {
+                    [Expr::Literal(v)] => !v.is_null(),
+                    _ => false,
+                }
+        }
+        _ => false,
+    }
+}
+
+/// Conjuncts of filters applied to the scan, [None] for inputs other than scans of tables.
+fn scan_filters(p: &LogicalPlan) -> Option<Vec<Expr>> {
+    match p {
+        LogicalPlan::Filter { predicate, input } => {
+            let mut filters = scan_filters(input)?;
+            split_conjunction(predicate, &mut filters);
+            Some(filters)
+        }
+        LogicalPlan::TableScan {
+            source, filters, ..
+        } => {
+            let table = source.as_any().downcast_ref::<CubeTableLogical>()?;
+            if table.sample_percent.is_some() {
+                return None;
+            }
+            let mut r = Vec::new();
+            for f in filters {
+                split_conjunction(f, &mut r);
+            }
+            Some(r)
+        }
+        _ => None,
+    }
+}
+
+fn split_conjunction(e: &Expr, out: &mut Vec<Expr>) {
+    match e {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            split_conjunction(left, out);
+            split_conjunction(right, out);
+        }
+        e => out.push(e.clone()),
+    }
+}
+
+fn aggregate_value(s: &IndexSnapshot, e: &Expr, data_type: &DataType) -> Option<ScalarValue> {
+    if is_count_rows(e) {
+        if data_type != &DataType::UInt64 {
+            return None;
+        }
+        return Some(ScalarValue::UInt64(Some(count_rows(s))));
+    }
+    let (is_min, column) = match e {
+        Expr::AggregateFunction {
+            fun,
+            args,
+            distinct: _,
+        } => match (fun, args.as_slice()) {
+            (AggregateFunction::Min, [Expr::Column(c, _)]) => (true, c),
+            (AggregateFunction::Max, [Expr::Column(c, _)]) => (false, c),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let position = s
+        .index()
+        .get_row()
+        .columns()
+        .iter()
+        .position(|c| c.get_name() == column)?;
+    let bounds = merged_bounds(s.partitions())?;
+    let value = if is_min {
+        &bounds.min.values()[position]
+    } else {
+        &bounds.max.values()[position]
+    };
+    scalar_value(value, data_type)
+}
+
+/// Whether all rows of the chosen partitions are the rows selected by the query.
+fn is_exact(s: &IndexSnapshot, filters: &[Expr]) -> bool {
+    s.partitions().iter().all(|p| {
+        let partition = p.partition();
+        // Chunks of parent partitions that were not repartitioned yet contain rows of other
+        // partitions, workers filter them out on read.
+        p.chunks()
+            .iter()
+            .all(|c| c.get_row().get_partition_id() == partition.get_id())
+            && filters
+                .iter()
+                .all(|f| matches_all_rows(s.index(), partition, f))
+    })
+}
+
+fn count_rows(s: &IndexSnapshot) -> u64 {
+    s.partitions()
+        .iter()
+        .map(|p| {
+            p.partition().get_row().main_table_row_count()
+                + p.chunks()
+                    .iter()
+                    .map(|c| c.get_row().get_row_count())
+                    .sum::<u64>()
+        })
+        .sum()
+}
+
+/// Bounds of all rows of the partitions, [None] if some of them are unknown or if there are no
+/// rows.
+fn merged_bounds(partitions: &[PartitionSnapshot]) -> Option<ColumnBounds> {
+    let mut result: Option<ColumnBounds> = None;
+    for p in partitions {
+        let partition = p.partition().get_row();
+        // Deleted rows may hold the bounds.
+        if !partition.deleted_rows().is_empty() {
+            return None;
+        }
+        let mut bounds = Vec::new();
+        if partition.main_table_row_count() > 0 {
+            bounds.push(partition.column_bounds().as_ref()?);
+        }
+        for c in p.chunks() {
+            bounds.push(c.get_row().column_bounds().as_ref()?);
+        }
+        for b in bounds {
+            result = Some(match result {
+                Some(r) => r.merge(b),
+                None => b.clone(),
+            });
+        }
+    }
+    result
+}
+
+fn scalar_value(v: &TableValue, data_type: &DataType) -> Option<ScalarValue> {
+    let v = match (v, data_type) {
+        (TableValue::Null, _) => return ScalarValue::try_from(data_type).ok(),
+        (TableValue::Int(i), DataType::Int64) => ScalarValue::Int64(Some(*i)),
+        (TableValue::String(s), DataType::Utf8) => ScalarValue::Utf8(Some(s.clone())),
+        (TableValue::Float(f), DataType::Float64) => ScalarValue::Float64(Some(f.0)),
+        (TableValue::Boolean(b), DataType::Boolean) => ScalarValue::Boolean(Some(*b)),
+        (TableValue::Timestamp(t), DataType::Timestamp(TimeUnit::Microsecond, None)) => {
+            ScalarValue::TimestampMicrosecond(Some(t.get_time_stamp() / 1000))
+        }
+        _ => return None,
+    };
+    Some(v)
+}
+
+/// Whether all rows of `partition` pass the filter. Only comparisons of the first sort key column
+/// with literals are analyzed.
+fn matches_all_rows(index: &IdRow<Index>, partition: &IdRow<Partition>, filter: &Expr) -> bool {
+    let (column, op, value) = match filter {
+        Expr::BinaryExpr { left, op, right } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(c, _), Expr::Literal(v)) => (c, *op, v),
+            (Expr::Literal(v), Expr::Column(c, _)) => match op {
+                Operator::Lt => (c, Operator::Gt, v),
+                Operator::LtEq => (c, Operator::GtEq, v),
+                Operator::Gt => (c, Operator::Lt, v),
+                Operator::GtEq => (c, Operator::LtEq, v),
+                op => (c, *op, v),
+            },
+            _ => return false,
+        },
+        _ => return false,
+    };
+    let first_column = &index.get_row().columns()[0];
+    if first_column.get_name() != column {
+        return false;
+    }
+    let field: Field = first_column.clone().into();
+    let value = match PartitionFilter::literal_value(value, field.data_type()) {
+        Some(TableValue::Null) | None => return false,
+        Some(v) => v,
+    };
+
+    // Rows of the partition are in the [min_val, max_val) range. Nulls go first, so a non-null
+    // lower boundary also guarantees there are no nulls in the column.
+    let min = match partition.get_row().get_min_val() {
+        Some(r) if r.values()[0] != TableValue::Null => &r.values()[0],
+        _ => return false,
+    };
+    let above = |inclusive: bool| match cmp_same_types(min, &value) {
+        Ordering::Greater => true,
+        Ordering::Equal => inclusive,
+        Ordering::Less => false,
+    };
+    let below = |inclusive: bool| match partition.get_row().get_max_val() {
+        Some(max) => match cmp_same_types(&max.values()[0], &value) {
+            Ordering::Less => true,
+            Ordering::Equal => inclusive || max.values().len() == 1,
+            Ordering::Greater => false,
+        },
+        None => false,
+    };
+    match op {
+        Operator::Gt => above(false),
+        Operator::GtEq => above(true),
+        Operator::Lt => below(false),
+        Operator::LtEq => below(true),
+        Operator::Eq => above(true) && below(true),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{Chunk, Column, ColumnType};
+    use crate::table::data::TableValueR;
+    use crate::table::{Row, TimestampValue};
+    use datafusion::logical_plan::{col, lit};
+
+    #[test]
+    fn partition_in_range() {
+        let columns = vec![
+            Column::new("a".to_string(), ColumnType::Int, 0),
+            Column::new("b".to_string(), ColumnType::Int, 1),
+        ];
+        let index = IdRow::new(
+            1,
+            Index::try_new("default".to_string(), 1, columns, 2).unwrap(),
+        );
+        let partition = |min: Option<i64>, max: Option<i64>| {
+            let row = |v: i64| Row::new(vec![TableValue::Int(v), TableValue::Int(0)]);
+            IdRow::new(1, Partition::new(1, min.map(row), max.map(row)))
+        };
+
+        let p = partition(Some(10), Some(20));
+        assert!(matches_all_rows(&index, &p, &col("a").gt_eq(lit(10i64))));
+        assert!(matches_all_rows(&index, &p, &col("a").gt(lit(9i64))));
+        assert!(!matches_all_rows(&index, &p, &col("a").gt(lit(10i64))));
+        assert!(matches_all_rows(&index, &p, &col("a").lt_eq(lit(20i64))));
+        assert!(matches_all_rows(&index, &p, &col("a").lt(lit(21i64))));
+        // Rows with a = 20 and b < 0 belong to the partition.
+        assert!(!matches_all_rows(&index, &p, &col("a").lt(lit(20i64))));
+        assert!(matches_all_rows(&index, &p, &lit(25i64).gt(col("a"))));
+        assert!(!matches_all_rows(&index, &p, &col("b").gt(lit(0i64))));
+
+        // Unbounded partitions may contain nulls and values outside of any range.
+        let p = partition(None, Some(20));
+        assert!(!matches_all_rows(&index, &p, &col("a").lt(lit(25i64))));
+        let p = partition(Some(10), None);
+        assert!(matches_all_rows(&index, &p, &col("a").gt(lit(5i64))));
+        assert!(!matches_all_rows(&index, &p, &col("a").lt(lit(25i64))));
+    }
+
+    #[test]
+    fn bounds_of_partitions() {
+        let bounds =
+            |rows: &[[TableValueR; 2]]| Some(ColumnBounds::of_rows(2, rows.iter().map(|r| &r[..])));
+        let t = |nanos: i64| TableValueR::Timestamp(TimestampValue::new(nanos));
+        let chunk = |partition_id: u64, bounds: Option<ColumnBounds>| {
+            IdRow::new(1, Chunk::new(partition_id, 2, bounds))
+        };
+
+        let mut partition = IdRow::new(1, Partition::new(1, None, None));
+        let mut snapshot = vec![PartitionSnapshot {
+            partition: partition.clone(),
+            chunks: vec![
+                chunk(
+                    1,
+                    bounds(&[[TableValueR::Int(3), t(5000)], [TableValueR::Null, t(1000)]]),
+                ),
+                chunk(1, bounds(&[[TableValueR::Int(7), TableValueR::Null]])),
+            ],
+        }];
+        let b = merged_bounds(&snapshot).unwrap();
+        assert_eq!(
+            b.min.values(),
+            &vec![
+                TableValue::Int(3),
+                TableValue::Timestamp(TimestampValue::new(1000))
+            ]
+        );
+        assert_eq!(
+            b.max.values(),
+            &vec![
+                TableValue::Int(7),
+                TableValue::Timestamp(TimestampValue::new(5000))
+            ]
+        );
+        assert_eq!(
+            scalar_value(
+                &b.max.values()[1],
+                &DataType::Timestamp(TimeUnit::Microsecond, None)
+            ),
+            Some(ScalarValue::TimestampMicrosecond(Some(5)))
+        );
+        assert_eq!(
+            scalar_value(&TableValue::Null, &DataType::Int64),
+            Some(ScalarValue::Int64(None))
+        );
+
+        // Bounds of the partition file are unknown.
+        partition = IdRow::new(
+            1,
+            partition
+                .get_row()
+                .update_min_max_and_row_count(None, None, 10),
+        );
+        snapshot[0].partition = partition.clone();
+        assert_eq!(merged_bounds(&snapshot), None);
+        snapshot[0].partition = IdRow::new(
+            1,
+            partition
+                .get_row()
+                .set_column_bounds(bounds(&[[TableValueR::Int(1), t(9000)]])),
+        );
+        let b = merged_bounds(&snapshot).unwrap();
+        assert_eq!(b.min.values()[0], TableValue::Int(1));
+        assert_eq!(
+            b.max.values()[1],
+            TableValue::Timestamp(TimestampValue::new(9000))
+        );
+
+        snapshot[0].chunks.push(chunk(1, None));
+        assert_eq!(merged_bounds(&snapshot), None);
+    }
+}
@@ -0,0 +1,329 @@
+//! Scans of partition and chunk files in the local cache, see [MmapParquetExec].
+//!
+//! Files are memory-mapped and pages are decoded straight from the mapping, so they are not copied
+//! through the buffered reader of [datafusion::physical_plan::parquet::ParquetExec] first. Row
+//! groups are pruned by the predicate using their statistics. Pages inside a row group are not
+//! pruned: that needs the page index of the parquet format (column and offset indexes), which the
+//! parquet version we use can't write or read. Keep row groups small enough for pruning instead.
+use crate::CubeError;
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::{ArrowError, Result as ArrowResult};
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::{DFSchemaRef, Expr, ToDFSchema};
+use datafusion::physical_plan::parquet::RowGroupPredicateBuilder;
+use datafusion::physical_plan::{
+    ExecutionPlan, OptimizerHints, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+};
+use futures::stream::Stream;
+use memmap2::Mmap;
+use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+use parquet::errors::Result as ParquetResult;
+use parquet::file::reader::{ChunkReader, FileReader, Length, SerializedFileReader};
+use std::any::Any;
+use std::cmp::min;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// A memory-mapped parquet file.
+#[derive(Clone)]
+pub struct MmapParquetFile {
+    path: String,
+    map: Arc<Mmap>,
+}
+
+impl MmapParquetFile {
+    pub fn open(path: &str) -> Result<MmapParquetFile, CubeError> {
+        let file = File::open(path)?;
+        // Safety: partition and chunk files are never modified after they are written and are
+        // only removed once queries reading them are done, see [crate::store::leases].
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MmapParquetFile {
+            path: path.to_string(),
+            map: Arc::new(map),
+        })
+    }
+}
+
+impl Length for MmapParquetFile {
+    fn len(&self) -> u64 {
+        self.map.len() as u64
+    }
+}
+
+impl ChunkReader for MmapParquetFile {
+    type T = MappedRange;
+
+    fn get_read(&self, start: u64, length: usize) -> ParquetResult<MappedRange> {
+        let end = min(start as usize + length, self.map.len());
+        Ok(MappedRange {
+            map: self.map.clone(),
+            position: min(start as usize, end),
+            end,
+        })
+    }
+}
+
+/// Reads a range of a mapped file, copying bytes directly into the buffer of the caller.
+pub struct MappedRange {
+    map: Arc<Mmap>,
+    position: usize,
+    end: usize,
+}
+
+impl Read for MappedRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = min(buf.len(), self.end - self.position);
+        buf[..n].copy_from_slice(&self.map[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Reads `files` one after another, all of them must have the same schema.
+pub struct MmapParquetExec {
+    files: Vec<MmapParquetFile>,
+    schema: DFSchemaRef,
+    projection: Vec<usize>,
+    predicate: Option<Arc<RowGroupPredicateBuilder>>,
+    batch_size: usize,
+}
+
+impl fmt::Debug for MmapParquetExec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MmapParquetExec")
+            .field(
+                "files",
+                &self.files.iter().map(|f| &f.path).collect::<Vec<_>>(),
+            )
+            .field("projection", &self.projection)
+            .field("predicate", &self.predicate.is_some())
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
+
+impl MmapParquetExec {
+    pub fn try_new(
+        paths: &[&str],
+        projection: Option<Vec<usize>>,
+        predicate: Option<Expr>,
+        batch_size: usize,
+    ) -> Result<MmapParquetExec, CubeError> {
+        if paths.is_empty() {
+            return Err(CubeError::internal(
+                "No files to scan in MmapParquetExec".to_string(),
+            ));
+        }
+        let files = paths
+            .iter()
+            .map(|p| MmapParquetFile::open(p))
+            .collect::<Result<Vec<_>, _>>()?;
+        let file_schema =
+            ParquetFileArrowReader::new(Arc::new(SerializedFileReader::new(files[0].clone())?))
+                .get_schema()?;
+        let projection = projection.unwrap_or_else(|| (0..file_schema.fields().len()).collect());
+        let schema = Arc::new(Schema::new(
+            projection
+                .iter()
+                .map(|i| file_schema.field(*i).clone())
+                .collect(),
+        ));
+        let predicate = predicate.and_then(|p| {
+            RowGroupPredicateBuilder::try_new(&p, file_schema.clone())
+                .ok()
+                .map(Arc::new)
+        });
+        Ok(MmapParquetExec {
+            files,
+            schema: schema.to_dfschema_ref()?,
+            projection,
+            predicate,
+            batch_size,
+        })
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for MmapParquetExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.schema.clone()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        Vec::new()
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert!(children.is_empty());
+        Ok(Arc::new(MmapParquetExec {
+            files: self.files.clone(),
+            schema: self.schema.clone(),
+            projection: self.projection.clone(),
+            predicate: self.predicate.clone(),
+            batch_size: self.batch_size,
+        }))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        OptimizerHints::default()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        assert_eq!(partition, 0);
+        let (tx, rx) = mpsc::channel(2);
+        let files = self.files.clone();
+        let projection = self.projection.clone();
+        let predicate = self.predicate.clone();
+        let batch_size = self.batch_size;
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = read_files(files, projection, predicate, batch_size, &tx) {
+                let _ = tx.blocking_send(Err(ArrowError::ExternalError(Box::new(e))));
+            }
+        });
+        Ok(Box::pin(MmapParquetStream {
+            schema: self.schema.to_schema_ref(),
+            batches: ReceiverStream::new(rx),
+        }))
+    }
+}
+
+/// Stops early once the stream is dropped.
+fn read_files(
+    files: Vec<MmapParquetFile>,
+    projection: Vec<usize>,
+    predicate: Option<Arc<RowGroupPredicateBuilder>>,
+    batch_size: usize,
+    tx: &mpsc::Sender<ArrowResult<RecordBatch>>,
+) -> Result<(), CubeError> {
+    for file in files {
+        let mut reader = SerializedFileReader::new(file)?;
+        if let Some(predicate) = &predicate {
+            let row_group_predicate =
+                predicate.build_row_group_predicate(reader.metadata().row_groups());
+            reader.filter_row_groups(&row_group_predicate);
+        }
+        let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(reader));
+        for batch in arrow_reader.get_record_reader_by_columns(projection.clone(), batch_size)? {
+            if tx.blocking_send(batch).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+struct MmapParquetStream {
+    schema: SchemaRef,
+    batches: ReceiverStream<ArrowResult<RecordBatch>>,
+}
+
+impl Stream for MmapParquetStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.batches).poll_next(cx)
+    }
+}
+
+impl RecordBatchStream for MmapParquetStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field};
+    use datafusion::logical_plan::{col, lit};
+    use datafusion::physical_plan::collect;
+    use parquet::arrow::ArrowWriter;
+    use parquet::file::properties::WriterProperties;
+
+    fn write_file(path: &str) {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, false),
+            Field::new("name", DataType::Utf8, false),
+        ]));
+        let props = WriterProperties::builder()
+            .set_max_row_group_size(10)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(File::create(path).unwrap(), schema.clone(), Some(props)).unwrap();
+        // A row group per batch.
+        for start in (0..100).step_by(10) {
+            let ids = (start..start + 10).collect::<Vec<i64>>();
+            let names = ids.iter().map(|i| format!("n{}", i)).collect::<Vec<_>>();
+            let batch = RecordBatch::try_new(
+                schema.clone(),
+                vec![
+                    Arc::new(Int64Array::from(ids)),
+                    Arc::new(StringArray::from(
+                        names.iter().map(|n| n.as_str()).collect::<Vec<_>>(),
+                    )),
+                ],
+            )
+            .unwrap();
+            writer.write(&batch).unwrap();
+        }
+        writer.close().unwrap();
+    }
+
+    fn ids(batches: &[RecordBatch]) -> Vec<i64> {
+        batches
+            .iter()
+            .flat_map(|b| {
+                b.column(0)
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn scan_and_prune_row_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("1.parquet").to_str().unwrap().to_string();
+        let second = dir.path().join("2.parquet").to_str().unwrap().to_string();
+        write_file(&first);
+        write_file(&second);
+
+        let exec = MmapParquetExec::try_new(&[&first, &second], Some(vec![0]), None, 7).unwrap();
+        assert_eq!(exec.schema().fields().len(), 1);
+        let batches = collect(Arc::new(exec)).await.unwrap();
+        assert_eq!(ids(&batches).len(), 200);
+        assert!(batches.iter().all(|b| b.num_rows() <= 7));
+
+        // Only the row group with ids 50..60 is read, the filter itself is applied later.
+        let exec =
+            MmapParquetExec::try_new(&[&first], None, Some(col("id").eq(lit(55i64))), 100).unwrap();
+        let batches = collect(Arc::new(exec)).await.unwrap();
+        assert_eq!(ids(&batches), (50..60).collect::<Vec<_>>());
+    }
+}
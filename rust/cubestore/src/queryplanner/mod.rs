@@ -1,30 +1,67 @@
+pub mod batch_cache;
+pub mod casts;
+pub mod collation;
+mod decorrelate;
+pub mod deleted_rows;
+mod distinct_count;
+mod explain_pruning;
+mod having;
 pub mod hll;
+pub mod index_advisor;
+mod inline_values;
+pub mod linked_servers;
+mod metadata_count;
+pub mod mmap_parquet;
+pub mod operator_limits;
 mod optimizations;
+mod order_by;
+pub mod parallel_merge;
 mod partition_filter;
 mod planning;
+pub mod pre_aggregations;
 pub mod pretty_printers;
 pub mod query_executor;
+pub mod read_snapshot;
 pub mod serialized_plan;
+pub mod stored_types;
+mod table_sample;
+pub mod time_zones;
 mod topk;
 pub use topk::MIN_TOPK_STREAM_ROWS;
 pub mod udfs;
+pub mod unsupported_features;
 
+use crate::cluster::replication::TableReplicator;
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
+use crate::metastore::history::MetaStoreHistory;
+use crate::metastore::job::JobStatus;
 use crate::metastore::table::TablePath;
 use crate::metastore::{MetaStore, MetaStoreTable};
-use crate::queryplanner::planning::choose_index_ext;
+use crate::mysql::sessions::Sessions;
+use crate::queryplanner::explain_pruning::EXPLAIN_PRUNING_TABLE;
+use crate::queryplanner::index_advisor::IndexAdvisor;
+use crate::queryplanner::planning::{choose_index_ext, PruningReport};
 use crate::queryplanner::query_executor::batch_to_dataframe;
+use crate::queryplanner::read_snapshot::{ReadSnapshot, SnapshotIndexStore};
 use crate::queryplanner::serialized_plan::SerializedPlan;
+use crate::queryplanner::time_zones::QueryTimeZone;
 use crate::queryplanner::udfs::aggregate_udf_by_kind;
 use crate::queryplanner::udfs::{scalar_udf_by_kind, CubeAggregateUDFKind, CubeScalarUDFKind};
+use crate::queryplanner::unsupported_features::check_distributable;
+use crate::sql::export::{ExportStatus, ResultExports};
+use crate::sql::parser::{CubeStoreParser, Statement as CubeStoreStatement};
+use crate::sql::prefetch::ResultPrefetcher;
+use crate::sql::submitted_queries::{QueryStatus, SubmittedQueries};
+use crate::store::leases::DataFileLeases;
 use crate::store::DataFrame;
 use crate::CubeError;
-use arrow::array::StringArray;
-use arrow::datatypes::Field;
+use arrow::array::{StringArray, TimestampNanosecondArray, UInt64Array};
+use arrow::datatypes::{Field, TimeUnit};
 use arrow::{array::Array, datatypes::Schema, datatypes::SchemaRef};
 use arrow::{datatypes::DataType, record_batch::RecordBatch};
 use async_trait::async_trait;
+use chrono::Utc;
 use core::fmt;
 use datafusion::catalog::TableReference;
 use datafusion::datasource::datasource::{Statistics, TableProviderFilterPushDown};
@@ -37,27 +74,65 @@ use datafusion::physical_plan::{collect, ExecutionPlan, Partitioning, SendableRe
 use datafusion::sql::parser::Statement;
 use datafusion::sql::planner::{ContextProvider, SqlToRel};
 use datafusion::{datasource::TableProvider, prelude::ExecutionContext};
+use futures::future::join_all;
+use itertools::Itertools;
 use log::{debug, trace};
 use mockall::automock;
 use serde_derive::{Deserialize, Serialize};
 use smallvec::alloc::fmt::Formatter;
+use sqlparser::ast::Statement as SQLStatement;
 use std::any::Any;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 #[automock]
 #[async_trait]
 pub trait QueryPlanner: DIService + Send + Sync {
-    async fn logical_plan(&self, statement: Statement) -> Result<QueryPlan, CubeError>;
+    /// Plans the statement for the connection with `options`, see [SessionOptions].
+    async fn logical_plan(
+        &self,
+        statement: Statement,
+        options: SessionOptions,
+    ) -> Result<QueryPlan, CubeError>;
+    /// Plans the statement over source tables only, without reading pre-aggregations.
+    async fn source_logical_plan(
+        &self,
+        statement: Statement,
+        options: SessionOptions,
+    ) -> Result<QueryPlan, CubeError>;
+    /// Plans independent statements concurrently against a single snapshot of the table list.
+    /// Planning errors are reported per statement.
+    async fn logical_plans(
+        &self,
+        statements: Vec<Statement>,
+        options: SessionOptions,
+    ) -> Result<Vec<Result<QueryPlan, CubeError>>, CubeError>;
     async fn execute_meta_plan(&self, plan: LogicalPlan) -> Result<DataFrame, CubeError>;
 }
 
+/// Settings of the connection that plans of its queries depend on.
+#[derive(Clone, Debug, Default)]
+pub struct SessionOptions {
+    /// Partitions and chunks to read, see [read_snapshot].
+    pub read_snapshot: Option<Arc<ReadSnapshot>>,
+    /// [ConfigObj::time_zone] if not set, see [time_zones].
+    pub time_zone: Option<QueryTimeZone>,
+}
+
 crate::di_service!(MockQueryPlanner, [QueryPlanner]);
 
 pub struct QueryPlannerImpl {
     meta_store: Arc<dyn MetaStore>,
     config: Arc<dyn ConfigObj>,
+    index_advisor: Arc<IndexAdvisor>,
+    exports: Arc<ResultExports>,
+    submitted_queries: Arc<SubmittedQueries>,
+    prefetcher: Arc<ResultPrefetcher>,
+    replicator: Arc<TableReplicator>,
+    metastore_history: Arc<MetaStoreHistory>,
+    sessions: Arc<Sessions>,
+    file_leases: Arc<DataFileLeases>,
 }
 
 crate::di_service!(QueryPlannerImpl, [QueryPlanner]);
@@ -69,33 +144,38 @@ pub enum QueryPlan {
 
 #[async_trait]
 impl QueryPlanner for QueryPlannerImpl {
-    async fn logical_plan(&self, statement: Statement) -> Result<QueryPlan, CubeError> {
-        let ctx = self.execution_context().await?;
-
-        let schema_provider = MetaStoreSchemaProvider::new(
-            self.meta_store.get_tables_with_path().await?,
-            self.meta_store.clone(),
-        );
-
-        let query_planner = SqlToRel::new(&schema_provider);
-        let mut logical_plan = query_planner.statement_to_plan(&statement)?;
-
-        logical_plan = ctx.optimize(&logical_plan)?;
-        trace!("Logical Plan: {:#?}", &logical_plan);
+    async fn logical_plan(
+        &self,
+        statement: Statement,
+        options: SessionOptions,
+    ) -> Result<QueryPlan, CubeError> {
+        let tables = self.meta_store.get_tables_with_path().await?;
+        self.plan_with_tables(statement, tables, true, &options)
+            .await
+    }
 
-        let plan = if SerializedPlan::is_data_select_query(&logical_plan) {
-            let (logical_plan, index_snapshots) = choose_index_ext(
-                &logical_plan,
-                &self.meta_store.as_ref(),
-                self.config.enable_topk(),
-            )
-            .await?;
-            QueryPlan::Select(SerializedPlan::try_new(logical_plan, index_snapshots).await?)
-        } else {
-            QueryPlan::Meta(logical_plan)
-        };
+    async fn source_logical_plan(
+        &self,
+        statement: Statement,
+        options: SessionOptions,
+    ) -> Result<QueryPlan, CubeError> {
+        let tables = self.meta_store.get_tables_with_path().await?;
+        self.plan_with_tables(statement, tables, false, &options)
+            .await
+    }
 
-        Ok(plan)
+    async fn logical_plans(
+        &self,
+        statements: Vec<Statement>,
+        options: SessionOptions,
+    ) -> Result<Vec<Result<QueryPlan, CubeError>>, CubeError> {
+        let tables = self.meta_store.get_tables_with_path().await?;
+        Ok(join_all(
+            statements
+                .into_iter()
+                .map(|s| self.plan_with_tables(s, tables.clone(), true, &options)),
+        )
+        .await)
     }
 
     async fn execute_meta_plan(&self, plan: LogicalPlan) -> Result<DataFrame, CubeError> {
@@ -123,8 +203,26 @@ impl QueryPlannerImpl {
     pub fn new(
         meta_store: Arc<dyn MetaStore>,
         config: Arc<dyn ConfigObj>,
+        exports: Arc<ResultExports>,
+        submitted_queries: Arc<SubmittedQueries>,
+        prefetcher: Arc<ResultPrefetcher>,
+        replicator: Arc<TableReplicator>,
+        metastore_history: Arc<MetaStoreHistory>,
+        sessions: Arc<Sessions>,
+        file_leases: Arc<DataFileLeases>,
     ) -> Arc<QueryPlannerImpl> {
-        Arc::new(QueryPlannerImpl { meta_store, config })
+        Arc::new(QueryPlannerImpl {
+            meta_store,
+            config,
+            index_advisor: Arc::new(IndexAdvisor::new()),
+            exports,
+            submitted_queries,
+            prefetcher,
+            replicator,
+            metastore_history,
+            sessions,
+            file_leases,
+        })
     }
 }
 
@@ -132,18 +230,210 @@ impl QueryPlannerImpl {
     async fn execution_context(&self) -> Result<Arc<ExecutionContext>, CubeError> {
         Ok(Arc::new(ExecutionContext::new()))
     }
+
+    async fn plan_with_tables(
+        &self,
+        mut statement: Statement,
+        tables: Vec<TablePath>,
+        use_pre_aggregations: bool,
+        options: &SessionOptions,
+    ) -> Result<QueryPlan, CubeError> {
+        let snapshot = options.read_snapshot.as_deref();
+        if let Some(snapshot) = snapshot {
+            snapshot.check_expired(Duration::from_secs(self.config.not_used_timeout()))?;
+        }
+        let explain_pruning = match explain_pruning::extract_explained_query(&mut statement)? {
+            Some(query) => Some(Arc::new(
+                self.explain_pruning(&query, tables.clone(), use_pre_aggregations, options)
+                    .await?,
+            )),
+            None => None,
+        };
+        let logical_plan = self
+            .build_logical_plan(
+                statement,
+                tables,
+                use_pre_aggregations,
+                options,
+                explain_pruning,
+            )
+            .await?;
+
+        let plan = if SerializedPlan::is_data_select_query(&logical_plan) {
+            check_distributable(
+                &logical_plan,
+                self.config.max_reported_unsupported_features(),
+            )?;
+            // Files are leased as soon as partitions and chunks are read, so they can't be removed
+            // before the select completes.
+            let index_store = SnapshotIndexStore::new(
+                self.meta_store.as_ref(),
+                snapshot,
+                Some(&self.file_leases),
+            );
+            let (indexed_plan, index_snapshots) = choose_index_ext(
+                &logical_plan,
+                &index_store,
+                self.config.enable_topk(),
+                Some(&self.index_advisor),
+                self.config.replicated_table_max_rows(),
+            )
+            .await?;
+            match metadata_count::answer_from_metadata(&logical_plan, &index_snapshots) {
+                Some(p) => QueryPlan::Meta(p),
+                None => QueryPlan::Select(
+                    SerializedPlan::try_new(indexed_plan, index_snapshots)
+                        .await?
+                        .with_file_lease(index_store.take_lease()),
+                ),
+            }
+        } else {
+            QueryPlan::Meta(logical_plan)
+        };
+
+        Ok(plan)
+    }
+
+    /// See [explain_pruning].
+    async fn explain_pruning(
+        &self,
+        query: &str,
+        tables: Vec<TablePath>,
+        use_pre_aggregations: bool,
+        options: &SessionOptions,
+    ) -> Result<Vec<PruningReport>, CubeError> {
+        let statement = match CubeStoreParser::new(query)?.parse_single_statement()? {
+            CubeStoreStatement::Statement(s @ SQLStatement::Query(_)) => Statement::Statement(s),
+            _ => {
+                return Err(CubeError::user(format!(
+                    "Only SELECT queries can be explained, found: {}",
+                    query
+                )))
+            }
+        };
+        let logical_plan = self
+            .build_logical_plan(statement, tables, use_pre_aggregations, options, None)
+            .await?;
+        if !SerializedPlan::is_data_select_query(&logical_plan) {
+            return Ok(Vec::new());
+        }
+        let index_store = SnapshotIndexStore::new(
+            self.meta_store.as_ref(),
+            options.read_snapshot.as_deref(),
+            None,
+        );
+        Ok(planning::explain_pruning(&logical_plan, &index_store).await?)
+    }
+
+    async fn build_logical_plan(
+        &self,
+        mut statement: Statement,
+        tables: Vec<TablePath>,
+        use_pre_aggregations: bool,
+        options: &SessionOptions,
+        explain_pruning: Option<Arc<Vec<PruningReport>>>,
+    ) -> Result<LogicalPlan, CubeError> {
+        let time_zone = options.time_zone.unwrap_or_else(|| self.config.time_zone());
+        // Pre-aggregations are built in the server time zone.
+        let use_pre_aggregations = use_pre_aggregations && time_zone == self.config.time_zone();
+        let ctx = self.execution_context().await?;
+
+        let table_samples = table_sample::extract_table_samples(&mut statement)?;
+        let schema_provider = MetaStoreSchemaProvider::new(
+            tables,
+            self.meta_store.clone(),
+            self.index_advisor.clone(),
+            self.exports.clone(),
+            self.submitted_queries.clone(),
+            self.prefetcher.clone(),
+            self.replicator.clone(),
+            self.metastore_history.clone(),
+            self.sessions.clone(),
+            explain_pruning,
+            table_samples,
+        );
+
+        let query_planner = SqlToRel::new(&schema_provider);
+        linked_servers::inline_linked_tables(
+            &mut statement,
+            &self.meta_store.get_linked_servers().await?,
+            self.config.linked_server_max_rows(),
+        )
+        .await?;
+        if use_pre_aggregations {
+            pre_aggregations::use_pre_aggregations(
+                &mut statement,
+                &self.meta_store.get_pre_aggregations().await?,
+            )?;
+        }
+        inline_values::rewrite_values(&mut statement)?;
+        decorrelate::decorrelate_subqueries(&mut statement);
+        having::push_having_to_where(&mut statement);
+        collation::apply_collations(&mut statement, &schema_provider.tables)?;
+        stored_types::expose_stored_types(&mut statement, &schema_provider.tables)?;
+        time_zones::rewrite_time_zones(&mut statement, &time_zone)?;
+        casts::rewrite_casts(&mut statement, self.config.cast_overflow())?;
+        order_by::set_nulls_order(&mut statement, self.config.nulls_largest());
+        order_by::reuse_select_items(&mut statement);
+        distinct_count::rewrite_distinct_count(&mut statement);
+        let mut logical_plan = match query_planner.statement_to_plan(&statement) {
+            Ok(p) => p,
+            // Retry with ORDER BY expressions computed in hidden columns.
+            Err(e) => match order_by::add_hidden_columns(&statement) {
+                Some(s) => query_planner.statement_to_plan(&s).map_err(|_| e)?,
+                None => return Err(e.into()),
+            },
+        };
+        logical_plan = order_by::remove_hidden_columns(logical_plan)?;
+
+        logical_plan = ctx.optimize(&logical_plan)?;
+        trace!("Logical Plan: {:#?}", &logical_plan);
+        Ok(logical_plan)
+    }
 }
 
 struct MetaStoreSchemaProvider {
     tables: HashMap<String, TablePath>,
     meta_store: Arc<dyn MetaStore>,
+    index_advisor: Arc<IndexAdvisor>,
+    exports: Arc<ResultExports>,
+    submitted_queries: Arc<SubmittedQueries>,
+    prefetcher: Arc<ResultPrefetcher>,
+    replicator: Arc<TableReplicator>,
+    metastore_history: Arc<MetaStoreHistory>,
+    sessions: Arc<Sessions>,
+    /// Set when the query selects from [EXPLAIN_PRUNING_TABLE].
+    explain_pruning: Option<Arc<Vec<PruningReport>>>,
+    /// Sampling percentages from `TABLESAMPLE` clauses.
+    table_samples: HashMap<String, f64>,
 }
 
 impl MetaStoreSchemaProvider {
-    pub fn new(tables: Vec<TablePath>, meta_store: Arc<dyn MetaStore>) -> Self {
+    pub fn new(
+        tables: Vec<TablePath>,
+        meta_store: Arc<dyn MetaStore>,
+        index_advisor: Arc<IndexAdvisor>,
+        exports: Arc<ResultExports>,
+        submitted_queries: Arc<SubmittedQueries>,
+        prefetcher: Arc<ResultPrefetcher>,
+        replicator: Arc<TableReplicator>,
+        metastore_history: Arc<MetaStoreHistory>,
+        sessions: Arc<Sessions>,
+        explain_pruning: Option<Arc<Vec<PruningReport>>>,
+        table_samples: HashMap<String, f64>,
+    ) -> Self {
         Self {
             tables: tables.into_iter().map(|t| (t.table_name(), t)).collect(),
             meta_store,
+            index_advisor,
+            exports,
+            submitted_queries,
+            prefetcher,
+            replicator,
+            metastore_history,
+            sessions,
+            explain_pruning,
+            table_samples,
         }
     }
 }
@@ -172,6 +462,7 @@ impl ContextProvider for MetaStoreSchemaProvider {
                 Arc::new(CubeTableLogical {
                     table: table.clone(),
                     schema,
+                    sample_percent: self.table_samples.get(name).cloned(),
                 })
             });
         res.or_else(|| match name {
@@ -183,6 +474,49 @@ impl ContextProvider for MetaStoreSchemaProvider {
                 self.meta_store.clone(),
                 InfoSchemaTable::Schemata,
             ))),
+            "system.jobs" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemJobs,
+            ))),
+            "system.index_recommendations" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemIndexRecommendations(self.index_advisor.clone()),
+            ))),
+            "system.exports" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemExports(self.exports.clone()),
+            ))),
+            "system.queries" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemQueries(self.submitted_queries.clone()),
+            ))),
+            "system.prefetches" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemPrefetches(self.prefetcher.clone()),
+            ))),
+            "system.replication" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemReplication(self.replicator.clone()),
+            ))),
+            "system.partition_stats" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemPartitionStats,
+            ))),
+            "system.metastore_log" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemMetaStoreLog(self.metastore_history.clone()),
+            ))),
+            "system.sessions" => Some(Arc::new(InfoSchemaTableProvider::new(
+                self.meta_store.clone(),
+                InfoSchemaTable::SystemSessions(self.sessions.clone()),
+            ))),
+            EXPLAIN_PRUNING_TABLE => {
+                let reports = self.explain_pruning.clone()?;
+                Some(Arc::new(InfoSchemaTableProvider::new(
+                    self.meta_store.clone(),
+                    InfoSchemaTable::SystemExplainPruning(reports),
+                )))
+            }
             _ => None,
         })
     }
@@ -190,6 +524,15 @@ impl ContextProvider for MetaStoreSchemaProvider {
     fn get_function_meta(&self, name: &str) -> Option<Arc<ScalarUDF>> {
         let kind = match name {
             "cardinality" | "CARDINALITY" => CubeScalarUDFKind::HllCardinality,
+            "row_hash" | "ROW_HASH" => CubeScalarUDFKind::RowHash,
+            "collation_key" | "COLLATION_KEY" => CubeScalarUDFKind::CollationKey,
+            "uuid_to_string" | "UUID_TO_STRING" => CubeScalarUDFKind::UuidToString,
+            "to_uuid" | "TO_UUID" => CubeScalarUDFKind::ToUuid,
+            "ip_to_string" | "IP_TO_STRING" => CubeScalarUDFKind::IpToString,
+            "ip_in_cidr" | "IP_IN_CIDR" => CubeScalarUDFKind::IpInCidr,
+            "ip_prefix" | "IP_PREFIX" => CubeScalarUDFKind::IpPrefix,
+            "cube_cast" | "CUBE_CAST" => CubeScalarUDFKind::Cast,
+            "cube_date_trunc" | "CUBE_DATE_TRUNC" => CubeScalarUDFKind::DateTrunc,
             _ => return None,
         };
         return Some(Arc::new(scalar_udf_by_kind(kind).descriptor()));
@@ -200,6 +543,7 @@ impl ContextProvider for MetaStoreSchemaProvider {
         // TODO: case-insensitive names.
         let kind = match name {
             "merge" | "MERGE" => CubeAggregateUDFKind::MergeHll,
+            "hash_sum" | "HASH_SUM" => CubeAggregateUDFKind::HashSum,
             _ => return None,
         };
         return Some(Arc::new(aggregate_udf_by_kind(kind).descriptor()));
@@ -210,6 +554,16 @@ impl ContextProvider for MetaStoreSchemaProvider {
 pub enum InfoSchemaTable {
     Tables,
     Schemata,
+    SystemJobs,
+    SystemIndexRecommendations(Arc<IndexAdvisor>),
+    SystemExports(Arc<ResultExports>),
+    SystemQueries(Arc<SubmittedQueries>),
+    SystemPrefetches(Arc<ResultPrefetcher>),
+    SystemReplication(Arc<TableReplicator>),
+    SystemPartitionStats,
+    SystemMetaStoreLog(Arc<MetaStoreHistory>),
+    SystemSessions(Arc<Sessions>),
+    SystemExplainPruning(Arc<Vec<PruningReport>>),
 }
 
 impl InfoSchemaTable {
@@ -224,6 +578,141 @@ impl InfoSchemaTable {
                 DataType::Utf8,
                 false,
             )])),
+            InfoSchemaTable::SystemJobs => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::UInt64, false),
+                Field::new("job_type", DataType::Utf8, false),
+                Field::new("row_reference", DataType::Utf8, false),
+                Field::new("status", DataType::Utf8, false),
+                Field::new("node", DataType::Utf8, true),
+                Field::new("error", DataType::Utf8, true),
+                Field::new("progress", DataType::Utf8, true),
+                Field::new(
+                    "last_heart_beat",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+            ])),
+            InfoSchemaTable::SystemIndexRecommendations(_) => Arc::new(Schema::new(vec![
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("columns", DataType::Utf8, false),
+                Field::new("queries", DataType::UInt64, false),
+                Field::new("rows_scanned", DataType::UInt64, false),
+                Field::new("index_definition", DataType::Utf8, false),
+            ])),
+            InfoSchemaTable::SystemExports(_) => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Utf8, false),
+                Field::new("query", DataType::Utf8, false),
+                Field::new("status", DataType::Utf8, false),
+                Field::new("error", DataType::Utf8, true),
+                Field::new("rows", DataType::UInt64, true),
+                Field::new(
+                    "created_at",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new(
+                    "finished_at",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    true,
+                ),
+            ])),
+            InfoSchemaTable::SystemQueries(_) => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Utf8, false),
+                Field::new("query", DataType::Utf8, false),
+                Field::new("status", DataType::Utf8, false),
+                Field::new("error", DataType::Utf8, true),
+                Field::new(
+                    "created_at",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new(
+                    "finished_at",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    true,
+                ),
+            ])),
+            InfoSchemaTable::SystemPrefetches(_) => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::Utf8, false),
+                Field::new("schedule", DataType::Utf8, false),
+                Field::new("query", DataType::Utf8, false),
+                Field::new(
+                    "next_refresh",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new(
+                    "last_prefetch",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    true,
+                ),
+                Field::new("last_error", DataType::Utf8, true),
+            ])),
+            InfoSchemaTable::SystemReplication(_) => Arc::new(Schema::new(vec![
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("pending_chunks", DataType::UInt64, false),
+                Field::new("lag_secs", DataType::UInt64, false),
+                Field::new("shipped_chunks", DataType::UInt64, false),
+                Field::new("shipped_rows", DataType::UInt64, false),
+                Field::new(
+                    "last_shipped",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    true,
+                ),
+                Field::new("last_error", DataType::Utf8, true),
+            ])),
+            InfoSchemaTable::SystemPartitionStats => Arc::new(Schema::new(vec![
+                Field::new("partition_id", DataType::UInt64, false),
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("index_name", DataType::Utf8, false),
+                Field::new("row_count", DataType::UInt64, false),
+                Field::new("read_count", DataType::UInt64, false),
+                Field::new(
+                    "last_read",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    true,
+                ),
+            ])),
+            InfoSchemaTable::SystemMetaStoreLog(_) => Arc::new(Schema::new(vec![
+                Field::new(
+                    "time",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new("operation", DataType::Utf8, false),
+                Field::new("entity", DataType::Utf8, false),
+                Field::new("id", DataType::UInt64, false),
+                Field::new("name", DataType::Utf8, true),
+                Field::new("parent_id", DataType::UInt64, true),
+            ])),
+            InfoSchemaTable::SystemSessions(_) => Arc::new(Schema::new(vec![
+                Field::new("id", DataType::UInt64, false),
+                Field::new("user", DataType::Utf8, true),
+                Field::new("client_address", DataType::Utf8, false),
+                Field::new(
+                    "connected_at",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new(
+                    "last_activity",
+                    DataType::Timestamp(TimeUnit::Nanosecond, None),
+                    false,
+                ),
+                Field::new("state", DataType::Utf8, false),
+                Field::new("query", DataType::Utf8, true),
+                Field::new("temporary_functions", DataType::UInt64, false),
+            ])),
+            InfoSchemaTable::SystemExplainPruning(_) => Arc::new(Schema::new(vec![
+                Field::new("table_name", DataType::Utf8, false),
+                Field::new("index_name", DataType::Utf8, false),
+                Field::new("partitions", DataType::UInt64, false),
+                Field::new("pruned_partitions", DataType::UInt64, false),
+                Field::new("chunks", DataType::UInt64, false),
+                Field::new("pruned_chunks", DataType::UInt64, false),
+                Field::new("pruning_filters", DataType::Utf8, false),
+                Field::new("other_filters", DataType::Utf8, false),
+            ])),
         }
     }
 
@@ -259,6 +748,459 @@ impl InfoSchemaTable {
                 ))];
                 Ok(RecordBatch::try_new(schema, columns)?)
             }
+            InfoSchemaTable::SystemJobs => {
+                let jobs = meta_store.all_jobs().await?;
+                let schema = self.schema();
+                let job_types = jobs
+                    .iter()
+                    .map(|j| format!("{:?}", j.get_row().job_type()))
+                    .collect::<Vec<_>>();
+                let row_references = jobs
+                    .iter()
+                    .map(|j| format!("{:?}", j.get_row().row_reference()))
+                    .collect::<Vec<_>>();
+                let statuses = jobs
+                    .iter()
+                    .map(|j| j.get_row().status())
+                    .collect::<Vec<_>>();
+                let progress = jobs
+                    .iter()
+                    .map(|j| j.get_row().progress_description())
+                    .collect::<Vec<_>>();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(UInt64Array::from(
+                        jobs.iter().map(|j| j.get_id()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        job_types.iter().map(|t| t.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        row_references
+                            .iter()
+                            .map(|r| r.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        statuses.iter().map(|s| s.name()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        statuses.iter().map(|s| s.node()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        statuses
+                            .iter()
+                            .map(|s| match s {
+                                JobStatus::Error(e) => Some(e.as_str()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        progress.iter().map(|p| p.as_deref()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        jobs.iter()
+                            .map(|j| j.get_row().last_heart_beat().timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemIndexRecommendations(advisor) => {
+                let recommendations = advisor.recommendations();
+                let schema = self.schema();
+                let column_lists = recommendations
+                    .iter()
+                    .map(|r| r.columns.join(", "))
+                    .collect::<Vec<_>>();
+                let definitions = recommendations
+                    .iter()
+                    .map(|r| r.index_definition())
+                    .collect::<Vec<_>>();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        recommendations
+                            .iter()
+                            .map(|r| r.table.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        column_lists.iter().map(|c| c.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        recommendations
+                            .iter()
+                            .map(|r| r.queries)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        recommendations
+                            .iter()
+                            .map(|r| r.rows_scanned)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        definitions.iter().map(|d| d.as_str()).collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemExports(exports) => {
+                let exports = exports.all();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        exports.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        exports.iter().map(|e| e.query.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        exports.iter().map(|e| e.status.name()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        exports
+                            .iter()
+                            .map(|e| match &e.status {
+                                ExportStatus::Error(e) => Some(e.as_str()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        exports.iter().map(|e| e.rows).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        exports
+                            .iter()
+                            .map(|e| e.created_at.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        exports
+                            .iter()
+                            .map(|e| e.finished_at.map(|t| t.timestamp_nanos()))
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemQueries(queries) => {
+                let queries = queries.all();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        queries.iter().map(|q| q.id.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        queries.iter().map(|q| q.query.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        queries.iter().map(|q| q.status.name()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        queries
+                            .iter()
+                            .map(|q| match &q.status {
+                                QueryStatus::Error(e) => Some(e.as_str()),
+                                _ => None,
+                            })
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        queries
+                            .iter()
+                            .map(|q| q.created_at.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        queries
+                            .iter()
+                            .map(|q| q.finished_at.map(|t| t.timestamp_nanos()))
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemPrefetches(prefetcher) => {
+                let prefetches = prefetcher.all();
+                let schedules = prefetches
+                    .iter()
+                    .map(|p| p.schedule.to_string())
+                    .collect::<Vec<_>>();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        prefetches.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        schedules.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        prefetches
+                            .iter()
+                            .map(|p| p.query.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        prefetches
+                            .iter()
+                            .map(|p| p.next_refresh.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        prefetches
+                            .iter()
+                            .map(|p| p.last_prefetch.map(|t| t.timestamp_nanos()))
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        prefetches
+                            .iter()
+                            .map(|p| p.last_error.as_deref())
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemReplication(replicator) => {
+                let tables = replicator.all();
+                let now = Utc::now();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        tables.iter().map(|t| t.table.as_str()).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        tables
+                            .iter()
+                            .map(|t| t.pending.len() as u64)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        tables
+                            .iter()
+                            .map(|t| t.lag(now).num_seconds() as u64)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        tables.iter().map(|t| t.shipped_chunks).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        tables.iter().map(|t| t.shipped_rows).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        tables
+                            .iter()
+                            .map(|t| t.last_shipped.map(|t| t.timestamp_nanos()))
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        tables
+                            .iter()
+                            .map(|t| t.last_error.as_deref())
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemPartitionStats => {
+                // Statistics of active partitions as last flushed by the nodes that read them.
+                let tables = meta_store
+                    .get_tables_with_path()
+                    .await?
+                    .into_iter()
+                    .map(|t| (t.table.get_id(), t.table_name()))
+                    .collect::<HashMap<_, _>>();
+                let indexes = meta_store
+                    .index_table()
+                    .all_rows()
+                    .await?
+                    .into_iter()
+                    .map(|i| (i.get_id(), i.into_row()))
+                    .collect::<HashMap<_, _>>();
+                let partitions = meta_store
+                    .partition_table()
+                    .all_rows()
+                    .await?
+                    .into_iter()
+                    .filter(|p| p.get_row().is_active())
+                    .filter_map(|p| {
+                        let index = indexes.get(&p.get_row().get_index_id())?;
+                        let table = tables.get(&index.table_id())?;
+                        Some((p, table.as_str(), index.get_name().as_str()))
+                    })
+                    .collect::<Vec<_>>();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(UInt64Array::from(
+                        partitions
+                            .iter()
+                            .map(|(p, _, _)| p.get_id())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        partitions.iter().map(|(_, t, _)| *t).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        partitions.iter().map(|(_, _, i)| *i).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        partitions
+                            .iter()
+                            .map(|(p, _, _)| p.get_row().main_table_row_count())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        partitions
+                            .iter()
+                            .map(|(p, _, _)| p.get_row().read_count())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        partitions
+                            .iter()
+                            .map(|(p, _, _)| p.get_row().last_used().map(|t| t.timestamp_nanos()))
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemMetaStoreLog(history) => {
+                let entries = history.all();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(TimestampNanosecondArray::from(
+                        entries
+                            .iter()
+                            .map(|e| e.time.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        entries.iter().map(|e| e.operation).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        entries.iter().map(|e| e.entity).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        entries.iter().map(|e| e.id).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        entries
+                            .iter()
+                            .map(|e| e.name.as_deref())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        entries.iter().map(|e| e.parent_id).collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemSessions(sessions) => {
+                let sessions = sessions.all();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(UInt64Array::from(
+                        sessions.iter().map(|s| s.id).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.user.as_deref())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.client_address.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.connected_at.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(TimestampNanosecondArray::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.last_activity.timestamp_nanos())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        sessions.iter().map(|s| s.state).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.query.as_deref())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        sessions
+                            .iter()
+                            .map(|s| s.temporary_functions)
+                            .collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
+            InfoSchemaTable::SystemExplainPruning(reports) => {
+                let pruning_filters = reports
+                    .iter()
+                    .map(|r| {
+                        r.pruning_filters
+                            .iter()
+                            .map(|(f, pruned)| format!("{} (pruned {})", f, pruned))
+                            .join(", ")
+                    })
+                    .collect::<Vec<_>>();
+                let other_filters = reports
+                    .iter()
+                    .map(|r| r.other_filters.join(", "))
+                    .collect::<Vec<_>>();
+                let schema = self.schema();
+                let columns: Vec<Arc<dyn Array>> = vec![
+                    Arc::new(StringArray::from(
+                        reports
+                            .iter()
+                            .map(|r| r.table_name.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        reports
+                            .iter()
+                            .map(|r| r.index_name.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        reports.iter().map(|r| r.partitions).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        reports
+                            .iter()
+                            .map(|r| r.pruned_partitions)
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        reports.iter().map(|r| r.chunks).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(UInt64Array::from(
+                        reports.iter().map(|r| r.pruned_chunks).collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        pruning_filters
+                            .iter()
+                            .map(|f| f.as_str())
+                            .collect::<Vec<_>>(),
+                    )),
+                    Arc::new(StringArray::from(
+                        other_filters.iter().map(|f| f.as_str()).collect::<Vec<_>>(),
+                    )),
+                ];
+                Ok(RecordBatch::try_new(schema, columns)?)
+            }
         }
     }
 }
@@ -358,6 +1300,7 @@ impl ExecutionPlan for InfoSchemaTableExec {
 pub struct CubeTableLogical {
     table: TablePath,
     schema: SchemaRef,
+    sample_percent: Option<f64>,
 }
 
 impl TableProvider for CubeTableLogical {
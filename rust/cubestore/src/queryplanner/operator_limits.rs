@@ -0,0 +1,398 @@
+//! Memory and CPU time budgets of single operators of worker plans, set with
+//! `CUBESTORE_OPERATOR_MEMORY_LIMIT_MB` and `CUBESTORE_OPERATOR_CPU_LIMIT_MS`. A query with an
+//! operator over budget fails with [LimitExceeded] in the details of the error, which name the
+//! operator, the worker and the partitions it ran for along with the usage, e.g.
+//!     PartialHashAggregate on worker-1:10001 for partitions [3, 4] exceeded its memory budget
+//!     of 536870912 bytes by 1048576 bytes
+//! Memory is only accounted for operators that hold all of their results at once, i.e. hash
+//! aggregates and sorts, as the size of the results they produce. CPU time is the time spent
+//! producing results of the operator itself, without the time of its inputs. Both are summed over
+//! all partitions of an operator and checked as results are produced.
+use crate::queryplanner::batch_cache::batches_size;
+use crate::queryplanner::query_executor::CubeTableExec;
+use crate::util::id_set::IdSet;
+use crate::{CubeError, CubeErrorDetails};
+use arrow::datatypes::SchemaRef;
+use arrow::error::{ArrowError, Result as ArrowResult};
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::DFSchemaRef;
+use datafusion::physical_plan::hash_aggregate::{
+    AggregateMode, AggregateStrategy, HashAggregateExec,
+};
+use datafusion::physical_plan::merge_join::MergeJoinExec;
+use datafusion::physical_plan::sort::SortExec;
+use datafusion::physical_plan::{
+    ExecutionPlan, OptimizerHints, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+};
+use futures::stream::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Zero disables a limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperatorLimits {
+    pub memory: u64,
+    pub cpu_ms: u64,
+}
+
+impl OperatorLimits {
+    fn is_disabled(&self) -> bool {
+        self.memory == 0 && self.cpu_ms == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LimitedResource {
+    /// In bytes.
+    Memory,
+    /// In milliseconds.
+    Cpu,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub operator: String,
+    pub node: String,
+    pub partitions: Vec<u64>,
+    pub resource: LimitedResource,
+    pub budget: u64,
+    pub used: u64,
+}
+
+impl LimitExceeded {
+    pub fn into_error(self) -> CubeError {
+        CubeError::user(self.to_string()).with_details(CubeErrorDetails::LimitExceeded(self))
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (resource, unit) = match self.resource {
+            LimitedResource::Memory => ("memory", "bytes"),
+            LimitedResource::Cpu => ("CPU time", "ms"),
+        };
+        write!(
+            f,
+            "{} on {} for partitions {:?} exceeded its {} budget of {} {} by {} {}",
+            self.operator,
+            self.node,
+            self.partitions,
+            resource,
+            self.budget,
+            unit,
+            self.used.saturating_sub(self.budget),
+            unit
+        )
+    }
+}
+
+/// Wraps all operators of `plan` running on `node` for `partitions` to check their usage.
+pub fn with_operator_limits(
+    plan: Arc<dyn ExecutionPlan>,
+    limits: OperatorLimits,
+    node: &str,
+    partitions: &IdSet,
+) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+    if limits.is_disabled() {
+        return Ok(plan);
+    }
+    let scope = Arc::new(Scope {
+        limits,
+        node: node.to_string(),
+        partitions: partitions.iter().collect(),
+    });
+    Ok(wrap(plan, &scope)?)
+}
+
+fn wrap(
+    plan: Arc<dyn ExecutionPlan>,
+    scope: &Arc<Scope>,
+) -> Result<Arc<OperatorLimitsExec>, DataFusionError> {
+    let mut children = Vec::new();
+    let mut children_usage = Vec::new();
+    for child in plan.children() {
+        let child = wrap(child, scope)?;
+        children_usage.push(child.usage.clone());
+        children.push(child as Arc<dyn ExecutionPlan>);
+    }
+    let holds_results = holds_results(plan.as_ref());
+    let operator = operator_name(plan.as_ref());
+    let input = if children.is_empty() {
+        plan
+    } else {
+        plan.with_new_children(children)?
+    };
+    Ok(Arc::new(OperatorLimitsExec {
+        input,
+        operator,
+        holds_results,
+        scope: scope.clone(),
+        usage: Arc::new(Usage::default()),
+        children_usage,
+    }))
+}
+
+fn holds_results(plan: &dyn ExecutionPlan) -> bool {
+    let a = plan.as_any();
+    if let Some(agg) = a.downcast_ref::<HashAggregateExec>() {
+        return agg.strategy() == AggregateStrategy::Hash;
+    }
+    a.is::<SortExec>()
+}
+
+/// Names operators the same way as [crate::queryplanner::pretty_printers].
+fn operator_name(plan: &dyn ExecutionPlan) -> String {
+    let a = plan.as_any();
+    if let Some(agg) = a.downcast_ref::<HashAggregateExec>() {
+        let mode = match agg.mode() {
+            AggregateMode::Partial => "Partial",
+            AggregateMode::Final => "Final",
+            AggregateMode::Full => "Full",
+        };
+        let strategy = match agg.strategy() {
+            AggregateStrategy::Hash => "Hash",
+            AggregateStrategy::InplaceSorted => "Inplace",
+        };
+        format!("{}{}Aggregate", mode, strategy)
+    } else if a.is::<SortExec>() {
+        "Sort".to_string()
+    } else if a.is::<MergeJoinExec>() {
+        "MergeJoin".to_string()
+    } else if a.is::<CubeTableExec>() {
+        "Scan".to_string()
+    } else {
+        // Names of other operators are the names of their types without `Exec`.
+        let debug = format!("{:?}", plan);
+        let name = debug
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap();
+        name.strip_suffix("Exec").unwrap_or(name).to_string()
+    }
+}
+
+/// Limits of a single execution of a worker plan.
+#[derive(Debug)]
+struct Scope {
+    limits: OperatorLimits,
+    node: String,
+    partitions: Vec<u64>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    /// Includes time of inputs polled by the operator.
+    cpu_nanos: AtomicU64,
+    memory: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct OperatorLimitsExec {
+    input: Arc<dyn ExecutionPlan>,
+    operator: String,
+    holds_results: bool,
+    scope: Arc<Scope>,
+    usage: Arc<Usage>,
+    children_usage: Vec<Arc<Usage>>,
+}
+
+impl OperatorLimitsExec {
+    fn check(&self) -> Result<(), LimitExceeded> {
+        let limits = &self.scope.limits;
+        let exceeded = |resource, budget, used| LimitExceeded {
+            operator: self.operator.clone(),
+            node: self.scope.node.clone(),
+            partitions: self.scope.partitions.clone(),
+            resource,
+            budget,
+            used,
+        };
+        let memory = self.usage.memory.load(Ordering::Relaxed);
+        if limits.memory != 0 && limits.memory < memory {
+            return Err(exceeded(LimitedResource::Memory, limits.memory, memory));
+        }
+        let inputs_nanos = self
+            .children_usage
+            .iter()
+            .map(|u| u.cpu_nanos.load(Ordering::Relaxed))
+            .sum::<u64>();
+        let cpu_ms = self
+            .usage
+            .cpu_nanos
+            .load(Ordering::Relaxed)
+            .saturating_sub(inputs_nanos)
+            / 1_000_000;
+        if limits.cpu_ms != 0 && limits.cpu_ms < cpu_ms {
+            return Err(exceeded(LimitedResource::Cpu, limits.cpu_ms, cpu_ms));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for OperatorLimitsExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert_eq!(children.len(), 1);
+        Ok(Arc::new(OperatorLimitsExec {
+            input: children.remove(0),
+            operator: self.operator.clone(),
+            holds_results: self.holds_results,
+            scope: self.scope.clone(),
+            usage: self.usage.clone(),
+            children_usage: self.children_usage.clone(),
+        }))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        self.input.output_hints()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        let input = self.input.execute(partition).await?;
+        Ok(Box::pin(OperatorLimitsStream {
+            schema: input.schema(),
+            input,
+            exec: OperatorLimitsExec {
+                input: self.input.clone(),
+                operator: self.operator.clone(),
+                holds_results: self.holds_results,
+                scope: self.scope.clone(),
+                usage: self.usage.clone(),
+                children_usage: self.children_usage.clone(),
+            },
+        }))
+    }
+}
+
+struct OperatorLimitsStream {
+    schema: SchemaRef,
+    input: SendableRecordBatchStream,
+    /// Shares usage with the executed plan.
+    exec: OperatorLimitsExec,
+}
+
+impl Stream for OperatorLimitsStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let start = Instant::now();
+        let result = self.input.poll_next_unpin(cx);
+        let usage = &self.exec.usage;
+        usage
+            .cpu_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        if let Poll::Ready(Some(Ok(batch))) = &result {
+            if self.exec.holds_results {
+                usage.memory.fetch_add(
+                    batches_size(std::slice::from_ref(batch)) as u64,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+        if let Err(exceeded) = self.exec.check() {
+            let error = exceeded.into_error().to_engine_message();
+            return Poll::Ready(Some(Err(ArrowError::ComputeError(error))));
+        }
+        result
+    }
+}
+
+impl RecordBatchStream for OperatorLimitsStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::Int64Array;
+    use arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::physical_plan::collect;
+    use datafusion::physical_plan::expressions::{col, PhysicalSortExpr};
+    use datafusion::physical_plan::memory::MemoryExec;
+
+    #[tokio::test]
+    async fn memory_limit() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(
+                (0..1000).rev().collect::<Vec<_>>(),
+            ))],
+        )
+        .unwrap();
+        let input = Arc::new(MemoryExec::try_new(&[vec![batch]], schema, None).unwrap());
+        let sort: Arc<dyn ExecutionPlan> = Arc::new(
+            SortExec::try_new(
+                vec![PhysicalSortExpr {
+                    expr: col("a"),
+                    options: Default::default(),
+                }],
+                input,
+            )
+            .unwrap(),
+        );
+        let partitions = vec![3, 4].into_iter().collect::<IdSet>();
+        let limits = OperatorLimits {
+            memory: 100,
+            cpu_ms: 0,
+        };
+        let plan = with_operator_limits(sort.clone(), limits, "worker-1", &partitions).unwrap();
+        let error = CubeError::from(collect(plan).await.unwrap_err());
+        let exceeded = match error.details {
+            Some(CubeErrorDetails::LimitExceeded(e)) => e,
+            d => panic!("unexpected details: {:?}", d),
+        };
+        assert_eq!(exceeded.operator, "Sort");
+        assert_eq!(exceeded.node, "worker-1");
+        assert_eq!(exceeded.partitions, vec![3, 4]);
+        assert_eq!(exceeded.resource, LimitedResource::Memory);
+        assert_eq!(exceeded.budget, 100);
+        assert!(exceeded.used >= 8000);
+        assert!(error
+            .message
+            .starts_with("Sort on worker-1 for partitions [3, 4] exceeded its memory budget"));
+        let client_message = error.client_message();
+        let details: CubeErrorDetails =
+            serde_json::from_str(client_message.lines().last().unwrap()).unwrap();
+        assert_eq!(details, CubeErrorDetails::LimitExceeded(exceeded));
+
+        let limits = OperatorLimits {
+            memory: 1 << 20,
+            cpu_ms: 0,
+        };
+        let plan = with_operator_limits(sort, limits, "worker-1", &partitions).unwrap();
+        assert_eq!(collect(plan).await.unwrap()[0].num_rows(), 1000);
+    }
+}
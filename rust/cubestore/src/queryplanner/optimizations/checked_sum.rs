@@ -0,0 +1,390 @@
+use crate::queryplanner::casts::CastOverflow;
+use crate::queryplanner::optimizations::exact_float_sum::ExactFloatSum;
+use crate::queryplanner::topk::AggregateTopKExec;
+use crate::CubeError;
+use arrow::array::{
+    Array, ArrayRef, Int64Array, Int64Decimal0Array, Int64Decimal10Array, Int64Decimal1Array,
+    Int64Decimal2Array, Int64Decimal3Array, Int64Decimal4Array, Int64Decimal5Array, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, SchemaRef};
+use bigdecimal::BigDecimal;
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::expressions::{Avg, Sum};
+use datafusion::physical_plan::hash_aggregate::HashAggregateExec;
+use datafusion::physical_plan::{Accumulator, AggregateExpr, ExecutionPlan, PhysicalExpr};
+use datafusion::scalar::ScalarValue;
+use num::BigInt;
+use smallvec::smallvec;
+use smallvec::SmallVec;
+use std::any::Any;
+use std::sync::Arc;
+
+/// Replaces `SUM` of integers and decimals with [CheckedSum]. Sums are accumulated in 128 bits,
+/// so partial sums of workers never overflow and the result only depends on the final value.
+/// `overflow` says what happens when it is out of range of the column type.
+/// With `exact_floats`, `SUM` and `AVG` of floats are replaced with [ExactFloatSum].
+pub fn use_checked_sums(
+    p: Arc<dyn ExecutionPlan>,
+    overflow: CastOverflow,
+    exact_floats: bool,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let options = SumOptions {
+        overflow,
+        exact_floats,
+    };
+    if let Some(agg) = p.as_any().downcast_ref::<HashAggregateExec>() {
+        let input_schema = agg.input_schema().clone();
+        let aggr_expr = match checked_sums(agg.aggr_expr(), Some(&input_schema), options)? {
+            Some(e) => e,
+            None => return Ok(p),
+        };
+        Ok(Arc::new(HashAggregateExec::try_new(
+            agg.strategy(),
+            *agg.mode(),
+            agg.group_expr().into(),
+            aggr_expr,
+            agg.input().clone(),
+            input_schema,
+        )?))
+    } else if let Some(topk) = p.as_any().downcast_ref::<AggregateTopKExec>() {
+        // Workers send final values, the router adds them up with accumulators of `agg_expr`.
+        let agg_expr = match checked_sums(&topk.agg_expr, None, options)? {
+            Some(e) => e,
+            None => return Ok(p),
+        };
+        Ok(Arc::new(AggregateTopKExec::new(
+            topk.limit,
+            topk.key_len,
+            agg_expr,
+            &topk
+                .agg_descr
+                .iter()
+                .map(|(f, _, _)| f.clone())
+                .collect::<Vec<_>>(),
+            topk.order_by.clone(),
+            topk.cluster.clone(),
+            topk.schema.clone(),
+        )))
+    } else {
+        Ok(p)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SumOptions {
+    overflow: CastOverflow,
+    exact_floats: bool,
+}
+
+/// `None` if there are no sums to replace. Averages are only replaced when `input_schema` is
+/// known, it gives the type of their argument.
+fn checked_sums(
+    aggr_expr: &[Arc<dyn AggregateExpr>],
+    input_schema: Option<&SchemaRef>,
+    options: SumOptions,
+) -> Result<Option<Vec<Arc<dyn AggregateExpr>>>, DataFusionError> {
+    let mut changed = false;
+    let mut r = Vec::with_capacity(aggr_expr.len());
+    for e in aggr_expr {
+        let field = e.field()?;
+        let expr = e.expressions()[0].clone();
+        let is_sum = e.as_any().is::<Sum>();
+        let replacement: Arc<dyn AggregateExpr> = if is_sum && is_checked_type(field.data_type()) {
+            Arc::new(CheckedSum {
+                expr,
+                field,
+                overflow: options.overflow,
+            })
+        } else if is_sum && options.exact_floats && field.data_type() == &DataType::Float64 {
+            Arc::new(ExactFloatSum::new(expr, field.name().clone(), false))
+        } else if e.as_any().is::<Avg>()
+            && options.exact_floats
+            && match input_schema {
+                Some(s) => expr.data_type(s.as_ref())? == DataType::Float64,
+                None => false,
+            }
+        {
+            Arc::new(ExactFloatSum::new(expr, field.name().clone(), true))
+        } else {
+            r.push(e.clone());
+            continue;
+        };
+        changed = true;
+        r.push(replacement);
+    }
+    Ok(if changed { Some(r) } else { None })
+}
+
+fn is_checked_type(t: &DataType) -> bool {
+    match t {
+        DataType::Int64 | DataType::UInt64 => true,
+        DataType::Int64Decimal(0)
+        | DataType::Int64Decimal(1)
+        | DataType::Int64Decimal(2)
+        | DataType::Int64Decimal(3)
+        | DataType::Int64Decimal(4)
+        | DataType::Int64Decimal(5)
+        | DataType::Int64Decimal(10) => true,
+        _ => false,
+    }
+}
+
+/// `SUM` of integers and decimals that does not wrap around. Partial states are the high and the
+/// low 64 bits of the 128-bit sum.
+#[derive(Debug)]
+pub struct CheckedSum {
+    expr: Arc<dyn PhysicalExpr>,
+    /// Name and type of the result, same as for the replaced `SUM`.
+    field: Field,
+    overflow: CastOverflow,
+}
+
+impl AggregateExpr for CheckedSum {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn field(&self) -> Result<Field, DataFusionError> {
+        Ok(self.field.clone())
+    }
+
+    fn create_accumulator(&self) -> Result<Box<dyn Accumulator>, DataFusionError> {
+        Ok(Box::new(CheckedSumAccumulator {
+            sum: None,
+            data_type: self.field.data_type().clone(),
+            overflow: self.overflow,
+        }))
+    }
+
+    fn state_fields(&self) -> Result<Vec<Field>, DataFusionError> {
+        Ok(vec![
+            Field::new(
+                &format!("{}[sum_high]", self.field.name()),
+                DataType::Int64,
+                true,
+            ),
+            Field::new(
+                &format!("{}[sum_low]", self.field.name()),
+                DataType::Int64,
+                true,
+            ),
+        ])
+    }
+
+    fn expressions(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.expr.clone()]
+    }
+
+    fn name(&self) -> &str {
+        self.field.name()
+    }
+}
+
+#[derive(Debug)]
+struct CheckedSumAccumulator {
+    /// `None` until the first non-null value.
+    sum: Option<i128>,
+    data_type: DataType,
+    overflow: CastOverflow,
+}
+
+impl CheckedSumAccumulator {
+    fn add(&mut self, v: i128) {
+        // Values are 64-bit, 2^64 of them would be needed to overflow.
+        self.sum = Some(self.sum.unwrap_or(0) + v);
+    }
+
+    fn result(&self, v: Option<i64>) -> ScalarValue {
+        match &self.data_type {
+            DataType::UInt64 => ScalarValue::UInt64(v.map(|v| v as u64)),
+            DataType::Int64Decimal(scale) => ScalarValue::Int64Decimal(v, *scale),
+            _ => ScalarValue::Int64(v),
+        }
+    }
+
+    fn display(&self, sum: i128) -> String {
+        match &self.data_type {
+            DataType::Int64Decimal(scale) => {
+                BigDecimal::new(BigInt::from(sum), *scale as i64).to_string()
+            }
+            _ => sum.to_string(),
+        }
+    }
+}
+
+impl Accumulator for CheckedSumAccumulator {
+    fn reset(&mut self) {
+        self.sum = None;
+    }
+
+    fn state(&self) -> Result<SmallVec<[ScalarValue; 2]>, DataFusionError> {
+        return Ok(smallvec![
+            ScalarValue::Int64(self.sum.map(|s| (s >> 64) as i64)),
+            ScalarValue::Int64(self.sum.map(|s| s as i64)),
+        ]);
+    }
+
+    fn update(&mut self, row: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(row.len(), 1);
+        match &row[0] {
+            ScalarValue::Int64(Some(v)) | ScalarValue::Int64Decimal(Some(v), _) => {
+                self.add(*v as i128)
+            }
+            ScalarValue::UInt64(Some(v)) => self.add(*v as i128),
+            ScalarValue::Int64(None)
+            | ScalarValue::Int64Decimal(None, _)
+            | ScalarValue::UInt64(None) => {}
+            v => {
+                return Err(CubeError::internal(format!(
+                    "invalid scalar value passed to SUM: {:?}",
+                    v
+                ))
+                .into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<(), DataFusionError> {
+        macro_rules! add_array {
+            ($ARRAY_TYPE: ident) => {{
+                let a = values[0].as_any().downcast_ref::<$ARRAY_TYPE>().unwrap();
+                for i in 0..a.len() {
+                    if a.is_valid(i) {
+                        self.add(a.value(i) as i128)
+                    }
+                }
+            }};
+        }
+        assert_eq!(values.len(), 1);
+        match values[0].data_type() {
+            DataType::Int64 => add_array!(Int64Array),
+            DataType::UInt64 => add_array!(UInt64Array),
+            DataType::Int64Decimal(0) => add_array!(Int64Decimal0Array),
+            DataType::Int64Decimal(1) => add_array!(Int64Decimal1Array),
+            DataType::Int64Decimal(2) => add_array!(Int64Decimal2Array),
+            DataType::Int64Decimal(3) => add_array!(Int64Decimal3Array),
+            DataType::Int64Decimal(4) => add_array!(Int64Decimal4Array),
+            DataType::Int64Decimal(5) => add_array!(Int64Decimal5Array),
+            DataType::Int64Decimal(10) => add_array!(Int64Decimal10Array),
+            t => {
+                return Err(CubeError::internal(format!(
+                    "invalid array of type {:?} passed to SUM",
+                    t
+                ))
+                .into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, states: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(states.len(), 2);
+        match (&states[0], &states[1]) {
+            (ScalarValue::Int64(Some(high)), ScalarValue::Int64(Some(low))) => {
+                self.add(((*high as i128) << 64) | (*low as u64 as i128))
+            }
+            (ScalarValue::Int64(None), ScalarValue::Int64(None)) => {}
+            _ => return Err(CubeError::internal("invalid state in SUM".to_string()).into()),
+        }
+        return Ok(());
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue, DataFusionError> {
+        let sum = match self.sum {
+            Some(s) => s,
+            None => return Ok(self.result(None)),
+        };
+        let (min, max) = match &self.data_type {
+            DataType::UInt64 => (0, u64::MAX as i128),
+            _ => (i64::MIN as i128, i64::MAX as i128),
+        };
+        // Unsigned values are kept in the same 64 bits.
+        if min <= sum && sum <= max {
+            return Ok(self.result(Some(sum as i64)));
+        }
+        match self.overflow {
+            CastOverflow::Error => Err(CubeError::user(format!(
+                "Sum {} is out of range of {:?}",
+                self.display(sum),
+                self.data_type
+            ))
+            .into()),
+            CastOverflow::Saturate => {
+                Ok(self.result(Some(if sum < min { min } else { max } as i64)))
+            }
+            CastOverflow::Null => Ok(self.result(None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accumulator(data_type: DataType, overflow: CastOverflow) -> CheckedSumAccumulator {
+        CheckedSumAccumulator {
+            sum: None,
+            data_type,
+            overflow,
+        }
+    }
+
+    #[test]
+    fn partial_states() {
+        let mut partial = accumulator(DataType::Int64, CastOverflow::Error);
+        let values: ArrayRef = Arc::new(Int64Array::from(vec![Some(i64::MAX), None, Some(10)]));
+        partial.update_batch(&[values]).unwrap();
+
+        let mut negative = accumulator(DataType::Int64, CastOverflow::Error);
+        negative.update(&[ScalarValue::Int64(Some(-20))]).unwrap();
+
+        let mut fin = accumulator(DataType::Int64, CastOverflow::Error);
+        assert_eq!(fin.evaluate().unwrap(), ScalarValue::Int64(None));
+        fin.merge(&partial.state().unwrap()).unwrap();
+        assert!(fin.evaluate().is_err());
+        fin.merge(&negative.state().unwrap()).unwrap();
+        assert_eq!(
+            fin.evaluate().unwrap(),
+            ScalarValue::Int64(Some(i64::MAX - 10))
+        );
+    }
+
+    #[test]
+    fn overflow() {
+        let sum = |data_type: DataType, overflow: CastOverflow, values: &[ScalarValue]| {
+            let mut a = accumulator(data_type, overflow);
+            for v in values {
+                a.update(&[v.clone()]).unwrap();
+            }
+            a.evaluate()
+        };
+        let min = [
+            ScalarValue::Int64(Some(i64::MIN)),
+            ScalarValue::Int64(Some(-1)),
+        ];
+        let e = sum(DataType::Int64, CastOverflow::Error, &min).unwrap_err();
+        assert!(e.to_string().contains("-9223372036854775809"), "{}", e);
+        assert_eq!(
+            sum(DataType::Int64, CastOverflow::Saturate, &min).unwrap(),
+            ScalarValue::Int64(Some(i64::MIN))
+        );
+        assert_eq!(
+            sum(DataType::Int64, CastOverflow::Null, &min).unwrap(),
+            ScalarValue::Int64(None)
+        );
+
+        let max = [
+            ScalarValue::UInt64(Some(u64::MAX)),
+            ScalarValue::UInt64(Some(1)),
+        ];
+        assert_eq!(
+            sum(DataType::UInt64, CastOverflow::Saturate, &max[..1]).unwrap(),
+            ScalarValue::UInt64(Some(u64::MAX))
+        );
+        assert_eq!(
+            sum(DataType::UInt64, CastOverflow::Saturate, &max).unwrap(),
+            ScalarValue::UInt64(Some(u64::MAX))
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use crate::queryplanner::planning::WorkerExec;
+use crate::queryplanner::query_executor::{ClusterSendExec, CubeTableExec};
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::alias::AliasedSchemaExec;
+use datafusion::physical_plan::expressions::Column;
+use datafusion::physical_plan::filter::FilterExec;
+use datafusion::physical_plan::hash_aggregate::{AggregateMode, HashAggregateExec};
+use datafusion::physical_plan::merge::MergeExec;
+use datafusion::physical_plan::merge_sort::MergeSortExec;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::ExecutionPlan;
+use std::sync::Arc;
+
+/// Finishes grouping without aggregate functions on workers when all rows of each group are on a
+/// single worker, e.g. the distinct values of COUNT(DISTINCT), see
+/// [crate::queryplanner::distinct_count]. Partitions are ranges of the sort key of their index,
+/// so this holds when the group keys include all sort key columns.
+///
+/// Transforms from:
+///     AggregateFinal
+///     `- Merge
+///        `- ClusterSend
+///           `- AggregatePartial
+/// to:
+///     Merge
+///     `- ClusterSend
+///        `- AggregateFinal
+///           `- AggregatePartial
+///
+/// A partial aggregate over these groups then runs on workers as well, so workers send their
+/// counts of distinct values instead of the values. Transforms from:
+///     AggregatePartial
+///     `- Merge
+///        `- ClusterSend
+///           `- AggregateFinal
+/// to:
+///     Merge
+///     `- ClusterSend
+///        `- AggregatePartial
+///           `- AggregateFinal
+pub fn push_final_aggregate_to_workers(
+    p: Arc<dyn ExecutionPlan>,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let agg;
+    if let Some(a) = p.as_any().downcast_ref::<HashAggregateExec>() {
+        agg = a;
+    } else {
+        return Ok(p);
+    }
+    if *agg.mode() == AggregateMode::Final {
+        push_final_groups(&p, agg)
+    } else if *agg.mode() == AggregateMode::Partial {
+        push_partial_over_final_groups(&p, agg)
+    } else {
+        Ok(p)
+    }
+}
+
+fn push_final_groups(
+    p: &Arc<dyn ExecutionPlan>,
+    agg: &HashAggregateExec,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    if !agg.aggr_expr().is_empty() || agg.group_expr().is_empty() {
+        return Ok(p.clone());
+    }
+    let send = skip_merge(agg.input().clone());
+    if let Some(cs) = send.as_any().downcast_ref::<ClusterSendExec>() {
+        // Router plan.
+        if !groups_in_single_partition(&cs.input_for_optimizations) {
+            return Ok(p.clone());
+        }
+        let worker_agg =
+            p.with_new_children(vec![merge_partitions(cs.input_for_optimizations.clone())?])?;
+        merge_partitions(Arc::new(
+            cs.with_changed_schema(agg.schema().clone(), worker_agg),
+        ))
+    } else if let Some(w) = send.as_any().downcast_ref::<WorkerExec>() {
+        // Worker plan.
+        if !groups_in_single_partition(&w.input) {
+            return Ok(p.clone());
+        }
+        Ok(Arc::new(WorkerExec {
+            input: p.with_new_children(vec![merge_partitions(w.input.clone())?])?,
+            schema: agg.schema().clone(),
+            max_batch_rows: w.max_batch_rows,
+        }))
+    } else {
+        Ok(p.clone())
+    }
+}
+
+fn push_partial_over_final_groups(
+    p: &Arc<dyn ExecutionPlan>,
+    agg: &HashAggregateExec,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    // Nodes that process rows one by one, e.g. the alias of a subquery, go to workers as well.
+    let mut row_nodes = Vec::new();
+    let mut input = agg.input().clone();
+    while input.as_any().is::<ProjectionExec>()
+        || input.as_any().is::<AliasedSchemaExec>()
+        || input.as_any().is::<FilterExec>()
+    {
+        let child = input.children()[0].clone();
+        row_nodes.push(input);
+        input = child;
+    }
+    let with_row_nodes = |input: Arc<dyn ExecutionPlan>| {
+        row_nodes
+            .iter()
+            .rev()
+            .try_fold(input, |input, n| n.with_new_children(vec![input]))
+    };
+
+    let send = skip_merge(input);
+    if let Some(cs) = send.as_any().downcast_ref::<ClusterSendExec>() {
+        // Router plan.
+        if !is_final_aggregate(&cs.input_for_optimizations) {
+            return Ok(p.clone());
+        }
+        let worker_agg =
+            p.with_new_children(vec![with_row_nodes(cs.input_for_optimizations.clone())?])?;
+        merge_partitions(Arc::new(
+            cs.with_changed_schema(agg.schema().clone(), worker_agg),
+        ))
+    } else if let Some(w) = send.as_any().downcast_ref::<WorkerExec>() {
+        // Worker plan.
+        if !is_final_aggregate(&w.input) {
+            return Ok(p.clone());
+        }
+        Ok(Arc::new(WorkerExec {
+            input: p.with_new_children(vec![with_row_nodes(w.input.clone())?])?,
+            schema: agg.schema().clone(),
+            max_batch_rows: w.max_batch_rows,
+        }))
+    } else {
+        Ok(p.clone())
+    }
+}
+
+/// Whether keys of `p`, a partial aggregate reading a single index, include all columns of the
+/// sort key of the index.
+fn groups_in_single_partition(p: &Arc<dyn ExecutionPlan>) -> bool {
+    let agg = match p.as_any().downcast_ref::<HashAggregateExec>() {
+        Some(a) if *a.mode() == AggregateMode::Partial => a,
+        _ => return false,
+    };
+    // Nodes that keep positions of columns. Unions and joins read more than one index.
+    let mut scan = agg.input().clone();
+    while scan.as_any().is::<FilterExec>()
+        || scan.as_any().is::<AliasedSchemaExec>()
+        || scan.as_any().is::<MergeExec>()
+        || scan.as_any().is::<MergeSortExec>()
+    {
+        scan = scan.children()[0].clone();
+    }
+    let table = match scan.as_any().downcast_ref::<CubeTableExec>() {
+        Some(t) => t,
+        None => return false,
+    };
+
+    let input_schema = agg.input().schema().to_schema_ref();
+    let mut group_columns = Vec::with_capacity(agg.group_expr().len());
+    for (e, _) in agg.group_expr() {
+        let column = match e.as_any().downcast_ref::<Column>() {
+            Some(c) => c,
+            None => return false,
+        };
+        match column
+            .lookup_field(&input_schema)
+            .ok()
+            .and_then(|f| input_schema.index_of(f.name()).ok())
+        {
+            Some(i) => group_columns.push(i),
+            None => return false,
+        }
+    }
+
+    let index = table.index_snapshot.index().get_row();
+    let sort_key = &index.get_columns()[..index.sort_key_size() as usize];
+    !sort_key.is_empty()
+        && sort_key.iter().all(|c| {
+            table
+                .schema()
+                .index_of(c.get_name())
+                .map_or(false, |i| group_columns.contains(&i))
+        })
+}
+
+fn is_final_aggregate(p: &Arc<dyn ExecutionPlan>) -> bool {
+    match p.as_any().downcast_ref::<HashAggregateExec>() {
+        Some(a) => *a.mode() == AggregateMode::Final,
+        None => false,
+    }
+}
+
+fn skip_merge(p: Arc<dyn ExecutionPlan>) -> Arc<dyn ExecutionPlan> {
+    if p.as_any().is::<MergeExec>() || p.as_any().is::<MergeSortExec>() {
+        p.children()[0].clone()
+    } else {
+        p
+    }
+}
+
+/// Merges partitions of `p` into one, keeping the order of rows if `p` is sorted.
+fn merge_partitions(p: Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    if p.output_partitioning().partition_count() == 1 {
+        return Ok(p);
+    }
+    match p.output_hints().sort_order {
+        Some(sort_order) if !sort_order.is_empty() => {
+            let sort_columns = sort_order
+                .into_iter()
+                .map(|i| p.schema().field(i).qualified_name())
+                .collect();
+            Ok(Arc::new(MergeSortExec::try_new(p, sort_columns)?))
+        }
+        _ => Ok(Arc::new(MergeExec::new(p))),
+    }
+}
@@ -0,0 +1,135 @@
+use crate::queryplanner::planning::WorkerExec;
+use crate::queryplanner::query_executor::ClusterSendExec;
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::limit::{GlobalLimitExec, LocalLimitExec};
+use datafusion::physical_plan::merge::MergeExec;
+use datafusion::physical_plan::merge_sort::MergeSortExec;
+use datafusion::physical_plan::skip::SkipExec;
+use datafusion::physical_plan::sort::SortExec;
+use datafusion::physical_plan::ExecutionPlan;
+use std::sync::Arc;
+
+/// Transforms from:
+///     Limit
+///     `- ClusterSend
+/// to:
+///     Limit
+///     `- ClusterSend
+///        `- LocalLimit
+///
+/// Workers stop reading data as soon as they produce enough rows, instead of sending all rows to
+/// the router only to be discarded there. The router also stops selects still running on other
+/// workers once it has received enough rows, see [ClusterSendExec::with_limit].
+///
+/// With an ordering between the limit and the cluster send, workers sort their rows and send
+/// only the first ones. The router still sorts to combine results of workers:
+///     Limit
+///     `- Sort
+///        `- ClusterSend
+///           `- LocalLimit
+///              `- Sort
+///
+/// Rows skipped by an `OFFSET` are skipped on the router, so workers send `limit + offset` rows.
+pub fn push_limit_to_workers(
+    p: Arc<dyn ExecutionPlan>,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let (limit, input) = if let Some(l) = p.as_any().downcast_ref::<LocalLimitExec>() {
+        (l.limit(), l.input())
+    } else if let Some(l) = p.as_any().downcast_ref::<GlobalLimitExec>() {
+        (l.limit(), l.input())
+    } else {
+        return Ok(p);
+    };
+
+    if let Some(skip) = input.as_any().downcast_ref::<SkipExec>() {
+        match try_push_limit(skip.input(), limit + skip.skip())? {
+            Some(skip_input) => {
+                p.with_new_children(vec![input.with_new_children(vec![skip_input])?])
+            }
+            None => Ok(p),
+        }
+    } else {
+        match try_push_limit(input, limit)? {
+            Some(input) => p.with_new_children(vec![input]),
+            None => Ok(p),
+        }
+    }
+}
+
+/// Returns `p` with the cluster send or the worker inside it producing at most `limit` rows, or
+/// `None` if there are other nodes in between.
+fn try_push_limit(
+    p: &Arc<dyn ExecutionPlan>,
+    limit: usize,
+) -> Result<Option<Arc<dyn ExecutionPlan>>, DataFusionError> {
+    let sort = if p.as_any().is::<SortExec>() || p.as_any().is::<MergeSortExec>() {
+        Some(p)
+    } else {
+        None
+    };
+    let (merge, send) = {
+        let input = match sort {
+            Some(sort) => sort.children().into_iter().next().unwrap(),
+            None => p.clone(),
+        };
+        match input.as_any().downcast_ref::<MergeExec>() {
+            Some(m) => (Some(input.clone()), m.input().clone()),
+            None => (None, input),
+        }
+    };
+
+    let worker_limit =
+        |input: Arc<dyn ExecutionPlan>| -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+            let input = match sort {
+                Some(sort) => sort_on_worker(sort, input)?,
+                None => input,
+            };
+            Ok(Arc::new(LocalLimitExec::new(input, limit)))
+        };
+    let send: Arc<dyn ExecutionPlan> =
+        if let Some(cs) = send.as_any().downcast_ref::<ClusterSendExec>() {
+            // Router plan, keep the limit on the router to combine results of workers.
+            let cs = cs.with_changed_schema(
+                cs.schema(),
+                worker_limit(cs.input_for_optimizations.clone())?,
+            );
+            match sort {
+                // Rows of any worker will do, so stop waiting for the rest once there are enough.
+                None => Arc::new(cs.with_limit(limit)),
+                Some(_) => Arc::new(cs),
+            }
+        } else if let Some(w) = send.as_any().downcast_ref::<WorkerExec>() {
+            // Worker plan, apply the limit inside the worker.
+            Arc::new(WorkerExec {
+                input: worker_limit(w.input.clone())?,
+                schema: w.schema.clone(),
+                max_batch_rows: w.max_batch_rows,
+            })
+        } else {
+            return Ok(None);
+        };
+
+    let send = match merge {
+        Some(merge) => merge.with_new_children(vec![send])?,
+        None => send,
+    };
+    match sort {
+        Some(sort) => Ok(Some(sort.with_new_children(vec![send])?)),
+        None => Ok(Some(send)),
+    }
+}
+
+/// Applies the sort of the router to rows of a single worker.
+fn sort_on_worker(
+    sort: &Arc<dyn ExecutionPlan>,
+    input: Arc<dyn ExecutionPlan>,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    // Unlike merge sort, sort needs a single input partition.
+    let input =
+        if sort.as_any().is::<SortExec>() && input.output_partitioning().partition_count() != 1 {
+            Arc::new(MergeExec::new(input))
+        } else {
+            input
+        };
+    sort.with_new_children(vec![input])
+}
@@ -0,0 +1,294 @@
+use crate::CubeError;
+use arrow::array::{Array, ArrayRef, Float64Array};
+use arrow::datatypes::{DataType, Field};
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::{Accumulator, AggregateExpr, PhysicalExpr};
+use datafusion::scalar::ScalarValue;
+use smallvec::smallvec;
+use smallvec::SmallVec;
+use std::any::Any;
+use std::convert::TryInto;
+use std::sync::Arc;
+
+/// `SUM` or `AVG` of floats that gives the same result whatever order values are added in, e.g.
+/// when partial sums of workers arrive in a different order. Values are added up exactly and the
+/// result is rounded once, like Python's `math.fsum` does. Partial states are serialized
+/// [ExactSum]s.
+#[derive(Debug)]
+pub struct ExactFloatSum {
+    expr: Arc<dyn PhysicalExpr>,
+    /// Name of the result, same as for the replaced aggregate. The type is always `Float64`.
+    name: String,
+    avg: bool,
+}
+
+impl ExactFloatSum {
+    pub fn new(expr: Arc<dyn PhysicalExpr>, name: String, avg: bool) -> ExactFloatSum {
+        ExactFloatSum { expr, name, avg }
+    }
+}
+
+impl AggregateExpr for ExactFloatSum {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn field(&self) -> Result<Field, DataFusionError> {
+        Ok(Field::new(&self.name, DataType::Float64, true))
+    }
+
+    fn create_accumulator(&self) -> Result<Box<dyn Accumulator>, DataFusionError> {
+        Ok(Box::new(ExactFloatSumAccumulator {
+            sum: ExactSum::default(),
+            avg: self.avg,
+        }))
+    }
+
+    fn state_fields(&self) -> Result<Vec<Field>, DataFusionError> {
+        Ok(vec![Field::new(
+            &format!("{}[exact_sum]", self.name),
+            DataType::Binary,
+            true,
+        )])
+    }
+
+    fn expressions(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.expr.clone()]
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Sum of floats without rounding errors, kept as non-overlapping partials in the increasing
+/// order of magnitude. See Shewchuk, "Adaptive Precision Floating-Point Arithmetic and Fast
+/// Robust Geometric Predicates".
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ExactSum {
+    count: u64,
+    partials: Vec<f64>,
+    /// Sum of infinities and NaNs, they are not represented by partials.
+    special: f64,
+}
+
+impl ExactSum {
+    pub fn add(&mut self, v: f64) {
+        self.count += 1;
+        if !v.is_finite() {
+            self.special += v;
+            return;
+        }
+        self.add_partial(v);
+    }
+
+    fn add_partial(&mut self, mut x: f64) {
+        let mut i = 0;
+        for j in 0..self.partials.len() {
+            let mut y = self.partials[j];
+            if x.abs() < y.abs() {
+                std::mem::swap(&mut x, &mut y);
+            }
+            let hi = x + y;
+            let lo = y - (hi - x);
+            if lo != 0. {
+                self.partials[i] = lo;
+                i += 1;
+            }
+            x = hi;
+        }
+        self.partials.truncate(i);
+        self.partials.push(x);
+    }
+
+    pub fn merge(&mut self, o: &ExactSum) {
+        self.count += o.count;
+        self.special += o.special;
+        for p in &o.partials {
+            self.add_partial(*p);
+        }
+    }
+
+    /// The exact sum rounded to the nearest float, `None` if no values were added.
+    pub fn sum(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        if self.special != 0. {
+            return Some(self.special);
+        }
+        let p = &self.partials;
+        let mut n = p.len();
+        if n == 0 {
+            return Some(0.);
+        }
+        n -= 1;
+        let mut hi = p[n];
+        let mut lo = 0.;
+        while n > 0 {
+            let x = hi;
+            n -= 1;
+            let y = p[n];
+            hi = x + y;
+            lo = y - (hi - x);
+            if lo != 0. {
+                break;
+            }
+        }
+        // Round half to even in the presence of the remaining partials.
+        if n > 0 && ((lo < 0. && p[n - 1] < 0.) || (lo > 0. && p[n - 1] > 0.)) {
+            let y = lo * 2.;
+            let x = hi + y;
+            if y == x - hi {
+                hi = x;
+            }
+        }
+        Some(hi)
+    }
+
+    fn write(&self) -> Vec<u8> {
+        let mut r = Vec::with_capacity(16 + 8 * self.partials.len());
+        r.extend_from_slice(&self.count.to_le_bytes());
+        r.extend_from_slice(&self.special.to_le_bytes());
+        for p in &self.partials {
+            r.extend_from_slice(&p.to_le_bytes());
+        }
+        r
+    }
+
+    fn read(data: &[u8]) -> Result<ExactSum, CubeError> {
+        if data.len() < 16 || data.len() % 8 != 0 {
+            return Err(CubeError::internal(
+                "invalid state of an exact sum".to_string(),
+            ));
+        }
+        let word = |i: usize| -> [u8; 8] { data[8 * i..8 * (i + 1)].try_into().unwrap() };
+        Ok(ExactSum {
+            count: u64::from_le_bytes(word(0)),
+            special: f64::from_le_bytes(word(1)),
+            partials: (2..data.len() / 8)
+                .map(|i| f64::from_le_bytes(word(i)))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ExactFloatSumAccumulator {
+    sum: ExactSum,
+    avg: bool,
+}
+
+impl Accumulator for ExactFloatSumAccumulator {
+    fn reset(&mut self) {
+        self.sum = ExactSum::default();
+    }
+
+    fn state(&self) -> Result<SmallVec<[ScalarValue; 2]>, DataFusionError> {
+        return Ok(smallvec![ScalarValue::Binary(Some(self.sum.write()))]);
+    }
+
+    fn update(&mut self, row: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(row.len(), 1);
+        match &row[0] {
+            ScalarValue::Float64(Some(v)) => self.sum.add(*v),
+            ScalarValue::Float64(None) => {}
+            v => {
+                return Err(CubeError::internal(format!(
+                    "invalid scalar value passed to an exact sum: {:?}",
+                    v
+                ))
+                .into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<(), DataFusionError> {
+        assert_eq!(values.len(), 1);
+        let a = match values[0].as_any().downcast_ref::<Float64Array>() {
+            Some(a) => a,
+            None => {
+                return Err(CubeError::internal(format!(
+                    "invalid array of type {:?} passed to an exact sum",
+                    values[0].data_type()
+                ))
+                .into())
+            }
+        };
+        for i in 0..a.len() {
+            if a.is_valid(i) {
+                self.sum.add(a.value(i))
+            }
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, states: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(states.len(), 1);
+        match &states[0] {
+            ScalarValue::Binary(Some(data)) => self.sum.merge(&ExactSum::read(data)?),
+            ScalarValue::Binary(None) => {}
+            _ => {
+                return Err(CubeError::internal("invalid state of an exact sum".to_string()).into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue, DataFusionError> {
+        let sum = self.sum.sum();
+        if !self.avg {
+            return Ok(ScalarValue::Float64(sum));
+        }
+        Ok(ScalarValue::Float64(sum.map(|s| s / self.sum.count as f64)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    #[test]
+    fn order_does_not_matter() {
+        let values = vec![1e100, 0.1, -1e100, 0.2, 1e-20, 0.3, 3.5e15, -3.5e15];
+        let expected = {
+            let mut s = ExactSum::default();
+            values.iter().for_each(|v| s.add(*v));
+            s.sum().unwrap()
+        };
+        assert_eq!(expected, 0.6);
+        for p in values.iter().permutations(values.len()).step_by(97) {
+            // Split into two partial sums.
+            let mut l = ExactSum::default();
+            let mut r = ExactSum::default();
+            for (i, v) in p.iter().enumerate() {
+                if i % 3 == 0 {
+                    l.add(**v)
+                } else {
+                    r.add(**v)
+                }
+            }
+            let mut r = ExactSum::read(&r.write()).unwrap();
+            r.merge(&l);
+            assert_eq!(r.sum().unwrap(), expected);
+            assert_eq!(r.count, values.len() as u64);
+        }
+    }
+
+    #[test]
+    fn special_values() {
+        let sum = |values: &[f64]| {
+            let mut s = ExactSum::default();
+            values.iter().for_each(|v| s.add(*v));
+            s.sum()
+        };
+        assert_eq!(sum(&[]), None);
+        assert_eq!(sum(&[1., -1.]), Some(0.));
+        assert_eq!(sum(&[1., f64::INFINITY]), Some(f64::INFINITY));
+        assert!(sum(&[f64::NEG_INFINITY, 1., f64::INFINITY])
+            .unwrap()
+            .is_nan());
+    }
+}
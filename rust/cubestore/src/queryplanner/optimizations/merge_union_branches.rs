@@ -0,0 +1,69 @@
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::alias::AliasedSchemaExec;
+use datafusion::physical_plan::filter::FilterExec;
+use datafusion::physical_plan::merge::MergeExec;
+use datafusion::physical_plan::merge_sort::MergeSortExec;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::union::UnionExec;
+use datafusion::physical_plan::ExecutionPlan;
+use std::sync::Arc;
+
+/// Merges partitions of all union branches at once, instead of merging each branch first. Unions
+/// of many tables, e.g. rollups of different periods, only need a single merge then:
+///     MergeSort
+///     `- Union
+///        |- Projection
+///        |  `- MergeSort
+///        |     `- Scan, index: a
+///        `- Projection
+///           `- MergeSort
+///              `- Scan, index: b
+/// becomes:
+///     MergeSort
+///     `- Union
+///        |- Projection
+///        |  `- Scan, index: a
+///        `- Projection
+///           `- Scan, index: b
+pub fn try_merge_union_branches(
+    p: Arc<dyn ExecutionPlan>,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let sorted = p.as_any().is::<MergeSortExec>();
+    if !sorted && !p.as_any().is::<MergeExec>() {
+        return Ok(p);
+    }
+    let union = p.children().into_iter().next().unwrap();
+    if !union.as_any().is::<UnionExec>() {
+        return Ok(p);
+    }
+    let new_union = union.with_new_children(
+        union
+            .children()
+            .into_iter()
+            .map(remove_merges)
+            .collect::<Result<_, DataFusionError>>()?,
+    )?;
+    // Partitions of the branches must keep the order the merge relies on.
+    if sorted && new_union.output_hints().sort_order != union.output_hints().sort_order {
+        return Ok(p);
+    }
+    p.with_new_children(vec![new_union])
+}
+
+fn remove_merges(p: Arc<dyn ExecutionPlan>) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    if p.as_any().is::<MergeSortExec>() || p.as_any().is::<MergeExec>() {
+        return Ok(p.children().into_iter().next().unwrap());
+    }
+    if p.as_any().is::<ProjectionExec>()
+        || p.as_any().is::<FilterExec>()
+        || p.as_any().is::<AliasedSchemaExec>()
+    {
+        return p.with_new_children(
+            p.children()
+                .into_iter()
+                .map(remove_merges)
+                .collect::<Result<_, DataFusionError>>()?,
+        );
+    }
+    Ok(p)
+}
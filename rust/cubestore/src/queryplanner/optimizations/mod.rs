@@ -1,6 +1,13 @@
 use crate::cluster::Cluster;
+use crate::queryplanner::casts::CastOverflow;
+use crate::queryplanner::optimizations::checked_sum::use_checked_sums;
+use crate::queryplanner::optimizations::distributed_final_aggregate::push_final_aggregate_to_workers;
+use crate::queryplanner::optimizations::distributed_limit::push_limit_to_workers;
 use crate::queryplanner::optimizations::distributed_partial_aggregate::push_aggregate_to_workers;
+use crate::queryplanner::optimizations::merge_union_branches::try_merge_union_branches;
+use crate::queryplanner::optimizations::parallel_final_aggregate::try_parallel_final_aggregate;
 use crate::queryplanner::optimizations::prefer_inplace_aggregates::try_switch_to_inplace_aggregates;
+use crate::queryplanner::parallel_merge::ParallelMergeOptions;
 use crate::queryplanner::planning::CubeExtensionPlanner;
 use crate::queryplanner::serialized_plan::SerializedPlan;
 use datafusion::error::DataFusionError;
@@ -11,23 +18,33 @@ use datafusion::physical_plan::{ExecutionPlan, PhysicalPlanner};
 use rewrite_plan::rewrite_physical_plan;
 use std::sync::Arc;
 
+mod checked_sum;
+mod distributed_final_aggregate;
+mod distributed_limit;
 mod distributed_partial_aggregate;
+mod exact_float_sum;
+mod merge_union_branches;
+mod parallel_final_aggregate;
 mod prefer_inplace_aggregates;
 pub mod rewrite_plan;
 
 pub struct CubeQueryPlanner {
     cluster: Option<Arc<dyn Cluster>>,
     serialized_plan: Arc<SerializedPlan>,
+    /// Only used on the router.
+    parallel_merge: Option<ParallelMergeOptions>,
 }
 
 impl CubeQueryPlanner {
     pub fn new_on_router(
         cluster: Arc<dyn Cluster>,
         serialized_plan: Arc<SerializedPlan>,
+        parallel_merge: Option<ParallelMergeOptions>,
     ) -> CubeQueryPlanner {
         CubeQueryPlanner {
             cluster: Some(cluster),
             serialized_plan,
+            parallel_merge,
         }
     }
 
@@ -35,6 +52,7 @@ impl CubeQueryPlanner {
         CubeQueryPlanner {
             serialized_plan,
             cluster: None,
+            parallel_merge: None,
         }
     }
 }
@@ -52,13 +70,34 @@ impl QueryPlanner for CubeQueryPlanner {
             })])
             .create_physical_plan(logical_plan, ctx_state)?;
         // TODO: assert there is only a single ClusterSendExec in the plan.
-        finalize_physical_plan(p)
+        finalize_physical_plan(
+            p,
+            self.serialized_plan.sum_overflow(),
+            self.serialized_plan.exact_float_sums(),
+            self.parallel_merge.as_ref(),
+        )
     }
 }
 
 fn finalize_physical_plan(
     p: Arc<dyn ExecutionPlan>,
+    sum_overflow: CastOverflow,
+    exact_float_sums: bool,
+    parallel_merge: Option<&ParallelMergeOptions>,
 ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    // Must run before aggregates are split between workers and the router.
+    let p = rewrite_physical_plan(p.as_ref(), &mut |p| {
+        use_checked_sums(p, sum_overflow, exact_float_sums)
+    })?;
     let p = rewrite_physical_plan(p.as_ref(), &mut |p| try_switch_to_inplace_aggregates(p))?;
-    rewrite_physical_plan(p.as_ref(), &mut |p| push_aggregate_to_workers(p))
+    let p = rewrite_physical_plan(p.as_ref(), &mut |p| try_merge_union_branches(p))?;
+    let p = rewrite_physical_plan(p.as_ref(), &mut |p| push_aggregate_to_workers(p))?;
+    let p = rewrite_physical_plan(p.as_ref(), &mut |p| push_final_aggregate_to_workers(p))?;
+    let p = rewrite_physical_plan(p.as_ref(), &mut |p| push_limit_to_workers(p))?;
+    match parallel_merge {
+        Some(options) => rewrite_physical_plan(p.as_ref(), &mut |p| {
+            try_parallel_final_aggregate(p, options)
+        }),
+        None => Ok(p),
+    }
 }
@@ -0,0 +1,70 @@
+use crate::queryplanner::parallel_merge::{ParallelMergeExec, ParallelMergeOptions};
+use crate::queryplanner::query_executor::ClusterSendExec;
+use crate::queryplanner::udfs::is_hashable;
+use datafusion::error::DataFusionError;
+use datafusion::physical_plan::hash_aggregate::{
+    AggregateMode, AggregateStrategy, HashAggregateExec,
+};
+use datafusion::physical_plan::merge::MergeExec;
+use datafusion::physical_plan::ExecutionPlan;
+use std::sync::Arc;
+
+/// Transforms from:
+///     AggregateFinal
+///     `- Merge
+///        `- ClusterSend
+/// to:
+///     ParallelMerge
+///     `- Merge
+///        `- ClusterSend
+///
+/// The latter runs the final aggregation on multiple cores of the router and spills when
+/// results of workers do not fit into memory. Sorted aggregates already stream their groups.
+pub fn try_parallel_final_aggregate(
+    p: Arc<dyn ExecutionPlan>,
+    options: &ParallelMergeOptions,
+) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+    let agg;
+    if let Some(a) = p.as_any().downcast_ref::<HashAggregateExec>() {
+        agg = a;
+    } else {
+        return Ok(p);
+    }
+    if *agg.mode() != AggregateMode::Final
+        || agg.strategy() != AggregateStrategy::Hash
+        || agg.group_expr().is_empty()
+    {
+        return Ok(p);
+    }
+    let input = agg.input();
+    let send = match input.as_any().downcast_ref::<MergeExec>() {
+        Some(m) => m.input(),
+        None => input,
+    };
+    if !send.as_any().is::<ClusterSendExec>() {
+        return Ok(p);
+    }
+
+    let input_schema = input.schema().to_schema_ref();
+    let group_expr = agg
+        .group_expr()
+        .iter()
+        .map(|(e, _)| e.clone())
+        .collect::<Vec<_>>();
+    for e in &group_expr {
+        if !is_hashable(&e.data_type(input_schema.as_ref())?) {
+            return Ok(p);
+        }
+    }
+    let input = if input.output_partitioning().partition_count() == 1 {
+        input.clone()
+    } else {
+        Arc::new(MergeExec::new(input.clone()))
+    };
+    Ok(Arc::new(ParallelMergeExec::new(
+        p.clone(),
+        group_expr,
+        input,
+        options.clone(),
+    )))
+}
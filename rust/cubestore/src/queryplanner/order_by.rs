@@ -0,0 +1,260 @@
+//! The SQL planner resolves ORDER BY against the output of the projection. To order by an
+//! expression that is not a plain output column, we add it to the projection as a hidden column
+//! and remove hidden columns after planning.
+use crate::CubeError;
+use datafusion::logical_plan::{DFSchema, Expr as LogicalExpr, LogicalPlan};
+use datafusion::sql::parser::Statement as DFStatement;
+use itertools::Itertools;
+use sqlparser::ast::{
+    Expr, Ident, Query, Select, SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value,
+};
+use std::sync::Arc;
+
+const HIDDEN_COLUMN_PREFIX: &str = "__order_by_";
+
+/// Replaces ORDER BY expressions that repeat an item of the select list with a reference to
+/// that item.
+pub fn reuse_select_items(statement: &mut DFStatement) {
+    if let Some((select, order_by)) = select_and_order_by(statement) {
+        for e in order_by {
+            if is_ordinal(e) || is_identifier(e) {
+                continue;
+            }
+            let item = select.projection.iter().position(|p| match p {
+                SelectItem::UnnamedExpr(p) | SelectItem::ExprWithAlias { expr: p, .. } => p == e,
+                SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => false,
+            });
+            if let Some(item) = item {
+                *e = select_item_ref(select, item);
+            }
+        }
+    }
+}
+
+/// Sets null ordering of ORDER BY keys that do not specify it, see
+/// [crate::config::ConfigObj::nulls_largest]. The planner puts nulls first by default.
+pub fn set_nulls_order(statement: &mut DFStatement, nulls_largest: bool) {
+    if !nulls_largest {
+        return;
+    }
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        set_query_nulls_order(q);
+    }
+}
+
+fn set_query_nulls_order(q: &mut Query) {
+    for o in q.order_by.iter_mut() {
+        if o.nulls_first.is_none() {
+            o.nulls_first = Some(o.asc == Some(false));
+        }
+    }
+    set_body_nulls_order(&mut q.body);
+}
+
+fn set_body_nulls_order(body: &mut SetExpr) {
+    match body {
+        SetExpr::Select(s) => {
+            for t in s.from.iter_mut() {
+                set_tables_nulls_order(t);
+            }
+        }
+        SetExpr::Query(q) => set_query_nulls_order(q),
+        SetExpr::SetOperation { left, right, .. } => {
+            set_body_nulls_order(left);
+            set_body_nulls_order(right);
+        }
+        _ => {}
+    }
+}
+
+fn set_tables_nulls_order(t: &mut TableWithJoins) {
+    for r in std::iter::once(&mut t.relation).chain(t.joins.iter_mut().map(|j| &mut j.relation)) {
+        match r {
+            TableFactor::Derived { subquery, .. } => set_query_nulls_order(subquery),
+            TableFactor::NestedJoin(t) => set_tables_nulls_order(t),
+            _ => {}
+        }
+    }
+}
+
+/// Computes ORDER BY expressions that do not refer to output columns in hidden columns. Returns
+/// [None] if there is nothing to change.
+pub fn add_hidden_columns(statement: &DFStatement) -> Option<DFStatement> {
+    let mut statement = statement.clone();
+    let (select, order_by) = select_and_order_by(&mut statement)?;
+    if select.distinct {
+        // Hidden columns would change the result.
+        return None;
+    }
+    let mut changed = false;
+    for e in order_by {
+        if is_ordinal(e) || is_output_column(e, &select.projection) {
+            continue;
+        }
+        *e = add_hidden_column(select, e.clone());
+        changed = true;
+    }
+    if changed {
+        Some(statement)
+    } else {
+        None
+    }
+}
+
+pub fn remove_hidden_columns(plan: LogicalPlan) -> Result<LogicalPlan, CubeError> {
+    let schema = plan.schema();
+    if !schema
+        .fields()
+        .iter()
+        .any(|f| f.name().starts_with(HIDDEN_COLUMN_PREFIX))
+    {
+        return Ok(plan);
+    }
+    let fields = schema
+        .fields()
+        .iter()
+        .filter(|f| !f.name().starts_with(HIDDEN_COLUMN_PREFIX))
+        .cloned()
+        .collect_vec();
+    let expr = fields
+        .iter()
+        .map(|f| LogicalExpr::Column(f.name().clone(), f.qualifier().cloned()))
+        .collect_vec();
+    Ok(LogicalPlan::Projection {
+        expr,
+        schema: Arc::new(DFSchema::new(fields)?),
+        input: Arc::new(plan),
+    })
+}
+
+fn select_and_order_by(statement: &mut DFStatement) -> Option<(&mut Select, Vec<&mut Expr>)> {
+    let query = match statement {
+        DFStatement::Statement(Statement::Query(q)) => q.as_mut(),
+        _ => return None,
+    };
+    let Query { body, order_by, .. } = query;
+    match body {
+        SetExpr::Select(select) if !order_by.is_empty() => Some((
+            select.as_mut(),
+            order_by.iter_mut().map(|o| &mut o.expr).collect(),
+        )),
+        _ => None,
+    }
+}
+
+fn select_item_ref(select: &mut Select, item: usize) -> Expr {
+    match &select.projection[item] {
+        SelectItem::ExprWithAlias { alias, .. } => Expr::Identifier(alias.clone()),
+        SelectItem::UnnamedExpr(e) => {
+            let e = e.clone();
+            add_hidden_column(select, e)
+        }
+        SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => {
+            panic!("wildcard can't be referenced")
+        }
+    }
+}
+
+fn add_hidden_column(select: &mut Select, e: Expr) -> Expr {
+    let alias = Ident::new(format!(
+        "{}{}",
+        HIDDEN_COLUMN_PREFIX,
+        select.projection.len()
+    ));
+    select.projection.push(SelectItem::ExprWithAlias {
+        expr: e,
+        alias: alias.clone(),
+    });
+    Expr::Identifier(alias)
+}
+
+fn is_ordinal(e: &Expr) -> bool {
+    match e {
+        Expr::Value(Value::Number(..)) => true,
+        _ => false,
+    }
+}
+
+fn is_identifier(e: &Expr) -> bool {
+    match e {
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => true,
+        _ => false,
+    }
+}
+
+fn is_output_column(e: &Expr, projection: &[SelectItem]) -> bool {
+    if !is_identifier(e) {
+        return false;
+    }
+    projection.iter().any(|p| match p {
+        SelectItem::UnnamedExpr(p) => p == e,
+        SelectItem::ExprWithAlias { alias, .. } => match e {
+            Expr::Identifier(i) => i == alias,
+            _ => false,
+        },
+        SelectItem::Wildcard | SelectItem::QualifiedWildcard(_) => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn parse(s: &str) -> DFStatement {
+        match CubeStoreParser::new(s).unwrap().parse_statement().unwrap() {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    fn to_sql(s: &DFStatement) -> String {
+        match s {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut s = parse("SELECT a, SUM(b) s FROM t GROUP BY 1 ORDER BY SUM(b) DESC, 1");
+        reuse_select_items(&mut s);
+        assert_eq!(
+            to_sql(&s),
+            "SELECT a, SUM(b) AS s FROM t GROUP BY 1 ORDER BY s DESC, 1"
+        );
+
+        let mut s = parse("SELECT a, SUM(b) FROM t GROUP BY 1 ORDER BY SUM(b)");
+        reuse_select_items(&mut s);
+        assert_eq!(
+            to_sql(&s),
+            "SELECT a, SUM(b), SUM(b) AS __order_by_2 FROM t GROUP BY 1 ORDER BY __order_by_2"
+        );
+
+        let s = parse("SELECT a FROM t ORDER BY b + 1, a");
+        assert_eq!(
+            to_sql(&add_hidden_columns(&s).unwrap()),
+            "SELECT a, b + 1 AS __order_by_1 FROM t ORDER BY __order_by_1, a"
+        );
+
+        assert!(add_hidden_columns(&parse("SELECT a FROM t ORDER BY a")).is_none());
+        assert!(add_hidden_columns(&parse("SELECT * FROM t ORDER BY b")).is_none());
+        assert!(add_hidden_columns(&parse("SELECT DISTINCT a FROM t ORDER BY b")).is_none());
+    }
+
+    #[test]
+    fn nulls_order() {
+        let sql = "SELECT a FROM (SELECT a, b FROM t ORDER BY b DESC LIMIT 10) AS x \
+                   ORDER BY a, b DESC, a DESC NULLS LAST, b NULLS FIRST";
+        let mut s = parse(sql);
+        set_nulls_order(&mut s, false);
+        assert_eq!(to_sql(&s), to_sql(&parse(sql)));
+
+        set_nulls_order(&mut s, true);
+        assert_eq!(
+            to_sql(&s),
+            "SELECT a FROM (SELECT a, b FROM t ORDER BY b DESC NULLS FIRST LIMIT 10) AS x \
+             ORDER BY a NULLS LAST, b DESC NULLS FIRST, a DESC NULLS LAST, b NULLS FIRST"
+        );
+    }
+}
@@ -0,0 +1,396 @@
+//! Parallel final aggregation of worker results on the router. Without it, partial aggregates of
+//! all workers go through a single hash table, so a big GROUP BY runs on one core and keeps all
+//! groups and all received batches in memory at once.
+//!
+//! [ParallelMergeExec] splits received rows into buckets by the hash of their group keys, so each
+//! group ends up in exactly one bucket, and runs the final aggregation of buckets on
+//! [ParallelMergeOptions::partitions] cores at once. Buffered rows over
+//! [ParallelMergeOptions::memory_limit] are spilled to [ScratchSpace], largest buckets first. There
+//! are more buckets than cores, so only a fraction of groups is aggregated in memory at a time.
+use crate::queryplanner::batch_cache::batches_size;
+use crate::queryplanner::udfs::hash_value;
+use crate::util::scratch::{QueryScratch, ScratchSpace};
+use crate::CubeError;
+use arrow::array::{ArrayRef, UInt32Array};
+use arrow::compute::take;
+use arrow::datatypes::SchemaRef;
+use arrow::error::Result as ArrowResult;
+use arrow::ipc::reader::StreamReader;
+use arrow::ipc::writer::MemStreamWriter;
+use arrow::record_batch::RecordBatch;
+use async_trait::async_trait;
+use datafusion::error::DataFusionError;
+use datafusion::logical_plan::DFSchemaRef;
+use datafusion::physical_plan::memory::MemoryExec;
+use datafusion::physical_plan::{
+    collect, ExecutionPlan, OptimizerHints, Partitioning, PhysicalExpr, RecordBatchStream,
+    SendableRecordBatchStream,
+};
+use futures::stream::{Stream, StreamExt};
+use log::debug;
+use std::any::Any;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Buckets per partition. More buckets make less groups aggregated in memory at a time.
+const BUCKETS_PER_PARTITION: usize = 4;
+
+#[derive(Clone, Debug)]
+pub struct ParallelMergeOptions {
+    /// Number of final aggregations running at once.
+    pub partitions: usize,
+    /// Bytes of received batches kept in memory before spilling.
+    pub memory_limit: usize,
+    pub scratch: Arc<ScratchSpace>,
+}
+
+/// Runs `aggregate`, a final hash aggregate, in parallel on buckets of its input.
+#[derive(Debug)]
+pub struct ParallelMergeExec {
+    aggregate: Arc<dyn ExecutionPlan>,
+    group_expr: Vec<Arc<dyn PhysicalExpr>>,
+    input: Arc<dyn ExecutionPlan>,
+    options: ParallelMergeOptions,
+}
+
+impl ParallelMergeExec {
+    /// `group_expr` must compute group keys of the final aggregate from rows of `input`.
+    pub fn new(
+        aggregate: Arc<dyn ExecutionPlan>,
+        group_expr: Vec<Arc<dyn PhysicalExpr>>,
+        input: Arc<dyn ExecutionPlan>,
+        options: ParallelMergeOptions,
+    ) -> ParallelMergeExec {
+        ParallelMergeExec {
+            aggregate,
+            group_expr,
+            input,
+            options,
+        }
+    }
+
+    pub fn options(&self) -> &ParallelMergeOptions {
+        &self.options
+    }
+
+    fn num_buckets(&self) -> usize {
+        self.options.partitions * BUCKETS_PER_PARTITION
+    }
+
+    async fn split_input(&self, scratch: &QueryScratch) -> Result<Vec<Bucket>, CubeError> {
+        let schema = self.input.schema().to_schema_ref();
+        let mut buckets = (0..self.num_buckets())
+            .map(|_| Bucket::default())
+            .collect::<Vec<_>>();
+        let mut buffered = 0;
+        // Single input partition when the input is a merge, see the rewrite.
+        let mut input = self.input.execute(0).await?;
+        while let Some(batch) = input.next().await {
+            for (bucket, part) in buckets.iter_mut().zip(self.split_batch(&batch?)?) {
+                if let Some(part) = part {
+                    let size = batches_size(std::slice::from_ref(&part));
+                    bucket.batches.push(part);
+                    bucket.size += size;
+                    buffered += size;
+                }
+            }
+            while self.options.memory_limit < buffered {
+                let largest = buckets.iter_mut().max_by_key(|b| b.size).unwrap();
+                buffered -= largest.size;
+                largest.spill(scratch, &schema).await?;
+            }
+        }
+        Ok(buckets)
+    }
+
+    /// Parts of the batch for each bucket, [None] for buckets without rows.
+    fn split_batch(&self, batch: &RecordBatch) -> Result<Vec<Option<RecordBatch>>, CubeError> {
+        let keys = self
+            .group_expr
+            .iter()
+            .map(|e| Ok(e.evaluate(batch)?.into_array(batch.num_rows())))
+            .collect::<Result<Vec<ArrayRef>, CubeError>>()?;
+        let mut indices = vec![Vec::new(); self.num_buckets()];
+        for row in 0..batch.num_rows() {
+            let mut h = DefaultHasher::new();
+            for k in &keys {
+                hash_value(k, row, &mut h)?;
+            }
+            indices[(h.finish() % indices.len() as u64) as usize].push(row as u32);
+        }
+        indices
+            .into_iter()
+            .map(|rows| {
+                if rows.is_empty() {
+                    return Ok(None);
+                }
+                let rows = UInt32Array::from(rows);
+                let columns = batch
+                    .columns()
+                    .iter()
+                    .map(|c| take(c.as_ref(), &rows, None))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Some(RecordBatch::try_new(batch.schema(), columns)?))
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for ParallelMergeExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> DFSchemaRef {
+        self.aggregate.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        Partitioning::UnknownPartitioning(1)
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![self.input.clone()]
+    }
+
+    fn with_new_children(
+        &self,
+        mut children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>, DataFusionError> {
+        assert_eq!(children.len(), 1);
+        Ok(Arc::new(ParallelMergeExec::new(
+            self.aggregate.clone(),
+            self.group_expr.clone(),
+            children.remove(0),
+            self.options.clone(),
+        )))
+    }
+
+    fn output_hints(&self) -> OptimizerHints {
+        OptimizerHints::default()
+    }
+
+    async fn execute(
+        &self,
+        partition: usize,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
+        assert_eq!(partition, 0);
+        let scratch = Arc::new(self.options.scratch.for_query("parallel merge"));
+        let buckets = self
+            .split_input(&scratch)
+            .await
+            .map_err(|e| DataFusionError::Execution(e.to_string()))?;
+        debug!(
+            "Parallel merge of {} buckets, {} spilled",
+            buckets.len(),
+            buckets.iter().filter(|b| !b.files.is_empty()).count()
+        );
+
+        let input_schema = self.input.schema().to_schema_ref();
+        let aggregate = self.aggregate.clone();
+        let results =
+            futures::stream::iter(buckets.into_iter().filter(|b| !b.is_empty()))
+                .map(move |bucket| {
+                    let aggregate = aggregate.clone();
+                    let input_schema = input_schema.clone();
+                    let scratch = scratch.clone();
+                    async move {
+                        tokio::spawn(bucket.aggregate(aggregate, input_schema, scratch)).await?
+                    }
+                })
+                .buffer_unordered(self.options.partitions)
+                .flat_map(|r: Result<Vec<RecordBatch>, CubeError>| {
+                    futures::stream::iter(match r {
+                        Ok(batches) => batches.into_iter().map(Ok).collect::<Vec<_>>(),
+                        Err(e) => vec![Err(e.into())],
+                    })
+                });
+        Ok(Box::pin(ParallelMergeStream {
+            schema: self.schema().to_schema_ref(),
+            results: Box::pin(results),
+        }))
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    batches: Vec<RecordBatch>,
+    /// Bytes of `batches`.
+    size: usize,
+    files: Vec<(PathBuf, u64)>,
+}
+
+impl Bucket {
+    fn is_empty(&self) -> bool {
+        self.batches.is_empty() && self.files.is_empty()
+    }
+
+    async fn spill(&mut self, scratch: &QueryScratch, schema: &SchemaRef) -> Result<(), CubeError> {
+        let file = scratch.new_file("merge").await?;
+        let batches = std::mem::take(&mut self.batches);
+        let size = std::mem::take(&mut self.size) as u64;
+        scratch.reserve(size)?;
+        let schema = schema.clone();
+        let path = file.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), CubeError> {
+            let mut writer = MemStreamWriter::try_new(Cursor::new(Vec::new()), &schema)?;
+            for b in &batches {
+                writer.write(b)?;
+            }
+            std::fs::write(&path, writer.finish()?.into_inner())?;
+            Ok(())
+        })
+        .await??;
+        self.files.push((file, size));
+        Ok(())
+    }
+
+    async fn aggregate(
+        self,
+        aggregate: Arc<dyn ExecutionPlan>,
+        input_schema: SchemaRef,
+        scratch: Arc<QueryScratch>,
+    ) -> Result<Vec<RecordBatch>, CubeError> {
+        let mut batches = self.batches;
+        for (file, size) in self.files {
+            let path = file.clone();
+            let spilled = tokio::task::spawn_blocking(move || -> Result<_, CubeError> {
+                let reader = StreamReader::try_new(std::fs::File::open(&path)?)?;
+                Ok(reader.collect::<Result<Vec<_>, _>>()?)
+            })
+            .await??;
+            batches.extend(spilled);
+            tokio::fs::remove_file(&file).await?;
+            scratch.release(size);
+        }
+        let input = MemoryExec::try_new(&vec![batches], input_schema, None)?;
+        Ok(collect(aggregate.with_new_children(vec![Arc::new(input)])?).await?)
+    }
+}
+
+struct ParallelMergeStream {
+    schema: SchemaRef,
+    results: Pin<Box<dyn Stream<Item = ArrowResult<RecordBatch>> + Send>>,
+}
+
+impl Stream for ParallelMergeStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.results.as_mut().poll_next(cx)
+    }
+}
+
+impl RecordBatchStream for ParallelMergeStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::{DataType, Field, Schema};
+    use datafusion::logical_plan::DFSchema;
+    use datafusion::physical_plan::expressions::{col, Count, Sum};
+    use datafusion::physical_plan::hash_aggregate::{
+        AggregateMode, AggregateStrategy, HashAggregateExec,
+    };
+    use datafusion::physical_plan::AggregateExpr;
+    use itertools::Itertools;
+    use std::convert::TryFrom;
+
+    #[tokio::test]
+    async fn parallel_merge() {
+        let root = std::env::current_dir().unwrap().join("parallel-merge-test");
+        let _ = std::fs::remove_dir_all(&root);
+        let scratch = ScratchSpace::new(root.clone(), 0);
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("key", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let batches = (0..10)
+            .map(|i| {
+                let keys = (0..100).map(|k| format!("k{}", (k + i) % 50)).collect_vec();
+                RecordBatch::try_new(
+                    schema.clone(),
+                    vec![
+                        Arc::new(StringArray::from(
+                            keys.iter().map(|k| k.as_str()).collect_vec(),
+                        )),
+                        Arc::new(Int64Array::from(vec![1; 100])),
+                    ],
+                )
+                .unwrap()
+            })
+            .collect_vec();
+        let input = Arc::new(MemoryExec::try_new(&vec![batches], schema.clone(), None).unwrap());
+        let input_schema = Arc::new(DFSchema::try_from(schema.as_ref().clone()).unwrap());
+        let aggr_expr: Vec<Arc<dyn AggregateExpr>> = vec![
+            Arc::new(Sum::new(col("value"), "sum".to_string(), DataType::Int64)),
+            Arc::new(Count::new(
+                col("value"),
+                "count".to_string(),
+                DataType::UInt64,
+            )),
+        ];
+        let aggregate = Arc::new(
+            HashAggregateExec::try_new(
+                AggregateStrategy::Hash,
+                AggregateMode::Full,
+                vec![(col("key"), "key".to_string())],
+                aggr_expr,
+                input.clone(),
+                input_schema,
+            )
+            .unwrap(),
+        );
+        let expected = collect(aggregate.clone()).await.unwrap();
+
+        // A limit of 0 spills every batch.
+        for memory_limit in vec![usize::MAX, 0] {
+            let merge = Arc::new(ParallelMergeExec::new(
+                aggregate.clone(),
+                vec![col("key")],
+                input.clone(),
+                ParallelMergeOptions {
+                    partitions: 3,
+                    memory_limit,
+                    scratch: scratch.clone(),
+                },
+            ));
+            let result = collect(merge).await.unwrap();
+            assert_eq!(sorted_rows(&result), sorted_rows(&expected));
+            assert_eq!(sorted_rows(&result).len(), 50);
+            assert_eq!(scratch.used(), 0);
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    fn sorted_rows(batches: &[RecordBatch]) -> Vec<(String, i64, u64)> {
+        let mut rows = Vec::new();
+        for b in batches {
+            let keys = b.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+            let sums = b.column(1).as_any().downcast_ref::<Int64Array>().unwrap();
+            let counts = b
+                .column(2)
+                .as_any()
+                .downcast_ref::<arrow::array::UInt64Array>()
+                .unwrap();
+            for i in 0..b.num_rows() {
+                rows.push((keys.value(i).to_string(), sums.value(i), counts.value(i)));
+            }
+        }
+        rows.sort();
+        rows
+    }
+}
@@ -1,6 +1,9 @@
-use crate::table::{cmp_same_types, TableValue};
+use crate::queryplanner::stored_types::{cidr_range, parse_cidr, parse_inet, parse_uuid};
+use crate::sql::timestamp_from_string;
+use crate::table::{cmp_same_types, TableValue, TimestampValue};
 use arrow::datatypes::{DataType, Schema};
 use datafusion::logical_plan::{Expr, Operator};
+use datafusion::physical_plan::functions::BuiltinScalarFunction;
 use datafusion::scalar::ScalarValue;
 use std::cmp::Ordering;
 
@@ -43,6 +46,11 @@ impl PartitionFilter {
             (None, None) => true,
         }
     }
+
+    /// Converts a literal compared with a column of type `t` the same way the filters do.
+    pub fn literal_value(v: &ScalarValue, t: &DataType) -> Option<TableValue> {
+        Builder::scalar_to_value(v, t)
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -123,6 +131,9 @@ struct Builder<'a> {
 impl Builder<'_> {
     #[must_use]
     fn extract_filter(&self, e: &Expr, mut r: Vec<MinMaxCondition>) -> Vec<MinMaxCondition> {
+        if let Some(e) = Self::stored_value_filter(e) {
+            return self.extract_filter(&e, r);
+        }
         match e {
             Expr::BinaryExpr {
                 left: box Expr::Column(name, alias),
@@ -298,12 +309,7 @@ impl Builder<'_> {
         value: &Expr,
     ) -> Option<ColumnStat> {
         // TODO: fold constant expressions.
-        let scalar;
-        if let Expr::Literal(s) = value {
-            scalar = s;
-        } else {
-            return None;
-        }
+        let scalar = Self::literal(value)?;
 
         let field = datafusion::physical_plan::expressions::Column::new_with_alias(
             col_name,
@@ -314,7 +320,7 @@ impl Builder<'_> {
 
         // TODO: all the other types. For now assume strings and numbers.
         let limit_val;
-        if let Some(v) = Self::scalar_to_value(scalar, field.data_type()) {
+        if let Some(v) = Self::scalar_to_value(&scalar, field.data_type()) {
             limit_val = v;
         } else {
             return None;
@@ -364,10 +370,146 @@ impl Builder<'_> {
         }
     }
 
+    /// Literals, including timestamps written as `to_timestamp('...')` or casts of strings.
+    fn literal(e: &Expr) -> Option<ScalarValue> {
+        match e {
+            Expr::Literal(s) => Some(s.clone()),
+            Expr::ScalarFunction {
+                fun: BuiltinScalarFunction::ToTimestamp,
+                args,
+            } => match args.as_slice() {
+                [Expr::Literal(ScalarValue::Utf8(Some(s)))] => Self::timestamp_literal(s),
+                _ => None,
+            },
+            Expr::Cast {
+                expr: box Expr::Literal(ScalarValue::Utf8(Some(s))),
+                data_type: DataType::Timestamp(_, _),
+            } => Self::timestamp_literal(s),
+            _ => None,
+        }
+    }
+
+    /// Filters on values converted for queries, see [crate::queryplanner::stored_types], as
+    /// filters on the stored values.
+    fn stored_value_filter(e: &Expr) -> Option<Expr> {
+        match e {
+            Expr::BinaryExpr { left, op, right } if Self::is_comparison(*op) => {
+                if let Some((c, v)) = Self::stored_value(left, right, *op) {
+                    return Some(Expr::BinaryExpr {
+                        left: Box::new(c),
+                        op: *op,
+                        right: Box::new(v),
+                    });
+                }
+                let (c, v) = Self::stored_value(right, left, *op)?;
+                Some(Expr::BinaryExpr {
+                    left: Box::new(v),
+                    op: *op,
+                    right: Box::new(c),
+                })
+            }
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let mut column = None;
+                let mut values = Vec::with_capacity(list.len());
+                for l in list {
+                    let (c, v) = Self::stored_value(expr, l, Operator::Eq)?;
+                    column = Some(c);
+                    values.push(v);
+                }
+                Some(Expr::InList {
+                    expr: Box::new(column?),
+                    list: values,
+                    negated: *negated,
+                })
+            }
+            // Addresses of a network are a range of the stored values.
+            Expr::ScalarUDF { fun, args } if fun.name == "IP_IN_CIDR" => match args.as_slice() {
+                [Expr::ScalarUDF { fun, args }, Expr::Literal(ScalarValue::Utf8(Some(cidr)))]
+                    if fun.name == "IP_TO_STRING" =>
+                {
+                    let c = match args.as_slice() {
+                        [c @ Expr::Column(..)] => c,
+                        _ => return None,
+                    };
+                    let (first, last) = cidr_range(cidr).ok()?;
+                    let bound = |op, v| Expr::BinaryExpr {
+                        left: Box::new(c.clone()),
+                        op,
+                        right: Box::new(Expr::Literal(ScalarValue::Binary(Some(v)))),
+                    };
+                    Some(Expr::BinaryExpr {
+                        left: Box::new(bound(Operator::GtEq, first)),
+                        op: Operator::And,
+                        right: Box::new(bound(Operator::LtEq, last)),
+                    })
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Column with the stored values of `converted` and the stored form of `literal`.
+    fn stored_value(converted: &Expr, literal: &Expr, op: Operator) -> Option<(Expr, Expr)> {
+        let s = match literal {
+            Expr::Literal(ScalarValue::Utf8(Some(s))) => s,
+            _ => return None,
+        };
+        match converted {
+            Expr::ScalarUDF { fun, args } if fun.name == "UUID_TO_STRING" => {
+                match args.as_slice() {
+                    [c @ Expr::Column(..)] => Some((
+                        c.clone(),
+                        Expr::Literal(ScalarValue::Binary(Some(parse_uuid(s).ok()?))),
+                    )),
+                    _ => None,
+                }
+            }
+            // Text of IP addresses is not ordered as the addresses.
+            Expr::ScalarUDF { fun, args } if fun.name == "IP_TO_STRING" && op == Operator::Eq => {
+                match args.as_slice() {
+                    [c @ Expr::Column(..)] => Some((
+                        c.clone(),
+                        Expr::Literal(ScalarValue::Binary(Some(
+                            parse_inet(s).or_else(|_| parse_cidr(s)).ok()?,
+                        ))),
+                    )),
+                    _ => None,
+                }
+            }
+            // Positions of enum values are not ordered as the values.
+            Expr::Case {
+                expr: Some(box c @ Expr::Column(..)),
+                when_then_expr,
+                else_expr: None,
+            } if op == Operator::Eq => {
+                let (position, _) = when_then_expr.iter().find(|(_, v)| match v.as_ref() {
+                    Expr::Literal(ScalarValue::Utf8(Some(v))) => v == s,
+                    _ => false,
+                })?;
+                Some((c.clone(), position.as_ref().clone()))
+            }
+            _ => None,
+        }
+    }
+
+    fn timestamp_literal(s: &str) -> Option<ScalarValue> {
+        let nanos = timestamp_from_string(s).ok()?.get_time_stamp();
+        Some(ScalarValue::TimestampNanosecond(Some(nanos)))
+    }
+
     fn min_value(t: &DataType) -> Option<TableValue> {
         match t {
             t if Self::is_signed_int(t) => Some(TableValue::Int(i64::min_value())),
             DataType::Utf8 => Some(TableValue::String("".to_string())),
+            DataType::Binary => Some(TableValue::Bytes(Vec::new())),
+            DataType::Timestamp(_, _) => {
+                Some(TableValue::Timestamp(TimestampValue::new(i64::min_value())))
+            }
             _ => None,
             // TODO: more data types
         }
@@ -397,11 +539,34 @@ impl Builder<'_> {
             t if Self::is_signed_int(t) => Self::extract_signed_int(v),
             DataType::Boolean => Self::extract_bool(v),
             DataType::Utf8 => Self::extract_string(v),
+            DataType::Timestamp(_, _) => Self::extract_timestamp(v),
+            DataType::Binary => Self::extract_bytes(v),
             _ => None,
             // TODO: more data types
         }
     }
 
+    fn extract_timestamp(v: &ScalarValue) -> Option<TableValue> {
+        let nanos = match v {
+            ScalarValue::TimestampSecond(Some(v)) => v.checked_mul(1_000_000_000)?,
+            ScalarValue::TimestampMillisecond(Some(v)) => v.checked_mul(1_000_000)?,
+            ScalarValue::TimestampMicrosecond(Some(v)) => v.checked_mul(1000)?,
+            ScalarValue::TimestampNanosecond(Some(v)) => *v,
+            ScalarValue::Utf8(Some(s)) | ScalarValue::LargeUtf8(Some(s)) => {
+                timestamp_from_string(s).ok()?.get_time_stamp()
+            }
+            _ => return None,
+        };
+        Some(TableValue::Timestamp(TimestampValue::new(nanos)))
+    }
+
+    fn extract_bytes(v: &ScalarValue) -> Option<TableValue> {
+        match v {
+            ScalarValue::Binary(Some(b)) => Some(TableValue::Bytes(b.clone())),
+            _ => None,
+        }
+    }
+
     fn extract_bool(v: &ScalarValue) -> Option<TableValue> {
         match v {
             ScalarValue::Boolean(v) => v.as_ref().map(|v| TableValue::Boolean(*v)),
@@ -507,8 +672,9 @@ impl Builder<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::queryplanner::udfs::{scalar_kind_by_name, scalar_udf_by_kind};
     use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
-    use arrow::datatypes::Field;
+    use arrow::datatypes::{Field, TimeUnit};
     use datafusion::catalog::TableReference;
     use datafusion::datasource::TableProvider;
     use datafusion::logical_plan::ToDFSchema;
@@ -639,6 +805,132 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_timestamps() {
+        let s = schema(&[("a", DataType::Timestamp(TimeUnit::Microsecond, None))]);
+        let extract = |sql| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+
+        let ts = |nanos| Some(TableValue::Timestamp(TimestampValue::new(nanos)));
+        let nanos = 1_609_459_200_000_123_000;
+        assert_eq!(
+            extract("a >= to_timestamp('2021-01-01T00:00:00.000123Z')").min_max,
+            vec![MinMaxCondition {
+                min: vec![ts(nanos)],
+                max: vec![None],
+            }]
+        );
+        assert_eq!(
+            extract("a < CAST('2021-01-01 00:00:00.000123 UTC' AS TIMESTAMP)").min_max,
+            vec![MinMaxCondition {
+                min: vec![None],
+                max: vec![ts(nanos)],
+            }]
+        );
+        assert_eq!(
+            extract("a = '2021-01-01T00:00:00.000123Z'").min_max,
+            vec![MinMaxCondition {
+                min: vec![ts(nanos)],
+                max: vec![ts(nanos)],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_uuids() {
+        let s = schema(&[("a", DataType::Binary)]);
+        let extract = |sql: &str| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+
+        let id = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let bytes = || Some(TableValue::Bytes(parse_uuid(id).unwrap()));
+        assert_eq!(
+            extract(&format!("UUID_TO_STRING(a) = '{}'", id)).min_max,
+            vec![MinMaxCondition {
+                min: vec![bytes()],
+                max: vec![bytes()],
+            }]
+        );
+        assert_eq!(
+            extract(&format!("UUID_TO_STRING(a) IN ('{}')", id)).min_max,
+            vec![MinMaxCondition {
+                min: vec![bytes()],
+                max: vec![bytes()],
+            }]
+        );
+        assert_eq!(
+            extract(&format!("UUID_TO_STRING(a) >= '{}'", id)).min_max,
+            vec![MinMaxCondition {
+                min: vec![bytes()],
+                max: vec![None],
+            }]
+        );
+        // Not a UUID, compared as a string.
+        assert_eq!(extract("UUID_TO_STRING(a) >= '67e5'").min_max, vec![]);
+    }
+
+    #[test]
+    fn test_ips() {
+        let s = schema(&[("a", DataType::Binary)]);
+        let extract = |sql: &str| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+
+        let ip = || Some(TableValue::Bytes(parse_inet("10.1.2.3").unwrap()));
+        assert_eq!(
+            extract("IP_TO_STRING(a) = '10.1.2.3'").min_max,
+            vec![MinMaxCondition {
+                min: vec![ip()],
+                max: vec![ip()],
+            }]
+        );
+        let network = || Some(TableValue::Bytes(parse_cidr("10.0.0.0/8").unwrap()));
+        assert_eq!(
+            extract("IP_TO_STRING(a) IN ('10.0.0.0/8')").min_max,
+            vec![MinMaxCondition {
+                min: vec![network()],
+                max: vec![network()],
+            }]
+        );
+        let (first, last) = cidr_range("10.0.0.0/8").unwrap();
+        assert_eq!(
+            extract("IP_IN_CIDR(IP_TO_STRING(a), '10.0.0.0/8')").min_max,
+            vec![MinMaxCondition {
+                min: vec![Some(TableValue::Bytes(first))],
+                max: vec![Some(TableValue::Bytes(last))],
+            }]
+        );
+        // Text of addresses is not ordered as the addresses.
+        assert_eq!(extract("IP_TO_STRING(a) >= '10.1.2.3'").min_max, vec![]);
+    }
+
+    #[test]
+    fn test_enums() {
+        let s = schema(&[("a", DataType::Int64)]);
+        let extract = |sql: &str| PartitionFilter::extract(&s, &[parse(sql, &s)]);
+
+        let plan = "CASE a WHEN 0 THEN 'free' WHEN 1 THEN 'pro' END";
+        assert_eq!(
+            extract(&format!("{} = 'pro'", plan)).min_max,
+            vec![MinMaxCondition {
+                min: vec![Some(TableValue::Int(1))],
+                max: vec![Some(TableValue::Int(1))],
+            }]
+        );
+        assert_eq!(
+            extract(&format!("{} IN ('free', 'pro')", plan)).min_max,
+            vec![
+                MinMaxCondition {
+                    min: vec![Some(TableValue::Int(0))],
+                    max: vec![Some(TableValue::Int(0))],
+                },
+                MinMaxCondition {
+                    min: vec![Some(TableValue::Int(1))],
+                    max: vec![Some(TableValue::Int(1))],
+                }
+            ]
+        );
+        // Positions are not ordered as the values.
+        assert_eq!(extract(&format!("{} < 'pro'", plan)).min_max, vec![]);
+        assert_eq!(extract(&format!("{} = 'unknown'", plan)).min_max, vec![]);
+    }
+
     #[test]
     fn test_bools() {
         let s = schema(&[("a", DataType::Boolean)]);
@@ -1116,8 +1408,9 @@ mod tests {
             None
         }
 
-        fn get_function_meta(&self, _name: &str) -> Option<Arc<ScalarUDF>> {
-            None
+        fn get_function_meta(&self, name: &str) -> Option<Arc<ScalarUDF>> {
+            let kind = scalar_kind_by_name(&name.to_uppercase())?;
+            Some(Arc::new(scalar_udf_by_kind(kind).descriptor()))
         }
 
         fn get_aggregate_meta(&self, _name: &str) -> Option<Arc<AggregateUDF>> {
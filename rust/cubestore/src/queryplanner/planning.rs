@@ -24,7 +24,9 @@ use arrow::datatypes::Field;
 use async_trait::async_trait;
 use datafusion::error::DataFusionError;
 use datafusion::execution::context::ExecutionContextState;
-use datafusion::logical_plan::{DFSchemaRef, Expr, LogicalPlan, UserDefinedLogicalNode};
+use datafusion::logical_plan::{
+    DFSchemaRef, Expr, JoinType, LogicalPlan, PlanVisitor, UserDefinedLogicalNode,
+};
 use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::planner::ExtensionPlanner;
 use datafusion::physical_plan::{
@@ -34,15 +36,17 @@ use flatbuffers::bitflags::_core::any::Any;
 use flatbuffers::bitflags::_core::fmt::Formatter;
 use itertools::Itertools;
 
-use crate::cluster::Cluster;
+use crate::cluster::{index_row_count, is_replicated_table, Cluster};
 use crate::metastore::table::{Table, TablePath};
 use crate::metastore::{Chunk, IdRow, Index, MetaStore, Partition, Schema};
+use crate::queryplanner::index_advisor::IndexAdvisor;
 use crate::queryplanner::optimizations::rewrite_plan::{rewrite_plan, PlanRewriter};
 use crate::queryplanner::partition_filter::PartitionFilter;
 use crate::queryplanner::query_executor::{ClusterSendExec, CubeTable};
 use crate::queryplanner::serialized_plan::{IndexSnapshot, PartitionSnapshot, SerializedPlan};
 use crate::queryplanner::topk::{materialize_topk, plan_topk, ClusterAggregateTopK};
 use crate::queryplanner::CubeTableLogical;
+use crate::util::id_set::IdSet;
 use crate::CubeError;
 
 #[cfg(test)]
@@ -50,13 +54,15 @@ pub async fn choose_index(
     p: &LogicalPlan,
     metastore: &dyn PlanIndexStore,
 ) -> Result<(LogicalPlan, Vec<IndexSnapshot>), DataFusionError> {
-    choose_index_ext(p, metastore, true).await
+    choose_index_ext(p, metastore, true, None, 0).await
 }
 
 pub async fn choose_index_ext(
     p: &LogicalPlan,
     metastore: &dyn PlanIndexStore,
     enable_topk: bool,
+    index_advisor: Option<&IndexAdvisor>,
+    replicated_table_max_rows: u64,
 ) -> Result<(LogicalPlan, Vec<IndexSnapshot>), DataFusionError> {
     // Prepare information to choose the index.
     let mut collector = CollectConstraints::default();
@@ -79,7 +85,9 @@ pub async fn choose_index_ext(
         .await?;
     assert_eq!(tables.len(), collector.constraints.len());
     let mut indices = Vec::new();
+    let mut table_indices = Vec::new();
     for (c, inputs) in collector.constraints.iter().zip(tables) {
+        table_indices.push(inputs.2.clone());
         indices.push(pick_index(c, inputs.0, inputs.1, inputs.2).await?)
     }
     let partitions = metastore
@@ -93,7 +101,38 @@ pub async fn choose_index_ext(
         .zip(collector.constraints.iter())
         .zip(partitions)
     {
-        i.partitions = pick_partitions(i, c, ps)?
+        i.replicated = is_replicated_table(index_row_count(&ps), replicated_table_max_rows);
+        i.partitions = pick_partitions(i, c, &ps)?
+    }
+    if let Some(advisor) = index_advisor {
+        for ((i, c), table_indices) in indices
+            .iter()
+            .zip(collector.constraints.iter())
+            .zip(table_indices.iter())
+        {
+            let group_by: &[String] = match &c.sort_on {
+                Some(sort) if !sort.required => &sort.sort_on,
+                _ => &[],
+            };
+            let rows_scanned = i
+                .partitions
+                .iter()
+                .map(|p| {
+                    p.partition.get_row().main_table_row_count()
+                        + p.chunks
+                            .iter()
+                            .map(|c| c.get_row().get_row_count())
+                            .sum::<u64>()
+                })
+                .sum();
+            advisor.record(
+                &c.table_name,
+                &c.filters,
+                group_by,
+                table_indices,
+                rows_scanned,
+            );
+        }
     }
 
     // We have enough information to finalize the logical plan.
@@ -108,6 +147,105 @@ pub async fn choose_index_ext(
     Ok((plan, indices))
 }
 
+/// Partitions of the index chosen for a table scan and the ones pruned by filters of the scan, see
+/// [crate::queryplanner::explain_pruning].
+#[derive(Debug, Clone)]
+pub struct PruningReport {
+    /// `schema.table`.
+    pub table_name: String,
+    pub index_name: String,
+    pub partitions: u64,
+    pub pruned_partitions: u64,
+    pub chunks: u64,
+    pub pruned_chunks: u64,
+    /// Filters that prune partitions on their own, with the number of partitions each prunes.
+    pub pruning_filters: Vec<(String, u64)>,
+    /// Filters that prune nothing on their own.
+    pub other_filters: Vec<String>,
+}
+
+/// Chooses indexes as [choose_index_ext] does and reports partitions pruned by filters of each
+/// table scan. Sampling of partitions is not applied.
+pub async fn explain_pruning(
+    p: &LogicalPlan,
+    metastore: &dyn PlanIndexStore,
+) -> Result<Vec<PruningReport>, DataFusionError> {
+    let mut collector = CollectConstraints::default();
+    rewrite_plan(p, &None, &mut collector)?;
+
+    let tables = metastore
+        .get_tables_with_indexes(
+            collector
+                .constraints
+                .iter()
+                .map(|c| {
+                    let mut parts = c.table_name.splitn(2, ".");
+                    let schema = parts.next().unwrap();
+                    let table = parts.next().unwrap();
+                    (schema.to_string(), table.to_string())
+                })
+                .collect_vec(),
+        )
+        .await?;
+    let mut indices = Vec::new();
+    for (c, inputs) in collector.constraints.iter().zip(tables) {
+        indices.push(pick_index(c, inputs.0, inputs.1, inputs.2).await?)
+    }
+    let partitions = metastore
+        .get_active_partitions_and_chunks_by_index_id_for_select(
+            indices.iter().map(|i| i.index.get_id()).collect_vec(),
+        )
+        .await?;
+    let mut reports = Vec::new();
+    for ((i, c), ps) in indices
+        .iter()
+        .zip(collector.constraints.iter())
+        .zip(partitions)
+    {
+        let schema = partition_filter_schema(&i.index);
+        let can_match = |filter: &PartitionFilter, p: &IdRow<Partition>| {
+            filter.can_match(
+                p.get_row()
+                    .get_min_val()
+                    .as_ref()
+                    .map(|r| r.values().as_slice()),
+                p.get_row()
+                    .get_max_val()
+                    .as_ref()
+                    .map(|r| r.values().as_slice()),
+            )
+        };
+        let filter = PartitionFilter::extract(&schema, &c.filters);
+        let mut report = PruningReport {
+            table_name: c.table_name.clone(),
+            index_name: i.index.get_row().get_name().clone(),
+            partitions: ps.len() as u64,
+            pruned_partitions: 0,
+            chunks: ps.iter().map(|(_, chunks)| chunks.len() as u64).sum(),
+            pruned_chunks: 0,
+            pruning_filters: Vec::new(),
+            other_filters: Vec::new(),
+        };
+        for (p, chunks) in ps.iter() {
+            if !can_match(&filter, p) {
+                report.pruned_partitions += 1;
+                report.pruned_chunks += chunks.len() as u64;
+            }
+        }
+        for f in c.filters.iter() {
+            let filter = PartitionFilter::extract(&schema, std::slice::from_ref(f));
+            let pruned = ps.iter().filter(|(p, _)| !can_match(&filter, p)).count() as u64;
+            if pruned != 0 {
+                report.pruning_filters.push((format!("{:?}", f), pruned));
+            } else {
+                report.other_filters.push(format!("{:?}", f));
+            }
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
 #[async_trait]
 pub trait PlanIndexStore: Send + Sync {
     async fn get_tables_with_indexes(
@@ -117,7 +255,7 @@ pub trait PlanIndexStore: Send + Sync {
     async fn get_active_partitions_and_chunks_by_index_id_for_select(
         &self,
         index_id: Vec<u64>,
-    ) -> Result<Vec<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>, CubeError>;
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError>;
 }
 
 #[async_trait]
@@ -132,7 +270,7 @@ impl<'a> PlanIndexStore for &'a dyn MetaStore {
     async fn get_active_partitions_and_chunks_by_index_id_for_select(
         &self,
         index_id: Vec<u64>,
-    ) -> Result<Vec<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>, CubeError> {
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError> {
         MetaStore::get_active_partitions_and_chunks_by_index_id_for_select(*self, index_id).await
     }
 }
@@ -148,6 +286,7 @@ struct IndexConstraints {
     table_name: String,
     projection: Option<Vec<usize>>,
     filters: Vec<Expr>,
+    sample_percent: Option<f64>,
 }
 
 #[derive(Default)]
@@ -171,12 +310,16 @@ impl PlanRewriter for CollectConstraints {
                 source,
                 ..
             } => {
-                assert!(source.as_any().is::<CubeTableLogical>());
+                let table = source
+                    .as_any()
+                    .downcast_ref::<CubeTableLogical>()
+                    .expect("expected CubeTableLogical");
                 self.constraints.push(IndexConstraints {
                     sort_on: c.clone(),
                     table_name: table_name.clone(),
                     projection: projection.clone(),
                     filters: filters.clone(),
+                    sample_percent: table.sample_percent,
                 })
             }
             _ => {}
@@ -218,7 +361,11 @@ impl PlanRewriter for CollectConstraints {
         _: &Option<SortColumns>,
     ) -> Option<Option<SortColumns>> {
         let join_on;
-        if let LogicalPlan::Join { on, .. } = join {
+        if let LogicalPlan::Join { on, right, .. } = join {
+            if !has_table_scan(right) {
+                // Joins with inline data do not need sorted inputs.
+                return Some(None);
+            }
             join_on = on;
         } else {
             panic!("expected join node");
@@ -238,7 +385,10 @@ impl PlanRewriter for CollectConstraints {
         _c: &Self::Context,
     ) -> Option<Self::Context> {
         let join_on;
-        if let LogicalPlan::Join { on, .. } = join {
+        if let LogicalPlan::Join { on, left, .. } = join {
+            if !has_table_scan(left) {
+                return Some(None);
+            }
             join_on = on;
         } else {
             panic!("expected join node");
@@ -305,12 +455,13 @@ impl ChooseIndex<'_> {
                     snapshot.clone(),
                     // Filled by workers
                     HashMap::new(),
-                    HashSet::new(),
+                    IdSet::new(),
                 )?);
 
                 return Ok(ClusterSendNode {
                     input: Arc::new(p),
                     snapshots: vec![vec![snapshot]],
+                    inner_join: false,
                 }
                 .into_plan());
             }
@@ -330,6 +481,8 @@ async fn pick_index(
 
     let mut indices = indices.into_iter();
     let default_index = indices.next().expect("no default index");
+    // Rows are still being copied to indexes that are not ready.
+    let indices = indices.filter(|i| i.get_row().is_ready());
     let (index, sort_on) = if let Some(projection_column_indices) = &c.projection {
         let projection_columns = CubeTable::project_to_table(&table, &projection_column_indices);
         if let Some((index, _)) = indices
@@ -398,6 +551,13 @@ async fn pick_index(
         (default_index, None)
     };
 
+    let sort_on = sort_on.map(|(cols, required)| {
+        if required {
+            cols.clone()
+        } else {
+            in_index_order(cols, &index)
+        }
+    });
     Ok(IndexSnapshot {
         index,
         partitions: Vec::new(), // filled with results of `pick_partitions` later.
@@ -405,14 +565,32 @@ async fn pick_index(
             table,
             schema: Arc::new(schema),
         },
-        sort_on: sort_on.map(|(cols, _)| cols.clone()),
+        sort_on,
+        replicated: false, // set along with partitions.
     })
 }
 
+/// Group-by columns can go in any order, but sorted aggregation and merges of partitions need
+/// them in the order of the index sort key.
+fn in_index_order(columns: &[String], index: &IdRow<Index>) -> Vec<String> {
+    columns
+        .iter()
+        .sorted_by_key(|c| {
+            index
+                .get_row()
+                .get_columns()
+                .iter()
+                .position(|ic| ic.get_name() == *c)
+                .unwrap_or(usize::MAX)
+        })
+        .cloned()
+        .collect()
+}
+
 fn pick_partitions(
     i: &IndexSnapshot,
     c: &IndexConstraints,
-    partitions: Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>,
+    partitions: &[(IdRow<Partition>, Vec<IdRow<Chunk>>)],
 ) -> Result<Vec<PartitionSnapshot>, DataFusionError> {
     let partition_filter = PartitionFilter::extract(&partition_filter_schema(&i.index), &c.filters);
     log::trace!("Extracted partition filter is {:?}", partition_filter);
@@ -420,7 +598,7 @@ fn pick_partitions(
     let mut pruned_partitions = 0;
 
     let mut partition_snapshots = Vec::new();
-    for (partition, chunks) in partitions.into_iter() {
+    for (partition, chunks) in partitions.iter() {
         let min_row = partition
             .get_row()
             .get_min_val()
@@ -436,8 +614,17 @@ fn pick_partitions(
             pruned_partitions += 1;
             continue;
         }
+        if let Some(percent) = c.sample_percent {
+            if percent <= rand::random::<f64>() * 100. {
+                pruned_partitions += 1;
+                continue;
+            }
+        }
 
-        partition_snapshots.push(PartitionSnapshot { chunks, partition });
+        partition_snapshots.push(PartitionSnapshot {
+            chunks: chunks.clone(),
+            partition: partition.clone(),
+        });
     }
     log::trace!(
         "Pruned {} of {} partitions",
@@ -464,6 +651,9 @@ fn partition_filter_schema(index: &IdRow<Index>) -> arrow::datatypes::Schema {
 pub struct ClusterSendNode {
     pub input: Arc<LogicalPlan>,
     pub snapshots: Vec<Vec<IndexSnapshot>>,
+    /// Set when `snapshots` are the two sides of an inner join on their `sort_on` columns. Pairs
+    /// of partitions that can't have matching rows are not sent to workers then.
+    pub inner_join: bool,
 }
 
 impl ClusterSendNode {
@@ -510,12 +700,14 @@ impl UserDefinedLogicalNode for ClusterSendNode {
         Arc::new(ClusterSendNode {
             input: Arc::new(inputs[0].clone()),
             snapshots: self.snapshots.clone(),
+            inner_join: self.inner_join,
         })
     }
 }
 
 fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionError> {
     let snapshots;
+    let mut inner_join = false;
     match &mut p {
         // These nodes have no children, return unchanged.
         LogicalPlan::TableScan { .. }
@@ -539,12 +731,19 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
                 return Ok(p);
             }
             snapshots = send.snapshots.clone();
+            inner_join = send.inner_join;
             // Code after 'match' will wrap `p` in ClusterSend.
             *input = send.input.clone();
         }
         LogicalPlan::Union { inputs, .. } => {
+            // Inline data, e.g. from VALUES lists, is computed on the router.
+            if !inputs.iter().any(has_table_scan) {
+                return Ok(p);
+            }
             let mut union_snapshots = Vec::new();
-            for i in inputs {
+            let mut union_inputs = Vec::with_capacity(inputs.len());
+            let mut only_empty_inputs = true;
+            for i in inputs.iter() {
                 let send;
                 if let Some(s) = try_extract_cluster_send(i) {
                     send = s;
@@ -553,45 +752,109 @@ fn pull_up_cluster_send(mut p: LogicalPlan) -> Result<LogicalPlan, DataFusionErr
                         "UNION argument not supported".to_string(),
                     ));
                 }
+                // Branches that read no partitions produce no rows, e.g. rollups of periods
+                // excluded by filters. Keep at least one branch to preserve the schema.
+                let is_empty = send
+                    .snapshots
+                    .iter()
+                    .flatten()
+                    .all(|s| s.partitions.is_empty());
+                if is_empty && !union_inputs.is_empty() {
+                    continue;
+                }
+                if !is_empty && only_empty_inputs {
+                    union_inputs.clear();
+                    union_snapshots.clear();
+                    only_empty_inputs = false;
+                }
                 union_snapshots.extend(send.snapshots.concat());
                 // Code after 'match' will wrap `p` in ClusterSend.
-                *i = send.input.as_ref().clone();
+                union_inputs.push(send.input.as_ref().clone());
             }
+            *inputs = union_inputs;
             snapshots = vec![union_snapshots];
         }
-        LogicalPlan::Join { left, right, .. } => {
-            let lsend;
-            let rsend;
-            if let (Some(l), Some(r)) = (
+        LogicalPlan::Join {
+            left,
+            right,
+            join_type,
+            ..
+        } => {
+            match (
                 try_extract_cluster_send(left),
                 try_extract_cluster_send(right),
             ) {
-                lsend = l;
-                rsend = r;
-            } else {
-                return Err(DataFusionError::Plan(
-                    "JOIN argument not supported".to_string(),
-                ));
+                (Some(lsend), Some(rsend)) => {
+                    snapshots = lsend
+                        .snapshots
+                        .iter()
+                        .chain(rsend.snapshots.iter())
+                        .cloned()
+                        .collect();
+                    inner_join = matches!(join_type, JoinType::Inner)
+                        && lsend.snapshots.len() == 1
+                        && rsend.snapshots.len() == 1;
+                    // Code after 'match' will wrap `p` in ClusterSend.
+                    *left = lsend.input.clone();
+                    *right = rsend.input.clone();
+                }
+                // Inline data, e.g. from VALUES lists, is sent to workers along with the plan.
+                // Rows of the inline side must not be produced without matches, as every worker
+                // would produce them.
+                (Some(lsend), None)
+                    if !has_table_scan(right)
+                        && matches!(join_type, JoinType::Inner | JoinType::Left) =>
+                {
+                    snapshots = lsend.snapshots.clone();
+                    inner_join = lsend.inner_join;
+                    *left = lsend.input.clone();
+                }
+                (None, Some(rsend))
+                    if !has_table_scan(left)
+                        && matches!(join_type, JoinType::Inner | JoinType::Right) =>
+                {
+                    snapshots = rsend.snapshots.clone();
+                    inner_join = rsend.inner_join;
+                    *right = rsend.input.clone();
+                }
+                _ => {
+                    return Err(DataFusionError::Plan(
+                        "JOIN argument not supported".to_string(),
+                    ));
+                }
             }
-            snapshots = lsend
-                .snapshots
-                .iter()
-                .chain(rsend.snapshots.iter())
-                .cloned()
-                .collect();
-            // Code after 'match' will wrap `p` in ClusterSend.
-            *left = lsend.input.clone();
-            *right = rsend.input.clone();
         }
     }
 
     Ok(ClusterSendNode {
         input: Arc::new(p),
         snapshots,
+        inner_join,
     }
     .into_plan())
 }
 
+pub(crate) fn has_table_scan(p: &LogicalPlan) -> bool {
+    struct Visitor {
+        seen_scans: bool,
+    }
+    impl PlanVisitor for Visitor {
+        type Error = ();
+
+        fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<bool, Self::Error> {
+            if let LogicalPlan::TableScan { .. } = plan {
+                self.seen_scans = true;
+                return Ok(false);
+            }
+            Ok(true)
+        }
+    }
+
+    let mut v = Visitor { seen_scans: false };
+    p.accept(&mut v).expect("no failures possible");
+    v.seen_scans
+}
+
 pub struct CubeExtensionPlanner {
     pub cluster: Option<Arc<dyn Cluster>>,
     pub serialized_plan: Arc<SerializedPlan>,
@@ -610,6 +873,7 @@ impl ExtensionPlanner for CubeExtensionPlanner {
             Ok(Some(self.plan_cluster_send(
                 input.clone(),
                 &cs.snapshots,
+                cs.inner_join,
                 cs.schema().clone(),
                 false,
                 usize::MAX,
@@ -629,6 +893,7 @@ impl CubeExtensionPlanner {
         &self,
         input: Arc<dyn ExecutionPlan>,
         snapshots: &Vec<Vec<IndexSnapshot>>,
+        inner_join: bool,
         schema: DFSchemaRef,
         use_streaming: bool,
         max_batch_rows: usize,
@@ -643,6 +908,7 @@ impl CubeExtensionPlanner {
                 c.clone(),
                 self.serialized_plan.clone(),
                 snapshots.clone(),
+                inner_join,
                 input,
                 use_streaming,
             )))
@@ -746,8 +1012,10 @@ pub mod tests {
     use crate::metastore::{Chunk, Column, ColumnType, IdRow, Index, Partition, Schema};
     use crate::queryplanner::planning::{choose_index, PlanIndexStore};
     use crate::queryplanner::pretty_printers::PPOptions;
+    use crate::queryplanner::serialized_plan::SerializedPlan;
     use crate::queryplanner::{pretty_printers, CubeTableLogical};
     use crate::sql::parser::{CubeStoreParser, Statement};
+    use crate::table::{Row, TableValue};
     use crate::CubeError;
     use datafusion::catalog::TableReference;
 
@@ -957,6 +1225,80 @@ pub mod tests {
         assert!(!pp.contains("TopK"), "plan contained topk:\n{}", pp);
     }
 
+    #[tokio::test]
+    pub async fn test_worker_plan_partitions() {
+        let mut indices = default_indices();
+        // Customers default index has id 0.
+        indices.partitions.push(Partition::new(0, None, None));
+        indices.partitions.push(Partition::new(0, None, None));
+        indices.partitions.push(Partition::new(0, None, None));
+
+        let plan = initial_plan("SELECT * FROM s.Customers", &indices);
+        let (plan, snapshots) = choose_index(&plan, &indices).await.unwrap();
+        let plan = SerializedPlan::try_new(plan, snapshots).await.unwrap();
+        assert_eq!(plan.index_snapshots()[0].partitions().len(), 3);
+
+        let worker_plan = plan.with_partition_id_to_execute(vec![1].into_iter().collect());
+        let partitions = worker_plan.index_snapshots()[0]
+            .partitions()
+            .iter()
+            .map(|p| p.partition().get_id())
+            .collect_vec();
+        assert_eq!(partitions, vec![1]);
+    }
+
+    #[tokio::test]
+    pub async fn test_prune_union_branches() {
+        let mut indices = TestIndices::default();
+        for name in &["Rollup1", "Rollup2"] {
+            indices.add_table(Table::new(
+                name.to_string(),
+                0,
+                int_columns(&["day", "hits"]),
+                None,
+                None,
+                true,
+                None,
+                None,
+            ));
+        }
+        let bound = |day| Some(Row::new(vec![TableValue::Int(day), TableValue::Int(0)]));
+        // Default indexes of the tables have ids 0 and 1.
+        indices.partitions.push(Partition::new(0, None, bound(100)));
+        indices.partitions.push(Partition::new(1, bound(100), None));
+
+        let query = |day| {
+            format!(
+                "SELECT * FROM s.Rollup1 WHERE day >= {0} \
+                 UNION ALL \
+                 SELECT * FROM s.Rollup2 WHERE day >= {0}",
+                day
+            )
+        };
+        let plan = initial_plan(&query(0), &indices);
+        let plan = choose_index(&plan, &indices).await.unwrap().0;
+        assert_eq!(
+            pretty_printers::pp_plan(&plan),
+            "ClusterSend, indices: [[0, 1]]\
+           \n  Union\
+           \n    Filter\
+           \n      Scan s.Rollup1, source: CubeTable(index: default:0:[0]), fields: *\
+           \n    Filter\
+           \n      Scan s.Rollup2, source: CubeTable(index: default:1:[1]), fields: *"
+        );
+
+        // Branches that can't match the filters are removed.
+        let plan = initial_plan(&query(150), &indices);
+        let plan = choose_index(&plan, &indices).await.unwrap().0;
+        assert_eq!(
+            pretty_printers::pp_plan(&plan),
+            "ClusterSend, indices: [[1]]\
+           \n  Union\
+           \n    Filter\
+           \n      Scan s.Rollup2, source: CubeTable(index: default:1:[1]), fields: *"
+        );
+    }
+
     /// Most tests in this module use this schema.
     fn default_indices() -> TestIndices {
         const SCHEMA: u64 = 0;
@@ -975,6 +1317,8 @@ pub mod tests {
             None,
             None,
             true,
+            None,
+            None,
         ));
         i.indices.push(
             Index::try_new(
@@ -1000,6 +1344,8 @@ pub mod tests {
             None,
             None,
             true,
+            None,
+            None,
         ));
         i.indices.push(
             Index::try_new(
@@ -1027,6 +1373,8 @@ pub mod tests {
             None,
             None,
             true,
+            None,
+            None,
         ));
 
         i
@@ -1117,6 +1465,7 @@ pub mod tests {
                             schema: Arc::new(self.schema()),
                         },
                         schema,
+                        sample_percent: None,
                     })
                 })
         }
@@ -1157,16 +1506,18 @@ pub mod tests {
         async fn get_active_partitions_and_chunks_by_index_id_for_select(
             &self,
             index_id: Vec<u64>,
-        ) -> Result<Vec<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>, CubeError> {
+        ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError> {
             Ok(index_id
                 .iter()
                 .map(|index_id| {
-                    self.partitions
-                        .iter()
-                        .enumerate()
-                        .filter(|(_, p)| p.get_index_id() == *index_id)
-                        .map(|(id, p)| (IdRow::new(id as u64, p.clone()), vec![]))
-                        .collect()
+                    Arc::new(
+                        self.partitions
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, p)| p.get_index_id() == *index_id)
+                            .map(|(id, p)| (IdRow::new(id as u64, p.clone()), vec![]))
+                            .collect(),
+                    )
                 })
                 .collect())
         }
@@ -0,0 +1,753 @@
+//! Pre-aggregations are rollups of a table that are materialized into tables of their schema and
+//! read instead of the source table by queries they can answer, e.g.:
+//!     CREATE PRE_AGGREGATION s.orders_by_day
+//!     WITH (refresh_key = 'SELECT max(updated_at) FROM s.orders',
+//!           partition_granularity = 'month', time_dimension = 'created_at')
+//!     AS SELECT date_trunc('day', created_at) AS day, country, sum(amount) AS amount,
+//!        count(*) AS orders
+//!     FROM s.orders GROUP BY 1, 2
+//! The query must read a single table and group by all columns except `sum`, `count`, `min` and
+//! `max` aggregates, which must be named. [crate::sql::pre_aggregations] builds the tables and
+//! rebuilds them when the refresh key changes.
+//!
+//! A select is answered by a pre-aggregation if it reads the same table with the same filters,
+//! possibly more, and uses source columns only through dimensions and aggregates of the
+//! pre-aggregation, e.g. `SELECT country, sum(amount) AS amount FROM s.orders
+//! WHERE date_trunc('day', created_at) >= to_timestamp('2021-01-01T00:00:00Z') GROUP BY 1`.
+//! Aggregates are computed from the aggregated values, i.e. counts are summed. Expressions are
+//! matched by their text without table qualifiers. Output columns other than plain columns must be
+//! named, so their names don't depend on the rewrite. The rewrite is disabled by
+//! [NO_PRE_AGGREGATIONS_HINT].
+use crate::metastore::pre_aggregation::PreAggregation;
+use crate::metastore::IdRow;
+use crate::queryplanner::collation::function;
+use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use datafusion::sql::parser::Statement as DFStatement;
+use log::warn;
+use sqlparser::ast::{
+    BinaryOperator, Expr, FunctionArg, Ident, OrderByExpr, Query, Select, SelectItem, SetExpr,
+    Statement, TableFactor, TableWithJoins, Value,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Plans the query over source tables, e.g. `/*+ NO_PRE_AGGREGATIONS */ SELECT ...`.
+pub const NO_PRE_AGGREGATIONS_HINT: &str = "NO_PRE_AGGREGATIONS";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregate {
+    Sum,
+    Count,
+    Min,
+    Max,
+}
+
+/// Columns of a pre-aggregation by the text of their source expressions.
+#[derive(Debug)]
+pub struct Rollup {
+    /// Source table as `schema.table`.
+    pub table: String,
+    filters: Vec<String>,
+    dimensions: HashMap<String, Ident>,
+    measures: HashMap<String, (Aggregate, Ident)>,
+}
+
+impl Rollup {
+    pub fn parse(query: &str) -> Result<Rollup, CubeError> {
+        let invalid =
+            |reason: &str| CubeError::user(format!("Pre-aggregation query {}: {}", reason, query));
+        let q = parse_query(query)?;
+        if !q.order_by.is_empty() || q.limit.is_some() || q.offset.is_some() {
+            return Err(invalid("can't have ORDER BY, LIMIT or OFFSET"));
+        }
+        let select = match &q.body {
+            SetExpr::Select(s) if !s.distinct && s.having.is_none() => s,
+            _ => return Err(invalid("must be a select without DISTINCT and HAVING")),
+        };
+        let (table, qualifiers) =
+            single_table(select).ok_or_else(|| invalid("must read a single table"))?;
+        let mut select = (**select).clone();
+        normalize_select(&mut select, &qualifiers);
+
+        let mut dimensions = HashMap::new();
+        let mut measures = HashMap::new();
+        let mut positions = HashMap::new();
+        for (i, item) in select.projection.iter().enumerate() {
+            let (expr, alias) = match item {
+                SelectItem::UnnamedExpr(Expr::Identifier(c)) => (Expr::Identifier(c.clone()), c),
+                SelectItem::ExprWithAlias { expr, alias } => (expr.clone(), alias),
+                _ => return Err(invalid("must name all columns except plain columns")),
+            };
+            match aggregate(&expr) {
+                Some(a) => {
+                    measures.insert(expr.to_string(), (a, alias.clone()));
+                }
+                None => {
+                    positions.insert((i + 1).to_string(), expr.to_string());
+                    dimensions.insert(expr.to_string(), alias.clone());
+                }
+            }
+        }
+        // A row per combination of dimensions, otherwise aggregates of aggregates are wrong.
+        let grouped = select
+            .group_by
+            .iter()
+            .filter_map(|g| match g {
+                Expr::Value(Value::Number(n, _)) => positions.get(n).cloned(),
+                Expr::Identifier(c) => dimensions
+                    .iter()
+                    .find(|(_, alias)| *alias == c)
+                    .map(|(key, _)| key.clone())
+                    .or_else(|| Some(g.to_string())),
+                g => Some(g.to_string()),
+            })
+            .collect::<HashSet<_>>();
+        if grouped != dimensions.keys().cloned().collect() {
+            return Err(invalid(
+                "must group by all columns except sum, count, min and max",
+            ));
+        }
+        Ok(Rollup {
+            table,
+            filters: conjuncts(&select.selection)
+                .iter()
+                .map(|e| e.to_string())
+                .collect(),
+            dimensions,
+            measures,
+        })
+    }
+
+    /// Returns the select reading `relation` instead of the source table and its ORDER BY, or
+    /// [None] if the pre-aggregation can't answer the select.
+    fn rewrite(
+        &self,
+        select: &Select,
+        order_by: &[OrderByExpr],
+        relation: &TableFactor,
+    ) -> Option<(Select, Vec<OrderByExpr>)> {
+        let (table, qualifiers) = single_table(select)?;
+        if table != self.table || select.distinct {
+            return None;
+        }
+        let mut select = select.clone();
+        normalize_select(&mut select, &qualifiers);
+        let mut order_by = order_by.to_vec();
+        for o in order_by.iter_mut() {
+            normalize(&mut o.expr, &qualifiers);
+        }
+
+        let mut filters = Vec::new();
+        let mut missing = self.filters.iter().collect::<HashSet<_>>();
+        for c in conjuncts(&select.selection) {
+            let key = c.to_string();
+            if missing.remove(&key) {
+                continue;
+            }
+            filters.push(self.substitute(&c, false, &[])?);
+        }
+        if !missing.is_empty() {
+            return None;
+        }
+
+        let mut aliases = Vec::new();
+        let mut projection = Vec::new();
+        let mut uses_measures = false;
+        for item in &select.projection {
+            let item = match item {
+                SelectItem::UnnamedExpr(Expr::Identifier(c)) => {
+                    // Plain columns keep their names.
+                    match self.substitute(&Expr::Identifier(c.clone()), false, &[])? {
+                        Expr::Identifier(d) if d == *c => {
+                            SelectItem::UnnamedExpr(Expr::Identifier(d))
+                        }
+                        e => SelectItem::ExprWithAlias {
+                            expr: e,
+                            alias: c.clone(),
+                        },
+                    }
+                }
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    uses_measures |= self.substitute(expr, false, &[]).is_none();
+                    SelectItem::ExprWithAlias {
+                        expr: self.substitute(expr, true, &[])?,
+                        alias: alias.clone(),
+                    }
+                }
+                _ => return None,
+            };
+            if let SelectItem::ExprWithAlias { alias, .. } = &item {
+                aliases.push(alias.value.clone());
+            }
+            projection.push(item);
+        }
+        // Selects without aggregation would return rows of the pre-aggregation.
+        if select.group_by.is_empty() && !uses_measures {
+            return None;
+        }
+        let group_by = select
+            .group_by
+            .iter()
+            .map(|g| match g {
+                Expr::Value(Value::Number(_, _)) => Some(g.clone()),
+                g => self.substitute(g, false, &aliases),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let having = match &select.having {
+            Some(h) => Some(self.substitute(h, true, &aliases)?),
+            None => None,
+        };
+        for o in order_by.iter_mut() {
+            if !matches!(o.expr, Expr::Value(Value::Number(_, _))) {
+                o.expr = self.substitute(&o.expr, true, &aliases)?;
+            }
+        }
+
+        let select = Select {
+            projection,
+            from: vec![TableWithJoins {
+                relation: relation.clone(),
+                joins: Vec::new(),
+            }],
+            selection: filters.into_iter().reduce(|l, r| Expr::BinaryOp {
+                left: Box::new(l),
+                op: BinaryOperator::And,
+                right: Box::new(r),
+            }),
+            group_by,
+            having,
+            ..select
+        };
+        Some((select, order_by))
+    }
+
+    /// Replaces dimensions and, if `measures` is set, aggregates with columns of the
+    /// pre-aggregation. Returns [None] if any other source column is used. Identifiers in
+    /// `aliases` refer to the output columns.
+    fn substitute(&self, e: &Expr, measures: bool, aliases: &[String]) -> Option<Expr> {
+        let key = e.to_string();
+        if let Some(c) = self.dimensions.get(&key) {
+            return Some(Expr::Identifier(c.clone()));
+        }
+        if let Some((aggregate, c)) = self.measures.get(&key) {
+            if !measures {
+                return None;
+            }
+            let column = Expr::Identifier(c.clone());
+            return Some(match aggregate {
+                Aggregate::Sum => function("SUM", vec![column]),
+                // Sum of no rows is null, count is zero.
+                Aggregate::Count => Expr::Case {
+                    operand: None,
+                    conditions: vec![Expr::IsNull(Box::new(function(
+                        "SUM",
+                        vec![column.clone()],
+                    )))],
+                    results: vec![Expr::Value(Value::Number("0".to_string(), false))],
+                    else_result: Some(Box::new(function("SUM", vec![column]))),
+                },
+                Aggregate::Min => function("MIN", vec![column]),
+                Aggregate::Max => function("MAX", vec![column]),
+            });
+        }
+        let sub = |e: &Expr| self.substitute(e, measures, aliases).map(Box::new);
+        Some(match e {
+            Expr::Identifier(c) if aliases.contains(&c.value) => e.clone(),
+            Expr::Value(_) => e.clone(),
+            Expr::BinaryOp { left, op, right } => Expr::BinaryOp {
+                left: sub(left)?,
+                op: op.clone(),
+                right: sub(right)?,
+            },
+            Expr::UnaryOp { op, expr } => Expr::UnaryOp {
+                op: op.clone(),
+                expr: sub(expr)?,
+            },
+            Expr::Nested(expr) => Expr::Nested(sub(expr)?),
+            Expr::Cast { expr, data_type } => Expr::Cast {
+                expr: sub(expr)?,
+                data_type: data_type.clone(),
+            },
+            Expr::IsNull(expr) => Expr::IsNull(sub(expr)?),
+            Expr::IsNotNull(expr) => Expr::IsNotNull(sub(expr)?),
+            Expr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => Expr::Between {
+                expr: sub(expr)?,
+                negated: *negated,
+                low: sub(low)?,
+                high: sub(high)?,
+            },
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } => Expr::InList {
+                expr: sub(expr)?,
+                list: list
+                    .iter()
+                    .map(|e| self.substitute(e, measures, aliases))
+                    .collect::<Option<_>>()?,
+                negated: *negated,
+            },
+            // Other aggregates can't be computed from the pre-aggregation.
+            Expr::Function(f) if aggregate(e).is_none() && f.over.is_none() && !f.distinct => {
+                let mut f = f.clone();
+                for a in f.args.iter_mut() {
+                    match a {
+                        FunctionArg::Unnamed(a) => *a = self.substitute(a, measures, aliases)?,
+                        _ => return None,
+                    }
+                }
+                Expr::Function(f)
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Rewrites selects of the statement to read pre-aggregations with built tables instead of
+/// source tables. Smaller pre-aggregations, i.e. with fewer dimensions, are preferred.
+pub fn use_pre_aggregations(
+    statement: &mut DFStatement,
+    pre_aggregations: &[IdRow<PreAggregation>],
+) -> Result<(), CubeError> {
+    let mut rollups = Vec::new();
+    for p in pre_aggregations {
+        let p = p.get_row();
+        if p.state().partitions.is_empty() {
+            continue;
+        }
+        match Rollup::parse(p.query()) {
+            Ok(r) => rollups.push((r, relation(p)?)),
+            Err(e) => warn!(
+                "Can't use pre-aggregation {}.{}: {}",
+                p.get_schema(),
+                p.get_name(),
+                e
+            ),
+        }
+    }
+    if rollups.is_empty() {
+        return Ok(());
+    }
+    rollups.sort_by_key(|(r, _)| r.dimensions.len());
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        rewrite_query(q, &rollups);
+    }
+    Ok(())
+}
+
+fn rewrite_query(q: &mut Query, rollups: &[(Rollup, TableFactor)]) {
+    let mut order_by = std::mem::replace(&mut q.order_by, Vec::new());
+    rewrite_set_expr(&mut q.body, &mut order_by, rollups);
+    q.order_by = order_by;
+}
+
+fn rewrite_set_expr(
+    e: &mut SetExpr,
+    order_by: &mut Vec<OrderByExpr>,
+    rollups: &[(Rollup, TableFactor)],
+) {
+    match e {
+        SetExpr::Select(s) => {
+            for (rollup, relation) in rollups {
+                if let Some((select, o)) = rollup.rewrite(s, order_by, relation) {
+                    **s = select;
+                    *order_by = o;
+                    return;
+                }
+            }
+            for t in s.from.iter_mut() {
+                rewrite_table_factor(&mut t.relation, rollups);
+                for j in t.joins.iter_mut() {
+                    rewrite_table_factor(&mut j.relation, rollups);
+                }
+            }
+        }
+        SetExpr::Query(q) => rewrite_query(q, rollups),
+        SetExpr::SetOperation { left, right, .. } => {
+            // ORDER BY of the whole operation only refers to its output columns.
+            rewrite_set_expr(left, &mut Vec::new(), rollups);
+            rewrite_set_expr(right, &mut Vec::new(), rollups);
+        }
+        _ => {}
+    }
+}
+
+fn rewrite_table_factor(t: &mut TableFactor, rollups: &[(Rollup, TableFactor)]) {
+    match t {
+        TableFactor::Derived { subquery, .. } => rewrite_query(subquery, rollups),
+        TableFactor::NestedJoin(t) => {
+            rewrite_table_factor(&mut t.relation, rollups);
+            for j in t.joins.iter_mut() {
+                rewrite_table_factor(&mut j.relation, rollups);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Tables of the pre-aggregation, a union of all partitions if it is partitioned.
+fn relation(p: &PreAggregation) -> Result<TableFactor, CubeError> {
+    let tables = p
+        .state()
+        .partitions
+        .iter()
+        .map(|t| format!("{}.{}", p.get_schema(), t.table_name))
+        .collect::<Vec<_>>();
+    let relation = match tables.as_slice() {
+        [table] => table.clone(),
+        tables => format!(
+            "(SELECT * FROM {}) AS {}",
+            tables.join(" UNION ALL SELECT * FROM "),
+            p.get_name()
+        ),
+    };
+    let q = parse_query(&format!("SELECT * FROM {}", relation))?;
+    match q.body {
+        SetExpr::Select(s) => Ok(s.from[0].relation.clone()),
+        _ => panic!("unexpected parse result"),
+    }
+}
+
+/// Source query restricted to the partition `[start, end)` of the time dimension.
+pub fn partition_query(
+    query: &str,
+    time_dimension: &str,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<String, CubeError> {
+    let mut q = parse_query(query)?;
+    let range = parse_expr(&format!(
+        "{} >= to_timestamp('{}') AND {} < to_timestamp('{}')",
+        time_dimension,
+        start.format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        time_dimension,
+        end.format("%Y-%m-%dT%H:%M:%S%.3fZ")
+    ))?;
+    if let SetExpr::Select(s) = &mut q.body {
+        s.selection = Some(match s.selection.take() {
+            Some(filter) => Expr::BinaryOp {
+                left: Box::new(Expr::Nested(Box::new(filter))),
+                op: BinaryOperator::And,
+                right: Box::new(range),
+            },
+            None => range,
+        });
+    }
+    Ok(q.to_string())
+}
+
+/// Query of the minimum and maximum values of the time dimension in the source rows.
+pub fn time_range_query(query: &str, time_dimension: &str) -> Result<String, CubeError> {
+    let mut q = parse_query(query)?;
+    if let SetExpr::Select(s) = &mut q.body {
+        s.projection = vec![
+            SelectItem::UnnamedExpr(parse_expr(&format!("min({})", time_dimension))?),
+            SelectItem::UnnamedExpr(parse_expr(&format!("max({})", time_dimension))?),
+        ];
+        s.group_by = Vec::new();
+        s.having = None;
+    }
+    Ok(q.to_string())
+}
+
+fn parse_query(query: &str) -> Result<Query, CubeError> {
+    match CubeStoreParser::new(query)?.parse_statement()? {
+        CubeStatement::Statement(Statement::Query(q)) => Ok(*q),
+        _ => Err(CubeError::user(format!(
+            "Expected a select, found: {}",
+            query
+        ))),
+    }
+}
+
+fn parse_expr(expr: &str) -> Result<Expr, CubeError> {
+    match parse_query(&format!("SELECT {}", expr))?.body {
+        SetExpr::Select(s) => match s.projection.into_iter().next() {
+            Some(SelectItem::UnnamedExpr(e)) if s.from.is_empty() => Ok(e),
+            _ => Err(CubeError::user(format!("Invalid expression: {}", expr))),
+        },
+        _ => Err(CubeError::user(format!("Invalid expression: {}", expr))),
+    }
+}
+
+/// Returns `schema.table` and the names that columns of the table can be qualified with.
+fn single_table(select: &Select) -> Option<(String, Vec<String>)> {
+    match select.from.as_slice() {
+        [TableWithJoins { relation, joins }] if joins.is_empty() => match relation {
+            TableFactor::Table { name, alias, .. } if name.0.len() == 2 => {
+                let mut qualifiers = vec![name.to_string(), name.0[1].value.clone()];
+                if let Some(alias) = alias {
+                    qualifiers.push(alias.name.value.clone());
+                }
+                Some((
+                    format!("{}.{}", name.0[0].value, name.0[1].value),
+                    qualifiers,
+                ))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn aggregate(e: &Expr) -> Option<Aggregate> {
+    match e {
+        Expr::Function(f) if !f.distinct && f.over.is_none() && f.args.len() == 1 => {
+            match f.name.to_string().to_lowercase().as_str() {
+                "sum" => Some(Aggregate::Sum),
+                "count" => Some(Aggregate::Count),
+                "min" => Some(Aggregate::Min),
+                "max" => Some(Aggregate::Max),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn conjuncts(e: &Option<Expr>) -> Vec<Expr> {
+    fn collect(e: &Expr, out: &mut Vec<Expr>) {
+        match e {
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                collect(left, out);
+                collect(right, out);
+            }
+            Expr::Nested(e) => collect(e, out),
+            e => out.push(e.clone()),
+        }
+    }
+    let mut out = Vec::new();
+    if let Some(e) = e {
+        collect(e, &mut out);
+    }
+    out
+}
+
+fn normalize_select(select: &mut Select, qualifiers: &[String]) {
+    for item in select.projection.iter_mut() {
+        match item {
+            SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+                normalize(e, qualifiers)
+            }
+            _ => {}
+        }
+    }
+    for e in select
+        .selection
+        .iter_mut()
+        .chain(select.group_by.iter_mut())
+        .chain(select.having.iter_mut())
+    {
+        normalize(e, qualifiers);
+    }
+}
+
+/// Removes table qualifiers of columns and converts function names to lower case, so equal
+/// expressions have the same text.
+fn normalize(e: &mut Expr, qualifiers: &[String]) {
+    match e {
+        Expr::CompoundIdentifier(parts) => {
+            let (column, table) = parts.split_last().unwrap();
+            let table = table
+                .iter()
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>()
+                .join(".");
+            if qualifiers.iter().any(|q| *q == table) {
+                *e = Expr::Identifier(column.clone());
+            }
+        }
+        Expr::BinaryOp { left, right, .. } => {
+            normalize(left, qualifiers);
+            normalize(right, qualifiers);
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::Cast { expr, .. }
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr) => normalize(expr, qualifiers),
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            normalize(expr, qualifiers);
+            normalize(low, qualifiers);
+            normalize(high, qualifiers);
+        }
+        Expr::InList { expr, list, .. } => {
+            normalize(expr, qualifiers);
+            list.iter_mut().for_each(|e| normalize(e, qualifiers));
+        }
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            for e in operand
+                .iter_mut()
+                .chain(else_result.iter_mut())
+                .map(|e| e.as_mut())
+                .chain(conditions.iter_mut())
+                .chain(results.iter_mut())
+            {
+                normalize(e, qualifiers);
+            }
+        }
+        Expr::Function(f) => {
+            for i in f.name.0.iter_mut() {
+                i.value = i.value.to_lowercase();
+            }
+            for a in f.args.iter_mut() {
+                if let FunctionArg::Unnamed(a) = a {
+                    normalize(a, qualifiers);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::pre_aggregation::{PreAggregationPartition, PreAggregationState};
+    use chrono::TimeZone;
+
+    const ROLLUP: &str = "SELECT date_trunc('day', created_at) AS day, country, \
+                          sum(amount) AS amount, count(*) AS orders \
+                          FROM s.orders WHERE status = 'done' GROUP BY 1, 2";
+
+    fn rewrite(query: &str, partitions: &[&str]) -> String {
+        let pre_aggregation = PreAggregation::new(
+            "s".to_string(),
+            "orders_by_day".to_string(),
+            ROLLUP.to_string(),
+            None,
+            3600,
+            None,
+        )
+        .set_state(PreAggregationState {
+            partitions: partitions
+                .iter()
+                .enumerate()
+                .map(|(i, t)| PreAggregationPartition {
+                    start: None,
+                    table_id: i as u64,
+                    table_name: t.to_string(),
+                })
+                .collect(),
+            ..PreAggregationState::default()
+        });
+        let mut s = match CubeStoreParser::new(query)
+            .unwrap()
+            .parse_statement()
+            .unwrap()
+        {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        use_pre_aggregations(&mut s, &[IdRow::new(1, pre_aggregation)]).unwrap();
+        match s {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn rollup_definitions() {
+        let r = Rollup::parse(ROLLUP).unwrap();
+        assert_eq!(r.table, "s.orders");
+        assert_eq!(r.filters, vec!["status = 'done'".to_string()]);
+        assert_eq!(r.dimensions.len(), 2);
+        assert_eq!(
+            r.measures["count(*)"],
+            (Aggregate::Count, Ident::new("orders"))
+        );
+
+        for q in &[
+            "SELECT country, sum(amount) FROM s.orders GROUP BY 1",
+            "SELECT country, sum(amount) AS amount FROM s.orders",
+            "SELECT country, city, sum(amount) AS amount FROM s.orders GROUP BY 1",
+            "SELECT o.country, sum(amount) AS a FROM s.orders o JOIN s.c c ON o.id = c.id \
+             GROUP BY 1",
+            "SELECT country, avg(amount) AS amount FROM s.orders GROUP BY 1",
+            "SELECT country FROM s.orders GROUP BY 1 ORDER BY 1",
+        ] {
+            assert!(Rollup::parse(q).is_err(), "{}", q);
+        }
+    }
+
+    #[test]
+    fn rewrites() {
+        assert_eq!(
+            rewrite(
+                "SELECT o.country, SUM(o.amount) AS amount, count(*) AS n FROM s.orders AS o \
+                 WHERE o.status = 'done' AND country IN ('US', 'CA') GROUP BY 1 ORDER BY 2 DESC",
+                &["orders_by_day__1"]
+            ),
+            "SELECT country, SUM(amount) AS amount, \
+             CASE WHEN SUM(orders) IS NULL THEN 0 ELSE SUM(orders) END AS n \
+             FROM s.orders_by_day__1 WHERE country IN ('US', 'CA') GROUP BY 1 ORDER BY 2 DESC"
+        );
+        assert_eq!(
+            rewrite(
+                "SELECT date_trunc('day', created_at) AS d, sum(amount) AS m FROM s.orders \
+                 WHERE status = 'done' GROUP BY d HAVING m > 0 ORDER BY count(*) DESC",
+                &["t1", "t2"]
+            ),
+            "SELECT day AS d, SUM(amount) AS m \
+             FROM (SELECT * FROM s.t1 UNION ALL SELECT * FROM s.t2) AS orders_by_day \
+             GROUP BY d HAVING m > 0 \
+             ORDER BY CASE WHEN SUM(orders) IS NULL THEN 0 ELSE SUM(orders) END DESC"
+        );
+
+        for q in &[
+            // No filter of the pre-aggregation.
+            "SELECT country, sum(amount) AS amount FROM s.orders GROUP BY 1",
+            // Not a dimension.
+            "SELECT city, sum(amount) AS amount FROM s.orders WHERE status = 'done' GROUP BY 1",
+            "SELECT country, sum(amount) AS amount FROM s.orders \
+             WHERE status = 'done' AND amount > 10 GROUP BY 1",
+            // Not a measure.
+            "SELECT country, avg(amount) AS amount FROM s.orders WHERE status = 'done' GROUP BY 1",
+            "SELECT country, sum(amount) FROM s.orders WHERE status = 'done' GROUP BY 1",
+            // No aggregation.
+            "SELECT country FROM s.orders WHERE status = 'done'",
+            "SELECT country, sum(amount) AS amount FROM s.other WHERE status = 'done' GROUP BY 1",
+        ] {
+            assert_eq!(rewrite(q, &["t1"]), *q, "{}", q);
+        }
+        // Not built yet.
+        let q = "SELECT country, sum(amount) AS amount FROM s.orders WHERE status = 'done' \
+                 GROUP BY 1";
+        assert_eq!(rewrite(q, &[]), q);
+    }
+
+    #[test]
+    fn partition_queries() {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2021, 2, 1).and_hms(0, 0, 0);
+        assert_eq!(
+            partition_query(ROLLUP, "created_at", start, end).unwrap(),
+            "SELECT date_trunc('day', created_at) AS day, country, sum(amount) AS amount, \
+             count(*) AS orders FROM s.orders WHERE (status = 'done') \
+             AND created_at >= to_timestamp('2021-01-01T00:00:00.000Z') \
+             AND created_at < to_timestamp('2021-02-01T00:00:00.000Z') GROUP BY 1, 2"
+        );
+        assert_eq!(
+            time_range_query(ROLLUP, "created_at").unwrap(),
+            "SELECT min(created_at), max(created_at) FROM s.orders WHERE status = 'done'"
+        );
+    }
+}
@@ -14,6 +14,7 @@ use datafusion::physical_plan::sort::SortExec;
 use datafusion::physical_plan::ExecutionPlan;
 use itertools::{repeat_n, Itertools};
 
+use crate::queryplanner::parallel_merge::ParallelMergeExec;
 use crate::queryplanner::planning::{ClusterSendNode, WorkerExec};
 use crate::queryplanner::query_executor::{ClusterSendExec, CubeTable, CubeTableExec};
 use crate::queryplanner::serialized_plan::IndexSnapshot;
@@ -24,6 +25,7 @@ use datafusion::physical_plan::alias::AliasedSchemaExec;
 use datafusion::physical_plan::empty::EmptyExec;
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::skip::SkipExec;
 use datafusion::physical_plan::union::UnionExec;
 
 #[derive(Default, Clone, Copy)]
@@ -206,6 +208,9 @@ fn pp_index(index: &IndexSnapshot) -> String {
     if let Some(so) = &index.sort_on {
         r += &format!(":sort_on[{}]", so.join(", "))
     }
+    if index.replicated {
+        r += ":replicated"
+    }
     r
 }
 
@@ -229,7 +234,7 @@ fn pp_sort_columns(first_agg: usize, cs: &[SortColumn]) -> String {
                     r += " desc";
                 }
                 if !c.nulls_first {
-                    r += "null last";
+                    r += " nulls last";
                 }
                 r
             })
@@ -306,6 +311,8 @@ fn pp_phys_plan_indented(p: &dyn ExecutionPlan, indent: usize, o: &PPOptions, ou
             *out += &format!("LocalLimit, n: {}", l.limit());
         } else if let Some(l) = a.downcast_ref::<GlobalLimitExec>() {
             *out += &format!("GlobalLimit, n: {}", l.limit());
+        } else if let Some(s) = a.downcast_ref::<SkipExec>() {
+            *out += &format!("Skip, n: {}", s.skip());
         } else if let Some(f) = a.downcast_ref::<FilterExec>() {
             *out += "Filter";
             if o.show_filters {
@@ -356,6 +363,8 @@ fn pp_phys_plan_indented(p: &dyn ExecutionPlan, indent: usize, o: &PPOptions, ou
             *out += "Worker";
         } else if let Some(_) = a.downcast_ref::<MergeExec>() {
             *out += "Merge";
+        } else if let Some(m) = a.downcast_ref::<ParallelMergeExec>() {
+            *out += &format!("ParallelMerge, partitions: {}", m.options().partitions);
         } else if let Some(_) = a.downcast_ref::<MergeSortExec>() {
             *out += "MergeSort";
         } else if let Some(_) = a.downcast_ref::<MergeReSortExec>() {
@@ -1,12 +1,23 @@
+use crate::cluster::speculative::StragglerTracker;
 use crate::cluster::Cluster;
 use crate::config::injection::DIService;
+use crate::config::ConfigObj;
 use crate::metastore::table::Table;
 use crate::metastore::{Column, ColumnType, IdRow, Index, Partition};
+use crate::queryplanner::batch_cache::{BatchCache, BatchCacheKey, CachedScanExec};
+use crate::queryplanner::deleted_rows::DeletedRowsExec;
+use crate::queryplanner::mmap_parquet::MmapParquetExec;
+use crate::queryplanner::operator_limits::{with_operator_limits, OperatorLimits};
 use crate::queryplanner::optimizations::CubeQueryPlanner;
+use crate::queryplanner::parallel_merge::ParallelMergeOptions;
 use crate::queryplanner::planning::get_worker_plan;
 use crate::queryplanner::serialized_plan::{IndexSnapshot, SerializedPlan};
 use crate::store::DataFrame;
-use crate::table::{Row, TableValue, TimestampValue};
+use crate::table::parquet::offloaded_columns;
+use crate::table::{cmp_same_types, Row, TableValue, TimestampValue};
+use crate::util::id_set::IdSet;
+use crate::util::memory_pressure::register_cache;
+use crate::util::scratch::ScratchSpace;
 use crate::CubeError;
 use arrow::array::{
     Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, Int64Decimal0Array,
@@ -15,7 +26,8 @@ use arrow::array::{
     TimestampNanosecondArray, UInt64Array,
 };
 use arrow::compute::take;
-use arrow::datatypes::{DataType, Schema, SchemaRef, TimeUnit};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::error::Result as ArrowResult;
 use arrow::ipc::reader::StreamReader;
 use arrow::ipc::writer::MemStreamWriter;
 use arrow::record_batch::RecordBatch;
@@ -29,14 +41,24 @@ use datafusion::error::Result as DFResult;
 use datafusion::execution::context::{ExecutionConfig, ExecutionContext};
 use datafusion::logical_plan;
 use datafusion::logical_plan::{DFSchemaRef, Expr, LogicalPlan, ToDFSchema};
+use datafusion::optimizer::utils::expr_to_column_names;
 use datafusion::physical_plan::empty::EmptyExec;
+use datafusion::physical_plan::expressions::{
+    col as physical_col, CaseExpr, Column as PhysicalColumn, IsNotNullExpr, Literal,
+    PhysicalSortExpr,
+};
 use datafusion::physical_plan::memory::MemoryExec;
 use datafusion::physical_plan::merge::MergeExec;
 use datafusion::physical_plan::merge_sort::MergeSortExec;
 use datafusion::physical_plan::parquet::ParquetExec;
+use datafusion::physical_plan::projection::ProjectionExec;
+use datafusion::physical_plan::sort::{SortExec, SortOptions};
 use datafusion::physical_plan::{
-    collect, ExecutionPlan, OptimizerHints, Partitioning, SendableRecordBatchStream,
+    collect, ExecutionPlan, OptimizerHints, Partitioning, PhysicalExpr, RecordBatchStream,
+    SendableRecordBatchStream,
 };
+use datafusion::scalar::ScalarValue;
+use futures::{Stream, StreamExt};
 use itertools::Itertools;
 use log::{debug, error, trace, warn};
 use mockall::automock;
@@ -44,14 +66,24 @@ use num::BigInt;
 use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
 use std::any::Any;
-use std::cmp::min;
+use std::cmp::{min, Ordering};
 use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
 use std::fmt::{Debug, Formatter};
 use std::io::Cursor;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::time::SystemTime;
+use tokio_util::sync::CancellationToken;
 use tracing::{instrument, Instrument};
 
+/// Chunks with at most this many rows are read together, see
+/// [CubeTable::coalesced_parquet_exec].
+const COALESCE_CHUNK_MAX_ROWS: u64 = 16384;
+
 #[automock]
 #[async_trait]
 pub trait QueryExecutor: DIService + Send + Sync {
@@ -61,6 +93,14 @@ pub trait QueryExecutor: DIService + Send + Sync {
         cluster: Arc<dyn Cluster>,
     ) -> Result<DataFrame, CubeError>;
 
+    /// Like [QueryExecutor::execute_router_plan], but produces rows of each record batch as soon
+    /// as it is ready.
+    async fn execute_router_plan_stream(
+        &self,
+        plan: SerializedPlan,
+        cluster: Arc<dyn Cluster>,
+    ) -> Result<(Vec<Column>, RecordBatchStream), CubeError>;
+
     async fn execute_worker_plan(
         &self,
         plan: SerializedPlan,
@@ -77,11 +117,28 @@ pub trait QueryExecutor: DIService + Send + Sync {
         plan: SerializedPlan,
         remote_to_local_names: HashMap<String, String>,
     ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError>;
+
+    /// Applies [crate::config::ConfigObj::worker_batch_cache_max_size], which can change without
+    /// a restart, to the batch cache of worker plans.
+    fn set_batch_cache_max_size(&self, max_size: usize);
 }
 
 crate::di_service!(MockQueryExecutor, [QueryExecutor]);
 
-pub struct QueryExecutorImpl;
+/// Batches of final results, converted to rows by the consumer, see [crate::sql::ResultBatch].
+pub type RecordBatchStream = Pin<Box<dyn Stream<Item = Result<RecordBatch, CubeError>> + Send>>;
+
+pub struct QueryExecutorImpl {
+    /// Only used on workers, disabled while its size is zero.
+    batch_cache: Arc<BatchCache>,
+    /// Used for plans without the batch size of their own.
+    batch_size: usize,
+    /// Only used on the router.
+    parallel_merge: Option<ParallelMergeOptions>,
+    /// Only used on workers.
+    operator_limits: OperatorLimits,
+    server_name: String,
+}
 
 crate::di_service!(QueryExecutorImpl, [QueryExecutor]);
 
@@ -94,6 +151,7 @@ impl QueryExecutor for QueryExecutorImpl {
         cluster: Arc<dyn Cluster>,
     ) -> Result<DataFrame, CubeError> {
         let collect_span = tracing::span!(tracing::Level::TRACE, "collect_physical_plan");
+        let _lease = plan.file_lease();
         let (physical_plan, logical_plan) = self.router_plan(plan, cluster).await?;
         let split_plan = physical_plan;
 
@@ -134,12 +192,37 @@ impl QueryExecutor for QueryExecutorImpl {
         Ok(data_frame)
     }
 
+    #[instrument(level = "trace", skip(self, plan, cluster))]
+    async fn execute_router_plan_stream(
+        &self,
+        plan: SerializedPlan,
+        cluster: Arc<dyn Cluster>,
+    ) -> Result<(Vec<Column>, RecordBatchStream), CubeError> {
+        let lease = plan.file_lease();
+        let (physical_plan, _) = self.router_plan(plan, cluster).await?;
+        let columns = schema_to_columns(physical_plan.schema().as_ref())?;
+        let physical_plan = match physical_plan.output_partitioning().partition_count() {
+            1 => physical_plan,
+            _ => Arc::new(MergeExec::new(physical_plan)),
+        };
+        let batches = physical_plan.execute(0).await?;
+        // Files stay leased until the stream is dropped.
+        Ok((
+            columns,
+            Box::pin(batches.map(move |b| {
+                let _ = &lease;
+                Ok(b?)
+            })),
+        ))
+    }
+
     #[instrument(level = "trace", skip(self, plan, remote_to_local_names))]
     async fn execute_worker_plan(
         &self,
         plan: SerializedPlan,
         remote_to_local_names: HashMap<String, String>,
     ) -> Result<(SchemaRef, Vec<RecordBatch>), CubeError> {
+        let partition_ids = plan.partition_ids_to_execute().clone();
         let (physical_plan, logical_plan) = self.worker_plan(plan, remote_to_local_names).await?;
 
         let worker_plan;
@@ -156,8 +239,14 @@ impl QueryExecutor for QueryExecutorImpl {
 
         trace!("Partition Query Physical Plan: {:#?}", &worker_plan);
 
+        let limited_plan = with_operator_limits(
+            worker_plan.clone(),
+            self.operator_limits,
+            &self.server_name,
+            &partition_ids,
+        )?;
         let execution_time = SystemTime::now();
-        let results = collect(worker_plan.clone())
+        let results = collect(limited_plan)
             .instrument(tracing::span!(
                 tracing::Level::TRACE,
                 "collect_physical_plan"
@@ -191,6 +280,14 @@ impl QueryExecutor for QueryExecutorImpl {
                 &worker_plan
             );
         }
+        if self.batch_cache.max_size() != 0 {
+            let stats = self.batch_cache.stats();
+            debug!(
+                "Batch cache hit rate: {:.3}, stats: {:?}",
+                stats.hit_rate(),
+                stats
+            );
+        }
         // TODO: stream results as they become available.
         let results = regroup_batches(results?, max_batch_rows)?;
         Ok((worker_plan.schema().to_schema_ref(), results))
@@ -201,13 +298,10 @@ impl QueryExecutor for QueryExecutorImpl {
         plan: SerializedPlan,
         cluster: Arc<dyn Cluster>,
     ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError> {
-        let plan_to_move = plan.logical_plan(&HashMap::new())?;
+        let plan_to_move = plan.logical_plan(HashMap::new(), None)?;
         let serialized_plan = Arc::new(plan);
         let ctx = self.router_context(cluster.clone(), serialized_plan.clone())?;
-        Ok((
-            ctx.clone().create_physical_plan(&plan_to_move.clone())?,
-            plan_to_move,
-        ))
+        Ok((ctx.create_physical_plan(&plan_to_move)?, plan_to_move))
     }
 
     async fn worker_plan(
@@ -215,30 +309,59 @@ impl QueryExecutor for QueryExecutorImpl {
         plan: SerializedPlan,
         remote_to_local_names: HashMap<String, String>,
     ) -> Result<(Arc<dyn ExecutionPlan>, LogicalPlan), CubeError> {
-        let plan_to_move = plan.logical_plan(&remote_to_local_names)?;
+        let plan_to_move =
+            plan.logical_plan(remote_to_local_names, Some(self.batch_cache.clone()))?;
         let plan = Arc::new(plan);
         let ctx = self.worker_context(plan.clone())?;
-        let plan_ctx = ctx.clone();
-        Ok((
-            plan_ctx.create_physical_plan(&plan_to_move.clone())?,
-            plan_to_move,
-        ))
+        Ok((ctx.create_physical_plan(&plan_to_move)?, plan_to_move))
+    }
+
+    fn set_batch_cache_max_size(&self, max_size: usize) {
+        self.batch_cache.set_max_size(max_size);
     }
 }
 
 impl QueryExecutorImpl {
+    /// Final aggregates on the router run in parallel when `scratch` is passed, see
+    /// [crate::queryplanner::parallel_merge].
+    pub fn new(config: &dyn ConfigObj, scratch: Option<Arc<ScratchSpace>>) -> QueryExecutorImpl {
+        let batch_cache = Arc::new(BatchCache::new(config.worker_batch_cache_max_size()));
+        register_cache(batch_cache.clone());
+        QueryExecutorImpl {
+            batch_cache,
+            batch_size: config.query_batch_size(),
+            parallel_merge: match scratch {
+                Some(scratch) if 1 < config.router_merge_partitions() => {
+                    Some(ParallelMergeOptions {
+                        partitions: config.router_merge_partitions(),
+                        memory_limit: config.router_merge_memory_limit(),
+                        scratch,
+                    })
+                }
+                _ => None,
+            },
+            operator_limits: OperatorLimits {
+                memory: config.operator_memory_limit(),
+                cpu_ms: config.operator_cpu_limit_ms(),
+            },
+            server_name: config.server_name().clone(),
+        }
+    }
+
     fn router_context(
         &self,
         cluster: Arc<dyn Cluster>,
         serialized_plan: Arc<SerializedPlan>,
     ) -> Result<Arc<ExecutionContext>, CubeError> {
+        let batch_size = serialized_plan.batch_size().unwrap_or(self.batch_size);
         Ok(Arc::new(ExecutionContext::with_config(
             ExecutionConfig::new()
-                .with_batch_size(4096)
+                .with_batch_size(batch_size)
                 .with_concurrency(1)
                 .with_query_planner(Arc::new(CubeQueryPlanner::new_on_router(
                     cluster,
                     serialized_plan,
+                    self.parallel_merge.clone(),
                 ))),
         )))
     }
@@ -247,9 +370,10 @@ impl QueryExecutorImpl {
         &self,
         serialized_plan: Arc<SerializedPlan>,
     ) -> Result<Arc<ExecutionContext>, CubeError> {
+        let batch_size = serialized_plan.batch_size().unwrap_or(self.batch_size);
         Ok(Arc::new(ExecutionContext::with_config(
             ExecutionConfig::new()
-                .with_batch_size(4096)
+                .with_batch_size(batch_size)
                 .with_concurrency(1)
                 .with_query_planner(Arc::new(CubeQueryPlanner::new_on_worker(serialized_plan))),
         )))
@@ -258,9 +382,15 @@ impl QueryExecutorImpl {
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct CubeTable {
-    index_snapshot: IndexSnapshot,
-    remote_to_local_names: HashMap<String, String>,
-    worker_partition_ids: HashSet<u64>,
+    /// Shared with scans of the table, see [CubeTable::to_worker_table].
+    index_snapshot: Arc<IndexSnapshot>,
+    /// Only populated on workers, see [CubeTable::to_worker_table].
+    #[serde(skip)]
+    remote_to_local_names: Arc<HashMap<String, String>>,
+    worker_partition_ids: IdSet,
+    /// Only populated on workers.
+    #[serde(skip)]
+    batch_cache: Option<Arc<BatchCache>>,
     schema: SchemaRef,
 }
 
@@ -268,7 +398,7 @@ impl CubeTable {
     pub fn try_new(
         index_snapshot: IndexSnapshot,
         remote_to_local_names: HashMap<String, String>,
-        worker_partition_ids: HashSet<u64>,
+        worker_partition_ids: IdSet,
     ) -> Result<Self, CubeError> {
         let schema = Arc::new(Schema::new(
             index_snapshot
@@ -280,22 +410,25 @@ impl CubeTable {
                 .collect::<Vec<_>>(),
         ));
         Ok(Self {
-            index_snapshot,
+            index_snapshot: Arc::new(index_snapshot),
             schema,
-            remote_to_local_names,
+            remote_to_local_names: Arc::new(remote_to_local_names),
             worker_partition_ids,
+            batch_cache: None,
         })
     }
 
     #[must_use]
     pub fn to_worker_table(
         &self,
-        remote_to_local_names: HashMap<String, String>,
-        worker_partition_ids: HashSet<u64>,
+        remote_to_local_names: Arc<HashMap<String, String>>,
+        worker_partition_ids: IdSet,
+        batch_cache: Option<Arc<BatchCache>>,
     ) -> CubeTable {
         let mut t = self.clone();
         t.remote_to_local_names = remote_to_local_names;
         t.worker_partition_ids = worker_partition_ids;
+        t.batch_cache = batch_cache;
         t
     }
 
@@ -303,6 +436,17 @@ impl CubeTable {
         &self.index_snapshot
     }
 
+    #[must_use]
+    pub fn retain_partitions(&self, partition_ids: &IdSet) -> CubeTable {
+        CubeTable {
+            index_snapshot: Arc::new(self.index_snapshot.retain_partitions(partition_ids)),
+            remote_to_local_names: self.remote_to_local_names.clone(),
+            worker_partition_ids: self.worker_partition_ids.clone(),
+            batch_cache: self.batch_cache.clone(),
+            schema: self.schema.clone(),
+        }
+    }
+
     fn async_scan(
         &self,
         projection: &Option<Vec<usize>>,
@@ -337,33 +481,62 @@ impl CubeTable {
                     .remote_to_local_names
                     .get(remote_path.as_str())
                     .expect(format!("Missing remote path {}", remote_path).as_str());
-                let arc: Arc<dyn ExecutionPlan> = Arc::new(ParquetExec::try_from_path(
-                    &local_path,
-                    mapped_projection.clone(),
-                    predicate.clone(),
-                    batch_size,
-                    1,
-                    None, // TODO: propagate limit
-                )?);
-                partition_execs.push(arc);
+                if table.get_row().attached_location().is_some() {
+                    partition_execs.push(self.attached_file_exec(
+                        local_path,
+                        partition.get_row(),
+                        &mapped_projection,
+                        batch_size,
+                    )?);
+                } else {
+                    partition_execs.push(self.parquet_exec(
+                        local_path,
+                        &mapped_projection,
+                        &predicate,
+                        batch_size,
+                    )?);
+                }
             }
 
             let chunks = partition_snapshot.chunks();
+            let (small_chunks, chunks): (Vec<_>, Vec<_>) = chunks
+                .iter()
+                .partition(|c| c.get_row().get_row_count() <= COALESCE_CHUNK_MAX_ROWS);
+            let (small_chunks, chunks) = if small_chunks.len() < 2 {
+                (Vec::new(), small_chunks.into_iter().chain(chunks).collect())
+            } else {
+                (small_chunks, chunks)
+            };
             for chunk in chunks {
                 let remote_path = chunk.get_row().get_full_name(chunk.get_id());
                 let local_path = self
                     .remote_to_local_names
                     .get(&remote_path)
                     .expect(format!("Missing remote path {}", remote_path).as_str());
-                let node = Arc::new(ParquetExec::try_from_path(
+                partition_execs.push(self.parquet_exec(
                     local_path,
-                    mapped_projection.clone(),
-                    predicate.clone(),
+                    &mapped_projection,
+                    &predicate,
+                    batch_size,
+                )?);
+            }
+            if !small_chunks.is_empty() {
+                let local_paths = small_chunks
+                    .iter()
+                    .map(|chunk| {
+                        let remote_path = chunk.get_row().get_full_name(chunk.get_id());
+                        self.remote_to_local_names
+                            .get(&remote_path)
+                            .expect(format!("Missing remote path {}", remote_path).as_str())
+                            .as_str()
+                    })
+                    .collect_vec();
+                partition_execs.push(self.coalesced_parquet_exec(
+                    &local_paths,
+                    &mapped_projection,
+                    &predicate,
                     batch_size,
-                    1,
-                    None, // TODO: propagate limit
                 )?);
-                partition_execs.push(node);
             }
         }
 
@@ -408,6 +581,241 @@ impl CubeTable {
         Ok(plan)
     }
 
+    fn parquet_exec(
+        &self,
+        local_path: &str,
+        projection: &Option<Vec<usize>>,
+        predicate: &Option<Expr>,
+        batch_size: usize,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        self.with_offloaded(projection, predicate, |projection, predicate| {
+            let cache = match &self.batch_cache {
+                Some(cache) if cache.max_size() != 0 => cache,
+                _ => {
+                    return Ok(Arc::new(MmapParquetExec::try_new(
+                        &[local_path],
+                        projection,
+                        predicate,
+                        batch_size,
+                    )?));
+                }
+            };
+            // Cached data must not depend on the query, so row groups are not pruned by the
+            // predicate. Filters are still applied to the scan results.
+            let scan = Arc::new(MmapParquetExec::try_new(
+                &[local_path],
+                projection.clone(),
+                None,
+                batch_size,
+            )?);
+            let key = BatchCacheKey {
+                file: local_path.to_string(),
+                projection,
+            };
+            Ok(Arc::new(CachedScanExec::new(key, cache.clone(), scan)))
+        })
+    }
+
+    /// Files of indexes with offloaded strings also hold hidden columns with full values of long
+    /// strings, see [crate::table::parquet::offloaded_columns]. `scan` gets a projection that
+    /// never reads hidden columns of offloaded columns that are not projected, so long strings
+    /// are only read when they are selected. Offloaded columns have no statistics, predicates on
+    /// them don't prune row groups.
+    fn with_offloaded(
+        &self,
+        projection: &Option<Vec<usize>>,
+        predicate: &Option<Expr>,
+        scan: impl FnOnce(Option<Vec<usize>>, Option<Expr>) -> Result<Arc<dyn ExecutionPlan>, CubeError>,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        let columns = self.index_snapshot.index().get_row().get_columns();
+        let offloaded = offloaded_columns(columns);
+        if offloaded.is_empty() {
+            return scan(projection.clone(), predicate.clone());
+        }
+        let positions = match projection {
+            Some(p) => p.clone(),
+            None => (0..columns.len()).collect(),
+        };
+        let mut file_projection = positions.clone();
+        // Positions of projected offloaded columns and of their hidden columns in the scan.
+        let mut hidden = Vec::new();
+        for (i, p) in positions.iter().enumerate() {
+            if let Some((_, h)) = offloaded.iter().find(|(c, _)| c.get_index() == *p) {
+                hidden.push((i, file_projection.len()));
+                file_projection.push(h.get_index());
+            }
+        }
+        let predicate = predicate.clone().filter(|p| {
+            let mut names = HashSet::new();
+            expr_to_column_names(p, &mut names).is_ok()
+                && offloaded.iter().all(|(c, _)| !names.contains(c.get_name()))
+        });
+        let plan = scan(Some(file_projection), predicate)?;
+        if hidden.is_empty() {
+            return Ok(plan);
+        }
+        let schema = plan.schema();
+        let exprs = (0..positions.len())
+            .map(|i| {
+                let name = schema.field(i).name();
+                let inline: Arc<dyn PhysicalExpr> = Arc::new(PhysicalColumn::new(name));
+                let expr: Arc<dyn PhysicalExpr> = match hidden.iter().find(|(o, _)| *o == i) {
+                    Some((_, h)) => {
+                        let full: Arc<dyn PhysicalExpr> =
+                            Arc::new(PhysicalColumn::new(schema.field(*h).name()));
+                        Arc::new(CaseExpr::try_new(
+                            None,
+                            &[(Arc::new(IsNotNullExpr::new(full.clone())), full)],
+                            Some(inline),
+                        )?)
+                    }
+                    None => inline,
+                };
+                Ok((expr, name.clone()))
+            })
+            .collect::<Result<Vec<_>, CubeError>>()?;
+        Ok(Arc::new(ProjectionExec::try_new(exprs, plan)?))
+    }
+
+    /// Files of attached tables are read by column names, see [crate::sql::attach]. Sort key
+    /// columns missing in a file have the same value in all its rows, which is taken from the
+    /// lower bound of the partition. Other missing columns are nulls, e.g. after columns were
+    /// added to the table. Partitions without bounds hold rows that are not sorted by the index
+    /// and are sorted after the read.
+    fn attached_file_exec(
+        &self,
+        local_path: &str,
+        partition: &Partition,
+        projection: &Option<Vec<usize>>,
+        batch_size: usize,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        let index = self.index_snapshot.index().get_row();
+        let key_size = index.sort_key_size() as usize;
+        let mut positions = match projection {
+            Some(p) => p.clone(),
+            None => (0..index.get_columns().len()).collect(),
+        };
+        positions.sort_unstable();
+        let file_schema =
+            ParquetExec::try_from_path(local_path, None, None, batch_size, 1, None)?.schema();
+        let mut file_projection = positions
+            .iter()
+            .filter_map(|p| {
+                file_schema
+                    .index_of(index.get_columns()[*p].get_name())
+                    .ok()
+            })
+            .collect_vec();
+        // Batches without columns have no rows, read at least one column to count them.
+        if file_projection.is_empty() {
+            file_projection.push(0);
+        }
+        let mut scan: Arc<dyn ExecutionPlan> = Arc::new(ParquetExec::try_from_path(
+            local_path,
+            Some(file_projection),
+            None,
+            batch_size,
+            1,
+            None,
+        )?);
+        if !partition.deleted_rows().is_empty() {
+            scan = Arc::new(DeletedRowsExec::new(
+                scan,
+                Arc::new(partition.deleted_rows().clone()),
+            ));
+        }
+        let exprs = positions
+            .iter()
+            .map(|&p| {
+                let column = &index.get_columns()[p];
+                let field: Field = column.clone().into();
+                let name = column.get_name().clone();
+                let expr: Arc<dyn PhysicalExpr> = match file_schema.index_of(&name) {
+                    Ok(i) if file_schema.field(i).data_type() == field.data_type() => {
+                        Arc::new(PhysicalColumn::new(&name))
+                    }
+                    Ok(i) => {
+                        return Err(CubeError::user(format!(
+                            "Column {} of {} has type {:?}, expected {:?}",
+                            name,
+                            local_path,
+                            file_schema.field(i).data_type(),
+                            field.data_type()
+                        )))
+                    }
+                    Err(_) => {
+                        let value = match partition.get_min_val() {
+                            Some(min) if p < key_size => min.values()[p].clone(),
+                            _ => TableValue::Null,
+                        };
+                        Arc::new(Literal::new(scalar_value(&value, field.data_type())?))
+                    }
+                };
+                Ok((expr, name))
+            })
+            .collect::<Result<Vec<_>, CubeError>>()?;
+        let plan: Arc<dyn ExecutionPlan> = Arc::new(ProjectionExec::try_new(exprs, scan)?);
+        if partition.get_min_val().is_some() {
+            Ok(plan)
+        } else {
+            self.sort_by_index(plan)
+        }
+    }
+
+    /// Reads all `local_paths` sequentially in a single scan, saving the per-file overhead of
+    /// a scan per file on the many small chunks produced by streaming between compactions. The
+    /// result is sorted again, as [CubeTableExec] promises every input is sorted by the index.
+    /// Coalesced scans bypass the batch cache, small chunks are short-lived anyway.
+    fn coalesced_parquet_exec(
+        &self,
+        local_paths: &[&str],
+        projection: &Option<Vec<usize>>,
+        predicate: &Option<Expr>,
+        batch_size: usize,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        let scan = self.with_offloaded(projection, predicate, |projection, predicate| {
+            Ok(Arc::new(MmapParquetExec::try_new(
+                local_paths,
+                projection,
+                predicate,
+                batch_size,
+            )?))
+        })?;
+        self.sort_by_index(scan)
+    }
+
+    /// Sorts rows of `scan` by the index, as [CubeTableExec] promises every input is sorted.
+    fn sort_by_index(
+        &self,
+        scan: Arc<dyn ExecutionPlan>,
+    ) -> Result<Arc<dyn ExecutionPlan>, CubeError> {
+        let scan_schema = scan.schema();
+        let sort_columns = match self.index_snapshot.sort_on() {
+            Some(sort_on) => sort_on.clone(),
+            None => self
+                .index_snapshot
+                .index()
+                .get_row()
+                .get_columns()
+                .iter()
+                .take(self.index_snapshot.index().get_row().sort_key_size() as usize)
+                .map(|c| c.get_name().clone())
+                .take_while(|c| scan_schema.index_of(c).is_ok())
+                .collect_vec(),
+        };
+        if sort_columns.is_empty() {
+            return Ok(scan);
+        }
+        let sort_expr = sort_columns
+            .iter()
+            .map(|c| PhysicalSortExpr {
+                expr: physical_col(c),
+                options: SortOptions::default(),
+            })
+            .collect_vec();
+        Ok(Arc::new(SortExec::try_new(sort_expr, scan)?))
+    }
+
     pub fn project_to_index_positions(
         projection_columns: &Vec<Column>,
         i: &IdRow<Index>,
@@ -437,7 +845,7 @@ impl CubeTable {
 
 pub struct CubeTableExec {
     schema: DFSchemaRef,
-    pub(crate) index_snapshot: IndexSnapshot,
+    pub(crate) index_snapshot: Arc<IndexSnapshot>,
     partition_execs: Vec<Arc<dyn ExecutionPlan>>,
     pub(crate) filter: Option<Expr>,
 }
@@ -526,9 +934,33 @@ pub struct ClusterSendExec {
     /// Never executed, only stored to allow consistent optimization on router and worker.
     pub input_for_optimizations: Arc<dyn ExecutionPlan>,
     pub partitions: Vec<Vec<IdRow<Partition>>>,
+    /// Placements that pick the worker of each of `partitions`. Partitions of replicated tables
+    /// are on all workers and only count when the worker reads nothing else.
+    placement_ids: Vec<Vec<u64>>,
     pub cluster: Arc<dyn Cluster>,
     pub serialized_plan: Arc<SerializedPlan>,
     pub use_streaming: bool,
+    /// Shared by all partitions for speculative execution.
+    stragglers: Arc<StragglerTracker>,
+    /// Set when only this many rows are needed from all partitions together, see
+    /// [ClusterSendExec::with_limit].
+    limit: Option<Arc<ClusterSendLimit>>,
+}
+
+/// Rows received from all partitions of a [ClusterSendExec] with a limit.
+struct ClusterSendLimit {
+    limit: usize,
+    rows: AtomicUsize,
+    /// Cancelled once `limit` rows were received.
+    reached: CancellationToken,
+}
+
+impl ClusterSendLimit {
+    fn add_rows(&self, rows: usize) {
+        if self.rows.fetch_add(rows, AtomicOrdering::SeqCst) + rows >= self.limit {
+            self.reached.cancel();
+        }
+    }
 }
 
 impl ClusterSendExec {
@@ -537,29 +969,68 @@ impl ClusterSendExec {
         cluster: Arc<dyn Cluster>,
         serialized_plan: Arc<SerializedPlan>,
         union_snapshots: Vec<Vec<IndexSnapshot>>,
+        inner_join: bool,
         input_for_optimizations: Arc<dyn ExecutionPlan>,
         use_streaming: bool,
     ) -> Self {
         let to_multiply = union_snapshots
-            .into_iter()
+            .iter()
             .map(|union| {
                 union
                     .iter()
-                    .flat_map(|index| index.partitions().iter().map(|p| p.partition().clone()))
+                    .flat_map(|index| {
+                        index
+                            .partitions()
+                            .iter()
+                            .map(move |p| (p.partition().clone(), index))
+                    })
                     .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
-        let partitions = to_multiply
+        let (partitions, placement_ids) = to_multiply
             .into_iter()
             .multi_cartesian_product()
-            .collect::<Vec<Vec<_>>>();
+            .filter(|ps| !inner_join || ps.len() != 2 || may_have_matches(&ps[0], &ps[1]))
+            .map(|ps| {
+                let placement = |(p, _): &(IdRow<Partition>, &IndexSnapshot)| {
+                    p.get_row().placement_id(p.get_id())
+                };
+                let mut placement_ids = ps
+                    .iter()
+                    .filter(|(_, index)| !index.replicated())
+                    .map(placement)
+                    .unique()
+                    .collect_vec();
+                if placement_ids.is_empty() {
+                    placement_ids = ps.iter().map(placement).unique().collect_vec();
+                }
+                (ps.into_iter().map(|(p, _)| p).collect_vec(), placement_ids)
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        let stragglers = Arc::new(StragglerTracker::new(partitions.len()));
         Self {
             schema,
             partitions,
+            placement_ids,
             cluster,
             serialized_plan,
             input_for_optimizations,
             use_streaming,
+            stragglers,
+            limit: None,
+        }
+    }
+
+    /// Stops selects still running on workers once `limit` rows were received from the other
+    /// ones. Only valid when any `limit` rows of the result will do, i.e. there is no ordering.
+    pub fn with_limit(&self, limit: usize) -> Self {
+        ClusterSendExec {
+            limit: Some(Arc::new(ClusterSendLimit {
+                limit,
+                rows: AtomicUsize::new(0),
+                reached: CancellationToken::new(),
+            })),
+            ..self.with_changed_schema(self.schema.clone(), self.input_for_optimizations.clone())
         }
     }
 
@@ -571,10 +1042,13 @@ impl ClusterSendExec {
         ClusterSendExec {
             schema,
             partitions: self.partitions.clone(),
+            placement_ids: self.placement_ids.clone(),
             cluster: self.cluster.clone(),
             serialized_plan: self.serialized_plan.clone(),
             input_for_optimizations,
             use_streaming: self.use_streaming,
+            stragglers: self.stragglers.clone(),
+            limit: self.limit.clone(),
         }
     }
 }
@@ -608,10 +1082,13 @@ impl ExecutionPlan for ClusterSendExec {
         Ok(Arc::new(ClusterSendExec {
             schema: self.schema.clone(),
             partitions: self.partitions.clone(),
+            placement_ids: self.placement_ids.clone(),
             cluster: self.cluster.clone(),
             serialized_plan: self.serialized_plan.clone(),
             input_for_optimizations,
             use_streaming: self.use_streaming,
+            stragglers: self.stragglers.clone(),
+            limit: self.limit.clone(),
         }))
     }
 
@@ -624,28 +1101,162 @@ impl ExecutionPlan for ClusterSendExec {
         &self,
         partition: usize,
     ) -> Result<SendableRecordBatchStream, DataFusionError> {
-        let node_name = &self.cluster.node_name_by_partitions(
-            &self.partitions[partition]
-                .iter()
-                .map(|p| p.get_id())
-                .collect_vec(),
-        );
+        // Partitions of co-located tables go to the worker of the partition they are placed with.
+        let node_name = &self
+            .cluster
+            .node_name_by_partitions(&self.placement_ids[partition]);
         let plan = self.serialized_plan.with_partition_id_to_execute(
             self.partitions[partition]
                 .iter()
                 .map(|p| p.get_id())
                 .collect(),
         );
+        let limit = match &self.limit {
+            Some(limit) => limit,
+            None => return self.run_select(node_name, plan).await,
+        };
+        if limit.reached.is_cancelled() {
+            return self.empty_stream().await;
+        }
+        let select_id = rand::random::<u64>();
+        let plan = plan.with_select_id(Some(select_id));
+        tokio::select! {
+            res = self.run_select(node_name, plan) => {
+                let stream = res?;
+                Ok(Box::pin(LimitedClusterSendStream {
+                    input: stream,
+                    limit: limit.clone(),
+                }))
+            }
+            _ = limit.reached.cancelled() => {
+                let cluster = self.cluster.clone();
+                let node_name = node_name.to_string();
+                tokio::spawn(async move {
+                    if let Err(e) = cluster.cancel_select(&node_name, select_id).await {
+                        warn!("Error while cancelling select on {}: {}", node_name, e);
+                    }
+                });
+                self.empty_stream().await
+            }
+        }
+    }
+}
+
+impl ClusterSendExec {
+    async fn run_select(
+        &self,
+        node_name: &str,
+        plan: SerializedPlan,
+    ) -> Result<SendableRecordBatchStream, DataFusionError> {
         if self.use_streaming {
             Ok(self.cluster.run_select_stream(node_name, plan).await?)
         } else {
-            let record_batches = self.cluster.run_select(node_name, plan).await?;
+            let record_batches = match self.cluster.speculative_execution() {
+                Some(speculative) => {
+                    self.stragglers
+                        .run_select(&self.cluster, speculative, node_name, plan)
+                        .await?
+                }
+                None => self.cluster.run_select(node_name, plan).await?,
+            };
             // TODO .to_schema_ref()
             let memory_exec =
                 MemoryExec::try_new(&vec![record_batches], self.schema.to_schema_ref(), None)?;
             memory_exec.execute(0).await
         }
     }
+
+    async fn empty_stream(&self) -> Result<SendableRecordBatchStream, DataFusionError> {
+        MemoryExec::try_new(&vec![Vec::new()], self.schema.to_schema_ref(), None)?
+            .execute(0)
+            .await
+    }
+}
+
+/// Counts rows passed to the router towards the limit of [ClusterSendExec].
+struct LimitedClusterSendStream {
+    input: SendableRecordBatchStream,
+    limit: Arc<ClusterSendLimit>,
+}
+
+impl Stream for LimitedClusterSendStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let res = self.input.poll_next_unpin(cx);
+        if let Poll::Ready(Some(Ok(batch))) = &res {
+            self.limit.add_rows(batch.num_rows());
+        }
+        res
+    }
+}
+
+impl RecordBatchStream for LimitedClusterSendStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+/// Whether partitions of the two sides of an inner join on `sort_on` columns can have matching
+/// rows. Partitions whose key ranges are disjoint on the joined prefix of their sort keys can't.
+fn may_have_matches(
+    l: &(IdRow<Partition>, &IndexSnapshot),
+    r: &(IdRow<Partition>, &IndexSnapshot),
+) -> bool {
+    let key_size = joined_key_prefix(l.1, r.1);
+    key_size == 0
+        || !(ends_before(l.0.get_row(), r.0.get_row(), key_size)
+            || ends_before(r.0.get_row(), l.0.get_row(), key_size))
+}
+
+/// Number of leading sort key columns of both indexes that are joined with each other.
+fn joined_key_prefix(l: &IndexSnapshot, r: &IndexSnapshot) -> usize {
+    let (l_on, r_on) = match (l.sort_on(), r.sort_on()) {
+        (Some(l_on), Some(r_on)) => (l_on, r_on),
+        _ => return 0,
+    };
+    let l_index = l.index().get_row();
+    let r_index = r.index().get_row();
+    let sort_key_size = min(l_index.sort_key_size(), r_index.sort_key_size()) as usize;
+    (0..sort_key_size)
+        .take_while(|&i| {
+            match l_on
+                .iter()
+                .position(|c| c == l_index.get_columns()[i].get_name())
+            {
+                Some(j) => r_on.get(j) == Some(r_index.get_columns()[i].get_name()),
+                None => false,
+            }
+        })
+        .count()
+}
+
+/// Whether all rows of `a` have smaller `key_size` prefixes of the sort key than rows of `b`.
+fn ends_before(a: &Partition, b: &Partition, key_size: usize) -> bool {
+    let (a_max, b_min) = match (a.get_max_val(), b.get_min_val()) {
+        (Some(a_max), Some(b_min)) => (a_max.values(), b_min.values()),
+        _ => return false,
+    };
+    for i in 0..key_size {
+        match cmp_key_values(&a_max[i], &b_min[i]) {
+            Some(Ordering::Less) => return true,
+            Some(Ordering::Equal) => {}
+            _ => return false,
+        }
+    }
+    // The max value is excluded from the range. Rows with the same prefix are still in `a`,
+    // unless the rest of the max value is nulls, which sort first.
+    a_max[key_size..]
+        .iter()
+        .all(|v| matches!(v, TableValue::Null))
+}
+
+fn cmp_key_values(l: &TableValue, r: &TableValue) -> Option<Ordering> {
+    match (l, r) {
+        (TableValue::Null, _) | (_, TableValue::Null) => Some(cmp_same_types(l, r)),
+        _ if std::mem::discriminant(l) == std::mem::discriminant(r) => Some(cmp_same_types(l, r)),
+        _ => None,
+    }
 }
 
 impl fmt::Debug for ClusterSendExec {
@@ -731,20 +1342,48 @@ macro_rules! convert_array {
     }};
 }
 
+/// Scalar of `data_type` with the value of a partition bound.
+fn scalar_value(value: &TableValue, data_type: &DataType) -> Result<ScalarValue, CubeError> {
+    Ok(match (value, data_type) {
+        (TableValue::Null, _) => ScalarValue::try_from(data_type)?,
+        (TableValue::String(s), DataType::Utf8) => ScalarValue::Utf8(Some(s.clone())),
+        (TableValue::Int(i), DataType::Int64) => ScalarValue::Int64(Some(*i)),
+        (TableValue::Float(f), DataType::Float64) => ScalarValue::Float64(Some(f.0)),
+        (TableValue::Boolean(b), DataType::Boolean) => ScalarValue::Boolean(Some(*b)),
+        (TableValue::Timestamp(t), DataType::Timestamp(TimeUnit::Microsecond, None)) => {
+            ScalarValue::TimestampMicrosecond(Some(t.get_time_stamp() / 1000))
+        }
+        (v, t) => {
+            return Err(CubeError::internal(format!(
+                "Can't convert {:?} to {:?}",
+                v, t
+            )))
+        }
+    })
+}
+
+pub fn schema_to_columns(schema: &Schema) -> Result<Vec<Column>, CubeError> {
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(i, field)| {
+            Ok(Column::new(
+                field.name().clone(),
+                arrow_to_column_type(field.data_type().clone())?,
+                i,
+            ))
+        })
+        .collect()
+}
+
 pub fn batch_to_dataframe(batches: &Vec<RecordBatch>) -> Result<DataFrame, CubeError> {
     let mut cols = vec![];
     let mut all_rows = vec![];
 
     for batch in batches.iter() {
         if cols.len() == 0 {
-            let schema = batch.schema().clone();
-            for (i, field) in schema.fields().iter().enumerate() {
-                cols.push(Column::new(
-                    field.name().clone(),
-                    arrow_to_column_type(field.data_type().clone())?,
-                    i,
-                ));
-            }
+            cols = schema_to_columns(batch.schema().as_ref())?;
         }
         if batch.num_rows() == 0 {
             continue;
@@ -917,10 +1556,57 @@ pub fn arrow_to_column_type(arrow_type: DataType) -> Result<ColumnType, CubeErro
     }
 }
 
+/// Codec of record batches sent between nodes. The router asks for one in the select request,
+/// workers compress only batches that are large enough and mark each batch with the codec used.
+#[derive(Copy, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum TransportCompression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl TransportCompression {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CubeError> {
+        match self {
+            TransportCompression::None => Ok(data.to_vec()),
+            TransportCompression::Lz4 => Ok(lz4::block::compress(data, None, true)?),
+            // Zero is the default level.
+            TransportCompression::Zstd => Ok(zstd::stream::encode_all(data, 0)?),
+        }
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CubeError> {
+        match self {
+            TransportCompression::None => Ok(data.to_vec()),
+            TransportCompression::Lz4 => Ok(lz4::block::decompress(data, None)?),
+            TransportCompression::Zstd => Ok(zstd::stream::decode_all(data)?),
+        }
+    }
+}
+
+impl FromStr for TransportCompression {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(TransportCompression::None),
+            "lz4" => Ok(TransportCompression::Lz4),
+            "zstd" => Ok(TransportCompression::Zstd),
+            _ => Err(CubeError::user(format!(
+                "Transport compression should be one of 'none', 'lz4' or 'zstd' but found '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Record batch in the Arrow IPC stream format, the only form results take between workers and
+/// the router. Rows are only produced from the final results, see [batch_to_dataframe].
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SerializedRecordBatchStream {
     #[serde(with = "serde_bytes")] // serde_bytes makes serialization efficient.
     record_batch_file: Vec<u8>,
+    compression: TransportCompression,
 }
 
 impl SerializedRecordBatchStream {
@@ -936,13 +1622,35 @@ impl SerializedRecordBatchStream {
             let cursor = writer.finish()?;
             results.push(Self {
                 record_batch_file: cursor.into_inner(),
+                compression: TransportCompression::None,
             })
         }
         Ok(results)
     }
 
+    /// Batches smaller than `threshold` bytes are kept as is, compressing them saves little.
+    pub fn compress(
+        self,
+        compression: TransportCompression,
+        threshold: usize,
+    ) -> Result<Self, CubeError> {
+        if self.compression != TransportCompression::None
+            || self.record_batch_file.len() < threshold
+        {
+            return Ok(self);
+        }
+        Ok(Self {
+            record_batch_file: compression.compress(&self.record_batch_file)?,
+            compression,
+        })
+    }
+
     pub fn read(self) -> Result<RecordBatch, CubeError> {
-        let cursor = Cursor::new(self.record_batch_file);
+        let file = match self.compression {
+            TransportCompression::None => self.record_batch_file,
+            c => c.decompress(&self.record_batch_file)?,
+        };
+        let cursor = Cursor::new(file);
         let mut reader = StreamReader::try_new(cursor)?;
         let batch = reader.next();
         if batch.is_none() {
@@ -1005,3 +1713,105 @@ fn slice_copy(a: &dyn Array, start: usize, len: usize) -> Result<ArrayRef, CubeE
         None,
     )?)
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use arrow::array::{Int64Array, StringArray};
+    use arrow::datatypes::Field;
+    use test::Bencher;
+
+    fn test_batch(rows: i64) -> RecordBatch {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("n", DataType::Int64, false),
+            Field::new("s", DataType::Utf8, true),
+        ]));
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from((0..rows).collect::<Vec<i64>>())),
+                Arc::new(StringArray::from(
+                    (0..rows)
+                        .map(|i| Some(format!("value {}", i % 100)))
+                        .collect::<Vec<_>>(),
+                )),
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn transport_compression() {
+        let batch = test_batch(1000);
+        let schema = batch.schema();
+        for c in &[
+            TransportCompression::None,
+            TransportCompression::Lz4,
+            TransportCompression::Zstd,
+        ] {
+            let mut s = SerializedRecordBatchStream::write(&schema, vec![batch.clone()]).unwrap();
+            let size = s[0].record_batch_file.len();
+            let s = s.remove(0).compress(*c, 1024).unwrap();
+            assert_eq!(s.compression, *c);
+            if *c != TransportCompression::None {
+                assert!(s.record_batch_file.len() < size, "{:?}", c);
+            }
+            let r = s.read().unwrap();
+            assert_eq!(r.columns(), batch.columns());
+        }
+
+        let mut s = SerializedRecordBatchStream::write(&schema, vec![batch.clone()]).unwrap();
+        let s = s
+            .remove(0)
+            .compress(TransportCompression::Lz4, usize::MAX)
+            .unwrap();
+        assert_eq!(s.compression, TransportCompression::None);
+
+        assert_eq!(
+            "ZSTD".parse::<TransportCompression>().unwrap(),
+            TransportCompression::Zstd
+        );
+        assert!("gzip".parse::<TransportCompression>().is_err());
+    }
+
+    /// Results exchanged between workers and the router, compare with [convert_to_rows].
+    #[bench]
+    fn exchange_batches(b: &mut Bencher) {
+        let batches = vec![test_batch(64 * 1024); 16];
+        let schema = batches[0].schema();
+        b.iter(|| {
+            let s = SerializedRecordBatchStream::write(&schema, batches.clone()).unwrap();
+            let r = s
+                .into_iter()
+                .map(|s| s.read())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(r.unwrap().len(), batches.len());
+        });
+    }
+
+    #[bench]
+    fn exchange_batches_lz4(b: &mut Bencher) {
+        let batches = vec![test_batch(64 * 1024); 16];
+        let schema = batches[0].schema();
+        b.iter(|| {
+            let s = SerializedRecordBatchStream::write(&schema, batches.clone()).unwrap();
+            let r = s
+                .into_iter()
+                .map(|s| s.compress(TransportCompression::Lz4, 0)?.read())
+                .collect::<Result<Vec<_>, _>>();
+            assert_eq!(r.unwrap().len(), batches.len());
+        });
+    }
+
+    /// Conversion of the same batches to rows, as it is done for the final results only.
+    #[bench]
+    fn convert_to_rows(b: &mut Bencher) {
+        let batches = vec![test_batch(64 * 1024); 16];
+        b.iter(|| {
+            let r = batch_to_dataframe(&batches).unwrap();
+            assert_eq!(r.get_rows().len(), 16 * 64 * 1024);
+        });
+    }
+}
@@ -0,0 +1,261 @@
+//! `SET TRANSACTION SNAPSHOT` pins the partitions and chunks that selects of the connection read,
+//! so several queries, e.g. of a single dashboard page, add up to consistent totals:
+//!     SET TRANSACTION SNAPSHOT;
+//!     SELECT SUM(amount) FROM s.orders;
+//!     SELECT country, SUM(amount) FROM s.orders GROUP BY 1;
+//!     SET TRANSACTION SNAPSHOT NONE;
+//! Partitions and chunks of an index are pinned when a select of the connection reads the index
+//! for the first time, much like the read view of a MySQL transaction starts with its first read.
+//! So the snapshot only holds indexes the connection reads and changes made between `SET
+//! TRANSACTION SNAPSHOT` and the first select are visible. Indexes read for the first time by later
+//! selects are pinned as they are at that point, dropped tables can't be read.
+//!
+//! Files that compaction replaces are kept for [crate::config::ConfigObj::not_used_timeout],
+//! selects fail once the snapshot is older than that.
+use crate::metastore::table::Table;
+use crate::metastore::{Chunk, IdRow, Index, MetaStore, Partition, Schema};
+use crate::queryplanner::planning::PlanIndexStore;
+use crate::store::leases::{DataFileLease, DataFileLeases};
+use crate::CubeError;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct ReadSnapshot {
+    taken_at: DateTime<Utc>,
+    /// Keyed by index id.
+    partitions: Mutex<HashMap<u64, Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>>,
+}
+
+impl ReadSnapshot {
+    pub fn new() -> ReadSnapshot {
+        ReadSnapshot {
+            taken_at: Utc::now(),
+            partitions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fails once files of the snapshot may be removed.
+    pub fn check_expired(&self, not_used_timeout: Duration) -> Result<(), CubeError> {
+        let age = Utc::now()
+            .signed_duration_since(self.taken_at)
+            .to_std()
+            .unwrap_or_default();
+        if not_used_timeout <= age {
+            return Err(CubeError::user(format!(
+                "Transaction snapshot taken at {} has expired, set a new one with SET TRANSACTION SNAPSHOT",
+                self.taken_at
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Reads partitions and chunks from the snapshot, if there is one. Indexes missing in the snapshot
+/// are added to it. Files of partitions and chunks are leased right after they are read, see
+/// [SnapshotIndexStore::take_lease].
+pub struct SnapshotIndexStore<'a> {
+    meta_store: &'a dyn MetaStore,
+    snapshot: Option<&'a ReadSnapshot>,
+    file_leases: Option<&'a Arc<DataFileLeases>>,
+    lease: Mutex<Option<DataFileLease>>,
+}
+
+impl<'a> SnapshotIndexStore<'a> {
+    pub fn new(
+        meta_store: &'a dyn MetaStore,
+        snapshot: Option<&'a ReadSnapshot>,
+        file_leases: Option<&'a Arc<DataFileLeases>>,
+    ) -> SnapshotIndexStore<'a> {
+        SnapshotIndexStore {
+            meta_store,
+            snapshot,
+            file_leases,
+            lease: Mutex::new(None),
+        }
+    }
+
+    /// Lease on files of all partitions and chunks read so far.
+    pub fn take_lease(&self) -> Option<DataFileLease> {
+        self.lease.lock().unwrap().take()
+    }
+
+    fn lease_files(&self, partitions: &[Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>]) {
+        let file_leases = match self.file_leases {
+            Some(l) => l,
+            None => return,
+        };
+        let mut files = Vec::new();
+        for (partition, chunks) in partitions.iter().flat_map(|p| p.iter()) {
+            files.extend(partition.get_row().get_full_name(partition.get_id()));
+            files.extend(chunks.iter().map(|c| c.get_row().get_full_name(c.get_id())));
+        }
+        let lease = file_leases.acquire(files);
+        let mut held = self.lease.lock().unwrap();
+        match held.as_mut() {
+            Some(held) => held.extend(lease),
+            None => *held = Some(lease),
+        }
+    }
+
+    async fn read_partitions(
+        &self,
+        index_id: Vec<u64>,
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError> {
+        let snapshot = match self.snapshot {
+            Some(s) => s,
+            None => {
+                return self
+                    .meta_store
+                    .get_active_partitions_and_chunks_by_index_id_for_select(index_id)
+                    .await
+            }
+        };
+        let missing = {
+            let pinned = snapshot.partitions.lock().unwrap();
+            index_id
+                .iter()
+                .filter(|id| !pinned.contains_key(id))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        if !missing.is_empty() {
+            let partitions = self
+                .meta_store
+                .get_active_partitions_and_chunks_by_index_id_for_select(missing.clone())
+                .await?;
+            let mut pinned = snapshot.partitions.lock().unwrap();
+            for (id, p) in missing.into_iter().zip(partitions) {
+                // Concurrent selects of the connection may have pinned the index already.
+                pinned.entry(id).or_insert(p);
+            }
+        }
+        let pinned = snapshot.partitions.lock().unwrap();
+        Ok(index_id.iter().map(|id| pinned[id].clone()).collect())
+    }
+}
+
+#[async_trait]
+impl<'a> PlanIndexStore for SnapshotIndexStore<'a> {
+    async fn get_tables_with_indexes(
+        &self,
+        inputs: Vec<(String, String)>,
+    ) -> Result<Vec<(IdRow<Schema>, IdRow<Table>, Vec<IdRow<Index>>)>, CubeError> {
+        self.meta_store.get_tables_with_indexes(inputs).await
+    }
+
+    async fn get_active_partitions_and_chunks_by_index_id_for_select(
+        &self,
+        index_id: Vec<u64>,
+    ) -> Result<Vec<Arc<Vec<(IdRow<Partition>, Vec<IdRow<Chunk>>)>>>, CubeError> {
+        let partitions = self.read_partitions(index_id).await?;
+        self.lease_files(&partitions);
+        Ok(partitions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{Column, ColumnType, RocksMetaStore};
+
+    /// Number of chunks of the first partition of the index.
+    async fn chunks(
+        meta_store: &dyn MetaStore,
+        snapshot: Option<&ReadSnapshot>,
+        index_id: u64,
+    ) -> usize {
+        let store = SnapshotIndexStore::new(meta_store, snapshot, None);
+        let partitions = store
+            .get_active_partitions_and_chunks_by_index_id_for_select(vec![index_id])
+            .await
+            .unwrap();
+        partitions[0][0].1.len()
+    }
+
+    #[tokio::test]
+    async fn snapshot_pins_on_first_read() {
+        let (_, metastore) = RocksMetaStore::prepare_test_metastore("read_snapshot");
+        metastore
+            .create_schema("foo".to_string(), false)
+            .await
+            .unwrap();
+        metastore
+            .create_table(
+                "foo".to_string(),
+                "bar".to_string(),
+                vec![Column::new("n".to_string(), ColumnType::Int, 0)],
+                None,
+                None,
+                vec![],
+                true,
+                None,
+                None,
+                None,
+                vec![],
+                false,
+            )
+            .await
+            .unwrap();
+        let index = metastore.get_default_index(1).await.unwrap();
+        let partition = metastore.get_partition(1).await.unwrap();
+
+        let snapshot = ReadSnapshot::new();
+        // Nothing is pinned before the first read.
+        metastore
+            .create_chunk(partition.get_id(), 10)
+            .await
+            .unwrap();
+        metastore.chunk_uploaded(1).await.unwrap();
+        assert_eq!(
+            chunks(metastore.as_ref(), Some(&snapshot), index.get_id()).await,
+            1
+        );
+
+        metastore
+            .create_chunk(partition.get_id(), 10)
+            .await
+            .unwrap();
+        metastore.chunk_uploaded(2).await.unwrap();
+        assert_eq!(
+            chunks(metastore.as_ref(), Some(&snapshot), index.get_id()).await,
+            1
+        );
+        assert_eq!(chunks(metastore.as_ref(), None, index.get_id()).await, 2);
+        assert_eq!(snapshot.partitions.lock().unwrap().len(), 1);
+
+        // Files stay leased after the store is gone.
+        let leases = DataFileLeases::new();
+        let store = SnapshotIndexStore::new(metastore.as_ref(), Some(&snapshot), Some(&leases));
+        store
+            .get_active_partitions_and_chunks_by_index_id_for_select(vec![index.get_id()])
+            .await
+            .unwrap();
+        let chunk_file = metastore
+            .get_chunk(1)
+            .await
+            .unwrap()
+            .get_row()
+            .get_full_name(1);
+        let lease = store.take_lease();
+        drop(store);
+        assert!(leases.is_leased(&chunk_file));
+        assert!(!leases.is_leased(
+            &metastore
+                .get_chunk(2)
+                .await
+                .unwrap()
+                .get_row()
+                .get_full_name(2)
+        ));
+        drop(lease);
+        assert!(!leases.is_leased(&chunk_file));
+        assert!(snapshot.check_expired(Duration::from_secs(60)).is_ok());
+        assert!(snapshot.check_expired(Duration::from_secs(0)).is_err());
+
+        RocksMetaStore::cleanup_test_metastore("read_snapshot");
+    }
+}
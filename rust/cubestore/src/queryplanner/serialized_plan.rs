@@ -1,5 +1,7 @@
 use crate::metastore::table::{Table, TablePath};
 use crate::metastore::{Chunk, IdRow, Index, Partition};
+use crate::queryplanner::batch_cache::BatchCache;
+use crate::queryplanner::casts::CastOverflow;
 use crate::queryplanner::planning::ClusterSendNode;
 use crate::queryplanner::query_executor::CubeTable;
 use crate::queryplanner::topk::{ClusterAggregateTopK, SortColumn};
@@ -8,6 +10,10 @@ use crate::queryplanner::udfs::{
     aggregate_kind_by_name, scalar_kind_by_name, scalar_udf_by_kind, CubeAggregateUDFKind,
     CubeScalarUDFKind,
 };
+use crate::queryplanner::InfoSchemaTableProvider;
+use crate::sql::priority::QueryPriority;
+use crate::store::leases::DataFileLease;
+use crate::util::id_set::IdSet;
 use crate::CubeError;
 use arrow::datatypes::DataType;
 use datafusion::logical_plan::{
@@ -16,7 +22,8 @@ use datafusion::logical_plan::{
 use datafusion::physical_plan::{aggregates, functions};
 use datafusion::scalar::ScalarValue;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::sync::Arc;
 
@@ -24,7 +31,25 @@ use std::sync::Arc;
 pub struct SerializedPlan {
     logical_plan: Arc<SerializedLogicalPlan>,
     schema_snapshot: Arc<SchemaSnapshot>,
-    partition_ids_to_execute: HashSet<u64>,
+    partition_ids_to_execute: IdSet,
+    /// Rows in record batches produced by scans and aggregations. Nodes use
+    /// [crate::config::ConfigObj::query_batch_size] if it is not set for the query.
+    batch_size: Option<usize>,
+    /// Order in which selects waiting for a select process of a worker are executed.
+    priority: QueryPriority,
+    /// What sums out of range of their type produce, see
+    /// [crate::config::ConfigObj::sum_overflow]. Set by the router, so all nodes agree.
+    sum_overflow: CastOverflow,
+    /// Whether sums and averages of floats are computed exactly, see
+    /// [crate::config::ConfigObj::exact_float_sums].
+    exact_float_sums: bool,
+    /// See [crate::util::query_id].
+    query_id: Option<String>,
+    /// Set by the router for selects it may stop early, see [crate::cluster::running_selects].
+    select_id: Option<u64>,
+    /// Files of the plan stay leased while the router holds the plan. Not sent to workers.
+    #[serde(skip)]
+    file_lease: Option<Arc<DataFileLease>>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -38,6 +63,10 @@ pub struct IndexSnapshot {
     pub index: IdRow<Index>,
     pub partitions: Vec<PartitionSnapshot>,
     pub sort_on: Option<Vec<String>>,
+    /// Set when the table is replicated to all select workers, see
+    /// [crate::cluster::is_replicated_table]. Its partitions don't decide where joins run.
+    #[serde(default)]
+    pub replicated: bool,
 }
 
 impl IndexSnapshot {
@@ -60,6 +89,26 @@ impl IndexSnapshot {
     pub fn sort_on(&self) -> Option<&Vec<String>> {
         self.sort_on.as_ref()
     }
+
+    pub fn replicated(&self) -> bool {
+        self.replicated
+    }
+
+    /// Returns a copy of the snapshot that only references partitions from `partition_ids`.
+    pub fn retain_partitions(&self, partition_ids: &IdSet) -> IndexSnapshot {
+        IndexSnapshot {
+            table_path: self.table_path.clone(),
+            index: self.index.clone(),
+            partitions: self
+                .partitions
+                .iter()
+                .filter(|p| partition_ids.contains(&p.partition.get_id()))
+                .cloned()
+                .collect(),
+            sort_on: self.sort_on.clone(),
+            replicated: self.replicated,
+        }
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -139,6 +188,8 @@ pub enum SerializedLogicalPlan {
     ClusterSend {
         input: Arc<SerializedLogicalPlan>,
         snapshots: Vec<Vec<IndexSnapshot>>,
+        #[serde(default)]
+        inner_join: bool,
     },
     ClusterAggregateTopK {
         limit: usize,
@@ -151,6 +202,42 @@ pub enum SerializedLogicalPlan {
     },
 }
 
+/// Shared by all nodes of a plan while it is reconstructed.
+struct LogicalPlanContext {
+    remote_to_local_names: Arc<HashMap<String, String>>,
+    worker_partition_ids: IdSet,
+    batch_cache: Option<Arc<BatchCache>>,
+    /// Keyed by index id and ids of its partitions.
+    tables: RefCell<HashMap<(u64, Vec<u64>), Arc<CubeTable>>>,
+}
+
+impl LogicalPlanContext {
+    /// Scans of the same partitions, e.g. in self joins or unions of a table with itself, share a
+    /// table.
+    fn worker_table(&self, table: &CubeTable) -> Arc<CubeTable> {
+        let snapshot = table.index_snapshot();
+        let key = (
+            snapshot.index().get_id(),
+            snapshot
+                .partitions()
+                .iter()
+                .map(|p| p.partition().get_id())
+                .collect(),
+        );
+        self.tables
+            .borrow_mut()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(table.to_worker_table(
+                    self.remote_to_local_names.clone(),
+                    self.worker_partition_ids.clone(),
+                    self.batch_cache.clone(),
+                ))
+            })
+            .clone()
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum SerializePartitioning {
     RoundRobinBatch(usize),
@@ -158,11 +245,139 @@ pub enum SerializePartitioning {
 }
 
 impl SerializedLogicalPlan {
-    fn logical_plan(
-        &self,
-        remote_to_local_names: &HashMap<String, String>,
-        worker_partition_ids: &HashSet<u64>,
-    ) -> Result<LogicalPlan, CubeError> {
+    /// Drops metadata of partitions and chunks not listed in `partition_ids` from all table
+    /// sources and cluster sends.
+    fn retain_partitions(&self, partition_ids: &IdSet) -> SerializedLogicalPlan {
+        let retain = |p: &Arc<SerializedLogicalPlan>| Arc::new(p.retain_partitions(partition_ids));
+        let retain_snapshots = |snapshots: &Vec<Vec<IndexSnapshot>>| {
+            snapshots
+                .iter()
+                .map(|union| {
+                    union
+                        .iter()
+                        .map(|i| i.retain_partitions(partition_ids))
+                        .collect()
+                })
+                .collect()
+        };
+        match self {
+            SerializedLogicalPlan::Projection {
+                expr,
+                input,
+                schema,
+            } => SerializedLogicalPlan::Projection {
+                expr: expr.clone(),
+                input: retain(input),
+                schema: schema.clone(),
+            },
+            SerializedLogicalPlan::Filter { predicate, input } => SerializedLogicalPlan::Filter {
+                predicate: predicate.clone(),
+                input: retain(input),
+            },
+            SerializedLogicalPlan::Aggregate {
+                input,
+                group_expr,
+                aggr_expr,
+                schema,
+            } => SerializedLogicalPlan::Aggregate {
+                input: retain(input),
+                group_expr: group_expr.clone(),
+                aggr_expr: aggr_expr.clone(),
+                schema: schema.clone(),
+            },
+            SerializedLogicalPlan::Sort { expr, input } => SerializedLogicalPlan::Sort {
+                expr: expr.clone(),
+                input: retain(input),
+            },
+            SerializedLogicalPlan::Union {
+                inputs,
+                schema,
+                alias,
+            } => SerializedLogicalPlan::Union {
+                inputs: inputs.iter().map(retain).collect(),
+                schema: schema.clone(),
+                alias: alias.clone(),
+            },
+            SerializedLogicalPlan::Join {
+                left,
+                right,
+                on,
+                join_type,
+                schema,
+            } => SerializedLogicalPlan::Join {
+                left: retain(left),
+                right: retain(right),
+                on: on.clone(),
+                join_type: join_type.clone(),
+                schema: schema.clone(),
+            },
+            SerializedLogicalPlan::TableScan {
+                table_name,
+                source,
+                projection,
+                projected_schema,
+                filters,
+                alias,
+                limit,
+            } => SerializedLogicalPlan::TableScan {
+                table_name: table_name.clone(),
+                source: match source {
+                    SerializedTableSource::CubeTable(t) => {
+                        SerializedTableSource::CubeTable(t.retain_partitions(partition_ids))
+                    }
+                },
+                projection: projection.clone(),
+                projected_schema: projected_schema.clone(),
+                filters: filters.clone(),
+                alias: alias.clone(),
+                limit: limit.clone(),
+            },
+            SerializedLogicalPlan::EmptyRelation { .. } => self.clone(),
+            SerializedLogicalPlan::Limit { n, input } => SerializedLogicalPlan::Limit {
+                n: *n,
+                input: retain(input),
+            },
+            SerializedLogicalPlan::Skip { n, input } => SerializedLogicalPlan::Skip {
+                n: *n,
+                input: retain(input),
+            },
+            SerializedLogicalPlan::Repartition {
+                input,
+                partitioning_scheme,
+            } => SerializedLogicalPlan::Repartition {
+                input: retain(input),
+                partitioning_scheme: partitioning_scheme.clone(),
+            },
+            SerializedLogicalPlan::ClusterSend {
+                input,
+                snapshots,
+                inner_join,
+            } => SerializedLogicalPlan::ClusterSend {
+                input: retain(input),
+                snapshots: retain_snapshots(snapshots),
+                inner_join: *inner_join,
+            },
+            SerializedLogicalPlan::ClusterAggregateTopK {
+                limit,
+                input,
+                group_expr,
+                aggregate_expr,
+                sort_columns,
+                schema,
+                snapshots,
+            } => SerializedLogicalPlan::ClusterAggregateTopK {
+                limit: *limit,
+                input: retain(input),
+                group_expr: group_expr.clone(),
+                aggregate_expr: aggregate_expr.clone(),
+                sort_columns: sort_columns.clone(),
+                schema: schema.clone(),
+                snapshots: retain_snapshots(snapshots),
+            },
+        }
+    }
+
+    fn logical_plan(&self, ctx: &LogicalPlanContext) -> Result<LogicalPlan, CubeError> {
         Ok(match self {
             SerializedLogicalPlan::Projection {
                 expr,
@@ -170,12 +385,12 @@ impl SerializedLogicalPlan {
                 schema,
             } => LogicalPlan::Projection {
                 expr: expr.iter().map(|e| e.expr()).collect(),
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
                 schema: schema.clone(),
             },
             SerializedLogicalPlan::Filter { predicate, input } => LogicalPlan::Filter {
                 predicate: predicate.expr(),
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
             },
             SerializedLogicalPlan::Aggregate {
                 input,
@@ -185,12 +400,12 @@ impl SerializedLogicalPlan {
             } => LogicalPlan::Aggregate {
                 group_expr: group_expr.iter().map(|e| e.expr()).collect(),
                 aggr_expr: aggr_expr.iter().map(|e| e.expr()).collect(),
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
                 schema: schema.clone(),
             },
             SerializedLogicalPlan::Sort { expr, input } => LogicalPlan::Sort {
                 expr: expr.iter().map(|e| e.expr()).collect(),
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
             },
             SerializedLogicalPlan::Union {
                 inputs,
@@ -199,9 +414,7 @@ impl SerializedLogicalPlan {
             } => LogicalPlan::Union {
                 inputs: inputs
                     .iter()
-                    .map(|p| -> Result<LogicalPlan, CubeError> {
-                        Ok(p.logical_plan(remote_to_local_names, worker_partition_ids)?)
-                    })
+                    .map(|p| p.logical_plan(ctx))
                     .collect::<Result<Vec<_>, _>>()?,
                 schema: schema.clone(),
                 alias: alias.clone(),
@@ -217,10 +430,7 @@ impl SerializedLogicalPlan {
             } => LogicalPlan::TableScan {
                 table_name: table_name.clone(),
                 source: match source {
-                    SerializedTableSource::CubeTable(v) => Arc::new(v.to_worker_table(
-                        remote_to_local_names.clone(),
-                        worker_partition_ids.clone(),
-                    )),
+                    SerializedTableSource::CubeTable(v) => ctx.worker_table(v),
                 },
                 projection: projection.clone(),
                 projected_schema: projected_schema.clone(),
@@ -237,11 +447,11 @@ impl SerializedLogicalPlan {
             },
             SerializedLogicalPlan::Limit { n, input } => LogicalPlan::Limit {
                 n: *n,
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
             },
             SerializedLogicalPlan::Skip { n, input } => LogicalPlan::Skip {
                 n: *n,
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
             },
             SerializedLogicalPlan::Join {
                 left,
@@ -250,8 +460,8 @@ impl SerializedLogicalPlan {
                 join_type,
                 schema,
             } => LogicalPlan::Join {
-                left: Arc::new(left.logical_plan(remote_to_local_names, worker_partition_ids)?),
-                right: Arc::new(right.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                left: Arc::new(left.logical_plan(ctx)?),
+                right: Arc::new(right.logical_plan(ctx)?),
                 on: on.clone(),
                 join_type: join_type.clone(),
                 schema: schema.clone(),
@@ -260,7 +470,7 @@ impl SerializedLogicalPlan {
                 input,
                 partitioning_scheme,
             } => LogicalPlan::Repartition {
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
                 partitioning_scheme: match partitioning_scheme {
                     SerializePartitioning::RoundRobinBatch(s) => Partitioning::RoundRobinBatch(*s),
                     SerializePartitioning::Hash(e, s) => {
@@ -268,9 +478,14 @@ impl SerializedLogicalPlan {
                     }
                 },
             },
-            SerializedLogicalPlan::ClusterSend { input, snapshots } => ClusterSendNode {
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+            SerializedLogicalPlan::ClusterSend {
+                input,
+                snapshots,
+                inner_join,
+            } => ClusterSendNode {
+                input: Arc::new(input.logical_plan(ctx)?),
                 snapshots: snapshots.clone(),
+                inner_join: *inner_join,
             }
             .into_plan(),
             SerializedLogicalPlan::ClusterAggregateTopK {
@@ -283,7 +498,7 @@ impl SerializedLogicalPlan {
                 snapshots,
             } => ClusterAggregateTopK {
                 limit: *limit,
-                input: Arc::new(input.logical_plan(remote_to_local_names, worker_partition_ids)?),
+                input: Arc::new(input.logical_plan(ctx)?),
                 group_expr: group_expr.iter().map(|e| e.expr()).collect(),
                 aggregate_expr: aggregate_expr.iter().map(|e| e.expr()).collect(),
                 order_by: sort_columns.clone(),
@@ -363,6 +578,7 @@ pub enum SerializedExpr {
 }
 
 impl SerializedExpr {
+    /// [Expr] owns its column names, so they are copied rather than interned.
     fn expr(&self) -> Expr {
         match self {
             SerializedExpr::Alias(e, a) => Expr::Alias(Box::new(e.expr()), a.to_string()),
@@ -467,28 +683,129 @@ impl SerializedPlan {
         Ok(SerializedPlan {
             logical_plan: Arc::new(serialized_logical_plan),
             schema_snapshot: Arc::new(SchemaSnapshot { index_snapshots }),
-            partition_ids_to_execute: HashSet::new(),
+            partition_ids_to_execute: IdSet::new(),
+            batch_size: None,
+            priority: QueryPriority::Normal,
+            sum_overflow: CastOverflow::Error,
+            exact_float_sums: false,
+            query_id: None,
+            select_id: None,
+            file_lease: None,
         })
     }
 
-    pub fn with_partition_id_to_execute(&self, partition_ids_to_execute: HashSet<u64>) -> Self {
+    /// Produces a plan for a worker that executes `partition_ids_to_execute`. Metadata of other
+    /// partitions is dropped to keep plans sent over the network small for tables with a lot of
+    /// partitions and chunks.
+    pub fn with_partition_id_to_execute(&self, partition_ids_to_execute: IdSet) -> Self {
         Self {
-            logical_plan: self.logical_plan.clone(),
-            schema_snapshot: self.schema_snapshot.clone(),
+            logical_plan: Arc::new(
+                self.logical_plan
+                    .retain_partitions(&partition_ids_to_execute),
+            ),
+            schema_snapshot: Arc::new(SchemaSnapshot {
+                index_snapshots: self
+                    .schema_snapshot
+                    .index_snapshots
+                    .iter()
+                    .map(|i| i.retain_partitions(&partition_ids_to_execute))
+                    .collect(),
+            }),
             partition_ids_to_execute,
+            batch_size: self.batch_size,
+            priority: self.priority,
+            sum_overflow: self.sum_overflow,
+            exact_float_sums: self.exact_float_sums,
+            query_id: self.query_id.clone(),
+            select_id: self.select_id,
+            file_lease: None,
+        }
+    }
+
+    pub fn partition_ids_to_execute(&self) -> &IdSet {
+        &self.partition_ids_to_execute
+    }
+
+    pub fn with_batch_size(self, batch_size: usize) -> Self {
+        Self {
+            batch_size: Some(batch_size),
+            ..self
+        }
+    }
+
+    pub fn batch_size(&self) -> Option<usize> {
+        self.batch_size
+    }
+
+    pub fn with_priority(self, priority: QueryPriority) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn priority(&self) -> QueryPriority {
+        self.priority
+    }
+
+    pub fn with_sum_overflow(self, sum_overflow: CastOverflow) -> Self {
+        Self {
+            sum_overflow,
+            ..self
+        }
+    }
+
+    pub fn sum_overflow(&self) -> CastOverflow {
+        self.sum_overflow
+    }
+
+    pub fn with_exact_float_sums(self, exact_float_sums: bool) -> Self {
+        Self {
+            exact_float_sums,
+            ..self
+        }
+    }
+
+    pub fn exact_float_sums(&self) -> bool {
+        self.exact_float_sums
+    }
+
+    pub fn with_query_id(self, query_id: Option<String>) -> Self {
+        Self { query_id, ..self }
+    }
+
+    pub fn query_id(&self) -> Option<&String> {
+        self.query_id.as_ref()
+    }
+
+    pub fn with_select_id(self, select_id: Option<u64>) -> Self {
+        Self { select_id, ..self }
+    }
+
+    pub fn select_id(&self) -> Option<u64> {
+        self.select_id
+    }
+
+    pub fn with_file_lease(self, file_lease: Option<DataFileLease>) -> Self {
+        Self {
+            file_lease: file_lease.map(Arc::new),
+            ..self
         }
     }
 
-    pub fn partition_ids_to_execute(&self) -> HashSet<u64> {
-        self.partition_ids_to_execute.clone()
+    /// See [crate::store::leases].
+    pub fn file_lease(&self) -> Option<Arc<DataFileLease>> {
+        self.file_lease.clone()
     }
 
     pub fn logical_plan(
         &self,
-        remote_to_local_names: &HashMap<String, String>,
+        remote_to_local_names: HashMap<String, String>,
+        batch_cache: Option<Arc<BatchCache>>,
     ) -> Result<LogicalPlan, CubeError> {
-        self.logical_plan
-            .logical_plan(remote_to_local_names, &self.partition_ids_to_execute())
+        self.logical_plan.logical_plan(&LogicalPlanContext {
+            remote_to_local_names: Arc::new(remote_to_local_names),
+            worker_partition_ids: self.partition_ids_to_execute.clone(),
+            batch_cache,
+            tables: RefCell::new(HashMap::new()),
+        })
     }
 
     pub fn index_snapshots(&self) -> &Vec<IndexSnapshot> {
@@ -533,9 +850,8 @@ impl SerializedPlan {
             type Error = ();
 
             fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<bool, Self::Error> {
-                if let LogicalPlan::TableScan { table_name, .. } = plan {
-                    let name_split = table_name.split(".").collect::<Vec<_>>();
-                    if name_split[0].to_string() != "information_schema" {
+                if let LogicalPlan::TableScan { source, .. } = plan {
+                    if !source.as_any().is::<InfoSchemaTableProvider>() {
                         self.seen_data_scans = true;
                         return Ok(false);
                     }
@@ -627,6 +943,7 @@ impl SerializedPlan {
                     SerializedLogicalPlan::ClusterSend {
                         input: Arc::new(Self::serialized_logical_plan(&cs.input)),
                         snapshots: cs.snapshots.clone(),
+                        inner_join: cs.inner_join,
                     }
                 } else if let Some(topk) = node.as_any().downcast_ref::<ClusterAggregateTopK>() {
                     SerializedLogicalPlan::ClusterAggregateTopK {
@@ -791,3 +1108,54 @@ impl SerializedPlan {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::{Column, ColumnType, Schema};
+
+    fn cube_table(partition_ids: &[u64]) -> CubeTable {
+        let columns = vec![Column::new("a".to_string(), ColumnType::Int, 0)];
+        let table = Table::new(
+            "t".to_string(),
+            1,
+            columns.clone(),
+            None,
+            None,
+            true,
+            None,
+            None,
+        );
+        let index = Index::try_new("default".to_string(), 1, columns, 1).unwrap();
+        let snapshot = IndexSnapshot {
+            table_path: TablePath {
+                table: IdRow::new(1, table),
+                schema: Arc::new(IdRow::new(1, Schema::new("s".to_string()))),
+            },
+            index: IdRow::new(2, index),
+            partitions: partition_ids
+                .iter()
+                .map(|id| PartitionSnapshot {
+                    partition: IdRow::new(*id, Partition::new(2, None, None)),
+                    chunks: Vec::new(),
+                })
+                .collect(),
+            sort_on: None,
+            replicated: false,
+        };
+        CubeTable::try_new(snapshot, HashMap::new(), IdSet::new()).unwrap()
+    }
+
+    #[test]
+    fn scans_of_same_partitions_share_table() {
+        let ctx = LogicalPlanContext {
+            remote_to_local_names: Arc::new(HashMap::new()),
+            worker_partition_ids: IdSet::new(),
+            batch_cache: None,
+            tables: RefCell::new(HashMap::new()),
+        };
+        let table = ctx.worker_table(&cube_table(&[1, 2]));
+        assert!(Arc::ptr_eq(&table, &ctx.worker_table(&cube_table(&[1, 2]))));
+        assert!(!Arc::ptr_eq(&table, &ctx.worker_table(&cube_table(&[1]))));
+    }
+}
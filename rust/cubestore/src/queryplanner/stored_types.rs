@@ -0,0 +1,527 @@
+//! Column types stored in a more compact form than queries see them:
+//!   - UUID columns keep 16 bytes of each value, queries see strings in the canonical form, e.g.
+//!     `67e55044-10b1-426f-9247-bb680e5fe0c8`.
+//!   - ENUM columns keep positions of values in the list declared in the table, queries see the
+//!     values themselves.
+//!   - INET and CIDR columns keep IP addresses as 16 bytes, IPv4 addresses are mapped to IPv6, so
+//!     all addresses are ordered numerically. CIDR values also keep the length of the prefix.
+//!     Queries see strings, e.g. `10.1.2.3` and `10.0.0.0/8`.
+//! E.g. for:
+//!     CREATE TABLE s.events (id uuid, plan ENUM('free', 'pro'), n int)
+//! tables are replaced by subqueries that convert the values before planning, so:
+//!     SELECT n FROM s.events e WHERE e.id = '67E55044-10B1-426F-9247-BB680E5FE0C8'
+//! becomes:
+//!     SELECT n FROM (SELECT UUID_TO_STRING(id) AS id,
+//!                           CASE plan WHEN 0 THEN 'free' WHEN 1 THEN 'pro' END AS plan, n
+//!                    FROM s.events) AS e
+//!     WHERE e.id = '67e55044-10b1-426f-9247-bb680e5fe0c8'
+//! Canonical UUID strings are ordered the same way as the bytes, IP addresses are not. Filters on
+//! the converted values, including `IP_IN_CIDR`, are still used to prune partitions, see
+//! [crate::queryplanner::partition_filter].
+use crate::metastore::table::TablePath;
+use crate::metastore::{Column, ColumnType};
+use crate::queryplanner::collation::{function, table_name};
+use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+use crate::CubeError;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    BinaryOperator, DataType, Expr, FunctionArg, Ident, JoinConstraint, JoinOperator, Query,
+    Select, SelectItem, SetExpr, Statement, TableAlias, TableFactor, TableWithJoins, Value,
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use uuid::Uuid;
+
+/// Accepts the hyphenated and the simple forms in any case.
+pub fn parse_uuid(s: &str) -> Result<Vec<u8>, CubeError> {
+    Uuid::parse_str(s.trim())
+        .map(|u| u.as_bytes().to_vec())
+        .map_err(|e| CubeError::user(format!("Invalid UUID '{}': {}", s, e)))
+}
+
+/// Lowercase hyphenated form, `None` if `bytes` are not 16 bytes long.
+pub fn uuid_to_string(bytes: &[u8]) -> Option<String> {
+    Some(Uuid::from_slice(bytes).ok()?.to_hyphenated().to_string())
+}
+
+/// Stored form of UUID, INET and CIDR values.
+pub fn parse_stored_bytes(t: &ColumnType, s: &str) -> Result<Vec<u8>, CubeError> {
+    match t {
+        ColumnType::Uuid => parse_uuid(s),
+        ColumnType::Inet => parse_inet(s),
+        ColumnType::Cidr => parse_cidr(s),
+        t => Err(CubeError::internal(format!(
+            "Values of {:?} are not stored as bytes",
+            t
+        ))),
+    }
+}
+
+/// IPv4 addresses are mapped to IPv6, e.g. `10.1.2.3` is stored as `::ffff:10.1.2.3`.
+pub fn parse_inet(s: &str) -> Result<Vec<u8>, CubeError> {
+    let ip = s
+        .trim()
+        .parse::<IpAddr>()
+        .map_err(|e| CubeError::user(format!("Invalid IP address '{}': {}", s, e)))?;
+    Ok(ip_bytes(ip).to_vec())
+}
+
+/// Network address followed by the length of the prefix in the IPv6 address space. Bits of the
+/// address after the prefix must be zero, e.g. `10.0.0.0/8` is valid and `10.0.0.1/8` is not.
+pub fn parse_cidr(s: &str) -> Result<Vec<u8>, CubeError> {
+    let (address, len) = parse_network(s)?;
+    if (0..16).any(|i| address[i] & host_bits(len, i) != 0) {
+        return Err(CubeError::user(format!(
+            "Invalid CIDR '{}': bits are set to the right of the mask",
+            s
+        )));
+    }
+    let mut r = address.to_vec();
+    r.push(len);
+    Ok(r)
+}
+
+/// The first and the last stored address of the network.
+pub fn cidr_range(s: &str) -> Result<(Vec<u8>, Vec<u8>), CubeError> {
+    let (address, len) = parse_network(s)?;
+    let first = (0..16).map(|i| address[i] & !host_bits(len, i));
+    let last = (0..16).map(|i| address[i] | host_bits(len, i));
+    Ok((first.collect(), last.collect()))
+}
+
+pub fn ip_in_cidr(ip: &str, cidr: &str) -> Result<bool, CubeError> {
+    let ip = parse_inet(ip)?;
+    let (first, last) = cidr_range(cidr)?;
+    Ok(first <= ip && ip <= last)
+}
+
+/// Network of `ip` with the prefix of `len` bits, e.g. `10.1.0.0/16` for `10.1.2.3` and 16.
+pub fn ip_prefix(ip: &str, len: i64) -> Result<String, CubeError> {
+    let address = ip_bytes(
+        ip.trim()
+            .parse::<IpAddr>()
+            .map_err(|e| CubeError::user(format!("Invalid IP address '{}': {}", ip, e)))?,
+    );
+    let offset = if is_ipv4(&address) { 96 } else { 0 };
+    if len < 0 || 128 - offset < len {
+        return Err(CubeError::user(format!(
+            "Invalid prefix length {} of IP address '{}'",
+            len, ip
+        )));
+    }
+    let network = (0..16)
+        .map(|i| address[i] & !host_bits((len + offset) as u8, i))
+        .collect::<Vec<_>>();
+    let mut r = network;
+    r.push((len + offset) as u8);
+    Ok(ip_to_string(&r).unwrap())
+}
+
+/// Addresses of INET and networks of CIDR values, `None` if `bytes` are not a stored value.
+pub fn ip_to_string(bytes: &[u8]) -> Option<String> {
+    let mut address = [0; 16];
+    address.copy_from_slice(bytes.get(0..16)?);
+    let ip = if is_ipv4(&address) {
+        IpAddr::V4(Ipv4Addr::new(
+            address[12],
+            address[13],
+            address[14],
+            address[15],
+        ))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(address))
+    };
+    match bytes.len() {
+        16 => Some(ip.to_string()),
+        17 if ip.is_ipv4() => Some(format!("{}/{}", ip, bytes[16].checked_sub(96)?)),
+        17 => Some(format!("{}/{}", ip, bytes[16])),
+        _ => None,
+    }
+}
+
+fn ip_bytes(ip: IpAddr) -> [u8; 16] {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped().octets(),
+        IpAddr::V6(ip) => ip.octets(),
+    }
+}
+
+fn is_ipv4(address: &[u8; 16]) -> bool {
+    address[..10].iter().all(|b| *b == 0) && address[10] == 0xff && address[11] == 0xff
+}
+
+/// Address and the length of the prefix in the IPv6 address space, the whole address if the
+/// length is missing.
+fn parse_network(s: &str) -> Result<([u8; 16], u8), CubeError> {
+    let invalid = |e: String| CubeError::user(format!("Invalid CIDR '{}': {}", s, e));
+    let mut parts = s.trim().splitn(2, '/');
+    let ip = parts
+        .next()
+        .unwrap()
+        .parse::<IpAddr>()
+        .map_err(|e| invalid(e.to_string()))?;
+    let max_len = if ip.is_ipv4() { 32 } else { 128 };
+    let len = match parts.next() {
+        None => max_len,
+        Some(len) => match len.parse::<u8>() {
+            Ok(len) if len <= max_len => len,
+            _ => return Err(invalid(format!("invalid prefix length {}", len))),
+        },
+    };
+    Ok((ip_bytes(ip), len + (128 - max_len)))
+}
+
+/// Bits of the byte `i` of an address that are not in the prefix of `len` bits.
+fn host_bits(len: u8, i: usize) -> u8 {
+    let prefix_bits = (len as usize).saturating_sub(i * 8).min(8);
+    (0xffu16 >> prefix_bits) as u8
+}
+
+/// Position of `value` in `values` of an ENUM column, it is stored in place of the value.
+pub fn enum_position(column: &Column, values: &[String], value: &str) -> Result<i64, CubeError> {
+    match values.iter().position(|v| v == value) {
+        Some(p) => Ok(p as i64),
+        None => Err(CubeError::user(format!(
+            "Invalid value '{}' of column {}, expected one of: {}",
+            value,
+            column.get_name(),
+            values.join(", ")
+        ))),
+    }
+}
+
+/// `tables` are keyed by name in the `schema.table` form.
+pub fn expose_stored_types(
+    statement: &mut DFStatement,
+    tables: &HashMap<String, TablePath>,
+) -> Result<(), CubeError> {
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        visit_query(q, tables)?;
+    }
+    Ok(())
+}
+
+fn visit_query(q: &mut Query, tables: &HashMap<String, TablePath>) -> Result<(), CubeError> {
+    if let Some(with) = q.with.as_mut() {
+        for cte in with.cte_tables.iter_mut() {
+            visit_query(&mut cte.query, tables)?;
+        }
+    }
+    visit_set_expr(&mut q.body, tables)
+}
+
+fn visit_set_expr(e: &mut SetExpr, tables: &HashMap<String, TablePath>) -> Result<(), CubeError> {
+    match e {
+        SetExpr::Select(s) => visit_select(s, tables)?,
+        SetExpr::Query(q) => visit_query(q, tables)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, tables)?;
+            visit_set_expr(right, tables)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn visit_select(s: &mut Select, tables: &HashMap<String, TablePath>) -> Result<(), CubeError> {
+    let mut columns = HashMap::new();
+    for t in s.from.iter_mut() {
+        visit_table_with_joins(t, tables, &mut columns)?;
+    }
+    for t in s.from.iter_mut() {
+        for j in t.joins.iter_mut() {
+            match &mut j.join_operator {
+                JoinOperator::Inner(JoinConstraint::On(on))
+                | JoinOperator::LeftOuter(JoinConstraint::On(on))
+                | JoinOperator::RightOuter(JoinConstraint::On(on))
+                | JoinOperator::FullOuter(JoinConstraint::On(on)) => {
+                    visit_expr(on, tables, &columns)?
+                }
+                _ => {}
+            }
+        }
+    }
+    for p in s.projection.iter_mut() {
+        match p {
+            SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+                visit_expr(e, tables, &columns)?
+            }
+            _ => {}
+        }
+    }
+    if let Some(e) = s.selection.as_mut() {
+        visit_expr(e, tables, &columns)?;
+    }
+    if let Some(e) = s.having.as_mut() {
+        visit_expr(e, tables, &columns)?;
+    }
+    Ok(())
+}
+
+fn visit_table_with_joins(
+    t: &mut TableWithJoins,
+    tables: &HashMap<String, TablePath>,
+    columns: &mut HashMap<String, ColumnType>,
+) -> Result<(), CubeError> {
+    visit_table_factor(&mut t.relation, tables, columns)?;
+    for j in t.joins.iter_mut() {
+        visit_table_factor(&mut j.relation, tables, columns)?;
+    }
+    Ok(())
+}
+
+/// Replaces tables with columns of the stored types and collects types of columns compared with
+/// canonical strings.
+fn visit_table_factor(
+    t: &mut TableFactor,
+    tables: &HashMap<String, TablePath>,
+    columns: &mut HashMap<String, ColumnType>,
+) -> Result<(), CubeError> {
+    let (name, alias) = match t {
+        TableFactor::Table { name, alias, .. } => (name, alias),
+        TableFactor::Derived { subquery, .. } => return visit_query(subquery, tables),
+        TableFactor::NestedJoin(t) => return visit_table_with_joins(t, tables, columns),
+        _ => return Ok(()),
+    };
+    let table = match tables.get(&table_name(name)) {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+    let table_columns = table.table.get_row().get_columns();
+    if !table_columns.iter().any(|c| exposed_value(c).is_some()) {
+        return Ok(());
+    }
+    let projection = table_columns
+        .iter()
+        .map(|c| {
+            match c.get_column_type() {
+                t @ ColumnType::Uuid | t @ ColumnType::Inet | t @ ColumnType::Cidr => {
+                    columns.insert(c.get_name().clone(), t.clone());
+                }
+                _ => {}
+            }
+            match exposed_value(c) {
+                Some(expr) => SelectItem::ExprWithAlias {
+                    expr,
+                    alias: Ident::new(c.get_name()),
+                },
+                None => SelectItem::UnnamedExpr(Expr::Identifier(Ident::new(c.get_name()))),
+            }
+        })
+        .collect();
+    let mut subquery = match CubeStoreParser::new(&format!("SELECT * FROM {}", name))
+        .and_then(|mut p| p.parse_statement())?
+    {
+        CubeStatement::Statement(Statement::Query(q)) => q,
+        s => {
+            return Err(CubeError::internal(format!(
+                "Unexpected statement: {:?}",
+                s
+            )))
+        }
+    };
+    match &mut subquery.body {
+        SetExpr::Select(s) => s.projection = projection,
+        _ => unreachable!(),
+    }
+    let alias = alias.take().unwrap_or_else(|| TableAlias {
+        name: name.0.last().unwrap().clone(),
+        columns: Vec::new(),
+    });
+    *t = TableFactor::Derived {
+        lateral: false,
+        subquery,
+        alias: Some(alias),
+    };
+    Ok(())
+}
+
+/// Value of the column seen by queries, `None` if it is stored as is.
+fn exposed_value(c: &Column) -> Option<Expr> {
+    let column = Expr::Identifier(Ident::new(c.get_name()));
+    match c.get_column_type() {
+        ColumnType::Uuid => Some(function("UUID_TO_STRING", vec![column])),
+        ColumnType::Inet | ColumnType::Cidr => Some(function("IP_TO_STRING", vec![column])),
+        ColumnType::Enum(values) => Some(Expr::Case {
+            operand: Some(Box::new(column)),
+            conditions: (0..values.len())
+                .map(|i| Expr::Value(Value::Number(i.to_string(), false)))
+                .collect(),
+            results: values
+                .iter()
+                .map(|v| Expr::Value(Value::SingleQuotedString(v.clone())))
+                .collect(),
+            else_result: None,
+        }),
+        _ => None,
+    }
+}
+
+/// Brings literals compared with UUID, INET and CIDR columns into the canonical form, replaces
+/// casts to UUID and visits subqueries.
+fn visit_expr(
+    e: &mut Expr,
+    tables: &HashMap<String, TablePath>,
+    columns: &HashMap<String, ColumnType>,
+) -> Result<(), CubeError> {
+    match e {
+        Expr::BinaryOp { left, op, right } => {
+            let is_comparison = match op {
+                BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq => true,
+                _ => false,
+            };
+            if is_comparison {
+                if let Some(t) = column_type(left, columns) {
+                    canonicalize(right, t);
+                }
+                if let Some(t) = column_type(right, columns) {
+                    canonicalize(left, t);
+                }
+            }
+            visit_expr(left, tables, columns)?;
+            visit_expr(right, tables, columns)?;
+        }
+        Expr::InList { expr, list, .. } => {
+            if let Some(t) = column_type(expr, columns) {
+                list.iter_mut().for_each(|e| canonicalize(e, t));
+            }
+            visit_expr(expr, tables, columns)?;
+        }
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            if let Some(t) = column_type(expr, columns) {
+                canonicalize(low, t);
+                canonicalize(high, t);
+            }
+            visit_expr(expr, tables, columns)?;
+        }
+        Expr::Cast {
+            expr,
+            data_type: DataType::Uuid,
+        } => {
+            visit_expr(expr, tables, columns)?;
+            let value = expr.as_ref().clone();
+            *e = function("TO_UUID", vec![value]);
+        }
+        Expr::Cast { expr, .. } | Expr::UnaryOp { expr, .. } | Expr::Nested(expr) => {
+            visit_expr(expr, tables, columns)?
+        }
+        Expr::Function(f) => {
+            for a in f.args.iter_mut() {
+                if let FunctionArg::Unnamed(a) = a {
+                    visit_expr(a, tables, columns)?;
+                }
+            }
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            visit_expr(expr, tables, columns)?;
+            visit_query(subquery, tables)?;
+        }
+        Expr::Subquery(q) | Expr::Exists(q) => visit_query(q, tables)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn column_type<'a>(e: &Expr, columns: &'a HashMap<String, ColumnType>) -> Option<&'a ColumnType> {
+    match e {
+        Expr::Identifier(i) => columns.get(&i.value),
+        Expr::CompoundIdentifier(parts) => columns.get(&parts.last()?.value),
+        _ => None,
+    }
+}
+
+/// Strings that are not values of the type are kept as is, they are compared as strings.
+fn canonicalize(e: &mut Expr, t: &ColumnType) {
+    if let Expr::Value(Value::SingleQuotedString(s)) = e {
+        let canonical = match t {
+            ColumnType::Uuid => parse_uuid(s).ok().and_then(|b| uuid_to_string(&b)),
+            ColumnType::Inet => parse_inet(s).ok().and_then(|b| ip_to_string(&b)),
+            ColumnType::Cidr => parse_cidr(s).ok().and_then(|b| ip_to_string(&b)),
+            _ => None,
+        };
+        if let Some(canonical) = canonical {
+            *s = canonical;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuid_strings() {
+        let bytes = parse_uuid("67E55044-10B1-426F-9247-BB680E5FE0C8").unwrap();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(
+            parse_uuid("67e5504410b1426f9247bb680e5fe0c8").unwrap(),
+            bytes
+        );
+        assert_eq!(
+            uuid_to_string(&bytes).unwrap(),
+            "67e55044-10b1-426f-9247-bb680e5fe0c8"
+        );
+        assert!(parse_uuid("67e55044-10b1-426f").is_err());
+        assert_eq!(uuid_to_string(b"short"), None);
+
+        let mut e = Expr::Value(Value::SingleQuotedString(
+            "67E55044-10B1-426F-9247-BB680E5FE0C8".to_string(),
+        ));
+        canonicalize(&mut e, &ColumnType::Uuid);
+        assert_eq!(e.to_string(), "'67e55044-10b1-426f-9247-bb680e5fe0c8'");
+    }
+
+    #[test]
+    fn ip_strings() {
+        let ip = parse_inet("10.1.2.3").unwrap();
+        assert_eq!(ip.len(), 16);
+        assert_eq!(ip_to_string(&ip).unwrap(), "10.1.2.3");
+        assert_eq!(
+            ip_to_string(&parse_inet("2001:DB8::1").unwrap()).unwrap(),
+            "2001:db8::1"
+        );
+        assert!(parse_inet("10.1.2").is_err());
+        assert!(parse_inet("10.0.0.9").unwrap() < parse_inet("10.0.0.10").unwrap());
+
+        assert_eq!(
+            ip_to_string(&parse_cidr("10.0.0.0/8").unwrap()).unwrap(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            ip_to_string(&parse_cidr("10.1.2.3").unwrap()).unwrap(),
+            "10.1.2.3/32"
+        );
+        assert_eq!(
+            ip_to_string(&parse_cidr("2001:db8::/32").unwrap()).unwrap(),
+            "2001:db8::/32"
+        );
+        assert!(parse_cidr("10.0.0.1/8").is_err());
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+
+        let (first, last) = cidr_range("10.0.0.0/8").unwrap();
+        assert_eq!(ip_to_string(&first).unwrap(), "10.0.0.0");
+        assert_eq!(ip_to_string(&last).unwrap(), "10.255.255.255");
+        let (first, last) = cidr_range("10.1.2.3/23").unwrap();
+        assert_eq!(ip_to_string(&first).unwrap(), "10.1.2.0");
+        assert_eq!(ip_to_string(&last).unwrap(), "10.1.3.255");
+        assert!(ip_in_cidr("10.200.0.1", "10.0.0.0/8").unwrap());
+        assert!(!ip_in_cidr("11.0.0.1", "10.0.0.0/8").unwrap());
+        assert!(!ip_in_cidr("::1", "0.0.0.0/0").unwrap());
+
+        assert_eq!(ip_prefix("10.1.2.3", 16).unwrap(), "10.1.0.0/16");
+        assert_eq!(ip_prefix("10.1.2.3", 0).unwrap(), "0.0.0.0/0");
+        assert_eq!(ip_prefix("2001:db8::1", 32).unwrap(), "2001:db8::/32");
+        assert!(ip_prefix("10.1.2.3", 33).is_err());
+
+        let mut e = Expr::Value(Value::SingleQuotedString("2001:DB8:0::1".to_string()));
+        canonicalize(&mut e, &ColumnType::Inet);
+        assert_eq!(e.to_string(), "'2001:db8::1'");
+    }
+}
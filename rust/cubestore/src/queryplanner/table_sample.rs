@@ -0,0 +1,162 @@
+//! `TABLESAMPLE SYSTEM (n PERCENT)` reaches the planner as a table hint, see [TABLESAMPLE_HINT].
+//! We remove the hints before planning and pass the sampling percentage to the table providers.
+//! Sampling is done on the level of partitions when choosing the index, see
+//! [crate::queryplanner::planning].
+use crate::sql::parser::TABLESAMPLE_HINT;
+use crate::CubeError;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{Expr, ObjectName, Query, SetExpr, Statement, TableFactor, TableWithJoins};
+use std::collections::HashMap;
+
+/// Returns sampling percentages by table name, in the `schema.table` form.
+pub fn extract_table_samples(
+    statement: &mut DFStatement,
+) -> Result<HashMap<String, f64>, CubeError> {
+    let mut samples = HashMap::new();
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        visit_query(q, &mut samples)?;
+    }
+    Ok(samples)
+}
+
+fn visit_query(q: &mut Query, samples: &mut HashMap<String, f64>) -> Result<(), CubeError> {
+    visit_set_expr(&mut q.body, samples)
+}
+
+fn visit_set_expr(e: &mut SetExpr, samples: &mut HashMap<String, f64>) -> Result<(), CubeError> {
+    match e {
+        SetExpr::Select(s) => {
+            for t in s.from.iter_mut() {
+                visit_table_with_joins(t, samples)?;
+            }
+        }
+        SetExpr::Query(q) => visit_query(q, samples)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, samples)?;
+            visit_set_expr(right, samples)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn visit_table_with_joins(
+    t: &mut TableWithJoins,
+    samples: &mut HashMap<String, f64>,
+) -> Result<(), CubeError> {
+    visit_table_factor(&mut t.relation, samples)?;
+    for j in t.joins.iter_mut() {
+        visit_table_factor(&mut j.relation, samples)?;
+    }
+    Ok(())
+}
+
+fn visit_table_factor(
+    t: &mut TableFactor,
+    samples: &mut HashMap<String, f64>,
+) -> Result<(), CubeError> {
+    match t {
+        TableFactor::Table {
+            name, with_hints, ..
+        } => {
+            let mut percent = None;
+            for h in with_hints.iter() {
+                if let Some(p) = sample_percent(h)? {
+                    percent = Some(p);
+                }
+            }
+            if let Some(percent) = percent {
+                with_hints.retain(|h| !is_sample_hint(h));
+                let name = table_name(name);
+                match samples.insert(name.clone(), percent) {
+                    Some(other) if other != percent => {
+                        return Err(CubeError::user(format!(
+                            "Table {} is sampled with different percentages",
+                            name
+                        )))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        TableFactor::Derived { subquery, .. } => visit_query(subquery, samples)?,
+        TableFactor::NestedJoin(t) => visit_table_with_joins(t, samples)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+fn is_sample_hint(e: &Expr) -> bool {
+    match e {
+        Expr::Function(f) => f.name.to_string() == TABLESAMPLE_HINT,
+        _ => false,
+    }
+}
+
+fn sample_percent(e: &Expr) -> Result<Option<f64>, CubeError> {
+    if !is_sample_hint(e) {
+        return Ok(None);
+    }
+    let args = match e {
+        Expr::Function(f) => &f.args,
+        _ => unreachable!(),
+    };
+    let percent = match args.as_slice() {
+        [a] => a.to_string().parse::<f64>().ok(),
+        _ => None,
+    };
+    match percent {
+        Some(p) if 0. <= p && p <= 100. => Ok(Some(p)),
+        _ => Err(CubeError::user(format!(
+            "Sampling percentage must be between 0 and 100, found: {}",
+            args.iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+fn table_name(name: &ObjectName) -> String {
+    name.0
+        .iter()
+        .map(|i| i.value.as_str())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStatement};
+
+    fn extract(s: &str) -> Result<(String, HashMap<String, f64>), CubeError> {
+        let mut s = match CubeStoreParser::new(s)?.parse_statement()? {
+            CubeStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        let samples = extract_table_samples(&mut s)?;
+        match s {
+            DFStatement::Statement(s) => Ok((s.to_string(), samples)),
+            _ => panic!("not a statement"),
+        }
+    }
+
+    #[test]
+    fn table_samples() {
+        let (sql, samples) = extract(
+            "SELECT * FROM s.t AS t TABLESAMPLE SYSTEM (10 PERCENT) \
+             JOIN (SELECT * FROM s.u TABLESAMPLE SYSTEM (0.5)) AS u ON t.a = u.a",
+        )
+        .unwrap();
+        assert_eq!(
+            sql,
+            "SELECT * FROM s.t AS t JOIN (SELECT * FROM s.u) AS u ON t.a = u.a"
+        );
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples["s.t"], 10.);
+        assert_eq!(samples["s.u"], 0.5);
+
+        assert!(extract("SELECT * FROM s.t TABLESAMPLE SYSTEM (150)").is_err());
+    }
+}
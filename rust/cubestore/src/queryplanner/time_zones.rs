@@ -0,0 +1,403 @@
+//! Time zone of a connection, set by `SET time_zone = 'Europe/Berlin'` and defaulting to
+//! [crate::config::ConfigObj::time_zone]. Timestamps are stored as UTC instants, the time zone
+//! changes how they are read and shown:
+//!   - `DATE_TRUNC` truncates to the local day, week, etc., e.g. days of `America/New_York` start
+//!     at 05:00 UTC in winter and at 04:00 UTC in summer. Selects call `CUBE_DATE_TRUNC` instead:
+//!         SELECT DATE_TRUNC('day', ts) FROM s.t
+//!     becomes:
+//!         SELECT CUBE_DATE_TRUNC('day', ts, 'America/New_York') FROM s.t
+//!     So `DATE_TRUNC('day', now())` is the start of the local day.
+//!   - Timestamp literals without an offset are local times. `CAST('2021-03-14 12:00:00' AS
+//!     TIMESTAMP)`, `TIMESTAMP '2021-03-14 12:00:00'` and `to_timestamp('2021-03-14 12:00:00')`
+//!     are replaced with the UTC instant before planning, so partition filters still understand
+//!     them.
+//!   - The MySQL server shows timestamps as local times with the offset, e.g.
+//!     `2021-03-14T12:00:00.000-04:00`. Results of the HTTP API are always in UTC.
+//! Offsets of named zones follow daylight saving time of the IANA database. Local times skipped
+//! by a switch are read with the offset before it, so `02:30` on the day clocks move from 02:00
+//! to 03:00 is `03:30`. Local times repeated by a switch are read as the first of the two.
+//! Zones may also be fixed offsets, e.g. `+05:30`, which MySQL clients often set.
+//!
+//! Pre-aggregations are built in the server time zone and are not used by connections in other
+//! time zones.
+use crate::queryplanner::collation::function;
+use crate::sql::temporary_functions::visit_query;
+use crate::CubeError;
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset,
+    TimeZone, Timelike, Utc,
+};
+use chrono_tz::Tz;
+use datafusion::sql::parser::Statement as DFStatement;
+use sqlparser::ast::{
+    DataType as SqlDataType, Expr, Function, FunctionArg, ObjectName, Statement, Value,
+};
+use std::fmt::Write;
+use std::str::FromStr;
+
+pub const TIME_ZONE_VARIABLE: &str = "time_zone";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum QueryTimeZone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+impl Default for QueryTimeZone {
+    fn default() -> Self {
+        QueryTimeZone::Named(Tz::UTC)
+    }
+}
+
+impl FromStr for QueryTimeZone {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") {
+            return Ok(QueryTimeZone::Named(Tz::UTC));
+        }
+        if s.starts_with('+') || s.starts_with('-') {
+            if let Some(o) = parse_offset(s) {
+                return Ok(QueryTimeZone::Fixed(o));
+            }
+        } else if let Ok(tz) = s.parse::<Tz>() {
+            return Ok(QueryTimeZone::Named(tz));
+        }
+        Err(CubeError::user(format!(
+            "Time zone should be a name of the IANA database, e.g. 'Europe/Berlin', or an \
+             offset, e.g. '+05:30', but found '{}'",
+            s
+        )))
+    }
+}
+
+fn parse_offset(s: &str) -> Option<FixedOffset> {
+    let sign = if s.starts_with('-') { -1 } else { 1 };
+    let mut parts = s[1..].splitn(2, ':');
+    let hours = parts.next()?.parse::<i32>().ok()?;
+    let minutes = parts.next().unwrap_or("0").parse::<i32>().ok()?;
+    if 14 < hours || 59 < minutes {
+        return None;
+    }
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+impl QueryTimeZone {
+    pub fn name(&self) -> String {
+        match self {
+            QueryTimeZone::Named(tz) => tz.name().to_string(),
+            QueryTimeZone::Fixed(o) => o.to_string(),
+        }
+    }
+
+    pub fn is_utc(&self) -> bool {
+        match self {
+            QueryTimeZone::Named(tz) => *tz == Tz::UTC,
+            QueryTimeZone::Fixed(o) => o.local_minus_utc() == 0,
+        }
+    }
+
+    /// Offset from UTC at the instant.
+    fn offset(&self, utc: &NaiveDateTime) -> FixedOffset {
+        match self {
+            QueryTimeZone::Named(tz) => tz.offset_from_utc_datetime(utc).fix(),
+            QueryTimeZone::Fixed(o) => *o,
+        }
+    }
+
+    pub fn local_time(&self, nanos: i64) -> NaiveDateTime {
+        let utc = Utc.timestamp_nanos(nanos).naive_utc();
+        utc + Duration::seconds(self.offset(&utc).local_minus_utc() as i64)
+    }
+
+    /// UTC instant of the local time, see the module docs for DST switches.
+    pub fn from_local_time(&self, local: &NaiveDateTime) -> i64 {
+        let tz = match self {
+            QueryTimeZone::Named(tz) => tz,
+            QueryTimeZone::Fixed(o) => {
+                return (*local - Duration::seconds(o.local_minus_utc() as i64)).timestamp_nanos()
+            }
+        };
+        let utc = match tz.from_local_datetime(local) {
+            LocalResult::Single(t) | LocalResult::Ambiguous(t, _) => t.naive_utc(),
+            LocalResult::None => {
+                let before = tz.offset_from_utc_datetime(&(*local - Duration::days(1)));
+                *local - Duration::seconds(before.fix().local_minus_utc() as i64)
+            }
+        };
+        utc.timestamp_nanos()
+    }
+
+    /// Same as [crate::table::TimestampValue::to_string] in UTC, otherwise the local time with
+    /// the offset.
+    pub fn write_timestamp(&self, out: &mut String, nanos: i64) {
+        let subsec = if nanos % 1_000_000 == 0 {
+            "%.3f"
+        } else if nanos % 1000 == 0 {
+            "%.6f"
+        } else {
+            "%.9f"
+        };
+        let t = Utc.timestamp_nanos(nanos);
+        if self.is_utc() {
+            write!(
+                out,
+                "{}{}Z",
+                t.format("%Y-%m-%dT%H:%M:%S"),
+                t.format(subsec)
+            )
+            .unwrap();
+            return;
+        }
+        let t: DateTime<FixedOffset> = t.with_timezone(&self.offset(&t.naive_utc()));
+        write!(
+            out,
+            "{}{}{}",
+            t.format("%Y-%m-%dT%H:%M:%S"),
+            t.format(subsec),
+            t.format("%:z")
+        )
+        .unwrap();
+    }
+}
+
+/// Truncates the timestamp to the start of the local second, minute, hour, day, week, month,
+/// quarter or year.
+pub fn date_trunc(granularity: &str, nanos: i64, tz: &QueryTimeZone) -> Result<i64, CubeError> {
+    let local = tz.local_time(nanos);
+    let date = local.date();
+    let truncated = match granularity.to_lowercase().as_str() {
+        // Units shorter than a day keep the offset of the value, so hours repeated by a DST
+        // switch are different buckets.
+        "second" => return Ok(nanos - local.nanosecond() as i64),
+        "minute" => {
+            return Ok(nanos - local.nanosecond() as i64 - 1_000_000_000 * local.second() as i64)
+        }
+        "hour" => {
+            let start = date.and_hms(local.hour(), 0, 0);
+            return Ok(nanos - (local - start).num_nanoseconds().unwrap());
+        }
+        "day" => date,
+        "week" => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        "month" => NaiveDate::from_ymd(date.year(), date.month(), 1),
+        "quarter" => NaiveDate::from_ymd(date.year(), (date.month() - 1) / 3 * 3 + 1, 1),
+        "year" => NaiveDate::from_ymd(date.year(), 1, 1),
+        g => {
+            return Err(CubeError::user(format!(
+                "Unsupported granularity of DATE_TRUNC: {}",
+                g
+            )))
+        }
+    };
+    Ok(tz.from_local_time(&truncated.and_hms(0, 0, 0)))
+}
+
+/// Applies the time zone to selects, see the module docs.
+pub fn rewrite_time_zones(
+    statement: &mut DFStatement,
+    tz: &QueryTimeZone,
+) -> Result<(), CubeError> {
+    if tz.is_utc() {
+        return Ok(());
+    }
+    if let DFStatement::Statement(Statement::Query(q)) = statement {
+        visit_query(q, &mut |e| rewrite_expr(e, tz))?;
+    }
+    Ok(())
+}
+
+fn rewrite_expr(e: &mut Expr, tz: &QueryTimeZone) -> Result<(), CubeError> {
+    match e {
+        Expr::Cast {
+            expr,
+            data_type: SqlDataType::Timestamp,
+        } => {
+            if let Expr::Value(Value::SingleQuotedString(s)) = expr.as_mut() {
+                localize_literal(s, tz)?;
+            }
+        }
+        Expr::TypedString {
+            data_type: SqlDataType::Timestamp,
+            value,
+        } => localize_literal(value, tz)?,
+        Expr::Function(Function { name, args, .. }) if args.len() == 1 => {
+            if is_function(name, "to_timestamp") {
+                if let FunctionArg::Unnamed(Expr::Value(Value::SingleQuotedString(s))) =
+                    &mut args[0]
+                {
+                    localize_literal(s, tz)?;
+                }
+            }
+        }
+        Expr::Function(Function { name, args, .. }) if args.len() == 2 => {
+            if is_function(name, "date_trunc") {
+                let args = args
+                    .iter()
+                    .map(|a| match a {
+                        FunctionArg::Unnamed(e) | FunctionArg::Named { arg: e, .. } => e.clone(),
+                    })
+                    .chain(std::iter::once(Expr::Value(Value::SingleQuotedString(
+                        tz.name(),
+                    ))))
+                    .collect();
+                *e = function("CUBE_DATE_TRUNC", args);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn is_function(name: &ObjectName, expected: &str) -> bool {
+    name.0.len() == 1 && name.0[0].value.eq_ignore_ascii_case(expected)
+}
+
+/// Replaces a local time with the UTC instant, values with an offset are kept.
+fn localize_literal(s: &mut String, tz: &QueryTimeZone) -> Result<(), CubeError> {
+    let local = match parse_local_time(s) {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+    let mut utc = String::new();
+    QueryTimeZone::default().write_timestamp(&mut utc, tz.from_local_time(&local));
+    *s = utc;
+    Ok(())
+}
+
+/// Parses timestamps without an offset in formats that [crate::sql::timestamp_from_string]
+/// accepts.
+fn parse_local_time(s: &str) -> Option<NaiveDateTime> {
+    let s = s.trim();
+    if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(t);
+    }
+    if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f") {
+        return Some(t);
+    }
+    if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Some(d.and_hms(0, 0, 0));
+    }
+    // Values with an offset are instants already.
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::{CubeStoreParser, Statement as CubeStoreStatement};
+
+    fn ts(s: &str) -> i64 {
+        s.parse::<DateTime<Utc>>().unwrap().timestamp_nanos()
+    }
+
+    fn tz(s: &str) -> QueryTimeZone {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn daily_buckets_follow_dst() {
+        let ny = tz("America/New_York");
+        // Winter, summer and both days of switches.
+        for (value, day) in &[
+            ("2021-01-15T03:00:00Z", "2021-01-14T05:00:00Z"),
+            ("2021-07-15T03:00:00Z", "2021-07-14T04:00:00Z"),
+            ("2021-03-14T12:00:00Z", "2021-03-14T05:00:00Z"),
+            ("2021-11-07T12:00:00Z", "2021-11-07T04:00:00Z"),
+        ] {
+            assert_eq!(
+                date_trunc("day", ts(value), &ny).unwrap(),
+                ts(day),
+                "{}",
+                value
+            );
+        }
+        assert_eq!(
+            date_trunc("month", ts("2021-11-01T03:00:00Z"), &ny).unwrap(),
+            ts("2021-10-01T04:00:00Z")
+        );
+        assert_eq!(
+            date_trunc("week", ts("2021-03-15T03:00:00Z"), &ny).unwrap(),
+            ts("2021-03-08T05:00:00Z")
+        );
+        // Hours repeated when clocks go back are separate buckets.
+        assert_eq!(
+            date_trunc("hour", ts("2021-11-07T05:30:00Z"), &ny).unwrap(),
+            ts("2021-11-07T05:00:00Z")
+        );
+        assert_eq!(
+            date_trunc("hour", ts("2021-11-07T06:30:00Z"), &ny).unwrap(),
+            ts("2021-11-07T06:00:00Z")
+        );
+        // Half-hour offsets.
+        assert_eq!(
+            date_trunc("hour", ts("2021-01-01T10:20:00Z"), &tz("Asia/Kolkata")).unwrap(),
+            ts("2021-01-01T10:00:00Z") - 30 * 60 * 1_000_000_000
+        );
+        // Midnight does not exist on the day of the switch in Santiago.
+        assert_eq!(
+            date_trunc("day", ts("2021-09-05T12:00:00Z"), &tz("America/Santiago")).unwrap(),
+            ts("2021-09-05T04:00:00Z")
+        );
+        assert!(date_trunc("decade", 0, &ny).is_err());
+    }
+
+    #[test]
+    fn local_times() {
+        let ny = tz("America/New_York");
+        let local = |s: &str| parse_local_time(s).unwrap();
+        assert_eq!(
+            ny.from_local_time(&local("2021-03-14 12:00:00")),
+            ts("2021-03-14T16:00:00Z")
+        );
+        assert_eq!(
+            ny.from_local_time(&local("2021-03-14T02:30:00")),
+            ts("2021-03-14T07:30:00Z")
+        );
+        assert_eq!(
+            ny.from_local_time(&local("2021-11-07 01:30:00")),
+            ts("2021-11-07T05:30:00Z")
+        );
+        assert_eq!(
+            tz("+05:30").from_local_time(&local("2021-01-01")),
+            ts("2020-12-31T18:30:00Z")
+        );
+        assert!(parse_local_time("2021-01-01T00:00:00Z").is_none());
+
+        let mut s = String::new();
+        ny.write_timestamp(&mut s, ts("2021-03-14T16:00:00.000001Z"));
+        assert_eq!(s, "2021-03-14T12:00:00.000001-04:00");
+        s.clear();
+        QueryTimeZone::default().write_timestamp(&mut s, ts("2021-03-14T16:00:00Z"));
+        assert_eq!(s, "2021-03-14T16:00:00.000Z");
+
+        assert_eq!(tz("utc"), QueryTimeZone::default());
+        assert!("Mars/Olympus_Mons".parse::<QueryTimeZone>().is_err());
+        assert!("+25:00".parse::<QueryTimeZone>().is_err());
+    }
+
+    #[test]
+    fn rewrite() {
+        let query = "SELECT DATE_TRUNC('day', ts), count(*) FROM s.t \
+                     WHERE ts >= CAST('2021-03-14 00:00:00' AS TIMESTAMP) \
+                     AND ts < to_timestamp('2021-03-15T00:00:00Z') GROUP BY 1";
+        let mut statement = match CubeStoreParser::new(query)
+            .unwrap()
+            .parse_statement()
+            .unwrap()
+        {
+            CubeStoreStatement::Statement(s) => DFStatement::Statement(s),
+            _ => panic!("not a statement"),
+        };
+        rewrite_time_zones(&mut statement, &tz("America/New_York")).unwrap();
+        let sql = match statement {
+            DFStatement::Statement(s) => s.to_string(),
+            _ => panic!("not a statement"),
+        };
+        assert_eq!(
+            sql,
+            "SELECT CUBE_DATE_TRUNC('day', ts, 'America/New_York'), count(*) FROM s.t \
+             WHERE ts >= CAST('2021-03-14T05:00:00.000Z' AS TIMESTAMP) \
+             AND ts < to_timestamp('2021-03-15T00:00:00Z') GROUP BY 1"
+        );
+    }
+}
@@ -303,6 +303,7 @@ pub fn plan_topk(
     let cluster = ext_planner.plan_cluster_send(
         sort,
         &node.snapshots,
+        /*inner_join*/ false,
         schema.clone(),
         /*use_streaming*/ true,
         /*max_batch_rows*/ max(2 * node.limit, MIN_TOPK_STREAM_ROWS),
@@ -1,7 +1,18 @@
+use crate::queryplanner::casts::cast_array;
+use crate::queryplanner::collation::{collation_key, parse_collation};
 use crate::queryplanner::hll::Hll;
+use crate::queryplanner::stored_types::{
+    ip_in_cidr, ip_prefix, ip_to_string, parse_uuid, uuid_to_string,
+};
+use crate::queryplanner::time_zones::{date_trunc, QueryTimeZone};
 use crate::CubeError;
-use arrow::array::{Array, BinaryArray, UInt64Builder};
-use arrow::datatypes::DataType;
+use arrow::array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, Int64Decimal0Array,
+    Int64Decimal10Array, Int64Decimal1Array, Int64Decimal2Array, Int64Decimal3Array,
+    Int64Decimal4Array, Int64Decimal5Array, StringArray, StringBuilder, TimestampMicrosecondArray,
+    TimestampNanosecondArray, UInt64Array, UInt64Builder,
+};
+use arrow::datatypes::{DataType, TimeUnit};
 use datafusion::error::DataFusionError;
 use datafusion::physical_plan::functions::Signature;
 use datafusion::physical_plan::udaf::AggregateUDF;
@@ -11,11 +22,22 @@ use datafusion::scalar::ScalarValue;
 use serde_derive::{Deserialize, Serialize};
 use smallvec::smallvec;
 use smallvec::SmallVec;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CubeScalarUDFKind {
     HllCardinality, // cardinality(), accepting the HyperLogLog sketches.
+    RowHash,        // row_hash(), combines the hash of a previous column with the next value.
+    CollationKey,   // collation_key(), the string compared in place of a collated value.
+    UuidToString,   // uuid_to_string(), the canonical text form of a stored UUID.
+    ToUuid,         // to_uuid(), validates a UUID string and brings it into the canonical form.
+    IpToString,     // ip_to_string(), the text form of a stored INET or CIDR value.
+    IpInCidr,       // ip_in_cidr(), whether an IP address belongs to a network.
+    IpPrefix,       // ip_prefix(), the network of an IP address with a prefix of the given length.
+    Cast,           // cube_cast(), casts computed the same way on all nodes.
+    DateTrunc,      // cube_date_trunc(), date_trunc() in a time zone.
 }
 
 pub trait CubeScalarUDF {
@@ -27,6 +49,15 @@ pub trait CubeScalarUDF {
 pub fn scalar_udf_by_kind(k: CubeScalarUDFKind) -> Box<dyn CubeScalarUDF> {
     match k {
         CubeScalarUDFKind::HllCardinality => Box::new(HllCardinality {}),
+        CubeScalarUDFKind::RowHash => Box::new(RowHash {}),
+        CubeScalarUDFKind::CollationKey => Box::new(CollationKey {}),
+        CubeScalarUDFKind::UuidToString => Box::new(UuidToString {}),
+        CubeScalarUDFKind::ToUuid => Box::new(ToUuid {}),
+        CubeScalarUDFKind::IpToString => Box::new(IpToString {}),
+        CubeScalarUDFKind::IpInCidr => Box::new(IpInCidr {}),
+        CubeScalarUDFKind::IpPrefix => Box::new(IpPrefix {}),
+        CubeScalarUDFKind::Cast => Box::new(CubeCast {}),
+        CubeScalarUDFKind::DateTrunc => Box::new(DateTrunc {}),
     }
 }
 
@@ -35,12 +66,40 @@ pub fn scalar_kind_by_name(n: &str) -> Option<CubeScalarUDFKind> {
     if n == "CARDINALITY" {
         return Some(CubeScalarUDFKind::HllCardinality);
     }
+    if n == "ROW_HASH" {
+        return Some(CubeScalarUDFKind::RowHash);
+    }
+    if n == "COLLATION_KEY" {
+        return Some(CubeScalarUDFKind::CollationKey);
+    }
+    if n == "UUID_TO_STRING" {
+        return Some(CubeScalarUDFKind::UuidToString);
+    }
+    if n == "TO_UUID" {
+        return Some(CubeScalarUDFKind::ToUuid);
+    }
+    if n == "IP_TO_STRING" {
+        return Some(CubeScalarUDFKind::IpToString);
+    }
+    if n == "IP_IN_CIDR" {
+        return Some(CubeScalarUDFKind::IpInCidr);
+    }
+    if n == "IP_PREFIX" {
+        return Some(CubeScalarUDFKind::IpPrefix);
+    }
+    if n == "CUBE_CAST" {
+        return Some(CubeScalarUDFKind::Cast);
+    }
+    if n == "CUBE_DATE_TRUNC" {
+        return Some(CubeScalarUDFKind::DateTrunc);
+    }
     return None;
 }
 
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum CubeAggregateUDFKind {
     MergeHll, // merge(), accepting the HyperLogLog sketches.
+    HashSum,  // hash_sum(), wrapping sum of hashes, the result does not depend on the row order.
 }
 
 pub trait CubeAggregateUDF {
@@ -53,6 +112,7 @@ pub trait CubeAggregateUDF {
 pub fn aggregate_udf_by_kind(k: CubeAggregateUDFKind) -> Box<dyn CubeAggregateUDF> {
     match k {
         CubeAggregateUDFKind::MergeHll => Box::new(HllMergeUDF {}),
+        CubeAggregateUDFKind::HashSum => Box::new(HashSumUDF {}),
     }
 }
 
@@ -61,6 +121,9 @@ pub fn aggregate_kind_by_name(n: &str) -> Option<CubeAggregateUDFKind> {
     if n == "MERGE" {
         return Some(CubeAggregateUDFKind::MergeHll);
     }
+    if n == "HASH_SUM" {
+        return Some(CubeAggregateUDFKind::HashSum);
+    }
     return None;
 }
 
@@ -218,3 +281,634 @@ impl HllMergeAccumulator {
 fn read_sketch(data: &[u8]) -> Result<Hll, DataFusionError> {
     return Hll::read(&data).map_err(|e| DataFusionError::Execution(e.message));
 }
+
+/// Hashes do not depend on the platform, so results can be compared between nodes.
+struct RowHash {}
+impl CubeScalarUDF for RowHash {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::RowHash;
+    }
+
+    fn name(&self) -> &str {
+        return "ROW_HASH";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Any(2),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::UInt64))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let num_rows = a
+                    .iter()
+                    .find_map(|v| match v {
+                        ColumnarValue::Array(a) => Some(a.len()),
+                        ColumnarValue::Scalar(_) => None,
+                    })
+                    .unwrap_or(1);
+                let seeds = a[0].clone().into_array(num_rows);
+                let values = a[1].clone().into_array(num_rows);
+
+                let mut r = UInt64Builder::new(num_rows);
+                for i in 0..num_rows {
+                    let mut h = DefaultHasher::new();
+                    hash_value(&seeds, i, &mut h)?;
+                    hash_value(&values, i, &mut h)?;
+                    r.append_value(h.finish())?;
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
+struct CollationKey {}
+impl CubeScalarUDF for CollationKey {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::CollationKey;
+    }
+
+    fn name(&self) -> &str {
+        return "COLLATION_KEY";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Utf8))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let collation = match &a[1] {
+                    ColumnarValue::Scalar(ScalarValue::Utf8(Some(c))) => {
+                        parse_collation(c).map_err(|e| DataFusionError::Execution(e.message))?
+                    }
+                    _ => {
+                        return Err(DataFusionError::Execution(
+                            "Collation of COLLATION_KEY must be a string literal".to_string(),
+                        ))
+                    }
+                };
+                let values = match &a[0] {
+                    ColumnarValue::Array(a) => a,
+                    ColumnarValue::Scalar(ScalarValue::Utf8(v)) => {
+                        return Ok(ColumnarValue::Scalar(ScalarValue::Utf8(
+                            v.as_ref().map(|v| collation_key(collation, v)),
+                        )))
+                    }
+                    ColumnarValue::Scalar(v) => {
+                        return Err(DataFusionError::Execution(format!(
+                            "Unexpected argument of COLLATION_KEY: {:?}",
+                            v
+                        )))
+                    }
+                };
+                let num_rows = values.len();
+                let values = values
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+
+                let mut r = StringBuilder::new(num_rows);
+                for i in 0..num_rows {
+                    if values.is_null(i) {
+                        r.append_null()?;
+                    } else {
+                        r.append_value(&collation_key(collation, values.value(i)))?;
+                    }
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
+struct UuidToString {}
+impl CubeScalarUDF for UuidToString {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::UuidToString;
+    }
+
+    fn name(&self) -> &str {
+        return "UUID_TO_STRING";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Binary]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Utf8))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 1);
+                let to_string = |b: &[u8]| {
+                    uuid_to_string(b).ok_or_else(|| {
+                        DataFusionError::Execution(format!("Invalid UUID bytes: {:?}", b))
+                    })
+                };
+                let values = match &a[0] {
+                    ColumnarValue::Array(a) => a,
+                    ColumnarValue::Scalar(ScalarValue::Binary(v)) => {
+                        return Ok(ColumnarValue::Scalar(ScalarValue::Utf8(
+                            v.as_ref().map(|v| to_string(v)).transpose()?,
+                        )))
+                    }
+                    ColumnarValue::Scalar(v) => {
+                        return Err(DataFusionError::Execution(format!(
+                            "Unexpected argument of UUID_TO_STRING: {:?}",
+                            v
+                        )))
+                    }
+                };
+                let num_rows = values.len();
+                let values = values
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .expect("expected binary data");
+
+                let mut r = StringBuilder::new(num_rows);
+                for i in 0..num_rows {
+                    if values.is_null(i) {
+                        r.append_null()?;
+                    } else {
+                        r.append_value(&to_string(values.value(i))?)?;
+                    }
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
+struct ToUuid {}
+impl CubeScalarUDF for ToUuid {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::ToUuid;
+    }
+
+    fn name(&self) -> &str {
+        return "TO_UUID";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Utf8]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Utf8))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 1);
+                let canonical = |s: &str| {
+                    let bytes = parse_uuid(s).map_err(|e| DataFusionError::Execution(e.message))?;
+                    Ok(uuid_to_string(&bytes).unwrap())
+                };
+                let values = match &a[0] {
+                    ColumnarValue::Array(a) => a,
+                    ColumnarValue::Scalar(ScalarValue::Utf8(v)) => {
+                        return Ok(ColumnarValue::Scalar(ScalarValue::Utf8(
+                            v.as_ref().map(|v| canonical(v)).transpose()?,
+                        )))
+                    }
+                    ColumnarValue::Scalar(v) => {
+                        return Err(DataFusionError::Execution(format!(
+                            "Unexpected argument of TO_UUID: {:?}",
+                            v
+                        )))
+                    }
+                };
+                let num_rows = values.len();
+                let values = values
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+
+                let mut r = StringBuilder::new(num_rows);
+                for i in 0..num_rows {
+                    if values.is_null(i) {
+                        r.append_null()?;
+                    } else {
+                        r.append_value(&canonical(values.value(i))?)?;
+                    }
+                }
+                return Ok(ColumnarValue::Array(Arc::new(r.finish())));
+            }),
+        };
+    }
+}
+
+/// Arguments as arrays of the same length and whether all of them are scalars.
+fn rows_of(args: &[ColumnarValue]) -> (Vec<ArrayRef>, bool) {
+    let num_rows = args.iter().find_map(|a| match a {
+        ColumnarValue::Array(a) => Some(a.len()),
+        ColumnarValue::Scalar(_) => None,
+    });
+    let arrays = args
+        .iter()
+        .map(|a| a.clone().into_array(num_rows.unwrap_or(1)))
+        .collect();
+    (arrays, num_rows.is_none())
+}
+
+fn string_result(
+    mut values: Vec<Option<String>>,
+    is_scalar: bool,
+) -> Result<ColumnarValue, DataFusionError> {
+    if is_scalar {
+        return Ok(ColumnarValue::Scalar(ScalarValue::Utf8(
+            values.pop().unwrap(),
+        )));
+    }
+    let mut r = StringBuilder::new(values.len());
+    for v in values {
+        match v {
+            None => r.append_null()?,
+            Some(v) => r.append_value(&v)?,
+        }
+    }
+    Ok(ColumnarValue::Array(Arc::new(r.finish())))
+}
+
+struct IpToString {}
+impl CubeScalarUDF for IpToString {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::IpToString;
+    }
+
+    fn name(&self) -> &str {
+        return "IP_TO_STRING";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Binary]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Utf8))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 1);
+                let (arrays, is_scalar) = rows_of(a);
+                let values = arrays[0]
+                    .as_any()
+                    .downcast_ref::<BinaryArray>()
+                    .expect("expected binary data");
+                let r = (0..values.len())
+                    .map(|i| {
+                        if values.is_null(i) {
+                            return Ok(None);
+                        }
+                        let v = values.value(i);
+                        match ip_to_string(v) {
+                            Some(s) => Ok(Some(s)),
+                            None => Err(DataFusionError::Execution(format!(
+                                "Invalid IP address bytes: {:?}",
+                                v
+                            ))),
+                        }
+                    })
+                    .collect::<Result<Vec<_>, DataFusionError>>()?;
+                string_result(r, is_scalar)
+            }),
+        };
+    }
+}
+
+struct IpInCidr {}
+impl CubeScalarUDF for IpInCidr {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::IpInCidr;
+    }
+
+    fn name(&self) -> &str {
+        return "IP_IN_CIDR";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Utf8, DataType::Utf8]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Boolean))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let (arrays, is_scalar) = rows_of(a);
+                let ips = arrays[0]
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+                let cidrs = arrays[1]
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+                let r = (0..ips.len())
+                    .map(|i| {
+                        if ips.is_null(i) || cidrs.is_null(i) {
+                            return Ok(None);
+                        }
+                        ip_in_cidr(ips.value(i), cidrs.value(i))
+                            .map(Some)
+                            .map_err(|e| DataFusionError::Execution(e.message))
+                    })
+                    .collect::<Result<Vec<_>, DataFusionError>>()?;
+                if is_scalar {
+                    return Ok(ColumnarValue::Scalar(ScalarValue::Boolean(r[0])));
+                }
+                Ok(ColumnarValue::Array(Arc::new(BooleanArray::from(r))))
+            }),
+        };
+    }
+}
+
+struct IpPrefix {}
+impl CubeScalarUDF for IpPrefix {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::IpPrefix;
+    }
+
+    fn name(&self) -> &str {
+        return "IP_PREFIX";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::Utf8, DataType::Int64]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::Utf8))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 2);
+                let (arrays, is_scalar) = rows_of(a);
+                let ips = arrays[0]
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .expect("expected string data");
+                let lens = arrays[1]
+                    .as_any()
+                    .downcast_ref::<Int64Array>()
+                    .expect("expected int64 data");
+                let r = (0..ips.len())
+                    .map(|i| {
+                        if ips.is_null(i) || lens.is_null(i) {
+                            return Ok(None);
+                        }
+                        ip_prefix(ips.value(i), lens.value(i))
+                            .map(Some)
+                            .map_err(|e| DataFusionError::Execution(e.message))
+                    })
+                    .collect::<Result<Vec<_>, DataFusionError>>()?;
+                string_result(r, is_scalar)
+            }),
+        };
+    }
+}
+
+/// See [crate::queryplanner::casts].
+struct CubeCast {}
+impl CubeScalarUDF for CubeCast {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::Cast;
+    }
+
+    fn name(&self) -> &str {
+        return "CUBE_CAST";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Any(4),
+            // The second argument is a NULL of the result type.
+            return_type: Arc::new(|a| Ok(Arc::new(a[1].clone()))),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 4);
+                let string_arg = |v: &ColumnarValue| match v {
+                    ColumnarValue::Scalar(ScalarValue::Utf8(Some(s))) => Ok(s.clone()),
+                    v => Err(DataFusionError::Execution(format!(
+                        "Unexpected argument of CUBE_CAST: {:?}",
+                        v
+                    ))),
+                };
+                let target = string_arg(&a[2])?;
+                let mode = string_arg(&a[3])?;
+                let result_type = a[1].data_type();
+                let (arrays, is_scalar) = rows_of(&a[..1]);
+                let r = cast_array(&arrays[0], &result_type, &target, &mode)
+                    .map_err(|e| DataFusionError::Execution(e.message))?;
+                if is_scalar {
+                    return Ok(ColumnarValue::Scalar(ScalarValue::try_from_array(&r, 0)?));
+                }
+                Ok(ColumnarValue::Array(r))
+            }),
+        };
+    }
+}
+
+/// See [crate::queryplanner::time_zones].
+struct DateTrunc {}
+impl CubeScalarUDF for DateTrunc {
+    fn kind(&self) -> CubeScalarUDFKind {
+        return CubeScalarUDFKind::DateTrunc;
+    }
+
+    fn name(&self) -> &str {
+        return "CUBE_DATE_TRUNC";
+    }
+
+    fn descriptor(&self) -> ScalarUDF {
+        return ScalarUDF {
+            name: self.name().to_string(),
+            signature: Signature::Any(3),
+            return_type: Arc::new(|_| {
+                Ok(Arc::new(DataType::Timestamp(TimeUnit::Nanosecond, None)))
+            }),
+            fun: Arc::new(|a| {
+                assert_eq!(a.len(), 3);
+                let string_arg = |v: &ColumnarValue| match v {
+                    ColumnarValue::Scalar(ScalarValue::Utf8(Some(s))) => Ok(s.clone()),
+                    v => Err(DataFusionError::Execution(format!(
+                        "Unexpected argument of CUBE_DATE_TRUNC: {:?}",
+                        v
+                    ))),
+                };
+                let granularity = string_arg(&a[0])?;
+                let tz = string_arg(&a[2])?
+                    .parse::<QueryTimeZone>()
+                    .map_err(|e| DataFusionError::Execution(e.message))?;
+                let (arrays, is_scalar) = rows_of(&a[1..2]);
+                let nanos = |i: usize| -> Result<Option<i64>, DataFusionError> {
+                    let a = &arrays[0];
+                    if a.is_null(i) {
+                        return Ok(None);
+                    }
+                    match a.data_type() {
+                        DataType::Timestamp(TimeUnit::Nanosecond, None) => Ok(Some(
+                            a.as_any()
+                                .downcast_ref::<TimestampNanosecondArray>()
+                                .unwrap()
+                                .value(i),
+                        )),
+                        DataType::Timestamp(TimeUnit::Microsecond, None) => Ok(Some(
+                            a.as_any()
+                                .downcast_ref::<TimestampMicrosecondArray>()
+                                .unwrap()
+                                .value(i)
+                                * 1000,
+                        )),
+                        t => Err(DataFusionError::Execution(format!(
+                            "CUBE_DATE_TRUNC expects a timestamp, got {:?}",
+                            t
+                        ))),
+                    }
+                };
+                let r = (0..arrays[0].len())
+                    .map(|i| match nanos(i)? {
+                        None => Ok(None),
+                        Some(v) => date_trunc(&granularity, v, &tz)
+                            .map(Some)
+                            .map_err(|e| DataFusionError::Execution(e.message)),
+                    })
+                    .collect::<Result<Vec<_>, DataFusionError>>()?;
+                if is_scalar {
+                    return Ok(ColumnarValue::Scalar(ScalarValue::TimestampNanosecond(
+                        r[0],
+                    )));
+                }
+                Ok(ColumnarValue::Array(Arc::new(
+                    TimestampNanosecondArray::from_opt_vec(r, None),
+                )))
+            }),
+        };
+    }
+}
+
+/// Whether [hash_value] supports values of the type.
+pub(crate) fn is_hashable(t: &DataType) -> bool {
+    match t {
+        DataType::Int64
+        | DataType::UInt64
+        | DataType::Float64
+        | DataType::Int64Decimal(0)
+        | DataType::Int64Decimal(1)
+        | DataType::Int64Decimal(2)
+        | DataType::Int64Decimal(3)
+        | DataType::Int64Decimal(4)
+        | DataType::Int64Decimal(5)
+        | DataType::Int64Decimal(10)
+        | DataType::Timestamp(TimeUnit::Microsecond, None)
+        | DataType::Timestamp(TimeUnit::Nanosecond, None)
+        | DataType::Binary
+        | DataType::Utf8
+        | DataType::Boolean => true,
+        _ => false,
+    }
+}
+
+pub(crate) fn hash_value(
+    a: &ArrayRef,
+    i: usize,
+    h: &mut impl Hasher,
+) -> Result<(), DataFusionError> {
+    macro_rules! hash_array {
+        ($ARRAY_TYPE: ident, $TAG: expr) => {{
+            $TAG.hash(h);
+            a.as_any()
+                .downcast_ref::<$ARRAY_TYPE>()
+                .unwrap()
+                .value(i)
+                .hash(h);
+        }};
+    }
+    if a.is_null(i) {
+        0u8.hash(h);
+        return Ok(());
+    }
+    match a.data_type() {
+        DataType::Int64 => hash_array!(Int64Array, 1u8),
+        DataType::UInt64 => hash_array!(UInt64Array, 2u8),
+        DataType::Float64 => {
+            3u8.hash(h);
+            let v = a.as_any().downcast_ref::<Float64Array>().unwrap().value(i);
+            v.to_bits().hash(h);
+        }
+        DataType::Int64Decimal(0) => hash_array!(Int64Decimal0Array, (4u8, 0u8)),
+        DataType::Int64Decimal(1) => hash_array!(Int64Decimal1Array, (4u8, 1u8)),
+        DataType::Int64Decimal(2) => hash_array!(Int64Decimal2Array, (4u8, 2u8)),
+        DataType::Int64Decimal(3) => hash_array!(Int64Decimal3Array, (4u8, 3u8)),
+        DataType::Int64Decimal(4) => hash_array!(Int64Decimal4Array, (4u8, 4u8)),
+        DataType::Int64Decimal(5) => hash_array!(Int64Decimal5Array, (4u8, 5u8)),
+        DataType::Int64Decimal(10) => hash_array!(Int64Decimal10Array, (4u8, 10u8)),
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            5u8.hash(h);
+            let a = a.as_any().downcast_ref::<TimestampMicrosecondArray>();
+            (a.unwrap().value(i) * 1000).hash(h);
+        }
+        DataType::Timestamp(TimeUnit::Nanosecond, None) => {
+            hash_array!(TimestampNanosecondArray, 5u8)
+        }
+        DataType::Binary => hash_array!(BinaryArray, 6u8),
+        DataType::Utf8 => hash_array!(StringArray, 7u8),
+        DataType::Boolean => hash_array!(BooleanArray, 8u8),
+        t => {
+            return Err(DataFusionError::Execution(format!(
+                "ROW_HASH does not support {:?}",
+                t
+            )))
+        }
+    }
+    Ok(())
+}
+
+struct HashSumUDF {}
+impl CubeAggregateUDF for HashSumUDF {
+    fn kind(&self) -> CubeAggregateUDFKind {
+        return CubeAggregateUDFKind::HashSum;
+    }
+    fn name(&self) -> &str {
+        return "HASH_SUM";
+    }
+    fn descriptor(&self) -> AggregateUDF {
+        return AggregateUDF {
+            name: self.name().to_string(),
+            signature: Signature::Exact(vec![DataType::UInt64]),
+            return_type: Arc::new(|_| Ok(Arc::new(DataType::UInt64))),
+            accumulator: Arc::new(|| Ok(Box::new(HashSumAccumulator { sum: 0 }))),
+            state_type: Arc::new(|_| Ok(Arc::new(vec![DataType::UInt64]))),
+        };
+    }
+    fn accumulator(&self) -> Box<dyn Accumulator> {
+        return Box::new(HashSumAccumulator { sum: 0 });
+    }
+}
+
+#[derive(Debug)]
+struct HashSumAccumulator {
+    sum: u64,
+}
+
+impl Accumulator for HashSumAccumulator {
+    fn reset(&mut self) {
+        self.sum = 0;
+    }
+
+    fn state(&self) -> Result<SmallVec<[ScalarValue; 2]>, DataFusionError> {
+        return Ok(smallvec![self.evaluate()?]);
+    }
+
+    fn update(&mut self, row: &[ScalarValue]) -> Result<(), DataFusionError> {
+        assert_eq!(row.len(), 1);
+        match &row[0] {
+            ScalarValue::UInt64(Some(v)) => self.sum = self.sum.wrapping_add(*v),
+            ScalarValue::UInt64(None) => {}
+            _ => {
+                return Err(CubeError::internal(
+                    "invalid scalar value passed to HASH_SUM".to_string(),
+                )
+                .into())
+            }
+        }
+        return Ok(());
+    }
+
+    fn merge(&mut self, states: &[ScalarValue]) -> Result<(), DataFusionError> {
+        self.update(states)
+    }
+
+    fn evaluate(&self) -> Result<ScalarValue, DataFusionError> {
+        return Ok(ScalarValue::UInt64(Some(self.sum)));
+    }
+}
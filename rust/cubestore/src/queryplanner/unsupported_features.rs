@@ -0,0 +1,310 @@
+//! Checks that a select over data tables can be distributed to workers before indexes are chosen.
+//! Planning stops at the first part of the query it can't distribute, so without this check users
+//! would fix queries one error at a time. Everything unsupported is reported at once instead, up to
+//! [crate::config::ConfigObj::max_reported_unsupported_features] items, along with the list in
+//! [crate::CubeErrorDetails::UnsupportedFeatures].
+use crate::queryplanner::planning::has_table_scan;
+use crate::queryplanner::udfs::{aggregate_kind_by_name, scalar_kind_by_name};
+use crate::queryplanner::InfoSchemaTableProvider;
+use crate::{CubeError, CubeErrorDetails};
+use datafusion::logical_plan::{Expr, JoinType, LogicalPlan, PlanVisitor};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum UnsupportedFeatureKind {
+    /// Input of UNION that has to be computed on the router, e.g. an aggregation.
+    UnionArgument,
+    /// Input of JOIN that has to be computed on the router, or an outer join with inline data.
+    JoinArgument,
+    /// System tables in queries over data tables.
+    SystemTable,
+    /// Functions that can't be sent to workers.
+    Function,
+    /// Statements other than selects, e.g. EXPLAIN.
+    Statement,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnsupportedFeature {
+    pub kind: UnsupportedFeatureKind,
+    /// Part of the query, e.g. `Aggregate over foo.orders` or the name of a function.
+    pub fragment: String,
+}
+
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            UnsupportedFeatureKind::UnionArgument => "UNION argument",
+            UnsupportedFeatureKind::JoinArgument => "JOIN argument",
+            UnsupportedFeatureKind::SystemTable => "system table",
+            UnsupportedFeatureKind::Function => "function",
+            UnsupportedFeatureKind::Statement => "statement",
+        };
+        write!(f, "{}: {}", kind, self.fragment)
+    }
+}
+
+/// Fails with all unsupported features of `plan`, a select over data tables. At most `max_reported`
+/// features are listed in the message, all of them are listed in the details.
+pub fn check_distributable(plan: &LogicalPlan, max_reported: usize) -> Result<(), CubeError> {
+    let mut features = Vec::new();
+    collect_unsupported(plan, &mut features);
+    if features.is_empty() {
+        return Ok(());
+    }
+    let mut message = format!(
+        "Query uses {} unsupported feature{}:",
+        features.len(),
+        if features.len() == 1 { "" } else { "s" }
+    );
+    for f in features.iter().take(max_reported) {
+        message += &format!("\n- {}", f);
+    }
+    if max_reported < features.len() {
+        message += &format!("\n- and {} more", features.len() - max_reported);
+    }
+    Err(CubeError::user(message).with_details(CubeErrorDetails::UnsupportedFeatures(features)))
+}
+
+/// Returns whether `p` is computed on workers, mirrors the way ClusterSend nodes are pulled up
+/// during planning.
+fn collect_unsupported(p: &LogicalPlan, out: &mut Vec<UnsupportedFeature>) -> bool {
+    for e in node_expressions(p) {
+        collect_unsupported_functions(e, out);
+    }
+    match p {
+        LogicalPlan::TableScan {
+            table_name, source, ..
+        } => {
+            if source.as_any().is::<InfoSchemaTableProvider>() {
+                out.push(UnsupportedFeature {
+                    kind: UnsupportedFeatureKind::SystemTable,
+                    fragment: table_name.clone(),
+                });
+            }
+            true
+        }
+        LogicalPlan::EmptyRelation { .. } => false,
+        LogicalPlan::CreateExternalTable { .. } | LogicalPlan::Explain { .. } => {
+            out.push(UnsupportedFeature {
+                kind: UnsupportedFeatureKind::Statement,
+                fragment: node_name(p).to_string(),
+            });
+            false
+        }
+        LogicalPlan::Projection { input, .. } | LogicalPlan::Filter { input, .. } => {
+            collect_unsupported(input, out)
+        }
+        // Results of these nodes are collected on the router.
+        LogicalPlan::Aggregate { input, .. }
+        | LogicalPlan::Sort { input, .. }
+        | LogicalPlan::Limit { input, .. }
+        | LogicalPlan::Skip { input, .. }
+        | LogicalPlan::Repartition { input, .. } => {
+            collect_unsupported(input, out);
+            false
+        }
+        LogicalPlan::Extension { node } => {
+            for input in node.inputs() {
+                collect_unsupported(input, out);
+            }
+            false
+        }
+        LogicalPlan::Union { inputs, .. } => {
+            // Inline data, e.g. from VALUES lists, is computed on the router.
+            if !inputs.iter().any(has_table_scan) {
+                for input in inputs {
+                    collect_unsupported(input, out);
+                }
+                return false;
+            }
+            for input in inputs {
+                if !collect_unsupported(input, out) {
+                    out.push(UnsupportedFeature {
+                        kind: UnsupportedFeatureKind::UnionArgument,
+                        fragment: describe(input),
+                    });
+                }
+            }
+            true
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            join_type,
+            ..
+        } => {
+            let left_distributed = collect_unsupported(left, out);
+            let right_distributed = collect_unsupported(right, out);
+            if !has_table_scan(left) && !has_table_scan(right) {
+                return false;
+            }
+            let supported = match (left_distributed, right_distributed) {
+                (true, true) => true,
+                (true, false) => {
+                    !has_table_scan(right) && matches!(join_type, JoinType::Inner | JoinType::Left)
+                }
+                (false, true) => {
+                    !has_table_scan(left) && matches!(join_type, JoinType::Inner | JoinType::Right)
+                }
+                (false, false) => false,
+            };
+            if supported {
+                return true;
+            }
+            // Arguments over data tables that are computed on the router, or the inline argument
+            // of an outer join.
+            let mut arguments = vec![(left, left_distributed), (right, right_distributed)]
+                .into_iter()
+                .filter(|(p, distributed)| !distributed && has_table_scan(p))
+                .map(|(p, _)| p)
+                .collect_vec();
+            if arguments.is_empty() {
+                arguments.push(if left_distributed { right } else { left });
+            }
+            for argument in arguments {
+                out.push(UnsupportedFeature {
+                    kind: UnsupportedFeatureKind::JoinArgument,
+                    fragment: format!("{} in {:?} join", describe(argument), join_type),
+                });
+            }
+            true
+        }
+    }
+}
+
+fn node_expressions(p: &LogicalPlan) -> Vec<&Expr> {
+    match p {
+        LogicalPlan::Projection { expr, .. } | LogicalPlan::Sort { expr, .. } => {
+            expr.iter().collect()
+        }
+        LogicalPlan::Filter { predicate, .. } => vec![predicate],
+        LogicalPlan::Aggregate {
+            group_expr,
+            aggr_expr,
+            ..
+        } => group_expr.iter().chain(aggr_expr.iter()).collect(),
+        LogicalPlan::TableScan { filters, .. } => filters.iter().collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn collect_unsupported_functions(e: &Expr, out: &mut Vec<UnsupportedFeature>) {
+    let (unsupported, children): (_, Vec<&Expr>) = match e {
+        Expr::ScalarUDF { fun, args } => (
+            scalar_kind_by_name(&fun.name).is_none().then(|| &fun.name),
+            args.iter().collect(),
+        ),
+        Expr::AggregateUDF { fun, args } => (
+            aggregate_kind_by_name(&fun.name)
+                .is_none()
+                .then(|| &fun.name),
+            args.iter().collect(),
+        ),
+        Expr::ScalarFunction { args, .. } | Expr::AggregateFunction { args, .. } => {
+            (None, args.iter().collect())
+        }
+        Expr::Alias(e, _)
+        | Expr::Not(e)
+        | Expr::IsNotNull(e)
+        | Expr::IsNull(e)
+        | Expr::Negative(e)
+        | Expr::Cast { expr: e, .. }
+        | Expr::TryCast { expr: e, .. }
+        | Expr::Sort { expr: e, .. } => (None, vec![e.as_ref()]),
+        Expr::BinaryExpr { left, right, .. } => (None, vec![left.as_ref(), right.as_ref()]),
+        Expr::Between {
+            expr, low, high, ..
+        } => (None, vec![expr.as_ref(), low.as_ref(), high.as_ref()]),
+        Expr::InList { expr, list, .. } => {
+            (None, Some(expr.as_ref()).into_iter().chain(list).collect())
+        }
+        Expr::Case {
+            expr,
+            when_then_expr,
+            else_expr,
+        } => (
+            None,
+            expr.iter()
+                .chain(when_then_expr.iter().flat_map(|(w, t)| vec![w, t]))
+                .chain(else_expr.iter())
+                .map(|e| e.as_ref())
+                .collect(),
+        ),
+        Expr::Column(..) | Expr::ScalarVariable(_) | Expr::Literal(_) | Expr::Wildcard => {
+            (None, Vec::new())
+        }
+    };
+    if let Some(name) = unsupported {
+        if !out
+            .iter()
+            .any(|f| f.kind == UnsupportedFeatureKind::Function && &f.fragment == name)
+        {
+            out.push(UnsupportedFeature {
+                kind: UnsupportedFeatureKind::Function,
+                fragment: name.clone(),
+            });
+        }
+    }
+    for c in children {
+        collect_unsupported_functions(c, out);
+    }
+}
+
+fn node_name(p: &LogicalPlan) -> &'static str {
+    match p {
+        LogicalPlan::Projection { .. } => "Projection",
+        LogicalPlan::Filter { .. } => "Filter",
+        LogicalPlan::Aggregate { .. } => "Aggregate",
+        LogicalPlan::Sort { .. } => "Sort",
+        LogicalPlan::Join { .. } => "Join",
+        LogicalPlan::Repartition { .. } => "Repartition",
+        LogicalPlan::Union { .. } => "Union",
+        LogicalPlan::TableScan { .. } => "Scan",
+        LogicalPlan::EmptyRelation { .. } => "Empty",
+        LogicalPlan::Limit { .. } => "Limit",
+        LogicalPlan::Skip { .. } => "Skip",
+        LogicalPlan::CreateExternalTable { .. } => "CREATE EXTERNAL TABLE",
+        LogicalPlan::Explain { .. } => "EXPLAIN",
+        LogicalPlan::Extension { .. } => "Extension",
+    }
+}
+
+/// The nearest node under `p` that keeps it on the router with the tables it reads, e.g.
+/// `Aggregate over foo.orders, foo.customers`.
+fn describe(p: &LogicalPlan) -> String {
+    struct Tables(Vec<String>);
+    impl PlanVisitor for Tables {
+        type Error = ();
+
+        fn pre_visit(&mut self, plan: &LogicalPlan) -> Result<bool, Self::Error> {
+            if let LogicalPlan::TableScan { table_name, .. } = plan {
+                self.0.push(table_name.clone());
+            }
+            Ok(true)
+        }
+    }
+
+    let mut node = p;
+    loop {
+        match node {
+            LogicalPlan::Projection { input, .. } | LogicalPlan::Filter { input, .. } => {
+                node = input.as_ref()
+            }
+            _ => break,
+        }
+    }
+    let mut tables = Tables(Vec::new());
+    p.accept(&mut tables).expect("no failures possible");
+    if tables.0.is_empty() {
+        node_name(node).to_string()
+    } else {
+        format!(
+            "{} over {}",
+            node_name(node),
+            tables.0.into_iter().unique().join(", ")
+        )
+    }
+}
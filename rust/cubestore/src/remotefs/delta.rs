@@ -0,0 +1,963 @@
+//! Reader of the current snapshot of a Delta Lake table, see [crate::sql::attach] for how it is
+//! queried. The snapshot is replayed from the transaction log in `_delta_log`: the latest complete
+//! parquet checkpoint followed by the JSON commits after it. Files are identified by their paths
+//! and deletion vectors, so a file whose deletion vector changed is removed and added again.
+//!
+//! Only parquet data files and columns of types CubeStore reads from parquet as is are supported:
+//! long, double, boolean, string and timestamp. Tables with column mapping are not supported.
+//! Partition columns are not stored in data files, their values come from the log. Deletion
+//! vectors are resolved into positions of deleted rows of each data file.
+use crate::metastore::ColumnType;
+use crate::remotefs::iceberg::{normalize_path, read_file};
+use crate::remotefs::{unescape_path_name, RemoteFs};
+use crate::sql::timestamp_from_string;
+use crate::table::TableValue;
+use crate::CubeError;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, ListAccessor, MapAccessor};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+
+#[derive(Debug, PartialEq)]
+pub struct DeltaSnapshot {
+    /// Version of the last commit of the snapshot.
+    pub version: u64,
+    pub columns: Vec<(String, ColumnType)>,
+    /// Values of partition columns are the same in all rows of a data file.
+    pub partition_columns: Vec<String>,
+    pub files: Vec<DeltaDataFile>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DeltaDataFile {
+    pub path: String,
+    /// Rows of the file, including deleted ones.
+    pub record_count: u64,
+    /// Values of [DeltaSnapshot::partition_columns].
+    pub partition_values: Vec<TableValue>,
+    /// Sorted positions of deleted rows.
+    pub deleted_rows: Vec<u64>,
+}
+
+/// Reader features of the protocol that don't change how data files are read. Column mapping is
+/// checked by the table configuration as it can be enabled with the `none` mode.
+const SUPPORTED_READER_FEATURES: &[&str] = &["deletionVectors", "columnMapping", "timestampNtz"];
+
+/// Magic number of deletion vectors serialized as arrays of 32-bit roaring bitmaps.
+const ROARING_ARRAY_MAGIC: u32 = 1681511377;
+
+/// Version of the latest commit of the table. Only lists the log, so it's cheap to check whether
+/// the table changed.
+pub async fn delta_table_version(
+    remote_fs: &dyn RemoteFs,
+    location: &str,
+) -> Result<u64, CubeError> {
+    list_log(remote_fs, location).await?.version(location)
+}
+
+pub async fn read_delta_table(
+    remote_fs: &dyn RemoteFs,
+    location: &str,
+) -> Result<DeltaSnapshot, CubeError> {
+    let log = list_log(remote_fs, location).await?;
+    let version = log.version(location)?;
+
+    let mut state = LogState::default();
+    if let Some((_, parts)) = &log.checkpoint {
+        for part in parts {
+            let local_file = remote_fs.download_file(part).await?;
+            let actions =
+                tokio::task::spawn_blocking(move || read_checkpoint(&local_file)).await??;
+            for action in actions {
+                state
+                    .apply(&action)
+                    .map_err(|e| CubeError::user(format!("Invalid {}: {}", part, e.message)))?;
+            }
+        }
+    }
+    for path in log.commits.values() {
+        let data = read_file(remote_fs, path).await?;
+        for line in data.split(|b| *b == b'\n').filter(|l| !l.is_empty()) {
+            serde_json::from_slice(line)
+                .map_err(|e| CubeError::user(e.to_string()))
+                .and_then(|action: JsonValue| state.apply(&action))
+                .map_err(|e| CubeError::user(format!("Invalid {}: {}", path, e.message)))?;
+        }
+    }
+
+    let (columns, partition_columns) = state
+        .table_schema()
+        .map_err(|e| CubeError::user(format!("Delta Lake table {}: {}", location, e.message)))?;
+    let mut files = Vec::with_capacity(state.files.len());
+    for add in state.files.values() {
+        let file = parse_add(add, location, &columns, &partition_columns).map_err(|e| {
+            CubeError::user(format!("Delta Lake table {}: {}", location, e.message))
+        })?;
+        let record_count = match file.record_count {
+            Some(c) => c,
+            None => {
+                let local_file = remote_fs.download_file(&file.path).await?;
+                tokio::task::spawn_blocking(move || -> Result<u64, CubeError> {
+                    let reader = SerializedFileReader::new(File::open(local_file)?)?;
+                    Ok(reader.metadata().file_metadata().num_rows() as u64)
+                })
+                .await??
+            }
+        };
+        let deleted_rows = match &file.deletion_vector {
+            None => Vec::new(),
+            Some(dv) => read_deletion_vector(remote_fs, location, dv)
+                .await
+                .map_err(|e| {
+                    CubeError::user(format!(
+                        "Can't read deletion vector of {}: {}",
+                        file.path, e.message
+                    ))
+                })?,
+        };
+        files.push(DeltaDataFile {
+            path: file.path,
+            record_count,
+            partition_values: file.partition_values,
+            deleted_rows,
+        });
+    }
+    Ok(DeltaSnapshot {
+        version,
+        columns,
+        partition_columns,
+        files,
+    })
+}
+
+/// Files of `_delta_log` the snapshot is read from.
+#[derive(Debug, PartialEq)]
+struct LogFiles {
+    /// Version and parts of the latest complete checkpoint.
+    checkpoint: Option<(u64, Vec<String>)>,
+    /// Commits after the checkpoint by version.
+    commits: BTreeMap<u64, String>,
+}
+
+impl LogFiles {
+    /// Checks that no commits are missing and returns the version of the last one.
+    fn version(&self, location: &str) -> Result<u64, CubeError> {
+        let mut expected = self.checkpoint.as_ref().map(|(v, _)| v + 1).unwrap_or(0);
+        for v in self.commits.keys() {
+            if *v != expected {
+                return Err(CubeError::user(format!(
+                    "Commit {} is missing in the log of Delta Lake table {}",
+                    expected, location
+                )));
+            }
+            expected += 1;
+        }
+        match expected {
+            0 => Err(CubeError::user(format!(
+                "No Delta Lake log found in {}/_delta_log",
+                location
+            ))),
+            _ => Ok(expected - 1),
+        }
+    }
+}
+
+async fn list_log(remote_fs: &dyn RemoteFs, location: &str) -> Result<LogFiles, CubeError> {
+    let log_dir = format!("{}/_delta_log/", location);
+    let paths = remote_fs.list(&log_dir).await?;
+    Ok(log_files(&log_dir, paths))
+}
+
+/// Commits are `<version>.json` and checkpoints are `<version>.checkpoint.parquet` or
+/// `<version>.checkpoint.<part>.<parts>.parquet`, with versions padded to 20 digits.
+fn log_files(log_dir: &str, paths: Vec<String>) -> LogFiles {
+    let mut commits = BTreeMap::new();
+    // Parts of checkpoints by version, part and number of parts.
+    let mut checkpoints = BTreeMap::<u64, BTreeMap<(u64, u64), String>>::new();
+    for path in paths {
+        let name = match path.strip_prefix(log_dir) {
+            Some(n) if !n.contains('/') => n.to_string(),
+            _ => continue,
+        };
+        let parts = name.split('.').collect::<Vec<_>>();
+        let version = match parts[0].parse::<u64>() {
+            Ok(v) if parts[0].len() == 20 => v,
+            _ => continue,
+        };
+        match &parts[1..] {
+            ["json"] => {
+                commits.insert(version, path);
+            }
+            ["checkpoint", "parquet"] => {
+                checkpoints.entry(version).or_default().insert((1, 1), path);
+            }
+            ["checkpoint", part, total, "parquet"] => {
+                if let (Ok(part), Ok(total)) = (part.parse(), total.parse()) {
+                    checkpoints
+                        .entry(version)
+                        .or_default()
+                        .insert((part, total), path);
+                }
+            }
+            _ => {}
+        }
+    }
+    let checkpoint = checkpoints.into_iter().rev().find_map(|(version, parts)| {
+        // Writers may leave an incomplete checkpoint next to a complete one of the same version.
+        let (_, total) = parts.keys().last()?;
+        let complete = (1..=*total)
+            .map(|part| parts.get(&(part, *total)).cloned())
+            .collect::<Option<Vec<_>>>()?;
+        Some((version, complete))
+    });
+    if let Some((version, _)) = &checkpoint {
+        commits = commits.split_off(&(version + 1));
+    }
+    LogFiles {
+        checkpoint,
+        commits,
+    }
+}
+
+/// Actions of the log reconciled up to some version.
+#[derive(Debug, Default)]
+struct LogState {
+    protocol: Option<JsonValue>,
+    metadata: Option<JsonValue>,
+    /// `add` actions of live files by their ids, see [file_id].
+    files: BTreeMap<String, JsonValue>,
+}
+
+impl LogState {
+    fn apply(&mut self, action: &JsonValue) -> Result<(), CubeError> {
+        let action = action
+            .as_object()
+            .ok_or_else(|| CubeError::user("action is not an object".to_string()))?;
+        for (kind, value) in action {
+            match kind.as_str() {
+                _ if value.is_null() => {}
+                "add" => {
+                    self.files.insert(file_id(value)?, value.clone());
+                }
+                "remove" => {
+                    self.files.remove(&file_id(value)?);
+                }
+                "metaData" => self.metadata = Some(value.clone()),
+                "protocol" => self.protocol = Some(value.clone()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Columns and partition columns of the table.
+    fn table_schema(&self) -> Result<(Vec<(String, ColumnType)>, Vec<String>), CubeError> {
+        let error = |field: &str| CubeError::user(format!("{} is missing or invalid", field));
+        let protocol = self.protocol.as_ref().ok_or_else(|| error("protocol"))?;
+        let reader_version = protocol
+            .get("minReaderVersion")
+            .and_then(|v| v.as_i64())
+            .ok_or_else(|| error("minReaderVersion"))?;
+        if reader_version > 3 {
+            return Err(CubeError::user(format!(
+                "reader version {} is not supported",
+                reader_version
+            )));
+        }
+        for feature in protocol
+            .get("readerFeatures")
+            .and_then(|f| f.as_array())
+            .into_iter()
+            .flatten()
+        {
+            match feature.as_str() {
+                Some(f) if SUPPORTED_READER_FEATURES.contains(&f) => {}
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "reader feature {} is not supported",
+                        feature
+                    )))
+                }
+            }
+        }
+
+        let metadata = self.metadata.as_ref().ok_or_else(|| error("metaData"))?;
+        match metadata
+            .pointer("/configuration/delta.columnMapping.mode")
+            .and_then(|m| m.as_str())
+        {
+            None | Some("none") => {}
+            Some(mode) => {
+                return Err(CubeError::user(format!(
+                    "column mapping mode {} is not supported",
+                    mode
+                )))
+            }
+        }
+        match metadata
+            .pointer("/format/provider")
+            .and_then(|p| p.as_str())
+        {
+            None | Some("parquet") => {}
+            Some(provider) => {
+                return Err(CubeError::user(format!(
+                    "format {} is not supported",
+                    provider
+                )))
+            }
+        }
+        let schema: JsonValue = metadata
+            .get("schemaString")
+            .and_then(|s| s.as_str())
+            .and_then(|s| serde_json::from_str(s).ok())
+            .ok_or_else(|| error("schemaString"))?;
+        let mut columns = Vec::new();
+        for field in schema
+            .get("fields")
+            .and_then(|f| f.as_array())
+            .ok_or_else(|| error("schema fields"))?
+        {
+            let name = field
+                .get("name")
+                .and_then(|n| n.as_str())
+                .ok_or_else(|| error("field name"))?;
+            let column_type = match field.get("type").and_then(|t| t.as_str()) {
+                Some("long") => ColumnType::Int,
+                Some("double") => ColumnType::Float,
+                Some("boolean") => ColumnType::Boolean,
+                Some("string") => ColumnType::String,
+                Some("timestamp") => ColumnType::Timestamp,
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "unsupported type {} of column {}",
+                        field.get("type").unwrap_or(&JsonValue::Null),
+                        name
+                    )))
+                }
+            };
+            columns.push((name.to_string(), column_type));
+        }
+
+        let mut partition_columns = Vec::new();
+        for c in metadata
+            .get("partitionColumns")
+            .and_then(|c| c.as_array())
+            .ok_or_else(|| error("partitionColumns"))?
+        {
+            let name = c.as_str().ok_or_else(|| error("partitionColumns"))?;
+            match columns.iter().find(|(n, _)| n == name) {
+                // Floats can't be compared as bounds of partitions.
+                Some((_, ColumnType::Float)) => {
+                    return Err(CubeError::user(format!(
+                        "partition column {} of type double is not supported",
+                        name
+                    )))
+                }
+                Some(_) => partition_columns.push(name.to_string()),
+                None => return Err(error("partitionColumns")),
+            }
+        }
+        Ok((columns, partition_columns))
+    }
+}
+
+/// Files are identified by their paths and deletion vectors.
+fn file_id(action: &JsonValue) -> Result<String, CubeError> {
+    let path = action
+        .get("path")
+        .and_then(|p| p.as_str())
+        .ok_or_else(|| CubeError::user("path of a file action is missing".to_string()))?;
+    match action.get("deletionVector") {
+        Some(dv) if !dv.is_null() => Ok(format!(
+            "{} {}{}@{}",
+            path,
+            dv.get("storageType").and_then(|t| t.as_str()).unwrap_or(""),
+            dv.get("pathOrInlineDv")
+                .and_then(|p| p.as_str())
+                .unwrap_or(""),
+            dv.get("offset").and_then(|o| o.as_i64()).unwrap_or(0)
+        )),
+        _ => Ok(path.to_string()),
+    }
+}
+
+/// Data file of an `add` action.
+#[derive(Debug, PartialEq)]
+struct AddedFile {
+    path: String,
+    /// `numRecords` of file statistics.
+    record_count: Option<u64>,
+    partition_values: Vec<TableValue>,
+    deletion_vector: Option<DeletionVector>,
+}
+
+#[derive(Debug, PartialEq)]
+struct DeletionVector {
+    storage_type: String,
+    path_or_inline_dv: String,
+    offset: Option<u64>,
+    size_in_bytes: u64,
+    cardinality: u64,
+}
+
+fn parse_add(
+    add: &JsonValue,
+    location: &str,
+    columns: &[(String, ColumnType)],
+    partition_columns: &[String],
+) -> Result<AddedFile, CubeError> {
+    let error = |field: &str| CubeError::user(format!("{} of add action is invalid", field));
+    let path = add.get("path").and_then(|p| p.as_str()).unwrap();
+    let path = resolve_path(location, path)?;
+    let record_count = add
+        .get("stats")
+        .and_then(|s| s.as_str())
+        .and_then(|s| serde_json::from_str::<JsonValue>(s).ok())
+        .and_then(|s| s.get("numRecords").and_then(|n| n.as_u64()));
+
+    let mut partition_values = Vec::with_capacity(partition_columns.len());
+    for column in partition_columns {
+        let column_type = &columns.iter().find(|(n, _)| n == column).unwrap().1;
+        let value = match add
+            .get("partitionValues")
+            .and_then(|v| v.get(column))
+            .and_then(|v| v.as_str())
+        {
+            // Spark writes nulls as empty strings.
+            None | Some("") => TableValue::Null,
+            Some(v) => match column_type {
+                ColumnType::String => TableValue::String(v.to_string()),
+                ColumnType::Int => TableValue::Int(v.parse().map_err(|_| error(column))?),
+                ColumnType::Boolean => TableValue::Boolean(v.parse().map_err(|_| error(column))?),
+                ColumnType::Timestamp => TableValue::Timestamp(timestamp_from_string(v)?),
+                _ => return Err(error(column)),
+            },
+        };
+        partition_values.push(value);
+    }
+
+    let deletion_vector = match add.get("deletionVector") {
+        Some(dv) if !dv.is_null() => {
+            let string = |field: &str| {
+                dv.get(field)
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string())
+                    .ok_or_else(|| error(field))
+            };
+            let number = |field: &str| {
+                dv.get(field)
+                    .and_then(|v| v.as_u64())
+                    .ok_or_else(|| error(field))
+            };
+            Some(DeletionVector {
+                storage_type: string("storageType")?,
+                path_or_inline_dv: string("pathOrInlineDv")?,
+                offset: dv.get("offset").and_then(|o| o.as_u64()),
+                size_in_bytes: number("sizeInBytes")?,
+                cardinality: number("cardinality")?,
+            })
+        }
+        _ => None,
+    };
+
+    Ok(AddedFile {
+        path,
+        record_count,
+        partition_values,
+        deletion_vector,
+    })
+}
+
+/// Paths in the log are URIs, relative to the table location or absolute.
+fn resolve_path(location: &str, path: &str) -> Result<String, CubeError> {
+    let path = unescape_path_name(path)?;
+    if path.contains(":/") {
+        Ok(normalize_path(&path))
+    } else {
+        Ok(format!("{}/{}", location, path))
+    }
+}
+
+async fn read_deletion_vector(
+    remote_fs: &dyn RemoteFs,
+    location: &str,
+    dv: &DeletionVector,
+) -> Result<Vec<u64>, CubeError> {
+    let path = match dv.storage_type.as_str() {
+        "i" => {
+            let mut data = z85_decode(&dv.path_or_inline_dv)?;
+            data.truncate(dv.size_in_bytes as usize);
+            return check_cardinality(read_roaring_array(&data)?, dv);
+        }
+        // Random prefix of the directory followed by the UUID of the file.
+        "u" if dv.path_or_inline_dv.len() >= 20 => {
+            let (prefix, encoded_uuid) = dv
+                .path_or_inline_dv
+                .split_at(dv.path_or_inline_dv.len() - 20);
+            let uuid = uuid::Uuid::from_slice(&z85_decode(encoded_uuid)?)
+                .map_err(|e| CubeError::user(e.to_string()))?;
+            let file_name = format!("deletion_vector_{}.bin", uuid);
+            if prefix.is_empty() {
+                format!("{}/{}", location, file_name)
+            } else {
+                format!("{}/{}/{}", location, prefix, file_name)
+            }
+        }
+        "p" => resolve_path(location, &dv.path_or_inline_dv)?,
+        t => {
+            return Err(CubeError::user(format!(
+                "unsupported storage type {} of deletion vector",
+                t
+            )))
+        }
+    };
+    let data = read_file(remote_fs, &path).await?;
+    // Files start with a format version, each vector is prefixed by its size and followed by a
+    // checksum.
+    let offset = dv.offset.unwrap_or(1) as usize;
+    let size = data
+        .get(offset..offset + 4)
+        .map(|s| u32::from_be_bytes(s.try_into().unwrap()) as usize)
+        .ok_or_else(|| CubeError::user(format!("{} is truncated", path)))?;
+    let vector = data
+        .get(offset + 4..offset + 4 + size)
+        .ok_or_else(|| CubeError::user(format!("{} is truncated", path)))?;
+    check_cardinality(read_roaring_array(vector)?, dv)
+}
+
+fn check_cardinality(rows: Vec<u64>, dv: &DeletionVector) -> Result<Vec<u64>, CubeError> {
+    if rows.len() as u64 != dv.cardinality {
+        return Err(CubeError::user(format!(
+            "expected {} deleted rows, found {}",
+            dv.cardinality,
+            rows.len()
+        )));
+    }
+    Ok(rows)
+}
+
+const Z85_ALPHABET: &[u8] =
+    b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ.-:+=^!/*?&<>()[]{}@%$#";
+
+/// Z85 encodes 4 bytes as 5 characters.
+fn z85_decode(s: &str) -> Result<Vec<u8>, CubeError> {
+    let error = || CubeError::user(format!("invalid Z85 string {}", s));
+    if s.len() % 5 != 0 {
+        return Err(error());
+    }
+    let mut result = Vec::with_capacity(s.len() / 5 * 4);
+    for chunk in s.as_bytes().chunks(5) {
+        let mut value = 0u64;
+        for c in chunk {
+            let digit = Z85_ALPHABET.iter().position(|a| a == c).ok_or_else(error)?;
+            value = value * 85 + digit as u64;
+        }
+        if value > u32::MAX as u64 {
+            return Err(error());
+        }
+        result.extend_from_slice(&(value as u32).to_be_bytes());
+    }
+    Ok(result)
+}
+
+/// Little-endian reader of serialized bitmaps.
+struct BitmapReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitmapReader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], CubeError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| CubeError::user("deletion vector is truncated".to_string()))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, CubeError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, CubeError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CubeError> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
+
+/// Deletion vectors are arrays of 32-bit roaring bitmaps, one per distinct high 32 bits of row
+/// positions, in the portable serialization format of roaring bitmaps. Returns sorted positions.
+fn read_roaring_array(data: &[u8]) -> Result<Vec<u64>, CubeError> {
+    let mut reader = BitmapReader { data, pos: 0 };
+    if reader.u32()? != ROARING_ARRAY_MAGIC {
+        return Err(CubeError::user(
+            "unsupported format of deletion vector".to_string(),
+        ));
+    }
+    let bitmaps = reader.u64()?;
+    let mut rows = Vec::new();
+    for _ in 0..bitmaps {
+        let high = (reader.u32()? as u64) << 32;
+        rows.extend(
+            read_roaring_bitmap(&mut reader)?
+                .into_iter()
+                .map(|low| high | low as u64),
+        );
+    }
+    Ok(rows)
+}
+
+fn read_roaring_bitmap(reader: &mut BitmapReader) -> Result<Vec<u32>, CubeError> {
+    const SERIAL_COOKIE_NO_RUNCONTAINER: u32 = 12346;
+    const SERIAL_COOKIE: u32 = 12347;
+    let cookie = reader.u32()?;
+    let (containers, run_flags) = if cookie & 0xFFFF == SERIAL_COOKIE {
+        let containers = (cookie >> 16) as usize + 1;
+        (containers, Some(reader.bytes((containers + 7) / 8)?))
+    } else if cookie == SERIAL_COOKIE_NO_RUNCONTAINER {
+        (reader.u32()? as usize, None)
+    } else {
+        return Err(CubeError::user(
+            "invalid roaring bitmap in deletion vector".to_string(),
+        ));
+    };
+    // Keys and cardinalities of containers.
+    let mut headers = Vec::with_capacity(containers);
+    for _ in 0..containers {
+        headers.push((reader.u16()? as u32, reader.u16()? as usize + 1));
+    }
+    // Offsets of containers are not needed to read them in order.
+    if run_flags.is_none() || containers >= 4 {
+        reader.bytes(4 * containers)?;
+    }
+    let mut values = Vec::new();
+    for (i, (key, cardinality)) in headers.into_iter().enumerate() {
+        let high = key << 16;
+        let is_run = run_flags.map_or(false, |f| f[i / 8] & (1 << (i % 8)) != 0);
+        if is_run {
+            for _ in 0..reader.u16()? {
+                let start = reader.u16()? as u32;
+                let length = reader.u16()? as u32;
+                values.extend((start..=start + length).map(|low| high | low));
+            }
+        } else if cardinality <= 4096 {
+            for _ in 0..cardinality {
+                values.push(high | reader.u16()? as u32);
+            }
+        } else {
+            for word_index in 0..1024u32 {
+                let word = reader.u64()?;
+                for bit in 0..64u32 {
+                    if word & (1 << bit) != 0 {
+                        values.push(high | (word_index * 64 + bit));
+                    }
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Actions of a checkpoint as they are written in commits.
+fn read_checkpoint(file: &str) -> Result<Vec<JsonValue>, CubeError> {
+    let reader = SerializedFileReader::new(File::open(file)?)?;
+    let mut actions = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        actions.push(JsonValue::Object(
+            row.get_column_iter()
+                .map(|(name, value)| (name.clone(), field_to_json(value)))
+                .collect(),
+        ));
+    }
+    Ok(actions)
+}
+
+/// Lists and maps of checkpoints only hold strings in fields that are read.
+fn field_to_json(field: &Field) -> JsonValue {
+    match field {
+        Field::Bool(b) => JsonValue::from(*b),
+        Field::Int(i) => JsonValue::from(*i),
+        Field::Long(i) => JsonValue::from(*i),
+        Field::Str(s) => JsonValue::from(s.as_str()),
+        Field::Group(row) => JsonValue::Object(
+            row.get_column_iter()
+                .map(|(name, value)| (name.clone(), field_to_json(value)))
+                .collect(),
+        ),
+        Field::ListInternal(list) => JsonValue::Array(
+            (0..list.len())
+                .map(|i| {
+                    list.get_string(i)
+                        .map(|s| JsonValue::from(s.as_str()))
+                        .unwrap_or(JsonValue::Null)
+                })
+                .collect(),
+        ),
+        Field::MapInternal(map) => {
+            let keys = map.get_keys();
+            let values = map.get_values();
+            JsonValue::Object(
+                (0..map.len())
+                    .filter_map(|i| {
+                        let value = values
+                            .get_string(i)
+                            .map(|s| JsonValue::from(s.as_str()))
+                            .unwrap_or(JsonValue::Null);
+                        Some((keys.get_string(i).ok()?.clone(), value))
+                    })
+                    .collect(),
+            )
+        }
+        _ => JsonValue::Null,
+    }
+}
+
+#[cfg(test)]
+pub fn z85_encode(data: &[u8]) -> String {
+    assert_eq!(data.len() % 4, 0);
+    let mut result = String::with_capacity(data.len() / 4 * 5);
+    for chunk in data.chunks(4) {
+        let mut value = u32::from_be_bytes(chunk.try_into().unwrap()) as u64;
+        let mut chars = [0u8; 5];
+        for c in chars.iter_mut().rev() {
+            *c = Z85_ALPHABET[(value % 85) as usize];
+            value /= 85;
+        }
+        result.push_str(std::str::from_utf8(&chars).unwrap());
+    }
+    result
+}
+
+/// Serializes sorted `rows` as a deletion vector of array containers, padded for Z85.
+#[cfg(test)]
+pub fn write_roaring_array(rows: &[u64]) -> Vec<u8> {
+    let mut bitmaps = BTreeMap::<u32, BTreeMap<u16, Vec<u16>>>::new();
+    for r in rows {
+        bitmaps
+            .entry((r >> 32) as u32)
+            .or_default()
+            .entry((r >> 16) as u16)
+            .or_default()
+            .push(*r as u16);
+    }
+    let mut data = Vec::new();
+    data.extend_from_slice(&ROARING_ARRAY_MAGIC.to_le_bytes());
+    data.extend_from_slice(&(bitmaps.len() as u64).to_le_bytes());
+    for (high, containers) in bitmaps {
+        data.extend_from_slice(&high.to_le_bytes());
+        let start = data.len();
+        data.extend_from_slice(&12346u32.to_le_bytes());
+        data.extend_from_slice(&(containers.len() as u32).to_le_bytes());
+        for (key, values) in &containers {
+            data.extend_from_slice(&key.to_le_bytes());
+            data.extend_from_slice(&(values.len() as u16 - 1).to_le_bytes());
+        }
+        let mut offset = data.len() - start + 4 * containers.len();
+        for values in containers.values() {
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += 2 * values.len();
+        }
+        for v in containers.values().flatten() {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::TimestampValue;
+
+    #[test]
+    fn z85() {
+        // Example of the Z85 specification.
+        let data = [0x86, 0x4F, 0xD2, 0x6F, 0xB5, 0x59, 0xF7, 0x5B];
+        assert_eq!(z85_encode(&data), "HelloWorld");
+        assert_eq!(z85_decode("HelloWorld").unwrap(), data.to_vec());
+        assert!(z85_decode("Hello").is_ok());
+        assert!(z85_decode("Hell").is_err());
+        assert!(z85_decode("Hell~").is_err());
+        assert!(z85_decode("#####").is_err());
+    }
+
+    #[test]
+    fn roaring() {
+        let rows = vec![0, 3, 65536, 65537, 1 << 32, (5 << 32) + 7];
+        assert_eq!(
+            read_roaring_array(&write_roaring_array(&rows)).unwrap(),
+            rows
+        );
+
+        // A run container of 10..=12 and an array container of 70000 without offsets.
+        let mut data = Vec::new();
+        data.extend_from_slice(&ROARING_ARRAY_MAGIC.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&(12347u32 | 1 << 16).to_le_bytes());
+        data.push(0b01);
+        for v in &[0u16, 2, 1, 0, 1, 10, 2, 70000 - 65536] {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        assert_eq!(read_roaring_array(&data).unwrap(), vec![10, 11, 12, 70000]);
+
+        // A bitmap container.
+        let mut data = Vec::new();
+        data.extend_from_slice(&ROARING_ARRAY_MAGIC.to_le_bytes());
+        data.extend_from_slice(&1u64.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&12346u32.to_le_bytes());
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&(4992u16 - 1).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+        for i in 0..1024 {
+            let word = if i < 78 { u64::MAX } else { 0 };
+            data.extend_from_slice(&word.to_le_bytes());
+        }
+        assert_eq!(
+            read_roaring_array(&data).unwrap(),
+            (0..78 * 64).map(|i| i as u64).collect::<Vec<_>>()
+        );
+        assert!(read_roaring_array(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn log() {
+        let dir = "s3://b/t/_delta_log/";
+        let files = log_files(
+            dir,
+            vec![
+                "00000000000000000000.json",
+                "00000000000000000001.json",
+                "00000000000000000002.json",
+                "00000000000000000002.checkpoint.parquet",
+                "00000000000000000003.json",
+                "00000000000000000004.checkpoint.1.2.parquet",
+                "00000000000000000004.json",
+                "00000000000000000005.json",
+                "_last_checkpoint",
+                "00000000000000000005.json.tmp",
+                "_commits/00000000000000000006.json",
+            ]
+            .into_iter()
+            .map(|f| format!("{}{}", dir, f))
+            .collect(),
+        );
+        assert_eq!(
+            files,
+            LogFiles {
+                checkpoint: Some((
+                    2,
+                    vec![format!("{}00000000000000000002.checkpoint.parquet", dir)]
+                )),
+                commits: (3..=5)
+                    .map(|v| (v, format!("{}{:020}.json", dir, v)))
+                    .collect(),
+            }
+        );
+        assert_eq!(files.version("s3://b/t").unwrap(), 5);
+
+        let files = log_files(
+            dir,
+            vec!["00000000000000000001.json", "00000000000000000003.json"]
+                .into_iter()
+                .map(|f| format!("{}{}", dir, f))
+                .collect(),
+        );
+        assert!(files.version("s3://b/t").is_err());
+        assert!(log_files(dir, Vec::new()).version("s3://b/t").is_err());
+    }
+
+    #[test]
+    fn replay() {
+        let mut state = LogState::default();
+        let schema = r#"{"type":"struct","fields":[
+            {"name":"id","type":"long","nullable":true,"metadata":{}},
+            {"name":"dt","type":"timestamp","nullable":true,"metadata":{}},
+            {"name":"region","type":"string","nullable":true,"metadata":{}}
+        ]}"#;
+        let dv = r#"{"storageType":"u","pathOrInlineDv":"ab^-aqEH.-t@S}K{vb[*k^","offset":1,"sizeInBytes":36,"cardinality":2}"#;
+        for action in vec![
+            r#"{"commitInfo":{"operation":"WRITE"}}"#.to_string(),
+            r#"{"protocol":{"minReaderVersion":3,"minWriterVersion":7,"readerFeatures":["deletionVectors"]}}"#.to_string(),
+            format!(
+                r#"{{"metaData":{{"id":"x","format":{{"provider":"parquet","options":{{}}}},"schemaString":{},"partitionColumns":["region","dt"],"configuration":{{}}}}}}"#,
+                JsonValue::from(schema)
+            ),
+            r#"{"add":{"path":"region=us%2Fwest/dt=2021-06-01%2000%253A00%253A00/a.parquet","partitionValues":{"region":"us/west","dt":"2021-06-01 00:00:00"},"size":1,"dataChange":true,"stats":"{\"numRecords\":10}"}}"#.to_string(),
+            r#"{"add":{"path":"s3a://other/b.parquet","partitionValues":{"region":null,"dt":""},"size":1,"dataChange":true}}"#.to_string(),
+            format!(r#"{{"add":{{"path":"s3a://other/b.parquet","partitionValues":{{"region":null,"dt":""}},"size":1,"dataChange":true,"deletionVector":{}}}}}"#, dv),
+            r#"{"remove":{"path":"s3a://other/b.parquet","dataChange":true}}"#.to_string(),
+            r#"{"remove":{"path":"c.parquet","dataChange":true}}"#.to_string(),
+        ] {
+            state
+                .apply(&serde_json::from_str(&action).unwrap())
+                .unwrap();
+        }
+        let (columns, partition_columns) = state.table_schema().unwrap();
+        assert_eq!(
+            columns,
+            vec![
+                ("id".to_string(), ColumnType::Int),
+                ("dt".to_string(), ColumnType::Timestamp),
+                ("region".to_string(), ColumnType::String),
+            ]
+        );
+        assert_eq!(
+            partition_columns,
+            vec!["region".to_string(), "dt".to_string()]
+        );
+        let files = state
+            .files
+            .values()
+            .map(|a| parse_add(a, "s3://b/t", &columns, &partition_columns).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            vec![
+                AddedFile {
+                    path: "s3://b/t/region=us/west/dt=2021-06-01 00%3A00%3A00/a.parquet"
+                        .to_string(),
+                    record_count: Some(10),
+                    partition_values: vec![
+                        TableValue::String("us/west".to_string()),
+                        TableValue::Timestamp(TimestampValue::new(1622505600000000000)),
+                    ],
+                    deletion_vector: None,
+                },
+                AddedFile {
+                    path: "s3://other/b.parquet".to_string(),
+                    record_count: None,
+                    partition_values: vec![TableValue::Null, TableValue::Null],
+                    deletion_vector: Some(DeletionVector {
+                        storage_type: "u".to_string(),
+                        path_or_inline_dv: "ab^-aqEH.-t@S}K{vb[*k^".to_string(),
+                        offset: Some(1),
+                        size_in_bytes: 36,
+                        cardinality: 2,
+                    }),
+                },
+            ]
+        );
+
+        state
+            .apply(
+                &serde_json::from_str(
+                    r#"{"protocol":{"minReaderVersion":3,"minWriterVersion":7,"readerFeatures":["v2Checkpoint"]}}"#,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert!(state.table_schema().is_err());
+    }
+}
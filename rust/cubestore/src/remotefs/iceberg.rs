@@ -0,0 +1,564 @@
+//! Reader of the current snapshot of an Apache Iceberg table, see [crate::sql::attach] for how
+//! it is queried. Metadata is read directly from the table location without a catalog: the
+//! latest `metadata/*.metadata.json` file gives the current schema, partition specs and snapshot,
+//! whose manifest list and manifests are Avro files listing live data and delete files.
+//!
+//! Only parquet data files and columns of types CubeStore reads from parquet as is are supported:
+//! long, double, boolean, string, timestamp and timestamptz. Position deletes are resolved into
+//! positions of deleted rows of each data file, equality deletes are not supported. Columns are
+//! matched to files by name, so renamed columns read as nulls in files written before the rename.
+use crate::metastore::ColumnType;
+use crate::remotefs::RemoteFs;
+use crate::table::{TableValue, TimestampValue};
+use crate::util::avro::{read_container, AvroValue};
+use crate::CubeError;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::fs::File;
+
+#[derive(Debug, PartialEq)]
+pub struct IcebergSnapshot {
+    /// Columns of the current schema.
+    pub columns: Vec<(String, ColumnType)>,
+    /// Columns with identity transforms in the default partition spec, their values are the same
+    /// in all rows of a data file.
+    pub partition_columns: Vec<String>,
+    pub files: Vec<IcebergDataFile>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IcebergDataFile {
+    pub path: String,
+    /// Rows of the file, including deleted ones.
+    pub record_count: u64,
+    /// Values of [IcebergSnapshot::partition_columns], [None] if the file was written with a
+    /// partition spec that lacks some of them.
+    pub partition_values: Option<Vec<TableValue>>,
+    /// Sorted positions of deleted rows.
+    pub deleted_rows: Vec<u64>,
+}
+
+/// Parts of the table metadata file needed to read a snapshot.
+#[derive(Debug, PartialEq)]
+struct TableMetadata {
+    columns: Vec<(String, ColumnType)>,
+    partition_columns: Vec<String>,
+    /// Names of partition fields with the values of each of `partition_columns` by spec id.
+    partition_fields: HashMap<i64, Vec<Option<String>>>,
+    /// [None] for tables without snapshots.
+    manifest_list: Option<String>,
+}
+
+pub async fn read_iceberg_table(
+    remote_fs: &dyn RemoteFs,
+    location: &str,
+) -> Result<IcebergSnapshot, CubeError> {
+    let metadata_dir = format!("{}/metadata/", location);
+    let metadata_file = remote_fs
+        .list(&metadata_dir)
+        .await?
+        .into_iter()
+        .filter(|p| p.ends_with(".metadata.json"))
+        .filter_map(|p| Some((metadata_version(&p[metadata_dir.len()..])?, p)))
+        .max()
+        .map(|(_, p)| p)
+        .ok_or_else(|| {
+            CubeError::user(format!(
+                "No Iceberg metadata files found in {}",
+                metadata_dir
+            ))
+        })?;
+    let json: JsonValue = serde_json::from_slice(&read_file(remote_fs, &metadata_file).await?)
+        .map_err(|e| CubeError::user(format!("Invalid {}: {}", metadata_file, e)))?;
+    let metadata = parse_metadata(&json)
+        .map_err(|e| CubeError::user(format!("Invalid {}: {}", metadata_file, e.message)))?;
+    let manifest_list = match &metadata.manifest_list {
+        None => {
+            return Ok(IcebergSnapshot {
+                columns: metadata.columns,
+                partition_columns: metadata.partition_columns,
+                files: Vec::new(),
+            })
+        }
+        Some(l) => normalize_path(l),
+    };
+
+    let mut files = Vec::new();
+    let mut delete_files = Vec::new();
+    for manifest in read_avro(remote_fs, &manifest_list).await? {
+        let manifest_path = normalize_path(avro_str(&manifest, "manifest_path")?);
+        let spec_id = avro_i64(&manifest, "partition_spec_id")?;
+        for entry in read_avro(remote_fs, &manifest_path).await? {
+            // Entries of files removed by the snapshot.
+            if avro_i64(&entry, "status")? == 2 {
+                continue;
+            }
+            let data_file = entry
+                .field("data_file")
+                .ok_or_else(|| invalid_avro(&manifest_path, "data_file"))?;
+            let path = normalize_path(avro_str(data_file, "file_path")?);
+            let format = avro_str(data_file, "file_format")?;
+            if !format.eq_ignore_ascii_case("parquet") {
+                return Err(CubeError::user(format!(
+                    "Unsupported format {} of Iceberg file {}, only parquet is supported",
+                    format, path
+                )));
+            }
+            match data_file.field("content").and_then(|c| c.as_i64()) {
+                None | Some(0) => {
+                    let partition_values = partition_values(
+                        &metadata,
+                        spec_id,
+                        data_file.field("partition"),
+                    )
+                    .map_err(|e| {
+                        CubeError::user(format!("Invalid {}: {}", manifest_path, e.message))
+                    })?;
+                    files.push(IcebergDataFile {
+                        path,
+                        record_count: avro_i64(data_file, "record_count")? as u64,
+                        partition_values,
+                        deleted_rows: Vec::new(),
+                    });
+                }
+                Some(1) => delete_files.push(path),
+                Some(_) => {
+                    return Err(CubeError::user(format!(
+                        "Iceberg table {} has equality deletes, which are not supported. Rewrite its data files to apply them",
+                        location
+                    )))
+                }
+            }
+        }
+    }
+
+    let mut deleted_rows = HashMap::<String, Vec<u64>>::new();
+    for path in delete_files {
+        let local_file = remote_fs.download_file(&path).await?;
+        let deletes =
+            tokio::task::spawn_blocking(move || read_position_deletes(&local_file)).await??;
+        for (file, pos) in deletes {
+            deleted_rows
+                .entry(normalize_path(&file))
+                .or_default()
+                .push(pos);
+        }
+    }
+    for f in files.iter_mut() {
+        if let Some(mut rows) = deleted_rows.remove(&f.path) {
+            rows.sort_unstable();
+            rows.dedup();
+            f.deleted_rows = rows;
+        }
+    }
+    Ok(IcebergSnapshot {
+        columns: metadata.columns,
+        partition_columns: metadata.partition_columns,
+        files,
+    })
+}
+
+/// Version of `v<n>.metadata.json` written by Hadoop tables or `<n>-<uuid>.metadata.json` written
+/// by catalogs.
+fn metadata_version(file_name: &str) -> Option<u64> {
+    let digits = file_name
+        .trim_start_matches('v')
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?;
+    digits.parse().ok()
+}
+
+/// Iceberg writes paths with the schemes of Hadoop file systems, e.g. `s3a://` or `file:/`.
+pub fn normalize_path(path: &str) -> String {
+    for (prefix, replacement) in &[
+        ("s3a://", "s3://"),
+        ("s3n://", "s3://"),
+        ("gs://", "gcs://"),
+        ("file:///", "file:///"),
+        ("file:/", "file:///"),
+    ] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            return format!("{}{}", replacement, rest);
+        }
+    }
+    path.to_string()
+}
+
+fn parse_metadata(json: &JsonValue) -> Result<TableMetadata, CubeError> {
+    let error = |field: &str| CubeError::user(format!("{} is missing or invalid", field));
+    let schema = match json.get("schemas") {
+        Some(JsonValue::Array(schemas)) => {
+            let id = json.get("current-schema-id").and_then(|i| i.as_i64());
+            schemas
+                .iter()
+                .find(|s| s.get("schema-id").and_then(|i| i.as_i64()) == id)
+                .ok_or_else(|| error("current-schema-id"))?
+        }
+        _ => json.get("schema").ok_or_else(|| error("schema"))?,
+    };
+    let mut columns = Vec::new();
+    let mut column_ids = HashMap::new();
+    for field in schema
+        .get("fields")
+        .and_then(|f| f.as_array())
+        .ok_or_else(|| error("schema fields"))?
+    {
+        let name = field
+            .get("name")
+            .and_then(|n| n.as_str())
+            .ok_or_else(|| error("field name"))?;
+        let id = field
+            .get("id")
+            .and_then(|i| i.as_i64())
+            .ok_or_else(|| error("field id"))?;
+        let column_type = match field.get("type").and_then(|t| t.as_str()) {
+            Some("long") => ColumnType::Int,
+            Some("double") => ColumnType::Float,
+            Some("boolean") => ColumnType::Boolean,
+            Some("string") => ColumnType::String,
+            Some("timestamp") | Some("timestamptz") => ColumnType::Timestamp,
+            _ => {
+                return Err(CubeError::user(format!(
+                    "unsupported type {} of column {}",
+                    field.get("type").unwrap_or(&JsonValue::Null),
+                    name
+                )))
+            }
+        };
+        column_ids.insert(id, columns.len());
+        columns.push((name.to_string(), column_type));
+    }
+
+    // Spec fields are `(name, transform, source column)`.
+    let mut specs = HashMap::new();
+    let parse_fields =
+        |fields: &JsonValue| -> Result<Vec<(String, String, Option<usize>)>, CubeError> {
+            fields
+                .as_array()
+                .ok_or_else(|| error("partition spec fields"))?
+                .iter()
+                .map(|f| {
+                    Ok((
+                        f.get("name")
+                            .and_then(|n| n.as_str())
+                            .ok_or_else(|| error("partition field name"))?
+                            .to_string(),
+                        f.get("transform")
+                            .and_then(|n| n.as_str())
+                            .ok_or_else(|| error("partition field transform"))?
+                            .to_string(),
+                        f.get("source-id")
+                            .and_then(|i| i.as_i64())
+                            .and_then(|i| column_ids.get(&i).cloned()),
+                    ))
+                })
+                .collect()
+        };
+    match json.get("partition-specs") {
+        Some(JsonValue::Array(all)) => {
+            for spec in all {
+                let id = spec
+                    .get("spec-id")
+                    .and_then(|i| i.as_i64())
+                    .ok_or_else(|| error("spec-id"))?;
+                specs.insert(
+                    id,
+                    parse_fields(spec.get("fields").ok_or_else(|| error("fields"))?)?,
+                );
+            }
+        }
+        _ => {
+            specs.insert(
+                0,
+                parse_fields(
+                    json.get("partition-spec")
+                        .ok_or_else(|| error("partition-spec"))?,
+                )?,
+            );
+        }
+    }
+    let default_spec_id = json
+        .get("default-spec-id")
+        .and_then(|i| i.as_i64())
+        .unwrap_or(0);
+    let default_spec = specs
+        .get(&default_spec_id)
+        .ok_or_else(|| error("default-spec-id"))?;
+
+    // Floats can't be compared as bounds of partitions.
+    let mut partition_sources = Vec::new();
+    for (_, transform, source) in default_spec {
+        match source {
+            Some(c)
+                if transform == "identity"
+                    && columns[*c].1 != ColumnType::Float
+                    && !partition_sources.contains(c) =>
+            {
+                partition_sources.push(*c)
+            }
+            _ => {}
+        }
+    }
+    let partition_fields = specs
+        .iter()
+        .map(|(id, fields)| {
+            let names = partition_sources
+                .iter()
+                .map(|c| {
+                    fields
+                        .iter()
+                        .find(|(_, transform, source)| {
+                            transform == "identity" && source == &Some(*c)
+                        })
+                        .map(|(name, _, _)| name.clone())
+                })
+                .collect();
+            (*id, names)
+        })
+        .collect();
+
+    let snapshot_id = json.get("current-snapshot-id").and_then(|i| i.as_i64());
+    let manifest_list = match snapshot_id {
+        None | Some(-1) => None,
+        Some(id) => {
+            let snapshot = json
+                .get("snapshots")
+                .and_then(|s| s.as_array())
+                .and_then(|s| {
+                    s.iter()
+                        .find(|s| s.get("snapshot-id").and_then(|i| i.as_i64()) == Some(id))
+                })
+                .ok_or_else(|| error("current-snapshot-id"))?;
+            Some(
+                snapshot
+                    .get("manifest-list")
+                    .and_then(|l| l.as_str())
+                    .ok_or_else(|| {
+                        CubeError::user(
+                            "snapshots without manifest-list are not supported".to_string(),
+                        )
+                    })?
+                    .to_string(),
+            )
+        }
+    };
+
+    Ok(TableMetadata {
+        partition_columns: partition_sources
+            .iter()
+            .map(|c| columns[*c].0.clone())
+            .collect(),
+        columns,
+        partition_fields,
+        manifest_list,
+    })
+}
+
+fn partition_values(
+    metadata: &TableMetadata,
+    spec_id: i64,
+    partition: Option<&AvroValue>,
+) -> Result<Option<Vec<TableValue>>, CubeError> {
+    let fields = metadata
+        .partition_fields
+        .get(&spec_id)
+        .ok_or_else(|| CubeError::user(format!("unknown partition spec {}", spec_id)))?;
+    let mut values = Vec::with_capacity(fields.len());
+    for (field, column) in fields.iter().zip(metadata.partition_columns.iter()) {
+        let field = match field {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let column_type = &metadata
+            .columns
+            .iter()
+            .find(|(name, _)| name == column)
+            .unwrap()
+            .1;
+        let value = partition
+            .and_then(|p| p.field(field))
+            .ok_or_else(|| CubeError::user(format!("partition field {} is missing", field)))?;
+        values.push(match (value, column_type) {
+            (AvroValue::Null, _) => TableValue::Null,
+            (AvroValue::String(s), ColumnType::String) => TableValue::String(s.clone()),
+            (AvroValue::Long(i), ColumnType::Int) => TableValue::Int(*i),
+            (AvroValue::Boolean(b), ColumnType::Boolean) => TableValue::Boolean(*b),
+            (AvroValue::Long(micros), ColumnType::Timestamp) => {
+                TableValue::Timestamp(TimestampValue::new(micros * 1000))
+            }
+            (v, t) => {
+                return Err(CubeError::user(format!(
+                    "value {:?} of partition field {} does not match type {:?}",
+                    v, field, t
+                )))
+            }
+        });
+    }
+    Ok(Some(values))
+}
+
+pub(crate) async fn read_file(remote_fs: &dyn RemoteFs, path: &str) -> Result<Vec<u8>, CubeError> {
+    let local_file = remote_fs.download_file(path).await?;
+    Ok(tokio::fs::read(local_file).await?)
+}
+
+async fn read_avro(remote_fs: &dyn RemoteFs, path: &str) -> Result<Vec<AvroValue>, CubeError> {
+    let data = read_file(remote_fs, path).await?;
+    let file = tokio::task::spawn_blocking(move || read_container(&data))
+        .await?
+        .map_err(|e| CubeError::user(format!("Can't read {}: {}", path, e.message)))?;
+    Ok(file.values)
+}
+
+fn avro_str<'a>(record: &'a AvroValue, field: &str) -> Result<&'a str, CubeError> {
+    record
+        .field(field)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| CubeError::user(format!("Iceberg field {} is missing", field)))
+}
+
+fn avro_i64(record: &AvroValue, field: &str) -> Result<i64, CubeError> {
+    record
+        .field(field)
+        .and_then(|v| v.as_i64())
+        .ok_or_else(|| CubeError::user(format!("Iceberg field {} is missing", field)))
+}
+
+fn invalid_avro(path: &str, field: &str) -> CubeError {
+    CubeError::user(format!("Field {} is missing in {}", field, path))
+}
+
+/// `(file_path, pos)` pairs of a position delete file.
+fn read_position_deletes(file: &str) -> Result<Vec<(String, u64)>, CubeError> {
+    let reader = SerializedFileReader::new(File::open(file)?)?;
+    let mut deletes = Vec::new();
+    for row in reader.get_row_iter(None)? {
+        let mut path = None;
+        let mut pos = None;
+        for (name, value) in row.get_column_iter() {
+            match (name.as_str(), value) {
+                ("file_path", Field::Str(s)) => path = Some(s.clone()),
+                ("pos", Field::Long(p)) => pos = Some(*p as u64),
+                _ => {}
+            }
+        }
+        match (path, pos) {
+            (Some(path), Some(pos)) => deletes.push((path, pos)),
+            _ => {
+                return Err(CubeError::user(format!(
+                    "Position delete file {} lacks file_path or pos",
+                    file
+                )))
+            }
+        }
+    }
+    Ok(deletes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let json: JsonValue = serde_json::from_str(
+            r#"{
+                "format-version": 2,
+                "current-schema-id": 1,
+                "schemas": [
+                    {"schema-id": 0, "fields": [{"id": 1, "name": "old", "type": "int"}]},
+                    {"schema-id": 1, "fields": [
+                        {"id": 1, "name": "id", "required": true, "type": "long"},
+                        {"id": 2, "name": "region", "required": false, "type": "string"},
+                        {"id": 3, "name": "ts", "required": false, "type": "timestamptz"},
+                        {"id": 4, "name": "amount", "required": false, "type": "double"}
+                    ]}
+                ],
+                "default-spec-id": 1,
+                "partition-specs": [
+                    {"spec-id": 0, "fields": []},
+                    {"spec-id": 1, "fields": [
+                        {"name": "region", "transform": "identity", "source-id": 2, "field-id": 1000},
+                        {"name": "ts_day", "transform": "day", "source-id": 3, "field-id": 1001},
+                        {"name": "amount", "transform": "identity", "source-id": 4, "field-id": 1002}
+                    ]}
+                ],
+                "current-snapshot-id": 7,
+                "snapshots": [
+                    {"snapshot-id": 6, "manifest-list": "s3a://b/t/metadata/snap-6.avro"},
+                    {"snapshot-id": 7, "manifest-list": "s3a://b/t/metadata/snap-7.avro"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let metadata = parse_metadata(&json).unwrap();
+        assert_eq!(
+            metadata.columns,
+            vec![
+                ("id".to_string(), ColumnType::Int),
+                ("region".to_string(), ColumnType::String),
+                ("ts".to_string(), ColumnType::Timestamp),
+                ("amount".to_string(), ColumnType::Float),
+            ]
+        );
+        assert_eq!(metadata.partition_columns, vec!["region".to_string()]);
+        assert_eq!(metadata.partition_fields[&0], vec![None]);
+        assert_eq!(
+            metadata.partition_fields[&1],
+            vec![Some("region".to_string())]
+        );
+        assert_eq!(
+            metadata.manifest_list,
+            Some("s3a://b/t/metadata/snap-7.avro".to_string())
+        );
+
+        let partition = AvroValue::Record(vec![
+            ("region".to_string(), AvroValue::String("us".to_string())),
+            ("ts_day".to_string(), AvroValue::Int(18000)),
+            ("amount".to_string(), AvroValue::Null),
+        ]);
+        assert_eq!(
+            partition_values(&metadata, 1, Some(&partition)).unwrap(),
+            Some(vec![TableValue::String("us".to_string())])
+        );
+        assert_eq!(partition_values(&metadata, 0, None).unwrap(), None);
+        assert!(partition_values(&metadata, 2, None).is_err());
+
+        let mut unsupported = json.clone();
+        unsupported["current-schema-id"] = JsonValue::from(0);
+        assert!(parse_metadata(&unsupported).is_err());
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(metadata_version("v12.metadata.json"), Some(12));
+        assert_eq!(
+            metadata_version("00003-6ff2c3a5-b2a9-4d43-a8b1-0b2d5a9f4a51.metadata.json"),
+            Some(3)
+        );
+        assert_eq!(metadata_version("version-hint.text"), None);
+
+        assert_eq!(
+            normalize_path("s3a://bucket/t/a.parquet"),
+            "s3://bucket/t/a.parquet"
+        );
+        assert_eq!(
+            normalize_path("gs://bucket/a.parquet"),
+            "gcs://bucket/a.parquet"
+        );
+        assert_eq!(
+            normalize_path("file:/tmp/t/a.parquet"),
+            "file:///tmp/t/a.parquet"
+        );
+        assert_eq!(
+            normalize_path("file:///tmp/t/a.parquet"),
+            "file:///tmp/t/a.parquet"
+        );
+        assert_eq!(
+            normalize_path("s3://bucket/a.parquet"),
+            "s3://bucket/a.parquet"
+        );
+    }
+}
@@ -1,6 +1,9 @@
+pub mod delta;
 pub mod gcs;
+pub mod iceberg;
 pub mod queue;
 pub mod s3;
+pub mod storage;
 
 use crate::config::injection::DIService;
 use crate::di_service;
@@ -75,6 +78,53 @@ pub trait RemoteFs: DIService + Send + Sync + Debug {
     async fn local_file(&self, remote_path: &str) -> Result<String, CubeError>;
 }
 
+/// Hive and Delta Lake escape special characters of file and directory names as `%XX`.
+pub fn unescape_path_name(s: &str) -> Result<String, CubeError> {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                result.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        result.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(result).map_err(|_| CubeError::user(format!("Invalid path name: {}", s)))
+}
+
+/// Escapes characters of directory names as Hive does, see [unescape_path_name].
+pub fn escape_path_name(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\u{1}'..='\u{1F}'
+            | '"'
+            | '#'
+            | '%'
+            | '\''
+            | '*'
+            | '/'
+            | ':'
+            | '='
+            | '?'
+            | '\\'
+            | '\u{7F}'
+            | '{'
+            | '['
+            | ']'
+            | '^' => result.push_str(&format!("%{:02X}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
 #[derive(Debug)]
 pub struct LocalDirRemoteFs {
     remote_dir_for_debug: Option<PathBuf>,
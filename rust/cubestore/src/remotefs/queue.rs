@@ -12,6 +12,7 @@ use smallvec::alloc::fmt::Formatter;
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::{broadcast, watch, RwLock};
 use tokio::task::spawn_blocking;
@@ -29,6 +30,13 @@ pub struct QueueRemoteFs {
     result_sender: broadcast::Sender<RemoteFsOpResult>,
     stopped_rx: watch::Receiver<bool>,
     stopped_tx: watch::Sender<bool>,
+    /// Set after a failed download until the next successful one. Only locally cached files can
+    /// be read in this state.
+    remote_unavailable: AtomicBool,
+    /// Downloads failed since the remote storage became unavailable.
+    failed_downloads: AtomicU64,
+    /// Files served from the local cache since the remote storage became unavailable.
+    cached_downloads: AtomicU64,
 }
 
 impl Debug for QueueRemoteFs {
@@ -73,6 +81,9 @@ impl QueueRemoteFs {
             _result_receiver: rx,
             stopped_tx,
             stopped_rx,
+            remote_unavailable: AtomicBool::new(false),
+            failed_downloads: AtomicU64::new(0),
+            cached_downloads: AtomicU64::new(0),
         })
     }
 
@@ -174,6 +185,7 @@ impl QueueRemoteFs {
         match to_process {
             RemoteFsOp::Download(file) => {
                 let result = self.remote_fs.download_file(file.as_str()).await;
+                let result = self.track_availability(&file, result);
                 let mut downloading =
                     acquire_lock("download loop downloading", self.downloading.write()).await?;
                 self.result_sender
@@ -185,6 +197,44 @@ impl QueueRemoteFs {
         Ok(())
     }
 
+    fn track_availability(
+        &self,
+        file: &str,
+        result: Result<String, CubeError>,
+    ) -> Result<String, CubeError> {
+        match result {
+            Ok(local_path) => {
+                if self.remote_unavailable.swap(false, Ordering::SeqCst) {
+                    log::info!(
+                        "Remote storage is available again. While it was unavailable {} downloads failed and {} files were read from the local cache",
+                        self.failed_downloads.swap(0, Ordering::SeqCst),
+                        self.cached_downloads.swap(0, Ordering::SeqCst)
+                    );
+                }
+                Ok(local_path)
+            }
+            Err(e) => {
+                if !self.remote_unavailable.swap(true, Ordering::SeqCst) {
+                    log::warn!(
+                        "Remote storage is unavailable, only locally cached files can be read: {}",
+                        e
+                    );
+                }
+                let failed = self.failed_downloads.fetch_add(1, Ordering::SeqCst) + 1;
+                log::debug!(
+                    "Download of {} failed, {} downloads failed and {} files were read from the local cache since remote storage became unavailable",
+                    file,
+                    failed,
+                    self.cached_downloads.load(Ordering::SeqCst)
+                );
+                Err(CubeError::unavailable(format!(
+                    "File {} is not cached locally and can't be downloaded from remote storage: {}",
+                    file, e.message
+                )))
+            }
+        }
+    }
+
     const CLEANUP_INTERVAL: Duration = Duration::from_secs(600);
     /// Periodically cleans up the local directory from the files removed on the remote side.
     /// This function currently removes only direct sibling files and does not touch subdirectories.
@@ -286,6 +336,10 @@ impl QueueRemoteFs {
 
 #[async_trait]
 impl RemoteFs for QueueRemoteFs {
+    async fn temp_upload_path(&self, remote_path: &str) -> Result<String, CubeError> {
+        self.remote_fs.temp_upload_path(remote_path).await
+    }
+
     async fn upload_file(
         &self,
         local_upload_path: &str,
@@ -316,6 +370,9 @@ impl RemoteFs for QueueRemoteFs {
         // We might be lucky and the file has already been downloaded.
         if let Ok(local_path) = self.local_file(remote_path).await {
             if tokio::fs::metadata(&local_path).await.is_ok() {
+                if self.remote_unavailable.load(Ordering::SeqCst) {
+                    self.cached_downloads.fetch_add(1, Ordering::SeqCst);
+                }
                 return Ok(local_path);
             }
         }
@@ -375,3 +432,33 @@ impl RemoteFs for QueueRemoteFs {
         self.remote_fs.local_file(remote_path).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::remotefs::LocalDirRemoteFs;
+    use crate::CubeErrorCauseType;
+    use std::env;
+
+    #[test]
+    fn remote_unavailable() {
+        let local_dir = env::current_dir().unwrap().join("queue-remote-unavailable");
+        let fs = QueueRemoteFs::new(
+            Config::test("queue-remote-unavailable").config_obj(),
+            LocalDirRemoteFs::new(None, local_dir),
+        );
+
+        let e = fs
+            .track_availability("1.parquet", Err(CubeError::internal("timeout".to_string())))
+            .unwrap_err();
+        assert!(matches!(e.cause, CubeErrorCauseType::Unavailable));
+        assert!(fs.remote_unavailable.load(Ordering::SeqCst));
+        assert_eq!(fs.failed_downloads.load(Ordering::SeqCst), 1);
+
+        fs.track_availability("2.parquet", Ok("2.parquet".to_string()))
+            .unwrap();
+        assert!(!fs.remote_unavailable.load(Ordering::SeqCst));
+        assert_eq!(fs.failed_downloads.load(Ordering::SeqCst), 0);
+    }
+}
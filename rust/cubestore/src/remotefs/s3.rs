@@ -1,7 +1,7 @@
 use crate::di_service;
 use crate::remotefs::{LocalDirRemoteFs, RemoteFile, RemoteFs};
 use crate::util::lock::acquire_lock;
-use crate::CubeError;
+use crate::{is_transient_http_status, CubeError};
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use log::{debug, info};
@@ -82,10 +82,7 @@ impl RemoteFs for S3RemoteFs {
         }
         info!("Uploaded {} ({:?})", remote_path, time.elapsed()?);
         if status_code != 200 {
-            return Err(CubeError::user(format!(
-                "S3 upload returned non OK status: {}",
-                status_code
-            )));
+            return Err(status_error("upload", status_code));
         }
         Ok(())
     }
@@ -117,10 +114,7 @@ impl RemoteFs for S3RemoteFs {
             .await??;
             info!("Downloaded {} ({:?})", remote_path, time.elapsed()?);
             if status_code != 200 {
-                return Err(CubeError::user(format!(
-                    "S3 download returned non OK status: {}",
-                    status_code
-                )));
+                return Err(status_error("download", status_code));
             }
         }
         Ok(local_file_str)
@@ -135,10 +129,7 @@ impl RemoteFs for S3RemoteFs {
             tokio::task::spawn_blocking(move || bucket.delete_object_blocking(path)).await??;
         info!("Deleting {} ({:?})", remote_path, time.elapsed()?);
         if status_code != 204 {
-            return Err(CubeError::user(format!(
-                "S3 delete returned non OK status: {}",
-                status_code
-            )));
+            return Err(status_error("delete", status_code));
         }
 
         let _guard = acquire_lock("delete file", self.delete_mut.lock()).await?;
@@ -194,6 +185,16 @@ impl RemoteFs for S3RemoteFs {
     }
 }
 
+/// Retrying helps with throttling and failures of S3 itself, other statuses fail the same way.
+fn status_error(operation: &str, status_code: u16) -> CubeError {
+    let message = format!("S3 {} returned non OK status: {}", operation, status_code);
+    if is_transient_http_status(status_code) {
+        CubeError::unavailable(message)
+    } else {
+        CubeError::user(message)
+    }
+}
+
 impl S3RemoteFs {
     fn s3_path(&self, remote_path: &str) -> String {
         format!(
@@ -0,0 +1,198 @@
+//! Tables created with `WITH (storage = '<location>')` keep their partitions and chunks in
+//! `<location>` instead of the storage configured for the whole cluster, e.g. to keep datasets
+//! of different compliance boundaries in separate buckets. Supported locations are
+//! `s3://<bucket>[/<path>]`, `gcs://<bucket>[/<path>]` and `file://<absolute path>`.
+//! Remote paths of such files are prefixed with the location, [StorageRemoteFs] routes them to
+//! the backend of the location. Local copies are kept in a subdirectory of the local dir named
+//! after the location.
+use crate::di_service;
+use crate::remotefs::gcs::GCSRemoteFs;
+use crate::remotefs::s3::S3RemoteFs;
+use crate::remotefs::{LocalDirRemoteFs, RemoteFile, RemoteFs};
+use crate::CubeError;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::env;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Remote path of `file_name` stored in `storage`.
+pub fn storage_file_name(storage: &Option<String>, file_name: String) -> String {
+    match storage {
+        Some(location) => format!("{}/{}", location.trim_end_matches('/'), file_name),
+        None => file_name,
+    }
+}
+
+/// Checks the location is supported.
+pub fn validate_storage(location: &str) -> Result<(), CubeError> {
+    let (scheme, path) = split_location(location)
+        .ok_or_else(|| CubeError::user(format!("Invalid storage location: {}", location)))?;
+    match scheme {
+        "s3" | "gcs" if !path.is_empty() && !path.starts_with('/') => Ok(()),
+        "file" if path.starts_with('/') => Ok(()),
+        _ => Err(CubeError::user(format!(
+            "Unsupported storage location '{}', expected s3://<bucket>, gcs://<bucket> or file://<absolute path>",
+            location
+        ))),
+    }
+}
+
+fn split_location(location: &str) -> Option<(&str, &str)> {
+    let i = location.find("://")?;
+    Some((&location[..i], location[i + 3..].trim_end_matches('/')))
+}
+
+#[derive(Debug)]
+pub struct StorageRemoteFs {
+    default: Arc<dyn RemoteFs>,
+    dir: PathBuf,
+    storages: RwLock<HashMap<String, Arc<dyn RemoteFs>>>,
+}
+
+di_service!(StorageRemoteFs, [RemoteFs]);
+
+impl StorageRemoteFs {
+    pub fn new(default: Arc<dyn RemoteFs>, dir: PathBuf) -> Arc<Self> {
+        Arc::new(Self {
+            default,
+            dir,
+            storages: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Backend for the path and the path relative to it.
+    async fn route<'a>(
+        &self,
+        remote_path: &'a str,
+    ) -> Result<(Arc<dyn RemoteFs>, &'a str), CubeError> {
+        if !remote_path.contains("://") {
+            return Ok((self.default.clone(), remote_path));
+        }
+        let i = remote_path.rfind('/').unwrap();
+        let (location, file_name) = (&remote_path[..i], &remote_path[i + 1..]);
+        if let Some(fs) = self.storages.read().await.get(location) {
+            return Ok((fs.clone(), file_name));
+        }
+        let mut storages = self.storages.write().await;
+        if !storages.contains_key(location) {
+            storages.insert(location.to_string(), self.create_storage(location)?);
+        }
+        Ok((storages[location].clone(), file_name))
+    }
+
+    fn create_storage(&self, location: &str) -> Result<Arc<dyn RemoteFs>, CubeError> {
+        validate_storage(location)?;
+        let (scheme, path) = split_location(location).unwrap();
+        let dir = self.dir.join(scheme).join(path.trim_start_matches('/'));
+        let (bucket, sub_path) = match path.find('/') {
+            Some(i) => (path[..i].to_string(), Some(path[i + 1..].to_string())),
+            None => (path.to_string(), None),
+        };
+        let fs: Arc<dyn RemoteFs> = match scheme {
+            "s3" => {
+                let region = env::var("CUBESTORE_S3_REGION").map_err(|_| {
+                    CubeError::user(format!(
+                        "CUBESTORE_S3_REGION is required for storage {}",
+                        location
+                    ))
+                })?;
+                S3RemoteFs::new(dir, region, bucket, sub_path)?
+            }
+            "gcs" => GCSRemoteFs::new(dir, bucket, sub_path)?,
+            _ => LocalDirRemoteFs::new(Some(PathBuf::from(path)), dir),
+        };
+        Ok(fs)
+    }
+}
+
+#[async_trait]
+impl RemoteFs for StorageRemoteFs {
+    async fn temp_upload_path(&self, remote_path: &str) -> Result<String, CubeError> {
+        let (fs, path) = self.route(remote_path).await?;
+        fs.temp_upload_path(path).await
+    }
+
+    async fn upload_file(
+        &self,
+        temp_upload_path: &str,
+        remote_path: &str,
+    ) -> Result<(), CubeError> {
+        let (fs, path) = self.route(remote_path).await?;
+        fs.upload_file(temp_upload_path, path).await
+    }
+
+    async fn download_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let (fs, path) = self.route(remote_path).await?;
+        fs.download_file(path).await
+    }
+
+    async fn delete_file(&self, remote_path: &str) -> Result<(), CubeError> {
+        let (fs, path) = self.route(remote_path).await?;
+        fs.delete_file(path).await
+    }
+
+    /// Paths of files in other locations are prefixed with the location.
+    async fn list(&self, remote_prefix: &str) -> Result<Vec<String>, CubeError> {
+        Ok(self
+            .list_with_metadata(remote_prefix)
+            .await?
+            .into_iter()
+            .map(|f| f.remote_path)
+            .collect())
+    }
+
+    async fn list_with_metadata(&self, remote_prefix: &str) -> Result<Vec<RemoteFile>, CubeError> {
+        let (fs, prefix) = self.route(remote_prefix).await?;
+        let files = fs.list_with_metadata(prefix).await?;
+        if !remote_prefix.contains("://") {
+            return Ok(files);
+        }
+        let location = &remote_prefix[..remote_prefix.len() - prefix.len()];
+        Ok(files
+            .into_iter()
+            .map(|f| RemoteFile {
+                remote_path: format!("{}{}", location, f.remote_path),
+                updated: f.updated,
+            })
+            .collect())
+    }
+
+    async fn local_path(&self) -> String {
+        self.default.local_path().await
+    }
+
+    async fn local_file(&self, remote_path: &str) -> Result<String, CubeError> {
+        let (fs, path) = self.route(remote_path).await?;
+        fs.local_file(path).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storage_locations() {
+        assert_eq!(
+            storage_file_name(&None, "1.parquet".to_string()),
+            "1.parquet"
+        );
+        assert_eq!(
+            storage_file_name(
+                &Some("s3://bucket/path/".to_string()),
+                "1.parquet".to_string()
+            ),
+            "s3://bucket/path/1.parquet"
+        );
+
+        assert!(validate_storage("s3://bucket").is_ok());
+        assert!(validate_storage("gcs://bucket/path").is_ok());
+        assert!(validate_storage("file:///tmp/cubestore").is_ok());
+        assert!(validate_storage("file://relative").is_err());
+        assert!(validate_storage("s3://").is_err());
+        assert!(validate_storage("ftp://host/path").is_err());
+        assert!(validate_storage("bucket").is_err());
+    }
+}
@@ -0,0 +1,577 @@
+//! Replays the workload captured in the query log, see [crate::sql::query_log], with
+//! `cubestored replay <log> [options]`, to validate upgrades and config changes on a test cluster
+//! before they reach production. Queries are sent over MySQL to [ReplayOptions::target]. Queries
+//! of the same session are sent in their recorded order over a single connection, so `SET`
+//! statements apply to the same queries as before.
+//!
+//! With [Pacing::Recorded] each query starts at its recorded offset from the first query divided
+//! by [ReplayOptions::speed], which also reproduces the recorded concurrency. With [Pacing::None]
+//! queries are sent as soon as the previous query of the session completes, with up to
+//! [ReplayOptions::concurrency] sessions at a time.
+//!
+//! Only read-only statements are replayed unless [ReplayOptions::include_writes] is set, as other
+//! statements change the data of the target. The report compares the replay with the recorded
+//! run: queries that fail now but did not before, queries that return a different number of rows
+//! and latencies of both runs.
+use crate::sql::is_read_only;
+use crate::sql::parser::CubeStoreParser;
+use crate::sql::query_log::QueryLogEntry;
+use crate::CubeError;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use mysql_async::prelude::Queryable;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const USAGE: &str = "Usage: cubestored replay <query log> [options]
+    --target <url>           MySQL URL of the cluster to replay on, mysql://127.0.0.1:3306 by default
+    --pacing <recorded|none> start queries at their recorded times or as fast as possible,
+                             recorded by default
+    --speed <n>              speed up recorded pacing n times, 1 by default
+    --concurrency <n>        sessions replayed at a time without pacing, 1 by default
+    --include-writes         also replay statements that change data or schema";
+
+/// Number of failed and mismatched queries listed in the report.
+const MAX_LISTED: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    Recorded,
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    pub log: PathBuf,
+    pub target: String,
+    pub pacing: Pacing,
+    pub speed: f64,
+    pub concurrency: usize,
+    pub include_writes: bool,
+}
+
+impl ReplayOptions {
+    /// Arguments after `replay`.
+    pub fn parse(args: &[String]) -> Result<ReplayOptions, CubeError> {
+        let mut log = None;
+        let mut options = ReplayOptions {
+            log: PathBuf::new(),
+            target: "mysql://127.0.0.1:3306".to_string(),
+            pacing: Pacing::Recorded,
+            speed: 1.0,
+            concurrency: 1,
+            include_writes: false,
+        };
+        let mut args = args.iter();
+        while let Some(name) = args.next() {
+            if !name.starts_with("--") && log.is_none() {
+                log = Some(PathBuf::from(name));
+                continue;
+            }
+            if name == "--include-writes" {
+                options.include_writes = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| CubeError::user(format!("Missing value of {}\n{}", name, USAGE)))?;
+            let invalid = |expected: &str| {
+                CubeError::user(format!(
+                    "Value of {} must be {}, found: {}",
+                    name, expected, value
+                ))
+            };
+            match name.as_str() {
+                "--target" => options.target = value.to_string(),
+                "--pacing" => {
+                    options.pacing = match value.as_str() {
+                        "recorded" => Pacing::Recorded,
+                        "none" => Pacing::None,
+                        _ => return Err(invalid("recorded or none")),
+                    }
+                }
+                "--speed" => {
+                    options.speed = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|v| *v > 0.0)
+                        .ok_or_else(|| invalid("a positive number"))?
+                }
+                "--concurrency" => {
+                    options.concurrency = value
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|v| *v > 0)
+                        .ok_or_else(|| invalid("a positive integer"))?
+                }
+                _ => {
+                    return Err(CubeError::user(format!(
+                        "Unknown option: {}\n{}",
+                        name, USAGE
+                    )))
+                }
+            }
+        }
+        options.log =
+            log.ok_or_else(|| CubeError::user(format!("Missing query log\n{}", USAGE)))?;
+        Ok(options)
+    }
+}
+
+/// Cluster the workload is replayed on.
+#[async_trait]
+pub trait ReplayTarget: Send + Sync {
+    /// Connection that runs queries of a single session.
+    async fn connect(&self) -> Result<Box<dyn ReplaySession>, CubeError>;
+}
+
+#[async_trait]
+pub trait ReplaySession: Send {
+    /// Returns the number of rows of the result.
+    async fn execute(&mut self, query: &str) -> Result<u64, CubeError>;
+}
+
+pub struct MySqlTarget {
+    opts: mysql_async::Opts,
+}
+
+impl MySqlTarget {
+    pub fn new(url: &str) -> Result<MySqlTarget, CubeError> {
+        let opts = mysql_async::Opts::from_url(url)
+            .map_err(|e| CubeError::user(format!("Invalid target {}: {}", url, e)))?;
+        Ok(MySqlTarget { opts })
+    }
+}
+
+#[async_trait]
+impl ReplayTarget for MySqlTarget {
+    async fn connect(&self) -> Result<Box<dyn ReplaySession>, CubeError> {
+        let conn = mysql_async::Conn::new(self.opts.clone())
+            .await
+            .map_err(|e| CubeError::user(format!("Can't connect to target: {}", e)))?;
+        Ok(Box::new(MySqlSession(conn)))
+    }
+}
+
+struct MySqlSession(mysql_async::Conn);
+
+#[async_trait]
+impl ReplaySession for MySqlSession {
+    async fn execute(&mut self, query: &str) -> Result<u64, CubeError> {
+        let rows: Vec<mysql_async::Row> = self
+            .0
+            .query(query)
+            .await
+            .map_err(|e| CubeError::user(e.to_string()))?;
+        Ok(rows.len() as u64)
+    }
+}
+
+#[derive(Debug)]
+pub struct QueryOutcome {
+    pub entry: QueryLogEntry,
+    pub duration: Duration,
+    /// Number of rows or the error.
+    pub result: Result<u64, String>,
+}
+
+impl QueryOutcome {
+    fn is_new_error(&self) -> bool {
+        self.result.is_err() && self.entry.error.is_none()
+    }
+
+    fn is_fixed_error(&self) -> bool {
+        self.result.is_ok() && self.entry.error.is_some()
+    }
+
+    fn is_mismatch(&self) -> bool {
+        match (&self.result, self.entry.rows) {
+            (Ok(rows), Some(recorded)) => *rows != recorded,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplayReport {
+    /// In the order of the log.
+    pub outcomes: Vec<QueryOutcome>,
+    pub skipped_writes: usize,
+    pub total: Duration,
+}
+
+impl ReplayReport {
+    pub fn new_errors(&self) -> Vec<&QueryOutcome> {
+        self.outcomes.iter().filter(|o| o.is_new_error()).collect()
+    }
+
+    pub fn mismatches(&self) -> Vec<&QueryOutcome> {
+        self.outcomes.iter().filter(|o| o.is_mismatch()).collect()
+    }
+
+    /// Whether all queries behaved as recorded, latencies aside.
+    pub fn passed(&self) -> bool {
+        self.new_errors().is_empty() && self.mismatches().is_empty()
+    }
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Replayed {} queries in {:.1} s, {} writes skipped",
+            self.outcomes.len(),
+            self.total.as_secs_f64(),
+            self.skipped_writes
+        )?;
+        let new_errors = self.new_errors();
+        let mismatches = self.mismatches();
+        writeln!(
+            f,
+            "Errors: {} new, {} fixed, {} unchanged",
+            new_errors.len(),
+            self.outcomes.iter().filter(|o| o.is_fixed_error()).count(),
+            self.outcomes
+                .iter()
+                .filter(|o| o.result.is_err() && o.entry.error.is_some())
+                .count()
+        )?;
+        writeln!(f, "Row count mismatches: {}", mismatches.len())?;
+        if !self.outcomes.is_empty() {
+            let mut recorded = self
+                .outcomes
+                .iter()
+                .map(|o| Duration::from_millis(o.entry.duration_ms))
+                .collect::<Vec<_>>();
+            let mut replayed = self.outcomes.iter().map(|o| o.duration).collect::<Vec<_>>();
+            recorded.sort();
+            replayed.sort();
+            let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+            writeln!(
+                f,
+                "{:<10}{:>14}{:>14}",
+                "latency", "recorded ms", "replayed ms"
+            )?;
+            for (name, p) in &[("p50", 50), ("p95", 95), ("p99", 99), ("max", 100)] {
+                writeln!(
+                    f,
+                    "{:<10}{:>14.1}{:>14.1}",
+                    name,
+                    ms(percentile(&recorded, *p)),
+                    ms(percentile(&replayed, *p))
+                )?;
+            }
+        }
+        for o in new_errors.iter().take(MAX_LISTED) {
+            writeln!(
+                f,
+                "New error: {}\n    {}",
+                o.result.as_ref().unwrap_err(),
+                o.entry.query
+            )?;
+        }
+        for o in mismatches.iter().take(MAX_LISTED) {
+            writeln!(
+                f,
+                "Rows {} instead of {}:\n    {}",
+                o.result.as_ref().unwrap(),
+                o.entry.rows.unwrap(),
+                o.entry.query
+            )?;
+        }
+        let unlisted = new_errors.len().saturating_sub(MAX_LISTED)
+            + mismatches.len().saturating_sub(MAX_LISTED);
+        if unlisted != 0 {
+            writeln!(f, "{} more not listed", unlisted)?;
+        }
+        Ok(())
+    }
+}
+
+/// `sorted` must not be empty.
+fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    let i = (sorted.len() * p + 99) / 100;
+    sorted[i.max(1) - 1]
+}
+
+/// Replays `entries` of the query log on `target`.
+pub async fn run(
+    target: Arc<dyn ReplayTarget>,
+    entries: Vec<QueryLogEntry>,
+    options: &ReplayOptions,
+) -> Result<ReplayReport, CubeError> {
+    let (sessions, skipped_writes) = group_sessions(entries, options.include_writes);
+    let first_time = match sessions.iter().map(|s| s[0].time).min() {
+        Some(time) => time,
+        None => {
+            return Ok(ReplayReport {
+                outcomes: Vec::new(),
+                skipped_writes,
+                total: Duration::from_secs(0),
+            })
+        }
+    };
+    let concurrency = match options.pacing {
+        Pacing::Recorded => sessions.len(),
+        Pacing::None => options.concurrency,
+    };
+    let start = Instant::now();
+    let mut outcomes = stream::iter(sessions.into_iter().map(|session| {
+        let target = target.clone();
+        async move {
+            let mut outcomes = Vec::with_capacity(session.len());
+            let mut connection = None;
+            for entry in session {
+                if options.pacing == Pacing::Recorded {
+                    let offset = (entry.time - first_time).to_std().unwrap_or_default();
+                    let at = start + Duration::from_secs_f64(offset.as_secs_f64() / options.speed);
+                    tokio::time::sleep_until(at.into()).await;
+                }
+                let query_start = Instant::now();
+                if connection.is_none() {
+                    connection = Some(target.connect().await.map_err(|e| e.message));
+                }
+                let result = match connection.as_mut().unwrap() {
+                    Ok(connection) => connection
+                        .execute(&entry.query)
+                        .await
+                        .map_err(|e| e.message),
+                    Err(e) => Err(e.clone()),
+                };
+                outcomes.push(QueryOutcome {
+                    entry,
+                    duration: query_start.elapsed(),
+                    result,
+                });
+            }
+            outcomes
+        }
+    }))
+    .buffer_unordered(concurrency)
+    .collect::<Vec<_>>()
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    let total = start.elapsed();
+    outcomes.sort_by_key(|o| o.entry.time);
+    Ok(ReplayReport {
+        outcomes,
+        skipped_writes,
+        total,
+    })
+}
+
+/// Groups entries by session in the order they started. Queries without a session are replayed
+/// as sessions of their own. Also returns the number of skipped writes.
+fn group_sessions(
+    mut entries: Vec<QueryLogEntry>,
+    include_writes: bool,
+) -> (Vec<Vec<QueryLogEntry>>, usize) {
+    entries.sort_by_key(|e| e.time);
+    let mut sessions = Vec::<Vec<QueryLogEntry>>::new();
+    let mut session_indices = HashMap::new();
+    let mut skipped_writes = 0;
+    for entry in entries {
+        if !include_writes && !is_read_only_query(&entry.query) {
+            skipped_writes += 1;
+            continue;
+        }
+        match entry.session {
+            Some(session) => {
+                let i = *session_indices.entry(session).or_insert_with(|| {
+                    sessions.push(Vec::new());
+                    sessions.len() - 1
+                });
+                sessions[i].push(entry);
+            }
+            None => sessions.push(vec![entry]),
+        }
+    }
+    (sessions, skipped_writes)
+}
+
+/// Queries that can't be parsed are replayed, they fail the same way as before.
+fn is_read_only_query(query: &str) -> bool {
+    match CubeStoreParser::new(query).and_then(|mut p| p.parse_statement()) {
+        Ok(statement) => is_read_only(&statement),
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::sql::SqlService;
+    use chrono::{DateTime, Utc};
+
+    struct ServiceTarget(Arc<dyn SqlService>);
+
+    #[async_trait]
+    impl ReplayTarget for ServiceTarget {
+        async fn connect(&self) -> Result<Box<dyn ReplaySession>, CubeError> {
+            Ok(Box::new(ServiceTarget(self.0.clone())))
+        }
+    }
+
+    #[async_trait]
+    impl ReplaySession for ServiceTarget {
+        async fn execute(&mut self, query: &str) -> Result<u64, CubeError> {
+            Ok(self.0.exec_query(query).await?.get_rows().len() as u64)
+        }
+    }
+
+    fn entry(
+        start: DateTime<Utc>,
+        offset_ms: i64,
+        session: Option<u64>,
+        query: &str,
+        rows: Option<u64>,
+    ) -> QueryLogEntry {
+        QueryLogEntry {
+            time: start + chrono::Duration::milliseconds(offset_ms),
+            session,
+            user: None,
+            query: query.to_string(),
+            duration_ms: 10,
+            rows,
+            error: if rows.is_none() {
+                Some("Error".to_string())
+            } else {
+                None
+            },
+            query_id: None,
+        }
+    }
+
+    #[test]
+    fn options() {
+        let args = |s: &str| s.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
+        let options =
+            ReplayOptions::parse(&args("queries.log --pacing none --concurrency 4")).unwrap();
+        assert_eq!(options.log, PathBuf::from("queries.log"));
+        assert_eq!(options.pacing, Pacing::None);
+        assert_eq!(options.concurrency, 4);
+        assert!(!options.include_writes);
+        let options = ReplayOptions::parse(&args(
+            "--speed 2.5 --include-writes queries.log --target mysql://test:3306",
+        ))
+        .unwrap();
+        assert_eq!(options.speed, 2.5);
+        assert!(options.include_writes);
+        assert_eq!(options.target, "mysql://test:3306");
+
+        assert!(ReplayOptions::parse(&args("--pacing none")).is_err());
+        assert!(ReplayOptions::parse(&args("queries.log --pacing fast")).is_err());
+        assert!(ReplayOptions::parse(&args("queries.log --speed 0")).is_err());
+        assert!(ReplayOptions::parse(&args("queries.log --concurrency")).is_err());
+    }
+
+    #[test]
+    fn schedule() {
+        let start = Utc::now();
+        let entries = vec![
+            entry(start, 30, Some(1), "SELECT 2", Some(1)),
+            entry(start, 0, Some(1), "SET time_zone = 'UTC'", Some(0)),
+            entry(start, 10, None, "SELECT 3", Some(1)),
+            entry(
+                start,
+                20,
+                Some(2),
+                "INSERT INTO s.t (a) VALUES (1)",
+                Some(0),
+            ),
+            entry(start, 25, None, "SELECT 4", Some(1)),
+        ];
+        let queries = |sessions: Vec<Vec<QueryLogEntry>>| {
+            sessions
+                .into_iter()
+                .map(|s| s.into_iter().map(|e| e.query).collect::<Vec<_>>())
+                .collect::<Vec<_>>()
+        };
+        let (read_only, skipped) = group_sessions(entries.clone(), false);
+        assert_eq!(skipped, 1);
+        assert_eq!(
+            queries(read_only),
+            vec![
+                vec!["SET time_zone = 'UTC'", "SELECT 2"],
+                vec!["SELECT 3"],
+                vec!["SELECT 4"]
+            ]
+        );
+        let (all, skipped) = group_sessions(entries, true);
+        assert_eq!(skipped, 0);
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[2][0].query, "INSERT INTO s.t (a) VALUES (1)");
+    }
+
+    #[tokio::test]
+    async fn replay() {
+        Config::test("replay")
+            .start_test(async move |services| {
+                let service = services.sql_service;
+                service.exec_query("CREATE SCHEMA s").await.unwrap();
+                service
+                    .exec_query("CREATE TABLE s.t (a int)")
+                    .await
+                    .unwrap();
+                service
+                    .exec_query("INSERT INTO s.t (a) VALUES (1), (2), (3)")
+                    .await
+                    .unwrap();
+
+                let start = Utc::now();
+                let entries = vec![
+                    entry(start, 0, Some(1), "SELECT * FROM s.t", Some(3)),
+                    entry(start, 20, Some(1), "SELECT * FROM s.t WHERE a > 1", Some(1)),
+                    entry(start, 10, Some(2), "SELECT * FROM s.missing", Some(1)),
+                    entry(start, 30, None, "SELECT a FROM s.t LIMIT 1", None),
+                    entry(start, 40, None, "DROP TABLE s.t", Some(0)),
+                ];
+                let options = ReplayOptions::parse(&["queries.log".to_string()]).unwrap();
+                let target = Arc::new(ServiceTarget(service.clone()));
+                let report = run(target, entries, &options).await.unwrap();
+                assert!(report.total >= Duration::from_millis(30));
+                assert_eq!(report.skipped_writes, 1);
+                assert_eq!(
+                    report
+                        .outcomes
+                        .iter()
+                        .map(|o| o.entry.query.as_str())
+                        .collect::<Vec<_>>(),
+                    vec![
+                        "SELECT * FROM s.t",
+                        "SELECT * FROM s.missing",
+                        "SELECT * FROM s.t WHERE a > 1",
+                        "SELECT a FROM s.t LIMIT 1"
+                    ]
+                );
+                assert!(!report.passed());
+                let new_errors = report.new_errors();
+                assert_eq!(new_errors.len(), 1);
+                assert_eq!(new_errors[0].entry.query, "SELECT * FROM s.missing");
+                let mismatches = report.mismatches();
+                assert_eq!(mismatches.len(), 1);
+                assert_eq!(mismatches[0].result, Ok(2));
+                let printed = report.to_string();
+                assert!(printed.contains("Errors: 1 new, 1 fixed, 0 unchanged"));
+                assert!(printed.contains("Rows 2 instead of 1:"));
+
+                // The table is still there, writes were skipped.
+                assert_eq!(
+                    service
+                        .exec_query("SELECT * FROM s.t")
+                        .await
+                        .unwrap()
+                        .get_rows()
+                        .len(),
+                    3
+                );
+            })
+            .await;
+    }
+}
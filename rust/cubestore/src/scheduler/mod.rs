@@ -1,11 +1,13 @@
 use crate::cluster::Cluster;
 use crate::config::ConfigObj;
 use crate::metastore::job::{Job, JobType};
-use crate::metastore::{MetaStore, MetaStoreEvent, RowKey, TableId};
+use crate::metastore::{IdRow, MetaStore, MetaStoreEvent, Partition, RowKey, TableId};
 use crate::remotefs::RemoteFs;
+use crate::store::leases::DataFileLeases;
 use crate::store::{ChunkStore, WALStore};
 use crate::CubeError;
 use flatbuffers::bitflags::_core::time::Duration;
+use futures::FutureExt;
 use log::error;
 use std::sync::Arc;
 use tokio::sync::broadcast::Receiver;
@@ -24,6 +26,7 @@ pub struct SchedulerImpl {
     gc_loop: Mutex<DataGCLoop>,
     gc_sender: UnboundedSender<GCTimedTask>,
     config: Arc<dyn ConfigObj>,
+    file_leases: Arc<DataFileLeases>,
 }
 
 crate::di_service!(SchedulerImpl, []);
@@ -35,10 +38,15 @@ impl SchedulerImpl {
         remote_fs: Arc<dyn RemoteFs>,
         event_receiver: Receiver<MetaStoreEvent>,
         config: Arc<dyn ConfigObj>,
+        file_leases: Arc<DataFileLeases>,
     ) -> SchedulerImpl {
         let (tx, rx) = watch::channel(false);
-        let (gc_loop, gc_sender) =
-            DataGCLoop::new(meta_store.clone(), remote_fs.clone(), rx.clone());
+        let (gc_loop, gc_sender) = DataGCLoop::new(
+            meta_store.clone(),
+            remote_fs.clone(),
+            file_leases.clone(),
+            rx.clone(),
+        );
         SchedulerImpl {
             meta_store,
             cluster,
@@ -49,6 +57,7 @@ impl SchedulerImpl {
             gc_loop: Mutex::new(gc_loop),
             gc_sender,
             config,
+            file_leases,
         }
     }
 
@@ -114,7 +123,7 @@ impl SchedulerImpl {
             let p = self.meta_store.get_partition(row_id).await?;
             if p.get_row().is_active() && !p.get_row().is_warmed_up() {
                 if let Some(path) = p.get_row().get_full_name(p.get_id()) {
-                    self.schedule_partition_warmup(p.get_id(), path).await?;
+                    self.schedule_partition_warmup(&p, path).await?;
                     self.meta_store.mark_partition_warmed_up(row_id).await?;
                 }
             }
@@ -171,16 +180,16 @@ impl SchedulerImpl {
                 .await?;
             tokio::fs::remove_file(file).await?;
         }
-        if let MetaStoreEvent::Delete(TableId::Chunks, row_id) = event {
-            self.remote_fs
-                .delete_file(ChunkStore::chunk_remote_path(row_id).as_str())
+        if let MetaStoreEvent::DeleteChunk(chunk) = &event {
+            self.delete_when_not_leased(ChunkStore::chunk_file_name(chunk.clone()))
                 .await?
         }
         if let MetaStoreEvent::DeletePartition(partition) = &event {
             // remove file only if partition is active otherwise it should be removed when it's deactivated
-            if partition.get_row().is_active() {
+            // Attached files belong to another cluster.
+            if partition.get_row().is_active() && partition.get_row().attached_file().is_none() {
                 if let Some(file_name) = partition.get_row().get_full_name(partition.get_id()) {
-                    self.remote_fs.delete_file(file_name.as_str()).await?;
+                    self.delete_when_not_leased(file_name).await?;
                 }
             }
         }
@@ -222,8 +231,22 @@ impl SchedulerImpl {
         Ok(())
     }
 
+    /// Files of dropped partitions and chunks may still be read by queries, see
+    /// [crate::store::leases]. Deletion of leased files is left to the GC loop.
+    async fn delete_when_not_leased(&self, file_name: String) -> Result<(), CubeError> {
+        if self.file_leases.is_leased(&file_name) {
+            self.gc_sender.send(GCTimedTask(
+                Instant::now(),
+                GCTask::RemoveRemoteFile(file_name),
+            ))?;
+            Ok(())
+        } else {
+            self.remote_fs.delete_file(&file_name).await
+        }
+    }
+
     async fn schedule_repartition(&self, partition_id: u64) -> Result<(), CubeError> {
-        let node = self.cluster.node_name_by_partitions(&[partition_id]);
+        let node = self.cluster.node_name_for_job(partition_id);
         let job = self
             .meta_store
             .add_job(Job::new(
@@ -280,7 +303,7 @@ impl SchedulerImpl {
     }
 
     async fn schedule_partition_to_compact(&self, partition_id: u64) -> Result<(), CubeError> {
-        let node = self.cluster.node_name_by_partitions(&[partition_id]);
+        let node = self.cluster.node_name_for_job(partition_id);
         let job = self
             .meta_store
             .add_job(Job::new(
@@ -298,11 +321,15 @@ impl SchedulerImpl {
 
     async fn schedule_partition_warmup(
         &self,
-        partition_id: u64,
+        partition: &IdRow<Partition>,
         path: String,
     ) -> Result<(), CubeError> {
-        let node_name = self.cluster.node_name_by_partitions(&[partition_id]);
-        self.cluster.warmup_download(&node_name, path).await
+        for node_name in self.cluster.warmup_node_names(partition).await? {
+            self.cluster
+                .warmup_download(&node_name, path.clone())
+                .await?;
+        }
+        Ok(())
     }
 }
 
@@ -314,19 +341,29 @@ enum GCTask {
     DeleteChunk(/*chunk_id*/ u64),
 }
 
+/// How often files are checked for leases to be released, see [crate::store::leases].
+const LEASED_FILE_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
 /// Cleans up deactivated partitions and chunks on remote fs.
-/// Ensures enough time has passed that queries over those files finish.
+/// Ensures enough time has passed that queries over those files finish, files that are still
+/// leased by queries are kept until the leases are released.
 struct DataGCLoop {
     metastore: Arc<dyn MetaStore>,
     remote_fs: Arc<dyn RemoteFs>,
+    file_leases: Arc<DataFileLeases>,
     stop: watch::Receiver<bool>,
     to_delete: UnboundedReceiver<GCTimedTask>,
+    /// Puts back removals of leased files.
+    retry: UnboundedSender<GCTimedTask>,
+    /// Task received while draining expired tasks that is not due yet.
+    pending: Option<GCTimedTask>,
 }
 
 impl DataGCLoop {
     fn new(
         metastore: Arc<dyn MetaStore>,
         remote_fs: Arc<dyn RemoteFs>,
+        file_leases: Arc<DataFileLeases>,
         stop: watch::Receiver<bool>,
     ) -> (DataGCLoop, UnboundedSender<GCTimedTask>) {
         let (sender, receiver) = unbounded_channel();
@@ -334,8 +371,11 @@ impl DataGCLoop {
             DataGCLoop {
                 metastore,
                 remote_fs,
+                file_leases,
                 stop,
                 to_delete: receiver,
+                retry: sender.clone(),
+                pending: None,
             },
             sender,
         )
@@ -343,18 +383,22 @@ impl DataGCLoop {
 
     async fn run(&mut self) {
         loop {
-            let GCTimedTask(deadline, task) = tokio::select! {
-                res = self.stop.changed() => {
-                    if res.is_err() || *self.stop.borrow() {
-                        return;
-                    } else {
-                        continue;
+            let GCTimedTask(deadline, task) = if let Some(t) = self.pending.take() {
+                t
+            } else {
+                tokio::select! {
+                    res = self.stop.changed() => {
+                        if res.is_err() || *self.stop.borrow() {
+                            return;
+                        } else {
+                            continue;
+                        }
                     }
-                }
-                event = self.to_delete.recv() => {
-                    match event {
-                        None => return, // channel closed.
-                        Some(e) => e,
+                    event = self.to_delete.recv() => {
+                        match event {
+                            None => return, // channel closed.
+                            Some(e) => e,
+                        }
                     }
                 }
             };
@@ -373,24 +417,70 @@ impl DataGCLoop {
                 }
             }
 
-            match task {
-                GCTask::RemoveRemoteFile(remote_path) => {
-                    log::trace!("Removing deactivated data file: {}", remote_path);
-                    if let Err(e) = self.remote_fs.delete_file(&remote_path).await {
-                        log::error!(
-                            "Could not remove deactivated data file({}): {}",
-                            remote_path,
-                            e
-                        );
-                    }
+            // Pick up all other expired tasks so chunks are deleted in a single metastore write.
+            let mut tasks = vec![task];
+            let now = Instant::now();
+            while let Some(Some(t)) = self.to_delete.recv().now_or_never() {
+                if t.0 <= now {
+                    tasks.push(t.1);
+                } else {
+                    self.pending = Some(t);
+                    break;
                 }
-                GCTask::DeleteChunk(chunk_id) => {
-                    log::trace!("Removing deactivated chunk {}", chunk_id);
-                    if let Err(e) = self.metastore.delete_chunk(chunk_id).await {
-                        log::error!("Could not remove deactivated chunk ({}): {}", chunk_id, e);
+            }
+
+            let mut chunks_to_delete = Vec::new();
+            for task in tasks {
+                match task {
+                    GCTask::RemoveRemoteFile(remote_path)
+                        if self.file_leases.is_leased(&remote_path) =>
+                    {
+                        log::trace!("Postponing removal of leased data file: {}", remote_path);
+                        let _ = self.retry.send(GCTimedTask(
+                            Instant::now() + LEASED_FILE_RECHECK_INTERVAL,
+                            GCTask::RemoveRemoteFile(remote_path),
+                        ));
+                    }
+                    GCTask::RemoveRemoteFile(remote_path) => {
+                        log::trace!("Removing deactivated data file: {}", remote_path);
+                        if let Err(e) = self.remote_fs.delete_file(&remote_path).await {
+                            log::error!(
+                                "Could not remove deactivated data file({}): {}",
+                                remote_path,
+                                e
+                            );
+                        }
                     }
+                    GCTask::DeleteChunk(chunk_id) => chunks_to_delete.push(chunk_id),
                 }
             }
+            self.delete_chunks(chunks_to_delete).await;
+        }
+    }
+
+    async fn delete_chunks(&self, chunk_ids: Vec<u64>) {
+        match chunk_ids.len() {
+            0 => return,
+            1 => {}
+            _ => {
+                log::trace!("Removing deactivated chunks {:?}", chunk_ids);
+                if self
+                    .metastore
+                    .delete_chunks(chunk_ids.clone())
+                    .await
+                    .is_ok()
+                {
+                    return;
+                }
+                // Fall back to deleting chunks one by one so a single bad chunk doesn't block
+                // the rest of the batch.
+            }
+        }
+        for chunk_id in chunk_ids {
+            log::trace!("Removing deactivated chunk {}", chunk_id);
+            if let Err(e) = self.metastore.delete_chunk(chunk_id).await {
+                log::error!("Could not remove deactivated chunk ({}): {}", chunk_id, e);
+            }
         }
     }
 }
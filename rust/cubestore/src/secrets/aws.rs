@@ -0,0 +1,174 @@
+//! Reads secrets from AWS Secrets Manager, e.g. `aws-sm://us-east-1/prod/pg` reads the secret
+//! `prod/pg` in `us-east-1`. The secret string must be a JSON object, its fields are properties
+//! of the secret. Requests are signed with the same credentials as S3 storage, i.e.
+//! `CUBESTORE_AWS_ACCESS_KEY_ID` and `CUBESTORE_AWS_SECRET_ACCESS_KEY` or the default AWS
+//! credentials of the environment.
+use crate::secrets::{json_properties, SecretProperties};
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac, NewMac};
+use s3::creds::Credentials;
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::env;
+
+const SERVICE: &str = "secretsmanager";
+
+pub async fn read_secret(
+    client: &reqwest::Client,
+    region: &str,
+    secret_id: &str,
+) -> Result<SecretProperties, CubeError> {
+    let credentials = Credentials::new(
+        env::var("CUBESTORE_AWS_ACCESS_KEY_ID").as_deref().ok(),
+        env::var("CUBESTORE_AWS_SECRET_ACCESS_KEY").as_deref().ok(),
+        None,
+        None,
+        None,
+    )?;
+    let (access_key, secret_key) = match (&credentials.access_key, &credentials.secret_key) {
+        (Some(access_key), Some(secret_key)) => (access_key, secret_key),
+        _ => {
+            return Err(CubeError::user(
+                "AWS credentials are required to read secrets from AWS Secrets Manager".to_string(),
+            ))
+        }
+    };
+
+    let host = format!("{}.{}.amazonaws.com", SERVICE, region);
+    let body = json!({ "SecretId": secret_id }).to_string();
+    let now = Utc::now();
+    // Sorted by name as required for signing.
+    let mut headers = vec![
+        ("content-type", "application/x-amz-json-1.1".to_string()),
+        ("host", host.clone()),
+        ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+    ];
+    if let Some(token) = credentials
+        .security_token
+        .as_ref()
+        .or(credentials.session_token.as_ref())
+    {
+        headers.push(("x-amz-security-token", token.to_string()));
+    }
+    headers.push(("x-amz-target", "secretsmanager.GetSecretValue".to_string()));
+    let authorization = authorization(access_key, secret_key, &now, region, &headers, &body);
+
+    let mut request = client.post(&format!("https://{}/", host)).body(body);
+    for (name, value) in headers.into_iter().filter(|(n, _)| *n != "host") {
+        request = request.header(name, value);
+    }
+    let response = request
+        .header("authorization", authorization)
+        .send()
+        .await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(CubeError::user(format!(
+            "Can't read secret {} from AWS Secrets Manager: {} {}",
+            secret_id,
+            status,
+            response.text().await?
+        )));
+    }
+    let response = response.json::<JsonValue>().await?;
+    response["SecretString"]
+        .as_str()
+        .and_then(|s| serde_json::from_str::<JsonValue>(s).ok())
+        .and_then(|s| json_properties(&s))
+        .ok_or_else(|| {
+            CubeError::user(format!(
+                "Secret string of {} in AWS Secrets Manager must be a JSON object",
+                secret_id
+            ))
+        })
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac(format!("AWS4{}", secret_key).as_bytes(), date);
+    let key = hmac(&key, region);
+    let key = hmac(&key, service);
+    hmac(&key, "aws4_request")
+}
+
+/// Signature Version 4 of a POST request to the root path, `headers` must be sorted by name.
+fn authorization(
+    access_key: &str,
+    secret_key: &str,
+    now: &DateTime<Utc>,
+    region: &str,
+    headers: &[(&str, String)],
+    body: &str,
+) -> String {
+    let date = now.format("%Y%m%d").to_string();
+    let signed_headers = headers
+        .iter()
+        .map(|(n, _)| *n)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "POST\n/\n\n{}\n{}\n{}",
+        headers
+            .iter()
+            .map(|(n, v)| format!("{}:{}\n", n, v.trim()))
+            .collect::<String>(),
+        signed_headers,
+        hex::encode(Sha256::digest(body.as_bytes()))
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, SERVICE);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        now.format("%Y%m%dT%H%M%SZ"),
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let signature = hex::encode(hmac(
+        &signing_key(secret_key, &date, region, SERVICE),
+        &string_to_sign,
+    ));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, scope, signed_headers, signature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn signing() {
+        // Example of the AWS documentation.
+        assert_eq!(
+            hex::encode(signing_key(
+                "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                "20120215",
+                "us-east-1",
+                "iam"
+            )),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+
+        let now = Utc.ymd(2021, 6, 1).and_hms(12, 0, 0);
+        let headers = vec![
+            ("content-type", "application/x-amz-json-1.1".to_string()),
+            ("host", "secretsmanager.us-east-1.amazonaws.com".to_string()),
+            ("x-amz-date", "20210601T120000Z".to_string()),
+        ];
+        let authorization = authorization("AKID", "secret", &now, "us-east-1", &headers, "{}");
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKID/20210601/us-east-1/secretsmanager/aws4_request, SignedHeaders=content-type;host;x-amz-date, Signature="
+        ), "{}", authorization);
+        assert_ne!(
+            authorization,
+            super::authorization("AKID", "secret", &now, "us-east-1", &headers, "{ }")
+        );
+    }
+}
@@ -0,0 +1,323 @@
+//! Named credentials of table sources, so they are not stored as part of table locations, e.g.:
+//!     CREATE SECRET pg WITH (user = 'cube', password = 'secret')
+//!     CREATE TABLE s.users (id int, name text) WITH (secret = 'pg')
+//!     LOCATION 'postgres://db:5432/app/users'
+//! Secrets of database locations set the user and password of the connection. Secrets of HTTP
+//! locations set basic authentication with `user` and `password`, or the bearer `token`.
+//! Properties are stored in the metastore encrypted with the key in `CUBESTORE_SECRETS_KEY`.
+//! Secrets can also be kept in an external secret store, they are read on each import:
+//!     CREATE SECRET pg FROM 'vault://secret/data/pg'
+//!     CREATE SECRET pg FROM 'aws-sm://us-east-1/prod/pg'
+//! See [vault] and [aws] for details. Secrets are replaced with `CREATE OR REPLACE SECRET`,
+//! removed with `DROP SECRET` and listed, without their values, with `SHOW SECRETS`.
+pub mod aws;
+pub mod vault;
+
+use crate::config::ConfigObj;
+use crate::metastore::secret::{Secret, SecretValue};
+use crate::metastore::{IdRow, MetaStore};
+use crate::CubeError;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::Rng;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use url::Url;
+
+pub type SecretProperties = BTreeMap<String, String>;
+
+/// Properties secrets stored in the metastore can have.
+const PROPERTIES: [&str; 3] = ["user", "password", "token"];
+
+pub struct SecretStore {
+    meta_store: Arc<dyn MetaStore>,
+    config: Arc<dyn ConfigObj>,
+    client: reqwest::Client,
+}
+
+crate::di_service!(SecretStore, []);
+
+enum ExternalSecret<'a> {
+    Vault(&'a str),
+    AwsSecretsManager { region: &'a str, secret_id: &'a str },
+}
+
+fn external_secret(location: &str) -> Result<ExternalSecret, CubeError> {
+    if let Some(path) = location.strip_prefix("vault://") {
+        if !path.is_empty() {
+            return Ok(ExternalSecret::Vault(path));
+        }
+    } else if let Some(path) = location.strip_prefix("aws-sm://") {
+        let mut parts = path.splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(region), Some(secret_id)) if !region.is_empty() && !secret_id.is_empty() => {
+                return Ok(ExternalSecret::AwsSecretsManager { region, secret_id })
+            }
+            _ => {}
+        }
+    }
+    Err(CubeError::user(format!(
+        "Secret location must be 'vault://<path>' or 'aws-sm://<region>/<secret id>', found: {}",
+        location
+    )))
+}
+
+impl SecretStore {
+    pub fn new(meta_store: Arc<dyn MetaStore>, config: Arc<dyn ConfigObj>) -> Arc<Self> {
+        Arc::new(Self {
+            meta_store,
+            config,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Stores `properties` in the metastore, or the `location` of the secret in an external
+    /// secret store.
+    pub async fn create_secret(
+        &self,
+        name: String,
+        properties: SecretProperties,
+        location: Option<String>,
+        or_replace: bool,
+    ) -> Result<IdRow<Secret>, CubeError> {
+        let value = match location {
+            Some(location) => {
+                external_secret(&location)?;
+                SecretValue::External(location)
+            }
+            None => {
+                if let Some(p) = properties
+                    .keys()
+                    .find(|p| !PROPERTIES.contains(&p.as_str()))
+                {
+                    return Err(CubeError::user(format!(
+                        "Unsupported secret property: {}, expected one of: {}",
+                        p,
+                        PROPERTIES.join(", ")
+                    )));
+                }
+                self.encrypt(&properties)?
+            }
+        };
+        self.meta_store.create_secret(name, value, or_replace).await
+    }
+
+    pub async fn resolve(&self, name: &str) -> Result<SecretProperties, CubeError> {
+        let secret = self.meta_store.get_secret(name.to_string()).await?;
+        match secret.get_row().value() {
+            SecretValue::Encrypted { nonce, data } => self.decrypt(name, nonce, data),
+            SecretValue::External(location) => match external_secret(location)? {
+                ExternalSecret::Vault(path) => {
+                    vault::read_secret(&self.client, self.config.as_ref(), path).await
+                }
+                ExternalSecret::AwsSecretsManager { region, secret_id } => {
+                    aws::read_secret(&self.client, region, secret_id).await
+                }
+            },
+        }
+    }
+
+    fn cipher(&self) -> Result<Aes256Gcm, CubeError> {
+        let key = self.config.secrets_key().as_ref().ok_or_else(|| {
+            CubeError::user(
+                "CUBESTORE_SECRETS_KEY must be set to store secrets in the metastore".to_string(),
+            )
+        })?;
+        let key = base64::decode(key).map_err(|e| {
+            CubeError::user(format!(
+                "CUBESTORE_SECRETS_KEY must be base64 encoded: {}",
+                e
+            ))
+        })?;
+        if key.len() != 32 {
+            return Err(CubeError::user(format!(
+                "CUBESTORE_SECRETS_KEY must be a 256-bit key, found {} bits",
+                key.len() * 8
+            )));
+        }
+        Ok(Aes256Gcm::new(Key::from_slice(&key)))
+    }
+
+    fn encrypt(&self, properties: &SecretProperties) -> Result<SecretValue, CubeError> {
+        let nonce = rand::thread_rng().gen::<[u8; 12]>();
+        let data = self
+            .cipher()?
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                serde_json::to_vec(properties)?.as_ref(),
+            )
+            .map_err(|_| CubeError::internal("Can't encrypt secret".to_string()))?;
+        Ok(SecretValue::Encrypted {
+            nonce: nonce.to_vec(),
+            data,
+        })
+    }
+
+    fn decrypt(
+        &self,
+        name: &str,
+        nonce: &[u8],
+        data: &[u8],
+    ) -> Result<SecretProperties, CubeError> {
+        let properties = self
+            .cipher()?
+            .decrypt(Nonce::from_slice(nonce), data)
+            .map_err(|_| {
+                CubeError::user(format!(
+                    "Can't decrypt secret {}, CUBESTORE_SECRETS_KEY differs from the key it was created with",
+                    name
+                ))
+            })?;
+        Ok(serde_json::from_slice(&properties)?)
+    }
+}
+
+/// Properties of a secret read from an external secret store. Values of other types than
+/// strings are kept in the JSON format.
+fn json_properties(value: &JsonValue) -> Option<SecretProperties> {
+    Some(
+        value
+            .as_object()?
+            .iter()
+            .map(|(k, v)| match v {
+                JsonValue::String(s) => (k.to_string(), s.to_string()),
+                v => (k.to_string(), v.to_string()),
+            })
+            .collect(),
+    )
+}
+
+/// Whether credentials of the location can be set by a secret.
+pub fn supports_secret(location: &str) -> bool {
+    location.starts_with("http")
+        || location.starts_with("mysql://")
+        || location.starts_with("postgres://")
+        || location.starts_with("postgresql://")
+}
+
+/// Sets the user and password of the database location, see [crate::import::database].
+pub fn database_location_with_secret(
+    location: &str,
+    secret: &SecretProperties,
+) -> Result<String, CubeError> {
+    let invalid = || CubeError::user(format!("Can't set credentials of location {}", location));
+    let mut url = Url::parse(location).map_err(|_| invalid())?;
+    if let Some(user) = secret.get("user") {
+        url.set_username(user).map_err(|_| invalid())?;
+    }
+    if let Some(password) = secret.get("password") {
+        url.set_password(Some(password)).map_err(|_| invalid())?;
+    }
+    Ok(url.to_string())
+}
+
+pub fn authorize_request(
+    request: reqwest::RequestBuilder,
+    secret: &SecretProperties,
+) -> reqwest::RequestBuilder {
+    match (secret.get("token"), secret.get("user")) {
+        (Some(token), _) => request.bearer_auth(token),
+        (None, Some(user)) => request.basic_auth(user, secret.get("password")),
+        (None, None) => request,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn properties(p: &[(&str, &str)]) -> SecretProperties {
+        p.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn encryption() {
+        let key = base64::encode([7u8; 32]);
+        Config::test("secrets_encryption")
+            .update_config(|mut c| {
+                c.secrets_key = Some(key);
+                c
+            })
+            .start_test(async move |services| {
+                let store = services.injector.get_service_typed::<SecretStore>().await;
+                let secret = properties(&[("user", "cube"), ("password", "p@ss")]);
+                let value = store.encrypt(&secret).unwrap();
+                match &value {
+                    SecretValue::Encrypted { nonce, data } => {
+                        assert_eq!(store.decrypt("s", nonce, data).unwrap(), secret);
+                        let mut tampered = data.clone();
+                        tampered[0] ^= 1;
+                        assert!(store.decrypt("s", nonce, &tampered).is_err());
+                    }
+                    v => panic!("unexpected value: {:?}", v),
+                }
+                assert_ne!(store.encrypt(&secret).unwrap(), value);
+
+                assert!(store
+                    .create_secret("s".to_string(), properties(&[("pwd", "x")]), None, false)
+                    .await
+                    .is_err());
+                assert!(store
+                    .create_secret(
+                        "s".to_string(),
+                        SecretProperties::new(),
+                        Some("vault://".to_string()),
+                        false
+                    )
+                    .await
+                    .is_err());
+            })
+            .await;
+    }
+
+    #[test]
+    fn locations() {
+        let secret = properties(&[("user", "cube"), ("password", "p@ss/word")]);
+        assert_eq!(
+            database_location_with_secret("postgres://db:5432/app/users?since_column=ts", &secret)
+                .unwrap(),
+            "postgres://cube:p%40ss%2Fword@db:5432/app/users?since_column=ts"
+        );
+        assert_eq!(
+            database_location_with_secret("mysql://other@db/app/users", &properties(&[])).unwrap(),
+            "mysql://other@db/app/users"
+        );
+
+        let client = reqwest::Client::new();
+        let header = |secret: &SecretProperties| {
+            authorize_request(client.get("https://example.com/data.csv"), secret)
+                .build()
+                .unwrap()
+                .headers()
+                .get("authorization")
+                .map(|h| h.to_str().unwrap().to_string())
+        };
+        assert_eq!(
+            header(&properties(&[("user", "u"), ("password", "p")])),
+            Some(format!("Basic {}", base64::encode("u:p")))
+        );
+        assert_eq!(
+            header(&properties(&[("token", "t")])),
+            Some("Bearer t".to_string())
+        );
+        assert_eq!(header(&properties(&[])), None);
+
+        assert!(matches!(
+            external_secret("aws-sm://us-east-1/prod/pg").unwrap(),
+            ExternalSecret::AwsSecretsManager {
+                region: "us-east-1",
+                secret_id: "prod/pg"
+            }
+        ));
+        assert!(matches!(
+            external_secret("vault://secret/data/pg").unwrap(),
+            ExternalSecret::Vault("secret/data/pg")
+        ));
+        assert!(external_secret("aws-sm://us-east-1").is_err());
+        assert!(external_secret("s3://bucket/secret").is_err());
+    }
+}
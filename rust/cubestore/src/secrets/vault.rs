@@ -0,0 +1,71 @@
+//! Reads secrets from the KV secrets engine of HashiCorp Vault, e.g. `vault://secret/data/pg`
+//! reads `<CUBESTORE_VAULT_ADDR>/v1/secret/data/pg` with the token in `CUBESTORE_VAULT_TOKEN`.
+//! Both versions of the engine are supported, keys of the secret are its properties.
+use crate::config::ConfigObj;
+use crate::secrets::{json_properties, SecretProperties};
+use crate::CubeError;
+use serde_json::Value as JsonValue;
+
+pub async fn read_secret(
+    client: &reqwest::Client,
+    config: &dyn ConfigObj,
+    path: &str,
+) -> Result<SecretProperties, CubeError> {
+    let address = config.vault_address().as_ref().ok_or_else(|| {
+        CubeError::user("CUBESTORE_VAULT_ADDR must be set to read secrets from Vault".to_string())
+    })?;
+    let token = config.vault_token().as_ref().ok_or_else(|| {
+        CubeError::user("CUBESTORE_VAULT_TOKEN must be set to read secrets from Vault".to_string())
+    })?;
+    let response = client
+        .get(&format!(
+            "{}/v1/{}",
+            address.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        ))
+        .header("X-Vault-Token", token)
+        .send()
+        .await?
+        .error_for_status()
+        .map_err(|e| CubeError::user(format!("Can't read secret {} from Vault: {}", path, e)))?
+        .json::<JsonValue>()
+        .await?;
+    vault_properties(path, &response)
+}
+
+fn vault_properties(path: &str, response: &JsonValue) -> Result<SecretProperties, CubeError> {
+    let data = &response["data"];
+    // Version 2 of the engine returns the secret next to its metadata.
+    let data = match (data.get("data"), data.get("metadata")) {
+        (Some(secret), Some(_)) => secret,
+        _ => data,
+    };
+    json_properties(data).ok_or_else(|| {
+        CubeError::user(format!(
+            "Secret {} in Vault must be a key-value object",
+            path
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn responses() {
+        let expected = vec![
+            ("password".to_string(), "p".to_string()),
+            ("port".to_string(), "5432".to_string()),
+        ]
+        .into_iter()
+        .collect::<SecretProperties>();
+        let v1 = json!({"lease_duration": 3600, "data": {"password": "p", "port": 5432}});
+        assert_eq!(vault_properties("secret/pg", &v1).unwrap(), expected);
+        let v2 =
+            json!({"data": {"data": {"password": "p", "port": 5432}, "metadata": {"version": 2}}});
+        assert_eq!(vault_properties("secret/data/pg", &v2).unwrap(), expected);
+        assert!(vault_properties("secret/pg", &json!({"errors": []})).is_err());
+    }
+}
@@ -0,0 +1,522 @@
+//! `ATTACH TABLE <name> FROM '<location>' PARTITIONED BY (<columns>)` makes parquet files of a
+//! directory in the hive layout, e.g. written by Spark, queryable without ingesting them. Files
+//! are found under `<location>/<column>=<value>/.../<file>.parquet` with one directory level per
+//! PARTITIONED BY column, files and directories starting with `_` or `.` are skipped. Directory
+//! names give values of the PARTITIONED BY columns, which are strings. Other columns come from the
+//! schema of the files, which must be the same in all files and only use types CubeStore writes
+//! itself: INT64, DOUBLE, BOOLEAN, UTF8 byte arrays and TIMESTAMP_MICROS.
+//!
+//! Each file becomes an attached partition of the default index, as in [crate::sql::manifest].
+//! Rows of the files are not sorted, so the sort key of the index is the PARTITIONED BY columns,
+//! whose values are the same for all rows of a file, and filters on them prune files. Workers add
+//! these columns to the rows they read, see [crate::queryplanner::query_executor::CubeTable].
+//! Files are downloaded once on attach to read their schemas and row counts.
+//!
+//! `ATTACH TABLE <name> FROM '<location>' FORMAT ICEBERG` attaches the current snapshot of an
+//! Iceberg table, see [crate::remotefs::iceberg]. Data files of Iceberg tables hold all columns.
+//! Columns with identity partition transforms are the sort key, other tables are keyed by their
+//! first column and rows of their files are sorted by workers when read. Deleted rows are skipped
+//! by workers, see [crate::queryplanner::deleted_rows].
+//!
+//! `ATTACH TABLE <name> FROM '<location>' FORMAT DELTA` attaches the current snapshot of a Delta
+//! Lake table, see [crate::remotefs::delta]. Partition columns of Delta Lake tables are the sort
+//! key and are not stored in data files, so they are added by workers like the PARTITIONED BY
+//! columns. Deletion vectors are skipped as deleted rows of Iceberg tables.
+//!
+//! Attached tables are read-only. Delta Lake tables are refreshed by [AttachedTableRefresher] to
+//! pick up new commits, other tables only see files that existed at the time of ATTACH, drop and
+//! attach them again to pick up new files. Files are never modified or removed.
+use crate::config::ConfigObj;
+use crate::metastore::table::Table;
+use crate::metastore::{AttachedFile, Column, ColumnType, IdRow, MetaStore};
+use crate::remotefs::delta::{delta_table_version, read_delta_table, DeltaSnapshot};
+use crate::remotefs::iceberg::read_iceberg_table;
+use crate::remotefs::storage::validate_storage;
+use crate::remotefs::{unescape_path_name, RemoteFs};
+use crate::table::{Row, TableValue, TimestampValue};
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use futures_timer::Delay;
+use log::warn;
+use parquet::basic::{ConvertedType, Type};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use std::fs::File;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Value of the directory name for nulls used by Hive and Spark.
+pub const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+pub async fn attach_table(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    schema_name: String,
+    table_name: String,
+    location: &str,
+    partitioned_by: Vec<String>,
+) -> Result<IdRow<Table>, CubeError> {
+    let location = location.trim_end_matches('/');
+    if location.contains("://") {
+        validate_storage(location)?;
+    }
+    let dir = format!("{}/", location);
+    let mut paths = remote_fs
+        .list(&dir)
+        .await?
+        .into_iter()
+        .filter(|p| {
+            p.ends_with(".parquet")
+                && !p[dir.len()..]
+                    .split('/')
+                    .any(|s| s.starts_with('_') || s.starts_with('.'))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    if paths.is_empty() {
+        return Err(CubeError::user(format!(
+            "No parquet files found in {}",
+            location
+        )));
+    }
+
+    let mut file_columns: Option<Vec<(String, ColumnType)>> = None;
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let values = hive_values(&path[dir.len()..], &partitioned_by)?;
+        let local_file = remote_fs.download_file(&path).await?;
+        let (columns, row_count) =
+            tokio::task::spawn_blocking(move || read_parquet_schema(&local_file)).await??;
+        match &file_columns {
+            None => file_columns = Some(columns),
+            Some(first) if first != &columns => {
+                return Err(CubeError::user(format!(
+                    "Schema of {} differs from other files in {}",
+                    path, location
+                )))
+            }
+            Some(_) => {}
+        }
+        files.push(AttachedFile {
+            path,
+            min_value: Some(Row::new(values.clone())),
+            max_value: successor(values, &ColumnType::String).map(Row::new),
+            row_count,
+            deleted_rows: Vec::new(),
+        });
+    }
+
+    let mut columns = partitioned_by
+        .iter()
+        .map(|c| (c.clone(), ColumnType::String))
+        .collect::<Vec<_>>();
+    for (name, column_type) in file_columns.unwrap() {
+        if partitioned_by.contains(&name) {
+            return Err(CubeError::user(format!(
+                "Partition column {} is also stored in files of {}",
+                name, location
+            )));
+        }
+        columns.push((name, column_type));
+    }
+    let columns = columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, column_type))| Column::new(name, column_type, i))
+        .collect();
+
+    meta_store
+        .attach_table(
+            schema_name,
+            table_name,
+            columns,
+            partitioned_by.len() as u64,
+            location.to_string(),
+            files,
+            None,
+        )
+        .await
+}
+
+pub async fn attach_iceberg_table(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    schema_name: String,
+    table_name: String,
+    location: &str,
+) -> Result<IdRow<Table>, CubeError> {
+    let location = location.trim_end_matches('/');
+    if location.contains("://") {
+        validate_storage(location)?;
+    }
+    let snapshot = read_iceberg_table(remote_fs, location).await?;
+
+    let key = &snapshot.partition_columns;
+    let (columns, last_key_type) = key_columns_first(&snapshot.columns, key);
+    let files = snapshot
+        .files
+        .into_iter()
+        .map(|f| {
+            let (min_value, max_value) = file_bounds(f.partition_values, &last_key_type);
+            AttachedFile {
+                path: f.path,
+                min_value,
+                max_value,
+                row_count: f.record_count - f.deleted_rows.len() as u64,
+                deleted_rows: f.deleted_rows,
+            }
+        })
+        .collect();
+    meta_store
+        .attach_table(
+            schema_name,
+            table_name,
+            columns,
+            key.len().max(1) as u64,
+            location.to_string(),
+            files,
+            None,
+        )
+        .await
+}
+
+pub async fn attach_delta_table(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    schema_name: String,
+    table_name: String,
+    location: &str,
+) -> Result<IdRow<Table>, CubeError> {
+    let location = location.trim_end_matches('/');
+    if location.contains("://") {
+        validate_storage(location)?;
+    }
+    let snapshot = read_delta_table(remote_fs, location).await?;
+    let key_size = snapshot.partition_columns.len().max(1) as u64;
+    let version = snapshot.version;
+    let (columns, files) = delta_files(snapshot);
+    meta_store
+        .attach_table(
+            schema_name,
+            table_name,
+            columns,
+            key_size,
+            location.to_string(),
+            files,
+            Some(version),
+        )
+        .await
+}
+
+/// Reads the latest snapshot of an attached Delta Lake table if it has new commits. Tables whose
+/// schema changed are not refreshed, they must be attached again.
+pub async fn refresh_delta_table(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    table: &IdRow<Table>,
+) -> Result<(), CubeError> {
+    let location = match table.get_row().attached_location() {
+        Some(l) => l,
+        None => return Ok(()),
+    };
+    let version = delta_table_version(remote_fs, location).await?;
+    if table.get_row().attached_version() == &Some(version) {
+        return Ok(());
+    }
+    let snapshot = read_delta_table(remote_fs, location).await?;
+    let version = snapshot.version;
+    let (columns, files) = delta_files(snapshot);
+    if &columns != table.get_row().get_columns() {
+        return Err(CubeError::user(format!(
+            "Schema of Delta Lake table {} changed in version {}, attach it again to read new commits",
+            location, version
+        )));
+    }
+    meta_store
+        .refresh_attached_table(table.get_id(), version, files)
+        .await?;
+    Ok(())
+}
+
+/// Columns and files of an attached Delta Lake table.
+fn delta_files(snapshot: DeltaSnapshot) -> (Vec<Column>, Vec<AttachedFile>) {
+    let key = &snapshot.partition_columns;
+    let (columns, last_key_type) = key_columns_first(&snapshot.columns, key);
+    let files = snapshot
+        .files
+        .into_iter()
+        .map(|f| {
+            let partition_values = if f.partition_values.is_empty() {
+                None
+            } else {
+                Some(f.partition_values)
+            };
+            let (min_value, max_value) = file_bounds(partition_values, &last_key_type);
+            AttachedFile {
+                path: f.path,
+                min_value,
+                max_value,
+                row_count: f.record_count - f.deleted_rows.len() as u64,
+                deleted_rows: f.deleted_rows,
+            }
+        })
+        .collect();
+    (columns, files)
+}
+
+/// Columns of a table keyed by `key` columns, which go first in the order of `key`. Also returns
+/// the type of the last key column.
+fn key_columns_first(
+    all_columns: &[(String, ColumnType)],
+    key: &[String],
+) -> (Vec<Column>, Option<ColumnType>) {
+    let mut columns = all_columns
+        .iter()
+        .filter(|(name, _)| key.contains(name))
+        .cloned()
+        .collect::<Vec<_>>();
+    columns.sort_by_key(|(name, _)| key.iter().position(|k| k == name));
+    let last_key_type = columns.last().map(|(_, t)| t.clone());
+    columns.extend(
+        all_columns
+            .iter()
+            .filter(|(name, _)| !key.contains(name))
+            .cloned(),
+    );
+    let columns = columns
+        .into_iter()
+        .enumerate()
+        .map(|(i, (name, column_type))| Column::new(name, column_type, i))
+        .collect();
+    (columns, last_key_type)
+}
+
+/// Bounds of a file with the same `key_values` in all rows, unbounded if they are unknown.
+fn file_bounds(
+    key_values: Option<Vec<TableValue>>,
+    last_key_type: &Option<ColumnType>,
+) -> (Option<Row>, Option<Row>) {
+    match (key_values, last_key_type) {
+        (Some(values), Some(last_type)) => (
+            Some(Row::new(values.clone())),
+            successor(values, last_type).map(Row::new),
+        ),
+        _ => (None, None),
+    }
+}
+
+/// Periodically refreshes attached Delta Lake tables to pick up new commits, see
+/// [refresh_delta_table].
+pub struct AttachedTableRefresher {
+    meta_store: Arc<dyn MetaStore>,
+    remote_fs: Arc<dyn RemoteFs>,
+    interval: Duration,
+    refresh_loop: WorkerLoop,
+}
+
+crate::di_service!(AttachedTableRefresher, []);
+
+impl AttachedTableRefresher {
+    pub fn new(
+        meta_store: Arc<dyn MetaStore>,
+        remote_fs: Arc<dyn RemoteFs>,
+        config: &dyn ConfigObj,
+    ) -> Arc<AttachedTableRefresher> {
+        Arc::new(AttachedTableRefresher {
+            meta_store,
+            remote_fs,
+            interval: Duration::from_secs(config.attached_table_refresh_secs()),
+            refresh_loop: WorkerLoop::new("AttachedTableRefresher"),
+        })
+    }
+
+    pub async fn wait_processing_loop(self: Arc<Self>) {
+        if self.interval == Duration::from_secs(0) {
+            return;
+        }
+        let interval = self.interval;
+        self.refresh_loop
+            .process(
+                self.clone(),
+                async move |_| {
+                    Delay::new(interval).await;
+                    Ok(())
+                },
+                async move |r, _| r.refresh_all().await,
+            )
+            .await
+    }
+
+    pub fn stop_processing_loop(&self) {
+        self.refresh_loop.stop();
+    }
+
+    async fn refresh_all(&self) -> Result<(), CubeError> {
+        for table in self.meta_store.get_tables().await? {
+            if table.get_row().attached_version().is_none() {
+                continue;
+            }
+            // A table that can't be read keeps its last snapshot and doesn't stop the others.
+            if let Err(e) =
+                refresh_delta_table(self.meta_store.as_ref(), self.remote_fs.as_ref(), &table).await
+            {
+                warn!(
+                    "Can't refresh attached table {}: {}",
+                    table.get_row().get_table_name(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Values of partition columns from directories of the `path` relative to the attached location.
+fn hive_values(path: &str, partitioned_by: &[String]) -> Result<Vec<TableValue>, CubeError> {
+    let dirs = path.split('/').collect::<Vec<_>>();
+    let dirs = &dirs[..dirs.len() - 1];
+    let error = || {
+        CubeError::user(format!(
+            "File {} is not in <column>=<value> directories of PARTITIONED BY ({})",
+            path,
+            partitioned_by.join(", ")
+        ))
+    };
+    if dirs.len() != partitioned_by.len() {
+        return Err(error());
+    }
+    dirs.iter()
+        .zip(partitioned_by.iter())
+        .map(|(d, column)| {
+            let mut name_and_value = d.splitn(2, '=');
+            match (name_and_value.next(), name_and_value.next()) {
+                (Some(name), Some(_)) if name != column => Err(error()),
+                (Some(_), Some(HIVE_DEFAULT_PARTITION)) => Ok(TableValue::Null),
+                (Some(_), Some(value)) => Ok(TableValue::String(unescape_path_name(value)?)),
+                _ => Err(error()),
+            }
+        })
+        .collect()
+}
+
+/// The smallest key above `values`, so the partition range `[values, successor)` only holds
+/// `values`. [None] if there is no such key, i.e. the range is unbounded.
+fn successor(mut values: Vec<TableValue>, last_type: &ColumnType) -> Option<Vec<TableValue>> {
+    let last = match (values.pop().unwrap(), last_type) {
+        (TableValue::Null, ColumnType::String) => TableValue::String(String::new()),
+        (TableValue::Null, ColumnType::Int) => TableValue::Int(i64::MIN),
+        (TableValue::Null, ColumnType::Timestamp) => {
+            TableValue::Timestamp(TimestampValue::new(i64::MIN))
+        }
+        (TableValue::Null, ColumnType::Boolean) => TableValue::Boolean(false),
+        (TableValue::String(s), _) => TableValue::String(format!("{}\0", s)),
+        (TableValue::Int(i), _) => TableValue::Int(i.checked_add(1)?),
+        // Timestamps are stored with microsecond precision.
+        (TableValue::Timestamp(t), _) => {
+            TableValue::Timestamp(TimestampValue::new(t.get_time_stamp().checked_add(1000)?))
+        }
+        (TableValue::Boolean(false), _) => TableValue::Boolean(true),
+        _ => return None,
+    };
+    values.push(last);
+    Some(values)
+}
+
+/// Columns and the row count of a parquet file.
+fn read_parquet_schema(file: &str) -> Result<(Vec<(String, ColumnType)>, u64), CubeError> {
+    let reader = SerializedFileReader::new(File::open(file)?)?;
+    let metadata = reader.metadata().file_metadata();
+    let columns = metadata
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|c| {
+            let column_type = match (c.physical_type(), c.converted_type()) {
+                _ if c.path().parts().len() != 1 => None,
+                (Type::INT64, ConvertedType::NONE) | (Type::INT64, ConvertedType::INT_64) => {
+                    Some(ColumnType::Int)
+                }
+                (Type::INT64, ConvertedType::TIMESTAMP_MICROS) => Some(ColumnType::Timestamp),
+                (Type::DOUBLE, ConvertedType::NONE) => Some(ColumnType::Float),
+                (Type::BOOLEAN, ConvertedType::NONE) => Some(ColumnType::Boolean),
+                (Type::BYTE_ARRAY, ConvertedType::UTF8) => Some(ColumnType::String),
+                _ => None,
+            };
+            match column_type {
+                Some(t) => Ok((c.name().to_string(), t)),
+                None => Err(CubeError::user(format!(
+                    "Unsupported type of column {} in {}: {:?} {:?}",
+                    c.path().string(),
+                    file,
+                    c.physical_type(),
+                    c.converted_type()
+                ))),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((columns, metadata.num_rows() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hive_directories() {
+        let columns = vec!["dt".to_string(), "region".to_string()];
+        assert_eq!(
+            hive_values("dt=2021-06-01/region=us%2Fwest/part-0.parquet", &columns).unwrap(),
+            vec![
+                TableValue::String("2021-06-01".to_string()),
+                TableValue::String("us/west".to_string())
+            ]
+        );
+        assert_eq!(
+            hive_values(
+                "dt=2021-06-01/region=__HIVE_DEFAULT_PARTITION__/part-0.parquet",
+                &columns
+            )
+            .unwrap(),
+            vec![
+                TableValue::String("2021-06-01".to_string()),
+                TableValue::Null
+            ]
+        );
+        assert!(hive_values("dt=2021-06-01/part-0.parquet", &columns).is_err());
+        assert!(hive_values("region=us/dt=2021-06-01/part-0.parquet", &columns).is_err());
+        assert!(hive_values("dt=2021-06-01/us/part-0.parquet", &columns).is_err());
+
+        assert_eq!(
+            successor(
+                vec![TableValue::String("a".to_string()), TableValue::Null],
+                &ColumnType::String
+            ),
+            Some(vec![
+                TableValue::String("a".to_string()),
+                TableValue::String("".to_string())
+            ])
+        );
+        assert_eq!(
+            successor(
+                vec![TableValue::String("a".to_string())],
+                &ColumnType::String
+            ),
+            Some(vec![TableValue::String("a\0".to_string())])
+        );
+        assert_eq!(
+            successor(vec![TableValue::Int(5)], &ColumnType::Int),
+            Some(vec![TableValue::Int(6)])
+        );
+        assert_eq!(
+            successor(vec![TableValue::Null], &ColumnType::Int),
+            Some(vec![TableValue::Int(i64::MIN)])
+        );
+        assert_eq!(
+            successor(vec![TableValue::Int(i64::MAX)], &ColumnType::Int),
+            None
+        );
+        assert_eq!(
+            successor(vec![TableValue::Boolean(true)], &ColumnType::Boolean),
+            None
+        );
+    }
+}
@@ -54,6 +54,12 @@ impl SqlResultCache {
         }
     }
 
+    /// Evicts least recently used results if there are more than `capacity` of them. Zero
+    /// capacity disables caching.
+    pub async fn resize(&self, capacity: usize) {
+        self.cache.write().await.resize(capacity);
+    }
+
     pub async fn get<F>(
         &self,
         query: &str,
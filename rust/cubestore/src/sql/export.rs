@@ -0,0 +1,186 @@
+//! `EXPORT <query>` runs the query in the background and writes its result as CSV to the remote
+//! storage, returning an export id right away. This decouples long queries from lifetimes of
+//! client connections: clients poll `system.exports` for completion and download finished
+//! results with `GET /export/<id>` from the HTTP server.
+//! Exports are tracked in memory of the router. Finished exports expire after
+//! CUBESTORE_EXPORT_TTL_SECS, files of expired exports are removed when the next export starts.
+use crate::store::DataFrame;
+use crate::table::TableValue;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use hex::ToHex;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct ResultExports {
+    exports: Mutex<HashMap<String, Export>>,
+    ttl: Duration,
+}
+
+crate::di_service!(ResultExports, []);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Export {
+    pub id: String,
+    pub query: String,
+    pub status: ExportStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Number of rows in the result, set when the export completes.
+    pub rows: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExportStatus {
+    Running,
+    Completed,
+    Error(String),
+}
+
+impl ExportStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportStatus::Running => "running",
+            ExportStatus::Completed => "completed",
+            ExportStatus::Error(_) => "error",
+        }
+    }
+}
+
+/// Path of the export result in the remote storage.
+pub fn export_file_name(id: &str) -> String {
+    format!("exports/{}.csv", id)
+}
+
+impl ResultExports {
+    pub fn new(ttl: Duration) -> ResultExports {
+        ResultExports {
+            exports: Mutex::new(HashMap::new()),
+            ttl,
+        }
+    }
+
+    /// Registers a running export and returns its id.
+    pub fn start(&self, query: &str) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.exports.lock().unwrap().insert(
+            id.clone(),
+            Export {
+                id: id.clone(),
+                query: query.to_string(),
+                status: ExportStatus::Running,
+                created_at: Utc::now(),
+                finished_at: None,
+                rows: None,
+            },
+        );
+        id
+    }
+
+    pub fn finish(&self, id: &str, result: Result<u64, CubeError>) {
+        if let Some(e) = self.exports.lock().unwrap().get_mut(id) {
+            match result {
+                Ok(rows) => {
+                    e.status = ExportStatus::Completed;
+                    e.rows = Some(rows);
+                }
+                Err(err) => e.status = ExportStatus::Error(err.message),
+            }
+            e.finished_at = Some(Utc::now());
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<Export> {
+        self.exports.lock().unwrap().get(id).cloned()
+    }
+
+    /// Oldest exports go first.
+    pub fn all(&self) -> Vec<Export> {
+        let mut r = self
+            .exports
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        r.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        r
+    }
+
+    /// Forgets exports that finished more than TTL ago and returns them, so their files can be
+    /// removed.
+    pub fn take_expired(&self, now: DateTime<Utc>) -> Vec<Export> {
+        let ttl = chrono::Duration::from_std(self.ttl).unwrap_or(chrono::Duration::max_value());
+        let mut exports = self.exports.lock().unwrap();
+        let expired = exports
+            .values()
+            .filter(|e| match e.finished_at {
+                Some(t) => t + ttl < now,
+                None => false,
+            })
+            .map(|e| e.id.clone())
+            .collect::<Vec<_>>();
+        expired
+            .into_iter()
+            .filter_map(|id| exports.remove(&id))
+            .collect()
+    }
+}
+
+/// Nulls are written as empty fields, binary values as hex strings.
+pub fn write_csv(data_frame: &DataFrame, path: &str) -> Result<(), CubeError> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(data_frame.get_columns().iter().map(|c| c.get_name()))?;
+    for row in data_frame.get_rows() {
+        writer.write_record(row.values().iter().map(|v| match v {
+            TableValue::Null => String::new(),
+            TableValue::String(s) | TableValue::Decimal(s) => s.clone(),
+            TableValue::Int(i) => i.to_string(),
+            TableValue::Float(f) => f.to_string(),
+            TableValue::Bytes(b) => b.encode_hex_upper::<String>(),
+            TableValue::Timestamp(t) => t.to_string(),
+            TableValue::Boolean(b) => b.to_string(),
+        }))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expiration() {
+        let exports = ResultExports::new(Duration::from_secs(60));
+        let e1 = exports.start("SELECT 1");
+        let e2 = exports.start("SELECT 2");
+        let e3 = exports.start("SELECT 3");
+        exports.finish(&e1, Ok(1));
+        exports.finish(&e2, Err(CubeError::user("failed".to_string())));
+        assert_eq!(exports.get(&e1).unwrap().rows, Some(1));
+        assert_eq!(
+            exports.get(&e2).unwrap().status,
+            ExportStatus::Error("failed".to_string())
+        );
+
+        assert_eq!(exports.take_expired(Utc::now()), vec![]);
+        let later = Utc::now() + chrono::Duration::seconds(61);
+        let mut expired = exports
+            .take_expired(later)
+            .into_iter()
+            .map(|e| e.id)
+            .collect::<Vec<_>>();
+        expired.sort();
+        let mut expected = vec![e1, e2];
+        expected.sort();
+        assert_eq!(expired, expected);
+        // Running exports never expire.
+        assert_eq!(
+            exports.all().into_iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![e3]
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Rollups built by CubeStore can be written back to object storage for Spark, Athena and other
+//! engines that read the hive layout. `CREATE TABLE ... WITH (export_location = '<location>',
+//! export_partitioned_by = '<column>, ...')` writes rows of the table to
+//! `<location>/<column>=<value>/.../part-0.parquet` once the table is built, i.e. imported from its
+//! LOCATION or filled by AS SELECT. The location is a storage location described in
+//! [crate::remotefs::storage] or a path of the storage of the cluster. The table is dropped if the
+//! export fails, so the build can be retried.
+//!
+//! Each distinct combination of values of the export_partitioned_by columns becomes a directory
+//! with a single file holding the rest of the columns, in the types CubeStore stores them with,
+//! sorted by the first of them.
+//! Values are escaped as by Hive, nulls and empty strings go to `__HIVE_DEFAULT_PARTITION__`.
+//! Rows are read with a select on the router, so the table must fit into its memory as for CREATE
+//! TABLE AS SELECT. Files of previous exports to the same location are overwritten, files of
+//! partitions that are gone are kept, so every version of a rollup should have its own location.
+//! Exported files can be attached back with ATTACH TABLE, see [crate::sql::attach].
+use crate::metastore::{Column, Index};
+use crate::remotefs::{escape_path_name, RemoteFs};
+use crate::sql::attach::HIVE_DEFAULT_PARTITION;
+use crate::store::DataFrame;
+use crate::table::data::MutRows;
+use crate::table::parquet::ParquetTableStore;
+use crate::table::{Row, TableStore, TableValue};
+use crate::CubeError;
+use chrono::{TimeZone, Utc};
+use hex::ToHex;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HiveExport {
+    pub location: String,
+    pub partitioned_by: Vec<String>,
+}
+
+/// Writes `data` to the location of `export` and returns the number of written files.
+pub async fn export_hive_table(
+    remote_fs: &dyn RemoteFs,
+    export: &HiveExport,
+    data: &DataFrame,
+) -> Result<u64, CubeError> {
+    let (columns, partitions) = hive_partitions(data, &export.partitioned_by)?;
+    let location = export.location.trim_end_matches('/');
+    let files = partitions.len() as u64;
+    for (dir, mut rows) in partitions {
+        // Files are sorted by their first column as partitions of CubeStore tables are.
+        rows.sort_by(|a, b| a.sort_key(1).cmp(&b.sort_key(1)));
+        let remote_path = format!("{}/{}part-0.parquet", location, dir);
+        let temp_path = remote_fs.temp_upload_path(&remote_path).await?;
+        let index = Index::try_new("default".to_string(), 0, columns.clone(), 1)?;
+        let path = temp_path.clone();
+        tokio::task::spawn_blocking(move || -> Result<(), CubeError> {
+            let rows = MutRows::from_heap_allocated(index.get_columns().len(), &rows).freeze();
+            ParquetTableStore::new(index, 16384).merge_rows(None, vec![path], rows.view(), 1)?;
+            Ok(())
+        })
+        .await??;
+        remote_fs.upload_file(&temp_path, &remote_path).await?;
+    }
+    Ok(files)
+}
+
+/// Columns stored in files and rows of every directory, whose path ends with `/` unless it's the
+/// location itself.
+fn hive_partitions(
+    data: &DataFrame,
+    partitioned_by: &[String],
+) -> Result<(Vec<Column>, BTreeMap<String, Vec<Row>>), CubeError> {
+    let all_columns = data.get_columns();
+    let mut partition_indices = Vec::with_capacity(partitioned_by.len());
+    for name in partitioned_by {
+        match all_columns.iter().position(|c| c.get_name() == name) {
+            Some(i) if !partition_indices.contains(&i) => partition_indices.push(i),
+            Some(_) => {
+                return Err(CubeError::user(format!(
+                    "Column {} is listed twice in export_partitioned_by",
+                    name
+                )))
+            }
+            None => {
+                return Err(CubeError::user(format!(
+                    "Column {} of export_partitioned_by is not found",
+                    name
+                )))
+            }
+        }
+    }
+    let file_indices = (0..all_columns.len())
+        .filter(|i| !partition_indices.contains(i))
+        .collect::<Vec<_>>();
+    if file_indices.is_empty() {
+        return Err(CubeError::user(
+            "At least one column must not be in export_partitioned_by".to_string(),
+        ));
+    }
+    let columns = file_indices
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let column = &all_columns[*c];
+            Column::new(
+                column.get_name().clone(),
+                column.get_column_type().clone(),
+                i,
+            )
+        })
+        .collect();
+
+    let mut partitions = BTreeMap::<String, Vec<Row>>::new();
+    for row in data.get_rows() {
+        let values = row.values();
+        let dir = partition_indices
+            .iter()
+            .map(|i| {
+                format!(
+                    "{}={}/",
+                    escape_path_name(all_columns[*i].get_name()),
+                    hive_value(&values[*i])
+                )
+            })
+            .collect::<String>();
+        partitions.entry(dir).or_default().push(Row::new(
+            file_indices.iter().map(|i| values[*i].clone()).collect(),
+        ));
+    }
+    Ok((columns, partitions))
+}
+
+/// Escaped directory name of a partition value.
+fn hive_value(value: &TableValue) -> String {
+    let value = match value {
+        TableValue::Null => return HIVE_DEFAULT_PARTITION.to_string(),
+        TableValue::String(s) if s.is_empty() => return HIVE_DEFAULT_PARTITION.to_string(),
+        TableValue::String(s) | TableValue::Decimal(s) => s.clone(),
+        TableValue::Int(i) => i.to_string(),
+        TableValue::Float(f) => f.to_string(),
+        TableValue::Bytes(b) => b.encode_hex_upper::<String>(),
+        // Format of timestamp partitions written by Hive and Spark.
+        TableValue::Timestamp(t) => Utc
+            .timestamp_nanos(t.get_time_stamp())
+            .format("%Y-%m-%d %H:%M:%S%.f")
+            .to_string(),
+        TableValue::Boolean(b) => b.to_string(),
+    };
+    escape_path_name(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metastore::ColumnType;
+    use crate::table::TimestampValue;
+
+    #[test]
+    fn partitions() {
+        let data = DataFrame::new(
+            vec![
+                Column::new("dt".to_string(), ColumnType::Timestamp, 0),
+                Column::new("amount".to_string(), ColumnType::Int, 1),
+                Column::new("region".to_string(), ColumnType::String, 2),
+            ],
+            vec![
+                Row::new(vec![
+                    TableValue::Timestamp(TimestampValue::new(1622505600000000000)),
+                    TableValue::Int(1),
+                    TableValue::String("us/west".to_string()),
+                ]),
+                Row::new(vec![
+                    TableValue::Timestamp(TimestampValue::new(1622505600000000000)),
+                    TableValue::Int(2),
+                    TableValue::Null,
+                ]),
+                Row::new(vec![
+                    TableValue::Timestamp(TimestampValue::new(1622505600000000000)),
+                    TableValue::Int(3),
+                    TableValue::String("us/west".to_string()),
+                ]),
+            ],
+        );
+        let (columns, partitions) =
+            hive_partitions(&data, &["region".to_string(), "dt".to_string()]).unwrap();
+        assert_eq!(
+            columns,
+            vec![Column::new("amount".to_string(), ColumnType::Int, 0)]
+        );
+        assert_eq!(
+            partitions.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    "region=__HIVE_DEFAULT_PARTITION__/dt=2021-06-01 00%3A00%3A00/".to_string(),
+                    vec![Row::new(vec![TableValue::Int(2)])]
+                ),
+                (
+                    "region=us%2Fwest/dt=2021-06-01 00%3A00%3A00/".to_string(),
+                    vec![
+                        Row::new(vec![TableValue::Int(1)]),
+                        Row::new(vec![TableValue::Int(3)])
+                    ]
+                ),
+            ]
+        );
+
+        let (_, partitions) = hive_partitions(&data, &[]).unwrap();
+        assert_eq!(partitions[""].len(), 3);
+        assert!(hive_partitions(&data, &["unknown".to_string()]).is_err());
+        assert!(hive_partitions(&data, &["dt".to_string(), "dt".to_string()]).is_err());
+        assert!(hive_partitions(
+            &data,
+            &["dt".to_string(), "amount".to_string(), "region".to_string()]
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//! `CREATE INDEX` on a table with data adds the index and copies existing rows to it while the
+//! table stays available for queries and inserts. Inserts go to the new index from the moment it
+//! is added. Rows that partitions and chunks of the default index had at that moment are read on
+//! the router, split into chunks of the new index and activated at once. Only then queries start
+//! to use the index, see [crate::metastore::MetaStore::start_index_build].
+//!
+//! Files being copied are leased, so compaction and DROP TABLE don't remove them meanwhile, see
+//! [crate::store::leases]. A failed build removes the index again.
+use crate::metastore::{Chunk, IdRow, Index, IndexDef, MetaStore, Partition};
+use crate::remotefs::RemoteFs;
+use crate::store::leases::DataFileLeases;
+use crate::store::{read_remote_rows, ChunkDataStore};
+use crate::table::data::MutRows;
+use crate::CubeError;
+use futures::future::join_all;
+use log::{info, warn};
+use std::sync::Arc;
+
+pub async fn build_index(
+    meta_store: &dyn MetaStore,
+    chunk_store: &dyn ChunkDataStore,
+    remote_fs: &dyn RemoteFs,
+    file_leases: &Arc<DataFileLeases>,
+    schema_name: String,
+    table_name: String,
+    index_def: IndexDef,
+) -> Result<IdRow<Index>, CubeError> {
+    let (index, partitions) = meta_store
+        .start_index_build(schema_name, table_name, index_def)
+        .await?;
+    info!(
+        "Building index {} from {} partitions",
+        index.get_row().get_name(),
+        partitions.len()
+    );
+    // Leased right away, so compaction can't remove files before they are read.
+    let files = data_files(&partitions);
+    let _lease = file_leases.acquire(files.clone());
+    let result = async {
+        let chunk_ids = copy_rows(
+            meta_store,
+            chunk_store,
+            remote_fs,
+            &index,
+            &partitions,
+            files,
+        )
+        .await?;
+        meta_store
+            .finish_index_build(index.get_id(), chunk_ids)
+            .await
+    }
+    .await;
+    if result.is_err() {
+        if let Err(e) = meta_store.abort_index_build(index.get_id()).await {
+            warn!(
+                "Failed to remove index {} after failed build: {}",
+                index.get_id(),
+                e
+            );
+        }
+    }
+    result
+}
+
+fn data_files(partitions: &[(IdRow<Partition>, Vec<IdRow<Chunk>>)]) -> Vec<String> {
+    let mut files = Vec::new();
+    for (p, chunks) in partitions.iter() {
+        if p.get_row().main_table_row_count() > 0 {
+            if let Some(file) = p.get_row().get_full_name(p.get_id()) {
+                files.push(file);
+            }
+        }
+        files.extend(chunks.iter().map(|c| c.get_row().get_full_name(c.get_id())));
+    }
+    files
+}
+
+/// Returns ids of uploaded chunks of `index`.
+async fn copy_rows(
+    meta_store: &dyn MetaStore,
+    chunk_store: &dyn ChunkDataStore,
+    remote_fs: &dyn RemoteFs,
+    index: &IdRow<Index>,
+    partitions: &[(IdRow<Partition>, Vec<IdRow<Chunk>>)],
+    files: Vec<String>,
+) -> Result<Vec<u64>, CubeError> {
+    let source_index = match partitions.first() {
+        Some((p, _)) => meta_store.get_index(p.get_row().get_index_id()).await?,
+        None => return Ok(Vec::new()),
+    };
+
+    let columns = index.get_row().get_columns();
+    let mut chunk_jobs = Vec::new();
+    for file in files.iter() {
+        let rows = read_remote_rows(remote_fs, source_index.get_row(), file, columns).await?;
+        if rows.is_empty() {
+            continue;
+        }
+        let rows = MutRows::from_heap_allocated(columns.len(), &rows).freeze();
+        chunk_jobs.append(
+            &mut chunk_store
+                .partition_index_data(index.get_id(), rows)
+                .await?,
+        );
+    }
+    join_all(chunk_jobs)
+        .await
+        .into_iter()
+        .map(|c| Ok(c??.get_id()))
+        .collect()
+}
@@ -0,0 +1,90 @@
+//! `LOAD DATA [LOCAL] INFILE '<location>' INTO TABLE <name> [IGNORE <n> LINES] [(<columns>)]`
+//! ingests a CSV file without a header into the table as INSERT does, values are parsed and
+//! validated as on CSV import. Values are in the order of the column list or of the columns of the
+//! table. Files ending with `.gz` are decompressed.
+//!
+//! Without LOCAL, the location is a file uploaded to `/upload-temp-file?name=<name>` of the HTTP
+//! server, given as `temp://<name>`. Paths on the router are never opened, clients could read any
+//! file of the router otherwise. With LOCAL, the MySQL client sends its file once the server asks
+//! for it, see [crate::mysql::local_infile].
+use crate::metastore::Column;
+use crate::remotefs::RemoteFs;
+use crate::CubeError;
+use async_compression::tokio::bufread::GzipDecoder;
+use sqlparser::ast::Ident;
+use std::pin::Pin;
+use tempfile::TempPath;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, BufReader};
+
+/// Opens an uploaded file.
+pub async fn open_location(
+    remote_fs: &dyn RemoteFs,
+    location: &str,
+) -> Result<Pin<Box<dyn AsyncBufRead + Send>>, CubeError> {
+    let name = location.strip_prefix("temp://").ok_or_else(|| {
+        CubeError::user(format!(
+            "LOAD DATA INFILE only loads uploaded files, upload '{}' to \
+             /upload-temp-file?name=<name> and load 'temp://<name>' or use LOAD DATA LOCAL INFILE",
+            location
+        ))
+    })?;
+    // Names are relative to the upload directory.
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name == ".." {
+        return Err(CubeError::user(format!(
+            "Invalid name of an uploaded file: '{}'",
+            name
+        )));
+    }
+    let local_path = remote_fs
+        .download_file(&format!("temp-uploads/{}", name))
+        .await?;
+    Ok(reader(File::open(local_path).await?, location))
+}
+
+/// Opens the file a MySQL client sent for `location`. It's removed once `path` is dropped.
+pub async fn open_local_infile(
+    path: &TempPath,
+    location: &str,
+) -> Result<Pin<Box<dyn AsyncBufRead + Send>>, CubeError> {
+    Ok(reader(File::open(path).await?, location))
+}
+
+fn reader(file: File, location: &str) -> Pin<Box<dyn AsyncBufRead + Send>> {
+    if location.ends_with(".gz") {
+        Box::pin(BufReader::new(GzipDecoder::new(BufReader::new(file))))
+    } else {
+        Box::pin(BufReader::new(file))
+    }
+}
+
+/// Names of the columns of values in the file. Generated columns can't be loaded.
+pub fn load_data_header(
+    table_columns: &[Column],
+    columns: &[Ident],
+    table_name: &str,
+) -> Result<Vec<String>, CubeError> {
+    if columns.is_empty() {
+        return Ok(table_columns
+            .iter()
+            .filter(|c| c.generated().is_none())
+            .map(|c| c.get_name().clone())
+            .collect());
+    }
+    columns
+        .iter()
+        .map(
+            |i| match table_columns.iter().find(|c| *c.get_name() == i.value) {
+                None => Err(CubeError::user(format!(
+                    "Column {} does not exist in {}",
+                    i.value, table_name
+                ))),
+                Some(c) if c.generated().is_some() => Err(CubeError::user(format!(
+                    "Generated column {} can't be loaded",
+                    i.value
+                ))),
+                Some(c) => Ok(c.get_name().clone()),
+            },
+        )
+        .collect()
+}
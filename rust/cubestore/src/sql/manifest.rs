@@ -0,0 +1,313 @@
+//! Table manifests let another cluster query a table without copying its data.
+//! `EXPORT TABLE <name> MANIFEST TO '<path>'` writes the schema, indexes, partition bounds and
+//! data file paths of the table as JSON to a path of the remote storage, e.g. `s3://bucket/t.json`
+//! or `manifests/t.json` in the storage of the cluster. `IMPORT TABLE [<name>] FROM MANIFEST
+//! '<path>'` creates the table with the same indexes and partitions. Imported partitions are
+//! attached to the files of the source cluster, the storage must be reachable from both clusters.
+//! Chunks of the source are copied, so the manifest is only read once.
+//!
+//! A manifest is a snapshot: the source keeps compacting its partitions and removes replaced files
+//! after [crate::config::ConfigObj::not_used_timeout], queries on the imported table fail from then
+//! on. Export manifests of tables that are not written anymore or import them again after writes.
+//! Attached partitions are never compacted and their files are never removed by the importing
+//! cluster, rows inserted into the imported table stay in chunks.
+use crate::metastore::table::Table;
+use crate::metastore::{Column, IdRow, Index, IndexDef, MetaStore, Partition};
+use crate::remotefs::storage::storage_file_name;
+use crate::remotefs::RemoteFs;
+use crate::store::{read_remote_rows, ChunkDataStore};
+use crate::table::data::MutRows;
+use crate::table::Row;
+use crate::CubeError;
+use futures::future::join_all;
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// Bumped on incompatible changes of the format.
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct TableManifest {
+    pub version: u32,
+    pub schema_name: String,
+    pub table_name: String,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<IndexManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct IndexManifest {
+    pub name: String,
+    /// Names of the columns in the order of the index.
+    pub columns: Vec<String>,
+    pub sort_key_size: u64,
+    pub partitions: Vec<PartitionManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PartitionManifest {
+    pub min_value: Option<Row>,
+    pub max_value: Option<Row>,
+    pub row_count: u64,
+    /// Full remote path, [None] for partitions without data.
+    pub file: Option<String>,
+    pub chunks: Vec<ChunkManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChunkManifest {
+    pub row_count: u64,
+    /// Full remote path.
+    pub file: String,
+}
+
+/// Remote paths are relative to the storage of the cluster unless they name a location.
+fn full_path(storage_location: &Option<String>, path: String) -> Result<String, CubeError> {
+    if path.contains("://") {
+        return Ok(path);
+    }
+    match storage_location {
+        Some(_) => Ok(storage_file_name(storage_location, path)),
+        None => Err(CubeError::user(format!(
+            "Can't export {} as the storage of this cluster is local to the node, configure a remote storage",
+            path
+        ))),
+    }
+}
+
+pub async fn export_manifest(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    storage_location: &Option<String>,
+    schema_name: String,
+    table_name: String,
+    location: &str,
+) -> Result<TableManifest, CubeError> {
+    let table = meta_store
+        .get_table(schema_name.clone(), table_name.clone())
+        .await?;
+    if !table.get_row().is_ready() {
+        return Err(CubeError::user(format!(
+            "Can't export {}.{} as it is not ready",
+            schema_name, table_name
+        )));
+    }
+    let mut indexes = Vec::new();
+    for index in meta_store.get_table_indexes(table.get_id()).await? {
+        let mut partitions = Vec::new();
+        for p in meta_store
+            .get_active_partitions_by_index_id(index.get_id())
+            .await?
+        {
+            let file = match p.get_row().get_full_name(p.get_id()) {
+                Some(f) if p.get_row().main_table_row_count() > 0 => {
+                    Some(full_path(storage_location, f)?)
+                }
+                _ => None,
+            };
+            let mut chunks = Vec::new();
+            for c in meta_store
+                .get_chunks_by_partition(p.get_id(), false)
+                .await?
+            {
+                chunks.push(ChunkManifest {
+                    row_count: c.get_row().get_row_count(),
+                    file: full_path(storage_location, c.get_row().get_full_name(c.get_id()))?,
+                });
+            }
+            partitions.push(PartitionManifest {
+                min_value: p.get_row().get_min_val().clone(),
+                max_value: p.get_row().get_max_val().clone(),
+                row_count: p.get_row().main_table_row_count(),
+                file,
+                chunks,
+            });
+        }
+        indexes.push(IndexManifest {
+            name: index.get_row().get_name().clone(),
+            columns: index_column_names(index.get_row()),
+            sort_key_size: index.get_row().sort_key_size(),
+            partitions,
+        });
+    }
+    let manifest = TableManifest {
+        version: MANIFEST_VERSION,
+        schema_name,
+        table_name,
+        columns: table.get_row().get_columns().clone(),
+        indexes,
+    };
+
+    let temp_path = remote_fs.temp_upload_path(location).await?;
+    tokio::fs::write(&temp_path, serde_json::to_vec_pretty(&manifest)?).await?;
+    remote_fs.upload_file(&temp_path, location).await?;
+    Ok(manifest)
+}
+
+/// Without `name`, the table is created under the name from the manifest.
+pub async fn import_manifest(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    chunk_store: &dyn ChunkDataStore,
+    location: &str,
+    name: Option<(String, String)>,
+) -> Result<IdRow<Table>, CubeError> {
+    remote_fs.download_file(location).await?;
+    let local_file = remote_fs.local_file(location).await?;
+    let manifest: TableManifest = serde_json::from_slice(&tokio::fs::read(&local_file).await?)
+        .map_err(|e| CubeError::user(format!("Invalid manifest {}: {}", location, e)))?;
+    if manifest.version != MANIFEST_VERSION {
+        return Err(CubeError::user(format!(
+            "Unsupported version {} of manifest {}, expected {}",
+            manifest.version, location, MANIFEST_VERSION
+        )));
+    }
+    let (schema_name, table_name) =
+        name.unwrap_or_else(|| (manifest.schema_name.clone(), manifest.table_name.clone()));
+
+    meta_store.create_schema(schema_name.clone(), true).await?;
+    let index_defs = manifest
+        .indexes
+        .iter()
+        .filter(|i| i.name != "default")
+        .map(|i| IndexDef {
+            name: i.name.clone(),
+            columns: i.columns[..i.sort_key_size as usize].to_vec(),
+        })
+        .collect();
+    // Keeps the sort order of the source, so attached files match the default index.
+    let sort_order = manifest
+        .indexes
+        .iter()
+        .find(|i| i.name == "default")
+        .map(|i| i.columns[..i.sort_key_size as usize].to_vec())
+        .unwrap_or_default();
+    let table = meta_store
+        .create_table(
+            schema_name,
+            table_name,
+            manifest.columns.clone(),
+            None,
+            None,
+            index_defs,
+            false,
+            None,
+            None,
+            None,
+            sort_order,
+            false,
+        )
+        .await?;
+
+    if let Err(e) = attach_partitions(
+        meta_store,
+        remote_fs,
+        chunk_store,
+        &manifest,
+        location,
+        &table,
+    )
+    .await
+    {
+        // The table is not ready yet, drop it so the import can be retried under the same name.
+        if let Err(drop_err) = meta_store.drop_table(table.get_id()).await {
+            warn!(
+                "Failed to drop {} after failed import: {}",
+                table.get_row().get_table_name(),
+                drop_err
+            );
+        }
+        return Err(e);
+    }
+    meta_store.table_ready(table.get_id(), true).await
+}
+
+async fn attach_partitions(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    chunk_store: &dyn ChunkDataStore,
+    manifest: &TableManifest,
+    location: &str,
+    table: &IdRow<Table>,
+) -> Result<(), CubeError> {
+    let mut chunk_jobs = Vec::new();
+    for index in meta_store.get_table_indexes(table.get_id()).await? {
+        let index_manifest = manifest
+            .indexes
+            .iter()
+            .find(|i| &i.name == index.get_row().get_name())
+            .ok_or_else(|| {
+                CubeError::internal(format!(
+                    "Index {} is missing in manifest {}",
+                    index.get_row().get_name(),
+                    location
+                ))
+            })?;
+        if index_column_names(index.get_row()) != index_manifest.columns {
+            return Err(CubeError::user(format!(
+                "Columns of index {} in manifest {} do not match columns of the imported index",
+                index_manifest.name, location
+            )));
+        }
+
+        for root in meta_store
+            .get_active_partitions_by_index_id(index.get_id())
+            .await?
+        {
+            meta_store.delete_partition(root.get_id()).await?;
+        }
+        for p in index_manifest.partitions.iter() {
+            meta_store
+                .create_partition(
+                    Partition::new(index.get_id(), None, None)
+                        .update_min_max_and_row_count(
+                            p.min_value.clone(),
+                            p.max_value.clone(),
+                            p.row_count,
+                        )
+                        .set_attached_file(p.file.clone()),
+                )
+                .await?;
+        }
+        // Chunks are routed to the new partitions by their bounds, as the source did.
+        for c in index_manifest
+            .partitions
+            .iter()
+            .flat_map(|p| p.chunks.iter())
+        {
+            let rows = read_remote_rows(
+                remote_fs,
+                index.get_row(),
+                &c.file,
+                index.get_row().get_columns(),
+            )
+            .await?;
+            let rows =
+                MutRows::from_heap_allocated(index.get_row().get_columns().len(), &rows).freeze();
+            chunk_jobs.append(
+                &mut chunk_store
+                    .partition_index_data(index.get_id(), rows)
+                    .await?,
+            );
+        }
+    }
+    let chunk_ids = join_all(chunk_jobs)
+        .await
+        .into_iter()
+        .map(|c| Ok(c??.get_id()))
+        .collect::<Result<Vec<_>, CubeError>>()?;
+    if !chunk_ids.is_empty() {
+        meta_store
+            .activate_chunks(table.get_id(), chunk_ids)
+            .await?;
+    }
+    Ok(())
+}
+
+fn index_column_names(index: &Index) -> Vec<String> {
+    index
+        .get_columns()
+        .iter()
+        .map(|c| c.get_name().clone())
+        .collect()
+}
@@ -1,8 +1,11 @@
-use sqlparser::ast::{HiveDistributionStyle, ObjectName, Statement as SQLStatement};
+use crate::CubeError;
+use sqlparser::ast::{
+    Expr, HiveDistributionStyle, Ident, ObjectName, Query, SqlOption, Statement as SQLStatement,
+};
 use sqlparser::dialect::keywords::Keyword;
 use sqlparser::dialect::Dialect;
 use sqlparser::parser::{Parser, ParserError};
-use sqlparser::tokenizer::{Token, Tokenizer};
+use sqlparser::tokenizer::{Token, Tokenizer, Whitespace};
 
 #[derive(Debug)]
 pub struct MySqlDialectWithBackTicks {}
@@ -37,19 +40,226 @@ pub enum Statement {
         schema_name: ObjectName,
         if_not_exists: bool,
     },
+    System(SystemCommand),
+    ChecksumTable {
+        table_name: ObjectName,
+    },
+    /// Rewrites files of the table with the current settings. Only indexes with one of `columns`
+    /// are rewritten, all of them if `columns` is empty.
+    OptimizeTable {
+        table_name: ObjectName,
+        columns: Vec<Ident>,
+    },
+    Export {
+        query: Box<Query>,
+    },
+    /// See [crate::sql::load_data].
+    LoadData {
+        local: bool,
+        location: String,
+        table_name: ObjectName,
+        /// Lines skipped at the start of the file, e.g. the header.
+        ignore_lines: u64,
+        /// Columns of the values in the file, all columns of the table if empty.
+        columns: Vec<Ident>,
+    },
+    FetchQuery {
+        query_id: String,
+    },
+    CancelQuery {
+        query_id: String,
+    },
+    /// `KILL [CONNECTION] <id>`, see [crate::mysql::sessions].
+    KillConnection {
+        connection_id: u64,
+    },
+    /// See [crate::sql::prefetch].
+    CreatePrefetch {
+        schedule: String,
+        query: String,
+    },
+    DropPrefetch {
+        prefetch_id: String,
+    },
+    /// See [crate::sql::manifest].
+    ExportManifest {
+        table_name: ObjectName,
+        location: String,
+    },
+    /// Without the name, the table is imported under the name from the manifest.
+    ImportManifest {
+        table_name: Option<ObjectName>,
+        location: String,
+    },
+    /// See [crate::sql::attach].
+    AttachTable {
+        table_name: ObjectName,
+        location: String,
+        /// Empty if `format` is set.
+        partitioned_by: Vec<Ident>,
+        format: Option<Ident>,
+    },
+    /// See [crate::secrets].
+    CreateSecret {
+        name: Ident,
+        or_replace: bool,
+        /// Properties of secrets stored in the metastore, empty if `location` is set.
+        options: Vec<SqlOption>,
+        /// Location of the secret in an external secret store.
+        location: Option<String>,
+    },
+    DropSecret {
+        name: Ident,
+    },
+    /// See [crate::queryplanner::linked_servers].
+    CreateLinkedServer {
+        name: Ident,
+        or_replace: bool,
+        location: String,
+        options: Vec<SqlOption>,
+    },
+    DropLinkedServer {
+        name: Ident,
+    },
+    /// See [crate::queryplanner::pre_aggregations].
+    CreatePreAggregation {
+        name: ObjectName,
+        options: Vec<SqlOption>,
+        query: Box<Query>,
+    },
+    DropPreAggregation {
+        name: ObjectName,
+    },
+    /// See [crate::sql::temporary_functions].
+    CreateTemporaryFunction {
+        name: Ident,
+        or_replace: bool,
+        params: Vec<Ident>,
+        body: Expr,
+    },
+    DropTemporaryFunction {
+        name: Ident,
+    },
+    /// See [crate::config::dynamic].
+    SetGlobal {
+        name: Ident,
+        value: u64,
+    },
+    /// See [crate::queryplanner::read_snapshot].
+    SetTransactionSnapshot {
+        none: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SystemCommand {
+    CancelJob {
+        job_id: u64,
+    },
+    CompactTable {
+        table_name: ObjectName,
+    },
+    RepartitionTable {
+        table_name: ObjectName,
+    },
+    RepairTable {
+        table_name: ObjectName,
+    },
+    /// See [crate::config::dynamic].
+    ReloadConfig,
 }
 
 pub struct CubeStoreParser<'a> {
     parser: Parser<'a>,
+    hints: Vec<String>,
+    /// Kept to point at the position of syntax errors, see [CubeStoreParser::locate_error].
+    sql: String,
+    /// Tokens passed to the parser, without whitespace.
+    tokens_count: usize,
+}
+
+/// Name of the table hint that replaces `TABLESAMPLE SYSTEM (n PERCENT)`, as the SQL parser does
+/// not support sampling clauses. E.g. `FROM s.t AS t TABLESAMPLE SYSTEM (10 PERCENT)` is parsed as
+/// `FROM s.t AS t WITH (__tablesample(10))`.
+pub const TABLESAMPLE_HINT: &str = "__tablesample";
+
+/// Name of the function in `DEFAULT` that replaces expressions of generated columns, as the SQL
+/// parser does not support them. E.g. `day AS (DATE_TRUNC('day', ts)) STORED` is parsed as
+/// `day __generated DEFAULT __generated(DATE_TRUNC('day', ts))`. Columns without a type get
+/// [GENERATED_COLUMN_FUNCTION] as a type, it is inferred from the expression.
+pub const GENERATED_COLUMN_FUNCTION: &str = "__generated";
+
+/// Prefix of the type that replaces timestamps with precision, which the SQL parser does not
+/// support. E.g. `ts TIMESTAMP(3)` is parsed as `ts __timestamp_3`.
+pub const TIMESTAMP_WITH_PRECISION_TYPE: &str = "__timestamp_";
+
+/// Name of the function in `DEFAULT` that replaces column encodings, which the SQL parser does not
+/// support. E.g. `ts TIMESTAMP ENCODING DELTA` is parsed as `ts TIMESTAMP DEFAULT __encoding(DELTA)`.
+pub const COLUMN_ENCODING_FUNCTION: &str = "__encoding";
+
+/// Prefix of the type that replaces enums, followed by the JSON array of the values.
+/// E.g. `plan ENUM('free', 'pro')` is parsed as `plan __enum_["free","pro"]`.
+pub const ENUM_TYPE: &str = "__enum_";
+
+/// Parses a standalone expression, e.g. of a generated column.
+pub fn parse_expr(sql: &str) -> Result<Expr, ParserError> {
+    let dialect = &MySqlDialectWithBackTicks {};
+    let tokens = Tokenizer::new(dialect, sql).tokenize()?;
+    let mut parser = Parser::new(tokens, dialect);
+    let expr = parser.parse_expr()?;
+    match parser.peek_token() {
+        Token::EOF => Ok(expr),
+        t => Err(ParserError::ParserError(format!(
+            "Expected end of expression, found: {}",
+            t
+        ))),
+    }
 }
 
 impl<'a> CubeStoreParser<'a> {
     pub fn new(sql: &str) -> Result<Self, ParserError> {
+        Self::new_with_identifier_folding(sql, false)
+    }
+
+    /// With `fold_identifiers`, unquoted identifiers are converted to lower case, as in PostgreSQL,
+    /// so `SELECT UserId FROM Foo.Orders` reads column `userid` of table `foo.orders`. Names in
+    /// CREATE statements are folded the same way, quoted identifiers are kept as is.
+    pub fn new_with_identifier_folding(
+        sql: &str,
+        fold_identifiers: bool,
+    ) -> Result<Self, ParserError> {
         let dialect = &MySqlDialectWithBackTicks {};
         let mut tokenizer = Tokenizer::new(dialect, sql);
-        let tokens = tokenizer.tokenize()?;
+        let mut tokens = tokenizer.tokenize()?;
+        if fold_identifiers {
+            tokens = fold_unquoted_identifiers(tokens);
+        }
+        let tokens = rewrite_table_sample(tokens)?;
+        let tokens = rewrite_generated_columns(tokens)?;
+        let tokens = rewrite_column_types(tokens);
+        let hints = query_hints(&tokens);
+        let tokens_count = tokens.iter().filter(|t| !is_whitespace(t)).count();
         Ok(CubeStoreParser {
             parser: Parser::new(tokens, dialect),
+            hints,
+            sql: sql.to_string(),
+            tokens_count,
+        })
+    }
+
+    /// Whether the query has an optimizer hint comment with `name`, e.g. `/*+ NAME */`.
+    pub fn has_hint(&self, name: &str) -> bool {
+        self.hints.iter().any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// Value of the hint in the `NAME=value` form, e.g. `/*+ NAME=10 */`.
+    pub fn hint_value(&self, name: &str) -> Option<&str> {
+        self.hints.iter().find_map(|h| {
+            let mut parts = h.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(n), Some(v)) if n.eq_ignore_ascii_case(name) => Some(v),
+                _ => None,
+            }
         })
     }
 
@@ -60,22 +270,330 @@ impl<'a> CubeStoreParser<'a> {
                     self.parser.next_token();
                     self.parse_create()
                 }
+                Keyword::DROP => {
+                    self.parser.next_token();
+                    if self.parse_custom_token("prefetch") {
+                        let prefetch_id = self.parser.parse_literal_string()?;
+                        Ok(Statement::DropPrefetch { prefetch_id })
+                    } else if self.parse_custom_token("secret") {
+                        let name = self.parser.parse_identifier()?;
+                        Ok(Statement::DropSecret { name })
+                    } else if self.parse_custom_token("linked") {
+                        self.expect_custom_token("server")?;
+                        let name = self.parser.parse_identifier()?;
+                        Ok(Statement::DropLinkedServer { name })
+                    } else if self.parse_custom_token("pre_aggregation") {
+                        let name = self.parser.parse_object_name()?;
+                        Ok(Statement::DropPreAggregation { name })
+                    } else if self.parse_custom_token("temporary") {
+                        self.expect_custom_token("function")?;
+                        let name = self.parser.parse_identifier()?;
+                        Ok(Statement::DropTemporaryFunction { name })
+                    } else {
+                        self.parser.prev_token();
+                        Ok(Statement::Statement(self.parser.parse_statement()?))
+                    }
+                }
+                Keyword::SET => {
+                    self.parser.next_token();
+                    if self.parse_custom_token("global") {
+                        let name = self.parser.parse_identifier()?;
+                        self.parser.expect_token(&Token::Eq)?;
+                        let value = self.parser.parse_literal_uint()?;
+                        Ok(Statement::SetGlobal { name, value })
+                    } else if self.parse_custom_token("transaction") {
+                        self.expect_custom_token("snapshot")?;
+                        let none = self.parse_custom_token("none");
+                        Ok(Statement::SetTransactionSnapshot { none })
+                    } else {
+                        self.parser.prev_token();
+                        Ok(Statement::Statement(self.parser.parse_statement()?))
+                    }
+                }
+                _ if w.value.eq_ignore_ascii_case("system") => {
+                    self.parser.next_token();
+                    self.parse_system()
+                }
+                _ if w.value.eq_ignore_ascii_case("checksum") => {
+                    self.parser.next_token();
+                    self.parser.expect_keyword(Keyword::TABLE)?;
+                    let table_name = self.parser.parse_object_name()?;
+                    Ok(Statement::ChecksumTable { table_name })
+                }
+                _ if w.value.eq_ignore_ascii_case("optimize") => {
+                    self.parser.next_token();
+                    self.parser.expect_keyword(Keyword::TABLE)?;
+                    let table_name = self.parser.parse_object_name()?;
+                    let mut columns = Vec::new();
+                    if self.parse_custom_token("columns") {
+                        self.parser.expect_token(&Token::LParen)?;
+                        columns = self
+                            .parser
+                            .parse_comma_separated(Parser::parse_identifier)?;
+                        self.parser.expect_token(&Token::RParen)?;
+                    }
+                    Ok(Statement::OptimizeTable {
+                        table_name,
+                        columns,
+                    })
+                }
+                _ if w.value.eq_ignore_ascii_case("export") => {
+                    self.parser.next_token();
+                    if self.parser.parse_keyword(Keyword::TABLE) {
+                        let table_name = self.parser.parse_object_name()?;
+                        self.expect_custom_token("manifest")?;
+                        self.parser.expect_keyword(Keyword::TO)?;
+                        let location = self.parser.parse_literal_string()?;
+                        return Ok(Statement::ExportManifest {
+                            table_name,
+                            location,
+                        });
+                    }
+                    let query = Box::new(self.parser.parse_query()?);
+                    Ok(Statement::Export { query })
+                }
+                _ if w.value.eq_ignore_ascii_case("import") => {
+                    self.parser.next_token();
+                    self.parser.expect_keyword(Keyword::TABLE)?;
+                    let table_name = if self.parser.parse_keyword(Keyword::FROM) {
+                        None
+                    } else {
+                        let name = self.parser.parse_object_name()?;
+                        self.parser.expect_keyword(Keyword::FROM)?;
+                        Some(name)
+                    };
+                    self.expect_custom_token("manifest")?;
+                    let location = self.parser.parse_literal_string()?;
+                    Ok(Statement::ImportManifest {
+                        table_name,
+                        location,
+                    })
+                }
+                _ if w.value.eq_ignore_ascii_case("attach") => {
+                    self.parser.next_token();
+                    self.parser.expect_keyword(Keyword::TABLE)?;
+                    let table_name = self.parser.parse_object_name()?;
+                    self.parser.expect_keyword(Keyword::FROM)?;
+                    let location = self.parser.parse_literal_string()?;
+                    let (partitioned_by, format) = if self.parse_custom_token("format") {
+                        (Vec::new(), Some(self.parser.parse_identifier()?))
+                    } else {
+                        self.expect_custom_token("partitioned")?;
+                        self.parser.expect_keyword(Keyword::BY)?;
+                        self.parser.expect_token(&Token::LParen)?;
+                        let partitioned_by = self
+                            .parser
+                            .parse_comma_separated(Parser::parse_identifier)?;
+                        self.parser.expect_token(&Token::RParen)?;
+                        (partitioned_by, None)
+                    };
+                    Ok(Statement::AttachTable {
+                        table_name,
+                        location,
+                        partitioned_by,
+                        format,
+                    })
+                }
+                _ if w.value.eq_ignore_ascii_case("load") => {
+                    self.parser.next_token();
+                    self.parse_load_data()
+                }
+                _ if w.value.eq_ignore_ascii_case("fetch") => {
+                    self.parser.next_token();
+                    let query_id = self.parse_query_id()?;
+                    Ok(Statement::FetchQuery { query_id })
+                }
+                _ if w.value.eq_ignore_ascii_case("cancel") => {
+                    self.parser.next_token();
+                    let query_id = self.parse_query_id()?;
+                    Ok(Statement::CancelQuery { query_id })
+                }
+                _ if w.value.eq_ignore_ascii_case("kill") => {
+                    self.parser.next_token();
+                    self.parse_custom_token("connection");
+                    let connection_id = self.parser.parse_literal_uint()?;
+                    Ok(Statement::KillConnection { connection_id })
+                }
                 _ => Ok(Statement::Statement(self.parser.parse_statement()?)),
             },
             _ => Ok(Statement::Statement(self.parser.parse_statement()?)),
         }
     }
 
+    /// Parses the only statement of the query, optionally followed by semicolons. Errors point at
+    /// the position of the error, see [CubeStoreParser::locate_error].
+    pub fn parse_single_statement(&mut self) -> Result<Statement, CubeError> {
+        let statement = self
+            .parse_statement()
+            .and_then(|statement| {
+                while self.parser.consume_token(&Token::SemiColon) {}
+                match self.parser.peek_token() {
+                    Token::EOF => Ok(statement),
+                    t => Err(ParserError::ParserError(format!(
+                        "Expected end of statement, found: {}",
+                        t
+                    ))),
+                }
+            })
+            .map_err(|e| self.locate_error(e))?;
+        Ok(statement)
+    }
+
+    /// Adds the position of the error in the query, the fragment of the query around it and
+    /// keywords that were possibly misspelled to `error` returned by the parser. Consumes the
+    /// rest of the tokens, the parser can't be used afterwards.
+    pub fn locate_error(&mut self, error: ParserError) -> CubeError {
+        let message = match error {
+            ParserError::TokenizerError(m) => return tokenizer_error(&self.sql, &m),
+            ParserError::ParserError(m) => m,
+        };
+        let mut remaining = 0;
+        while self.parser.next_token() != Token::EOF {
+            remaining += 1;
+        }
+        let next = self.tokens_count.saturating_sub(remaining);
+        let tokens = match Tokenizer::new(&MySqlDialectWithBackTicks {}, &self.sql).tokenize() {
+            Ok(tokens) => token_offsets(&self.sql, tokens),
+            Err(_) => return CubeError::user(message),
+        };
+        // Errors are raised either on the next token or on the token that was just consumed.
+        let next = next.min(tokens.len());
+        let consumed_found = match message.find("found: ") {
+            Some(i) if next != 0 => tokens[next - 1]
+                .0
+                .to_string()
+                .eq_ignore_ascii_case(&message[i + 7..]),
+            _ => false,
+        };
+        let index = if consumed_found { next - 1 } else { next };
+        let offset = tokens.get(index).map_or(self.sql.len(), |(_, o)| *o);
+        let mut suggestions = Vec::new();
+        for (t, _) in tokens[index.saturating_sub(1)..tokens.len().min(index + 1)]
+            .iter()
+            .rev()
+        {
+            if let Token::Word(w) = t {
+                if w.keyword == Keyword::NoKeyword && w.quote_style.is_none() {
+                    if let Some(k) = nearest_keyword(&w.value) {
+                        suggestions.push(format!("{} instead of {}", k, w.value));
+                    }
+                }
+            }
+        }
+        located_error(&self.sql, offset, &message, &suggestions)
+    }
+
     pub fn parse_create(&mut self) -> Result<Statement, ParserError> {
+        if self.parser.parse_keywords(&[Keyword::OR, Keyword::REPLACE]) {
+            if self.parse_custom_token("secret") {
+                return self.parse_create_secret(true);
+            }
+            if self.parse_custom_token("linked") {
+                return self.parse_create_linked_server(true);
+            }
+            if self.parse_custom_token("temporary") {
+                if self.parse_custom_token("function") {
+                    return self.parse_create_temporary_function(true);
+                }
+                self.parser.prev_token();
+            }
+            // Other statements parse OR REPLACE themselves.
+            self.parser.prev_token();
+            self.parser.prev_token();
+        }
         if self.parser.parse_keyword(Keyword::SCHEMA) {
             self.parse_create_schema()
         } else if self.parser.parse_keyword(Keyword::TABLE) {
             self.parse_create_table()
+        } else if self.parse_custom_token("prefetch") {
+            let schedule = self.parser.parse_literal_string()?;
+            let query = self.parse_query_id()?;
+            Ok(Statement::CreatePrefetch { schedule, query })
+        } else if self.parse_custom_token("secret") {
+            self.parse_create_secret(false)
+        } else if self.parse_custom_token("linked") {
+            self.parse_create_linked_server(false)
+        } else if self.parse_custom_token("pre_aggregation") {
+            let name = self.parser.parse_object_name()?;
+            let options = self.parser.parse_with_options()?;
+            self.parser.expect_keyword(Keyword::AS)?;
+            let query = Box::new(self.parser.parse_query()?);
+            Ok(Statement::CreatePreAggregation {
+                name,
+                options,
+                query,
+            })
+        } else if self.parse_custom_token("temporary") {
+            if self.parse_custom_token("function") {
+                return self.parse_create_temporary_function(false);
+            }
+            self.parser.prev_token();
+            Ok(Statement::Statement(self.parser.parse_create()?))
         } else {
             Ok(Statement::Statement(self.parser.parse_create()?))
         }
     }
 
+    fn parse_create_temporary_function(
+        &mut self,
+        or_replace: bool,
+    ) -> Result<Statement, ParserError> {
+        let name = self.parser.parse_identifier()?;
+        let mut params = Vec::new();
+        if self.parser.consume_token(&Token::LParen) {
+            if !self.parser.consume_token(&Token::RParen) {
+                params = self
+                    .parser
+                    .parse_comma_separated(Parser::parse_identifier)?;
+                self.parser.expect_token(&Token::RParen)?;
+            }
+        }
+        self.parser.expect_keyword(Keyword::AS)?;
+        let body = self.parser.parse_expr()?;
+        Ok(Statement::CreateTemporaryFunction {
+            name,
+            or_replace,
+            params,
+            body,
+        })
+    }
+
+    fn parse_create_secret(&mut self, or_replace: bool) -> Result<Statement, ParserError> {
+        let name = self.parser.parse_identifier()?;
+        let (options, location) = if self.parser.parse_keyword(Keyword::FROM) {
+            (Vec::new(), Some(self.parser.parse_literal_string()?))
+        } else {
+            let options = self.parser.parse_with_options()?;
+            if options.is_empty() {
+                return Err(ParserError::ParserError(format!(
+                    "Expected WITH or FROM, found: {}",
+                    self.parser.peek_token()
+                )));
+            }
+            (options, None)
+        };
+        Ok(Statement::CreateSecret {
+            name,
+            or_replace,
+            options,
+            location,
+        })
+    }
+
+    fn parse_create_linked_server(&mut self, or_replace: bool) -> Result<Statement, ParserError> {
+        self.expect_custom_token("server")?;
+        let name = self.parser.parse_identifier()?;
+        self.expect_custom_token("location")?;
+        let location = self.parser.parse_literal_string()?;
+        let options = self.parser.parse_with_options()?;
+        Ok(Statement::CreateLinkedServer {
+            name,
+            or_replace,
+            location,
+            options,
+        })
+    }
+
     pub fn parse_create_table(&mut self) -> Result<Statement, ParserError> {
         // Note that we disable hive extensions as they clash with `location`.
         let statement = self.parser.parse_create_table_ext(false, false, false)?;
@@ -155,6 +673,138 @@ impl<'a> CubeStoreParser<'a> {
         })
     }
 
+    fn parse_system(&mut self) -> Result<Statement, ParserError> {
+        let command = if self.parse_custom_token("cancel") {
+            if !self.parse_custom_token("job") {
+                return Err(ParserError::ParserError(format!(
+                    "Expected JOB, found: {}",
+                    self.parser.peek_token()
+                )));
+            }
+            let job_id = self.parser.parse_literal_uint()?;
+            SystemCommand::CancelJob { job_id }
+        } else if self.parse_custom_token("compact") {
+            self.parser.expect_keyword(Keyword::TABLE)?;
+            let table_name = self.parser.parse_object_name()?;
+            SystemCommand::CompactTable { table_name }
+        } else if self.parse_custom_token("repartition") {
+            self.parser.expect_keyword(Keyword::TABLE)?;
+            let table_name = self.parser.parse_object_name()?;
+            SystemCommand::RepartitionTable { table_name }
+        } else if self.parse_custom_token("repair") {
+            self.parser.expect_keyword(Keyword::TABLE)?;
+            let table_name = self.parser.parse_object_name()?;
+            SystemCommand::RepairTable { table_name }
+        } else if self.parse_custom_token("reload") {
+            self.expect_custom_token("config")?;
+            SystemCommand::ReloadConfig
+        } else {
+            return Err(ParserError::ParserError(format!(
+                "Expected CANCEL, COMPACT, REPARTITION, REPAIR or RELOAD, found: {}",
+                self.parser.peek_token()
+            )));
+        };
+        Ok(Statement::System(command))
+    }
+
+    /// `LOAD DATA [LOCAL] INFILE '<location>' INTO TABLE <name>`, optionally followed by the
+    /// MySQL clauses of the only supported CSV dialect, `IGNORE <n> LINES` and the column list.
+    fn parse_load_data(&mut self) -> Result<Statement, ParserError> {
+        self.expect_custom_token("data")?;
+        let local = self.parse_custom_token("local");
+        self.expect_custom_token("infile")?;
+        let location = self.parser.parse_literal_string()?;
+        self.parser.expect_keyword(Keyword::INTO)?;
+        self.parser.expect_keyword(Keyword::TABLE)?;
+        let table_name = self.parser.parse_object_name()?;
+        if self.parse_custom_token("fields") || self.parse_custom_token("columns") {
+            if self.parse_custom_token("terminated") {
+                self.parser.expect_keyword(Keyword::BY)?;
+                self.expect_load_data_literal("FIELDS TERMINATED BY", &[","])?;
+            }
+            self.parse_custom_token("optionally");
+            if self.parse_custom_token("enclosed") {
+                self.parser.expect_keyword(Keyword::BY)?;
+                self.expect_load_data_literal("ENCLOSED BY", &["\"", ""])?;
+            }
+        }
+        if self.parse_custom_token("lines") {
+            self.expect_custom_token("terminated")?;
+            self.parser.expect_keyword(Keyword::BY)?;
+            self.expect_load_data_literal("LINES TERMINATED BY", &["\\n", "\n", "\\r\\n", "\r\n"])?;
+        }
+        let mut ignore_lines = 0;
+        if self.parse_custom_token("ignore") {
+            ignore_lines = self.parser.parse_literal_uint()?;
+            if !self.parse_custom_token("lines") {
+                self.expect_custom_token("rows")?;
+            }
+        }
+        let mut columns = Vec::new();
+        if self.parser.consume_token(&Token::LParen) {
+            columns = self
+                .parser
+                .parse_comma_separated(Parser::parse_identifier)?;
+            self.parser.expect_token(&Token::RParen)?;
+        }
+        Ok(Statement::LoadData {
+            local,
+            location,
+            table_name,
+            ignore_lines,
+            columns,
+        })
+    }
+
+    fn expect_load_data_literal(
+        &mut self,
+        clause: &str,
+        supported: &[&str],
+    ) -> Result<(), ParserError> {
+        let value = self.parser.parse_literal_string()?;
+        if supported.contains(&value.as_str()) {
+            Ok(())
+        } else {
+            Err(ParserError::ParserError(format!(
+                "Only '{}' is supported in {}, found: '{}'",
+                supported[0], clause, value
+            )))
+        }
+    }
+
+    /// `QUERY '<id>'` of statements on submitted queries, `QUERY '<text>'` of prefetches.
+    fn parse_query_id(&mut self) -> Result<String, ParserError> {
+        if !self.parse_custom_token("query") {
+            return Err(ParserError::ParserError(format!(
+                "Expected QUERY, found: {}",
+                self.parser.peek_token()
+            )));
+        }
+        self.parser.parse_literal_string()
+    }
+
+    fn parse_custom_token(&mut self, token: &str) -> bool {
+        if let Token::Word(w) = self.parser.peek_token() {
+            if w.value.eq_ignore_ascii_case(token) {
+                self.parser.next_token();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn expect_custom_token(&mut self, token: &str) -> Result<(), ParserError> {
+        if self.parse_custom_token(token) {
+            Ok(())
+        } else {
+            Err(ParserError::ParserError(format!(
+                "Expected {}, found: {}",
+                token.to_uppercase(),
+                self.parser.peek_token()
+            )))
+        }
+    }
+
     fn parse_create_schema(&mut self) -> Result<Statement, ParserError> {
         let if_not_exists =
             self.parser
@@ -166,3 +816,1086 @@ impl<'a> CubeStoreParser<'a> {
         })
     }
 }
+
+/// Returns the statement of `SUBMIT <statement>`, see [crate::sql::submitted_queries]. It is
+/// extracted from the text, so it can be executed as any other query.
+pub fn submitted_statement(query: &str) -> Option<&str> {
+    let query = query.trim_start();
+    let keyword = query.get(0..6)?;
+    let rest = &query[6..];
+    if keyword.eq_ignore_ascii_case("submit") && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim())
+    } else {
+        None
+    }
+}
+
+fn query_hints(tokens: &[Token]) -> Vec<String> {
+    let mut hints = Vec::new();
+    for t in tokens {
+        if let Token::Whitespace(w) = t {
+            let comment = w.to_string();
+            if comment.starts_with("/*+") && comment.ends_with("*/") {
+                let comment = &comment[3..comment.len() - 2];
+                hints.extend(comment.split_whitespace().map(|h| h.to_string()));
+            }
+        }
+    }
+    hints
+}
+
+fn rewrite_table_sample(tokens: Vec<Token>) -> Result<Vec<Token>, ParserError> {
+    fn is_word(t: &Token, value: &str) -> bool {
+        match t {
+            Token::Word(w) => w.value.eq_ignore_ascii_case(value),
+            _ => false,
+        }
+    }
+    fn next(tokens: &mut impl Iterator<Item = Token>) -> Option<Token> {
+        tokens.find(|t| !matches!(t, Token::Whitespace(_)))
+    }
+    fn expected(found: Option<Token>, what: &str) -> ParserError {
+        ParserError::ParserError(format!(
+            "Expected {} in TABLESAMPLE, found: {}",
+            what,
+            found.map(|t| t.to_string()).unwrap_or("EOF".to_string())
+        ))
+    }
+
+    let mut r = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter();
+    while let Some(t) = tokens.next() {
+        if !is_word(&t, "TABLESAMPLE") {
+            r.push(t);
+            continue;
+        }
+        match next(&mut tokens) {
+            Some(t) if is_word(&t, "SYSTEM") => {}
+            t => return Err(expected(t, "SYSTEM")),
+        }
+        match next(&mut tokens) {
+            Some(Token::LParen) => {}
+            t => return Err(expected(t, "(")),
+        }
+        let percent = match next(&mut tokens) {
+            Some(t @ Token::Number(..)) => t,
+            t => return Err(expected(t, "sampling percentage")),
+        };
+        match next(&mut tokens) {
+            Some(t) if is_word(&t, "PERCENT") => match next(&mut tokens) {
+                Some(Token::RParen) => {}
+                t => return Err(expected(t, ")")),
+            },
+            Some(Token::RParen) => {}
+            t => return Err(expected(t, ")")),
+        }
+        r.extend(vec![
+            Token::make_keyword("WITH"),
+            Token::LParen,
+            Token::make_word(TABLESAMPLE_HINT, None),
+            Token::LParen,
+            percent,
+            Token::RParen,
+            Token::RParen,
+        ]);
+    }
+    Ok(r)
+}
+
+fn fold_unquoted_identifiers(tokens: Vec<Token>) -> Vec<Token> {
+    tokens
+        .into_iter()
+        .map(|t| match t {
+            Token::Word(mut w) if w.quote_style.is_none() => {
+                w.value = w.value.to_lowercase();
+                Token::Word(w)
+            }
+            t => t,
+        })
+        .collect()
+}
+
+/// See [GENERATED_COLUMN_FUNCTION]. Only the column list of CREATE TABLE is rewritten.
+fn rewrite_generated_columns(tokens: Vec<Token>) -> Result<Vec<Token>, ParserError> {
+    let mut words = tokens.iter().filter(|t| !is_whitespace(t));
+    if !(words.next().map_or(false, |t| is_word(t, "CREATE"))
+        && words.next().map_or(false, |t| is_word(t, "TABLE")))
+    {
+        return Ok(tokens);
+    }
+
+    let mut r = Vec::with_capacity(tokens.len());
+    let mut tokens = tokens.into_iter().peekable();
+    // Table name and options before the column list. There is no column list in
+    // `CREATE TABLE ... AS SELECT`.
+    while let Some(t) = tokens.next() {
+        let starts_columns = t == Token::LParen;
+        let is_as = is_word(&t, "AS");
+        r.push(t);
+        if is_as {
+            r.extend(tokens);
+            return Ok(r);
+        }
+        if starts_columns {
+            break;
+        }
+    }
+
+    let mut depth = 1;
+    // Non-whitespace tokens of the current column definition.
+    let mut column_tokens = 0;
+    while let Some(t) = tokens.next() {
+        if depth == 1 && is_word(&t, "AS") {
+            while tokens.peek().map_or(false, is_whitespace) {
+                tokens.next();
+            }
+            if tokens.peek() != Some(&Token::LParen) {
+                return Err(ParserError::ParserError(
+                    "Expected ( after AS in generated column".to_string(),
+                ));
+            }
+            // `GENERATED ALWAYS AS` is the same as `AS`.
+            let previous = r
+                .iter()
+                .rev()
+                .filter(|t| !is_whitespace(t))
+                .take(2)
+                .collect::<Vec<_>>();
+            if previous.len() == 2
+                && is_word(previous[0], "ALWAYS")
+                && is_word(previous[1], "GENERATED")
+            {
+                for _ in 0..2 {
+                    while r.last().map_or(false, is_whitespace) {
+                        r.pop();
+                    }
+                    r.pop();
+                }
+                column_tokens = column_tokens.saturating_sub(2);
+            }
+            if column_tokens == 1 {
+                r.push(Token::Whitespace(Whitespace::Space));
+                r.push(Token::make_word(GENERATED_COLUMN_FUNCTION, None));
+            }
+            r.extend(vec![
+                Token::Whitespace(Whitespace::Space),
+                Token::make_keyword("DEFAULT"),
+                Token::Whitespace(Whitespace::Space),
+                Token::make_word(GENERATED_COLUMN_FUNCTION, None),
+            ]);
+            let mut expr_depth = 0;
+            while let Some(t) = tokens.next() {
+                match t {
+                    Token::LParen => expr_depth += 1,
+                    Token::RParen => expr_depth -= 1,
+                    Token::EOF => break,
+                    _ => {}
+                }
+                r.push(t);
+                if expr_depth == 0 {
+                    break;
+                }
+            }
+            while tokens.peek().map_or(false, is_whitespace) {
+                tokens.next();
+            }
+            match tokens.peek() {
+                Some(t) if is_word(t, "STORED") => {
+                    tokens.next();
+                }
+                Some(t) if is_word(t, "VIRTUAL") => {
+                    return Err(ParserError::ParserError(
+                        "Only STORED generated columns are supported".to_string(),
+                    ))
+                }
+                _ => {}
+            }
+            column_tokens += 1;
+            continue;
+        }
+        match t {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            _ => {}
+        }
+        if depth == 1 && t == Token::Comma {
+            column_tokens = 0;
+        } else if !is_whitespace(&t) {
+            column_tokens += 1;
+        }
+        r.push(t);
+        if depth == 0 {
+            break;
+        }
+    }
+    r.extend(tokens);
+    Ok(r)
+}
+
+/// See [TIMESTAMP_WITH_PRECISION_TYPE] and [ENUM_TYPE]. Only types in the column list of
+/// CREATE TABLE are rewritten.
+fn rewrite_column_types(tokens: Vec<Token>) -> Vec<Token> {
+    let mut words = tokens.iter().filter(|t| !is_whitespace(t));
+    if !(words.next().map_or(false, |t| is_word(t, "CREATE"))
+        && words.next().map_or(false, |t| is_word(t, "TABLE")))
+    {
+        return tokens;
+    }
+
+    let mut r = Vec::with_capacity(tokens.len());
+    let mut depth = 0;
+    // Non-whitespace tokens of the current column definition.
+    let mut column_tokens = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let t = &tokens[i];
+        i += 1;
+        match t {
+            Token::LParen => depth += 1,
+            Token::RParen => depth -= 1,
+            Token::Comma if depth == 1 => {
+                column_tokens = 0;
+                r.push(t.clone());
+                continue;
+            }
+            t if depth == 0 && is_word(t, "AS") => {
+                // `CREATE TABLE ... AS SELECT` has no column list.
+                r.extend(tokens[i - 1..].iter().cloned());
+                return r;
+            }
+            Token::Word(w) if depth == 1 && w.quote_style.is_none() && is_word(t, "TIMESTAMP") => {
+                let rest = tokens[i..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| !is_whitespace(t))
+                    .take(3)
+                    .collect::<Vec<_>>();
+                if let [(_, Token::LParen), (_, Token::Number(n, ..)), (end, Token::RParen)] =
+                    rest.as_slice()
+                {
+                    r.push(Token::make_word(
+                        &format!("{}{}", TIMESTAMP_WITH_PRECISION_TYPE, n),
+                        None,
+                    ));
+                    i += end + 1;
+                    continue;
+                }
+            }
+            // The name of the column might be `encoding` too.
+            Token::Word(w)
+                if depth == 1
+                    && column_tokens != 0
+                    && w.quote_style.is_none()
+                    && is_word(t, "ENCODING") =>
+            {
+                let next = tokens[i..]
+                    .iter()
+                    .enumerate()
+                    .find(|(_, t)| !is_whitespace(t));
+                if let Some((k, Token::Word(encoding))) = next {
+                    r.extend(vec![
+                        Token::make_keyword("DEFAULT"),
+                        Token::Whitespace(Whitespace::Space),
+                        Token::make_word(COLUMN_ENCODING_FUNCTION, None),
+                        Token::LParen,
+                        Token::make_word(&encoding.value, None),
+                        Token::RParen,
+                    ]);
+                    column_tokens += 2;
+                    i += k + 1;
+                    continue;
+                }
+            }
+            Token::Word(w) if depth == 1 && w.quote_style.is_none() && is_word(t, "ENUM") => {
+                let rest = tokens[i..]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| !is_whitespace(t));
+                let mut values = Vec::new();
+                let mut end = None;
+                for (j, (k, t)) in rest.enumerate() {
+                    match (j, t) {
+                        (0, Token::LParen) => {}
+                        (j, Token::SingleQuotedString(s)) if j % 2 == 1 => values.push(s.clone()),
+                        (j, Token::Comma) if j % 2 == 0 => {}
+                        (j, Token::RParen) if j % 2 == 0 && !values.is_empty() => {
+                            end = Some(k);
+                            break;
+                        }
+                        _ => break,
+                    }
+                }
+                if let Some(end) = end {
+                    r.push(Token::make_word(
+                        &format!("{}{}", ENUM_TYPE, serde_json::to_string(&values).unwrap()),
+                        None,
+                    ));
+                    i += end + 1;
+                    continue;
+                }
+            }
+            _ => {}
+        }
+        if depth == 1 && !is_whitespace(t) && *t != Token::LParen {
+            column_tokens += 1;
+        }
+        r.push(t.clone());
+    }
+    r
+}
+
+fn is_word(t: &Token, value: &str) -> bool {
+    match t {
+        Token::Word(w) => w.value.eq_ignore_ascii_case(value),
+        _ => false,
+    }
+}
+
+/// Error of [CubeStoreParser::new], e.g. on an unterminated string. Errors of parsing statements
+/// are returned by [CubeStoreParser::locate_error].
+pub fn syntax_error(sql: &str, error: ParserError) -> CubeError {
+    match error {
+        ParserError::TokenizerError(m) => tokenizer_error(sql, &m),
+        ParserError::ParserError(m) => CubeError::user(m),
+    }
+}
+
+fn tokenizer_error(sql: &str, message: &str) -> CubeError {
+    // The tokenizer reports positions as "<message> at Line: 1, Column 5".
+    let position = message.rfind(" at Line: ").and_then(|i| {
+        let mut parts = message[i + 10..].splitn(2, ", Column ");
+        let line = parts.next()?.trim().parse::<usize>().ok()?;
+        let column = parts.next()?.trim().parse::<usize>().ok()?;
+        Some((i, line, column))
+    });
+    match position {
+        Some((i, line, column)) => {
+            let offset = sql
+                .split_inclusive('\n')
+                .take(line.saturating_sub(1))
+                .map(|l| l.len())
+                .sum::<usize>()
+                + sql
+                    .split('\n')
+                    .nth(line.saturating_sub(1))
+                    .unwrap_or("")
+                    .chars()
+                    .take(column.saturating_sub(1))
+                    .map(|c| c.len_utf8())
+                    .sum::<usize>();
+            located_error(sql, offset.min(sql.len()), &message[..i], &[])
+        }
+        None => CubeError::user(message.to_string()),
+    }
+}
+
+/// Keywords that are suggested for misspelled words.
+const SUGGESTED_KEYWORDS: &[&str] = &[
+    "ALL", "AND", "ASC", "BETWEEN", "CASE", "CREATE", "CROSS", "DESC", "DISTINCT", "DROP", "ELSE",
+    "END", "EXISTS", "FROM", "FULL", "GROUP", "HAVING", "INNER", "INSERT", "INTERVAL", "INTO",
+    "JOIN", "LEFT", "LIKE", "LIMIT", "NULL", "OFFSET", "ORDER", "OUTER", "RIGHT", "SCHEMA",
+    "SELECT", "TABLE", "THEN", "UNION", "USING", "VALUES", "WHEN", "WHERE", "WITH",
+];
+
+/// Keyword the word is likely a misspelling of, if any.
+fn nearest_keyword(word: &str) -> Option<&'static str> {
+    if word.len() < 3 {
+        return None;
+    }
+    let word = word.to_uppercase();
+    let max_distance = if word.len() < 5 { 1 } else { 2 };
+    SUGGESTED_KEYWORDS
+        .iter()
+        .map(|k| (edit_distance(&word, k), *k))
+        .filter(|(d, _)| *d <= max_distance)
+        .min()
+        .map(|(_, k)| k)
+}
+
+/// Edit distance where swapping adjacent characters counts as a single edit, e.g. FORM and FROM
+/// are 1 edit apart.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        d[i][0] = i;
+    }
+    for j in 0..=b.len() {
+        d[0][j] = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if 1 < i && 1 < j && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+/// Tokens other than whitespace with their byte offsets in `sql`.
+fn token_offsets(sql: &str, tokens: Vec<Token>) -> Vec<(Token, usize)> {
+    let mut offset = 0;
+    let mut result = Vec::new();
+    for t in tokens {
+        let text = t.to_string();
+        let rest = &sql[offset..];
+        let len = match &t {
+            // Escaped quotes make strings longer in the query than they are printed.
+            Token::SingleQuotedString(_)
+            | Token::NationalStringLiteral(_)
+            | Token::HexStringLiteral(_) => {
+                let start = rest.find('\'').unwrap_or(0);
+                let mut end = start + 1;
+                let bytes = rest.as_bytes();
+                while end < bytes.len() {
+                    if bytes[end] == b'\'' {
+                        if bytes.get(end + 1) == Some(&b'\'') {
+                            end += 2;
+                            continue;
+                        }
+                        break;
+                    }
+                    end += 1;
+                }
+                (end + 1).min(rest.len())
+            }
+            _ => text.len().min(rest.len()),
+        };
+        if !is_whitespace(&t) {
+            result.push((t, offset));
+        }
+        offset += len;
+        while !sql.is_char_boundary(offset) {
+            offset += 1;
+        }
+    }
+    result
+}
+
+/// Longest fragment of a line shown in errors, queries generated by Cube.js are often a single
+/// line of several kilobytes.
+const ERROR_FRAGMENT_CHARS: usize = 80;
+
+fn located_error(sql: &str, offset: usize, message: &str, suggestions: &[String]) -> CubeError {
+    let line_start = sql[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = sql[offset..].find('\n').map_or(sql.len(), |i| offset + i);
+    let line = sql[..offset].matches('\n').count() + 1;
+    let column = sql[line_start..offset].chars().count() + 1;
+
+    let line_text = &sql[line_start..line_end];
+    let chars = line_text.chars().collect::<Vec<_>>();
+    let start = (column - 1).saturating_sub(ERROR_FRAGMENT_CHARS / 3);
+    let end = chars.len().min(start + ERROR_FRAGMENT_CHARS);
+    let prefix = if start == 0 { "" } else { "..." };
+    let suffix = if end == chars.len() { "" } else { "..." };
+    let fragment = chars[start..end].iter().collect::<String>();
+    let caret = " ".repeat(prefix.len() + column - 1 - start);
+
+    let mut error = format!(
+        "Syntax error at line {}, column {}: {}\n{}{}{}\n{}^",
+        line, column, message, prefix, fragment, suffix, caret
+    );
+    if !suggestions.is_empty() {
+        error += &format!("\nDid you mean {}?", suggestions.join(" or "));
+    }
+    CubeError::user(error)
+}
+
+fn is_whitespace(t: &Token) -> bool {
+    matches!(t, Token::Whitespace(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints() {
+        let p =
+            CubeStoreParser::new("/*+ NO_SCAN_LIMITS */ SELECT * /* comment */ FROM s.t").unwrap();
+        assert!(p.has_hint("no_scan_limits"));
+        assert!(!p.has_hint("comment"));
+        let p = CubeStoreParser::new("SELECT * FROM s.t -- NO_SCAN_LIMITS").unwrap();
+        assert!(!p.has_hint("NO_SCAN_LIMITS"));
+        let p = CubeStoreParser::new("SELECT /*+ batch_size=128 */ * FROM s.t").unwrap();
+        assert_eq!(p.hint_value("BATCH_SIZE"), Some("128"));
+        assert!(!p.has_hint("BATCH_SIZE"));
+        assert_eq!(p.hint_value("NO_SCAN_LIMITS"), None);
+    }
+
+    #[test]
+    fn submitted_queries() {
+        assert_eq!(
+            submitted_statement(" submit\nSELECT * FROM s.t "),
+            Some("SELECT * FROM s.t")
+        );
+        assert_eq!(submitted_statement("SUBMITTED"), None);
+        assert_eq!(submitted_statement("SELECT 1"), None);
+
+        let statement = CubeStoreParser::new("FETCH QUERY 'abc'")
+            .unwrap()
+            .parse_statement()
+            .unwrap();
+        assert_eq!(
+            statement,
+            Statement::FetchQuery {
+                query_id: "abc".to_string()
+            }
+        );
+        let statement = CubeStoreParser::new("cancel query 'abc'")
+            .unwrap()
+            .parse_statement()
+            .unwrap();
+        assert_eq!(
+            statement,
+            Statement::CancelQuery {
+                query_id: "abc".to_string()
+            }
+        );
+        assert!(CubeStoreParser::new("CANCEL 'abc'")
+            .unwrap()
+            .parse_statement()
+            .is_err());
+    }
+
+    #[test]
+    fn kill_connection() {
+        for sql in &["KILL 1622541600000001", "kill connection 1622541600000001"] {
+            assert_eq!(
+                CubeStoreParser::new(sql)
+                    .unwrap()
+                    .parse_statement()
+                    .unwrap(),
+                Statement::KillConnection {
+                    connection_id: 1622541600000001
+                }
+            );
+        }
+        assert!(CubeStoreParser::new("KILL QUERY 1")
+            .unwrap()
+            .parse_statement()
+            .is_err());
+    }
+
+    #[test]
+    fn prefetch_statements() {
+        let statement = CubeStoreParser::new(
+            "CREATE PREFETCH '0 9 * * *' QUERY 'SELECT * FROM s.t WHERE a = ''x'''",
+        )
+        .unwrap()
+        .parse_statement()
+        .unwrap();
+        assert_eq!(
+            statement,
+            Statement::CreatePrefetch {
+                schedule: "0 9 * * *".to_string(),
+                query: "SELECT * FROM s.t WHERE a = 'x'".to_string(),
+            }
+        );
+        let statement = CubeStoreParser::new("drop prefetch 'abc'")
+            .unwrap()
+            .parse_statement()
+            .unwrap();
+        assert_eq!(
+            statement,
+            Statement::DropPrefetch {
+                prefetch_id: "abc".to_string()
+            }
+        );
+        let statement = CubeStoreParser::new("DROP TABLE s.t")
+            .unwrap()
+            .parse_statement()
+            .unwrap();
+        assert!(matches!(
+            statement,
+            Statement::Statement(SQLStatement::Drop { .. })
+        ));
+    }
+
+    #[test]
+    fn secret_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        match parse("CREATE SECRET pg WITH (user = 'u', password = 'p')").unwrap() {
+            Statement::CreateSecret {
+                name,
+                or_replace,
+                options,
+                location,
+            } => {
+                assert_eq!(name, Ident::new("pg"));
+                assert!(!or_replace);
+                assert_eq!(
+                    options
+                        .iter()
+                        .map(|o| (o.name.value.as_str(), o.value.to_string()))
+                        .collect::<Vec<_>>(),
+                    vec![("user", "'u'".to_string()), ("password", "'p'".to_string())]
+                );
+                assert_eq!(location, None);
+            }
+            s => panic!("unexpected statement: {:?}", s),
+        }
+        assert_eq!(
+            parse("create or replace secret pg from 'vault://secret/data/pg'").unwrap(),
+            Statement::CreateSecret {
+                name: Ident::new("pg"),
+                or_replace: true,
+                options: Vec::new(),
+                location: Some("vault://secret/data/pg".to_string()),
+            }
+        );
+        assert_eq!(
+            parse("DROP SECRET pg").unwrap(),
+            Statement::DropSecret {
+                name: Ident::new("pg")
+            }
+        );
+        assert!(parse("CREATE SECRET pg").is_err());
+        assert!(matches!(
+            parse("CREATE OR REPLACE VIEW s.v AS SELECT 1").unwrap(),
+            Statement::Statement(SQLStatement::CreateView { .. })
+        ));
+    }
+
+    #[test]
+    fn linked_server_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        match parse("CREATE LINKED SERVER crm LOCATION 'postgres://host/crm' WITH (max_rows = 100)")
+            .unwrap()
+        {
+            Statement::CreateLinkedServer {
+                name,
+                or_replace,
+                location,
+                options,
+            } => {
+                assert_eq!(name, Ident::new("crm"));
+                assert!(!or_replace);
+                assert_eq!(location, "postgres://host/crm");
+                assert_eq!(options.len(), 1);
+                assert_eq!(options[0].name, Ident::new("max_rows"));
+                assert_eq!(options[0].value.to_string(), "100");
+            }
+            s => panic!("unexpected statement: {:?}", s),
+        }
+        assert_eq!(
+            parse("create or replace linked server crm location 'mysql://host/crm'").unwrap(),
+            Statement::CreateLinkedServer {
+                name: Ident::new("crm"),
+                or_replace: true,
+                location: "mysql://host/crm".to_string(),
+                options: Vec::new(),
+            }
+        );
+        assert_eq!(
+            parse("DROP LINKED SERVER crm").unwrap(),
+            Statement::DropLinkedServer {
+                name: Ident::new("crm")
+            }
+        );
+        assert!(parse("CREATE LINKED SERVER crm").is_err());
+        assert!(parse("CREATE LINKED crm LOCATION 'mysql://host/crm'").is_err());
+    }
+
+    #[test]
+    fn pre_aggregation_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        match parse(
+            "CREATE PRE_AGGREGATION s.by_day WITH (partition_granularity = 'month') \
+             AS SELECT day, sum(amount) AS amount FROM s.orders GROUP BY 1",
+        )
+        .unwrap()
+        {
+            Statement::CreatePreAggregation {
+                name,
+                options,
+                query,
+            } => {
+                assert_eq!(name.to_string(), "s.by_day");
+                assert_eq!(options.len(), 1);
+                assert_eq!(options[0].name, Ident::new("partition_granularity"));
+                assert_eq!(
+                    query.to_string(),
+                    "SELECT day, sum(amount) AS amount FROM s.orders GROUP BY 1"
+                );
+            }
+            s => panic!("unexpected statement: {:?}", s),
+        }
+        assert!(matches!(
+            parse("create pre_aggregation s.by_day as select 1").unwrap(),
+            Statement::CreatePreAggregation { .. }
+        ));
+        assert_eq!(
+            parse("DROP PRE_AGGREGATION s.by_day").unwrap(),
+            Statement::DropPreAggregation {
+                name: ObjectName(vec![Ident::new("s"), Ident::new("by_day")])
+            }
+        );
+        assert!(parse("CREATE PRE_AGGREGATION s.by_day SELECT 1").is_err());
+    }
+
+    #[test]
+    fn temporary_function_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        match parse("CREATE TEMPORARY FUNCTION bucket(v, size) AS floor(v / size) * size").unwrap()
+        {
+            Statement::CreateTemporaryFunction {
+                name,
+                or_replace,
+                params,
+                body,
+            } => {
+                assert_eq!(name, Ident::new("bucket"));
+                assert!(!or_replace);
+                assert_eq!(params, vec![Ident::new("v"), Ident::new("size")]);
+                assert_eq!(body.to_string(), "floor(v / size) * size");
+            }
+            s => panic!("unexpected statement: {:?}", s),
+        }
+        match parse("create or replace temporary function now_utc() as now()").unwrap() {
+            Statement::CreateTemporaryFunction {
+                or_replace, params, ..
+            } => {
+                assert!(or_replace);
+                assert!(params.is_empty());
+            }
+            s => panic!("unexpected statement: {:?}", s),
+        }
+        assert_eq!(
+            parse("DROP TEMPORARY FUNCTION bucket").unwrap(),
+            Statement::DropTemporaryFunction {
+                name: Ident::new("bucket")
+            }
+        );
+        assert!(parse("CREATE TEMPORARY FUNCTION f(v) v").is_err());
+        assert!(parse("DROP TEMPORARY TABLE t").is_err());
+    }
+
+    #[test]
+    fn config_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        assert_eq!(
+            parse("SET GLOBAL max_rows_per_query = 1000").unwrap(),
+            Statement::SetGlobal {
+                name: Ident::new("max_rows_per_query"),
+                value: 1000,
+            }
+        );
+        assert!(parse("SET GLOBAL query_timeout = 'x'").is_err());
+        assert_eq!(
+            parse("SET TRANSACTION SNAPSHOT").unwrap(),
+            Statement::SetTransactionSnapshot { none: false }
+        );
+        assert_eq!(
+            parse("set transaction snapshot none").unwrap(),
+            Statement::SetTransactionSnapshot { none: true }
+        );
+        assert!(matches!(
+            parse("SET query_priority = 'low'").unwrap(),
+            Statement::Statement(SQLStatement::SetVariable { .. })
+        ));
+        assert_eq!(
+            parse("system reload config").unwrap(),
+            Statement::System(SystemCommand::ReloadConfig)
+        );
+        assert!(parse("SYSTEM RELOAD").is_err());
+    }
+
+    #[test]
+    fn manifest_statements() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        assert_eq!(
+            parse("EXPORT TABLE s.t MANIFEST TO 's3://bucket/t.json'").unwrap(),
+            Statement::ExportManifest {
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("t")]),
+                location: "s3://bucket/t.json".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("import table s.copy from manifest 't.json'").unwrap(),
+            Statement::ImportManifest {
+                table_name: Some(ObjectName(vec![Ident::new("s"), Ident::new("copy")])),
+                location: "t.json".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("IMPORT TABLE FROM MANIFEST 't.json'").unwrap(),
+            Statement::ImportManifest {
+                table_name: None,
+                location: "t.json".to_string(),
+            }
+        );
+        assert!(parse("EXPORT TABLE s.t TO 't.json'").is_err());
+        assert!(matches!(
+            parse("EXPORT SELECT * FROM s.t").unwrap(),
+            Statement::Export { .. }
+        ));
+    }
+
+    #[test]
+    fn optimize_table() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        assert_eq!(
+            parse("OPTIMIZE TABLE s.events").unwrap(),
+            Statement::OptimizeTable {
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                columns: Vec::new(),
+            }
+        );
+        assert_eq!(
+            parse("optimize table s.events columns (ts, payload)").unwrap(),
+            Statement::OptimizeTable {
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                columns: vec![Ident::new("ts"), Ident::new("payload")],
+            }
+        );
+        assert!(parse("OPTIMIZE s.events").is_err());
+        assert!(parse("OPTIMIZE TABLE s.events COLUMNS ()").is_err());
+    }
+
+    #[test]
+    fn load_data() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        assert_eq!(
+            parse("LOAD DATA LOCAL INFILE '/tmp/events.csv' INTO TABLE s.events").unwrap(),
+            Statement::LoadData {
+                local: true,
+                location: "/tmp/events.csv".to_string(),
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                ignore_lines: 0,
+                columns: Vec::new(),
+            }
+        );
+        assert_eq!(
+            parse(
+                "load data infile 'temp://events.csv' into table s.events \
+                 fields terminated by ',' optionally enclosed by '\"' \
+                 lines terminated by '\\n' ignore 1 lines (id, name)"
+            )
+            .unwrap(),
+            Statement::LoadData {
+                local: false,
+                location: "temp://events.csv".to_string(),
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                ignore_lines: 1,
+                columns: vec![Ident::new("id"), Ident::new("name")],
+            }
+        );
+        assert!(parse(
+            "LOAD DATA INFILE 'temp://events.tsv' INTO TABLE s.events FIELDS TERMINATED BY ';'"
+        )
+        .is_err());
+        assert!(parse("LOAD DATA INFILE 'temp://events.csv' INTO s.events").is_err());
+    }
+
+    #[test]
+    fn attach_table() {
+        let parse = |s: &str| CubeStoreParser::new(s).unwrap().parse_statement();
+        assert_eq!(
+            parse("ATTACH TABLE s.events FROM 's3://bucket/events/' PARTITIONED BY (dt, region)")
+                .unwrap(),
+            Statement::AttachTable {
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                location: "s3://bucket/events/".to_string(),
+                partitioned_by: vec![Ident::new("dt"), Ident::new("region")],
+                format: None,
+            }
+        );
+        assert_eq!(
+            parse("ATTACH TABLE s.events FROM 's3://bucket/events' FORMAT iceberg").unwrap(),
+            Statement::AttachTable {
+                table_name: ObjectName(vec![Ident::new("s"), Ident::new("events")]),
+                location: "s3://bucket/events".to_string(),
+                partitioned_by: Vec::new(),
+                format: Some(Ident::new("iceberg")),
+            }
+        );
+        assert!(parse("ATTACH TABLE s.events FROM 's3://bucket/events/'").is_err());
+        assert!(
+            parse("ATTACH TABLE s.events FROM 's3://bucket/events/' PARTITIONED BY ()").is_err()
+        );
+    }
+
+    #[test]
+    fn identifier_folding() {
+        let parse =
+            |s: &str, fold: bool| match CubeStoreParser::new_with_identifier_folding(s, fold)
+                .unwrap()
+                .parse_statement()
+                .unwrap()
+            {
+                Statement::Statement(s) => s.to_string(),
+                _ => panic!("not a statement"),
+            };
+        let query = "SELECT UserId, \"Name\", `Total` AS \"MyTotal\", LOWER('ABC') FROM Foo.Orders";
+        assert_eq!(parse(query, false), query);
+        assert_eq!(
+            parse(query, true),
+            "SELECT userid, \"Name\", `Total` AS \"MyTotal\", lower('ABC') FROM foo.orders"
+        );
+    }
+
+    #[test]
+    fn timestamp_precision() {
+        let parse = |s: &str| match CubeStoreParser::new(s)?.parse_statement()? {
+            Statement::CreateTable { create_table, .. } => Ok(create_table.to_string()),
+            _ => panic!("not a create table"),
+        };
+        assert_eq!(
+            parse(
+                "CREATE TABLE s.t (ts timestamp, ms TIMESTAMP (3), \
+                 day timestamp(0) AS (DATE_TRUNC('day', ts)) STORED)"
+            )
+            .unwrap(),
+            "CREATE TABLE s.t (ts TIMESTAMP, ms __timestamp_3, \
+             day __timestamp_0 DEFAULT __generated(DATE_TRUNC('day', ts)))"
+        );
+        let create_as = Tokenizer::new(
+            &MySqlDialectWithBackTicks {},
+            "CREATE TABLE s.t AS SELECT CAST(x AS TIMESTAMP) AS y FROM s.u",
+        )
+        .tokenize()
+        .unwrap();
+        assert_eq!(rewrite_column_types(create_as.clone()), create_as);
+    }
+
+    #[test]
+    fn enums() {
+        let parse = |s: &str| match CubeStoreParser::new(s)?.parse_statement()? {
+            Statement::CreateTable { create_table, .. } => Ok(create_table.to_string()),
+            _ => panic!("not a create table"),
+        };
+        assert_eq!(
+            parse("CREATE TABLE s.t (plan ENUM('free', 'Pro, \"annual\"'), n int)").unwrap(),
+            "CREATE TABLE s.t (plan __enum_[\"free\",\"Pro, \\\"annual\\\"\"], n INT)"
+        );
+        assert!(parse("CREATE TABLE s.t (plan ENUM())").is_err());
+        assert!(parse("CREATE TABLE s.t (plan ENUM('a',))").is_err());
+    }
+
+    #[test]
+    fn column_encodings() {
+        let parse = |s: &str| match CubeStoreParser::new(s)?.parse_statement()? {
+            Statement::CreateTable { create_table, .. } => Ok(create_table.to_string()),
+            _ => panic!("not a create table"),
+        };
+        assert_eq!(
+            parse(
+                "CREATE TABLE s.t (ts timestamp ENCODING delta NOT NULL, encoding int, \
+                 seq int DEFAULT 0 encoding DELTA, n int)"
+            )
+            .unwrap(),
+            "CREATE TABLE s.t (ts TIMESTAMP DEFAULT __encoding(delta) NOT NULL, encoding INT, \
+             seq INT DEFAULT 0 DEFAULT __encoding(DELTA), n INT)"
+        );
+        assert!(parse("CREATE TABLE s.t (ts timestamp ENCODING)").is_err());
+    }
+
+    #[test]
+    fn generated_columns() {
+        let parse = |s: &str| match CubeStoreParser::new(s)?.parse_statement()? {
+            Statement::CreateTable { create_table, .. } => Ok(create_table.to_string()),
+            _ => panic!("not a create table"),
+        };
+        assert_eq!(
+            parse(
+                "CREATE TABLE s.t (ts timestamp, day timestamp AS (DATE_TRUNC('day', ts)) STORED, \
+                 email text, lower_email GENERATED ALWAYS AS (lower(email)), n int)"
+            )
+            .unwrap(),
+            "CREATE TABLE s.t (ts TIMESTAMP, day TIMESTAMP DEFAULT __generated(DATE_TRUNC('day', ts)), \
+             email TEXT, lower_email __generated DEFAULT __generated(lower(email)), n INT)"
+        );
+        let tokenize = |s: &str| {
+            Tokenizer::new(&MySqlDialectWithBackTicks {}, s)
+                .tokenize()
+                .unwrap()
+        };
+        let create_as = tokenize("CREATE TABLE s.t AS (SELECT CAST(x AS int) AS y FROM s.u)");
+        assert_eq!(
+            rewrite_generated_columns(create_as.clone()).unwrap(),
+            create_as
+        );
+        assert!(parse("CREATE TABLE s.t (a int, b int AS (a + 1) VIRTUAL)").is_err());
+        assert!(parse("CREATE TABLE s.t (a int, b int AS a + 1)").is_err());
+
+        assert_eq!(
+            parse_expr("DATE_TRUNC('day', ts)").unwrap().to_string(),
+            "DATE_TRUNC('day', ts)"
+        );
+        assert!(parse_expr("a b").is_err());
+    }
+
+    #[test]
+    fn table_sample() {
+        let parse = |s: &str| match CubeStoreParser::new(s)?.parse_statement()? {
+            Statement::Statement(s) => Ok(s.to_string()),
+            _ => panic!("not a statement"),
+        };
+        assert_eq!(
+            parse("SELECT * FROM s.t AS t TABLESAMPLE SYSTEM (10 PERCENT) WHERE t.a = 1").unwrap(),
+            "SELECT * FROM s.t AS t WITH (__tablesample(10)) WHERE t.a = 1"
+        );
+        assert_eq!(
+            parse("SELECT * FROM s.t tablesample system (0.5)").unwrap(),
+            "SELECT * FROM s.t WITH (__tablesample(0.5))"
+        );
+        assert!(parse("SELECT * FROM s.t TABLESAMPLE BERNOULLI (10)").is_err());
+        assert!(parse("SELECT * FROM s.t TABLESAMPLE SYSTEM (10 PERCENT").is_err());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let error = |s: &str| {
+            CubeStoreParser::new(s)
+                .unwrap()
+                .parse_single_statement()
+                .unwrap_err()
+                .message
+        };
+        assert_eq!(
+            error("SELECT a FORM s.t"),
+            "Syntax error at line 1, column 15: Expected end of statement, found: s\n\
+             SELECT a FORM s.t\n\
+             \x20             ^\n\
+             Did you mean FROM instead of FORM?"
+        );
+        assert!(error("SELECT a\nFROM s.t\nGROUP a").starts_with(
+            "Syntax error at line 3, column 1: Expected end of statement, found: GROUP\nGROUP a\n^"
+        ));
+
+        let long = format!("SELECT {}b FROM s.t WHERE", "a, ".repeat(100));
+        let e = error(&long);
+        let lines = e.lines().collect::<Vec<_>>();
+        assert!(lines[0].starts_with("Syntax error at line 1, column 324: "));
+        assert!(lines[1].starts_with("...") && lines[1].ends_with("s.t WHERE"));
+        assert_eq!(lines[2].len(), lines[1].len() + 1);
+        assert!(lines[2].ends_with('^'));
+
+        let unterminated = "SELECT 'abc";
+        let e = syntax_error(
+            unterminated,
+            CubeStoreParser::new(unterminated).err().unwrap(),
+        );
+        assert!(
+            e.message.starts_with("Syntax error at line 1, column "),
+            "{}",
+            e
+        );
+
+        assert!(CubeStoreParser::new("SELECT 1;;")
+            .unwrap()
+            .parse_single_statement()
+            .is_ok());
+        assert_eq!(nearest_keyword("selec"), Some("SELECT"));
+        assert_eq!(nearest_keyword("WHRE"), Some("WHERE"));
+        assert_eq!(nearest_keyword("user_id"), None);
+        assert_eq!(nearest_keyword("ab"), None);
+    }
+}
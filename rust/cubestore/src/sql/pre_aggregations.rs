@@ -0,0 +1,373 @@
+//! Builds tables of pre-aggregations, see [crate::queryplanner::pre_aggregations].
+//!
+//! Pre-aggregations with a refresh key are rebuilt when the value of the refresh key changes,
+//! others every `refresh_every` seconds. Builds run `CREATE TABLE ... AS <query>` over source
+//! tables and replace the tables of the pre-aggregation when all of them are built, so queries
+//! read either the old or the new tables. Replaced tables are dropped after the query timeout.
+//!
+//! Partitioned pre-aggregations have a table per time range of `time_dimension`. Only new ranges
+//! and the last built range are rebuilt on refresh, i.e. older source rows are assumed to be
+//! immutable.
+use crate::config::ConfigObj;
+use crate::metastore::pre_aggregation::{
+    PreAggregation, PreAggregationPartition, PreAggregationPartitioning, PreAggregationState,
+};
+use crate::metastore::{IdRow, MetaStore};
+use crate::queryplanner::pre_aggregations::{
+    partition_query, time_range_query, Rollup, NO_PRE_AGGREGATIONS_HINT,
+};
+use crate::sql::SqlService;
+use crate::table::TableValue;
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use futures_timer::Delay;
+use log::{trace, warn};
+use sqlparser::ast::{ObjectName, Query, SqlOption, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Rebuild interval of pre-aggregations without `refresh_key` and `refresh_every`.
+pub const DEFAULT_REFRESH_EVERY_SECS: u64 = 3600;
+
+/// Parses `CREATE PRE_AGGREGATION` options:
+///     refresh_key = '<select of a single value>', refresh_every = <seconds>,
+///     partition_granularity = 'hour' | 'day' | 'week' | 'month' | 'quarter' | 'year',
+///     time_dimension = '<column of the source table>'
+pub fn pre_aggregation_from_statement(
+    name: ObjectName,
+    options: Vec<SqlOption>,
+    query: Query,
+) -> Result<PreAggregation, CubeError> {
+    let (schema, name) = match name.0.as_slice() {
+        [schema, name] => (schema.value.clone(), name.value.clone()),
+        _ => {
+            return Err(CubeError::user(format!(
+                "Pre-aggregation name must be in the schema.name form, found: {}",
+                name
+            )))
+        }
+    };
+    let mut refresh_key = None;
+    let mut refresh_every_secs = DEFAULT_REFRESH_EVERY_SECS;
+    let mut granularity = None;
+    let mut time_dimension = None;
+    for o in options {
+        match (o.name.value.to_lowercase().as_str(), o.value) {
+            ("refresh_key", Value::SingleQuotedString(q)) => refresh_key = Some(q),
+            ("refresh_every", Value::Number(n, _)) => {
+                refresh_every_secs = match n.parse::<u64>() {
+                    Ok(n) if n != 0 => n,
+                    _ => return Err(CubeError::user(format!("Invalid refresh_every: {}", n))),
+                }
+            }
+            ("partition_granularity", Value::SingleQuotedString(g)) => {
+                granularity = Some(g.parse()?)
+            }
+            ("time_dimension", Value::SingleQuotedString(c)) => time_dimension = Some(c),
+            (_, v) => {
+                return Err(CubeError::user(format!(
+                    "Unsupported pre-aggregation option: {} = {}",
+                    o.name, v
+                )))
+            }
+        }
+    }
+    let partitioning =
+        match (granularity, time_dimension) {
+            (Some(granularity), Some(time_dimension)) => Some(PreAggregationPartitioning {
+                time_dimension,
+                granularity,
+            }),
+            (None, None) => None,
+            _ => return Err(CubeError::user(
+                "Partitioned pre-aggregations need both partition_granularity and time_dimension"
+                    .to_string(),
+            )),
+        };
+    let query = query.to_string();
+    Rollup::parse(&query)?;
+    Ok(PreAggregation::new(
+        schema,
+        name,
+        query,
+        refresh_key,
+        refresh_every_secs,
+        partitioning,
+    ))
+}
+
+/// Periodically checks if pre-aggregations need to be rebuilt and rebuilds them.
+pub struct PreAggregationRefresher {
+    meta_store: Arc<dyn MetaStore>,
+    interval: Duration,
+    /// Replaced tables are dropped when queries that could read them have timed out.
+    retired_table_ttl: Duration,
+    refresh_loop: WorkerLoop,
+}
+
+crate::di_service!(PreAggregationRefresher, []);
+
+impl PreAggregationRefresher {
+    pub fn new(
+        meta_store: Arc<dyn MetaStore>,
+        config: &dyn ConfigObj,
+    ) -> Arc<PreAggregationRefresher> {
+        Arc::new(PreAggregationRefresher {
+            meta_store,
+            interval: Duration::from_secs(config.pre_aggregation_refresh_secs()),
+            retired_table_ttl: Duration::from_secs(config.query_timeout()),
+            refresh_loop: WorkerLoop::new("PreAggregationRefresher"),
+        })
+    }
+
+    pub async fn wait_processing_loop(self: Arc<Self>, sql_service: Arc<dyn SqlService>) {
+        if self.interval == Duration::from_secs(0) {
+            return;
+        }
+        let interval = self.interval;
+        self.refresh_loop
+            .process(
+                self.clone(),
+                async move |_| {
+                    Delay::new(interval).await;
+                    Ok(())
+                },
+                move |r, _| {
+                    let sql_service = sql_service.clone();
+                    async move { r.refresh_all(sql_service.as_ref()).await }
+                },
+            )
+            .await
+    }
+
+    pub fn stop_processing_loop(&self) {
+        self.refresh_loop.stop();
+    }
+
+    pub async fn refresh_all(&self, sql_service: &dyn SqlService) -> Result<(), CubeError> {
+        for p in self.meta_store.get_pre_aggregations().await? {
+            // A failed build keeps the previous tables and doesn't stop the others.
+            if let Err(e) = self.refresh(sql_service, &p, Utc::now()).await {
+                warn!(
+                    "Can't refresh pre-aggregation {}.{}: {}",
+                    p.get_row().get_schema(),
+                    p.get_row().get_name(),
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    async fn refresh(
+        &self,
+        sql_service: &dyn SqlService,
+        p: &IdRow<PreAggregation>,
+        now: DateTime<Utc>,
+    ) -> Result<(), CubeError> {
+        let row = p.get_row();
+        let mut state = row.state().clone();
+
+        let ttl = ChronoDuration::from_std(self.retired_table_ttl).unwrap();
+        let (expired, retired) = state
+            .retired_tables
+            .iter()
+            .cloned()
+            .partition::<Vec<_>, _>(|(_, retired_at)| *retired_at + ttl <= now);
+        if !expired.is_empty() {
+            for (table_id, _) in &expired {
+                drop_table(self.meta_store.as_ref(), *table_id).await;
+            }
+            state.retired_tables = retired;
+            self.meta_store
+                .update_pre_aggregation_state(p.get_id(), state.clone())
+                .await?;
+        }
+
+        let refresh_key_value = match row.refresh_key() {
+            Some(key) => Some(refresh_key_value(sql_service, key).await?),
+            None => None,
+        };
+        let due = match (&refresh_key_value, state.last_refresh) {
+            (_, None) => true,
+            (Some(v), _) => state.refresh_key_value.as_ref() != Some(v),
+            (None, Some(last)) => {
+                last + ChronoDuration::seconds(row.refresh_every_secs() as i64) <= now
+            }
+        };
+        if !due {
+            return Ok(());
+        }
+
+        trace!(
+            "Building pre-aggregation {}.{}",
+            row.get_schema(),
+            row.get_name()
+        );
+        let version = state.version + 1;
+        let mut built = Vec::new();
+        let res = self
+            .build(sql_service, row, version, &state.partitions, &mut built)
+            .await;
+        let partitions = match res {
+            Ok(partitions) => partitions,
+            Err(e) => {
+                for t in built {
+                    drop_table(self.meta_store.as_ref(), t.table_id).await;
+                }
+                state.last_error = Some(e.message.clone());
+                self.meta_store
+                    .update_pre_aggregation_state(p.get_id(), state)
+                    .await?;
+                return Err(e);
+            }
+        };
+        for old in &state.partitions {
+            if !partitions.iter().any(|t| t.table_id == old.table_id) {
+                state.retired_tables.push((old.table_id, now));
+            }
+        }
+        let new_state = PreAggregationState {
+            version,
+            partitions,
+            refresh_key_value,
+            last_refresh: Some(now),
+            last_error: None,
+            retired_tables: state.retired_tables,
+        };
+        if let Err(e) = self
+            .meta_store
+            .update_pre_aggregation_state(p.get_id(), new_state)
+            .await
+        {
+            // Dropped while it was built.
+            for t in built {
+                drop_table(self.meta_store.as_ref(), t.table_id).await;
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Returns all tables of the pre-aggregation, `built` are the new ones.
+    async fn build(
+        &self,
+        sql_service: &dyn SqlService,
+        p: &PreAggregation,
+        version: u64,
+        existing: &[PreAggregationPartition],
+        built: &mut Vec<PreAggregationPartition>,
+    ) -> Result<Vec<PreAggregationPartition>, CubeError> {
+        let partitioning = match p.partitioning() {
+            None => {
+                let table_name = format!("{}__{}", p.get_name(), version);
+                let t = self
+                    .build_table(sql_service, p, &table_name, p.query(), None)
+                    .await?;
+                built.push(t.clone());
+                return Ok(vec![t]);
+            }
+            Some(partitioning) => partitioning,
+        };
+        let (min, max) = match time_range(sql_service, p, partitioning).await? {
+            Some(range) => range,
+            None => return Ok(Vec::new()),
+        };
+        let granularity = partitioning.granularity;
+        let last_built = existing.iter().filter_map(|t| t.start).max();
+        let mut partitions = Vec::new();
+        let mut start = granularity.truncate(min);
+        while start <= max {
+            let end = granularity.next(start);
+            match existing.iter().find(|t| t.start == Some(start)) {
+                Some(t) if Some(start) < last_built => partitions.push(t.clone()),
+                _ => {
+                    let table_name =
+                        format!("{}__{}_{}", p.get_name(), version, start.format("%Y%m%d%H"));
+                    let query =
+                        partition_query(p.query(), &partitioning.time_dimension, start, end)?;
+                    let t = self
+                        .build_table(sql_service, p, &table_name, &query, Some(start))
+                        .await?;
+                    built.push(t.clone());
+                    partitions.push(t);
+                }
+            }
+            start = end;
+        }
+        Ok(partitions)
+    }
+
+    async fn build_table(
+        &self,
+        sql_service: &dyn SqlService,
+        p: &PreAggregation,
+        table_name: &str,
+        query: &str,
+        start: Option<DateTime<Utc>>,
+    ) -> Result<PreAggregationPartition, CubeError> {
+        sql_service
+            .exec_query(&format!(
+                "/*+ {} */ CREATE TABLE {}.{} AS {}",
+                NO_PRE_AGGREGATIONS_HINT,
+                p.get_schema(),
+                table_name,
+                query
+            ))
+            .await?;
+        let table = self
+            .meta_store
+            .get_table(p.get_schema().to_string(), table_name.to_string())
+            .await?;
+        Ok(PreAggregationPartition {
+            start,
+            table_id: table.get_id(),
+            table_name: table_name.to_string(),
+        })
+    }
+}
+
+/// Tables of pre-aggregations are dropped with them, so they may already be dropped here.
+async fn drop_table(meta_store: &dyn MetaStore, table_id: u64) {
+    if let Err(e) = meta_store.drop_table(table_id).await {
+        warn!("Can't drop pre-aggregation table {}: {}", table_id, e);
+    }
+}
+
+async fn refresh_key_value(sql_service: &dyn SqlService, key: &str) -> Result<String, CubeError> {
+    let data = sql_service
+        .exec_query(&format!("/*+ {} */ {}", NO_PRE_AGGREGATIONS_HINT, key))
+        .await?;
+    Ok(format!("{:?}", data.get_rows().first().map(|r| r.values())))
+}
+
+/// Minimum and maximum of the time dimension, [None] if there are no source rows.
+async fn time_range(
+    sql_service: &dyn SqlService,
+    p: &PreAggregation,
+    partitioning: &PreAggregationPartitioning,
+) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>, CubeError> {
+    let data = sql_service
+        .exec_query(&format!(
+            "/*+ {} */ {}",
+            NO_PRE_AGGREGATIONS_HINT,
+            time_range_query(p.query(), &partitioning.time_dimension)?
+        ))
+        .await?;
+    let values = match data.get_rows().first() {
+        Some(r) => r.values(),
+        None => return Ok(None),
+    };
+    match values.as_slice() {
+        [TableValue::Timestamp(min), TableValue::Timestamp(max)] => Ok(Some((
+            Utc.timestamp_nanos(min.get_time_stamp()),
+            Utc.timestamp_nanos(max.get_time_stamp()),
+        ))),
+        [TableValue::Null, TableValue::Null] => Ok(None),
+        _ => Err(CubeError::user(format!(
+            "Time dimension {} must be a timestamp",
+            partitioning.time_dimension
+        ))),
+    }
+}
@@ -0,0 +1,365 @@
+//! Prefetching of results ahead of scheduled dashboard refreshes. `CREATE PREFETCH '<cron>' QUERY
+//! '<query>'` registers a query with the schedule its dashboards refresh on, e.g.
+//! `CREATE PREFETCH '0 9 * * 1-5' QUERY 'SELECT ...'`. The router runs the query
+//! CUBESTORE_RESULT_PREFETCH_LEAD_SECS before each refresh, which puts its result into
+//! [crate::sql::cache::SqlResultCache], so the load of the refresh is spread ahead of time.
+//! Results are cached by the query text, clients must send exactly the registered text to use
+//! them. `system.prefetches` lists registered queries and `DROP PREFETCH '<id>'` removes one.
+//! Prefetches are kept in memory of the router.
+use crate::sql::SqlService;
+use crate::util::WorkerLoop;
+use crate::CubeError;
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, Timelike, Utc};
+use futures::future::join_all;
+use futures_timer::Delay;
+use log::{trace, warn};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How often the router looks for prefetches that are due.
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+pub struct ResultPrefetcher {
+    prefetches: Mutex<HashMap<String, Prefetch>>,
+    lead: Duration,
+    worker_loop: WorkerLoop,
+}
+
+crate::di_service!(ResultPrefetcher, []);
+
+impl std::fmt::Debug for ResultPrefetcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResultPrefetcher")
+            .field("prefetches", &self.prefetches)
+            .field("lead", &self.lead)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Prefetch {
+    pub id: String,
+    pub schedule: CronSchedule,
+    pub query: String,
+    /// The refresh the next prefetch is run for.
+    pub next_refresh: DateTime<Utc>,
+    pub last_prefetch: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl ResultPrefetcher {
+    pub fn new(lead: Duration) -> ResultPrefetcher {
+        ResultPrefetcher {
+            prefetches: Mutex::new(HashMap::new()),
+            lead,
+            worker_loop: WorkerLoop::new("ResultPrefetcher"),
+        }
+    }
+
+    /// Returns the id of the prefetch.
+    pub fn register(&self, schedule: CronSchedule, query: String) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let next_refresh = schedule.next_after(Utc::now() + self.lead_duration());
+        self.prefetches.lock().unwrap().insert(
+            id.clone(),
+            Prefetch {
+                id: id.clone(),
+                schedule,
+                query,
+                next_refresh,
+                last_prefetch: None,
+                last_error: None,
+            },
+        );
+        id
+    }
+
+    pub fn remove(&self, id: &str) -> Result<(), CubeError> {
+        match self.prefetches.lock().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(CubeError::user(format!("Unknown prefetch: {}", id))),
+        }
+    }
+
+    pub fn all(&self) -> Vec<Prefetch> {
+        let mut prefetches = self
+            .prefetches
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        prefetches.sort_by(|a, b| a.next_refresh.cmp(&b.next_refresh));
+        prefetches
+    }
+
+    pub async fn wait_processing_loop(self: Arc<Self>, sql_service: Arc<dyn SqlService>) {
+        self.worker_loop
+            .process(
+                self.clone(),
+                async move |_| {
+                    Delay::new(CHECK_INTERVAL).await;
+                    Ok(())
+                },
+                move |prefetcher, _| prefetcher.prefetch_due(Utc::now(), sql_service.clone()),
+            )
+            .await
+    }
+
+    pub fn stop_processing_loop(&self) {
+        self.worker_loop.stop();
+    }
+
+    async fn prefetch_due(
+        self: Arc<Self>,
+        now: DateTime<Utc>,
+        sql_service: Arc<dyn SqlService>,
+    ) -> Result<(), CubeError> {
+        let due = self.take_due(now);
+        join_all(due.into_iter().map(|(id, query)| {
+            let sql_service = sql_service.clone();
+            let prefetcher = self.clone();
+            async move {
+                trace!("Prefetching '{}'", query);
+                let res = sql_service.exec_query(&query).await;
+                if let Err(e) = &res {
+                    warn!("Prefetch of '{}' failed: {}", query, e);
+                }
+                if let Some(p) = prefetcher.prefetches.lock().unwrap().get_mut(&id) {
+                    p.last_prefetch = Some(Utc::now());
+                    p.last_error = res.err().map(|e| e.message);
+                }
+            }
+        }))
+        .await;
+        Ok(())
+    }
+
+    /// Queries of prefetches whose refresh is within the lead time. Their next refresh is
+    /// advanced, so each refresh is prefetched once.
+    fn take_due(&self, now: DateTime<Utc>) -> Vec<(String, String)> {
+        let horizon = now + self.lead_duration();
+        let mut due = Vec::new();
+        for p in self.prefetches.lock().unwrap().values_mut() {
+            if p.next_refresh <= horizon {
+                due.push((p.id.clone(), p.query.clone()));
+                // Refreshes missed while the router was busy are skipped.
+                p.next_refresh = p.schedule.next_after(std::cmp::max(p.next_refresh, now));
+            }
+        }
+        due
+    }
+
+    fn lead_duration(&self) -> ChronoDuration {
+        ChronoDuration::from_std(self.lead).unwrap()
+    }
+}
+
+/// Schedule in the cron format, `minute hour day-of-month month day-of-week` in UTC. Fields are
+/// `*`, numbers, ranges `a-b`, lists `a,b` and steps `*/n` or `a-b/n`. Sunday is 0 or 7. As in
+/// cron, a time matches either restricted day field when both days are restricted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl CronSchedule {
+    /// The first matching minute strictly after `time`.
+    pub fn next_after(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let mut t =
+            time.with_second(0).unwrap().with_nanosecond(0).unwrap() + ChronoDuration::minutes(1);
+        // Every schedule matches at least once in 4 years, e.g. on February 29.
+        for _ in 0..4 * 366 * 24 * 60 {
+            if self.matches(t) {
+                return t;
+            }
+            t = t + ChronoDuration::minutes(1);
+        }
+        panic!("No matching time for schedule {}", self.source)
+    }
+
+    fn matches(&self, t: DateTime<Utc>) -> bool {
+        let bit = |mask: u64, v: u32| mask & (1 << v) != 0;
+        let day = bit(self.days, t.day());
+        let weekday = bit(self.weekdays, t.weekday().num_days_from_sunday());
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        };
+        bit(self.minutes, t.minute())
+            && bit(self.hours, t.hour())
+            && bit(self.months, t.month())
+            && day_matches
+    }
+
+    fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, CubeError> {
+        let error = || {
+            CubeError::user(format!(
+                "Invalid schedule field '{}', expected values from {} to {}",
+                field, min, max
+            ))
+        };
+        let number = |s: &str| match s.parse::<u32>() {
+            Ok(n) if min <= n && n <= max => Ok(n),
+            _ => Err(error()),
+        };
+        let mut mask = 0;
+        for part in field.split(',') {
+            let mut range_and_step = part.splitn(2, '/');
+            let range = range_and_step.next().unwrap();
+            let step = match range_and_step.next() {
+                Some(s) => match s.parse::<u32>() {
+                    Ok(n) if n != 0 => n,
+                    _ => return Err(error()),
+                },
+                None => 1,
+            };
+            let (from, to) = if range == "*" {
+                (min, max)
+            } else {
+                let mut bounds = range.splitn(2, '-');
+                let from = number(bounds.next().unwrap())?;
+                match bounds.next() {
+                    Some(to) => (from, number(to)?),
+                    None if step == 1 => (from, from),
+                    None => (from, max),
+                }
+            };
+            if to < from {
+                return Err(error());
+            }
+            for v in (from..=to).step_by(step as usize) {
+                mask |= 1 << v;
+            }
+        }
+        Ok(mask)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 5 {
+            return Err(CubeError::user(format!(
+                "Schedule must have 5 fields: minute hour day-of-month month day-of-week, found: {}",
+                s
+            )));
+        }
+        let mut weekdays = CronSchedule::parse_field(fields[4], 0, 7)?;
+        // Sunday is both 0 and 7.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(CronSchedule {
+            source: s.to_string(),
+            minutes: CronSchedule::parse_field(fields[0], 0, 59)?,
+            hours: CronSchedule::parse_field(fields[1], 0, 23)?,
+            days: CronSchedule::parse_field(fields[2], 1, 31)?,
+            months: CronSchedule::parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+}
+
+impl ToString for CronSchedule {
+    fn to_string(&self) -> String {
+        self.source.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn cron_schedule() {
+        let at = |s: &str| Utc.datetime_from_str(s, "%Y-%m-%d %H:%M").unwrap();
+        // 2021-06-04 is a Friday.
+        let weekdays = "0 9 * * 1-5".parse::<CronSchedule>().unwrap();
+        assert_eq!(
+            weekdays.next_after(at("2021-06-04 08:30")),
+            at("2021-06-04 09:00")
+        );
+        assert_eq!(
+            weekdays.next_after(at("2021-06-04 09:00")),
+            at("2021-06-07 09:00")
+        );
+
+        let every_15 = "*/15 * * * *".parse::<CronSchedule>().unwrap();
+        assert_eq!(
+            every_15.next_after(at("2021-06-04 23:50")),
+            at("2021-06-05 00:00")
+        );
+
+        let lists = "5,35 8-10/2 1 * 0".parse::<CronSchedule>().unwrap();
+        // The 1st of the month or Sundays.
+        assert_eq!(
+            lists.next_after(at("2021-06-04 10:40")),
+            at("2021-06-06 08:05")
+        );
+        assert_eq!(
+            lists.next_after(at("2021-06-06 10:35")),
+            at("2021-06-13 08:05")
+        );
+        assert_eq!(
+            lists.next_after(at("2021-06-28 12:00")),
+            at("2021-07-01 08:05")
+        );
+
+        let sunday = "0 0 * * 7".parse::<CronSchedule>().unwrap();
+        assert_eq!(
+            sunday.next_after(at("2021-06-04 00:00")),
+            at("2021-06-06 00:00")
+        );
+
+        for s in &[
+            "* * * *",
+            "60 * * * *",
+            "* 5-1 * * *",
+            "*/0 * * * *",
+            "a * * * *",
+        ] {
+            assert!(s.parse::<CronSchedule>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn take_due() {
+        let prefetcher = ResultPrefetcher::new(Duration::from_secs(60));
+        let id = prefetcher.register(
+            "0 9 * * *".parse().unwrap(),
+            "SELECT 1 FROM s.t".to_string(),
+        );
+        let next = prefetcher.all()[0].next_refresh;
+        assert!(prefetcher
+            .take_due(next - ChronoDuration::minutes(2))
+            .is_empty());
+        assert_eq!(
+            prefetcher.take_due(next - ChronoDuration::seconds(30)),
+            vec![(id.clone(), "SELECT 1 FROM s.t".to_string())]
+        );
+        assert!(prefetcher.take_due(next).is_empty());
+        assert_eq!(
+            prefetcher.all()[0].next_refresh,
+            next + ChronoDuration::days(1)
+        );
+
+        prefetcher.remove(&id).unwrap();
+        assert!(prefetcher.all().is_empty());
+        assert!(prefetcher.remove(&id).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+//! Query priorities, set for a connection with `SET query_priority = <priority>` or for a single
+//! statement with the `/*+ PRIORITY=<priority> */` hint. The priority travels with the plan to
+//! workers, where selects waiting for a free select process are taken in order of priority.
+//! Dashboards are expected to use `high` and pre-aggregation builds `low`.
+use crate::CubeError;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Name of the variable that sets the priority of the connection's queries.
+pub const QUERY_PRIORITY_VARIABLE: &str = "query_priority";
+
+/// Name of the hint that sets the priority of a single statement.
+pub const PRIORITY_HINT: &str = "PRIORITY";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum QueryPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Default for QueryPriority {
+    fn default() -> Self {
+        QueryPriority::Normal
+    }
+}
+
+impl FromStr for QueryPriority {
+    type Err = CubeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "low" => Ok(QueryPriority::Low),
+            "normal" => Ok(QueryPriority::Normal),
+            "high" => Ok(QueryPriority::High),
+            _ => Err(CubeError::user(format!(
+                "Query priority must be one of low, normal or high, found: {}",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_priority() {
+        assert_eq!(
+            "HIGH".parse::<QueryPriority>().unwrap(),
+            QueryPriority::High
+        );
+        assert_eq!("low".parse::<QueryPriority>().unwrap(), QueryPriority::Low);
+        assert!("urgent".parse::<QueryPriority>().is_err());
+        assert!(QueryPriority::Low < QueryPriority::Normal);
+        assert!(QueryPriority::Normal < QueryPriority::High);
+    }
+}
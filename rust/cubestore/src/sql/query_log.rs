@@ -0,0 +1,177 @@
+//! Structured log of queries sent by MySQL and HTTP clients, enabled with
+//! `CUBESTORE_QUERY_LOG=<file>`. A JSON line is appended once a query completes:
+//!     {"time":"2021-06-01T10:00:00.120Z","session":1622541600000001,"user":"cube",
+//!      "query":"SELECT ..","duration_ms":12,"rows":10,"error":null,"query_id":"5f0c.."}
+//! `time` is when the query started. `session` identifies the MySQL connection the query was sent
+//! on, queries of HTTP clients don't share state and have no session. Queries of a batch are
+//! logged with the start and duration of the whole batch. `query_id` matches the query with log
+//! lines of nodes that executed it, see [crate::util::query_id]. The log is the input of
+//! `cubestored replay`, see [crate::replay].
+use crate::util::query_id::current_query_id;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueryLogEntry {
+    pub time: DateTime<Utc>,
+    pub session: Option<u64>,
+    pub user: Option<String>,
+    pub query: String,
+    pub duration_ms: u64,
+    /// Rows returned, not set on errors.
+    pub rows: Option<u64>,
+    pub error: Option<String>,
+    /// Not set in logs written by older versions.
+    #[serde(default)]
+    pub query_id: Option<String>,
+}
+
+pub struct QueryLog {
+    file: Option<Mutex<LineWriter<File>>>,
+    next_session: AtomicU64,
+}
+
+crate::di_service!(QueryLog, []);
+
+impl QueryLog {
+    /// Panics if the file can't be opened, same as on invalid environment variables.
+    pub fn new(path: Option<PathBuf>) -> Arc<QueryLog> {
+        let file = path.map(|path| {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .unwrap_or_else(|e| panic!("Can't open query log {}: {}", path.display(), e));
+            Mutex::new(LineWriter::new(file))
+        });
+        Arc::new(QueryLog {
+            file,
+            // Sessions stay unique when a restarted node appends to the same file.
+            next_session: AtomicU64::new(Utc::now().timestamp_millis() as u64 * 1000),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn new_session(&self) -> u64 {
+        self.next_session.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Logs the query that started at `start` and returned `rows` or failed. Errors of writes are
+    /// logged and otherwise ignored, so they don't fail queries. Called in the scope of the query
+    /// to log its id.
+    pub fn record(
+        &self,
+        start: DateTime<Utc>,
+        session: Option<u64>,
+        user: &Option<String>,
+        query: &str,
+        result: Result<u64, &CubeError>,
+    ) {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+        let (rows, error) = match result {
+            Ok(rows) => (Some(rows), None),
+            Err(e) => (None, Some(e.message.clone())),
+        };
+        let entry = QueryLogEntry {
+            time: start,
+            session,
+            user: user.clone(),
+            query: query.to_string(),
+            duration_ms: (Utc::now() - start).num_milliseconds().max(0) as u64,
+            rows,
+            error,
+            query_id: current_query_id(),
+        };
+        let mut line = serde_json::to_string(&entry).unwrap();
+        line.push('\n');
+        if let Err(e) = file.lock().unwrap().write_all(line.as_bytes()) {
+            error!("Error writing to query log: {}", e);
+        }
+    }
+}
+
+/// Entries of the log in `path`, in the order they were written.
+pub fn read_log(path: &Path) -> Result<Vec<QueryLogEntry>, CubeError> {
+    let file = File::open(path)
+        .map_err(|e| CubeError::user(format!("Can't read query log {}: {}", path.display(), e)))?;
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line).map_err(|e| {
+            CubeError::user(format!(
+                "Invalid query log {}, line {}: {}",
+                path.display(),
+                i + 1,
+                e
+            ))
+        })?);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::query_id::with_query_id;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn write_and_read() {
+        let file = NamedTempFile::new().unwrap();
+        let log = QueryLog::new(Some(file.path().to_path_buf()));
+        let session = log.new_session();
+        assert_ne!(log.new_session(), session);
+
+        let start = Utc::now();
+        let user = Some("cube".to_string());
+        futures::executor::block_on(with_query_id(Some("q1".to_string()), async {
+            log.record(start, Some(session), &user, "SELECT 1", Ok(1))
+        }));
+        log.record(
+            start,
+            None,
+            &None,
+            "SELECT * FROM s.missing",
+            Err(&CubeError::user(
+                "Table s.missing was not found".to_string(),
+            )),
+        );
+
+        let entries = read_log(file.path()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].time, start);
+        assert_eq!(entries[0].session, Some(session));
+        assert_eq!(entries[0].user, user);
+        assert_eq!(entries[0].query, "SELECT 1");
+        assert_eq!(entries[0].rows, Some(1));
+        assert_eq!(entries[0].error, None);
+        assert_eq!(entries[0].query_id, Some("q1".to_string()));
+        assert_eq!(entries[1].query_id, None);
+        assert_eq!(entries[1].session, None);
+        assert_eq!(entries[1].rows, None);
+        assert_eq!(
+            entries[1].error,
+            Some("Table s.missing was not found".to_string())
+        );
+
+        std::fs::write(file.path(), "{\"time\": 1}\n").unwrap();
+        assert!(read_log(file.path()).is_err());
+        assert!(!QueryLog::new(None).is_enabled());
+    }
+}
@@ -0,0 +1,105 @@
+//! Limits on the size of results sent to a single connection, set with
+//! `SET max_result_rows = <n>` and `SET max_result_bytes = <n>`. They protect the router from
+//! queries that forgot a LIMIT. Results are shared between connections by
+//! [crate::sql::cache::SqlResultCache], so limits are checked after the query is executed, right
+//! before the result is handed to the client.
+use crate::store::DataFrame;
+use crate::table::TableValue;
+use crate::CubeError;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResultLimits {
+    /// Zero means no limit.
+    pub max_rows: u64,
+    /// Zero means no limit. Sizes are estimated from values of the result, not its wire format.
+    pub max_bytes: u64,
+}
+
+impl ResultLimits {
+    /// Returns false if `variable` is not one of the limits.
+    pub fn set(&mut self, variable: &str, value: &str) -> Result<bool, CubeError> {
+        let limit = match variable.to_lowercase().as_str() {
+            "max_result_rows" => &mut self.max_rows,
+            "max_result_bytes" => &mut self.max_bytes,
+            _ => return Ok(false),
+        };
+        *limit = value.parse().map_err(|_| {
+            CubeError::user(format!(
+                "Value of {} must be a non-negative integer, found: {}",
+                variable, value
+            ))
+        })?;
+        Ok(true)
+    }
+
+    pub fn check(&self, data_frame: &DataFrame) -> Result<(), CubeError> {
+        let rows = data_frame.get_rows();
+        if self.max_rows != 0 && self.max_rows < rows.len() as u64 {
+            return Err(CubeError::user(format!(
+                "Query returned more than {} rows, the limit set by max_result_rows. \
+                 Add a LIMIT to the query or raise the limit",
+                self.max_rows
+            )));
+        }
+        if self.max_bytes == 0 {
+            return Ok(());
+        }
+        let mut bytes = 0;
+        for r in rows {
+            bytes += r.values().iter().map(value_size).sum::<u64>();
+            if self.max_bytes < bytes {
+                return Err(CubeError::user(format!(
+                    "Query returned more than {} bytes, the limit set by max_result_bytes. \
+                     Add a LIMIT to the query or raise the limit",
+                    self.max_bytes
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn value_size(v: &TableValue) -> u64 {
+    match v {
+        TableValue::Null => 0,
+        TableValue::String(s) | TableValue::Decimal(s) => s.len() as u64,
+        TableValue::Bytes(b) => b.len() as u64,
+        TableValue::Boolean(_) => 1,
+        TableValue::Int(_) | TableValue::Float(_) | TableValue::Timestamp(_) => 8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table::Row;
+
+    #[test]
+    fn result_limits() {
+        let data_frame = DataFrame::new(
+            Vec::new(),
+            vec![
+                Row::new(vec![
+                    TableValue::Int(1),
+                    TableValue::String("abc".to_string()),
+                ]),
+                Row::new(vec![TableValue::Int(2), TableValue::Null]),
+            ],
+        );
+        let mut limits = ResultLimits::default();
+        limits.check(&data_frame).unwrap();
+
+        assert!(limits.set("MAX_RESULT_ROWS", "1").unwrap());
+        assert!(limits.check(&data_frame).is_err());
+        assert!(limits.set("max_result_rows", "2").unwrap());
+        limits.check(&data_frame).unwrap();
+
+        assert!(limits.set("max_result_bytes", "19").unwrap());
+        limits.check(&data_frame).unwrap();
+        assert!(limits.set("max_result_bytes", "18").unwrap());
+        assert!(limits.check(&data_frame).is_err());
+
+        assert!(!limits.set("autocommit", "1").unwrap());
+        assert!(limits.set("max_result_rows", "-1").is_err());
+    }
+}
@@ -0,0 +1,51 @@
+//! Guardrails that reject queries scanning too much data before they are sent to workers. The
+//! amount of data is estimated from the partitions chosen by the planner and row counts in the
+//! metastore, sizes of files are not tracked there.
+use crate::queryplanner::serialized_plan::IndexSnapshot;
+use crate::CubeError;
+
+/// Queries with the `/*+ NO_SCAN_LIMITS */` comment are not checked.
+pub const NO_SCAN_LIMITS_HINT: &str = "NO_SCAN_LIMITS";
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanLimits {
+    /// Zero means no limit.
+    pub max_partitions: u64,
+    /// Zero means no limit.
+    pub max_rows: u64,
+}
+
+impl ScanLimits {
+    pub fn check(&self, snapshots: &[IndexSnapshot]) -> Result<(), CubeError> {
+        let partitions = snapshots
+            .iter()
+            .map(|s| s.partitions().len() as u64)
+            .sum::<u64>();
+        if self.max_partitions != 0 && self.max_partitions < partitions {
+            return Err(CubeError::user(format!(
+                "Query scans {} partitions, which exceeds the limit of {} partitions per query. \
+                 Add filters on sort key columns or use the /*+ {} */ hint to run it anyway",
+                partitions, self.max_partitions, NO_SCAN_LIMITS_HINT
+            )));
+        }
+        let rows = snapshots
+            .iter()
+            .flat_map(|s| s.partitions())
+            .map(|p| {
+                p.partition().get_row().main_table_row_count()
+                    + p.chunks()
+                        .iter()
+                        .map(|c| c.get_row().get_row_count())
+                        .sum::<u64>()
+            })
+            .sum::<u64>();
+        if self.max_rows != 0 && self.max_rows < rows {
+            return Err(CubeError::user(format!(
+                "Query scans {} rows, which exceeds the limit of {} rows per query. \
+                 Add filters on sort key columns or use the /*+ {} */ hint to run it anyway",
+                rows, self.max_rows, NO_SCAN_LIMITS_HINT
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! `SUBMIT <statement>` runs the statement in the background and returns a query id right away,
+//! so long statements, e.g. builds of pre-aggregations, do not need to hold client connections
+//! open. `system.queries` reports progress, `FETCH QUERY '<id>'` returns the result of a
+//! completed statement and `CANCEL QUERY '<id>'` stops a running one. Cancellation stops the
+//! statement on the router, partition scans already sent to workers run to completion.
+//! Submitted queries are tracked in memory of the router, finished ones are forgotten after
+//! CUBESTORE_SUBMITTED_QUERY_TTL_SECS.
+use crate::store::DataFrame;
+use crate::util::query_id::with_query_id;
+use crate::CubeError;
+use chrono::{DateTime, Utc};
+use futures::future::{abortable, AbortHandle};
+use futures::Future;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct SubmittedQueries {
+    queries: Mutex<HashMap<String, SubmittedQuery>>,
+    ttl: Duration,
+    timeout: Duration,
+}
+
+crate::di_service!(SubmittedQueries, []);
+
+#[derive(Clone, Debug)]
+pub struct SubmittedQuery {
+    pub id: String,
+    pub query: String,
+    pub status: QueryStatus,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    result: Option<Arc<DataFrame>>,
+    abort: AbortHandle,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum QueryStatus {
+    Running,
+    Completed,
+    Cancelled,
+    Error(String),
+}
+
+impl QueryStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QueryStatus::Running => "running",
+            QueryStatus::Completed => "completed",
+            QueryStatus::Cancelled => "cancelled",
+            QueryStatus::Error(_) => "error",
+        }
+    }
+}
+
+impl SubmittedQueries {
+    pub fn new(ttl: Duration, timeout: Duration) -> SubmittedQueries {
+        SubmittedQueries {
+            queries: Mutex::new(HashMap::new()),
+            ttl,
+            timeout,
+        }
+    }
+
+    /// Used instead of the regular query timeout for submitted statements.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Runs `execution` in the background and returns the query id.
+    pub fn submit(
+        self: &Arc<Self>,
+        query: &str,
+        execution: impl Future<Output = Result<Arc<DataFrame>, CubeError>> + Send + 'static,
+    ) -> String {
+        self.remove_expired(Utc::now());
+        let id = uuid::Uuid::new_v4().to_string();
+        let (execution, abort) = abortable(execution);
+        self.queries.lock().unwrap().insert(
+            id.clone(),
+            SubmittedQuery {
+                id: id.clone(),
+                query: query.to_string(),
+                status: QueryStatus::Running,
+                created_at: Utc::now(),
+                finished_at: None,
+                result: None,
+                abort,
+            },
+        );
+        let queries = self.clone();
+        let query_id = id.clone();
+        // Logs of the execution carry the id of the submitted query, see [crate::util::query_id].
+        tokio::spawn(with_query_id(Some(id.clone()), async move {
+            // Cancelled queries are already marked as such.
+            if let Ok(result) = execution.await {
+                queries.finish(&query_id, result);
+            }
+        }));
+        id
+    }
+
+    fn finish(&self, id: &str, result: Result<Arc<DataFrame>, CubeError>) {
+        if let Some(q) = self.queries.lock().unwrap().get_mut(id) {
+            match result {
+                Ok(data_frame) => {
+                    q.status = QueryStatus::Completed;
+                    q.result = Some(data_frame);
+                }
+                Err(e) => q.status = QueryStatus::Error(e.message),
+            }
+            q.finished_at = Some(Utc::now());
+        }
+    }
+
+    pub fn cancel(&self, id: &str) -> Result<(), CubeError> {
+        let mut queries = self.queries.lock().unwrap();
+        let q = queries
+            .get_mut(id)
+            .ok_or_else(|| CubeError::user(format!("Query {} not found", id)))?;
+        if q.status != QueryStatus::Running {
+            return Err(CubeError::user(format!(
+                "Query {} is not running, its status is {}",
+                id,
+                q.status.name()
+            )));
+        }
+        q.abort.abort();
+        q.status = QueryStatus::Cancelled;
+        q.finished_at = Some(Utc::now());
+        Ok(())
+    }
+
+    /// Results can be fetched multiple times until the query expires.
+    pub fn fetch(&self, id: &str) -> Result<Arc<DataFrame>, CubeError> {
+        let queries = self.queries.lock().unwrap();
+        let q = queries
+            .get(id)
+            .ok_or_else(|| CubeError::user(format!("Query {} not found", id)))?;
+        match &q.status {
+            QueryStatus::Completed => Ok(q.result.clone().unwrap()),
+            QueryStatus::Error(e) => Err(CubeError::user(format!("Query {} failed: {}", id, e))),
+            s => Err(CubeError::user(format!(
+                "Query {} has no result, its status is {}",
+                id,
+                s.name()
+            ))),
+        }
+    }
+
+    /// Oldest queries go first.
+    pub fn all(&self) -> Vec<SubmittedQuery> {
+        let mut r = self
+            .queries
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        r.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        r
+    }
+
+    fn remove_expired(&self, now: DateTime<Utc>) {
+        let ttl = chrono::Duration::from_std(self.ttl).unwrap_or(chrono::Duration::max_value());
+        self.queries
+            .lock()
+            .unwrap()
+            .retain(|_, q| match q.finished_at {
+                Some(t) => now <= t + ttl,
+                None => true,
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_timer::Delay;
+
+    async fn wait_finished(queries: &SubmittedQueries, id: &str) -> QueryStatus {
+        loop {
+            let q = queries.all().into_iter().find(|q| q.id == id).unwrap();
+            if q.status != QueryStatus::Running {
+                return q.status;
+            }
+            Delay::new(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn submitted_queries() {
+        let queries = Arc::new(SubmittedQueries::new(
+            Duration::from_secs(60),
+            Duration::from_secs(60),
+        ));
+        let completed = queries.submit("SELECT 1", async {
+            Ok(Arc::new(DataFrame::new(Vec::new(), Vec::new())))
+        });
+        let failed = queries.submit("SELECT 2", async {
+            Err(CubeError::user("failed".to_string()))
+        });
+        let running = queries.submit("SELECT 3", async {
+            Delay::new(Duration::from_secs(60)).await;
+            Ok(Arc::new(DataFrame::new(Vec::new(), Vec::new())))
+        });
+
+        assert_eq!(
+            wait_finished(&queries, &completed).await,
+            QueryStatus::Completed
+        );
+        assert_eq!(queries.fetch(&completed).unwrap().len(), 0);
+        assert_eq!(
+            wait_finished(&queries, &failed).await,
+            QueryStatus::Error("failed".to_string())
+        );
+        assert!(queries.fetch(&failed).is_err());
+
+        assert!(queries.fetch(&running).is_err());
+        assert!(queries.cancel(&completed).is_err());
+        queries.cancel(&running).unwrap();
+        assert_eq!(
+            wait_finished(&queries, &running).await,
+            QueryStatus::Cancelled
+        );
+
+        queries.remove_expired(Utc::now() + chrono::Duration::seconds(61));
+        assert!(queries.all().is_empty());
+        assert!(queries.fetch("unknown").is_err());
+    }
+}
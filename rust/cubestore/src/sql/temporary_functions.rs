@@ -0,0 +1,417 @@
+//! Temporary functions are SQL macros defined for the connection, e.g.:
+//!     CREATE TEMPORARY FUNCTION bucket(v, size) AS floor(v / size) * size
+//!     SELECT bucket(amount, 100), count(*) FROM s.orders GROUP BY 1
+//! Calls are replaced by the body with parameters replaced by arguments before the query is
+//! planned, so plans sent to workers only have ordinary expressions:
+//!     SELECT (floor((amount) / (100)) * (100)), count(*) FROM s.orders GROUP BY 1
+//! Bodies may only reference parameters and may call temporary functions defined earlier, those
+//! calls are expanded when the function is created. Temporary functions shadow built-in functions
+//! with the same name and are dropped with the connection.
+use crate::sql::parser::Statement as CubeStoreStatement;
+use crate::CubeError;
+use sqlparser::ast::{
+    Expr, FunctionArg, Ident, JoinConstraint, JoinOperator, Query, SelectItem, SetExpr, Statement,
+    TableFactor, TableWithJoins, Values,
+};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug)]
+pub struct TemporaryFunction {
+    params: Vec<String>,
+    body: Expr,
+}
+
+/// Functions of a connection by lowercase names.
+pub type TemporaryFunctions = HashMap<String, TemporaryFunction>;
+
+pub fn create_temporary_function(
+    functions: &mut TemporaryFunctions,
+    name: &Ident,
+    params: &[Ident],
+    mut body: Expr,
+    or_replace: bool,
+) -> Result<(), CubeError> {
+    let key = name.value.to_lowercase();
+    if !or_replace && functions.contains_key(&key) {
+        return Err(CubeError::user(format!(
+            "Temporary function {} already exists",
+            name
+        )));
+    }
+    let params = params
+        .iter()
+        .map(|p| p.value.to_lowercase())
+        .collect::<Vec<_>>();
+    for (i, p) in params.iter().enumerate() {
+        if params[..i].contains(p) {
+            return Err(CubeError::user(format!(
+                "Parameter {} of temporary function {} is declared more than once",
+                p, name
+            )));
+        }
+    }
+    visit_expr(&mut body, &mut |e| match e {
+        Expr::Identifier(i) if params.contains(&i.value.to_lowercase()) => Ok(()),
+        Expr::Identifier(_) | Expr::CompoundIdentifier(_) => Err(CubeError::user(format!(
+            "Temporary function {} references {}, only its parameters can be referenced",
+            name, e
+        ))),
+        Expr::Subquery(_) | Expr::Exists(_) | Expr::InSubquery { .. } => Err(CubeError::user(
+            format!("Temporary function {} can not have subqueries", name),
+        )),
+        _ => expand_call(e, functions).map(|_| ()),
+    })?;
+    functions.insert(key, TemporaryFunction { params, body });
+    Ok(())
+}
+
+pub fn drop_temporary_function(
+    functions: &mut TemporaryFunctions,
+    name: &Ident,
+) -> Result<(), CubeError> {
+    match functions.remove(&name.value.to_lowercase()) {
+        Some(_) => Ok(()),
+        None => Err(CubeError::user(format!(
+            "Temporary function {} does not exist",
+            name
+        ))),
+    }
+}
+
+/// Expands calls of temporary functions in queries of the statement. Returns whether there were
+/// any.
+pub fn expand_temporary_functions(
+    statement: &mut CubeStoreStatement,
+    functions: &TemporaryFunctions,
+) -> Result<bool, CubeError> {
+    if functions.is_empty() {
+        return Ok(false);
+    }
+    let query = match statement {
+        CubeStoreStatement::Statement(Statement::Query(q))
+        | CubeStoreStatement::Export { query: q }
+        | CubeStoreStatement::CreatePreAggregation { query: q, .. } => q,
+        CubeStoreStatement::Statement(Statement::Explain { statement, .. }) => {
+            match statement.as_mut() {
+                Statement::Query(q) => q,
+                _ => return Ok(false),
+            }
+        }
+        CubeStoreStatement::CreateTable {
+            create_table: Statement::CreateTable { query: Some(q), .. },
+            ..
+        } => q,
+        _ => return Ok(false),
+    };
+    let mut expanded = false;
+    visit_query(query, &mut |e| {
+        expanded |= expand_call(e, functions)?;
+        Ok(())
+    })?;
+    Ok(expanded)
+}
+
+/// Replaces `e` with the body of the function if it is a call of a temporary function. Arguments
+/// are already expanded.
+fn expand_call(e: &mut Expr, functions: &TemporaryFunctions) -> Result<bool, CubeError> {
+    let f = match e {
+        Expr::Function(f) if f.name.0.len() == 1 => f,
+        _ => return Ok(false),
+    };
+    let function = match functions.get(&f.name.0[0].value.to_lowercase()) {
+        Some(function) => function,
+        None => return Ok(false),
+    };
+    if f.distinct || f.over.is_some() {
+        return Err(CubeError::user(format!(
+            "DISTINCT and OVER are not allowed in calls of temporary function {}",
+            f.name
+        )));
+    }
+    if f.args.len() != function.params.len() {
+        return Err(CubeError::user(format!(
+            "Temporary function {} expects {} arguments, found {}",
+            f.name,
+            function.params.len(),
+            f.args.len()
+        )));
+    }
+    let mut args = HashMap::new();
+    for (param, arg) in function.params.iter().zip(f.args.iter()) {
+        match arg {
+            FunctionArg::Unnamed(arg) => args.insert(param.as_str(), arg),
+            FunctionArg::Named { .. } => {
+                return Err(CubeError::user(format!(
+                    "Named arguments are not allowed in calls of temporary function {}",
+                    f.name
+                )))
+            }
+        };
+    }
+    let mut body = function.body.clone();
+    visit_expr(&mut body, &mut |e| {
+        if let Expr::Identifier(i) = e {
+            if let Some(arg) = args.get(i.value.to_lowercase().as_str()) {
+                *e = Expr::Nested(Box::new((*arg).clone()));
+            }
+        }
+        Ok(())
+    })?;
+    *e = Expr::Nested(Box::new(body));
+    Ok(true)
+}
+
+/// Calls `f` on all expressions of the query, children go first.
+pub(crate) fn visit_query<F>(q: &mut Query, f: &mut F) -> Result<(), CubeError>
+where
+    F: FnMut(&mut Expr) -> Result<(), CubeError>,
+{
+    if let Some(with) = q.with.as_mut() {
+        for cte in with.cte_tables.iter_mut() {
+            visit_query(&mut cte.query, f)?;
+        }
+    }
+    visit_set_expr(&mut q.body, f)?;
+    for o in q.order_by.iter_mut() {
+        visit_expr(&mut o.expr, f)?;
+    }
+    Ok(())
+}
+
+fn visit_set_expr<F>(e: &mut SetExpr, f: &mut F) -> Result<(), CubeError>
+where
+    F: FnMut(&mut Expr) -> Result<(), CubeError>,
+{
+    match e {
+        SetExpr::Select(s) => {
+            for item in s.projection.iter_mut() {
+                match item {
+                    SelectItem::UnnamedExpr(e) | SelectItem::ExprWithAlias { expr: e, .. } => {
+                        visit_expr(e, f)?
+                    }
+                    _ => {}
+                }
+            }
+            for t in s.from.iter_mut() {
+                visit_table_with_joins(t, f)?;
+            }
+            if let Some(e) = s.selection.as_mut() {
+                visit_expr(e, f)?;
+            }
+            for e in s.group_by.iter_mut() {
+                visit_expr(e, f)?;
+            }
+            if let Some(e) = s.having.as_mut() {
+                visit_expr(e, f)?;
+            }
+        }
+        SetExpr::Query(q) => visit_query(q, f)?,
+        SetExpr::SetOperation { left, right, .. } => {
+            visit_set_expr(left, f)?;
+            visit_set_expr(right, f)?;
+        }
+        SetExpr::Values(Values(rows)) => {
+            for e in rows.iter_mut().flatten() {
+                visit_expr(e, f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn visit_table_with_joins<F>(t: &mut TableWithJoins, f: &mut F) -> Result<(), CubeError>
+where
+    F: FnMut(&mut Expr) -> Result<(), CubeError>,
+{
+    visit_table_factor(&mut t.relation, f)?;
+    for j in t.joins.iter_mut() {
+        visit_table_factor(&mut j.relation, f)?;
+        match &mut j.join_operator {
+            JoinOperator::Inner(JoinConstraint::On(on))
+            | JoinOperator::LeftOuter(JoinConstraint::On(on))
+            | JoinOperator::RightOuter(JoinConstraint::On(on))
+            | JoinOperator::FullOuter(JoinConstraint::On(on)) => visit_expr(on, f)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn visit_table_factor<F>(t: &mut TableFactor, f: &mut F) -> Result<(), CubeError>
+where
+    F: FnMut(&mut Expr) -> Result<(), CubeError>,
+{
+    match t {
+        TableFactor::Derived { subquery, .. } => visit_query(subquery, f),
+        TableFactor::NestedJoin(t) => visit_table_with_joins(t, f),
+        _ => Ok(()),
+    }
+}
+
+fn visit_expr<F>(e: &mut Expr, f: &mut F) -> Result<(), CubeError>
+where
+    F: FnMut(&mut Expr) -> Result<(), CubeError>,
+{
+    match e {
+        Expr::BinaryOp { left, right, .. } => {
+            visit_expr(left, f)?;
+            visit_expr(right, f)?;
+        }
+        Expr::UnaryOp { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::TryCast { expr, .. }
+        | Expr::Nested(expr)
+        | Expr::IsNull(expr)
+        | Expr::IsNotNull(expr)
+        | Expr::Extract { expr, .. }
+        | Expr::Collate { expr, .. } => visit_expr(expr, f)?,
+        Expr::Between {
+            expr, low, high, ..
+        } => {
+            visit_expr(expr, f)?;
+            visit_expr(low, f)?;
+            visit_expr(high, f)?;
+        }
+        Expr::InList { expr, list, .. } => {
+            visit_expr(expr, f)?;
+            for e in list.iter_mut() {
+                visit_expr(e, f)?;
+            }
+        }
+        Expr::InSubquery { expr, subquery, .. } => {
+            visit_expr(expr, f)?;
+            visit_query(subquery, f)?;
+        }
+        Expr::Subquery(q) | Expr::Exists(q) => visit_query(q, f)?,
+        Expr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            if let Some(e) = operand {
+                visit_expr(e, f)?;
+            }
+            for e in conditions.iter_mut().chain(results.iter_mut()) {
+                visit_expr(e, f)?;
+            }
+            if let Some(e) = else_result {
+                visit_expr(e, f)?;
+            }
+        }
+        Expr::Function(func) => {
+            for a in func.args.iter_mut() {
+                match a {
+                    FunctionArg::Unnamed(a) | FunctionArg::Named { arg: a, .. } => {
+                        visit_expr(a, f)?
+                    }
+                }
+            }
+            if let Some(over) = func.over.as_mut() {
+                for e in over.partition_by.iter_mut() {
+                    visit_expr(e, f)?;
+                }
+                for o in over.order_by.iter_mut() {
+                    visit_expr(&mut o.expr, f)?;
+                }
+            }
+        }
+        _ => {}
+    }
+    f(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sql::parser::CubeStoreParser;
+
+    fn parse(s: &str) -> CubeStoreStatement {
+        CubeStoreParser::new(s).unwrap().parse_statement().unwrap()
+    }
+
+    fn create(functions: &mut TemporaryFunctions, s: &str) -> Result<(), CubeError> {
+        match parse(s) {
+            CubeStoreStatement::CreateTemporaryFunction {
+                name,
+                or_replace,
+                params,
+                body,
+            } => create_temporary_function(functions, &name, &params, body, or_replace),
+            s => panic!("unexpected statement: {:?}", s),
+        }
+    }
+
+    fn expand(functions: &TemporaryFunctions, s: &str) -> Result<String, CubeError> {
+        let mut s = parse(s);
+        expand_temporary_functions(&mut s, functions)?;
+        match s {
+            CubeStoreStatement::Statement(s) => Ok(s.to_string()),
+            s => panic!("unexpected statement: {:?}", s),
+        }
+    }
+
+    #[test]
+    fn expand_calls() {
+        let mut functions = TemporaryFunctions::new();
+        create(
+            &mut functions,
+            "CREATE TEMPORARY FUNCTION bucket(v, size) AS floor(v / size) * size",
+        )
+        .unwrap();
+        create(
+            &mut functions,
+            "CREATE TEMPORARY FUNCTION Hundreds(v) AS BUCKET(v, 100)",
+        )
+        .unwrap();
+
+        assert_eq!(
+            expand(
+                &functions,
+                "SELECT bucket(amount, 10), count(*) FROM s.orders \
+                 WHERE hundreds(amount + 1) > 0 GROUP BY 1 ORDER BY bucket(amount, 10)"
+            )
+            .unwrap(),
+            "SELECT (floor((amount) / (10)) * (10)), count(*) FROM s.orders \
+             WHERE ((floor(((amount + 1)) / (100)) * (100))) > 0 GROUP BY 1 \
+             ORDER BY (floor((amount) / (10)) * (10))"
+        );
+        assert_eq!(
+            expand(
+                &functions,
+                "SELECT * FROM (SELECT bucket(bucket(a, 2), 4) AS b FROM s.t) AS x"
+            )
+            .unwrap(),
+            "SELECT * FROM (SELECT (floor(((floor((a) / (2)) * (2))) / (4)) * (4)) AS b \
+             FROM s.t) AS x"
+        );
+        // Functions defined later do not change earlier definitions.
+        create(
+            &mut functions,
+            "CREATE OR REPLACE TEMPORARY FUNCTION bucket(v, size) AS v",
+        )
+        .unwrap();
+        assert_eq!(
+            expand(&functions, "SELECT hundreds(a), bucket(a, 1) FROM s.t").unwrap(),
+            "SELECT ((floor((a) / (100)) * (100))), ((a)) FROM s.t"
+        );
+
+        assert!(expand(&functions, "SELECT bucket(a) FROM s.t").is_err());
+        assert!(create(&mut functions, "CREATE TEMPORARY FUNCTION bucket(v) AS v").is_err());
+        assert!(create(&mut functions, "CREATE TEMPORARY FUNCTION f(v) AS v + w").is_err());
+        assert!(create(&mut functions, "CREATE TEMPORARY FUNCTION f(v, V) AS v").is_err());
+        assert!(create(
+            &mut functions,
+            "CREATE TEMPORARY FUNCTION f(v) AS v IN (SELECT 1)"
+        )
+        .is_err());
+
+        drop_temporary_function(&mut functions, &Ident::new("BUCKET")).unwrap();
+        assert!(drop_temporary_function(&mut functions, &Ident::new("bucket")).is_err());
+        assert_eq!(
+            expand(&functions, "SELECT bucket(a, 1) FROM s.t").unwrap(),
+            "SELECT bucket(a, 1) FROM s.t"
+        );
+    }
+}
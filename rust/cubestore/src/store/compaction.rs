@@ -1,21 +1,25 @@
 use crate::config::injection::DIService;
 use crate::config::ConfigObj;
-use crate::metastore::MetaStore;
+use crate::metastore::job::JobResult;
+use crate::metastore::{Chunk, ColumnBounds, IdRow, Index, MetaStore, Partition};
 use crate::remotefs::RemoteFs;
 use crate::store::ChunkDataStore;
-use crate::table::data::{cmp_row_key, RowsView, TableValueR};
+use crate::table::data::{cmp_row_key, Rows, RowsView, TableValueR};
 use crate::table::parquet::ParquetTableStore;
-use crate::table::TableStore;
+use crate::table::{Row, TableStore, TableValue};
+use crate::util::job_progress::report_job_progress;
 use crate::CubeError;
 use async_trait::async_trait;
 use itertools::{EitherOrBoth, Itertools};
 use num::integer::div_ceil;
+use std::cmp::Ordering;
+use std::iter::{once, repeat};
 use std::mem::swap;
 use std::sync::Arc;
 
 #[async_trait]
 pub trait CompactionService: DIService + Send + Sync {
-    async fn compact(&self, partition_id: u64) -> Result<(), CubeError>;
+    async fn compact(&self, partition_id: u64) -> Result<JobResult, CubeError>;
 }
 
 pub struct CompactionServiceImpl {
@@ -45,7 +49,7 @@ impl CompactionServiceImpl {
 
 #[async_trait]
 impl CompactionService for CompactionServiceImpl {
-    async fn compact(&self, partition_id: u64) -> Result<(), CubeError> {
+    async fn compact(&self, partition_id: u64) -> Result<JobResult, CubeError> {
         let mut chunks = self
             .meta_store
             .get_chunks_by_partition(partition_id, false)
@@ -68,20 +72,55 @@ impl CompactionService for CompactionServiceImpl {
             .meta_store
             .get_partition_for_compaction(partition_id)
             .await?;
+        // Files of attached partitions belong to another cluster, chunks added to them stay as is.
+        if partition.get_row().attached_file().is_some() {
+            return Ok(JobResult::default());
+        }
         let partition_id = partition.get_id();
         let chunks_row_count = chunks
             .iter()
             .map(|c| c.get_row().get_row_count())
             .sum::<u64>();
         let total_count = partition.get_row().main_table_row_count() + chunks_row_count;
-        let new_partitions_count =
-            div_ceil(total_count, self.config.partition_split_threshold()) as usize;
+        // Partitions of co-located tables are split at the boundaries of partitions they are
+        // co-located with first. Partitions that fit into one of them are split by size.
+        let colocation = self.colocation(&index).await?;
+        let split_keys = match &colocation {
+            Some(c) => c.split_keys(partition.get_row()),
+            None => Vec::new(),
+        };
+        let split_ranges = key_ranges(partition.get_row(), &split_keys);
+        let new_partitions_count = if split_keys.is_empty() {
+            div_ceil(total_count, self.config.partition_split_threshold()) as usize
+        } else {
+            split_keys.len() + 1
+        };
 
+        // Bounds of a partition that is rewritten as a whole are known without reading the data.
+        let column_bounds = if split_keys.is_empty() && new_partitions_count == 1 {
+            merged_bounds(partition.get_row(), &chunks)
+        } else {
+            None
+        };
         let mut new_partitions = Vec::new();
-        for _ in 0..new_partitions_count {
+        for i in 0..new_partitions_count {
+            let (min, max) = if split_keys.is_empty() {
+                &split_ranges[0]
+            } else {
+                &split_ranges[i]
+            };
+            let colocated_partition_id = colocation
+                .as_ref()
+                .and_then(|c| c.partition_containing(min, max));
             new_partitions.push(
                 self.meta_store
-                    .create_partition(partition.get_row().child(partition.get_id()))
+                    .create_partition(
+                        partition
+                            .get_row()
+                            .child(partition.get_id())
+                            .set_colocated_partition_id(colocated_partition_id)
+                            .set_column_bounds(column_bounds.clone()),
+                    )
                     .await?,
             );
         }
@@ -89,14 +128,23 @@ impl CompactionService for CompactionServiceImpl {
         let mut data = Vec::new();
         let mut total_data_rows = 0;
         let num_columns = index.get_row().columns().len();
-        for chunk in chunks.iter() {
+        // Chunks are read one by one, then merged and uploaded.
+        let steps = chunks.len() as u64 + 2;
+        for (i, chunk) in chunks.iter().enumerate() {
             let d = self.chunk_store.get_chunk(chunk.clone()).await?;
             assert_eq!(num_columns, d.num_columns());
             total_data_rows += d.num_rows();
             data.push(d);
+            report_job_progress(i as u64 + 1, Some(steps));
         }
 
-        let store = ParquetTableStore::new(index.get_row().clone(), 16384); // TODO config
+        let table = self
+            .meta_store
+            .get_table_by_id(index.get_row().table_id())
+            .await?;
+        let store = ParquetTableStore::new(index.get_row().clone(), 16384) // TODO config
+            .set_deduplicate(table.get_row().deduplicate());
+        let input_rows = partition.get_row().main_table_row_count() + total_data_rows as u64;
         let old_partition_local =
             if let Some(f) = partition.get_row().get_full_name(partition.get_id()) {
                 Some(self.remote_fs.download_file(&f).await?)
@@ -111,19 +159,36 @@ impl CompactionService for CompactionServiceImpl {
         }
 
         let new_partition_file_names = new_partition_local_files.clone();
+        let sort_key_size = index.get_row().sort_key_size() as usize;
+        if !split_keys.is_empty() {
+            let count_and_min_max = tokio::task::spawn_blocking(move || {
+                let merge_buffer = sorted_rows(&data, total_data_rows, num_columns, sort_key_size);
+                store.merge_rows_split_at(
+                    old_partition_local.as_ref().map(|s| s.as_str()),
+                    new_partition_file_names,
+                    RowsView::new(&merge_buffer, num_columns),
+                    sort_key_size,
+                    split_keys,
+                )
+            })
+            .await??;
+            report_job_progress(steps - 1, Some(steps));
+            let result = JobResult {
+                removed_duplicates: removed_rows(input_rows, count_and_min_max.iter().map(|c| c.0)),
+            };
+            self.swap_split_partitions(
+                partition,
+                chunks,
+                new_partitions,
+                new_partition_local_files,
+                split_ranges,
+                count_and_min_max,
+            )
+            .await?;
+            return Ok(result);
+        }
         let count_and_min_max = tokio::task::spawn_blocking(move || {
-            let mut merge_buffer = Vec::with_capacity(total_data_rows * num_columns);
-            for d in &data {
-                merge_buffer.extend_from_slice(d.all_values());
-            }
-            let sort_key_size = index.get_row().sort_key_size() as usize;
-            sort_rows(
-                &mut merge_buffer,
-                total_data_rows,
-                num_columns,
-                sort_key_size,
-            );
-
+            let merge_buffer = sorted_rows(&data, total_data_rows, num_columns, sort_key_size);
             let rows = RowsView::new(&merge_buffer, num_columns);
             store.merge_rows(
                 old_partition_local.as_ref().map(|s| s.as_str()),
@@ -133,6 +198,10 @@ impl CompactionService for CompactionServiceImpl {
             )
         })
         .await??;
+        report_job_progress(steps - 1, Some(steps));
+        let result = JobResult {
+            removed_duplicates: removed_rows(input_rows, count_and_min_max.iter().map(|c| c.0)),
+        };
 
         let mut filtered_partitions = Vec::new();
 
@@ -229,8 +298,236 @@ impl CompactionService for CompactionServiceImpl {
             )
             .await?;
 
-        Ok(())
+        Ok(result)
+    }
+}
+
+impl CompactionServiceImpl {
+    async fn colocation(&self, index: &IdRow<Index>) -> Result<Option<Colocation>, CubeError> {
+        let table = self
+            .meta_store
+            .get_table_by_id(index.get_row().table_id())
+            .await?;
+        let root = match table.get_row().colocate_with() {
+            Some(root) => *root,
+            None => return Ok(None),
+        };
+        let default_index = self.meta_store.get_default_index(table.get_id()).await?;
+        if default_index.get_id() != index.get_id() {
+            return Ok(None);
+        }
+        // Co-location ends if the table it is co-located with is dropped.
+        let root_index = match self.meta_store.get_default_index(root).await {
+            Ok(i) => i,
+            Err(_) => return Ok(None),
+        };
+        let key_size = index.get_row().colocation_key_size(root_index.get_row());
+        let sort_key_size = index.get_row().sort_key_size() as usize;
+        // Only the common key prefix is kept, the rest is nulls, which sort first.
+        let align = |r: &Option<Row>| {
+            r.as_ref().map(|r| {
+                Row::new(
+                    r.values()[..key_size]
+                        .iter()
+                        .cloned()
+                        .chain(repeat(TableValue::Null).take(sort_key_size - key_size))
+                        .collect(),
+                )
+            })
+        };
+        let partitions = self
+            .meta_store
+            .get_active_partitions_by_index_id(root_index.get_id())
+            .await?
+            .into_iter()
+            .map(|p| {
+                (
+                    p.get_id(),
+                    align(p.get_row().get_min_val()),
+                    align(p.get_row().get_max_val()),
+                )
+            })
+            .collect();
+        Ok(Some(Colocation {
+            partitions,
+            sort_key_size,
+        }))
+    }
+
+    /// Finishes compaction of a partition that was split at `split_keys` of its [Colocation].
+    /// Partitions that got no rows are dropped, their ranges are taken by the preceding ones.
+    async fn swap_split_partitions(
+        &self,
+        partition: IdRow<Partition>,
+        chunks: Vec<IdRow<Chunk>>,
+        new_partitions: Vec<IdRow<Partition>>,
+        new_partition_local_files: Vec<String>,
+        ranges: Vec<(Option<Row>, Option<Row>)>,
+        count_and_min_max: Vec<(u64, Option<(Row, Row)>)>,
+    ) -> Result<(), CubeError> {
+        let last = new_partitions.len() - 1;
+        let mut new_active = Vec::new();
+        for (i, ((p, local_file), ((min, _), (count, _)))) in new_partitions
+            .into_iter()
+            .zip(new_partition_local_files.iter())
+            .zip(ranges.into_iter().zip(count_and_min_max.into_iter()))
+            .enumerate()
+        {
+            // At least one partition must stay to cover the range.
+            if count == 0 && !(i == last && new_active.is_empty()) {
+                self.meta_store.delete_partition(p.get_id()).await?;
+                let _ = tokio::fs::remove_file(local_file).await;
+                continue;
+            }
+            let new_remote_path = p.get_row().get_full_name(p.get_id()).unwrap();
+            self.remote_fs
+                .upload_file(local_file, new_remote_path.as_str())
+                .await?;
+            new_active.push((p.get_id(), count, min));
+        }
+
+        let mut new_active_min_max = Vec::new();
+        for (i, (_, count, min)) in new_active.iter().enumerate() {
+            let min = if i == 0 {
+                partition.get_row().get_min_val().clone()
+            } else {
+                min.clone()
+            };
+            let max = match new_active.get(i + 1) {
+                Some((_, _, next_min)) => next_min.clone(),
+                None => partition.get_row().get_max_val().clone(),
+            };
+            new_active_min_max.push((*count, (min, max)));
+        }
+        self.meta_store
+            .swap_active_partitions(
+                vec![partition.get_id()],
+                new_active.iter().map(|(id, _, _)| *id).collect(),
+                chunks.iter().map(|c| c.get_id()).collect(),
+                new_active_min_max,
+            )
+            .await
+    }
+}
+
+/// Partitions of the table a partition is co-located with. Their key ranges are converted to the
+/// sort key of the co-located table.
+struct Colocation {
+    partitions: Vec<(u64, Option<Row>, Option<Row>)>,
+    sort_key_size: usize,
+}
+
+impl Colocation {
+    /// Sorted lower bounds of partitions that are inside the range of `partition`.
+    fn split_keys(&self, partition: &Partition) -> Vec<Row> {
+        let mut keys = self
+            .partitions
+            .iter()
+            .filter_map(|(_, min, _)| min.as_ref())
+            .filter(|k| {
+                self.above_min(k, partition.get_min_val())
+                    && self.below_max(k, partition.get_max_val())
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort_by(|a, b| self.cmp(a, b));
+        keys.dedup();
+        keys
+    }
+
+    /// The partition whose range contains the `[min, max)` range.
+    fn partition_containing(&self, min: &Option<Row>, max: &Option<Row>) -> Option<u64> {
+        self.partitions
+            .iter()
+            .find(|(_, p_min, p_max)| {
+                let min_inside = match (p_min, min) {
+                    (None, _) => true,
+                    (Some(_), None) => false,
+                    (Some(p_min), Some(min)) => self.cmp(p_min, min) != Ordering::Greater,
+                };
+                let max_inside = match (max, p_max) {
+                    (_, None) => true,
+                    (None, Some(_)) => false,
+                    (Some(max), Some(p_max)) => self.cmp(max, p_max) != Ordering::Greater,
+                };
+                min_inside && max_inside
+            })
+            .map(|(id, _, _)| *id)
+    }
+
+    /// Whether `key` is strictly above the lower bound `min`.
+    fn above_min(&self, key: &Row, min: &Option<Row>) -> bool {
+        match min {
+            None => true,
+            Some(min) => self.cmp(min, key) == Ordering::Less,
+        }
+    }
+
+    /// Whether `key` is strictly below the upper bound `max`.
+    fn below_max(&self, key: &Row, max: &Option<Row>) -> bool {
+        match max {
+            None => true,
+            Some(max) => self.cmp(key, max) == Ordering::Less,
+        }
+    }
+
+    fn cmp(&self, a: &Row, b: &Row) -> Ordering {
+        a.sort_key(self.sort_key_size as u64)
+            .cmp(&b.sort_key(self.sort_key_size as u64))
+    }
+}
+
+/// Ranges of the partition between `split_keys`.
+/// [None] if bounds of the partition file or of any of the chunks are unknown.
+fn merged_bounds(partition: &Partition, chunks: &[IdRow<Chunk>]) -> Option<ColumnBounds> {
+    if !partition.deleted_rows().is_empty() {
+        return None;
     }
+    let mut bounds = if partition.main_table_row_count() > 0 {
+        Some(partition.column_bounds().clone()?)
+    } else {
+        None
+    };
+    for c in chunks {
+        let chunk_bounds = c.get_row().column_bounds().as_ref()?;
+        bounds = Some(match bounds {
+            Some(b) => b.merge(chunk_bounds),
+            None => chunk_bounds.clone(),
+        });
+    }
+    bounds
+}
+
+/// Rows dropped while merging, i.e. duplicates removed by
+/// [crate::metastore::table::Table::deduplicate].
+fn removed_rows(input_rows: u64, output_rows: impl Iterator<Item = u64>) -> u64 {
+    input_rows.saturating_sub(output_rows.sum())
+}
+
+fn key_ranges(partition: &Partition, split_keys: &[Row]) -> Vec<(Option<Row>, Option<Row>)> {
+    let bounds = once(partition.get_min_val().clone())
+        .chain(split_keys.iter().cloned().map(Some))
+        .chain(once(partition.get_max_val().clone()))
+        .collect::<Vec<_>>();
+    bounds
+        .windows(2)
+        .map(|w| (w[0].clone(), w[1].clone()))
+        .collect()
+}
+
+/// Rows of all `data` sorted by the sort key.
+fn sorted_rows(
+    data: &'a [Rows],
+    num_rows: usize,
+    num_columns: usize,
+    sort_key_size: usize,
+) -> Vec<TableValueR<'a>> {
+    let mut merge_buffer = Vec::with_capacity(num_rows * num_columns);
+    for d in data {
+        merge_buffer.extend_from_slice(d.all_values());
+    }
+    sort_rows(&mut merge_buffer, num_rows, num_columns, sort_key_size);
+    merge_buffer
 }
 
 fn sort_rows(
@@ -286,6 +583,11 @@ mod tests {
                 None,
                 vec![],
                 true,
+                None,
+                None,
+                None,
+                vec![],
+                false,
             )
             .await
             .unwrap();
@@ -0,0 +1,103 @@
+//! Leases on partition and chunk files read by in-flight queries and index builds. Workers read
+//! files long after the router picked them, meanwhile DROP TABLE or compaction may remove the
+//! partitions and chunks from the metastore. The router holds a lease on every file of a query
+//! until it completes, and the scheduler postpones deletion of leased files until their leases
+//! are released, see [crate::scheduler::SchedulerImpl].
+//!
+//! Selects lease files as soon as the planner reads partitions and chunks from the metastore, see
+//! [crate::queryplanner::read_snapshot::SnapshotIndexStore], and keep the lease in the plan, see
+//! [crate::queryplanner::serialized_plan::SerializedPlan::file_lease].
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+pub struct DataFileLeases {
+    /// Number of leases held on each file.
+    files: Mutex<HashMap<String, usize>>,
+}
+
+crate::di_service!(DataFileLeases, []);
+
+impl DataFileLeases {
+    pub fn new() -> Arc<DataFileLeases> {
+        Arc::new(DataFileLeases {
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Files stay leased until the returned lease is dropped.
+    pub fn acquire(self: &Arc<Self>, files: Vec<String>) -> DataFileLease {
+        let mut leased = self.files.lock().unwrap();
+        for f in files.iter() {
+            *leased.entry(f.clone()).or_insert(0) += 1;
+        }
+        DataFileLease {
+            leases: self.clone(),
+            files,
+        }
+    }
+
+    pub fn is_leased(&self, file: &str) -> bool {
+        self.files.lock().unwrap().contains_key(file)
+    }
+
+    fn release(&self, files: &[String]) {
+        let mut leased = self.files.lock().unwrap();
+        for f in files {
+            if let Some(count) = leased.get_mut(f) {
+                *count -= 1;
+                if *count == 0 {
+                    leased.remove(f);
+                }
+            }
+        }
+    }
+}
+
+pub struct DataFileLease {
+    leases: Arc<DataFileLeases>,
+    files: Vec<String>,
+}
+
+impl DataFileLease {
+    /// Holds files of `other` until this lease is dropped.
+    pub fn extend(&mut self, mut other: DataFileLease) {
+        self.files.append(&mut other.files);
+    }
+}
+
+impl fmt::Debug for DataFileLease {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataFileLease")
+            .field("files", &self.files.len())
+            .finish()
+    }
+}
+
+impl Drop for DataFileLease {
+    fn drop(&mut self) {
+        self.leases.release(&self.files);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn released_with_last_lease() {
+        let leases = DataFileLeases::new();
+        let first = leases.acquire(vec!["1.parquet".to_string(), "2.chunk.parquet".to_string()]);
+        let second = leases.acquire(vec!["1.parquet".to_string()]);
+        assert!(leases.is_leased("1.parquet"));
+        assert!(leases.is_leased("2.chunk.parquet"));
+        assert!(!leases.is_leased("3.parquet"));
+
+        drop(first);
+        assert!(leases.is_leased("1.parquet"));
+        assert!(!leases.is_leased("2.chunk.parquet"));
+
+        drop(second);
+        assert!(!leases.is_leased("1.parquet"));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod compaction;
+pub mod leases;
+pub mod repair;
 
 use async_trait::async_trait;
 use datafusion::physical_plan::memory::MemoryExec;
@@ -9,7 +11,7 @@ extern crate bincode;
 use bincode::{deserialize_from, serialize_into};
 
 use crate::metastore::{
-    table::Table, Chunk, Column, ColumnType, IdRow, Index, MetaStore, Partition, WAL,
+    table::Table, Chunk, Column, ColumnBounds, ColumnType, IdRow, Index, MetaStore, WAL,
 };
 use crate::remotefs::RemoteFs;
 use crate::table::{Row, TableStore, TableValue};
@@ -24,6 +26,7 @@ use std::{
 use crate::config::injection::DIService;
 use crate::table::data::{cmp_row_key, cmp_row_key_heap, MutRows, Rows};
 use crate::table::parquet::ParquetTableStore;
+use crate::util::job_progress::report_job_progress;
 use arrow::array::{Array, Int64Builder, StringBuilder};
 use arrow::record_batch::RecordBatch;
 use futures::future::join_all;
@@ -88,7 +91,7 @@ impl DataFrame {
                     }
                     column_values.push(Arc::new(column.finish()));
                 }
-                ColumnType::Int => {
+                ColumnType::Int | ColumnType::Enum(_) => {
                     let mut column = Int64Builder::new(self.data.len());
                     for i in 0..self.data.len() {
                         let value = &self.data[i].values()[c.get_index()];
@@ -188,6 +191,13 @@ pub trait ChunkDataStore: DIService + Send + Sync {
         rows: Rows,
         columns: &[Column],
     ) -> Result<Vec<ChunkUploadJob>, CubeError>;
+    /// Same as [ChunkDataStore::partition_data] for a single index, columns of `rows` are in the
+    /// order of the index.
+    async fn partition_index_data(
+        &self,
+        index_id: u64,
+        rows: Rows,
+    ) -> Result<Vec<ChunkUploadJob>, CubeError>;
     async fn repartition(&self, partition_id: u64) -> Result<(), CubeError>;
     async fn get_chunk(&self, chunk: IdRow<Chunk>) -> Result<Rows, CubeError>;
     async fn download_chunk(&self, chunk: IdRow<Chunk>) -> Result<String, CubeError>;
@@ -284,11 +294,7 @@ impl ChunkStore {
     }
 
     pub fn chunk_file_name(chunk: IdRow<Chunk>) -> String {
-        Self::chunk_remote_path(chunk.get_id())
-    }
-
-    pub fn chunk_remote_path(chunk_id: u64) -> String {
-        format!("{}.chunk.parquet", chunk_id)
+        chunk.get_row().get_full_name(chunk.get_id())
     }
 }
 
@@ -304,6 +310,14 @@ impl ChunkDataStore for ChunkStore {
         self.build_index_chunks(&indexes, rows, columns).await
     }
 
+    async fn partition_index_data(
+        &self,
+        index_id: u64,
+        rows: Rows,
+    ) -> Result<Vec<ChunkUploadJob>, CubeError> {
+        self.partition_data_frame(index_id, rows).await
+    }
+
     async fn partition(&self, wal_id: u64) -> Result<(), CubeError> {
         let wal = self.meta_store.get_wal(wal_id).await?;
         let table_id = wal.get_row().table_id();
@@ -340,7 +354,8 @@ impl ChunkDataStore for ChunkStore {
             .await?;
         let mut new_chunks = Vec::new();
         let mut old_chunks = Vec::new();
-        for chunk in chunks.into_iter() {
+        let total = chunks.len() as u64;
+        for (i, chunk) in chunks.into_iter().enumerate() {
             let chunk_id = chunk.get_id();
             old_chunks.push(chunk_id);
             let rows = self.get_chunk(chunk).await?;
@@ -348,7 +363,8 @@ impl ChunkDataStore for ChunkStore {
                 &mut self
                     .partition_data_frame(partition.get_row().get_index_id(), rows)
                     .await?,
-            )
+            );
+            report_job_progress(i as u64 + 1, Some(total));
         }
 
         let new_chunk_ids: Result<Vec<u64>, CubeError> = join_all(new_chunks)
@@ -417,6 +433,26 @@ impl ChunkDataStore for ChunkStore {
     }
 }
 
+/// Reads rows of a partition or chunk file of `index`. Columns are matched by name and returned
+/// in the order of `columns`.
+pub async fn read_remote_rows(
+    remote_fs: &dyn RemoteFs,
+    index: &Index,
+    remote_path: &str,
+    columns: &[Column],
+) -> Result<Vec<Row>, CubeError> {
+    remote_fs.download_file(remote_path).await?;
+    let local_file = remote_fs.local_file(remote_path).await?;
+    let index = index.clone();
+    let columns = columns.to_vec();
+    tokio::task::spawn_blocking(move || -> Result<Vec<Row>, CubeError> {
+        let rows = ParquetTableStore::new(index.clone(), 16384).read_rows(&local_file)?;
+        let rows = remap_columns(&rows, index.get_columns(), &columns)?;
+        Ok(rows.view().convert_to_heap_allocated())
+    })
+    .await?
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -482,6 +518,11 @@ mod tests {
                     None,
                     Vec::new(),
                     true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
                 )
                 .await
                 .unwrap();
@@ -559,6 +600,11 @@ mod tests {
                     None,
                     vec![],
                     true,
+                    None,
+                    None,
+                    None,
+                    vec![],
+                    false,
                 )
                 .await
                 .unwrap();
@@ -586,8 +632,13 @@ mod tests {
                 rows.add_row_heap_allocated(&r);
             }
 
+            let rows = rows.freeze();
+            let chunk = meta_store
+                .create_chunk(partition.get_id(), rows.num_rows())
+                .await
+                .unwrap();
             let chunk = chunk_store
-                .add_chunk(index, partition, rows.freeze())
+                .write_chunk(index, chunk, rows)
                 .await
                 .unwrap()
                 .await
@@ -641,7 +692,7 @@ impl ChunkStore {
             remaining_rows = remaining_rows_again;
         }
 
-        let mut new_chunks = Vec::new();
+        let mut chunks_to_write = Vec::new();
 
         for partition in partitions.into_iter() {
             let (to_write, next) = remaining_rows.into_iter().partition::<Vec<_>, _>(|&r| {
@@ -665,31 +716,48 @@ impl ChunkStore {
                         .unwrap_or(true)
             });
             if to_write.len() > 0 {
-                new_chunks.push(
-                    self.add_chunk(index.clone(), partition, rows.copy_some_rows(&to_write))
-                        .await?,
-                );
+                chunks_to_write.push((partition, to_write));
             }
             remaining_rows = next;
         }
 
         assert_eq!(remaining_rows.len(), 0);
 
+        let chunk_defs = {
+            let view = rows.view();
+            chunks_to_write
+                .iter()
+                .map(|(p, to_write)| {
+                    let bounds = ColumnBounds::of_rows(
+                        rows.num_columns(),
+                        to_write.iter().map(|&r| &view[r]),
+                    );
+                    (p.get_id(), to_write.len(), Some(bounds))
+                })
+                .collect()
+        };
+        // Allocate all chunks of the index in one metastore write instead of one per partition.
+        let chunks = self.meta_store.create_chunks(chunk_defs).await?;
+
+        let mut new_chunks = Vec::with_capacity(chunks.len());
+        for (chunk, (_, to_write)) in chunks.into_iter().zip(chunks_to_write.into_iter()) {
+            new_chunks.push(
+                self.write_chunk(index.clone(), chunk, rows.copy_some_rows(&to_write))
+                    .await?,
+            );
+        }
+
         Ok(new_chunks)
     }
 
-    /// Processes data into parquet files in the current task and schedules an async file upload.
-    /// Join the returned handle to wait for the upload to finish.
-    async fn add_chunk(
+    /// Processes data of an already allocated chunk into parquet files in the current task and
+    /// schedules an async file upload. Join the returned handle to wait for the upload to finish.
+    async fn write_chunk(
         &'a self,
         index: IdRow<Index>,
-        partition: IdRow<Partition>,
+        chunk: IdRow<Chunk>,
         data: Rows,
     ) -> Result<ChunkUploadJob, CubeError> {
-        let chunk = self
-            .meta_store
-            .create_chunk(partition.get_id(), data.num_rows())
-            .await?;
         trace!("New chunk allocated during partitioning: {:?}", chunk);
         let remote_path = ChunkStore::chunk_file_name(chunk.clone()).clone();
         let local_file = self.remote_fs.temp_upload_path(&remote_path).await?;
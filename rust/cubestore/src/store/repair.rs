@@ -0,0 +1,137 @@
+//! `SYSTEM REPAIR TABLE` checks that every file the metastore references for a table is present
+//! in the remote storage and can be read. Files missing from the remote storage are uploaded again
+//! from the local cache of the node running the command. Chunks that can not be restored are
+//! deactivated, so queries skip them instead of failing. Lost partition files are only reported,
+//! deactivating the partition would drop its whole key range.
+use crate::metastore::{IdRow, Index, MetaStore};
+use crate::remotefs::RemoteFs;
+use crate::table::parquet::parquet_row_count;
+use crate::CubeError;
+use log::{error, warn};
+use std::collections::HashSet;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct RepairSummary {
+    pub ok: Vec<String>,
+    pub reuploaded: Vec<String>,
+    pub lost: Vec<String>,
+}
+
+struct TableFile {
+    name: String,
+    chunk_id: Option<u64>,
+}
+
+pub async fn repair_table(
+    meta_store: &dyn MetaStore,
+    remote_fs: &dyn RemoteFs,
+    indexes: Vec<IdRow<Index>>,
+) -> Result<RepairSummary, CubeError> {
+    let mut files = Vec::new();
+    for index in indexes {
+        let mut partitions = meta_store
+            .get_active_partitions_by_index_id(index.get_id())
+            .await?;
+        // Inactive partitions can still have chunks waiting to be repartitioned.
+        partitions.extend(
+            meta_store
+                .get_inactive_partitions_by_index_id(index.get_id())
+                .await?,
+        );
+        for p in partitions {
+            let partition = p.get_row();
+            if partition.is_active() && partition.main_table_row_count() > 0 {
+                if let Some(name) = partition.get_full_name(p.get_id()) {
+                    files.push(TableFile {
+                        name,
+                        chunk_id: None,
+                    });
+                }
+            }
+            for c in meta_store
+                .get_chunks_by_partition(p.get_id(), false)
+                .await?
+            {
+                files.push(TableFile {
+                    name: c.get_row().get_full_name(c.get_id()),
+                    chunk_id: Some(c.get_id()),
+                });
+            }
+        }
+    }
+
+    let remote_files = remote_fs
+        .list("")
+        .await?
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let mut summary = RepairSummary::default();
+    let mut lost_chunks = Vec::new();
+    for f in files {
+        // Errors of the remote storage abort the repair, files are never marked lost because
+        // of them.
+        let checked = if remote_files.contains(&f.name) {
+            check_remote(remote_fs, &f.name).await?
+        } else {
+            warn!("File {} is missing in remote storage", f.name);
+            reupload(remote_fs, &f.name).await?
+        };
+        match checked {
+            FileCheck::Ok => summary.ok.push(f.name),
+            FileCheck::Reuploaded => summary.reuploaded.push(f.name),
+            FileCheck::Lost(e) => {
+                error!("File {} is lost: {}", f.name, e);
+                if let Some(id) = f.chunk_id {
+                    lost_chunks.push(id);
+                }
+                summary.lost.push(f.name);
+            }
+        }
+    }
+    if !lost_chunks.is_empty() {
+        meta_store.deactivate_chunks(lost_chunks).await?;
+    }
+    Ok(summary)
+}
+
+enum FileCheck {
+    Ok,
+    Reuploaded,
+    Lost(CubeError),
+}
+
+async fn check_remote(remote_fs: &dyn RemoteFs, name: &str) -> Result<FileCheck, CubeError> {
+    let local_path = remote_fs.download_file(name).await?;
+    if let Err(e) = check_file(local_path.clone()).await {
+        // The locally cached copy might be the broken one, check the remote file itself.
+        warn!("Cached copy of {} can't be read: {}", name, e);
+        tokio::fs::remove_file(&local_path).await?;
+        let local_path = remote_fs.download_file(name).await?;
+        if let Err(e) = check_file(local_path).await {
+            return Ok(FileCheck::Lost(e));
+        }
+    }
+    Ok(FileCheck::Ok)
+}
+
+async fn reupload(remote_fs: &dyn RemoteFs, name: &str) -> Result<FileCheck, CubeError> {
+    let local_path = remote_fs.local_file(name).await?;
+    if tokio::fs::metadata(&local_path).await.is_err() {
+        return Ok(FileCheck::Lost(CubeError::internal(
+            "File is not cached locally".to_string(),
+        )));
+    }
+    if let Err(e) = check_file(local_path.clone()).await {
+        return Ok(FileCheck::Lost(e));
+    }
+    let upload_path = remote_fs.temp_upload_path(name).await?;
+    tokio::fs::copy(&local_path, &upload_path).await?;
+    remote_fs.upload_file(&upload_path, name).await?;
+    Ok(FileCheck::Reuploaded)
+}
+
+/// Reads metadata of the parquet file to make sure it is not truncated or corrupted.
+async fn check_file(local_path: String) -> Result<(), CubeError> {
+    tokio::task::spawn_blocking(move || parquet_row_count(&local_path)).await??;
+    Ok(())
+}
@@ -0,0 +1,48 @@
+//! Memory usage of the process as seen by the OS, see [crate::util::memory_pressure]. Values are
+//! read from `/proc` and cgroup files, so they are only available on Linux.
+
+/// Resident set size of the process in bytes.
+pub fn resident_set_size() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    proc_kb_value(&status, "VmRSS:")
+}
+
+/// Memory limit of the cgroup the process runs in, e.g. of the container. Without one, this is
+/// the physical memory of the machine.
+pub fn memory_limit() -> Option<u64> {
+    // cgroup v2 first, then v1. Groups without a limit report `max` or a value close to u64::MAX.
+    for path in &[
+        "/sys/fs/cgroup/memory.max",
+        "/sys/fs/cgroup/memory/memory.limit_in_bytes",
+    ] {
+        if let Ok(limit) = std::fs::read_to_string(path) {
+            match limit.trim().parse::<u64>() {
+                Ok(limit) if limit < 1 << 60 => return Some(limit),
+                _ => {}
+            }
+        }
+    }
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    proc_kb_value(&meminfo, "MemTotal:")
+}
+
+/// Value of the `<name> <value> kB` line of files in `/proc`, in bytes.
+fn proc_kb_value(content: &str, name: &str) -> Option<u64> {
+    content.lines().find_map(|l| {
+        let value = l.strip_prefix(name)?.trim().strip_suffix("kB")?;
+        value.trim().parse::<u64>().ok().map(|kb| kb * 1024)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proc_values() {
+        let status = "Name:\tcubestored\nVmPeak:\t  204800 kB\nVmRSS:\t   10240 kB\nThreads:\t8\n";
+        assert_eq!(proc_kb_value(status, "VmRSS:"), Some(10240 * 1024));
+        assert_eq!(proc_kb_value(status, "VmSwap:"), None);
+        assert_eq!(proc_kb_value(status, "Threads:"), None);
+    }
+}
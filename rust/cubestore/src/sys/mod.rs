@@ -1 +1,2 @@
 pub mod malloc;
+pub mod memory;
@@ -37,8 +37,16 @@ impl TimestampValue {
 
 impl ToString for TimestampValue {
     fn to_string(&self) -> String {
+        // Sub-millisecond digits are only shown when present.
+        let format = if self.unix_nano % 1_000_000 == 0 {
+            SecondsFormat::Millis
+        } else if self.unix_nano % 1000 == 0 {
+            SecondsFormat::Micros
+        } else {
+            SecondsFormat::Nanos
+        };
         Utc.timestamp_nanos(self.unix_nano)
-            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .to_rfc3339_opts(format, true)
     }
 }
 
@@ -116,6 +124,7 @@ pub fn cmp_same_types(l: &TableValue, r: &TableValue) -> Ordering {
         (TableValue::String(a), TableValue::String(b)) => a.cmp(b),
         (TableValue::Int(a), TableValue::Int(b)) => a.cmp(b),
         (TableValue::Decimal(a), TableValue::Decimal(b)) => a.cmp(b),
+        (TableValue::Float(a), TableValue::Float(b)) => a.cmp(b),
         (TableValue::Bytes(a), TableValue::Bytes(b)) => a.cmp(b),
         (TableValue::Timestamp(a), TableValue::Timestamp(b)) => a.cmp(b),
         (TableValue::Boolean(a), TableValue::Boolean(b)) => a.cmp(b),
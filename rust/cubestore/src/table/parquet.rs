@@ -1,7 +1,8 @@
 use super::TimestampValue;
-use crate::metastore::{Column, ColumnType, Index};
+use crate::metastore::{Column, ColumnEncoding, ColumnType, Index};
 use crate::table::{Row, TableStore};
 use crate::CubeError;
+use parquet::basic::Encoding;
 use parquet::column::reader::ColumnReader;
 use parquet::column::writer::ColumnWriter;
 use parquet::data_type::*;
@@ -9,11 +10,15 @@ use parquet::file::properties::{WriterProperties, WriterVersion};
 use parquet::file::reader::{FileReader, SerializedFileReader};
 use parquet::file::writer::{FileWriter, SerializedFileWriter};
 use parquet::schema::types;
+use parquet::schema::types::ColumnPath;
 use std::cmp::{max, min, Ordering};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 
 use crate::table::data::{
-    cmp_row_key, cmp_row_key_heap, convert_row_to_heap_allocated, MutRows, Rows, RowsView,
+    cmp_row_key, cmp_row_key_heap, convert_row_to_heap_allocated, MutRows, RowR, Rows, RowsView,
     TableValueR,
 };
 use bigdecimal::{BigDecimal, Num, ToPrimitive};
@@ -24,10 +29,73 @@ use std::sync::Arc;
 pub struct ParquetTableStore {
     table: Index,
     row_group_size: usize,
+    /// Merges drop rows equal to a row written before, see [DuplicateFilter].
+    deduplicate: bool,
+}
+
+/// Drops rows equal to a row passed before. Rows come in the sort order, so only the rows with
+/// the sort key of the last row are kept for comparison, by their hash.
+struct DuplicateFilter {
+    sort_key_size: usize,
+    last_row: Option<Row>,
+    key_rows: HashMap<u64, Vec<Row>>,
+}
+
+impl DuplicateFilter {
+    fn new(sort_key_size: usize) -> DuplicateFilter {
+        DuplicateFilter {
+            sort_key_size,
+            last_row: None,
+            key_rows: HashMap::new(),
+        }
+    }
+
+    fn filter(&mut self, rows: RowsView) -> Rows {
+        let mut result = MutRows::with_capacity(rows.num_columns(), rows.len());
+        for r in rows.iter() {
+            let same_key = match &self.last_row {
+                Some(last) => {
+                    cmp_row_key_heap(self.sort_key_size, last.values(), r) == Ordering::Equal
+                }
+                None => false,
+            };
+            if !same_key {
+                self.key_rows.clear();
+            }
+            let row = convert_row_to_heap_allocated(r);
+            let same_hash = self.key_rows.entry(hash_row(r)).or_default();
+            if same_hash.contains(&row) {
+                continue;
+            }
+            same_hash.push(row.clone());
+            self.last_row = Some(row);
+            result.add_row_copy(r);
+        }
+        result.freeze()
+    }
+}
+
+fn hash_row(r: &RowR) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for v in r {
+        match v {
+            TableValueR::Null => 0u8.hash(&mut hasher),
+            TableValueR::String(s) => (1u8, s).hash(&mut hasher),
+            TableValueR::Int(i) => (2u8, i).hash(&mut hasher),
+            TableValueR::Decimal(d) => (3u8, d).hash(&mut hasher),
+            TableValueR::Float(f) => (4u8, f.0.to_bits()).hash(&mut hasher),
+            TableValueR::Bytes(b) => (5u8, b).hash(&mut hasher),
+            TableValueR::Timestamp(t) => (6u8, t).hash(&mut hasher),
+            TableValueR::Boolean(b) => (7u8, b).hash(&mut hasher),
+        }
+    }
+    hasher.finish()
 }
 
 pub struct RowParquetWriter {
     columns: Vec<Column>,
+    /// Source column and threshold of every hidden column, see [offloaded_columns].
+    offloaded: Vec<(usize, usize)>,
     parquet_writer: SerializedFileWriter<File>,
     buffer: MutRows,
     row_group_size: usize,
@@ -43,6 +111,52 @@ enum ColumnAccessor {
 pub struct RowParquetReader<'a> {
     pub parquet_reader: SerializedFileReader<File>,
     column_with_buffer: Vec<(&'a Column, usize, ColumnAccessor, Option<Vec<i16>>)>,
+    offloaded: Vec<OffloadedBuffer>,
+}
+
+/// Full values of offloaded strings of the column `col_i` of
+/// [RowParquetReader::column_with_buffer], read from the hidden column `file_index`.
+struct OffloadedBuffer {
+    col_i: usize,
+    file_index: usize,
+    buffer: Vec<ByteArray>,
+    def_levels: Vec<i16>,
+}
+
+/// Prefix of names of the hidden parquet columns that keep full values of offloaded strings.
+pub const OFFLOADED_COLUMN_PREFIX: &str = "__offloaded_";
+
+/// Columns with [ColumnEncoding::Offload] paired with the hidden columns of the same file that
+/// keep their strings longer than the threshold. The column itself keeps only a prefix of such
+/// strings and has no min and max values, so long strings are only read when the column is
+/// projected. Hidden columns follow the columns of the index, their [Column::get_index] is the
+/// position in the file.
+pub fn offloaded_columns(columns: &[Column]) -> Vec<(&Column, Column)> {
+    columns
+        .iter()
+        .filter(|c| c.offload_threshold().is_some())
+        .enumerate()
+        .map(|(i, c)| {
+            let hidden = Column::new(
+                format!("{}{}", OFFLOADED_COLUMN_PREFIX, c.get_name()),
+                ColumnType::String,
+                columns.len() + i,
+            );
+            (c, hidden)
+        })
+        .collect()
+}
+
+/// Longest prefix of `s` that is at most `threshold` bytes and ends at a char boundary.
+fn offloaded_prefix(s: &str, threshold: usize) -> &str {
+    if s.len() <= threshold {
+        return s;
+    }
+    let mut end = threshold;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
 }
 
 impl TableStore for ParquetTableStore {
@@ -53,44 +167,24 @@ impl TableStore for ParquetTableStore {
         rows: RowsView<'a>,
         sort_key_size: usize,
     ) -> Result<Vec<(u64, (Row, Row))>, CubeError> {
-        let mut writers = Vec::new();
-        for f in dest_files.iter() {
-            writers.push(RowParquetWriter::open(
-                &self.table,
-                f,
-                self.row_group_size,
-                sort_key_size,
-            )?);
-        }
-        if source_file.is_none() {
-            let mut split_writer = SplitRowParquetWriter::new(writers, rows.len(), sort_key_size);
-            split_writer.write_rows(rows)?;
-            return Ok(split_writer.close()?);
-        }
-
-        let mut reader = RowParquetReader::open(&self.table, source_file.unwrap(), None)?;
-        let mut right_position = 0;
-        let total_row_number =
-            reader.parquet_reader.metadata().file_metadata().num_rows() as usize + rows.len();
+        let writers = self.open_writers(&dest_files, sort_key_size)?;
+        let mut reader = match source_file {
+            Some(f) => Some(RowParquetReader::open(&self.table, f, None)?),
+            None => None,
+        };
+        let total_row_number = reader
+            .as_ref()
+            .map(|r| r.parquet_reader.metadata().file_metadata().num_rows() as usize)
+            .unwrap_or(0)
+            + rows.len();
         let mut split_writer = SplitRowParquetWriter::new(writers, total_row_number, sort_key_size);
-
-        for row_group_index in 0..reader.parquet_reader.num_row_groups() {
-            let mut read_rows = MutRows::new(reader.column_with_buffer.len());
-            reader.read_rows(row_group_index, &mut read_rows)?;
-            let (new_pos, to_write) = ParquetTableStore::merge_sort(
-                read_rows.freeze(),
-                rows,
-                right_position,
-                sort_key_size as usize,
-            );
-            split_writer.write_rows(to_write.view())?;
-            right_position = new_pos;
-        }
-
-        if right_position < rows.len() {
-            split_writer.write_rows(rows.slice(right_position, rows.len()))?;
-        }
-
+        let mut duplicates = self.duplicate_filter(sort_key_size);
+        ParquetTableStore::merge_sorted(reader.as_mut(), rows, sort_key_size, |r| {
+            match &mut duplicates {
+                Some(d) => split_writer.write_rows(d.filter(r).view()),
+                None => split_writer.write_rows(r),
+            }
+        })?;
         Ok(split_writer.close()?)
     }
 
@@ -136,12 +230,99 @@ impl TableStore for ParquetTableStore {
     // }
 }
 
+/// Only reads the file metadata.
+pub fn parquet_row_count(file: &str) -> Result<u64, CubeError> {
+    let reader = SerializedFileReader::new(File::open(file)?)?;
+    Ok(reader.metadata().file_metadata().num_rows() as u64)
+}
+
 impl ParquetTableStore {
     pub fn new(table: Index, row_group_size: usize) -> ParquetTableStore {
         ParquetTableStore {
             table,
             row_group_size,
+            deduplicate: false,
+        }
+    }
+
+    /// Merges drop exact duplicate rows, including the ones already in the source file.
+    pub fn set_deduplicate(mut self, deduplicate: bool) -> ParquetTableStore {
+        self.deduplicate = deduplicate;
+        self
+    }
+
+    fn duplicate_filter(&self, sort_key_size: usize) -> Option<DuplicateFilter> {
+        if self.deduplicate {
+            Some(DuplicateFilter::new(sort_key_size))
+        } else {
+            None
+        }
+    }
+
+    /// Same as [TableStore::merge_rows], but splits rows between `dest_files` at `split_keys`
+    /// instead of evenly. Returns row counts and min and max rows of every file, including the
+    /// empty ones.
+    pub fn merge_rows_split_at<'a>(
+        &'a self,
+        source_file: Option<&'a str>,
+        dest_files: Vec<String>,
+        rows: RowsView<'a>,
+        sort_key_size: usize,
+        split_keys: Vec<Row>,
+    ) -> Result<Vec<(u64, Option<(Row, Row)>)>, CubeError> {
+        let writers = self.open_writers(&dest_files, sort_key_size)?;
+        let mut reader = match source_file {
+            Some(f) => Some(RowParquetReader::open(&self.table, f, None)?),
+            None => None,
+        };
+        let mut split_writer = KeySplitRowParquetWriter::new(writers, split_keys, sort_key_size);
+        let mut duplicates = self.duplicate_filter(sort_key_size);
+        ParquetTableStore::merge_sorted(reader.as_mut(), rows, sort_key_size, |r| {
+            match &mut duplicates {
+                Some(d) => split_writer.write_rows(d.filter(r).view()),
+                None => split_writer.write_rows(r),
+            }
+        })?;
+        split_writer.close()
+    }
+
+    fn open_writers(
+        &self,
+        dest_files: &[String],
+        sort_key_size: usize,
+    ) -> Result<Vec<RowParquetWriter>, CubeError> {
+        dest_files
+            .iter()
+            .map(|f| RowParquetWriter::open(&self.table, f, self.row_group_size, sort_key_size))
+            .collect()
+    }
+
+    /// Passes rows of `source` and `rows` to `write` in the sort order.
+    fn merge_sorted(
+        source: Option<&mut RowParquetReader>,
+        rows: RowsView,
+        sort_key_size: usize,
+        mut write: impl FnMut(RowsView) -> Result<(), CubeError>,
+    ) -> Result<(), CubeError> {
+        let mut right_position = 0;
+        if let Some(reader) = source {
+            for row_group_index in 0..reader.parquet_reader.num_row_groups() {
+                let mut read_rows = MutRows::new(reader.column_with_buffer.len());
+                reader.read_rows(row_group_index, &mut read_rows)?;
+                let (new_pos, to_write) = ParquetTableStore::merge_sort(
+                    read_rows.freeze(),
+                    rows,
+                    right_position,
+                    sort_key_size,
+                );
+                write(to_write.view())?;
+                right_position = new_pos;
+            }
+        }
+        if right_position < rows.len() {
+            write(rows.slice(right_position, rows.len()))?;
         }
+        Ok(())
     }
 
     #[cfg(test)]
@@ -214,10 +395,14 @@ impl<'a> RowParquetReader<'a> {
                     c.get_index(),
                     match c.get_column_type() {
                         ColumnType::String => ColumnAccessor::Bytes(vec![ByteArray::new(); 16384]),
-                        ColumnType::Bytes | ColumnType::HyperLogLog(_) => {
-                            ColumnAccessor::Bytes(vec![ByteArray::new(); 16384])
+                        ColumnType::Bytes
+                        | ColumnType::HyperLogLog(_)
+                        | ColumnType::Uuid
+                        | ColumnType::Inet
+                        | ColumnType::Cidr => ColumnAccessor::Bytes(vec![ByteArray::new(); 16384]),
+                        ColumnType::Int | ColumnType::Enum(_) => {
+                            ColumnAccessor::Int(vec![0; 16384])
                         }
-                        ColumnType::Int => ColumnAccessor::Int(vec![0; 16384]),
                         ColumnType::Decimal { .. } => ColumnAccessor::Int(vec![0; 16384]),
                         ColumnType::Timestamp => ColumnAccessor::Int(vec![0; 16384]),
                         ColumnType::Boolean => ColumnAccessor::Boolean(vec![false; 16384]),
@@ -228,9 +413,25 @@ impl<'a> RowParquetReader<'a> {
             })
             .collect::<Vec<_>>();
 
+        let offloaded = offloaded_columns(table.get_columns())
+            .into_iter()
+            .filter_map(|(c, hidden)| {
+                let col_i = column_with_buffer
+                    .iter()
+                    .position(|(read, ..)| read.get_index() == c.get_index())?;
+                Some(OffloadedBuffer {
+                    col_i,
+                    file_index: hidden.get_index(),
+                    buffer: vec![ByteArray::new(); 16384],
+                    def_levels: vec![0; 16384],
+                })
+            })
+            .collect();
+
         Ok(RowParquetReader {
             parquet_reader,
             column_with_buffer,
+            offloaded,
         })
     }
 
@@ -302,6 +503,18 @@ impl<'a> RowParquetReader<'a> {
                 }
             };
         }
+        for o in &mut self.offloaded {
+            if let ColumnReader::ByteArrayColumnReader(ref mut reader) =
+                row_group.get_column_reader(o.file_index)?
+            {
+                reader.read_batch(
+                    o.buffer.len(),
+                    Some(o.def_levels.as_mut_slice()),
+                    None,
+                    o.buffer.as_mut_slice(),
+                )?;
+            }
+        }
         Ok(values_read)
     }
 
@@ -319,9 +532,20 @@ impl<'a> RowParquetReader<'a> {
                     match col.get_column_type() {
                         ColumnType::String => {
                             if let ColumnAccessor::Bytes(buffer) = &column_accessor {
+                                let mut offloaded = self
+                                    .offloaded
+                                    .iter()
+                                    .find(|o| o.col_i == col_i)
+                                    .map(|o| (o, 0));
                                 for i in 0..values_read {
                                     if levels[i] == 1 {
-                                        let value = buffer[cur_value_index].as_utf8()?;
+                                        let mut value = buffer[cur_value_index].as_utf8()?;
+                                        if let Some((o, cur_offloaded)) = &mut offloaded {
+                                            if o.def_levels[i] == 1 {
+                                                value = o.buffer[*cur_offloaded].as_utf8()?;
+                                                *cur_offloaded += 1;
+                                            }
+                                        }
                                         result.set_interned(i, col_i, TableValueR::String(value));
                                         cur_value_index += 1;
                                     } else {
@@ -330,7 +554,11 @@ impl<'a> RowParquetReader<'a> {
                                 }
                             }
                         }
-                        ColumnType::Bytes | ColumnType::HyperLogLog(_) => {
+                        ColumnType::Bytes
+                        | ColumnType::HyperLogLog(_)
+                        | ColumnType::Uuid
+                        | ColumnType::Inet
+                        | ColumnType::Cidr => {
                             if let ColumnAccessor::Bytes(buffer) = &column_accessor {
                                 for i in 0..values_read {
                                     if levels[i] == 1 {
@@ -342,7 +570,7 @@ impl<'a> RowParquetReader<'a> {
                                 }
                             }
                         }
-                        ColumnType::Int => {
+                        ColumnType::Int | ColumnType::Enum(_) => {
                             if let ColumnAccessor::Int(buffer) = &column_accessor {
                                 for i in 0..values_read {
                                     if levels[i] == 1 {
@@ -593,6 +821,90 @@ impl SplitRowParquetWriter {
     }
 }
 
+/// Splits sorted rows between writers at `split_keys`. Writer `i` gets the rows that are not less
+/// than `split_keys[i - 1]` and less than `split_keys[i]`.
+pub struct KeySplitRowParquetWriter {
+    writers: Vec<RowParquetWriter>,
+    split_keys: Vec<Row>,
+    current_writer: usize,
+    count_and_min_max: Vec<(u64, Option<(Row, Row)>)>,
+    sort_key_size: usize,
+}
+
+impl KeySplitRowParquetWriter {
+    pub fn new(
+        writers: Vec<RowParquetWriter>,
+        split_keys: Vec<Row>,
+        sort_key_size: usize,
+    ) -> KeySplitRowParquetWriter {
+        assert_eq!(writers.len(), split_keys.len() + 1);
+        let count_and_min_max = vec![(0, None); writers.len()];
+        KeySplitRowParquetWriter {
+            writers,
+            split_keys,
+            current_writer: 0,
+            count_and_min_max,
+            sort_key_size,
+        }
+    }
+
+    fn write_rows(&mut self, rows: RowsView) -> Result<(), CubeError> {
+        let mut remaining_slice = rows;
+        while remaining_slice.len() > 0 {
+            let split_at = match self.split_keys.get(self.current_writer) {
+                Some(key) => {
+                    // First row that is not less than the key.
+                    let (mut lo, mut hi) = (0, remaining_slice.len());
+                    while lo < hi {
+                        let mid = (lo + hi) / 2;
+                        if cmp_row_key_heap(self.sort_key_size, key.values(), &remaining_slice[mid])
+                            == Ordering::Greater
+                        {
+                            lo = mid + 1;
+                        } else {
+                            hi = mid;
+                        }
+                    }
+                    lo
+                }
+                None => remaining_slice.len(),
+            };
+            if split_at != 0 {
+                let to_write = remaining_slice.slice(0, split_at);
+                self.writers[self.current_writer].write_rows(to_write)?;
+                let last_row = convert_row_to_heap_allocated(&to_write[split_at - 1]);
+                let (count, min_max) = &mut self.count_and_min_max[self.current_writer];
+                *count += split_at as u64;
+                match min_max {
+                    Some((_, max)) => *max = last_row,
+                    None => {
+                        *min_max = Some((convert_row_to_heap_allocated(&to_write[0]), last_row))
+                    }
+                }
+            }
+            if split_at == remaining_slice.len() {
+                break;
+            }
+            self.current_writer += 1;
+            remaining_slice = remaining_slice.slice(split_at, remaining_slice.len());
+        }
+        Ok(())
+    }
+
+    fn close(self) -> Result<Vec<(u64, Option<(Row, Row)>)>, CubeError> {
+        for w in self.writers.into_iter() {
+            w.close()?;
+        }
+        let sort_key_size = self.sort_key_size;
+        let key = |r: Row| Row::new(r.values.into_iter().take(sort_key_size).collect());
+        Ok(self
+            .count_and_min_max
+            .into_iter()
+            .map(|(c, min_max)| (c, min_max.map(|(min, max)| (key(min), key(max)))))
+            .collect())
+    }
+}
+
 impl RowParquetWriter {
     fn open(
         table: &'a Index,
@@ -602,9 +914,11 @@ impl RowParquetWriter {
     ) -> Result<RowParquetWriter, CubeError> {
         let file = File::create(file)?;
 
+        let offloaded = offloaded_columns(table.get_columns());
         let mut fields = table
             .get_columns()
             .iter()
+            .chain(offloaded.iter().map(|(_, hidden)| hidden))
             .map(|column| {
                 // TODO pass nullable columns
                 Arc::new(parquet::schema::types::Type::from(column))
@@ -618,11 +932,16 @@ impl RowParquetWriter {
                 .unwrap(),
         );
 
-        let props = Self::writer_props();
+        let props = Self::writer_props(table.get_columns());
         let parquet_writer = SerializedFileWriter::new(file.try_clone()?, schema, props)?;
 
+        let offloaded = offloaded
+            .iter()
+            .map(|(c, _)| (c.get_index(), c.offload_threshold().unwrap()))
+            .collect();
         Ok(RowParquetWriter {
             columns: table.get_columns().clone(),
+            offloaded,
             parquet_writer,
             row_group_size,
             buffer: MutRows::with_capacity(table.get_columns().len(), row_group_size as usize),
@@ -655,6 +974,26 @@ impl RowParquetWriter {
             while let Some(mut col_writer) = row_group_writer.next_column()? {
                 // TODO types
                 match col_writer {
+                    // Hidden column with full values of offloaded strings.
+                    ColumnWriter::ByteArrayColumnWriter(ref mut typed)
+                        if self.columns.len() <= column_index =>
+                    {
+                        let (source, threshold) = self.offloaded[column_index - self.columns.len()];
+                        let mut def_levels = Vec::with_capacity(rows_in_group);
+                        let mut column_values = Vec::new();
+                        for row_index in 0..rows_in_group {
+                            match &self.buffer.rows()[row_batch_index * batch_size + row_index]
+                                [source]
+                            {
+                                TableValueR::String(s) if threshold < s.len() => {
+                                    column_values.push(ByteArray::from(*s));
+                                    def_levels.push(1);
+                                }
+                                _ => def_levels.push(0),
+                            }
+                        }
+                        typed.write_batch(&column_values, Some(&def_levels), None)?;
+                    }
                     ColumnWriter::Int64ColumnWriter(ref mut typed) => {
                         let column = &self.columns[column_index];
                         let mut min = None;
@@ -782,7 +1121,9 @@ impl RowParquetWriter {
                         // Both vars store indicies into the `column_values`.
                         let mut min: Option<String> = None;
                         let mut max: Option<String> = None;
-                        let mut use_min_max = true;
+                        let threshold = self.columns[column_index].offload_threshold();
+                        // Prefixes of offloaded strings would give wrong bounds.
+                        let mut use_min_max = threshold.is_none();
                         let mut update_stats = |v: &TableValueR| {
                             if !use_min_max {
                                 return;
@@ -815,7 +1156,10 @@ impl RowParquetWriter {
                                 update_stats(v);
                                 // TODO types
                                 match v {
-                                    TableValueR::String(str) => ByteArray::from(*str),
+                                    TableValueR::String(str) => match threshold {
+                                        Some(t) => ByteArray::from(offloaded_prefix(str, t)),
+                                        None => ByteArray::from(*str),
+                                    },
                                     TableValueR::Bytes(bytes) => ByteArray::from(bytes.to_vec()),
                                     x => panic!("Unsupported value: {:?}", x),
                                 }
@@ -939,25 +1283,46 @@ impl RowParquetWriter {
         Ok(())
     }
 
-    fn writer_props() -> Arc<WriterProperties> {
-        Arc::new(
-            WriterProperties::builder()
-                // .set_key_value_metadata(Some(vec![KeyValue::new(
-                //     "key".to_string(),
-                //     "value".to_string(),
-                // )]))
-                .set_writer_version(WriterVersion::PARQUET_2_0)
-                .set_statistics_enabled(true)
-                // .set_column_dictionary_enabled(ColumnPath::new(vec!["col0".to_string()]), true)
-                // .set_column_encoding(ColumnPath::new(vec!["col1".to_string()]), Encoding::RLE)
-                .build(),
-        )
+    fn writer_props(columns: &[Column]) -> Arc<WriterProperties> {
+        let mut builder = WriterProperties::builder()
+            // .set_key_value_metadata(Some(vec![KeyValue::new(
+            //     "key".to_string(),
+            //     "value".to_string(),
+            // )]))
+            .set_writer_version(WriterVersion::PARQUET_2_0)
+            .set_statistics_enabled(true);
+        for c in columns {
+            match c.encoding() {
+                // Dictionary pages would take precedence over the encoding. Min and max values of
+                // column chunks are still written, so row groups are pruned as before.
+                Some(ColumnEncoding::Delta) => {
+                    let path = ColumnPath::new(vec![c.get_name().clone()]);
+                    builder = builder
+                        .set_column_dictionary_enabled(path.clone(), false)
+                        .set_column_encoding(path, Encoding::DELTA_BINARY_PACKED);
+                }
+                // Bounds of prefixes are not bounds of the values.
+                Some(ColumnEncoding::Offload { .. }) => {
+                    let path = ColumnPath::new(vec![c.get_name().clone()]);
+                    builder = builder.set_column_statistics_enabled(path, false);
+                }
+                None => {}
+            }
+        }
+        for (_, hidden) in offloaded_columns(columns) {
+            let path = ColumnPath::new(vec![hidden.get_name().clone()]);
+            builder = builder
+                .set_column_dictionary_enabled(path.clone(), false)
+                .set_column_statistics_enabled(path, false);
+        }
+        Arc::new(builder.build())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::metastore::{Column, ColumnType, Index};
+    use crate::metastore::{Column, ColumnEncoding, ColumnType, Index};
+    use crate::table::data::RowsView;
     use crate::table::parquet::{ColumnAccessor, ParquetTableStore, RowParquetReader};
     use crate::table::{Row, TableStore, TableValue};
     use std::{fs, io};
@@ -965,11 +1330,13 @@ mod tests {
     extern crate test;
 
     use crate::table::data::convert_row_to_heap_allocated;
+    use crate::table::TimestampValue;
     use bigdecimal::BigDecimal;
     use csv::ReaderBuilder;
     use itertools::Itertools;
     use num::BigInt;
-    use parquet::file::reader::FileReader;
+    use parquet::basic::Encoding;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
     use parquet::file::statistics::Statistics;
     use std::fs::File;
     use std::io::BufReader;
@@ -1001,6 +1368,7 @@ mod tests {
             )
             .unwrap(),
             row_group_size: 10,
+            deduplicate: false,
         };
         let file_name = "foo.parquet";
 
@@ -1157,6 +1525,254 @@ mod tests {
         fs::remove_file(split_2).unwrap();
     }
 
+    #[test]
+    fn split_at_keys() {
+        let store = ParquetTableStore {
+            table: Index::try_new(
+                "foo".to_string(),
+                1,
+                vec![
+                    Column::new("key".to_string(), ColumnType::Int, 0),
+                    Column::new("value".to_string(), ColumnType::String, 1),
+                ],
+                2,
+            )
+            .unwrap(),
+            row_group_size: 10,
+            deduplicate: false,
+        };
+        let row =
+            |k: i64, v: &str| Row::new(vec![TableValue::Int(k), TableValue::String(v.to_string())]);
+        let files = (0..4)
+            .map(|i| format!("foo-split-at-{}.parquet", i))
+            .collect_vec();
+        let rows = (0..30).map(|i| row(i / 2, "a")).collect_vec();
+        let mut buffer = Vec::new();
+        let mut small_buffer = Vec::new();
+        let result = store
+            .merge_rows_split_at(
+                None,
+                files.clone(),
+                RowsView::from_heap_allocated(&mut buffer, 2, &rows),
+                2,
+                vec![
+                    Row::new(vec![TableValue::Int(5), TableValue::Null]),
+                    Row::new(vec![
+                        TableValue::Int(5),
+                        TableValue::String("b".to_string()),
+                    ]),
+                    Row::new(vec![TableValue::Int(10), TableValue::Null]),
+                ],
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![
+                (10, Some((row(0, "a"), row(4, "a")))),
+                (2, Some((row(5, "a"), row(5, "a")))),
+                (8, Some((row(6, "a"), row(9, "a")))),
+                (10, Some((row(10, "a"), row(14, "a")))),
+            ]
+        );
+        assert_eq!(store.read_rows(&files[1]).unwrap().num_rows(), 2);
+
+        let result = store
+            .merge_rows_split_at(
+                None,
+                files[0..2].to_vec(),
+                RowsView::from_heap_allocated(&mut small_buffer, 2, &rows[0..4]),
+                2,
+                vec![Row::new(vec![TableValue::Int(7), TableValue::Null])],
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            vec![(4, Some((row(0, "a"), row(1, "a")))), (0, None)]
+        );
+
+        for f in files {
+            fs::remove_file(f).unwrap();
+        }
+    }
+
+    #[test]
+    fn deduplicate() {
+        let store = ParquetTableStore::new(
+            Index::try_new(
+                "foo".to_string(),
+                1,
+                vec![
+                    Column::new("key".to_string(), ColumnType::Int, 0),
+                    Column::new("value".to_string(), ColumnType::Float, 1),
+                ],
+                1,
+            )
+            .unwrap(),
+            3,
+        )
+        .set_deduplicate(true);
+        let row = |k: i64, v: f64| Row::new(vec![TableValue::Int(k), TableValue::Float(v.into())]);
+        let source = "foo-deduplicate-source.parquet".to_string();
+        let dest = "foo-deduplicate-dest.parquet".to_string();
+        let result = store
+            .merge_rows_from_heap(
+                None,
+                vec![source.clone()],
+                vec![row(1, 1.), row(1, 2.), row(1, 1.), row(2, 1.)],
+                1,
+            )
+            .unwrap();
+        assert_eq!(result, vec![(3, (row(1, 1.), row(2, 1.)))]);
+
+        // Duplicates of rows in the source file are dropped too, NaNs are equal to each other.
+        let result = store
+            .merge_rows_from_heap(
+                Some(source.as_str()),
+                vec![dest.clone()],
+                vec![row(1, 2.), row(2, 1.), row(2, f64::NAN), row(2, f64::NAN)],
+                1,
+            )
+            .unwrap();
+        assert_eq!(result[0].0, 4);
+        let read_rows = store.read_rows(&dest).unwrap();
+        assert_eq!(
+            read_rows
+                .view()
+                .iter()
+                .map(|r| convert_row_to_heap_allocated(&r))
+                .collect_vec(),
+            vec![row(1, 1.), row(1, 2.), row(2, 1.), row(2, f64::NAN)]
+        );
+
+        fs::remove_file(source).unwrap();
+        fs::remove_file(dest).unwrap();
+    }
+
+    #[test]
+    fn delta_encoding() {
+        let mut ts = Column::new("ts".to_string(), ColumnType::Timestamp, 0);
+        ts.set_encoding(ColumnEncoding::Delta);
+        let store = ParquetTableStore {
+            table: Index::try_new(
+                "foo".to_string(),
+                1,
+                vec![ts, Column::new("n".to_string(), ColumnType::Int, 1)],
+                1,
+            )
+            .unwrap(),
+            row_group_size: 1000,
+            deduplicate: false,
+        };
+        let file_name = "foo-delta.parquet";
+        let mut rows = (0..3000)
+            .map(|i| {
+                Row::new(vec![
+                    if i % 100 == 7 {
+                        TableValue::Null
+                    } else {
+                        TableValue::Timestamp(TimestampValue::new(
+                            1_600_000_000_000_000_000 + i * 1_000_000,
+                        ))
+                    },
+                    TableValue::Int(i % 3),
+                ])
+            })
+            .collect_vec();
+        rows.sort_by(|a, b| a.sort_key(1).cmp(&b.sort_key(1)));
+        store
+            .merge_rows_from_heap(None, vec![file_name.to_string()], rows.clone(), 1)
+            .unwrap();
+
+        let read_rows = store.read_rows(file_name).unwrap();
+        assert_eq!(
+            read_rows
+                .view()
+                .iter()
+                .map(|r| convert_row_to_heap_allocated(&r))
+                .collect_vec(),
+            rows
+        );
+
+        let reader = SerializedFileReader::new(File::open(file_name).unwrap()).unwrap();
+        for row_group in reader.metadata().row_groups() {
+            let ts = row_group.column(0);
+            assert!(ts.encodings().contains(&Encoding::DELTA_BINARY_PACKED));
+            assert!(!ts.encodings().contains(&Encoding::PLAIN_DICTIONARY));
+            assert!(!ts.encodings().contains(&Encoding::RLE_DICTIONARY));
+            assert!(ts.statistics().unwrap().has_min_max_set());
+            assert!(!row_group
+                .column(1)
+                .encodings()
+                .contains(&Encoding::DELTA_BINARY_PACKED));
+        }
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn string_offload() {
+        let mut payload = Column::new("payload".to_string(), ColumnType::String, 1);
+        payload.set_encoding(ColumnEncoding::Offload { threshold: 4 });
+        let store = ParquetTableStore {
+            table: Index::try_new(
+                "foo".to_string(),
+                1,
+                vec![Column::new("id".to_string(), ColumnType::Int, 0), payload],
+                1,
+            )
+            .unwrap(),
+            row_group_size: 10,
+            deduplicate: false,
+        };
+        let file_name = "foo-offload.parquet";
+        let rows = (0..25)
+            .map(|i| {
+                Row::new(vec![
+                    TableValue::Int(i),
+                    match i % 4 {
+                        0 => TableValue::Null,
+                        1 => TableValue::String("abc".to_string()),
+                        2 => TableValue::String(format!("long value {}", i)),
+                        _ => TableValue::String("ääää".to_string()),
+                    },
+                ])
+            })
+            .collect_vec();
+        store
+            .merge_rows_from_heap(None, vec![file_name.to_string()], rows.clone(), 1)
+            .unwrap();
+
+        let read_rows = store.read_rows(file_name).unwrap();
+        assert_eq!(
+            read_rows
+                .view()
+                .iter()
+                .map(|r| convert_row_to_heap_allocated(&r))
+                .collect_vec(),
+            rows
+        );
+
+        let reader = SerializedFileReader::new(File::open(file_name).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr();
+        assert_eq!(schema.num_columns(), 3);
+        assert_eq!(schema.column(2).name(), "__offloaded_payload");
+        for row_group in reader.metadata().row_groups() {
+            assert!(row_group.column(0).statistics().unwrap().has_min_max_set());
+            assert!(!row_group
+                .column(1)
+                .statistics()
+                .map(|s| s.has_min_max_set())
+                .unwrap_or(false));
+        }
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn offloaded_prefix() {
+        assert_eq!(super::offloaded_prefix("abc", 4), "abc");
+        assert_eq!(super::offloaded_prefix("abcdef", 4), "abcd");
+        assert_eq!(super::offloaded_prefix("ääää", 3), "ä");
+    }
+
     #[bench]
     fn filter_count(b: &mut Bencher) {
         if let Ok((store, columns_to_read)) = prepare_donors() {
@@ -1290,6 +1906,7 @@ mod tests {
             )
             .unwrap(),
             row_group_size: 16384,
+            deduplicate: false,
         };
 
         let column_mapping = vec![1, 0, 2, 3, 4];
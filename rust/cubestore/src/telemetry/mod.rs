@@ -1,3 +1,4 @@
+use crate::util::query_id::current_query_id;
 use crate::CubeError;
 use chrono::{SecondsFormat, Utc};
 use core::mem;
@@ -143,7 +144,18 @@ impl Log for ReportingLogger {
                     .collect(),
             )
         }
-        self.logger.log(record)
+        match current_query_id() {
+            Some(query_id) => self.logger.log(
+                &Record::builder()
+                    .args(format_args!("[query {}] {}", query_id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.logger.log(record),
+        }
     }
 
     fn flush(&self) {
@@ -0,0 +1,624 @@
+//! Reader of Avro object container files, which table formats like Iceberg use for their metadata.
+//! Values are decoded into [AvroValue] by the schema from the file header, resolution against
+//! another reader schema is not supported. Blocks may be uncompressed or deflate-compressed.
+use crate::CubeError;
+use flate2::read::DeflateDecoder;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::io::Read;
+
+const MAGIC: &[u8] = b"Obj\x01";
+const SYNC_SIZE: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AvroValue {
+    Null,
+    Boolean(bool),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    /// Also holds fixed values.
+    Bytes(Vec<u8>),
+    /// Also holds enum symbols.
+    String(String),
+    Array(Vec<AvroValue>),
+    Map(Vec<(String, AvroValue)>),
+    Record(Vec<(String, AvroValue)>),
+}
+
+impl AvroValue {
+    /// Field of a record, [None] for other values or missing fields.
+    pub fn field(&self, name: &str) -> Option<&AvroValue> {
+        match self {
+            AvroValue::Record(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AvroValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            AvroValue::Int(i) => Some(*i as i64),
+            AvroValue::Long(i) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
+pub struct AvroFile {
+    pub metadata: HashMap<String, Vec<u8>>,
+    pub values: Vec<AvroValue>,
+}
+
+impl AvroFile {
+    pub fn metadata_str(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .get(key)
+            .and_then(|v| std::str::from_utf8(v).ok())
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Fixed(usize),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Record(Vec<(String, Schema)>),
+    /// Reference to a named type defined earlier.
+    Named(String),
+}
+
+fn invalid(message: impl Into<String>) -> CubeError {
+    CubeError::user(format!("Invalid Avro data: {}", message.into()))
+}
+
+pub fn read_container(data: &[u8]) -> Result<AvroFile, CubeError> {
+    if !data.starts_with(MAGIC) {
+        return Err(invalid("not an object container file"));
+    }
+    let mut header = Decoder::new(&data[MAGIC.len()..]);
+    let mut metadata = HashMap::new();
+    header.read_blocks(|d| {
+        let key = d.read_string()?;
+        let value = d.read_bytes()?.to_vec();
+        metadata.insert(key, value);
+        Ok(())
+    })?;
+    let sync = header.read_fixed(SYNC_SIZE)?.to_vec();
+
+    let schema_json: JsonValue = serde_json::from_slice(
+        metadata
+            .get("avro.schema")
+            .ok_or_else(|| invalid("schema is missing"))?,
+    )
+    .map_err(|e| invalid(format!("schema is not JSON: {}", e)))?;
+    let mut named = HashMap::new();
+    let schema = parse_schema(&schema_json, None, &mut named)?;
+    let deflate = match metadata.get("avro.codec").map(|c| c.as_slice()) {
+        None | Some(b"null") => false,
+        Some(b"deflate") => true,
+        Some(c) => {
+            return Err(invalid(format!(
+                "unsupported codec {}",
+                String::from_utf8_lossy(c)
+            )))
+        }
+    };
+
+    let mut values = Vec::new();
+    let mut blocks = header;
+    while !blocks.is_empty() {
+        let count = blocks.read_long()?;
+        let size = blocks.read_long()?;
+        if count < 0 || size < 0 {
+            return Err(invalid("negative block size"));
+        }
+        let block = blocks.read_fixed(size as usize)?;
+        let mut inflated = Vec::new();
+        let block = if deflate {
+            DeflateDecoder::new(block)
+                .read_to_end(&mut inflated)
+                .map_err(|e| invalid(format!("can't inflate block: {}", e)))?;
+            inflated.as_slice()
+        } else {
+            block
+        };
+        let mut decoder = Decoder::new(block);
+        for _ in 0..count {
+            values.push(decoder.read_value(&schema, &named)?);
+        }
+        if blocks.read_fixed(SYNC_SIZE)? != sync.as_slice() {
+            return Err(invalid("sync marker mismatch"));
+        }
+    }
+    Ok(AvroFile { metadata, values })
+}
+
+fn parse_schema(
+    json: &JsonValue,
+    namespace: Option<&str>,
+    named: &mut HashMap<String, Schema>,
+) -> Result<Schema, CubeError> {
+    let object = match json {
+        JsonValue::String(name) => {
+            return Ok(match name.as_str() {
+                "null" => Schema::Null,
+                "boolean" => Schema::Boolean,
+                "int" => Schema::Int,
+                "long" => Schema::Long,
+                "float" => Schema::Float,
+                "double" => Schema::Double,
+                "bytes" => Schema::Bytes,
+                "string" => Schema::String,
+                _ => {
+                    let full_name = full_name(name, namespace);
+                    if named.contains_key(&full_name) {
+                        Schema::Named(full_name)
+                    } else if named.contains_key(name) {
+                        Schema::Named(name.clone())
+                    } else {
+                        return Err(invalid(format!("unknown type {}", name)));
+                    }
+                }
+            });
+        }
+        JsonValue::Array(branches) => {
+            return Ok(Schema::Union(
+                branches
+                    .iter()
+                    .map(|b| parse_schema(b, namespace, named))
+                    .collect::<Result<_, _>>()?,
+            ));
+        }
+        JsonValue::Object(object) => object,
+        _ => return Err(invalid(format!("unexpected schema {}", json))),
+    };
+    let type_name = match object.get("type") {
+        Some(JsonValue::String(t)) => t.as_str(),
+        // E.g. `{"type": {"type": "array", ...}}`.
+        Some(t) => return parse_schema(t, namespace, named),
+        None => return Err(invalid(format!("type is missing in {}", json))),
+    };
+    let name = object.get("name").and_then(|n| n.as_str());
+    let namespace = object
+        .get("namespace")
+        .and_then(|n| n.as_str())
+        .or(namespace);
+    let schema = match type_name {
+        "record" | "error" => {
+            let fields = object
+                .get("fields")
+                .and_then(|f| f.as_array())
+                .ok_or_else(|| invalid(format!("fields are missing in {}", json)))?;
+            Schema::Record(
+                fields
+                    .iter()
+                    .map(|f| {
+                        let field_name = f
+                            .get("name")
+                            .and_then(|n| n.as_str())
+                            .ok_or_else(|| invalid(format!("field name is missing in {}", f)))?;
+                        let field_type = f
+                            .get("type")
+                            .ok_or_else(|| invalid(format!("field type is missing in {}", f)))?;
+                        Ok((
+                            field_name.to_string(),
+                            parse_schema(field_type, namespace, named)?,
+                        ))
+                    })
+                    .collect::<Result<_, CubeError>>()?,
+            )
+        }
+        "enum" => Schema::Enum(
+            object
+                .get("symbols")
+                .and_then(|s| s.as_array())
+                .ok_or_else(|| invalid(format!("symbols are missing in {}", json)))?
+                .iter()
+                .map(|s| s.as_str().unwrap_or_default().to_string())
+                .collect(),
+        ),
+        "fixed" => Schema::Fixed(
+            object
+                .get("size")
+                .and_then(|s| s.as_u64())
+                .ok_or_else(|| invalid(format!("size is missing in {}", json)))?
+                as usize,
+        ),
+        "array" => Schema::Array(Box::new(parse_schema(
+            object
+                .get("items")
+                .ok_or_else(|| invalid(format!("items are missing in {}", json)))?,
+            namespace,
+            named,
+        )?)),
+        "map" => Schema::Map(Box::new(parse_schema(
+            object
+                .get("values")
+                .ok_or_else(|| invalid(format!("values are missing in {}", json)))?,
+            namespace,
+            named,
+        )?)),
+        // Primitive types with attributes, e.g. logical types.
+        _ => return parse_schema(&JsonValue::String(type_name.to_string()), namespace, named),
+    };
+    if let Some(name) = name {
+        named.insert(full_name(name, namespace), schema.clone());
+        named.insert(name.to_string(), schema.clone());
+    }
+    Ok(schema)
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(ns) if !name.contains('.') && !ns.is_empty() => format!("{}.{}", ns, name),
+        _ => name.to_string(),
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    fn read_fixed(&mut self, size: usize) -> Result<&'a [u8], CubeError> {
+        if self.data.len() - self.pos < size {
+            return Err(invalid("unexpected end of data"));
+        }
+        let bytes = &self.data[self.pos..self.pos + size];
+        self.pos += size;
+        Ok(bytes)
+    }
+
+    /// Zig-zag encoded variable-length integer.
+    fn read_long(&mut self) -> Result<i64, CubeError> {
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            if shift > 63 {
+                return Err(invalid("integer is too long"));
+            }
+            let b = self.read_fixed(1)?[0];
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8], CubeError> {
+        let len = self.read_long()?;
+        if len < 0 {
+            return Err(invalid("negative length"));
+        }
+        self.read_fixed(len as usize)
+    }
+
+    fn read_string(&mut self) -> Result<String, CubeError> {
+        String::from_utf8(self.read_bytes()?.to_vec()).map_err(|_| invalid("string is not UTF-8"))
+    }
+
+    /// Arrays and maps are written as blocks of items, ending with an empty block.
+    fn read_blocks(
+        &mut self,
+        mut read_item: impl FnMut(&mut Self) -> Result<(), CubeError>,
+    ) -> Result<(), CubeError> {
+        loop {
+            let mut count = self.read_long()?;
+            if count == 0 {
+                return Ok(());
+            }
+            if count < 0 {
+                count = -count;
+                // Byte size of the block, only useful to skip it.
+                self.read_long()?;
+            }
+            for _ in 0..count {
+                read_item(self)?;
+            }
+        }
+    }
+
+    fn read_value(
+        &mut self,
+        schema: &Schema,
+        named: &HashMap<String, Schema>,
+    ) -> Result<AvroValue, CubeError> {
+        Ok(match schema {
+            Schema::Null => AvroValue::Null,
+            Schema::Boolean => AvroValue::Boolean(self.read_fixed(1)?[0] != 0),
+            Schema::Int => AvroValue::Int(self.read_long()? as i32),
+            Schema::Long => AvroValue::Long(self.read_long()?),
+            Schema::Float => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.read_fixed(4)?);
+                AvroValue::Float(f32::from_le_bytes(bytes))
+            }
+            Schema::Double => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.read_fixed(8)?);
+                AvroValue::Double(f64::from_le_bytes(bytes))
+            }
+            Schema::Bytes => AvroValue::Bytes(self.read_bytes()?.to_vec()),
+            Schema::String => AvroValue::String(self.read_string()?),
+            Schema::Fixed(size) => AvroValue::Bytes(self.read_fixed(*size)?.to_vec()),
+            Schema::Enum(symbols) => {
+                let i = self.read_long()?;
+                AvroValue::String(
+                    symbols
+                        .get(i as usize)
+                        .ok_or_else(|| invalid(format!("enum index {} is out of range", i)))?
+                        .clone(),
+                )
+            }
+            Schema::Array(items) => {
+                let mut values = Vec::new();
+                self.read_blocks(|d| {
+                    values.push(d.read_value(items, named)?);
+                    Ok(())
+                })?;
+                AvroValue::Array(values)
+            }
+            Schema::Map(value_schema) => {
+                let mut values = Vec::new();
+                self.read_blocks(|d| {
+                    let key = d.read_string()?;
+                    values.push((key, d.read_value(value_schema, named)?));
+                    Ok(())
+                })?;
+                AvroValue::Map(values)
+            }
+            Schema::Union(branches) => {
+                let i = self.read_long()?;
+                let branch = branches
+                    .get(i as usize)
+                    .ok_or_else(|| invalid(format!("union index {} is out of range", i)))?;
+                self.read_value(branch, named)?
+            }
+            Schema::Record(fields) => AvroValue::Record(
+                fields
+                    .iter()
+                    .map(|(name, schema)| Ok((name.clone(), self.read_value(schema, named)?)))
+                    .collect::<Result<_, CubeError>>()?,
+            ),
+            Schema::Named(name) => self.read_value(&named[name], named)?,
+        })
+    }
+}
+
+/// Writes `values` as a container file of a single block. Unions are written as their first branch
+/// that fits the value.
+#[cfg(test)]
+pub fn write_container(schema_json: &str, values: &[AvroValue], deflate: bool) -> Vec<u8> {
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn write_long(out: &mut Vec<u8>, v: i64) {
+        let mut z = ((v << 1) ^ (v >> 63)) as u64;
+        while z >= 0x80 {
+            out.push((z as u8) | 0x80);
+            z >>= 7;
+        }
+        out.push(z as u8);
+    }
+    fn write_bytes(out: &mut Vec<u8>, b: &[u8]) {
+        write_long(out, b.len() as i64);
+        out.extend_from_slice(b);
+    }
+    fn fits(schema: &Schema, v: &AvroValue, named: &HashMap<String, Schema>) -> bool {
+        match (schema, v) {
+            (Schema::Named(n), _) => fits(&named[n], v, named),
+            (Schema::Null, AvroValue::Null)
+            | (Schema::Boolean, AvroValue::Boolean(_))
+            | (Schema::Int, AvroValue::Int(_))
+            | (Schema::Long, AvroValue::Long(_))
+            | (Schema::Float, AvroValue::Float(_))
+            | (Schema::Double, AvroValue::Double(_))
+            | (Schema::Bytes, AvroValue::Bytes(_))
+            | (Schema::Fixed(_), AvroValue::Bytes(_))
+            | (Schema::String, AvroValue::String(_))
+            | (Schema::Enum(_), AvroValue::String(_))
+            | (Schema::Array(_), AvroValue::Array(_))
+            | (Schema::Map(_), AvroValue::Map(_))
+            | (Schema::Record(_), AvroValue::Record(_)) => true,
+            _ => false,
+        }
+    }
+    fn write(out: &mut Vec<u8>, schema: &Schema, v: &AvroValue, named: &HashMap<String, Schema>) {
+        match (schema, v) {
+            (Schema::Named(n), _) => write(out, &named[n], v, named),
+            (Schema::Union(branches), _) => {
+                let i = branches.iter().position(|b| fits(b, v, named)).unwrap();
+                write_long(out, i as i64);
+                write(out, &branches[i], v, named);
+            }
+            (Schema::Null, AvroValue::Null) => {}
+            (Schema::Boolean, AvroValue::Boolean(b)) => out.push(*b as u8),
+            (Schema::Int, AvroValue::Int(i)) => write_long(out, *i as i64),
+            (Schema::Long, AvroValue::Long(i)) => write_long(out, *i),
+            (Schema::Float, AvroValue::Float(f)) => out.extend_from_slice(&f.to_le_bytes()),
+            (Schema::Double, AvroValue::Double(f)) => out.extend_from_slice(&f.to_le_bytes()),
+            (Schema::Bytes, AvroValue::Bytes(b)) => write_bytes(out, b),
+            (Schema::Fixed(_), AvroValue::Bytes(b)) => out.extend_from_slice(b),
+            (Schema::String, AvroValue::String(s)) => write_bytes(out, s.as_bytes()),
+            (Schema::Enum(symbols), AvroValue::String(s)) => {
+                write_long(out, symbols.iter().position(|x| x == s).unwrap() as i64)
+            }
+            (Schema::Array(items), AvroValue::Array(values)) => {
+                if !values.is_empty() {
+                    write_long(out, values.len() as i64);
+                    for v in values {
+                        write(out, items, v, named);
+                    }
+                }
+                write_long(out, 0);
+            }
+            (Schema::Map(value_schema), AvroValue::Map(values)) => {
+                if !values.is_empty() {
+                    write_long(out, values.len() as i64);
+                    for (k, v) in values {
+                        write_bytes(out, k.as_bytes());
+                        write(out, value_schema, v, named);
+                    }
+                }
+                write_long(out, 0);
+            }
+            (Schema::Record(fields), AvroValue::Record(_)) => {
+                for (name, schema) in fields {
+                    write(
+                        out,
+                        schema,
+                        v.field(name).unwrap_or(&AvroValue::Null),
+                        named,
+                    );
+                }
+            }
+            (s, v) => panic!("Can't write {:?} as {:?}", v, s),
+        }
+    }
+
+    let mut named = HashMap::new();
+    let schema = parse_schema(
+        &serde_json::from_str(schema_json).unwrap(),
+        None,
+        &mut named,
+    )
+    .unwrap();
+    let sync = [7u8; SYNC_SIZE];
+    let mut out = MAGIC.to_vec();
+    write_long(&mut out, if deflate { 2 } else { 1 });
+    write_bytes(&mut out, b"avro.schema");
+    write_bytes(&mut out, schema_json.as_bytes());
+    if deflate {
+        write_bytes(&mut out, b"avro.codec");
+        write_bytes(&mut out, b"deflate");
+    }
+    write_long(&mut out, 0);
+    out.extend_from_slice(&sync);
+    let mut block = Vec::new();
+    for v in values {
+        write(&mut block, &schema, v, &named);
+    }
+    if deflate {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&block).unwrap();
+        block = encoder.finish().unwrap();
+    }
+    write_long(&mut out, values.len() as i64);
+    write_long(&mut out, block.len() as i64);
+    out.extend_from_slice(&block);
+    out.extend_from_slice(&sync);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "entry",
+        "namespace": "test",
+        "fields": [
+            {"name": "status", "type": "int"},
+            {"name": "path", "type": ["null", "string"]},
+            {"name": "ts", "type": {"type": "long", "logicalType": "timestamp-micros"}},
+            {"name": "file", "type": {
+                "type": "record",
+                "name": "r2",
+                "fields": [
+                    {"name": "kind", "type": {"type": "enum", "name": "kind", "symbols": ["A", "B"]}},
+                    {"name": "bounds", "type": {"type": "map", "values": "bytes"}}
+                ]
+            }},
+            {"name": "other", "type": ["null", "test.r2"]},
+            {"name": "ids", "type": {"type": "array", "items": "long"}},
+            {"name": "ratio", "type": "double"}
+        ]
+    }"#;
+
+    fn entry(status: i32, path: Option<&str>) -> AvroValue {
+        AvroValue::Record(vec![
+            ("status".to_string(), AvroValue::Int(status)),
+            (
+                "path".to_string(),
+                path.map(|p| AvroValue::String(p.to_string()))
+                    .unwrap_or(AvroValue::Null),
+            ),
+            ("ts".to_string(), AvroValue::Long(-1234567)),
+            (
+                "file".to_string(),
+                AvroValue::Record(vec![
+                    ("kind".to_string(), AvroValue::String("B".to_string())),
+                    (
+                        "bounds".to_string(),
+                        AvroValue::Map(vec![("1".to_string(), AvroValue::Bytes(vec![1, 2]))]),
+                    ),
+                ]),
+            ),
+            ("other".to_string(), AvroValue::Null),
+            (
+                "ids".to_string(),
+                AvroValue::Array(vec![AvroValue::Long(1), AvroValue::Long(1 << 40)]),
+            ),
+            ("ratio".to_string(), AvroValue::Double(0.5)),
+        ])
+    }
+
+    #[test]
+    fn container() {
+        let values = vec![entry(1, Some("s3://bucket/a.parquet")), entry(-2, None)];
+        let data = write_container(SCHEMA, &values, false);
+        let file = read_container(&data).unwrap();
+        assert_eq!(file.values, values);
+        assert_eq!(
+            file.values[0].field("path").and_then(|p| p.as_str()),
+            Some("s3://bucket/a.parquet")
+        );
+        assert_eq!(
+            file.values[1].field("status").and_then(|s| s.as_i64()),
+            Some(-2)
+        );
+
+        assert!(read_container(&data[..data.len() - 1]).is_err());
+        assert!(read_container(b"not avro").is_err());
+    }
+
+    #[test]
+    fn deflate_codec() {
+        let values = vec![entry(0, Some("a")), entry(2, Some("b"))];
+        let data = write_container(SCHEMA, &values, true);
+        let file = read_container(&data).unwrap();
+        assert_eq!(file.metadata_str("avro.codec"), Some("deflate"));
+        assert_eq!(file.values, values);
+    }
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::iter::FromIterator;
+
+/// Compact set of ids stored as sorted non-overlapping ranges. Partition ids assigned to a single
+/// worker are mostly contiguous, so this takes a few bytes on the wire instead of a list of all
+/// ids.
+#[derive(Clone, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct IdSet {
+    /// Half-open ranges `[start, end)`, sorted and never adjacent or overlapping.
+    ranges: Vec<(u64, u64)>,
+}
+
+impl IdSet {
+    pub fn new() -> IdSet {
+        IdSet { ranges: Vec::new() }
+    }
+
+    pub fn contains(&self, id: &u64) -> bool {
+        let id = *id;
+        self.ranges
+            .binary_search_by(|(start, end)| {
+                if id < *start {
+                    Ordering::Greater
+                } else if *end <= id {
+                    Ordering::Less
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .is_ok()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.iter().map(|(s, e)| (e - s) as usize).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u64> + '_ {
+        self.ranges.iter().flat_map(|(s, e)| *s..*e)
+    }
+}
+
+impl FromIterator<u64> for IdSet {
+    fn from_iter<T: IntoIterator<Item = u64>>(iter: T) -> Self {
+        let mut ids = iter.into_iter().collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for id in ids {
+            match ranges.last_mut() {
+                Some((_, end)) if *end == id => *end += 1,
+                _ => ranges.push((id, id + 1)),
+            }
+        }
+        IdSet { ranges }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+    use std::collections::HashSet;
+
+    #[test]
+    fn contains() {
+        let s = vec![7, 1, 2, 3, 10, 2, 8].into_iter().collect::<IdSet>();
+        assert_eq!(s.ranges, vec![(1, 4), (7, 9), (10, 11)]);
+        assert_eq!(s.len(), 6);
+        assert_eq!(s.iter().collect_vec(), vec![1, 2, 3, 7, 8, 10]);
+        for id in 0..12 {
+            assert_eq!(
+                s.contains(&id),
+                [1, 2, 3, 7, 8, 10].contains(&id),
+                "id {}",
+                id
+            );
+        }
+
+        let empty = IdSet::new();
+        assert!(empty.is_empty());
+        assert!(!empty.contains(&0));
+    }
+
+    #[test]
+    fn serialized_size() {
+        fn serialized_len<T: Serialize>(v: &T) -> usize {
+            let mut ser = flexbuffers::FlexbufferSerializer::new();
+            v.serialize(&mut ser).unwrap();
+            ser.take_buffer().len()
+        }
+
+        let ids = (1000..11000).collect::<HashSet<u64>>();
+        let set = ids.iter().cloned().collect::<IdSet>();
+        assert!(serialized_len(&set) * 100 < serialized_len(&ids));
+    }
+}
@@ -0,0 +1,41 @@
+//! Progress of the job running in the current task, shown in `system.jobs`. The job runner sets
+//! the scope, see [crate::cluster::ClusterImpl], and writes reported progress to the meta store
+//! with heart beats. Reports outside of a job, e.g. of an INSERT, are ignored.
+use crate::metastore::job::JobProgress;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+tokio::task_local! {
+    static JOB_PROGRESS: Arc<Mutex<Option<JobProgress>>>;
+}
+
+/// Runs `f` reporting its progress to `progress`. Tasks spawned by `f` don't inherit it.
+pub async fn with_job_progress<F: Future>(
+    progress: Arc<Mutex<Option<JobProgress>>>,
+    f: F,
+) -> F::Output {
+    JOB_PROGRESS.scope(progress, f).await
+}
+
+pub fn report_job_progress(done: u64, total: Option<u64>) {
+    let _ = JOB_PROGRESS.try_with(|p| *p.lock().unwrap() = Some(JobProgress { done, total }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scopes() {
+        report_job_progress(1, None);
+        let progress = Arc::new(Mutex::new(None));
+        with_job_progress(progress.clone(), async { report_job_progress(2, Some(3)) }).await;
+        assert_eq!(
+            *progress.lock().unwrap(),
+            Some(JobProgress {
+                done: 2,
+                total: Some(3)
+            })
+        );
+    }
+}
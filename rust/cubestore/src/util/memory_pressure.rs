@@ -0,0 +1,149 @@
+//! Caches of decoded data, e.g. [crate::queryplanner::batch_cache::BatchCache], have a size
+//! budget of their own, but it does not account for memory taken by queries. When the resident
+//! set size gets close to the memory limit, registered caches evict their entries, so the process
+//! is not killed by the OOM killer in the middle of queries.
+use crate::sys::malloc::trim_allocs;
+use crate::sys::memory::{memory_limit, resident_set_size};
+use log::{info, warn};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+pub trait ShrinkableCache: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Evicts the least recently used entries until `bytes` are freed or the cache is empty.
+    fn shrink(&self, bytes: usize) -> Eviction;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Eviction {
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+lazy_static! {
+    static ref CACHES: Mutex<Vec<Weak<dyn ShrinkableCache>>> = Mutex::new(Vec::new());
+}
+
+/// The cache is shed on memory pressure until it is dropped.
+pub fn register_cache(cache: Arc<dyn ShrinkableCache>) {
+    let mut caches = CACHES.lock().unwrap();
+    caches.retain(|c| c.strong_count() != 0);
+    caches.push(Arc::downgrade(&cache));
+}
+
+/// Checks memory usage every `period` and frees caches when the resident set size exceeds
+/// `threshold_percent` of the memory limit, see [crate::sys::memory].
+pub fn spawn_memory_pressure_loop(period: Duration, threshold_percent: u64) {
+    let limit = match memory_limit() {
+        Some(limit) => limit,
+        None => {
+            warn!("Memory limit is unknown, caches are not shed on memory pressure");
+            return;
+        }
+    };
+    let threshold = limit / 100 * threshold_percent;
+    info!(
+        "Caches are shed when resident set size exceeds {} bytes of {} bytes limit",
+        threshold, limit
+    );
+
+    // We detach the thread, so have to be prepared it gets killed at any point.
+    std::thread::spawn(move || loop {
+        std::thread::sleep(period);
+        let rss = match resident_set_size() {
+            Some(rss) => rss,
+            None => continue,
+        };
+        if rss <= threshold {
+            continue;
+        }
+        let caches = {
+            let caches = CACHES.lock().unwrap();
+            caches
+                .iter()
+                .filter_map(|c| c.upgrade())
+                .collect::<Vec<_>>()
+        };
+        let freed = shed(&caches, (rss - threshold) as usize);
+        warn!(
+            "Resident set size of {} bytes exceeds {} bytes, freed {} bytes of caches",
+            rss, threshold, freed
+        );
+        if freed != 0 {
+            trim_allocs();
+        }
+    });
+}
+
+/// Shrinks `caches` in order until `bytes` are freed. Returns the number of freed bytes.
+fn shed(caches: &[Arc<dyn ShrinkableCache>], bytes: usize) -> usize {
+    let mut freed = 0;
+    for cache in caches {
+        if bytes <= freed {
+            break;
+        }
+        let eviction = cache.shrink(bytes - freed);
+        if eviction.entries != 0 {
+            warn!(
+                "Evicted {} entries of {} bytes from {} on memory pressure",
+                eviction.entries,
+                eviction.bytes,
+                cache.name()
+            );
+        }
+        freed += eviction.bytes;
+    }
+    freed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestCache {
+        entries: Mutex<Vec<usize>>,
+    }
+
+    impl ShrinkableCache for TestCache {
+        fn name(&self) -> &'static str {
+            "test cache"
+        }
+
+        fn shrink(&self, bytes: usize) -> Eviction {
+            let mut entries = self.entries.lock().unwrap();
+            let mut eviction = Eviction::default();
+            while eviction.bytes < bytes {
+                match entries.pop() {
+                    Some(size) => {
+                        eviction.entries += 1;
+                        eviction.bytes += size;
+                    }
+                    None => break,
+                }
+            }
+            eviction
+        }
+    }
+
+    #[test]
+    fn shed_in_order() {
+        let first = Arc::new(TestCache {
+            entries: Mutex::new(vec![100, 100]),
+        });
+        let second = Arc::new(TestCache {
+            entries: Mutex::new(vec![50, 50, 50]),
+        });
+        let caches: Vec<Arc<dyn ShrinkableCache>> = vec![first.clone(), second.clone()];
+
+        assert_eq!(shed(&caches, 150), 200);
+        assert!(first.entries.lock().unwrap().is_empty());
+        assert_eq!(second.entries.lock().unwrap().len(), 3);
+
+        assert_eq!(shed(&caches, 60), 100);
+        assert_eq!(second.entries.lock().unwrap().len(), 1);
+
+        assert_eq!(shed(&caches, 1000), 50);
+        assert_eq!(shed(&caches, 1000), 0);
+    }
+}
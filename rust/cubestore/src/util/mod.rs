@@ -1,8 +1,14 @@
+pub mod avro;
 pub mod error;
+pub mod id_set;
+pub mod job_progress;
 pub mod lock;
 mod malloc_trim_loop;
 pub mod maybe_owned;
+pub mod memory_pressure;
 pub mod ordfloat;
+pub mod query_id;
+pub mod scratch;
 pub mod time_span;
 
 pub use malloc_trim_loop::spawn_malloc_trim_loop;
@@ -0,0 +1,45 @@
+//! Every query sent by a client gets an id, so errors reported to the client can be matched with
+//! logs of the router and of the workers that executed it. The id is kept in a task-local
+//! variable while the query runs and is prepended to log lines by
+//! [crate::telemetry::ReportingLogger]. Workers get it along with the plan, see
+//! [crate::queryplanner::serialized_plan::SerializedPlan::query_id]. Tasks spawned by the query
+//! don't inherit the id.
+use std::future::Future;
+
+tokio::task_local! {
+    static QUERY_ID: String;
+}
+
+pub fn new_query_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Runs `f` with `query_id` as the current query id, `f` is run as is without the id.
+pub async fn with_query_id<F: Future>(query_id: Option<String>, f: F) -> F::Output {
+    match query_id {
+        Some(id) => QUERY_ID.scope(id, f).await,
+        None => f.await,
+    }
+}
+
+pub fn current_query_id() -> Option<String> {
+    QUERY_ID.try_with(|id| id.clone()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scopes() {
+        assert_eq!(current_query_id(), None);
+        let id = with_query_id(Some("q1".to_string()), async {
+            let inner = with_query_id(Some("q2".to_string()), async { current_query_id() }).await;
+            assert_eq!(inner, Some("q2".to_string()));
+            with_query_id(None, async { current_query_id() }).await
+        })
+        .await;
+        assert_eq!(id, Some("q1".to_string()));
+        assert_eq!(current_query_id(), None);
+    }
+}
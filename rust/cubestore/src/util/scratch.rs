@@ -0,0 +1,202 @@
+//! Local disk space for operators that spill intermediate data, e.g. sorts and shuffles that do not
+//! fit into memory. Every query gets its own directory inside `<data dir>/scratch`, which is
+//! removed with all its files when the query finishes. Directories left after a crash are removed
+//! on startup, before any query can run.
+use crate::CubeError;
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub struct ScratchSpace {
+    root: PathBuf,
+    /// Zero means no limit.
+    max_size: u64,
+    used: AtomicU64,
+    next_dir_id: AtomicU64,
+}
+
+crate::di_service!(ScratchSpace, []);
+
+impl ScratchSpace {
+    pub fn new(root: PathBuf, max_size: u64) -> Arc<ScratchSpace> {
+        Arc::new(ScratchSpace {
+            root,
+            max_size,
+            used: AtomicU64::new(0),
+            next_dir_id: AtomicU64::new(0),
+        })
+    }
+
+    /// Removes files left by previous runs of the process. Must be called before queries start.
+    pub async fn remove_orphans(&self) -> Result<(), CubeError> {
+        if tokio::fs::metadata(&self.root).await.is_err() {
+            return Ok(());
+        }
+        let mut orphans = 0;
+        let mut entries = tokio::fs::read_dir(&self.root).await?;
+        while let Some(e) = entries.next_entry().await? {
+            if e.file_type().await?.is_dir() {
+                tokio::fs::remove_dir_all(e.path()).await?;
+            } else {
+                tokio::fs::remove_file(e.path()).await?;
+            }
+            orphans += 1;
+        }
+        if orphans != 0 {
+            warn!(
+                "Removed {} orphaned entries from scratch directory {}",
+                orphans,
+                self.root.display()
+            );
+        }
+        Ok(())
+    }
+
+    /// Bytes reserved by all running queries.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// The directory is created on the first call to [QueryScratch::new_file].
+    pub fn for_query(self: &Arc<Self>, query: &str) -> QueryScratch {
+        let id = self.next_dir_id.fetch_add(1, Ordering::SeqCst);
+        QueryScratch {
+            space: self.clone(),
+            query: query.to_string(),
+            dir: self.root.join(id.to_string()),
+            files: AtomicU64::new(0),
+            used: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+        }
+    }
+
+    fn try_reserve(&self, bytes: u64) -> bool {
+        let mut used = self.used.load(Ordering::SeqCst);
+        loop {
+            let new_used = used + bytes;
+            if self.max_size != 0 && self.max_size < new_used {
+                return false;
+            }
+            match self
+                .used
+                .compare_exchange(used, new_used, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => return true,
+                Err(actual) => used = actual,
+            }
+        }
+    }
+}
+
+/// Scratch files of a single query. Operators reserve space before writing and release it after
+/// removing their files. Everything left is removed and released on drop.
+#[derive(Debug)]
+pub struct QueryScratch {
+    space: Arc<ScratchSpace>,
+    query: String,
+    dir: PathBuf,
+    files: AtomicU64,
+    used: AtomicU64,
+    peak: AtomicU64,
+}
+
+impl QueryScratch {
+    /// Returns a path for a new file, the file itself is not created.
+    pub async fn new_file(&self, suffix: &str) -> Result<PathBuf, CubeError> {
+        let id = self.files.fetch_add(1, Ordering::SeqCst);
+        tokio::fs::create_dir_all(&self.dir).await?;
+        Ok(self.dir.join(format!("{}.{}", id, suffix)))
+    }
+
+    pub fn reserve(&self, bytes: u64) -> Result<(), CubeError> {
+        if !self.space.try_reserve(bytes) {
+            return Err(CubeError::user(format!(
+                "Queries on this node exceeded the scratch space limit of {} bytes. \
+                 Consider raising CUBESTORE_SCRATCH_MAX_SIZE_MB",
+                self.space.max_size
+            )));
+        }
+        let used = self.used.fetch_add(bytes, Ordering::SeqCst) + bytes;
+        self.peak.fetch_max(used, Ordering::SeqCst);
+        Ok(())
+    }
+
+    pub fn release(&self, bytes: u64) {
+        let released = bytes.min(self.used.load(Ordering::SeqCst));
+        self.used.fetch_sub(released, Ordering::SeqCst);
+        self.space.used.fetch_sub(released, Ordering::SeqCst);
+    }
+
+    /// Bytes reserved by the query right now.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Maximum number of bytes reserved by the query at once.
+    pub fn peak(&self) -> u64 {
+        self.peak.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for QueryScratch {
+    fn drop(&mut self) {
+        self.release(self.used());
+        let files = *self.files.get_mut();
+        if files == 0 {
+            return;
+        }
+        info!(
+            "Query {} spilled {} files to scratch space, peak usage {} bytes",
+            self.query,
+            files,
+            self.peak()
+        );
+        // Runs on drop, so no async here. Directories that fail to be removed are cleaned up on
+        // the next start.
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            warn!(
+                "Failed to remove scratch directory {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scratch_space() {
+        let root = std::env::current_dir().unwrap().join("scratch-space-test");
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("orphan")).unwrap();
+        std::fs::write(root.join("orphan").join("0.spill"), "data").unwrap();
+
+        let space = ScratchSpace::new(root.clone(), 100);
+        space.remove_orphans().await.unwrap();
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+
+        let q1 = space.for_query("q1");
+        let q2 = space.for_query("q2");
+        let f = q1.new_file("spill").await.unwrap();
+        std::fs::write(&f, "data").unwrap();
+        q1.reserve(60).unwrap();
+        assert!(q2.reserve(50).is_err());
+        q1.release(20);
+        q2.reserve(50).unwrap();
+        assert_eq!(space.used(), 90);
+        assert_eq!(q1.peak(), 60);
+
+        drop(q1);
+        assert_eq!(space.used(), 50);
+        assert!(!f.exists());
+        drop(q2);
+        assert_eq!(space.used(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}